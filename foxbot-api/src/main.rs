@@ -0,0 +1,430 @@
+//! HTTP API for resolving links and finding image sources with the same
+//! loaders as the bot, for other bots and tools.
+//!
+//! * `POST /resolve` with a JSON body like `{"url": "https://..."}` returns
+//!   the posts found at the link.
+//! * `POST /reverse` with image bytes as the body returns the sources found
+//!   on FuzzySearch. A `distance` query parameter sets how similar sources
+//!   must be, from 0 to 10.
+//!
+//! Every request needs one of the configured `API_KEYS` as a bearer token.
+
+use std::{
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use anyhow::Context;
+use hyper::{body::HttpBody, Body, Method, Request, Response, StatusCode};
+use serde::Deserialize;
+use tracing_futures::Instrument;
+
+use foxbot_sites::{Proxies, Resolver};
+use foxbot_utils::download;
+
+mod rate_limit;
+
+/// Address to listen on when `API_HOST` isn't set.
+const DEFAULT_HOST: &str = "0.0.0.0:8082";
+/// Requests each key may make a minute when `API_RATE_LIMIT` isn't set.
+const DEFAULT_RATE_LIMIT: u32 = 60;
+/// Largest image that may be searched when `MEDIA_MAX_BYTES` isn't set.
+const DEFAULT_MAX_BYTES: usize = 50_000_000;
+/// Links that may be resolved at once when `API_RESOLVERS` isn't set.
+const DEFAULT_RESOLVERS: usize = 4;
+/// Distance used for reverse searches that don't specify one.
+const DEFAULT_DISTANCE: i64 = 3;
+/// Furthest distance a reverse search may use.
+const MAX_DISTANCE: i64 = 10;
+
+#[derive(Deserialize)]
+struct Config {
+    // Site config
+    fa_a: String,
+    fa_b: String,
+    flaresolverr_endpoint: Option<String>,
+    weasyl_apitoken: String,
+    inkbunny_username: String,
+    inkbunny_password: String,
+    e621_login: String,
+    e621_api_key: String,
+
+    // Twitter config
+    twitter_consumer_key: String,
+    twitter_consumer_secret: String,
+    twitter_thread_limit: Option<usize>,
+
+    // FuzzySearch config
+    fautil_apitoken: String,
+
+    // API config
+    api_host: Option<String>,
+    api_keys: Vec<String>,
+    api_rate_limit: Option<u32>,
+    api_resolvers: Option<usize>,
+    media_max_bytes: Option<usize>,
+    media_max_pixels: Option<u64>,
+    proxy: Option<String>,
+    site_proxies: Option<Vec<String>>,
+    database_url: String,
+}
+
+/// Credentials used only by the API.
+static API_CREDENTIALS: &[&str] = &["API_KEYS", "DATABASE_URL"];
+
+struct Api {
+    resolvers: Vec<tokio::sync::Mutex<Resolver>>,
+    next_resolver: AtomicUsize,
+    fapi: fuzzysearch::FuzzySearch,
+    keys: Vec<String>,
+    limiter: rate_limit::RateLimiter,
+    max_bytes: usize,
+}
+
+#[derive(Deserialize)]
+struct ResolveRequest {
+    url: String,
+}
+
+#[tokio::main]
+async fn main() {
+    load_env();
+
+    tracing_subscriber::fmt()
+        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+        .init();
+
+    let names: Vec<&str> = foxbot_utils::secrets::SITE_CREDENTIALS
+        .iter()
+        .chain(API_CREDENTIALS)
+        .copied()
+        .collect();
+    let secrets = foxbot_utils::secrets::providers_from_env();
+    let config: Config = foxbot_utils::config::load_config_with_secrets(&secrets, &names)
+        .await
+        .expect("unable to load config");
+
+    if config.api_keys.iter().all(|key| key.is_empty()) {
+        panic!("API_KEYS must contain at least one key");
+    }
+
+    let addr: SocketAddr = config
+        .api_host
+        .as_deref()
+        .unwrap_or(DEFAULT_HOST)
+        .parse()
+        .expect("API_HOST must be an address like 0.0.0.0:8082");
+
+    let pool = sqlx::postgres::PgPoolOptions::new()
+        .max_connections(2)
+        .connect_lazy(&config.database_url)
+        .expect("DATABASE_URL was invalid");
+
+    let proxies = Proxies::new(
+        config.proxy.clone(),
+        config.site_proxies.as_deref().unwrap_or_default(),
+    )
+    .expect("PROXY and SITE_PROXIES must be valid proxy URLs");
+
+    download::set_limits(download::DownloadLimits::new(
+        config.media_max_bytes,
+        config.media_max_pixels,
+    ));
+
+    // Sites need exclusive access while loading a link, so each resolver has
+    // its own sites to let several links be resolved at once.
+    let mut resolvers = Vec::new();
+    for _ in 0..config.api_resolvers.unwrap_or(DEFAULT_RESOLVERS).max(1) {
+        let sites = foxbot_sites::get_all_sites(
+            config.fa_a.clone(),
+            config.fa_b.clone(),
+            config.flaresolverr_endpoint.clone(),
+            config.fautil_apitoken.clone(),
+            config.weasyl_apitoken.clone(),
+            config.twitter_consumer_key.clone(),
+            config.twitter_consumer_secret.clone(),
+            config.twitter_thread_limit.unwrap_or_default(),
+            config.inkbunny_username.clone(),
+            config.inkbunny_password.clone(),
+            config.e621_login.clone(),
+            config.e621_api_key.clone(),
+            pool.clone(),
+            &proxies,
+        )
+        .await;
+
+        resolvers.push(tokio::sync::Mutex::new(Resolver::new(sites)));
+    }
+
+    let api = Arc::new(Api {
+        resolvers,
+        next_resolver: AtomicUsize::new(0),
+        fapi: fuzzysearch::FuzzySearch::new(config.fautil_apitoken),
+        keys: config
+            .api_keys
+            .into_iter()
+            .filter(|key| !key.is_empty())
+            .collect(),
+        limiter: rate_limit::RateLimiter::new(
+            config.api_rate_limit.unwrap_or(DEFAULT_RATE_LIMIT),
+            Duration::from_secs(60),
+        ),
+        max_bytes: config.media_max_bytes.unwrap_or(DEFAULT_MAX_BYTES),
+    });
+
+    let make_svc = hyper::service::make_service_fn(move |_conn| {
+        let api = api.clone();
+
+        async move {
+            Ok::<_, Infallible>(hyper::service::service_fn(move |req| {
+                let span = tracing::info_span!("handle_request", path = req.uri().path());
+                handle(req, api.clone()).instrument(span)
+            }))
+        }
+    });
+
+    tracing::info!("listening on http://{}", addr);
+
+    let graceful = hyper::Server::bind(&addr)
+        .serve(make_svc)
+        .with_graceful_shutdown(async {
+            tokio::signal::ctrl_c()
+                .await
+                .expect("unable to listen for ctrl-c");
+            tracing::info!("shutting down http server");
+        });
+
+    if let Err(e) = graceful.await {
+        tracing::error!("server error: {:?}", e);
+    }
+}
+
+async fn handle(req: Request<Body>, api: Arc<Api>) -> Result<Response<Body>, Infallible> {
+    let key = match authorized_key(&req, &api.keys) {
+        Some(key) => key,
+        None => return Ok(error_response(StatusCode::UNAUTHORIZED, "invalid api key")),
+    };
+
+    if let Err(retry_after) = api.limiter.check(key) {
+        let mut resp = error_response(StatusCode::TOO_MANY_REQUESTS, "rate limit exceeded");
+        resp.headers_mut().insert(
+            hyper::header::RETRY_AFTER,
+            hyper::header::HeaderValue::from(retry_after.as_secs().max(1)),
+        );
+
+        return Ok(resp);
+    }
+
+    let result = match (req.method(), req.uri().path()) {
+        (&Method::POST, "/resolve") => resolve(req, &api).await,
+        (&Method::POST, "/reverse") => reverse(req, &api).await,
+        _ => return Ok(error_response(StatusCode::NOT_FOUND, "not found")),
+    };
+
+    match result {
+        Ok(resp) => Ok(resp),
+        Err(err) => {
+            tracing::error!("api request failed: {:?}", err);
+
+            Ok(error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "internal error",
+            ))
+        }
+    }
+}
+
+impl Api {
+    /// Get a resolver that isn't in use, or wait for the next one in turn if
+    /// they all are.
+    async fn resolver(&self) -> tokio::sync::MutexGuard<'_, Resolver> {
+        if let Some(resolver) = self
+            .resolvers
+            .iter()
+            .find_map(|resolver| resolver.try_lock().ok())
+        {
+            return resolver;
+        }
+
+        let index = self.next_resolver.fetch_add(1, Ordering::Relaxed) % self.resolvers.len();
+        self.resolvers[index].lock().await
+    }
+}
+
+/// Load the posts from a link.
+async fn resolve(req: Request<Body>, api: &Api) -> anyhow::Result<Response<Body>> {
+    let body = match read_body(req.into_body(), api.max_bytes).await? {
+        Some(body) => body,
+        None => {
+            return Ok(error_response(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "body too large",
+            ))
+        }
+    };
+
+    let request: ResolveRequest = match serde_json::from_slice(&body) {
+        Ok(request) => request,
+        Err(_err) => {
+            return Ok(error_response(
+                StatusCode::BAD_REQUEST,
+                "body must be json with a url",
+            ))
+        }
+    };
+
+    let url = foxbot_utils::urls::normalize_url(&request.url).await;
+    if !foxbot_utils::urls::is_public_url(&url).await {
+        return Ok(error_response(
+            StatusCode::BAD_REQUEST,
+            "url must be a public http link",
+        ));
+    }
+
+    let posts = api.resolver().await.resolve(&url).await?;
+
+    Ok(json_response(serde_json::json!({
+        "url": url,
+        "posts": posts,
+    })))
+}
+
+/// Find sources for an image.
+async fn reverse(req: Request<Body>, api: &Api) -> anyhow::Result<Response<Body>> {
+    let distance = match query_distance(req.uri().query()) {
+        Some(distance) => distance,
+        None => {
+            return Ok(error_response(
+                StatusCode::BAD_REQUEST,
+                "distance must be a number from 0 to 10",
+            ))
+        }
+    };
+
+    let body = match read_body(req.into_body(), api.max_bytes).await? {
+        Some(body) => body,
+        None => {
+            return Ok(error_response(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "image too large",
+            ))
+        }
+    };
+
+    // Only decode images small enough to be downloaded from a link.
+    if let Err(err) = download::check_image(&body) {
+        tracing::debug!("image was not allowed: {:?}", err);
+
+        return Ok(error_response(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "body was not a supported image or had too many pixels",
+        ));
+    }
+
+    let hash = match foxbot_utils::decode::hash_bytes(body).await {
+        Ok(hash) => hash,
+        Err(_err) => {
+            return Ok(error_response(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "body was not a supported image",
+            ))
+        }
+    };
+
+    let sources = foxbot_utils::lookup_single_hash(&api.fapi, hash, Some(distance)).await?;
+
+    Ok(json_response(serde_json::json!({
+        "hash": hash,
+        "sources": sources,
+    })))
+}
+
+/// Get the distance from a query string, if it is allowed.
+fn query_distance(query: Option<&str>) -> Option<i64> {
+    let distance = query
+        .unwrap_or_default()
+        .split('&')
+        .filter_map(|pair| pair.strip_prefix("distance="))
+        .next();
+
+    match distance {
+        Some(distance) => distance
+            .parse()
+            .ok()
+            .filter(|distance| (0..=MAX_DISTANCE).contains(distance)),
+        None => Some(DEFAULT_DISTANCE),
+    }
+}
+
+/// Get which configured key a request was made with.
+fn authorized_key<'a>(req: &Request<Body>, keys: &'a [String]) -> Option<&'a str> {
+    let provided = req
+        .headers()
+        .get(hyper::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))?;
+
+    keys.iter()
+        .find(|key| foxbot_utils::constant_time_eq(provided, key))
+        .map(String::as_str)
+}
+
+/// Read a request body, returning `None` if it is larger than allowed.
+async fn read_body(mut body: Body, max_bytes: usize) -> anyhow::Result<Option<Vec<u8>>> {
+    let mut data = Vec::new();
+
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.context("unable to read request body")?;
+        if data.len() + chunk.len() > max_bytes {
+            return Ok(None);
+        }
+
+        data.extend_from_slice(&chunk);
+    }
+
+    Ok(Some(data))
+}
+
+fn json_response(value: serde_json::Value) -> Response<Body> {
+    let mut resp = Response::new(Body::from(value.to_string()));
+    resp.headers_mut().insert(
+        hyper::header::CONTENT_TYPE,
+        hyper::header::HeaderValue::from_static("application/json"),
+    );
+
+    resp
+}
+
+fn error_response(status: StatusCode, message: &str) -> Response<Body> {
+    let mut resp = json_response(serde_json::json!({ "error": message }));
+    *resp.status_mut() = status;
+
+    resp
+}
+
+#[cfg(feature = "env")]
+fn load_env() {
+    dotenv::dotenv().unwrap();
+}
+
+#[cfg(not(feature = "env"))]
+fn load_env() {}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_query_distance() {
+        use super::query_distance;
+
+        assert_eq!(query_distance(None), Some(super::DEFAULT_DISTANCE));
+        assert_eq!(query_distance(Some("distance=5")), Some(5));
+        assert_eq!(query_distance(Some("other=1&distance=0")), Some(0));
+        assert_eq!(query_distance(Some("distance=11")), None);
+        assert_eq!(query_distance(Some("distance=close")), None);
+    }
+}
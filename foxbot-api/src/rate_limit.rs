@@ -0,0 +1,78 @@
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Limits how many requests each API key may make within a window.
+pub struct RateLimiter {
+    limit: u32,
+    window: Duration,
+    windows: Mutex<HashMap<String, (Instant, u32)>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: u32, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            windows: Default::default(),
+        }
+    }
+
+    /// Count a request for a key, returning how long until another request
+    /// is allowed if it went over the limit.
+    pub fn check(&self, key: &str) -> Result<(), Duration> {
+        self.check_at(key, Instant::now())
+    }
+
+    fn check_at(&self, key: &str, now: Instant) -> Result<(), Duration> {
+        let mut windows = self.windows.lock().unwrap();
+
+        let (started, count) = windows.entry(key.to_string()).or_insert((now, 0));
+        if now.duration_since(*started) >= self.window {
+            *started = now;
+            *count = 0;
+        }
+
+        if *count >= self.limit {
+            return Err(self.window - now.duration_since(*started));
+        }
+
+        *count += 1;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::RateLimiter;
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(60));
+        let start = Instant::now();
+
+        assert!(limiter.check_at("a", start).is_ok());
+        assert!(limiter.check_at("a", start).is_ok());
+        assert_eq!(
+            limiter.check_at("a", start + Duration::from_secs(20)),
+            Err(Duration::from_secs(40))
+        );
+
+        assert!(
+            limiter.check_at("b", start).is_ok(),
+            "keys should be limited separately"
+        );
+
+        assert!(
+            limiter
+                .check_at("a", start + Duration::from_secs(60))
+                .is_ok(),
+            "limit should reset after the window"
+        );
+    }
+}
@@ -0,0 +1,36 @@
+use std::sync::Arc;
+
+use foxbot_models::{Actions, NewAction};
+
+use crate::*;
+
+/// Days to keep actions when `ACTION_RETENTION_DAYS` isn't set.
+pub const DEFAULT_RETENTION_DAYS: i32 = 30;
+
+/// Seconds between removing old actions.
+const CLEANUP_INTERVAL: u64 = 60 * 60;
+
+/// Record a change the bot made to a message.
+///
+/// Failing to record an action shouldn't fail the job that already made the
+/// change, so errors are only logged.
+pub async fn record(handler: &Handler, action: NewAction<'_>) {
+    if let Err(err) = Actions::record(&handler.conn, &action).await {
+        tracing::error!("unable to record action: {:?}", err);
+    }
+}
+
+/// Periodically remove actions older than the retention period.
+pub async fn run_cleanup(handler: Arc<Handler>, retention_days: i32) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(CLEANUP_INTERVAL));
+
+    loop {
+        interval.tick().await;
+
+        match Actions::remove_older_than(&handler.conn, retention_days).await {
+            Ok(0) => (),
+            Ok(count) => tracing::info!(count, "removed old actions"),
+            Err(err) => tracing::error!("unable to remove old actions: {:?}", err),
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use std::sync::Arc;
+
+use tgbotapi::requests::SendMessage;
+
+use crate::*;
+use foxbot_models::{Announcements, Broadcasts};
+
+/// Number of chats to send a broadcast to in each job.
+const BROADCAST_BATCH_SIZE: i64 = 100;
+
+/// Time between each message of a broadcast, keeping well under Telegram's
+/// limit of 30 messages per second.
+const BROADCAST_MESSAGE_DELAY: std::time::Duration = std::time::Duration::from_millis(40);
+
+/// Number of broadcasts in a row a chat may fail to receive before it stops
+/// receiving announcements.
+const MAX_BROADCAST_FAILURES: i32 = 3;
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct BroadcastBatch {
+    broadcast_id: i32,
+    /// Internal ID of the last chat the broadcast was sent to.
+    after_chat_id: i32,
+}
+
+/// Send a broadcast to the next batch of chats that opted into announcements,
+/// then enqueue the following batch until every chat has been sent it.
+#[tracing::instrument(skip(handler, job), fields(job_id = job.id()))]
+#[deny(clippy::unwrap_used)]
+pub async fn process_broadcast(handler: Arc<Handler>, job: faktory::Job) -> Result<(), Error> {
+    let data = job
+        .args()
+        .iter()
+        .next()
+        .ok_or(Error::MissingData)?
+        .to_owned();
+    let mut batch: BroadcastBatch = serde_json::value::from_value(data)?;
+
+    let broadcast = match Broadcasts::get(&handler.conn, batch.broadcast_id).await? {
+        Some(broadcast) => broadcast,
+        None => {
+            tracing::warn!("broadcast was missing");
+            return Ok(());
+        }
+    };
+
+    let chats =
+        Announcements::page(&handler.conn, batch.after_chat_id, BROADCAST_BATCH_SIZE).await?;
+
+    if chats.is_empty() {
+        Broadcasts::complete(&handler.conn, broadcast.id).await?;
+        send_summary(&handler, broadcast.id).await?;
+
+        return Ok(());
+    }
+
+    tracing::debug!(count = chats.len(), "sending broadcast batch");
+
+    let mut interval = tokio::time::interval(BROADCAST_MESSAGE_DELAY);
+    let (mut sent, mut failed) = (0, 0);
+    let mut retry_after = None;
+
+    for chat in chats {
+        interval.tick().await;
+
+        let send_message = SendMessage {
+            chat_id: chat.telegram_id.into(),
+            text: broadcast.text.clone(),
+            disable_web_page_preview: Some(true),
+            ..Default::default()
+        };
+
+        // Batches that are retried shouldn't send the broadcast to the same
+        // chat again.
+        let key = format!("broadcast:{}:{}", broadcast.id, chat.id);
+
+        match handler
+            .telegram
+            .make_idempotent_request(&key, &send_message)
+            .await
+        {
+            Ok(Some(_message)) => {
+                Announcements::delivered(&handler.conn, chat.id).await?;
+                sent += 1;
+            }
+            Ok(None) => (),
+            Err(tgbotapi::Error::Telegram(tgbotapi::TelegramError {
+                parameters:
+                    Some(tgbotapi::ResponseParameters {
+                        retry_after: Some(seconds),
+                        ..
+                    }),
+                ..
+            })) => {
+                tracing::warn!(retry_after = seconds, "rate limited, re-enqueuing");
+                retry_after = Some(seconds);
+
+                break;
+            }
+            Err(err) => {
+                tracing::info!(
+                    chat_id = chat.telegram_id,
+                    "unable to send broadcast: {:?}",
+                    err
+                );
+
+                let removed = Announcements::failed(
+                    &handler.conn,
+                    chat.id,
+                    &err.to_string(),
+                    MAX_BROADCAST_FAILURES,
+                )
+                .await?;
+                if removed {
+                    tracing::info!(
+                        chat_id = chat.telegram_id,
+                        "chat failed too many broadcasts, removing"
+                    );
+                }
+
+                failed += 1;
+            }
+        }
+
+        batch.after_chat_id = chat.id;
+    }
+
+    Broadcasts::record(&handler.conn, broadcast.id, sent, failed).await?;
+
+    let mut job =
+        faktory::Job::new("broadcast", vec![serde_json::to_value(&batch)?]).on_queue(QUEUE_LOW);
+    if let Some(retry_after) = retry_after {
+        job.at =
+            Some(chrono::offset::Utc::now().add(chrono::Duration::seconds(retry_after as i64)));
+    }
+    job.custom = get_faktory_custom();
+
+    handler.enqueue(job).await;
+
+    Ok(())
+}
+
+/// Let the user who sent a broadcast know it was sent to every chat.
+async fn send_summary(handler: &Handler, broadcast_id: i32) -> Result<(), Error> {
+    let broadcast = match Broadcasts::get(&handler.conn, broadcast_id).await? {
+        Some(broadcast) => broadcast,
+        None => return Ok(()),
+    };
+
+    tracing::info!(
+        sent = broadcast.sent,
+        failed = broadcast.failed,
+        "finished broadcast"
+    );
+
+    let text = handler
+        .get_fluent_bundle(None, |bundle| {
+            let mut args = fluent::FluentArgs::new();
+            args.insert("sent", broadcast.sent.into());
+            args.insert("failed", broadcast.failed.into());
+
+            get_message(bundle, "broadcast-complete", Some(args))
+        })
+        .await
+        .unwrap_or_default();
+
+    let send_message = SendMessage {
+        chat_id: broadcast.created_by.into(),
+        text,
+        ..Default::default()
+    };
+
+    if let Err(err) = handler.telegram.make_request(&send_message).await {
+        tracing::warn!("unable to send broadcast summary: {:?}", err);
+    }
+
+    Ok(())
+}
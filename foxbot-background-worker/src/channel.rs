@@ -1,6 +1,28 @@
 use std::sync::Arc;
 
+use anyhow::Context;
+
+use foxbot_models::{
+    ActionKind, CompanionReplies, GroupConfig, GroupConfigKey, NewAction, SourceStyle,
+    SourceTemplate,
+};
+
 use crate::*;
+use foxbot_utils::{
+    callback::{routes, CallbackData},
+    payload::{self, JobMessage},
+};
+
+/// Seconds sources found by a channel are shared with the rest of its network.
+const NETWORK_SOURCES_TTL: usize = 60 * 60 * 24;
+
+/// Matches for a channel post that were already looked up, such as in a batch
+/// while backfilling.
+#[derive(serde::Serialize, serde::Deserialize)]
+struct PrecomputedMatches {
+    hash: i64,
+    matches: Vec<fuzzysearch::File>,
+}
 
 #[tracing::instrument(skip(handler, job), fields(job_id = job.id()))]
 #[deny(clippy::unwrap_used)]
@@ -11,35 +33,50 @@ pub async fn process_channel_update(handler: Arc<Handler>, job: faktory::Job) ->
         .next()
         .ok_or(Error::MissingData)?
         .to_owned();
-    let message: tgbotapi::Message = serde_json::value::from_value(data)?;
+    let message = JobMessage::from_job(data, handler.download_limit)?;
+    let precomputed: Option<PrecomputedMatches> = match job.args().get(1) {
+        Some(precomputed) => serde_json::value::from_value(precomputed.to_owned())?,
+        None => None,
+    };
 
     tracing::trace!("got enqueued message: {:?}", message);
 
-    // Photos should exist for job to be enqueued.
-    let sizes = match &message.photo {
-        Some(photo) => photo,
-        _ => return Ok(()),
-    };
+    let file = message.file();
 
-    let file = find_best_photo(sizes).ok_or(Error::MissingData)?;
-    let (searched_hash, mut matches) = match_image(
-        &handler.telegram,
-        &handler.conn,
-        &handler.fuzzysearch,
-        file,
-        Some(3),
-    )
-    .await?;
+    let keys = dedup_keys("channel_update", &message);
+    if !claim_job(&handler.redis, job.id(), &keys).await {
+        tracing::debug!("message or file was already processed");
+        return Ok(());
+    }
+    let distance = GroupConfig::source_distance(&handler.conn, message.chat_id).await?;
+
+    let (searched_hash, mut matches) = match precomputed {
+        Some(PrecomputedMatches { hash, matches }) => (hash, matches),
+        None => match GroupConfig::channel_network(&handler.conn, message.chat_id).await? {
+            Some(network) => match_file_in_network(&handler, &network, file).await?,
+            None => {
+                match_file(
+                    &handler.telegram,
+                    &handler.conn,
+                    &handler.fuzzysearch,
+                    file,
+                    Some(distance as i64),
+                    None,
+                )
+                .await?
+            }
+        },
+    };
 
-    // Only keep matches with a distance of 3 or less
-    matches.retain(|m| m.distance.unwrap_or(10) <= 3);
+    // Only keep matches within the channel's chosen distance
+    matches.retain(|m| m.distance.map_or(false, |d| d <= distance));
 
     if matches.is_empty() {
         tracing::debug!("unable to find sources for image");
         return Ok(());
     }
 
-    let links = extract_links(&message);
+    let links = message.links();
 
     let mut sites = handler.sites.lock().await;
 
@@ -55,11 +92,15 @@ pub async fn process_channel_update(handler: Arc<Handler>, job: faktory::Job) ->
 
     if !links.is_empty() {
         let mut results: Vec<foxbot_sites::PostInfo> = Vec::new();
-        let _ = find_images(&tgbotapi::User::default(), links, &mut sites, &mut |info| {
+        let _ = find_images(&foxbot_sites::Anonymous, links, &mut sites, &mut |info| {
             results.extend(info.results);
         })
         .await;
 
+        // Images from sites that refuse to serve us are loaded from the
+        // cache, so they're only downloaded from the site once.
+        let results = rehost_results(&handler, results).await;
+
         let urls: Vec<_> = results
             .iter()
             .map::<&str, _>(|result| &result.url)
@@ -73,7 +114,15 @@ pub async fn process_channel_update(handler: Arc<Handler>, job: faktory::Job) ->
 
     drop(sites);
 
-    if already_had_source(&handler.redis, &message, &matches).await? {
+    let blocked = GroupConfig::blocked_sites(&handler.conn, message.chat_id).await?;
+    remove_blocked_sites(&blocked, &mut matches);
+
+    if matches.is_empty() {
+        tracing::debug!("every source was from a blocked site");
+        return Ok(());
+    }
+
+    if already_had_source(&handler.redis, message.media_group_id.as_deref(), &matches).await? {
         tracing::trace!("post group already contained source url");
         return Ok(());
     }
@@ -81,19 +130,107 @@ pub async fn process_channel_update(handler: Arc<Handler>, job: faktory::Job) ->
     // Keep order of sites consistent.
     sort_results_by(&foxbot_models::Sites::default_order(), &mut matches, true);
 
-    let firsts = first_of_each_site(&matches)
-        .into_iter()
-        .map(|(site, file)| (site, file.url()))
-        .collect();
+    let mut template: SourceTemplate = GroupConfig::get(
+        &handler.conn,
+        message.chat_id,
+        GroupConfigKey::SourceTemplate,
+    )
+    .await?
+    .unwrap_or_default();
 
-    let data = serde_json::to_value(&MessageEdit {
-        chat_id: message.chat.id.to_string(),
-        message_id: message.message_id,
-        media_group_id: message.media_group_id,
-        firsts,
-    })?;
+    if template.strict && !retain_strict_matches(&mut matches) {
+        tracing::debug!("no exact match by a single artist in strict mode");
+        return Ok(());
+    }
+
+    // Editing is doomed without permission, but a discussion reply may still
+    // be possible.
+    let can_edit: Option<bool> = GroupConfig::get(
+        &handler.conn,
+        message.chat_id,
+        GroupConfigKey::HasEditPermission,
+    )
+    .await?;
+    if can_edit == Some(false) {
+        if template.companion == CompanionReplies::Off {
+            tracing::debug!("missing permission to edit channel post");
+            return Ok(());
+        }
+
+        template.companion = CompanionReplies::Instead;
+    }
+
+    // Media groups can't have buttons to choose with, and channels only
+    // wanting discussion replies never edit the post.
+    if message.media_group_id.is_none() && template.companion != CompanionReplies::Instead {
+        let file_refs: Vec<_> = matches.iter().collect();
+        let mut choices = artist_choices(&file_refs);
+
+        // Use the artist someone already chose for this image instead of
+        // asking again.
+        match apply_chosen_artist(&handler.conn, message.chat_id, searched_hash, &mut choices)
+            .await?
+        {
+            Some(chosen) => matches = chosen.into_iter().cloned().collect(),
+            None if !choices.is_empty() => {
+                return offer_artist_choices(
+                    &handler,
+                    &job.queue,
+                    &message,
+                    &template,
+                    searched_hash,
+                    choices,
+                )
+                .await;
+            }
+            None => (),
+        }
+    }
+
+    let discussion_text = if template.companion == CompanionReplies::Off {
+        None
+    } else {
+        Some(
+            handler
+                .get_fluent_bundle(None, |bundle| build_discussion_text(bundle, &matches))
+                .await,
+        )
+    };
+
+    let discussion_text = match (template.companion, discussion_text) {
+        (CompanionReplies::Also, Some(text)) => {
+            enqueue_discussion_reply(
+                &handler,
+                &job.queue,
+                message.chat_id,
+                message.message_id,
+                text,
+            )
+            .await?;
+            None
+        }
+        (CompanionReplies::Instead, Some(text)) => {
+            enqueue_discussion_reply(
+                &handler,
+                &job.queue,
+                message.chat_id,
+                message.message_id,
+                text,
+            )
+            .await?;
+            return Ok(());
+        }
+        (_, text) => text,
+    };
 
-    let mut job = faktory::Job::new("channel_edit", vec![data]).on_queue("foxbot_background");
+    let data = serde_json::to_value(&build_message_edit(
+        &message,
+        &matches,
+        template,
+        discussion_text,
+    ))?;
+
+    let mut job = faktory::Job::new("channel_edit", vec![data]).on_queue(&job.queue);
     job.custom = get_faktory_custom();
 
     handler.enqueue(job).await;
@@ -118,13 +255,20 @@ pub async fn process_channel_edit(handler: Arc<Handler>, job: faktory::Job) -> R
         message_id,
         media_group_id,
         firsts,
+        distances,
+        caption,
+        caption_entities,
+        artists,
+        template,
+        discussion_text,
+        artist_choices,
     } = serde_json::value::from_value(data.clone())?;
     let chat_id: &str = &chat_id;
 
     if let Some(at) = check_more_time(&handler.redis, chat_id).await {
         tracing::trace!("need to wait more time for this chat: {}", at);
 
-        let mut job = faktory::Job::new("channel_edit", vec![data]).on_queue("foxbot_background");
+        let mut job = faktory::Job::new("channel_edit", vec![data]).on_queue(&job.queue);
         job.at = Some(at);
         job.custom = get_faktory_custom();
 
@@ -133,33 +277,122 @@ pub async fn process_channel_edit(handler: Arc<Handler>, job: faktory::Job) -> R
         return Ok(());
     }
 
+    let sources: Vec<String> = firsts.iter().map(|(_site, url)| url.clone()).collect();
+    let previous_caption = caption.clone();
+    let previous_caption_entities = caption_entities.clone();
+
+    let hashtags = if template.hashtags {
+        format_hashtags(&artists)
+    } else {
+        None
+    };
+
+    // Sources that didn't fit in the caption.
+    let mut overflow = vec![];
+
+    // If the job is retried after the post was edited, it shouldn't be
+    // edited again.
+    let key = format!("channel_edit:{}", job.id());
+
     // If this photo was part of a media group, we should set a caption on
-    // the image because we can't make an inline keyboard on it.
-    let resp = if media_group_id.is_some() {
-        let caption = firsts
+    // the image because we can't make an inline keyboard on it. Channels may
+    // also prefer to always have sources in the caption.
+    // Asking which artist is correct only adds a button for each artist,
+    // leaving the caption alone until a choice is made.
+    let resp = if !artist_choices.is_empty() {
+        let buttons = artist_choices
             .into_iter()
-            .map(|(_site, url)| url)
-            .collect::<Vec<_>>()
-            .join("\n");
+            .enumerate()
+            .map(|(index, name)| {
+                Ok(vec![InlineKeyboardButton {
+                    text: name,
+                    callback_data: Some(
+                        CallbackData::new(routes::ARTIST_CHOICE)
+                            .arg(index)
+                            .encode()?,
+                    ),
+                    ..Default::default()
+                }])
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let edit_reply_markup = EditMessageReplyMarkup {
+            chat_id: chat_id.into(),
+            message_id: Some(message_id),
+            reply_markup: Some(ReplyMarkup::InlineKeyboardMarkup(InlineKeyboardMarkup {
+                inline_keyboard: buttons,
+            })),
+            ..Default::default()
+        };
+
+        handler
+            .telegram
+            .make_idempotent_request(&key, &edit_reply_markup)
+            .await
+    } else if media_group_id.is_some() || template.style == SourceStyle::Caption {
+        let summary = summarize_caption(caption.as_deref(), &firsts, &template, hashtags);
+        overflow = summary.overflow;
+
+        let caption = match summary.caption {
+            Some(caption) => caption,
+            None => {
+                tracing::warn!("caption had no room for sources");
+
+                enqueue_overflow_reply(
+                    &handler,
+                    &job.queue,
+                    chat_id,
+                    message_id,
+                    &template,
+                    discussion_text,
+                    overflow,
+                )
+                .await?;
+
+                return Ok(());
+            }
+        };
+
+        if !overflow.is_empty() {
+            tracing::debug!(count = overflow.len(), "sources did not fit in caption");
+        }
 
+        // Links are only appended, so existing entities are still valid.
         let edit_caption_markup = EditMessageCaption {
             chat_id: chat_id.into(),
             message_id: Some(message_id),
             caption: Some(caption),
+            caption_entities,
             ..Default::default()
         };
 
-        handler.telegram.make_request(&edit_caption_markup).await
+        handler
+            .telegram
+            .make_idempotent_request(&key, &edit_caption_markup)
+            .await
     // Not a media group, we should create an inline keyboard.
     } else {
-        let buttons: Vec<_> = firsts
-            .into_iter()
-            .map(|(site, url)| InlineKeyboardButton {
-                text: site.as_str().to_string(),
-                url: Some(url),
-                ..Default::default()
+        let buttons: Vec<_> = handler
+            .get_fluent_bundle(None, |bundle| {
+                firsts
+                    .into_iter()
+                    .enumerate()
+                    .map(|(idx, (site, url))| {
+                        let distance = if template.show_distance {
+                            distances.get(idx).copied().flatten()
+                        } else {
+                            None
+                        };
+
+                        InlineKeyboardButton {
+                            text: source_button_text(bundle, site.as_str(), distance),
+                            url: Some(url),
+                            ..Default::default()
+                        }
+                    })
+                    .collect()
             })
-            .collect();
+            .await;
 
         let buttons = if buttons.len() % 2 == 0 {
             buttons.chunks(2).map(|chunk| chunk.to_vec()).collect()
@@ -167,18 +400,41 @@ pub async fn process_channel_edit(handler: Arc<Handler>, job: faktory::Job) -> R
             buttons.chunks(1).map(|chunk| chunk.to_vec()).collect()
         };
 
-        let markup = InlineKeyboardMarkup {
+        let markup = ReplyMarkup::InlineKeyboardMarkup(InlineKeyboardMarkup {
             inline_keyboard: buttons,
-        };
+        });
+
+        // Hashtags still need to go in the caption, which can be edited at
+        // the same time as the keyboard.
+        let caption = hashtags.and_then(|hashtags| append_caption(caption.as_deref(), &[hashtags]));
+
+        if let Some(caption) = caption {
+            let edit_caption_markup = EditMessageCaption {
+                chat_id: chat_id.into(),
+                message_id: Some(message_id),
+                caption: Some(caption),
+                caption_entities,
+                reply_markup: Some(markup),
+                ..Default::default()
+            };
 
-        let edit_reply_markup = EditMessageReplyMarkup {
-            chat_id: chat_id.into(),
-            message_id: Some(message_id),
-            reply_markup: Some(ReplyMarkup::InlineKeyboardMarkup(markup)),
-            ..Default::default()
-        };
+            handler
+                .telegram
+                .make_idempotent_request(&key, &edit_caption_markup)
+                .await
+        } else {
+            let edit_reply_markup = EditMessageReplyMarkup {
+                chat_id: chat_id.into(),
+                message_id: Some(message_id),
+                reply_markup: Some(markup),
+                ..Default::default()
+            };
 
-        handler.telegram.make_request(&edit_reply_markup).await
+            handler
+                .telegram
+                .make_idempotent_request(&key, &edit_reply_markup)
+                .await
+        }
     };
 
     match resp {
@@ -199,8 +455,7 @@ pub async fn process_channel_edit(handler: Arc<Handler>, job: faktory::Job) -> R
 
             needs_more_time(&handler.redis, chat_id, retry_at).await;
 
-            let mut job =
-                faktory::Job::new("channel_edit", vec![data]).on_queue("foxbot_background");
+            let mut job = faktory::Job::new("channel_edit", vec![data]).on_queue(&job.queue);
             job.at = Some(retry_at);
             job.custom = get_faktory_custom();
 
@@ -216,7 +471,8 @@ pub async fn process_channel_edit(handler: Arc<Handler>, job: faktory::Job) -> R
         // that they were generated by a bot.
         //
         // I'm not sure if there's any way to detect this before processing
-        // an update, so ignore these errors.
+        // an update, so ignore these errors unless the channel wanted a
+        // discussion reply instead.
         Err(tgbotapi::Error::Telegram(tgbotapi::TelegramError {
             error_code: Some(400),
             description,
@@ -224,6 +480,12 @@ pub async fn process_channel_edit(handler: Arc<Handler>, job: faktory::Job) -> R
         })) => {
             tracing::warn!("got 400 error, ignoring: {:?}", description);
 
+            if let Some(text) = discussion_text {
+                let channel_id = chat_id.parse().map_err(|_| Error::MissingData)?;
+                enqueue_discussion_reply(&handler, &job.queue, channel_id, message_id, text)
+                    .await?;
+            }
+
             Ok(())
         }
         // If permissions have changed (bot was removed from channel, etc.)
@@ -236,163 +498,1300 @@ pub async fn process_channel_edit(handler: Arc<Handler>, job: faktory::Job) -> R
         })) => {
             tracing::warn!("got 403 error, ignoring: {:?}", description);
 
+            // Remember so later posts don't enqueue edits that will fail.
+            if let Ok(chat_id) = chat_id.parse::<i64>() {
+                GroupConfig::set(
+                    &handler.conn,
+                    GroupConfigKey::HasEditPermission,
+                    chat_id,
+                    false,
+                )
+                .await?;
+            }
+
+            Ok(())
+        }
+        Ok(None) => Ok(()),
+        Ok(Some(_)) => {
+            if let Ok(chat_id) = chat_id.parse() {
+                let action = NewAction {
+                    chat_id,
+                    message_id,
+                    reply_message_id: None,
+                    kind: ActionKind::ChannelEdit,
+                    sources: &sources,
+                    job_id: Some(job.id()),
+                    previous_caption: previous_caption.as_deref(),
+                    previous_caption_entities: previous_caption_entities.as_deref(),
+                };
+
+                actions::record(&handler, action).await;
+            }
+
+            enqueue_overflow_reply(
+                &handler,
+                &job.queue,
+                chat_id,
+                message_id,
+                &template,
+                discussion_text,
+                overflow,
+            )
+            .await?;
+
             Ok(())
         }
-        Ok(_) => Ok(()),
         Err(e) => Err(e.into()),
     }
 }
 
-/// Telegram only shows a caption on a media group if there is a single caption
-/// anywhere in the group. When users upload a group, we need to check if we can
-/// only set a single source to make the link more visible. This can be done by
-/// ensuring our source has been previouly used in the media group.
-///
-/// For our implementation, this is done by maintaining a Redis set of every
-/// source previously displayed. If adding our source links returns fewer
-/// inserted than we had, it means a link was previously used and therefore we
-/// do not have to set a source.
-///
-/// Because Telegram doesn't send media groups at once, we have to store these
-/// values until we're sure the group is over. In this case, we will store
-/// values for 300 seconds.
-///
-/// No link normalization is required here because all links are already
-/// normalized when coming from FuzzySearch.
-async fn already_had_source(
-    conn: &redis::aio::ConnectionManager,
-    message: &tgbotapi::Message,
+/// Build the edit that adds sources from matches to a channel post.
+fn build_message_edit(
+    message: &JobMessage,
     matches: &[fuzzysearch::File],
-) -> anyhow::Result<bool> {
+    template: SourceTemplate,
+    discussion_text: Option<String>,
+) -> MessageEdit {
+    let per_site = template
+        .sources_per_site
+        .clamp(1, foxbot_models::MAX_SOURCES_PER_SITE);
+    let firsts = top_of_each_site(matches, per_site);
+
+    let mut artists: Vec<String> = firsts
+        .iter()
+        .flat_map(|(_site, file)| file.artists.clone().unwrap_or_default())
+        .collect();
+    artists.sort();
+    artists.dedup();
+
+    let distances = firsts.iter().map(|(_site, file)| file.distance).collect();
+    let firsts = firsts
+        .into_iter()
+        .map(|(site, file)| (site, file.url()))
+        .collect();
+
+    MessageEdit {
+        chat_id: message.chat_id.to_string(),
+        message_id: message.message_id,
+        media_group_id: message.media_group_id.clone(),
+        firsts,
+        distances,
+        caption: message.caption.clone(),
+        caption_entities: message.caption_entities.clone(),
+        artists,
+        template,
+        discussion_text,
+        artist_choices: vec![],
+    }
+}
+
+/// Add buttons to a channel post for choosing between artists when the
+/// closest matches were made by different artists, saving the edit for each
+/// choice. Channel administrators make the choice.
+///
+/// The buttons are added by a `channel_edit` job like any other edit.
+async fn offer_artist_choices(
+    handler: &Handler,
+    queue: &str,
+    message: &JobMessage,
+    template: &SourceTemplate,
+    hash: i64,
+    choices: Vec<(String, Vec<&fuzzysearch::File>)>,
+) -> Result<(), Error> {
     use redis::AsyncCommands;
 
-    let group_id = match &message.media_group_id {
-        Some(id) => id,
-        _ => return Ok(false),
+    tracing::debug!(count = choices.len(), "offering artist choices");
+
+    let mut names = Vec::with_capacity(choices.len());
+    let mut jobs = Vec::with_capacity(choices.len());
+
+    for (name, files) in choices {
+        let files: Vec<fuzzysearch::File> = files.into_iter().cloned().collect();
+        jobs.push(serde_json::to_value(&build_message_edit(
+            message,
+            &files,
+            template.clone(),
+            None,
+        ))?);
+
+        names.push(name);
+    }
+
+    let choices = ArtistChoices {
+        job_kind: "channel_edit".to_string(),
+        poster_id: None,
+        choices: jobs,
+        hash: Some(hash),
+        names: names.clone(),
     };
 
-    let key = format!("group-sources:{}", group_id);
+    let key = artist_choices_key(message.chat_id, message.message_id);
 
-    let mut urls: Vec<_> = matches.iter().map(|m| m.url()).collect();
-    urls.sort();
-    urls.dedup();
-    let source_count = urls.len();
+    let mut conn = handler.redis.clone();
+    conn.set_ex::<_, _, ()>(&key, serde_json::to_string(&choices)?, ARTIST_CHOICES_TTL)
+        .await
+        .context("unable to save artist choices")?;
 
-    tracing::trace!(%group_id, "adding new sources: {:?}", urls);
+    let mut edit = build_message_edit(message, &[], template.clone(), None);
+    edit.artist_choices = names;
 
-    let mut conn = conn.clone();
-    let added_links: usize = conn.sadd(&key, urls).await?;
-    conn.expire(&key, 300).await?;
+    let mut job =
+        faktory::Job::new("channel_edit", vec![serde_json::to_value(&edit)?]).on_queue(queue);
+    job.custom = get_faktory_custom();
 
-    tracing::debug!(
-        source_count,
-        added_links,
-        "determined existing and new source links"
-    );
+    handler.enqueue(job).await;
 
-    Ok(source_count > added_links)
+    Ok(())
 }
 
-/// Check if any of the provided image URLs have a hash similar to the given
-/// input.
-#[tracing::instrument(skip(urls))]
-async fn has_similar_hash(to: i64, urls: &[&str]) -> bool {
-    let to = to.to_be_bytes();
+/// Source a batch of a channel's existing messages, then schedule the next
+/// batch until the whole range has been checked.
+#[tracing::instrument(skip(handler, job), fields(job_id = job.id()))]
+#[deny(clippy::unwrap_used)]
+pub async fn process_channel_backfill(
+    handler: Arc<Handler>,
+    job: faktory::Job,
+) -> Result<(), Error> {
+    use tgbotapi::requests::{DeleteMessage, EditMessageText, ForwardMessage};
+
+    let data: serde_json::Value = job
+        .args()
+        .iter()
+        .next()
+        .ok_or(Error::MissingData)?
+        .to_owned();
+
+    tracing::trace!("got enqueued backfill: {:?}", data);
+
+    let mut backfill: ChannelBackfill = serde_json::value::from_value(data)?;
+
+    let batch_end =
+        (backfill.next_message_id + BACKFILL_BATCH_SIZE - 1).min(backfill.last_message_id);
 
-    for url in urls {
-        let check_size = CheckFileSize::new(url, 50_000_000);
-        let bytes = match check_size.into_bytes().await {
-            Ok(bytes) => bytes,
+    let mut messages = Vec::new();
+    let mut retry_after = None;
+
+    for message_id in backfill.next_message_id..=batch_end {
+        let forward_message = ForwardMessage {
+            chat_id: backfill.user_chat_id.into(),
+            from_chat_id: backfill.channel_id.into(),
+            message_id,
+            disable_notification: Some(true),
+        };
+
+        let forwarded = match handler.telegram.make_request(&forward_message).await {
+            Ok(forwarded) => forwarded,
+            Err(tgbotapi::Error::Telegram(tgbotapi::TelegramError {
+                parameters:
+                    Some(tgbotapi::ResponseParameters {
+                        retry_after: Some(seconds),
+                        ..
+                    }),
+                ..
+            })) => {
+                tracing::warn!(retry_after = seconds, "rate limiting, re-enqueuing");
+
+                backfill.next_message_id = message_id;
+                retry_after = Some(seconds as i64);
+
+                break;
+            }
+            // Deleted messages and service messages can't be forwarded.
+            Err(tgbotapi::Error::Telegram(tgbotapi::TelegramError {
+                error_code: Some(400),
+                ..
+            })) => continue,
+            // Nothing was forwarded yet, so the job can be retried as is.
+            Err(err) if message_id == backfill.next_message_id => return Err(err.into()),
+            // Messages were already forwarded, so continue from this message
+            // instead of forwarding them again.
             Err(err) => {
-                tracing::warn!("unable to download image: {:?}", err);
+                tracing::warn!(
+                    "unable to forward backfill message, re-enqueuing: {:?}",
+                    err
+                );
 
-                continue;
+                backfill.next_message_id = message_id;
+                retry_after = Some(BACKFILL_BATCH_DELAY);
+
+                break;
             }
         };
 
-        let hash = tokio::task::spawn_blocking(move || {
-            use std::convert::TryInto;
+        let delete_message = DeleteMessage {
+            chat_id: backfill.user_chat_id.into(),
+            message_id: forwarded.message_id,
+        };
+
+        if let Err(err) = handler.telegram.make_request(&delete_message).await {
+            tracing::warn!("unable to delete forwarded backfill message: {:?}", err);
+        }
+
+        let message = match backfill_message(
+            forwarded,
+            backfill.channel_id,
+            message_id,
+            handler.download_limit,
+        ) {
+            Some(message) => message,
+            None => continue,
+        };
+
+        messages.push(message);
+        backfill.found += 1;
+    }
+
+    enqueue_backfill_updates(&handler, backfill.channel_id, messages).await?;
 
-            let hasher = fuzzysearch::get_hasher();
+    if let Some(retry_after) = retry_after {
+        let retry_at = chrono::offset::Utc::now().add(chrono::Duration::seconds(retry_after));
 
-            let im = match image::load_from_memory(&bytes) {
-                Ok(im) => im,
-                Err(err) => {
-                    tracing::warn!("unable to load image: {:?}", err);
+        let mut job = faktory::Job::new("channel_backfill", vec![serde_json::to_value(&backfill)?])
+            .on_queue(QUEUE_LOW);
+        job.at = Some(retry_at);
+        job.custom = get_faktory_custom();
 
-                    return None;
-                }
-            };
+        handler.enqueue(job).await;
 
-            let hash = hasher.hash_image(&im);
-            let bytes: [u8; 8] = hash.as_bytes().try_into().unwrap_or_default();
+        return Ok(());
+    }
+
+    backfill.next_message_id = batch_end + 1;
+    let finished = backfill.next_message_id > backfill.last_message_id;
+
+    let text = handler
+        .get_fluent_bundle(backfill.lang.as_deref(), |bundle| {
+            let mut args = fluent::FluentArgs::new();
+            args.insert("found", backfill.found.into());
 
-            Some(bytes)
+            if finished {
+                get_message(bundle, "backfill-complete", Some(args))
+            } else {
+                let remaining = backfill.last_message_id - backfill.next_message_id + 1;
+                args.insert("remaining", remaining.into());
+
+                get_message(bundle, "backfill-progress", Some(args))
+            }
         })
-        .in_current_span()
         .await
         .unwrap_or_default();
 
-        let hash = match hash {
-            Some(hash) => hash,
-            _ => continue,
-        };
+    let edit_message = EditMessageText {
+        chat_id: backfill.user_chat_id.into(),
+        message_id: Some(backfill.progress_message_id),
+        text,
+        ..Default::default()
+    };
 
-        if hamming::distance_fast(&to, &hash).unwrap() <= 3 {
-            tracing::debug!(url, hash = i64::from_be_bytes(hash), "hashes were similar");
+    if let Err(err) = handler.telegram.make_request(&edit_message).await {
+        tracing::warn!("unable to update backfill progress: {:?}", err);
+    }
 
-            return true;
-        }
+    if finished {
+        tracing::info!(found = backfill.found, "finished channel backfill");
+
+        return Ok(());
     }
 
-    false
+    let mut job = faktory::Job::new("channel_backfill", vec![serde_json::to_value(&backfill)?])
+        .on_queue(QUEUE_LOW);
+    job.at = Some(chrono::offset::Utc::now().add(chrono::Duration::seconds(BACKFILL_BATCH_DELAY)));
+    job.custom = get_faktory_custom();
+
+    handler.enqueue(job).await;
+
+    Ok(())
 }
 
-#[cfg(test)]
-mod tests {
-    async fn get_redis() -> redis::aio::ConnectionManager {
-        let redis_client =
-            redis::Client::open(std::env::var("REDIS_DSN").expect("Missing REDIS_DSN")).unwrap();
-        redis::aio::ConnectionManager::new(redis_client)
-            .await
-            .expect("unable to open Redis connection")
+/// Enqueue updates for each backfilled post, looking up all of their images
+/// in a single request.
+///
+/// If the images can't be looked up together, each update looks up its own.
+async fn enqueue_backfill_updates(
+    handler: &Handler,
+    channel_id: i64,
+    messages: Vec<tgbotapi::Message>,
+) -> Result<(), Error> {
+    let messages: Vec<_> = messages
+        .iter()
+        .filter_map(|message| JobMessage::new(message, handler.download_limit))
+        .collect();
+
+    let mut hashes = Vec::with_capacity(messages.len());
+    for message in &messages {
+        match hash_file(&handler.telegram, &handler.conn, message.file()).await {
+            Ok(hash) => hashes.push(Some(hash)),
+            Err(err) => {
+                tracing::warn!("unable to hash backfilled image: {:?}", err);
+                hashes.push(None);
+            }
+        }
     }
 
-    #[tokio::test]
-    #[ignore]
-    async fn test_already_had_source() {
-        let _ = tracing_subscriber::fmt::try_init();
+    let distance = GroupConfig::source_distance(&handler.conn, channel_id).await?;
 
-        use super::already_had_source;
+    let mut unique_hashes: Vec<i64> = hashes.iter().flatten().copied().collect();
+    unique_hashes.sort_unstable();
+    unique_hashes.dedup();
 
-        let mut conn = get_redis().await;
-        let _: () = redis::cmd("FLUSHDB").query_async(&mut conn).await.unwrap();
+    let matches =
+        match lookup_hashes(&handler.fuzzysearch, &unique_hashes, Some(distance as i64)).await {
+            Ok(matches) => matches,
+            Err(err) => {
+                tracing::warn!("unable to lookup backfilled images: {:?}", err);
+                Default::default()
+            }
+        };
 
-        let site_info = Some(fuzzysearch::SiteInfo::FurAffinity(
-            fuzzysearch::FurAffinityFile { file_id: 123 },
-        ));
+    tracing::debug!(
+        count = messages.len(),
+        hashes = unique_hashes.len(),
+        "enqueueing backfilled posts"
+    );
 
-        let message = tgbotapi::Message {
-            media_group_id: Some("test-group".to_string()),
-            ..Default::default()
-        };
+    for (message, hash) in messages.iter().zip(hashes) {
+        let mut args = vec![payload::encode(message)?];
 
-        let sources = vec![fuzzysearch::File {
-            site_id: 123,
-            site_info: site_info.clone(),
-            ..Default::default()
-        }];
+        if let Some(hash) = hash {
+            if let Some(matches) = matches.get(&hash) {
+                args.push(serde_json::to_value(&PrecomputedMatches {
+                    hash,
+                    matches: matches.clone(),
+                })?);
+            }
+        }
 
-        let resp = already_had_source(&conn, &message, &sources).await;
-        assert!(resp.is_ok(), "filtering should not cause an error");
-        assert_eq!(
-            resp.unwrap(),
-            false,
-            "filtering with no results should have no status flag"
-        );
+        let job = faktory::Job::new("channel_update", args).on_queue(QUEUE_LOW);
+        handler.enqueue(job).await;
+    }
 
-        let resp = already_had_source(&conn, &message, &sources).await;
-        assert!(resp.is_ok(), "filtering should not cause an error");
+    Ok(())
+}
+
+/// Rebuild a channel post from a copy that was forwarded to another chat.
+///
+/// Returns `None` if the post was itself forwarded from somewhere else, can't
+/// be edited, or has nothing to source.
+fn backfill_message(
+    forwarded: tgbotapi::Message,
+    channel_id: i64,
+    message_id: i32,
+    max_document_size: usize,
+) -> Option<tgbotapi::Message> {
+    // Posts with existing keyboards were likely sent by a bot and can't be
+    // edited.
+    if forwarded.reply_markup.is_some() {
+        return None;
+    }
+
+    let chat = forwarded
+        .forward_from_chat
+        .clone()
+        .filter(|chat| chat.id == channel_id)?;
+
+    find_sourceable_file(&forwarded, max_document_size)?;
+
+    Some(tgbotapi::Message {
+        message_id,
+        chat,
+        from: None,
+        forward_from_chat: None,
+        forward_from_message_id: None,
+        forward_date: None,
+        ..forwarded
+    })
+}
+
+/// Reply to a channel post in its linked discussion group with full source
+/// details.
+///
+/// The bot only learns which discussion message belongs to a post once
+/// Telegram forwards it into the group, so this may need to wait for that
+/// update to arrive. Sending the reply is handled by the `group_source` job.
+#[tracing::instrument(skip(handler, job), fields(job_id = job.id()))]
+#[deny(clippy::unwrap_used)]
+pub async fn process_channel_discussion(
+    handler: Arc<Handler>,
+    job: faktory::Job,
+) -> Result<(), Error> {
+    let data: serde_json::Value = job
+        .args()
+        .iter()
+        .next()
+        .ok_or(Error::MissingData)?
+        .to_owned();
+
+    tracing::trace!("got enqueued discussion reply: {:?}", data);
+
+    let reply: DiscussionReply = serde_json::value::from_value(data)?;
+
+    let (chat_id, reply_to_message_id) =
+        match discussion_thread(&handler.redis, reply.channel_id, reply.message_id).await? {
+            Some(thread) => thread,
+            None if reply.attempt < DISCUSSION_ATTEMPTS => {
+                tracing::trace!(attempt = reply.attempt, "discussion thread not found yet");
+
+                let data = serde_json::to_value(&DiscussionReply {
+                    attempt: reply.attempt + 1,
+                    ..reply
+                })?;
+
+                let mut job =
+                    faktory::Job::new("channel_discussion", vec![data]).on_queue(&job.queue);
+                job.at = Some(
+                    chrono::offset::Utc::now()
+                        .add(chrono::Duration::seconds(DISCUSSION_RETRY_DELAY)),
+                );
+                job.custom = get_faktory_custom();
+
+                handler.enqueue(job).await;
+
+                return Ok(());
+            }
+            None => {
+                tracing::debug!("channel post was never forwarded to a discussion group");
+
+                return Ok(());
+            }
+        };
+
+    let data = serde_json::to_value(&GroupSource {
+        chat_id: chat_id.to_string(),
+        reply_to_message_id,
+        text: reply.text,
+        source_button: None,
+        sources: vec![],
+        hash: None,
+        source_hashes: vec![],
+        artist_choices: vec![],
+    })?;
+
+    let mut job = faktory::Job::new("group_source", vec![data]).on_queue(&job.queue);
+    job.custom = get_faktory_custom();
+
+    handler.enqueue(job).await;
+
+    Ok(())
+}
+
+/// Enqueue a reply to a channel post in its discussion group.
+async fn enqueue_discussion_reply(
+    handler: &Handler,
+    queue: &str,
+    channel_id: i64,
+    message_id: i32,
+    text: String,
+) -> Result<(), Error> {
+    let data = serde_json::to_value(&DiscussionReply {
+        channel_id,
+        message_id,
+        text,
+        attempt: 0,
+    })?;
+
+    let mut job = faktory::Job::new("channel_discussion", vec![data]).on_queue(queue);
+    job.custom = get_faktory_custom();
+
+    handler.enqueue(job).await;
+
+    Ok(())
+}
+
+/// Post sources that didn't fit in a channel post's caption as a reply in
+/// its discussion group.
+///
+/// Channels that already get a discussion reply with every source don't get
+/// another, and channels with a fallback reply get it instead of just the
+/// sources that didn't fit.
+async fn enqueue_overflow_reply(
+    handler: &Handler,
+    queue: &str,
+    chat_id: &str,
+    message_id: i32,
+    template: &SourceTemplate,
+    discussion_text: Option<String>,
+    overflow: Vec<String>,
+) -> Result<(), Error> {
+    if overflow.is_empty() || template.companion == CompanionReplies::Also {
+        return Ok(());
+    }
+
+    let channel_id = chat_id.parse().map_err(|_| Error::MissingData)?;
+
+    let text = match discussion_text {
+        Some(text) => text,
+        None => {
+            handler
+                .get_fluent_bundle(None, |bundle| {
+                    let mut text =
+                        get_message(bundle, "channel-overflow-header", None).unwrap_or_default();
+
+                    for line in &overflow {
+                        text.push('\n');
+                        text.push_str(line);
+                    }
+
+                    text
+                })
+                .await
+        }
+    };
+
+    enqueue_discussion_reply(handler, queue, channel_id, message_id, text).await
+}
+
+/// Find sources for a file, reusing those another channel in the same network
+/// already found.
+///
+/// Files are first looked up by ID, which skips downloading them, then by
+/// image hash so reuploaded copies still skip FuzzySearch. Networks always
+/// search at the largest distance so each channel can apply its own.
+async fn match_file_in_network(
+    handler: &Handler,
+    network: &str,
+    file: SourceableFile<'_>,
+) -> anyhow::Result<(i64, Vec<fuzzysearch::File>)> {
+    use redis::AsyncCommands;
+
+    let mut redis = handler.redis.clone();
+
+    let file_key = format!("channel-network:{}:file:{}", network, file.file_unique_id);
+    let cached: Option<String> = redis.get(&file_key).await?;
+    if let Some(PrecomputedMatches { hash, matches }) =
+        cached.and_then(|cached| serde_json::from_str(&cached).ok())
+    {
+        tracing::debug!(network, "reusing sources for file from channel network");
+        return Ok((hash, matches));
+    }
+
+    let hash = hash_file(&handler.telegram, &handler.conn, file).await?;
+
+    let hash_key = format!("channel-network:{}:hash:{}", network, hash);
+    let cached: Option<String> = redis.get(&hash_key).await?;
+    let matches = match cached.and_then(|cached| serde_json::from_str(&cached).ok()) {
+        Some(matches) => {
+            tracing::debug!(network, "reusing sources for hash from channel network");
+            matches
+        }
+        None => {
+            let (_hash, matches) = match_file(
+                &handler.telegram,
+                &handler.conn,
+                &handler.fuzzysearch,
+                file,
+                Some(foxbot_models::MAX_SOURCE_DISTANCE as i64),
+                None,
+            )
+            .await?;
+
+            matches
+        }
+    };
+
+    let precomputed = PrecomputedMatches { hash, matches };
+
+    let _: () = redis::pipe()
+        .set_ex(
+            &file_key,
+            serde_json::to_string(&precomputed)?,
+            NETWORK_SOURCES_TTL,
+        )
+        .ignore()
+        .set_ex(
+            &hash_key,
+            serde_json::to_string(&precomputed.matches)?,
+            NETWORK_SOURCES_TTL,
+        )
+        .ignore()
+        .query_async(&mut redis)
+        .await?;
+
+    Ok((precomputed.hash, precomputed.matches))
+}
+
+/// Look up the discussion group chat and message ID a channel post was
+/// forwarded to. These are saved by the bot's discussion handler.
+async fn discussion_thread(
+    conn: &redis::aio::ConnectionManager,
+    channel_id: i64,
+    message_id: i32,
+) -> anyhow::Result<Option<(i64, i32)>> {
+    use redis::AsyncCommands;
+
+    let mut conn = conn.clone();
+
+    let key = format!("discussion:{}:{}", channel_id, message_id);
+    let value: Option<String> = conn.get(key).await?;
+
+    Ok(value.as_deref().and_then(parse_discussion_thread))
+}
+
+fn parse_discussion_thread(value: &str) -> Option<(i64, i32)> {
+    let mut parts = value.splitn(2, ':');
+
+    let chat_id = parts.next()?.parse().ok()?;
+    let message_id = parts.next()?.parse().ok()?;
+
+    Some((chat_id, message_id))
+}
+
+/// Build a discussion reply listing every match along with its artists.
+fn build_discussion_text(bundle: &LangBundle, matches: &[fuzzysearch::File]) -> String {
+    let mut buf = get_message(bundle, "discussion-header", None).unwrap_or_default();
+
+    let mut seen = std::collections::HashSet::new();
+
+    for file in matches {
+        if seen.len() >= MAX_DISCUSSION_SOURCES {
+            break;
+        }
+
+        let url = file.url();
+        if !seen.insert(url.clone()) {
+            continue;
+        }
+
+        let mut args = fluent::FluentArgs::new();
+        args.insert("link", url.into());
+
+        let line = match file.artists.as_deref() {
+            Some(artists) if !artists.is_empty() => {
+                args.insert("artists", artists.join(", ").into());
+                get_message(bundle, "discussion-source-artists", Some(args))
+            }
+            _ => get_message(bundle, "discussion-source", Some(args)),
+        };
+
+        buf.push('\n');
+        buf.push_str(&line.unwrap_or_default());
+    }
+
+    buf
+}
+
+/// Append source links to an existing caption, keeping as many links as fit
+/// within Telegram's caption length limit.
+///
+/// Returns `None` if none of the links could be added.
+fn append_caption(existing: Option<&str>, links: &[String]) -> Option<String> {
+    let mut caption = existing.unwrap_or_default().to_string();
+    let mut added = false;
+
+    for link in links {
+        let separator = if caption.is_empty() { "" } else { "\n" };
+
+        let len = caption.encode_utf16().count()
+            + separator.encode_utf16().count()
+            + link.encode_utf16().count();
+        if len > MAX_CAPTION_LENGTH {
+            break;
+        }
+
+        caption.push_str(separator);
+        caption.push_str(link);
+        added = true;
+    }
+
+    if added {
+        Some(caption)
+    } else {
+        None
+    }
+}
+
+/// Sources that fit in a caption, and those that had to be left out.
+#[derive(Debug, PartialEq)]
+struct CaptionSummary {
+    caption: Option<String>,
+    overflow: Vec<String>,
+}
+
+/// Add as many sources to a caption as fit within Telegram's caption length
+/// limit.
+///
+/// The closest source from each site is added first, in site order, then any
+/// additional sources from each site. A source that doesn't fit is skipped so
+/// shorter sources after it may still be added, and hashtags are only added
+/// if there's room after the sources.
+fn summarize_caption(
+    existing: Option<&str>,
+    sources: &[(Sites, String)],
+    template: &SourceTemplate,
+    hashtags: Option<String>,
+) -> CaptionSummary {
+    let mut counts: std::collections::HashMap<&Sites, usize> = Default::default();
+    let mut ranked: Vec<(usize, String)> = sources
+        .iter()
+        .map(|(site, url)| {
+            let count = counts.entry(site).or_default();
+            let rank = *count;
+            *count += 1;
+
+            (rank, template.format_source(site.as_str(), url))
+        })
+        .collect();
+    ranked.sort_by_key(|(rank, _line)| *rank);
+
+    let mut caption = existing.unwrap_or_default().to_string();
+    let mut added = false;
+    let mut overflow = vec![];
+
+    for (_rank, line) in ranked {
+        if push_caption_line(&mut caption, &line) {
+            added = true;
+        } else {
+            overflow.push(line);
+        }
+    }
+
+    if added {
+        if let Some(hashtags) = hashtags {
+            push_caption_line(&mut caption, &hashtags);
+        }
+    }
+
+    CaptionSummary {
+        caption: if added { Some(caption) } else { None },
+        overflow,
+    }
+}
+
+/// Add a line to a caption if it fits, returning if it was added.
+fn push_caption_line(caption: &mut String, line: &str) -> bool {
+    let separator = if caption.is_empty() { "" } else { "\n" };
+
+    let len = caption.encode_utf16().count()
+        + separator.encode_utf16().count()
+        + line.encode_utf16().count();
+    if len > MAX_CAPTION_LENGTH {
+        return false;
+    }
+
+    caption.push_str(separator);
+    caption.push_str(line);
+
+    true
+}
+
+/// Convert artist names into a line of hashtags, removing any characters that
+/// can't be part of a hashtag.
+///
+/// Telegram doesn't treat hashtags of only numbers as hashtags, and the same
+/// artist is often found on multiple sites, so those are skipped.
+fn format_hashtags(artists: &[String]) -> Option<String> {
+    let mut seen = std::collections::HashSet::new();
+
+    let hashtags: Vec<_> = artists
+        .iter()
+        .map(|artist| {
+            artist
+                .chars()
+                .filter(|c| c.is_alphanumeric() || *c == '_')
+                .collect::<String>()
+        })
+        .filter(|artist| artist.chars().any(|c| !c.is_numeric()))
+        .filter(|artist| seen.insert(artist.to_lowercase()))
+        .map(|artist| format!("#{}", artist))
+        .collect();
+
+    if hashtags.is_empty() {
+        None
+    } else {
+        Some(hashtags.join(" "))
+    }
+}
+
+/// Label for a source button, including how similar the image was if a
+/// distance was given.
+fn source_button_text(bundle: &LangBundle, site: &str, distance: Option<u64>) -> String {
+    let mut args = fluent::FluentArgs::new();
+    args.insert("site", site.into());
+
+    match distance {
+        Some(0) => get_message(bundle, "source-button-exact", Some(args)).unwrap(),
+        Some(distance) => {
+            args.insert("distance", distance.into());
+            get_message(bundle, "source-button-distance", Some(args)).unwrap()
+        }
+        None => site.to_string(),
+    }
+}
+
+/// Telegram only shows a caption on a media group if there is a single caption
+/// anywhere in the group. When users upload a group, we need to check if we can
+/// only set a single source to make the link more visible. This can be done by
+/// ensuring our source has been previouly used in the media group.
+///
+/// For our implementation, this is done by maintaining a Redis set of every
+/// source previously displayed. If adding our source links returns fewer
+/// inserted than we had, it means a link was previously used and therefore we
+/// do not have to set a source.
+///
+/// Because Telegram doesn't send media groups at once, we have to store these
+/// values until we're sure the group is over. In this case, we will store
+/// values for 300 seconds.
+///
+/// No link normalization is required here because all links are already
+/// normalized when coming from FuzzySearch.
+async fn already_had_source(
+    conn: &redis::aio::ConnectionManager,
+    media_group_id: Option<&str>,
+    matches: &[fuzzysearch::File],
+) -> anyhow::Result<bool> {
+    use redis::AsyncCommands;
+
+    let group_id = match media_group_id {
+        Some(id) => id,
+        _ => return Ok(false),
+    };
+
+    let key = format!("group-sources:{}", group_id);
+
+    let mut urls: Vec<_> = matches.iter().map(|m| m.url()).collect();
+    urls.sort();
+    urls.dedup();
+    let source_count = urls.len();
+
+    tracing::trace!(%group_id, "adding new sources: {:?}", urls);
+
+    let mut conn = conn.clone();
+    let added_links: usize = conn.sadd(&key, urls).await?;
+    conn.expire(&key, 300).await?;
+
+    tracing::debug!(
+        source_count,
+        added_links,
+        "determined existing and new source links"
+    );
+
+    Ok(source_count > added_links)
+}
+
+/// Check if any of the provided image URLs have a hash similar to the given
+/// input.
+///
+/// Images are downloaded a few at a time and checking stops as soon as a
+/// similar one is found.
+#[tracing::instrument(skip(urls))]
+async fn has_similar_hash(to: i64, urls: &[&str]) -> bool {
+    use futures::StreamExt;
+
+    let to = to.to_be_bytes();
+
+    let mut hashes = futures::stream::iter(urls.iter().copied())
+        .map(|url| async move { (url, hash_url(url).await) })
+        .buffer_unordered(SIMILAR_HASH_CONCURRENCY);
+
+    while let Some((url, hash)) = hashes.next().await {
+        let hash = match hash {
+            Some(hash) => hash,
+            _ => continue,
+        };
+
+        if hamming::distance_fast(&to, &hash).unwrap() <= 3 {
+            tracing::debug!(url, hash = i64::from_be_bytes(hash), "hashes were similar");
+
+            return true;
+        }
+    }
+
+    false
+}
+
+/// Use rehosted images for posts from sites that need it.
+async fn rehost_results(
+    handler: &Handler,
+    results: Vec<foxbot_sites::PostInfo>,
+) -> Vec<foxbot_sites::PostInfo> {
+    let media_cache = match &handler.media_cache {
+        Some(media_cache) => media_cache,
+        None => return results,
+    };
+
+    let mut rehosted = Vec::with_capacity(results.len());
+
+    for post in results {
+        match media_cache.rehost(&handler.conn, &post).await {
+            Ok(post) => rehosted.push(post),
+            Err(err) => {
+                tracing::warn!("unable to rehost channel post link: {:?}", err);
+                rehosted.push(post);
+            }
+        }
+    }
+
+    rehosted
+}
+
+/// Download and hash an image, returning nothing if it couldn't be loaded.
+async fn hash_url(url: &str) -> Option<[u8; 8]> {
+    let check_size = CheckFileSize::new(url, 50_000_000);
+    let bytes = match check_size.into_bytes().await {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            tracing::warn!(url, "unable to download image: {:?}", err);
+
+            return None;
+        }
+    };
+
+    let hash = decode::run(move || {
+        use std::convert::TryInto;
+
+        let hasher = fuzzysearch::get_hasher();
+
+        let im = match image::load_from_memory(&bytes) {
+            Ok(im) => im,
+            Err(err) => {
+                tracing::warn!("unable to load image: {:?}", err);
+
+                return None;
+            }
+        };
+
+        let hash = hasher.hash_image(&im);
+        let bytes: [u8; 8] = hash.as_bytes().try_into().unwrap_or_default();
+
+        Some(bytes)
+    })
+    .await;
+
+    match hash {
+        Ok(hash) => hash,
+        Err(err) => {
+            tracing::warn!(url, "unable to hash image: {:?}", err);
+
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_format_hashtags() {
+        use super::format_hashtags;
+
+        assert_eq!(
+            format_hashtags(&[]),
+            None,
+            "no artists should have no hashtags"
+        );
+        assert_eq!(
+            format_hashtags(&["syfaro".to_string(), "some-artist".to_string()]).as_deref(),
+            Some("#syfaro #someartist"),
+            "artists should be converted to hashtags"
+        );
+        assert_eq!(
+            format_hashtags(&["---".to_string()]),
+            None,
+            "artists with no valid characters should be skipped"
+        );
+        assert_eq!(
+            format_hashtags(&["1234".to_string(), "artist_1234".to_string()]).as_deref(),
+            Some("#artist_1234"),
+            "artists with only numbers should be skipped"
+        );
+        assert_eq!(
+            format_hashtags(&["Syfaro".to_string(), "syfaro".to_string()]).as_deref(),
+            Some("#Syfaro"),
+            "the same artist from multiple sites should only be added once"
+        );
+    }
+
+    #[test]
+    fn test_dedup_keys() {
+        use crate::dedup_keys;
+        use foxbot_utils::payload::JobMessage;
+
+        let message = JobMessage {
+            chat_id: -100123,
+            message_id: 42,
+            file_id: "photo".to_string(),
+            file_unique_id: "photo-unique".to_string(),
+            ..Default::default()
+        };
+
+        let keys: Vec<_> = dedup_keys("channel_update", &message)
+            .into_iter()
+            .map(|(key, _ttl)| key)
+            .collect();
+
+        assert_eq!(
+            keys,
+            vec![
+                "dedup:channel_update:-100123:42",
+                "dedup:channel_update:-100123:file:photo-unique"
+            ],
+            "keys should include chat, message, and file"
+        );
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn test_retried_job_claims_keys() {
+        use crate::{claim_job, dedup_keys};
+        use foxbot_utils::payload::JobMessage;
+
+        let mut conn = get_redis().await;
+        let _: () = redis::cmd("FLUSHDB").query_async(&mut conn).await.unwrap();
+
+        let message = JobMessage {
+            chat_id: -100123,
+            message_id: 42,
+            file_unique_id: "photo-unique".to_string(),
+            ..Default::default()
+        };
+        let keys = dedup_keys("channel_update", &message);
+
+        let job = faktory::Job::new(
+            "channel_update",
+            vec![serde_json::to_value(&message).unwrap()],
+        );
+        assert!(
+            claim_job(&conn, job.id(), &keys).await,
+            "first job should claim keys"
+        );
+
+        let failed = foxbot_models::FailedJob {
+            id: 1,
+            job_id: job.id().to_string(),
+            kind: job.kind().to_string(),
+            queue: job.queue.clone(),
+            args: serde_json::Value::Array(job.args().to_vec()),
+        };
+        let retried = foxbot_utils::retry_failed_job(failed).unwrap();
+
+        assert_eq!(retried.id(), job.id(), "retried job should keep its id");
+        assert_eq!(
+            retried.args(),
+            job.args(),
+            "retried job should keep its args"
+        );
+        assert!(
+            claim_job(&conn, retried.id(), &keys).await,
+            "retried job should still own its keys"
+        );
+
+        let other = faktory::Job::new("channel_update", job.args().to_vec());
+        assert!(
+            !claim_job(&conn, other.id(), &keys).await,
+            "other jobs for the same message should be skipped"
+        );
+    }
+
+    #[test]
+    fn test_backfill_message() {
+        use super::backfill_message;
+        use foxbot_utils::bot_api::HOSTED_DOWNLOAD_LIMIT;
+
+        let channel = tgbotapi::Chat {
+            id: -100123,
+            chat_type: tgbotapi::ChatType::Channel,
+            ..Default::default()
+        };
+
+        let photo = vec![tgbotapi::PhotoSize {
+            file_id: "photo".to_string(),
+            file_unique_id: "photo-unique".to_string(),
+            ..Default::default()
+        }];
+
+        let forwarded = tgbotapi::Message {
+            message_id: 9001,
+            forward_from_chat: Some(channel.clone()),
+            forward_from_message_id: Some(42),
+            forward_date: Some(0),
+            photo: Some(photo.clone()),
+            ..Default::default()
+        };
+
+        let message = backfill_message(forwarded.clone(), channel.id, 42, HOSTED_DOWNLOAD_LIMIT)
+            .expect("photo from channel should be rebuilt");
+        assert_eq!(message.message_id, 42, "message should use channel ID");
+        assert_eq!(message.chat.id, channel.id, "message should be in channel");
+        assert!(
+            message.forward_date.is_none(),
+            "message should not appear forwarded"
+        );
+
+        assert!(
+            backfill_message(forwarded.clone(), -100456, 42, HOSTED_DOWNLOAD_LIMIT).is_none(),
+            "posts forwarded from other channels should be skipped"
+        );
+
+        let text_only = tgbotapi::Message {
+            photo: None,
+            ..forwarded.clone()
+        };
+        assert!(
+            backfill_message(text_only, channel.id, 42, HOSTED_DOWNLOAD_LIMIT).is_none(),
+            "posts without media should be skipped"
+        );
+
+        let with_markup = tgbotapi::Message {
+            reply_markup: Some(tgbotapi::InlineKeyboardMarkup {
+                inline_keyboard: Default::default(),
+            }),
+            ..forwarded
+        };
+        assert!(
+            backfill_message(with_markup, channel.id, 42, HOSTED_DOWNLOAD_LIMIT).is_none(),
+            "posts with keyboards should be skipped"
+        );
+    }
+
+    #[test]
+    fn test_parse_discussion_thread() {
+        use super::parse_discussion_thread;
+
+        assert_eq!(
+            parse_discussion_thread("-1001234:56"),
+            Some((-1001234, 56)),
+            "thread should be parsed into chat and message IDs"
+        );
+        assert_eq!(
+            parse_discussion_thread("-1001234"),
+            None,
+            "missing message ID should not be parsed"
+        );
+        assert_eq!(
+            parse_discussion_thread("chat:message"),
+            None,
+            "invalid IDs should not be parsed"
+        );
+    }
+
+    #[test]
+    fn test_summarize_caption() {
+        use super::{summarize_caption, CaptionSummary};
+        use foxbot_models::{Sites, SourceTemplate};
+
+        let template = SourceTemplate::default();
+        let sources = vec![
+            (
+                Sites::FurAffinity,
+                "https://www.furaffinity.net/view/1/".to_string(),
+            ),
+            (
+                Sites::FurAffinity,
+                "https://www.furaffinity.net/view/2/".to_string(),
+            ),
+            (Sites::E621, "https://e621.net/posts/1".to_string()),
+        ];
+
+        assert_eq!(
+            summarize_caption(None, &sources, &template, Some("#syfaro".to_string())),
+            CaptionSummary {
+                caption: Some(
+                    "https://www.furaffinity.net/view/1/\nhttps://e621.net/posts/1\nhttps://www.furaffinity.net/view/2/\n#syfaro"
+                        .to_string()
+                ),
+                overflow: vec![],
+            },
+            "the closest source from each site should come first"
+        );
+
+        let long = "a".repeat(990);
+        assert_eq!(
+            summarize_caption(
+                Some(&long),
+                &sources,
+                &template,
+                Some("#syfaro".to_string())
+            ),
+            CaptionSummary {
+                caption: Some(format!("{}\nhttps://e621.net/posts/1\n#syfaro", long)),
+                overflow: vec![
+                    "https://www.furaffinity.net/view/1/".to_string(),
+                    "https://www.furaffinity.net/view/2/".to_string(),
+                ],
+            },
+            "shorter sources should still be added when longer ones don't fit"
+        );
+
+        let full = "a".repeat(1024);
+        assert_eq!(
+            summarize_caption(Some(&full), &sources, &template, None).caption,
+            None,
+            "nothing should be added to a full caption"
+        );
+    }
+
+    #[test]
+    fn test_append_caption() {
+        use super::append_caption;
+
+        let links = vec![
+            "https://e621.net/posts/1".to_string(),
+            "https://twitter.com/a/status/1".to_string(),
+        ];
+
+        assert_eq!(
+            append_caption(None, &links).as_deref(),
+            Some("https://e621.net/posts/1\nhttps://twitter.com/a/status/1"),
+            "links should be joined when there was no caption"
+        );
+
+        assert_eq!(
+            append_caption(Some("Hello 🦊"), &links[..1]).as_deref(),
+            Some("Hello 🦊\nhttps://e621.net/posts/1"),
+            "links should be added after an existing caption"
+        );
+
+        let long = "a".repeat(990);
+        assert_eq!(
+            append_caption(Some(&long), &links),
+            Some(format!("{}\nhttps://e621.net/posts/1", long)),
+            "links should be dropped when they would exceed the limit"
+        );
+
+        let full = "a".repeat(1024);
+        assert_eq!(
+            append_caption(Some(&full), &links),
+            None,
+            "full captions should not be edited"
+        );
+    }
+
+    async fn get_redis() -> redis::aio::ConnectionManager {
+        let redis_client =
+            redis::Client::open(std::env::var("REDIS_DSN").expect("Missing REDIS_DSN")).unwrap();
+        redis::aio::ConnectionManager::new(redis_client)
+            .await
+            .expect("unable to open Redis connection")
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn test_already_had_source() {
+        let _ = tracing_subscriber::fmt::try_init();
+
+        use super::already_had_source;
+
+        let mut conn = get_redis().await;
+        let _: () = redis::cmd("FLUSHDB").query_async(&mut conn).await.unwrap();
+
+        let site_info = Some(fuzzysearch::SiteInfo::FurAffinity(
+            fuzzysearch::FurAffinityFile { file_id: 123 },
+        ));
+
+        let sources = vec![fuzzysearch::File {
+            site_id: 123,
+            site_info: site_info.clone(),
+            ..Default::default()
+        }];
+
+        let resp = already_had_source(&conn, Some("test-group"), &sources).await;
+        assert!(resp.is_ok(), "filtering should not cause an error");
+        assert_eq!(
+            resp.unwrap(),
+            false,
+            "filtering with no results should have no status flag"
+        );
+
+        let resp = already_had_source(&conn, Some("test-group"), &sources).await;
+        assert!(resp.is_ok(), "filtering should not cause an error");
         assert_eq!(
             resp.unwrap(),
             true,
@@ -405,7 +1804,7 @@ mod tests {
             ..Default::default()
         }];
 
-        let resp = already_had_source(&conn, &message, &sources).await;
+        let resp = already_had_source(&conn, Some("test-group"), &sources).await;
         assert!(resp.is_ok(), "filtering should not cause an error");
         assert_eq!(
             resp.unwrap(),
@@ -413,12 +1812,7 @@ mod tests {
             "filtering same group with new source should have no status flag"
         );
 
-        let message = tgbotapi::Message {
-            media_group_id: Some("test-group-2".to_string()),
-            ..Default::default()
-        };
-
-        let resp = already_had_source(&conn, &message, &sources).await;
+        let resp = already_had_source(&conn, Some("test-group-2"), &sources).await;
         assert!(resp.is_ok(), "filtering should not cause an error");
         assert_eq!(
             resp.unwrap(),
@@ -439,7 +1833,7 @@ mod tests {
             },
         ];
 
-        let resp = already_had_source(&conn, &message, &sources).await;
+        let resp = already_had_source(&conn, Some("test-group-2"), &sources).await;
         assert!(resp.is_ok(), "filtering should not cause an error");
         assert_eq!(
             resp.unwrap(),
@@ -0,0 +1,120 @@
+use foxbot_models::FailedJobs;
+
+use crate::*;
+
+/// Parts of a job needed to save it after it has been consumed by the job
+/// function.
+pub struct JobDetails {
+    id: String,
+    kind: String,
+    queue: String,
+    args: serde_json::Value,
+}
+
+impl JobDetails {
+    pub fn new(job: &faktory::Job) -> Self {
+        Self {
+            id: job.id().to_string(),
+            kind: job.kind().to_string(),
+            queue: job.queue.clone(),
+            args: serde_json::Value::Array(job.args().to_vec()),
+        }
+    }
+}
+
+/// Count a failed attempt for a job.
+///
+/// The error is returned so Faktory will retry the job until it has failed
+/// [`MAX_JOB_ATTEMPTS`] times. After that, the job is saved in the database,
+/// admins are notified, and it is marked as successful so Faktory stops
+/// retrying it.
+#[tracing::instrument(skip(handler, job, err), fields(job_id = %job.id))]
+pub async fn handle_failure(handler: &Handler, job: JobDetails, err: Error) -> Result<(), Error> {
+    let attempts = match record_attempt(&handler.redis, &job.id).await {
+        Ok(attempts) => attempts,
+        Err(redis_err) => {
+            tracing::error!("unable to record job attempt: {:?}", redis_err);
+
+            return Err(err);
+        }
+    };
+
+    if attempts < MAX_JOB_ATTEMPTS {
+        tracing::warn!(attempts, "job failed, will be retried: {:?}", err);
+
+        return Err(err);
+    }
+
+    tracing::error!(attempts, "job failed too many times: {:?}", err);
+
+    let error = err.to_string();
+
+    FailedJobs::insert(
+        &handler.conn,
+        &job.id,
+        &job.kind,
+        &job.queue,
+        job.args,
+        &error,
+    )
+    .await?;
+
+    // The job keeps its ID if it's retried, so it needs a fresh set of attempts.
+    if let Err(redis_err) = clear_attempts(&handler.redis, &job.id).await {
+        tracing::error!("unable to clear job attempts: {:?}", redis_err);
+    }
+
+    if let Some(admin_chat_id) = handler.admin_chat_id {
+        let text = handler
+            .get_fluent_bundle(None, |bundle| {
+                let mut args = fluent::FluentArgs::new();
+                args.insert("kind", job.kind.as_str().into());
+                args.insert("attempts", attempts.into());
+                args.insert("error", error.as_str().into());
+
+                get_message(bundle, "job-failed", Some(args))
+            })
+            .await
+            .unwrap_or(error);
+
+        let send_message = tgbotapi::requests::SendMessage {
+            chat_id: admin_chat_id.into(),
+            text,
+            disable_web_page_preview: Some(true),
+            ..Default::default()
+        };
+
+        if let Err(err) = handler.telegram.make_request(&send_message).await {
+            tracing::error!("unable to notify admins of failed job: {:?}", err);
+        }
+    }
+
+    Ok(())
+}
+
+/// Increment the number of times a job has failed, returning the new count.
+async fn record_attempt(
+    conn: &redis::aio::ConnectionManager,
+    job_id: &str,
+) -> redis::RedisResult<usize> {
+    use redis::AsyncCommands;
+
+    let mut conn = conn.clone();
+
+    let key = format!("job-attempts:{}", job_id);
+    let attempts: usize = conn.incr(&key, 1).await?;
+    conn.expire::<_, ()>(&key, JOB_ATTEMPTS_TTL).await?;
+
+    Ok(attempts)
+}
+
+/// Forget how many times a job has failed.
+async fn clear_attempts(
+    conn: &redis::aio::ConnectionManager,
+    job_id: &str,
+) -> redis::RedisResult<()> {
+    use redis::AsyncCommands;
+
+    let mut conn = conn.clone();
+    conn.del(format!("job-attempts:{}", job_id)).await
+}
@@ -1,4 +1,10 @@
+use anyhow::Context;
+
 use crate::*;
+use foxbot_utils::{
+    callback::{routes, CallbackData},
+    payload::JobMessage,
+};
 
 #[tracing::instrument(skip(handler, job), fields(job_id = job.id()))]
 #[deny(clippy::unwrap_used)]
@@ -12,29 +18,51 @@ pub async fn process_group_photo(handler: Arc<Handler>, job: faktory::Job) -> Re
         .ok_or(Error::MissingData)?
         .to_owned();
 
-    let message: tgbotapi::Message = serde_json::value::from_value(data)?;
-    let photo_sizes = match &message.photo {
-        Some(sizes) => sizes,
-        _ => return Ok(()),
-    };
+    let message = JobMessage::from_job(data, handler.download_limit)?;
+    let file = message.file();
 
     tracing::trace!("got enqueued message: {:?}", message);
 
-    match GroupConfig::get(&handler.conn, message.chat.id, GroupConfigKey::GroupAdd).await? {
-        Some(val) if val => (),
-        _ => return Ok(()),
+    let group_add = GroupConfig::get(&handler.conn, message.chat_id, GroupConfigKey::GroupAdd)
+        .await?
+        .unwrap_or(false);
+    let repost_warnings = GroupConfig::get(
+        &handler.conn,
+        message.chat_id,
+        GroupConfigKey::RepostWarnings,
+    )
+    .await?
+    .unwrap_or(false);
+
+    if !group_add && !repost_warnings {
+        return Ok(());
     }
 
-    let best_photo = find_best_photo(photo_sizes).unwrap();
-    let mut matches = match_image(
+    let keys = dedup_keys("group_photo", &message);
+    if !claim_job(&handler.redis, job.id(), &keys).await {
+        tracing::debug!("message or file was already processed");
+        return Ok(());
+    }
+
+    let distance = GroupConfig::source_distance(&handler.conn, message.chat_id).await?;
+
+    let (hash, mut matches) = match_file(
         &handler.telegram,
         &handler.conn,
         &handler.fuzzysearch,
-        best_photo,
-        Some(3),
+        file,
+        Some(distance as i64),
+        Some(message.chat_id),
     )
-    .await?
-    .1;
+    .await?;
+
+    if repost_warnings {
+        warn_if_repost(&handler, &message, hash).await?;
+    }
+
+    if !group_add {
+        return Ok(());
+    }
     sort_results(
         &handler.conn,
         message.from.as_ref().unwrap().id,
@@ -42,16 +70,19 @@ pub async fn process_group_photo(handler: Arc<Handler>, job: faktory::Job) -> Re
     )
     .await?;
 
-    let wanted_matches = matches
+    let blocked = GroupConfig::blocked_sites(&handler.conn, message.chat_id).await?;
+    remove_blocked_sites(&blocked, &mut matches);
+
+    let mut wanted_matches = matches
         .iter()
-        .filter(|m| m.distance.unwrap() <= MAX_SOURCE_DISTANCE)
+        .filter(|m| m.distance.unwrap() <= distance)
         .collect::<Vec<_>>();
 
     if wanted_matches.is_empty() {
         return Ok(());
     }
 
-    let links = extract_links(&message);
+    let links = message.links();
     let sites = handler.sites.lock().await;
 
     if wanted_matches
@@ -79,60 +110,157 @@ pub async fn process_group_photo(handler: Arc<Handler>, job: faktory::Job) -> Re
         return Ok(());
     }
 
+    let silent = GroupConfig::get(
+        &handler.conn,
+        message.chat_id,
+        GroupConfigKey::SilentSourcing,
+    )
+    .await?
+    .unwrap_or(false);
+    if silent {
+        return react_with_source(&handler, &message).await;
+    }
+
     let lang = message
         .from
         .as_ref()
         .and_then(|from| from.language_code.as_deref());
 
+    // Albums are collected and flushed together so only a single reply is
+    // sent for the whole group.
+    if let Some(media_group_id) = &message.media_group_id {
+        return buffer_album_matches(
+            &handler,
+            &message,
+            media_group_id,
+            lang,
+            hash,
+            &wanted_matches,
+        )
+        .await;
+    }
+
+    // Use the artist someone already chose for this image instead of asking
+    // again.
+    let mut choices = artist_choices(&wanted_matches);
+    if let Some(chosen) =
+        apply_chosen_artist(&handler.conn, message.chat_id, hash, &mut choices).await?
+    {
+        wanted_matches = chosen;
+    }
+
+    let details = e621_details(&handler, &wanted_matches).await;
+
     let text = handler
         .get_fluent_bundle(lang, |bundle| {
-            if wanted_matches.len() == 1 {
-                let mut args = fluent::FluentArgs::new();
-                let m = wanted_matches.first().unwrap();
-                args.insert("link", m.url().into());
+            let text = build_source_text(bundle, &wanted_matches);
 
-                if let Some(rating) = get_rating_bundle_name(&m.rating) {
-                    args.insert("rating", rating.into());
+            match details.and_then(|post| build_post_details(bundle, &post)) {
+                Some(details) => format!("{}\n\n{}", text.trim_end(), details),
+                None => text,
+            }
+        })
+        .await;
 
-                    get_message(bundle, "automatic-single", Some(args)).unwrap()
-                } else {
-                    get_message(bundle, "automatic-single-unknown", Some(args)).unwrap()
-                }
-            } else {
-                let mut buf = String::new();
+    // Images forwarded from elsewhere already point back to where they were
+    // posted, so only offer the sources instead of replying with them.
+    if let Some(origin) = message.forward_origin.clone() {
+        return offer_forwarded_source(&handler, &message, lang, origin, text).await;
+    }
 
-                buf.push_str(&get_message(bundle, "automatic-multiple", None).unwrap());
-                buf.push('\n');
+    if !choices.is_empty() {
+        return offer_artist_choices(&handler, &message, lang, hash, choices).await;
+    }
 
-                for result in wanted_matches {
-                    let mut args = fluent::FluentArgs::new();
-                    args.insert("link", result.url().into());
-
-                    let message = if let Some(rating) = get_rating_bundle_name(&result.rating) {
-                        let rating = get_message(bundle, rating, None).unwrap();
-                        args.insert("rating", rating.into());
-                        get_message(bundle, "automatic-multiple-result", Some(args)).unwrap()
-                    } else {
-                        get_message(bundle, "automatic-multiple-result-unknown", Some(args))
-                            .unwrap()
-                    };
-
-                    buf.push_str(&message);
-                    buf.push('\n');
-                }
+    let data = serde_json::to_value(&GroupSource {
+        chat_id: message.chat_id.to_string(),
+        reply_to_message_id: message.message_id,
+        text,
+        source_button: None,
+        sources: wanted_matches.iter().map(|file| file.url()).collect(),
+        hash: Some(hash),
+        source_hashes: vec![],
+        artist_choices: vec![],
+    })?;
 
-                buf
-            }
+    let mut job = faktory::Job::new("group_source", vec![data]).on_queue(QUEUE_HIGH);
+    job.custom = get_faktory_custom();
+
+    handler.enqueue(job).await;
+
+    Ok(())
+}
+
+#[tracing::instrument(skip(handler, job), fields(job_id = job.id()))]
+#[deny(clippy::unwrap_used)]
+pub async fn process_group_album(handler: Arc<Handler>, job: faktory::Job) -> Result<(), Error> {
+    let data: serde_json::Value = job
+        .args()
+        .iter()
+        .next()
+        .ok_or(Error::MissingData)?
+        .to_owned();
+
+    let GroupAlbum {
+        chat_id,
+        media_group_id,
+        reply_to_message_id,
+        lang,
+    } = serde_json::value::from_value(data)?;
+
+    let key = format!("group-album:{}", media_group_id);
+
+    // Matches pushed while flushing must either be included here or remain
+    // for the next flush, so they're read and removed at once.
+    let mut conn = handler.redis.clone();
+    let (items, _): (Vec<String>, usize) = redis::pipe()
+        .atomic()
+        .lrange(&key, 0, -1)
+        .del(&key)
+        .query_async(&mut conn)
+        .await
+        .context("unable to get album matches")?;
+
+    let mut seen_urls = std::collections::HashSet::new();
+    let mut matches = Vec::with_capacity(items.len());
+    let mut source_hashes = Vec::with_capacity(items.len());
+    for item in items {
+        let (hash, file): (i64, fuzzysearch::File) = serde_json::from_str(&item)?;
+        if seen_urls.insert(file.url()) {
+            matches.push(file);
+            source_hashes.push(hash);
+        }
+    }
+
+    tracing::debug!(
+        media_group_id = %media_group_id,
+        count = matches.len(),
+        "flushing album matches"
+    );
+
+    if matches.is_empty() {
+        return Ok(());
+    }
+
+    let matches: Vec<_> = matches.iter().collect();
+    let text = handler
+        .get_fluent_bundle(lang.as_deref(), |bundle| {
+            build_source_text(bundle, &matches)
         })
         .await;
 
     let data = serde_json::to_value(&GroupSource {
-        chat_id: message.chat.id.to_string(),
-        reply_to_message_id: message.message_id,
+        chat_id,
+        reply_to_message_id,
         text,
+        source_button: None,
+        sources: matches.iter().map(|file| file.url()).collect(),
+        hash: None,
+        source_hashes,
+        artist_choices: vec![],
     })?;
 
-    let mut job = faktory::Job::new("group_source", vec![data]).on_queue("foxbot_background");
+    let mut job = faktory::Job::new("group_source", vec![data]).on_queue(QUEUE_HIGH);
     job.custom = get_faktory_custom();
 
     handler.enqueue(job).await;
@@ -158,13 +286,18 @@ pub async fn process_group_source(handler: Arc<Handler>, job: faktory::Job) -> R
         chat_id,
         reply_to_message_id,
         text,
+        source_button,
+        sources,
+        hash,
+        source_hashes,
+        artist_choices,
     } = serde_json::value::from_value(data.clone())?;
     let chat_id: &str = &chat_id;
 
     if let Some(at) = check_more_time(&handler.redis, chat_id).await {
         tracing::trace!("need to wait more time for this chat: {}", at);
 
-        let mut job = faktory::Job::new("group_source", vec![data]).on_queue("foxbot_background");
+        let mut job = faktory::Job::new("group_source", vec![data]).on_queue(QUEUE_HIGH);
         job.at = Some(at);
         job.custom = get_faktory_custom();
 
@@ -173,16 +306,80 @@ pub async fn process_group_source(handler: Arc<Handler>, job: faktory::Job) -> R
         return Ok(());
     }
 
+    // Replies either offer to show sources or let people report the sources
+    // they included as wrong.
+    let button = match (source_button, hash) {
+        (Some(text), _) => Some(tgbotapi::InlineKeyboardButton {
+            text,
+            callback_data: Some(CallbackData::new(routes::FORWARDED_SOURCE).encode()?),
+            ..Default::default()
+        }),
+        (None, Some(hash)) if !sources.is_empty() => Some(tgbotapi::InlineKeyboardButton {
+            text: WRONG_SOURCE_BUTTON.to_string(),
+            callback_data: Some(CallbackData::new(routes::WRONG_SOURCE).arg(hash).encode()?),
+            ..Default::default()
+        }),
+        // Sources for albums have a hash each, which are saved once the reply
+        // is sent.
+        (None, None) if !sources.is_empty() && source_hashes.len() == sources.len() => {
+            Some(tgbotapi::InlineKeyboardButton {
+                text: WRONG_SOURCE_BUTTON.to_string(),
+                callback_data: Some(CallbackData::new(routes::WRONG_SOURCE).encode()?),
+                ..Default::default()
+            })
+        }
+        _ => None,
+    };
+
+    // Asking which artist is correct needs a button for each artist instead.
+    let inline_keyboard = if !artist_choices.is_empty() {
+        Some(
+            artist_choices
+                .into_iter()
+                .enumerate()
+                .map(|(index, name)| {
+                    Ok(vec![tgbotapi::InlineKeyboardButton {
+                        text: name,
+                        callback_data: Some(
+                            CallbackData::new(routes::ARTIST_CHOICE)
+                                .arg(index)
+                                .encode()?,
+                        ),
+                        ..Default::default()
+                    }])
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?,
+        )
+    } else {
+        button.map(|button| vec![vec![button]])
+    };
+
+    let reply_markup = inline_keyboard.map(|inline_keyboard| {
+        tgbotapi::requests::ReplyMarkup::InlineKeyboardMarkup(tgbotapi::InlineKeyboardMarkup {
+            inline_keyboard,
+        })
+    });
+
     let message = SendMessage {
         chat_id: chat_id.into(),
         reply_to_message_id: Some(reply_to_message_id),
         disable_web_page_preview: Some(true),
         disable_notification: Some(true),
         text,
+        reply_markup,
         ..Default::default()
     };
 
-    match handler.telegram.make_request(&message).await {
+    // If the job is retried after the reply was sent, it shouldn't be sent
+    // again.
+    let key = format!("group_source:{}", job.id());
+
+    match handler
+        .telegram
+        .make_idempotent_request(&key, &message)
+        .await
+    {
+        Ok(None) => Ok(()),
         Err(tgbotapi::Error::Telegram(tgbotapi::TelegramError {
             parameters:
                 Some(tgbotapi::ResponseParameters {
@@ -198,8 +395,7 @@ pub async fn process_group_source(handler: Arc<Handler>, job: faktory::Job) -> R
 
             needs_more_time(&handler.redis, chat_id, retry_at).await;
 
-            let mut job =
-                faktory::Job::new("group_source", vec![data]).on_queue("foxbot_background");
+            let mut job = faktory::Job::new("group_source", vec![data]).on_queue(QUEUE_HIGH);
             job.at = Some(retry_at);
             job.custom = get_faktory_custom();
 
@@ -207,11 +403,391 @@ pub async fn process_group_source(handler: Arc<Handler>, job: faktory::Job) -> R
 
             Ok(())
         }
-        Ok(_)
-        | Err(tgbotapi::Error::Telegram(tgbotapi::TelegramError {
+        Ok(Some(sent)) => {
+            if let Ok(chat_id) = chat_id.parse() {
+                if hash.is_none() && !source_hashes.is_empty() {
+                    save_source_hashes(
+                        &handler,
+                        chat_id,
+                        sent.message_id,
+                        &sources,
+                        &source_hashes,
+                    )
+                    .await;
+                }
+
+                let action = foxbot_models::NewAction {
+                    chat_id,
+                    message_id: reply_to_message_id,
+                    reply_message_id: Some(sent.message_id),
+                    kind: foxbot_models::ActionKind::Reply,
+                    sources: &sources,
+                    job_id: Some(job.id()),
+                    previous_caption: None,
+                    previous_caption_entities: None,
+                };
+
+                actions::record(&handler, action).await;
+            }
+
+            Ok(())
+        }
+        Err(tgbotapi::Error::Telegram(tgbotapi::TelegramError {
             error_code: Some(400),
             ..
         })) => Ok(()),
         Err(err) => Err(err.into()),
     }
 }
+
+/// Save the hash each source in a reply was found for, so they can be
+/// reported as wrong.
+///
+/// Reports are optional, so any problem saving the hashes is only logged.
+async fn save_source_hashes(
+    handler: &Handler,
+    chat_id: i64,
+    message_id: i32,
+    sources: &[String],
+    hashes: &[i64],
+) {
+    use redis::AsyncCommands;
+
+    let pairs: Vec<(&String, &i64)> = sources.iter().zip(hashes).collect();
+    let value = match serde_json::to_string(&pairs) {
+        Ok(value) => value,
+        Err(err) => {
+            tracing::warn!("unable to encode source hashes: {:?}", err);
+            return;
+        }
+    };
+
+    let mut conn = handler.redis.clone();
+    if let Err(err) = conn
+        .set_ex::<_, _, ()>(
+            wrong_source_key(chat_id, message_id),
+            value,
+            WRONG_SOURCE_TTL,
+        )
+        .await
+    {
+        tracing::warn!("unable to save source hashes: {:?}", err);
+    }
+}
+
+/// Remember an image posted in a chat, replying with when it was first
+/// posted if it has been seen before.
+async fn warn_if_repost(handler: &Handler, message: &JobMessage, hash: i64) -> Result<(), Error> {
+    use foxbot_models::ChatImageHashes;
+
+    let previous = ChatImageHashes::first_similar(&handler.conn, message.chat_id, hash).await?;
+
+    let posted_by = message.from.as_ref().map(|from| match &from.username {
+        Some(username) => format!("@{}", username),
+        None => from.first_name.clone(),
+    });
+
+    ChatImageHashes::insert(
+        &handler.conn,
+        message.chat_id,
+        message.message_id,
+        hash,
+        posted_by.as_deref(),
+    )
+    .await?;
+
+    let previous = match previous {
+        Some(previous) => previous,
+        None => return Ok(()),
+    };
+
+    tracing::debug!(
+        previous_message_id = previous.message_id,
+        "image was already posted in chat"
+    );
+
+    let lang = message
+        .from
+        .as_ref()
+        .and_then(|from| from.language_code.as_deref());
+
+    let mut args = fluent::FluentArgs::new();
+    args.insert("days", (previous.age / 60 / 60 / 24).into());
+
+    let name = match previous.posted_by {
+        Some(posted_by) => {
+            args.insert("user", posted_by.into());
+            "repost-warning-user"
+        }
+        None => "repost-warning",
+    };
+
+    let text = handler
+        .get_fluent_bundle(lang, |bundle| {
+            get_message(bundle, name, Some(args)).unwrap()
+        })
+        .await;
+
+    let data = serde_json::to_value(&GroupSource {
+        chat_id: message.chat_id.to_string(),
+        reply_to_message_id: message.message_id,
+        text,
+        source_button: None,
+        sources: vec![],
+        hash: None,
+        source_hashes: vec![],
+        artist_choices: vec![],
+    })?;
+
+    let job = faktory::Job::new("group_source", vec![data]).on_queue(QUEUE_HIGH);
+    handler.enqueue(job).await;
+
+    Ok(())
+}
+
+/// React to an image to show it has sources, without sending a message.
+///
+/// Sources can then be found by replying to the image with `/source`.
+async fn react_with_source(handler: &Handler, message: &JobMessage) -> Result<(), Error> {
+    let reaction = bot_api::SetMessageReaction {
+        chat_id: message.chat_id.into(),
+        message_id: message.message_id,
+        reaction: vec![bot_api::ReactionType::Emoji {
+            emoji: SOURCE_REACTION.to_string(),
+        }],
+    };
+
+    match handler.telegram.make_request(&reaction).await {
+        Ok(_) => Ok(()),
+        // Reactions may be disabled in the chat.
+        Err(tgbotapi::Error::Telegram(tgbotapi::TelegramError {
+            error_code: Some(400),
+            description,
+            ..
+        })) => {
+            tracing::warn!(?description, "unable to react to group photo");
+            Ok(())
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Load the closest e621 source, which has details like its artists and
+/// pools that FuzzySearch doesn't provide.
+///
+/// Details are optional, so any problem loading them is only logged.
+async fn e621_details(
+    handler: &Handler,
+    matches: &[&fuzzysearch::File],
+) -> Option<foxbot_sites::PostInfo> {
+    let file = matches
+        .iter()
+        .find(|file| file.site_name() == Sites::E621.as_str())?;
+    let url = file.url();
+
+    let mut sites = handler.sites.lock().await;
+    let mut post = None;
+
+    if let Err(err) = find_images(
+        &foxbot_sites::Anonymous,
+        vec![url.as_str()],
+        &mut sites,
+        &mut |info| {
+            if post.is_none() {
+                post = info.results.into_iter().next();
+            }
+        },
+    )
+    .await
+    {
+        tracing::warn!("unable to load e621 source details: {:?}", err);
+    }
+
+    post
+}
+
+/// Ask which artist's sources are correct when the closest matches were made
+/// by different artists, saving a reply for each choice.
+///
+/// The question is sent by a `group_source` job like any other reply.
+async fn offer_artist_choices(
+    handler: &Handler,
+    message: &JobMessage,
+    lang: Option<&str>,
+    hash: i64,
+    choices: Vec<(String, Vec<&fuzzysearch::File>)>,
+) -> Result<(), Error> {
+    use redis::AsyncCommands;
+
+    tracing::debug!(count = choices.len(), "offering artist choices");
+
+    let mut names = Vec::with_capacity(choices.len());
+    let mut jobs = Vec::with_capacity(choices.len());
+
+    for (name, files) in choices {
+        let text = handler
+            .get_fluent_bundle(lang, |bundle| build_source_text(bundle, &files))
+            .await;
+
+        jobs.push(serde_json::to_value(&GroupSource {
+            chat_id: message.chat_id.to_string(),
+            reply_to_message_id: message.message_id,
+            text,
+            source_button: None,
+            sources: files.iter().map(|file| file.url()).collect(),
+            hash: Some(hash),
+            source_hashes: vec![],
+            artist_choices: vec![],
+        })?);
+
+        names.push(name);
+    }
+
+    let choices = ArtistChoices {
+        job_kind: "group_source".to_string(),
+        poster_id: message.from.as_ref().map(|from| from.id),
+        choices: jobs,
+        hash: Some(hash),
+        names: names.clone(),
+    };
+
+    let key = artist_choices_key(message.chat_id, message.message_id);
+
+    let mut conn = handler.redis.clone();
+    conn.set_ex::<_, _, ()>(&key, serde_json::to_string(&choices)?, ARTIST_CHOICES_TTL)
+        .await
+        .context("unable to save artist choices")?;
+
+    let text = handler
+        .get_fluent_bundle(lang, |bundle| {
+            get_message(bundle, "artist-choice", None).unwrap()
+        })
+        .await;
+
+    let data = serde_json::to_value(&GroupSource {
+        chat_id: message.chat_id.to_string(),
+        reply_to_message_id: message.message_id,
+        text,
+        source_button: None,
+        sources: vec![],
+        hash: None,
+        source_hashes: vec![],
+        artist_choices: names,
+    })?;
+
+    let job = faktory::Job::new("group_source", vec![data]).on_queue(QUEUE_HIGH);
+    handler.enqueue(job).await;
+
+    Ok(())
+}
+
+/// Save the sources for a forwarded image and reply with a button to show
+/// them, instead of replying with the sources directly.
+async fn offer_forwarded_source(
+    handler: &Handler,
+    message: &JobMessage,
+    lang: Option<&str>,
+    origin: ForwardOrigin,
+    text: String,
+) -> Result<(), Error> {
+    use redis::AsyncCommands;
+
+    let key = forwarded_source_key(message.chat_id, message.message_id);
+
+    let mut conn = handler.redis.clone();
+    conn.set_ex::<_, _, ()>(&key, text, FORWARDED_SOURCE_TTL)
+        .await
+        .context("unable to save forwarded source")?;
+
+    let (text, button) = handler
+        .get_fluent_bundle(lang, |bundle| {
+            let text = match origin.link.or(origin.name) {
+                Some(origin) => {
+                    let mut args = fluent::FluentArgs::new();
+                    args.insert("origin", origin.into());
+
+                    get_message(bundle, "automatic-forwarded", Some(args)).unwrap()
+                }
+                None => get_message(bundle, "automatic-forwarded-unknown", None).unwrap(),
+            };
+
+            (
+                text,
+                get_message(bundle, "automatic-forwarded-button", None).unwrap(),
+            )
+        })
+        .await;
+
+    let data = serde_json::to_value(&GroupSource {
+        chat_id: message.chat_id.to_string(),
+        reply_to_message_id: message.message_id,
+        text,
+        source_button: Some(button),
+        sources: vec![],
+        hash: None,
+        source_hashes: vec![],
+        artist_choices: vec![],
+    })?;
+
+    let job = faktory::Job::new("group_source", vec![data]).on_queue(QUEUE_HIGH);
+    handler.enqueue(job).await;
+
+    Ok(())
+}
+
+/// Add matches from a message in an album to a Redis buffer for that album.
+///
+/// Telegram sends each item in an album as its own message, so the first
+/// message to add matches schedules a `group_album` job after
+/// [`ALBUM_FLUSH_DELAY`] seconds. That job then replies once with every source
+/// found for the album.
+async fn buffer_album_matches(
+    handler: &Handler,
+    message: &JobMessage,
+    media_group_id: &str,
+    lang: Option<&str>,
+    hash: i64,
+    matches: &[&fuzzysearch::File],
+) -> Result<(), Error> {
+    use redis::AsyncCommands;
+
+    let key = format!("group-album:{}", media_group_id);
+
+    // Each match is saved with the hash of the image it was for, so sources
+    // in the reply can be reported as wrong for the right image.
+    let items = matches
+        .iter()
+        .map(|file| serde_json::to_string(&(hash, file)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let added = items.len();
+
+    let mut conn = handler.redis.clone();
+    let len: usize = conn
+        .rpush(&key, items)
+        .await
+        .context("unable to buffer album matches")?;
+    conn.expire(&key, 300)
+        .await
+        .context("unable to set album expiration")?;
+
+    // Every other message in the album will be handled by this flush.
+    if len > added {
+        tracing::trace!(media_group_id, "album flush already scheduled");
+        return Ok(());
+    }
+
+    let data = serde_json::to_value(&GroupAlbum {
+        chat_id: message.chat_id.to_string(),
+        media_group_id: media_group_id.to_string(),
+        reply_to_message_id: message.message_id,
+        lang: lang.map(ToString::to_string),
+    })?;
+
+    let mut job = faktory::Job::new("group_album", vec![data]).on_queue(QUEUE_HIGH);
+    job.at = Some(chrono::Utc::now() + chrono::Duration::seconds(ALBUM_FLUSH_DELAY));
+    job.custom = get_faktory_custom();
+
+    handler.enqueue(job).await;
+
+    Ok(())
+}
@@ -0,0 +1,107 @@
+use std::sync::Arc;
+
+use crate::*;
+
+/// Seconds between checks that sites still accept their credentials.
+const LOGIN_CHECK_INTERVAL: u64 = 60 * 30;
+
+/// Periodically check that sites with expiring credentials, like FurAffinity's
+/// cookies, are still logged in, alerting admins when that changes.
+pub async fn run_login_checks(handler: Arc<Handler>) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(LOGIN_CHECK_INTERVAL));
+
+    loop {
+        interval.tick().await;
+
+        if let Err(err) = check_logins(&handler).await {
+            tracing::error!("unable to check site logins: {:?}", err);
+        }
+    }
+}
+
+async fn check_logins(handler: &Handler) -> anyhow::Result<()> {
+    let results = {
+        let mut sites = handler.sites.lock().await;
+
+        let mut results = Vec::new();
+        for site in sites.iter_mut() {
+            match site.check_login().await {
+                Ok(Some(logged_in)) => results.push((site.name(), logged_in)),
+                Ok(None) => (),
+                Err(err) => {
+                    tracing::warn!(site = site.name(), "unable to check site login: {:?}", err)
+                }
+            }
+        }
+
+        results
+    };
+
+    for (site, logged_in) in results {
+        metrics::site_logged_in(site, logged_in);
+
+        if !logged_in {
+            tracing::error!(site, "site is no longer accepting credentials");
+        }
+
+        if login_changed(&handler.redis, site, logged_in).await? {
+            notify_admins(handler, site, logged_in).await;
+        }
+    }
+
+    Ok(())
+}
+
+/// Record if a site is logged in, returning if admins should be told.
+///
+/// Every worker runs these checks, but only the one that changes the stored
+/// state sees the change. Admins are only told about a working login if it
+/// was previously broken.
+async fn login_changed(
+    conn: &redis::aio::ConnectionManager,
+    site: &str,
+    logged_in: bool,
+) -> anyhow::Result<bool> {
+    let mut conn = conn.clone();
+
+    let previous: Option<bool> = redis::cmd("GETSET")
+        .arg(format!("site-login:{}", site))
+        .arg(logged_in)
+        .query_async(&mut conn)
+        .await?;
+
+    Ok(previous.unwrap_or(true) != logged_in)
+}
+
+async fn notify_admins(handler: &Handler, site: &str, logged_in: bool) {
+    let admin_chat_id = match handler.admin_chat_id {
+        Some(admin_chat_id) => admin_chat_id,
+        None => return,
+    };
+
+    let name = if logged_in {
+        "site-login-restored"
+    } else {
+        "site-login-failed"
+    };
+
+    let text = handler
+        .get_fluent_bundle(None, |bundle| {
+            let mut args = fluent::FluentArgs::new();
+            args.insert("site", site.into());
+
+            get_message(bundle, name, Some(args))
+        })
+        .await
+        .unwrap_or_else(|_| format!("{} login changed: {}", site, logged_in));
+
+    let send_message = tgbotapi::requests::SendMessage {
+        chat_id: admin_chat_id.into(),
+        text,
+        ..Default::default()
+    };
+
+    if let Err(err) = handler.telegram.make_request(&send_message).await {
+        tracing::error!("unable to notify admins of site login: {:?}", err);
+    }
+}
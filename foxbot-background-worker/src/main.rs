@@ -1,5 +1,5 @@
 use std::ops::Add;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use std::{collections::HashMap, future::Future};
 
 use opentelemetry::propagation::TextMapPropagator;
@@ -13,10 +13,20 @@ use tracing_opentelemetry::OpenTelemetrySpanExt;
 use foxbot_models::Sites;
 use foxbot_sites::BoxedSite;
 use foxbot_utils::*;
+use queue::JobQueue;
 
+mod actions;
+mod broadcast;
 mod channel;
+mod dead_letter;
 mod group;
+mod login_check;
+mod metrics;
+mod queue;
+mod rehost;
+mod scheduler;
 mod subscribe;
+mod watch;
 
 fn main() {
     use opentelemetry::KeyValue;
@@ -38,19 +48,45 @@ fn main() {
         "release"
     };
 
-    opentelemetry::global::set_text_map_propagator(opentelemetry_jaeger::Propagator::new());
-
-    let tracer = runtime.block_on(async move {
-        opentelemetry_jaeger::new_pipeline()
-            .with_agent_endpoint(std::env::var("JAEGER_COLLECTOR").unwrap())
-            .with_service_name("foxbot_background_worker")
-            .with_tags(vec![
-                KeyValue::new("environment", env.to_owned()),
-                KeyValue::new("version", env!("CARGO_PKG_VERSION")),
-            ])
-            .install_batch(opentelemetry::runtime::Tokio)
-            .unwrap()
-    });
+    let tracer = match std::env::var("OTLP_ENDPOINT") {
+        Ok(endpoint) => {
+            opentelemetry::global::set_text_map_propagator(
+                opentelemetry::sdk::propagation::TraceContextPropagator::new(),
+            );
+
+            runtime.block_on(async move {
+                opentelemetry_otlp::new_pipeline()
+                    .with_endpoint(endpoint)
+                    .with_trace_config(opentelemetry::sdk::trace::config().with_resource(
+                        opentelemetry::sdk::Resource::new(vec![
+                            KeyValue::new("service.name", "foxbot_background_worker"),
+                            KeyValue::new("environment", env.to_owned()),
+                            KeyValue::new("version", env!("CARGO_PKG_VERSION")),
+                        ]),
+                    ))
+                    .install_batch(opentelemetry::runtime::Tokio)
+                    .unwrap()
+            })
+        }
+        Err(_) => {
+            opentelemetry::global::set_text_map_propagator(opentelemetry_jaeger::Propagator::new());
+
+            runtime.block_on(async move {
+                opentelemetry_jaeger::new_pipeline()
+                    .with_agent_endpoint(
+                        std::env::var("JAEGER_COLLECTOR")
+                            .expect("Missing JAEGER_COLLECTOR or OTLP_ENDPOINT"),
+                    )
+                    .with_service_name("foxbot_background_worker")
+                    .with_tags(vec![
+                        KeyValue::new("environment", env.to_owned()),
+                        KeyValue::new("version", env!("CARGO_PKG_VERSION")),
+                    ])
+                    .install_batch(opentelemetry::runtime::Tokio)
+                    .unwrap()
+            })
+        }
+    };
 
     let trace = tracing_opentelemetry::layer().with_tracer(tracer);
     let env_filter = tracing_subscriber::EnvFilter::from_default_env();
@@ -63,6 +99,7 @@ fn main() {
         let subscriber = tracing_subscriber::Registry::default()
             .with(env_filter)
             .with(trace)
+            .with(foxbot_utils::sentry_breadcrumbs())
             .with(subscriber);
         tracing::subscriber::set_global_default(subscriber).unwrap();
     } else {
@@ -70,6 +107,7 @@ fn main() {
         let subscriber = tracing_subscriber::Registry::default()
             .with(env_filter)
             .with(trace)
+            .with(foxbot_utils::sentry_breadcrumbs())
             .with(subscriber);
         tracing::subscriber::set_global_default(subscriber).unwrap();
     }
@@ -77,21 +115,18 @@ fn main() {
     tracing::info!("starting channel worker");
 
     load_env();
-    let config = match envy::from_env::<Config>() {
+    let secrets = Arc::new(foxbot_utils::secrets::providers_from_env());
+    let config = match runtime.block_on(Config::load(secrets.as_ref())) {
         Ok(config) => config,
-        Err(err) => panic!("{:#?}", err),
+        Err(err) => panic!("{:#}", err),
     };
 
-    let workers: usize = std::env::var("CHANNEL_WORKERS")
-        .as_deref()
-        .unwrap_or("2")
-        .parse()
-        .unwrap_or(2);
-
-    tracing::debug!(workers, "got worker count configuration");
+    let _guard = foxbot_utils::init_sentry(config.sentry_dsn.as_deref());
 
-    let mut faktory = faktory::ConsumerBuilder::default();
-    faktory.workers(workers);
+    download::set_limits(download::DownloadLimits::new(
+        config.media_max_bytes,
+        config.media_max_pixels,
+    ));
 
     let pool = sqlx::postgres::PgPoolOptions::new()
         .max_connections(2)
@@ -100,21 +135,11 @@ fn main() {
         .block_on(pool)
         .expect("unable to create database pool");
 
-    let sites = runtime.block_on(foxbot_sites::get_all_sites(
-        config.fa_a,
-        config.fa_b,
-        config.fautil_apitoken.clone(),
-        config.weasyl_apitoken,
-        config.twitter_consumer_key,
-        config.twitter_consumer_secret,
-        config.inkbunny_username,
-        config.inkbunny_password,
-        config.e621_login,
-        config.e621_api_key,
-        pool.clone(),
-    ));
+    let sites = runtime.block_on(build_sites(&config, pool.clone()));
 
-    let telegram = tgbotapi::Telegram::new(config.telegram_apitoken);
+    let download_limit = bot_api::download_limit(config.telegram_api_endpoint.as_deref());
+    let media_cache = config.media_cache();
+    let telegram = bot_api::telegram_client(config.telegram_apitoken, config.telegram_api_endpoint);
     let fuzzysearch = fuzzysearch::FuzzySearch::new(config.fautil_apitoken);
 
     let redis = redis::Client::open(config.redis_dsn).unwrap();
@@ -122,32 +147,259 @@ fn main() {
         .block_on(redis::aio::ConnectionManager::new(redis))
         .expect("unable to open redis connection");
 
-    let producer = faktory::Producer::connect(None).unwrap();
+    let queue: Arc<dyn JobQueue> = match config.queue_backend.as_deref() {
+        Some("postgres") => Arc::new(queue::PostgresQueue::new(
+            pool.clone(),
+            config.database_url.clone(),
+            runtime.clone(),
+        )),
+        _ => Arc::new(queue::FaktoryQueue::connect().expect("unable to connect to faktory")),
+    };
+
+    let telegram = bot_api::TelegramClient::new(telegram, redis.clone());
 
     let handler = Arc::new(Handler {
         sites: tokio::sync::Mutex::new(sites),
         telegram: Arc::new(telegram),
-        producer: Arc::new(Mutex::new(producer)),
+        download_limit,
+        queue: queue.clone(),
         fuzzysearch,
         conn: pool,
         redis,
-        langs: load_langs(),
-        best_langs: Default::default(),
+        l10n: foxbot_utils::l10n::Localization::load(foxbot_utils::l10n::langs_dir())
+            .expect("unable to load languages"),
+        admin_chat_id: config.admin_chat_id,
+        media_cache,
     });
 
-    let mut worker_environment = WorkerEnvironment::new(faktory, runtime, handler);
+    if let Some(metrics_host) = config.metrics_host.as_deref() {
+        metrics::serve(metrics_host, runtime.handle().clone(), handler.clone());
+    }
+
+    runtime.spawn(reload_sites_on_hangup(handler.clone(), secrets.clone()));
+    runtime.spawn(reload_sites_on_credential_change(
+        handler.clone(),
+        secrets,
+        config.credentials_key.clone(),
+    ));
+
+    // Faktory normally runs scheduled jobs, but this ensures they still run if
+    // Faktory is restarted and loses them.
+    let scheduler = {
+        let runtime = runtime.clone();
+        let handler = handler.clone();
+
+        std::thread::spawn(move || runtime.block_on(scheduler::run_fallback_scheduler(handler)))
+    };
+
+    let watch_poller = {
+        let runtime = runtime.clone();
+        let handler = handler.clone();
+
+        std::thread::spawn(move || runtime.block_on(watch::run_watch_poller(handler)))
+    };
+
+    if handler.media_cache.is_some() {
+        runtime.spawn(rehost::run_cleanup(handler.clone()));
+    }
+
+    runtime.spawn(login_check::run_login_checks(handler.clone()));
+    runtime.spawn(actions::run_cleanup(
+        handler.clone(),
+        config
+            .action_retention_days
+            .unwrap_or(actions::DEFAULT_RETENTION_DAYS),
+    ));
+
+    // Each tier has its own workers so lower priority jobs can never starve
+    // higher priority ones. Workers check their own queue first, then help
+    // with any higher priority queues when they are idle. The legacy queue is
+    // still consumed so jobs enqueued before priorities existed are finished.
+    let tiers = vec![
+        (config.high_workers.unwrap_or(2), vec![QUEUE_HIGH]),
+        (
+            config.channel_workers.unwrap_or(2),
+            vec![QUEUE_DEFAULT, "foxbot_background", QUEUE_HIGH],
+        ),
+        (
+            config.low_workers.unwrap_or(1),
+            vec![QUEUE_LOW, QUEUE_DEFAULT, QUEUE_HIGH],
+        ),
+    ];
+
+    let threads: Vec<_> = tiers
+        .into_iter()
+        .filter(|(workers, _queues)| *workers > 0)
+        .map(|(workers, queues)| {
+            tracing::debug!(workers, ?queues, "starting consumer");
+
+            let mut worker_environment = WorkerEnvironment::new(runtime.clone(), handler.clone());
+            register_jobs(&mut worker_environment);
+            let jobs = worker_environment.finalize();
+
+            let queue = queue.clone();
+            std::thread::spawn(move || queue.run(jobs, workers, queues))
+        })
+        .collect();
+
+    for thread in threads {
+        thread.join().unwrap();
+    }
+
+    scheduler.join().unwrap();
+    watch_poller.join().unwrap();
+}
+
+/// Create every site loader with credentials from the configuration.
+async fn build_sites(config: &Config, pool: sqlx::Pool<sqlx::Postgres>) -> Vec<BoxedSite> {
+    build_named_sites(config, pool, None).await
+}
+
+/// Create the site loaders with the given names, or every site loader.
+async fn build_named_sites(
+    config: &Config,
+    pool: sqlx::Pool<sqlx::Postgres>,
+    names: Option<&[&str]>,
+) -> Vec<BoxedSite> {
+    let credentials =
+        foxbot_utils::secrets::CredentialOverrides::load(&pool, config.credentials_key.as_deref())
+            .await;
+
+    foxbot_sites::get_sites(
+        names,
+        credentials.get("FA_A", &config.fa_a),
+        credentials.get("FA_B", &config.fa_b),
+        config.flaresolverr_endpoint.clone(),
+        credentials.get("FAUTIL_APITOKEN", &config.fautil_apitoken),
+        credentials.get("WEASYL_APITOKEN", &config.weasyl_apitoken),
+        credentials.get("TWITTER_CONSUMER_KEY", &config.twitter_consumer_key),
+        credentials.get("TWITTER_CONSUMER_SECRET", &config.twitter_consumer_secret),
+        config.twitter_thread_limit.unwrap_or_default(),
+        credentials.get("INKBUNNY_USERNAME", &config.inkbunny_username),
+        credentials.get("INKBUNNY_PASSWORD", &config.inkbunny_password),
+        credentials.get("E621_LOGIN", &config.e621_login),
+        credentials.get("E621_API_KEY", &config.e621_api_key),
+        pool,
+        &config.proxies().expect("proxies should be validated"),
+    )
+    .await
+}
+
+/// Rebuild sites with the current credentials and reload languages whenever
+/// the process receives SIGHUP, so they can be changed without restarting.
+#[cfg(unix)]
+async fn reload_sites_on_hangup(
+    handler: Arc<Handler>,
+    secrets: Arc<foxbot_utils::secrets::ChainedSecrets>,
+) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut stream = signal(SignalKind::hangup()).expect("unable to create hangup signal stream");
+
+    while stream.recv().await.is_some() {
+        match Config::load(secrets.as_ref()).await {
+            Ok(config) => {
+                let sites = build_sites(&config, handler.conn.clone()).await;
+                *handler.sites.lock().await = sites;
+                tracing::info!("rebuilt sites with current credentials");
+            }
+            Err(err) => tracing::error!("unable to reload configuration: {:#}", err),
+        }
+
+        if let Err(err) = handler.l10n.reload() {
+            tracing::error!("unable to reload languages: {:#}", err);
+        }
+    }
+}
+
+/// Rebuild the sites using credentials the bot stored, checking every minute.
+async fn reload_sites_on_credential_change(
+    handler: Arc<Handler>,
+    secrets: Arc<foxbot_utils::secrets::ChainedSecrets>,
+    credentials_key: Option<String>,
+) {
+    use foxbot_utils::secrets::CredentialOverrides;
+    use redis::AsyncCommands;
+
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(60));
+    let mut redis = handler.redis.clone();
+    let mut current_version = None;
+    let mut credentials =
+        CredentialOverrides::load(&handler.conn, credentials_key.as_deref()).await;
+
+    loop {
+        interval.tick().await;
 
+        let version: Option<i64> = match redis
+            .get(foxbot_utils::secrets::CREDENTIALS_VERSION_KEY)
+            .await
+        {
+            Ok(version) => version,
+            Err(err) => {
+                tracing::warn!("unable to check site credentials version: {:?}", err);
+                continue;
+            }
+        };
+
+        // Sites were built with the latest credentials at startup.
+        let version = version.unwrap_or_default();
+        if current_version
+            .replace(version)
+            .map_or(true, |previous| previous == version)
+        {
+            continue;
+        }
+
+        let config = match Config::load(secrets.as_ref()).await {
+            Ok(config) => config,
+            Err(err) => {
+                tracing::error!("unable to reload configuration: {:#}", err);
+                continue;
+            }
+        };
+
+        let updated =
+            CredentialOverrides::load(&handler.conn, config.credentials_key.as_deref()).await;
+        let changed = updated.changed_sites(&credentials);
+        credentials = updated;
+
+        if changed.is_empty() {
+            continue;
+        }
+
+        let rebuilt = build_named_sites(&config, handler.conn.clone(), Some(&changed)).await;
+
+        let mut sites = handler.sites.lock().await;
+        for rebuilt in rebuilt {
+            if let Some(site) = sites.iter_mut().find(|site| site.name() == rebuilt.name()) {
+                *site = rebuilt;
+            }
+        }
+        drop(sites);
+
+        tracing::info!(?changed, "rebuilt sites with updated credentials");
+    }
+}
+
+#[cfg(not(unix))]
+async fn reload_sites_on_hangup(
+    _handler: Arc<Handler>,
+    _secrets: Arc<foxbot_utils::secrets::ChainedSecrets>,
+) {
+}
+
+fn register_jobs(worker_environment: &mut WorkerEnvironment) {
     worker_environment.register("channel_update", channel::process_channel_update);
     worker_environment.register("channel_edit", channel::process_channel_edit);
+    worker_environment.register("channel_discussion", channel::process_channel_discussion);
+    worker_environment.register("channel_backfill", channel::process_channel_backfill);
     worker_environment.register("group_photo", group::process_group_photo);
+    worker_environment.register("group_album", group::process_group_album);
     worker_environment.register("group_source", group::process_group_source);
     worker_environment.register("hash_new", subscribe::process_hash_new);
     worker_environment.register("hash_notify", subscribe::process_hash_notify);
-
-    let faktory = worker_environment.finalize();
-
-    let faktory = faktory.connect(None).unwrap();
-    faktory.run_to_completion(&["foxbot_background"]);
+    worker_environment.register("watch_check", watch::process_watch_check);
+    worker_environment.register("broadcast", broadcast::process_broadcast);
 }
 
 #[cfg(feature = "env")]
@@ -159,19 +411,15 @@ fn load_env() {
 fn load_env() {}
 
 struct WorkerEnvironment {
-    faktory: faktory::ConsumerBuilder<Error>,
+    jobs: queue::Jobs,
     runtime: Arc<tokio::runtime::Runtime>,
     handler: Arc<Handler>,
 }
 
 impl WorkerEnvironment {
-    fn new(
-        faktory: faktory::ConsumerBuilder<Error>,
-        runtime: Arc<tokio::runtime::Runtime>,
-        handler: Arc<Handler>,
-    ) -> Self {
+    fn new(runtime: Arc<tokio::runtime::Runtime>, handler: Arc<Handler>) -> Self {
         Self {
-            faktory,
+            jobs: Default::default(),
             runtime,
             handler,
         }
@@ -184,19 +432,61 @@ impl WorkerEnvironment {
     {
         let runtime = self.runtime.clone();
         let handler = self.handler.clone();
+        let kind = name.to_string();
 
-        self.faktory
-            .register(name, move |job| -> Result<(), Error> {
+        self.jobs.insert(
+            name.to_string(),
+            Arc::new(move |job| -> Result<(), Error> {
                 let span = get_custom_span(&job);
-
-                runtime.block_on(f(handler.clone(), job).instrument(span))?;
-
-                Ok(())
-            });
+                let details = dead_letter::JobDetails::new(&job);
+                let tags = vec![
+                    ("job", kind.clone()),
+                    ("job_id", job.id().to_string()),
+                    ("queue", job.queue.clone()),
+                ];
+                let _timer = metrics::job_timer(&kind);
+                let job_id = job.id().to_string();
+
+                match runtime.block_on(scheduler::start(&handler.redis, &job_id)) {
+                    Ok(true) => (),
+                    Ok(false) => {
+                        tracing::warn!(
+                            job_id = job.id(),
+                            "replaced scheduled job is running or already ran"
+                        );
+                        return Ok(());
+                    }
+                    Err(err) => tracing::error!("unable to start scheduled job: {:?}", err),
+                }
+
+                let result = runtime.block_on(f(handler.clone(), job).instrument(span.clone()));
+
+                if let Err(err) =
+                    runtime.block_on(scheduler::finish(&handler.redis, &job_id, result.is_ok()))
+                {
+                    tracing::error!("unable to finish scheduled job: {:?}", err);
+                }
+
+                match result {
+                    Ok(()) => Ok(()),
+                    Err(err) => {
+                        metrics::job_failed(&kind, &err);
+
+                        span.in_scope(|| {
+                            with_user_scope(None, Some(tags), || sentry::capture_error(&err))
+                        });
+
+                        runtime.block_on(
+                            dead_letter::handle_failure(&handler, details, err).instrument(span),
+                        )
+                    }
+                }
+            }),
+        );
     }
 
-    fn finalize(self) -> faktory::ConsumerBuilder<Error> {
-        self.faktory
+    fn finalize(self) -> queue::Jobs {
+        self.jobs
     }
 }
 
@@ -212,44 +502,275 @@ pub enum Error {
     Other(#[from] anyhow::Error),
 }
 
-type BestLangs = std::collections::HashMap<String, LangBundle>;
-
-const MAX_SOURCE_DISTANCE: u64 = 3;
 const NOISY_SOURCE_COUNT: usize = 4;
+/// Maximum length of a caption, in UTF-16 code units.
+const MAX_CAPTION_LENGTH: usize = 1024;
+/// Reaction added to images with sources when a group prefers silence.
+const SOURCE_REACTION: &str = "👀";
+/// Text of the button for reporting that sources in a reply were wrong.
+const WRONG_SOURCE_BUTTON: &str = "👎";
+/// Seconds to keep sources for a forwarded image available.
+const FORWARDED_SOURCE_TTL: usize = 60 * 60 * 24;
+/// Seconds sources in a reply for an album may be reported as wrong.
+const WRONG_SOURCE_TTL: usize = 60 * 60 * 24 * 7;
+/// Seconds to wait for someone to choose between artists.
+const ARTIST_CHOICES_TTL: usize = 60 * 60 * 24;
+/// Seconds to wait for the rest of an album before replying with sources.
+const ALBUM_FLUSH_DELAY: i64 = 10;
+/// Maximum number of sources to include in a discussion group reply.
+const MAX_DISCUSSION_SOURCES: usize = 10;
+/// Seconds to wait between checks for a channel post's discussion thread.
+const DISCUSSION_RETRY_DELAY: i64 = 5;
+/// Number of times to check for a discussion thread before giving up.
+const DISCUSSION_ATTEMPTS: usize = 6;
+/// Number of times a job may fail before it is moved to the failed jobs table.
+const MAX_JOB_ATTEMPTS: usize = 5;
+/// Seconds to remember how many times a job has failed.
+const JOB_ATTEMPTS_TTL: usize = 60 * 60 * 24 * 7;
+/// Seconds to remember that a message has already been processed.
+const DEDUP_MESSAGE_TTL: usize = 60 * 60 * 24;
+/// Seconds to remember that a file has already been processed in a chat.
+const DEDUP_FILE_TTL: usize = 60 * 5;
+/// Number of channel messages to look at in each backfill job.
+const BACKFILL_BATCH_SIZE: i32 = 20;
+/// Seconds to wait between backfill jobs, to avoid hitting rate limits.
+const BACKFILL_BATCH_DELAY: i64 = 10;
+/// Most images to download at once when checking a post for a similar image.
+const SIMILAR_HASH_CONCURRENCY: usize = 4;
 
 #[derive(serde::Deserialize, Debug, Clone)]
 struct Config {
     // Site config
     fa_a: String,
     fa_b: String,
+    flaresolverr_endpoint: Option<String>,
     weasyl_apitoken: String,
     inkbunny_username: String,
     inkbunny_password: String,
     e621_login: String,
     e621_api_key: String,
+    credentials_key: Option<String>,
 
     // Twitter config
     twitter_consumer_key: String,
     twitter_consumer_secret: String,
+    twitter_thread_limit: Option<usize>,
 
     // Telegram config
     telegram_apitoken: String,
+    telegram_api_endpoint: Option<String>,
 
     // FuzzySearch config
     fautil_apitoken: String,
 
     // Worker configuration
     channel_workers: Option<usize>,
+    high_workers: Option<usize>,
+    low_workers: Option<usize>,
+    queue_backend: Option<String>,
+    metrics_host: Option<String>,
+    admin_chat_id: Option<i64>,
+    sentry_dsn: Option<String>,
+    media_max_bytes: Option<usize>,
+    media_max_pixels: Option<u64>,
+    proxy: Option<String>,
+    site_proxies: Option<Vec<String>>,
     database_url: String,
+
+    // Media cache config
+    s3_endpoint: Option<String>,
+    s3_region: Option<String>,
+    s3_token: Option<String>,
+    s3_secret: Option<String>,
+    s3_bucket: Option<String>,
+    s3_url: Option<String>,
+    rehost_sites: Option<Vec<String>>,
+    rehost_ttl: Option<i64>,
+    action_retention_days: Option<i32>,
     redis_dsn: String,
 }
 
+/// Credentials used only by the worker, which may be loaded from a
+/// [`SecretProvider`](foxbot_utils::secrets::SecretProvider) in addition to
+/// [`SITE_CREDENTIALS`](foxbot_utils::secrets::SITE_CREDENTIALS).
+static WORKER_CREDENTIALS: &[&str] = &[
+    "TELEGRAM_APITOKEN",
+    "DATABASE_URL",
+    "REDIS_DSN",
+    "S3_TOKEN",
+    "S3_SECRET",
+    "CREDENTIALS_KEY",
+];
+
+impl Config {
+    /// Load and validate configuration, using credentials from the secret
+    /// provider where available.
+    async fn load(secrets: &dyn foxbot_utils::secrets::SecretProvider) -> anyhow::Result<Self> {
+        let names: Vec<&str> = foxbot_utils::secrets::SITE_CREDENTIALS
+            .iter()
+            .chain(WORKER_CREDENTIALS)
+            .copied()
+            .collect();
+
+        let config: Self = foxbot_utils::config::load_config_with_secrets(secrets, &names).await?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// Build the proxies to use for each site.
+    fn proxies(&self) -> anyhow::Result<foxbot_sites::Proxies> {
+        foxbot_sites::Proxies::new(
+            self.proxy.clone(),
+            self.site_proxies.as_deref().unwrap_or_default(),
+        )
+    }
+
+    /// Check for settings that can't be used, reporting every problem at once.
+    fn validate(&self) -> anyhow::Result<()> {
+        let mut problems = vec![];
+
+        if self.proxies().is_err() {
+            problems.push(
+                "PROXY and SITE_PROXIES must be valid proxy URLs, with site proxies like FurAffinity=socks5://host:port",
+            );
+        }
+
+        if !matches!(
+            self.queue_backend.as_deref(),
+            None | Some("faktory") | Some("postgres")
+        ) {
+            problems.push("QUEUE_BACKEND must be faktory or postgres");
+        }
+
+        if let Some(key) = self.credentials_key.as_deref() {
+            if foxbot_utils::secrets::CredentialCipher::new(key).is_err() {
+                problems.push("CREDENTIALS_KEY must be a base64 encoded 32 byte key");
+            }
+        }
+
+        if let Some(metrics_host) = &self.metrics_host {
+            if metrics_host.parse::<std::net::SocketAddr>().is_err() {
+                problems.push("METRICS_HOST must be an address like 0.0.0.0:8081");
+            }
+        }
+
+        if matches!(&self.rehost_sites, Some(sites) if !sites.is_empty())
+            && self.media_cache_s3().is_none()
+        {
+            problems.push(
+                "S3_ENDPOINT, S3_REGION, S3_TOKEN, S3_SECRET, S3_BUCKET, and S3_URL must be set when REHOST_SITES is set",
+            );
+        }
+
+        let workers = [self.high_workers, self.channel_workers, self.low_workers];
+        if workers.iter().all(|workers| matches!(workers, Some(0))) {
+            problems.push(
+                "at least one of HIGH_WORKERS, CHANNEL_WORKERS, or LOW_WORKERS must be above 0",
+            );
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            anyhow::bail!("invalid configuration:\n  {}", problems.join("\n  "))
+        }
+    }
+
+    /// Get the S3 endpoint, region, token, secret, bucket, and URL, if all of
+    /// them are set.
+    #[allow(clippy::type_complexity)]
+    fn media_cache_s3(&self) -> Option<(&str, &str, &str, &str, &str, &str)> {
+        Some((
+            self.s3_endpoint.as_deref()?,
+            self.s3_region.as_deref()?,
+            self.s3_token.as_deref()?,
+            self.s3_secret.as_deref()?,
+            self.s3_bucket.as_deref()?,
+            self.s3_url.as_deref()?,
+        ))
+    }
+
+    /// Build the media cache, if S3 is configured. Without any sites to
+    /// rehost, it only removes expired images the bot stored.
+    fn media_cache(&self) -> Option<media_cache::MediaCache> {
+        let sites = self.rehost_sites.clone().unwrap_or_default();
+        let (endpoint, region, token, secret, bucket, url) = self.media_cache_s3()?;
+
+        let s3 = media_cache::s3_client(
+            endpoint.to_string(),
+            region.to_string(),
+            token.to_string(),
+            secret.to_string(),
+        );
+
+        Some(media_cache::MediaCache::new(
+            s3,
+            bucket.to_string(),
+            url.to_string(),
+            sites,
+            self.rehost_ttl,
+        ))
+    }
+}
+
 #[derive(serde::Deserialize, serde::Serialize)]
 struct MessageEdit {
     chat_id: String,
     message_id: i32,
     media_group_id: Option<String>,
     firsts: Vec<(Sites, String)>,
+    /// Hash distance of each source in `firsts`, if known.
+    #[serde(default)]
+    distances: Vec<Option<u64>>,
+    #[serde(default)]
+    caption: Option<String>,
+    #[serde(default)]
+    caption_entities: Option<Vec<tgbotapi::MessageEntity>>,
+    #[serde(default)]
+    artists: Vec<String>,
+    #[serde(default)]
+    template: foxbot_models::SourceTemplate,
+    /// Reply to post in the discussion group if the edit fails.
+    #[serde(default)]
+    discussion_text: Option<String>,
+    /// Artists to ask administrators to choose between with buttons, instead
+    /// of adding sources.
+    #[serde(default)]
+    artist_choices: Vec<String>,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+struct DiscussionReply {
+    channel_id: i64,
+    message_id: i32,
+    text: String,
+    #[serde(default)]
+    attempt: usize,
+}
+
+/// Progress of sourcing a range of a channel's existing messages.
+///
+/// Channel history can't be read directly, so each message is forwarded to
+/// the requesting user's chat to see its contents and then deleted.
+#[derive(serde::Deserialize, serde::Serialize)]
+struct ChannelBackfill {
+    channel_id: i64,
+    next_message_id: i32,
+    last_message_id: i32,
+    user_chat_id: i64,
+    progress_message_id: i32,
+    lang: Option<String>,
+    #[serde(default)]
+    found: usize,
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+struct GroupAlbum {
+    chat_id: String,
+    media_group_id: String,
+    reply_to_message_id: i32,
+    lang: Option<String>,
 }
 
 #[derive(serde::Deserialize, serde::Serialize)]
@@ -257,29 +778,87 @@ struct GroupSource {
     chat_id: String,
     reply_to_message_id: i32,
     text: String,
+    /// Text for a button to reveal sources, instead of including them.
+    #[serde(default)]
+    source_button: Option<String>,
+    /// Links to the sources included in the reply.
+    #[serde(default)]
+    sources: Vec<String>,
+    /// Hash of the image the sources are for, so they can be reported as
+    /// wrong.
+    #[serde(default)]
+    hash: Option<i64>,
+    /// Hash of the image each source was found for, when the sources are for
+    /// several images like an album.
+    #[serde(default)]
+    source_hashes: Vec<i64>,
+    /// Artists to ask people to choose between with buttons, instead of
+    /// sending sources.
+    #[serde(default)]
+    artist_choices: Vec<String>,
 }
 
 pub struct Handler {
     sites: tokio::sync::Mutex<Vec<BoxedSite>>,
 
-    langs: Langs,
-    best_langs: tokio::sync::RwLock<BestLangs>,
+    l10n: foxbot_utils::l10n::Localization,
 
-    producer: Arc<Mutex<faktory::Producer<std::net::TcpStream>>>,
-    telegram: Arc<tgbotapi::Telegram>,
+    queue: Arc<dyn JobQueue>,
+    telegram: Arc<bot_api::TelegramClient>,
+    /// Largest document that can be downloaded to find sources.
+    download_limit: usize,
     fuzzysearch: fuzzysearch::FuzzySearch,
     conn: sqlx::Pool<sqlx::Postgres>,
     redis: redis::aio::ConnectionManager,
+
+    /// Chat to notify when jobs fail too many times.
+    admin_chat_id: Option<i64>,
+    /// Rehosts images from sites Telegram has trouble loading, if enabled.
+    media_cache: Option<media_cache::MediaCache>,
 }
 
 impl Handler {
-    /// Enqueue a new Faktory job by spawning a blocking task.
-    async fn enqueue(&self, job: faktory::Job) {
-        let producer = self.producer.clone();
-        tokio::task::spawn_blocking(move || {
-            let mut producer = producer.lock().unwrap();
-            producer.enqueue(job).unwrap();
-        });
+    /// Enqueue a new job on the configured queue backend.
+    ///
+    /// Jobs scheduled for later are also saved in Redis, in case Faktory
+    /// loses them before they run. The current trace context is attached if
+    /// the job doesn't already have one.
+    async fn enqueue(&self, mut job: faktory::Job) {
+        if job.custom.is_empty() {
+            job.custom = get_faktory_custom();
+        }
+
+        if let Some(at) = job.at {
+            if let Err(err) = scheduler::remember(&self.redis, &job, at).await {
+                tracing::error!("unable to remember scheduled job: {:?}", err);
+            }
+        }
+
+        if let Err(err) = self.queue.enqueue(job).await {
+            tracing::error!("unable to enqueue job: {:?}", err);
+        }
+    }
+
+    /// Check each dependency needed to process jobs.
+    async fn health_report(&self) -> foxbot_utils::health::HealthReport {
+        use foxbot_utils::health;
+
+        let mut report = health::HealthReport::default();
+
+        report
+            .check("postgres", true, health::check_postgres(&self.conn))
+            .await;
+        report
+            .check("redis", true, health::check_redis(&self.redis))
+            .await;
+        report
+            .check(self.queue.name(), true, self.queue.check())
+            .await;
+        report
+            .check("telegram", false, health::check_telegram(&self.telegram))
+            .await;
+
+        report
     }
 
     /// Build a fluent language bundle for a specified language and cache the
@@ -288,30 +867,12 @@ impl Handler {
     where
         C: FnOnce(&fluent::concurrent::FluentBundle<fluent::FluentResource>) -> R,
     {
-        let requested = requested.unwrap_or(L10N_LANGS[0]);
+        let requested = requested.unwrap_or(DEFAULT_LANG);
 
         tracing::trace!(lang = requested, "Looking up language bundle");
 
-        {
-            let lock = self.best_langs.read().await;
-            if let Some(bundle) = lock.get(requested) {
-                return callback(bundle);
-            }
-        }
-
-        tracing::debug!(lang = requested, "Got new language, building bundle");
-
-        let bundle = get_lang_bundle(&self.langs, requested);
-
-        // Lock for writing for as short as possible.
-        {
-            let mut lock = self.best_langs.write().await;
-            lock.insert(requested.to_string(), bundle);
-        }
-
-        let lock = self.best_langs.read().await;
-        let bundle = lock.get(requested).expect("value just inserted is missing");
-        callback(bundle)
+        let bundle = self.l10n.bundle(requested);
+        callback(&bundle)
     }
 }
 
@@ -373,6 +934,77 @@ pub async fn check_more_time(
     }
 }
 
+/// Build the keys used to detect duplicate jobs for a message, one for the
+/// message itself and one for the file it contained.
+fn dedup_keys(name: &str, message: &payload::JobMessage) -> Vec<(String, usize)> {
+    vec![
+        (
+            format!("dedup:{}:{}:{}", name, message.chat_id, message.message_id),
+            DEDUP_MESSAGE_TTL,
+        ),
+        (
+            format!(
+                "dedup:{}:{}:file:{}",
+                name, message.chat_id, message.file_unique_id
+            ),
+            DEDUP_FILE_TTL,
+        ),
+    ]
+}
+
+/// Attempt to claim every key for a job, returning false if another job has
+/// already claimed any of them.
+///
+/// Keys store the ID of the job that claimed them, so retries of the same job
+/// are still allowed to run. If Redis is unavailable, the job is processed.
+#[tracing::instrument(skip(conn, keys))]
+pub async fn claim_job(
+    conn: &redis::aio::ConnectionManager,
+    job_id: &str,
+    keys: &[(String, usize)],
+) -> bool {
+    use redis::AsyncCommands;
+
+    let mut conn = conn.clone();
+
+    for (key, ttl) in keys {
+        let claimed: Option<String> = match redis::cmd("SET")
+            .arg(key)
+            .arg(job_id)
+            .arg("NX")
+            .arg("EX")
+            .arg(*ttl)
+            .query_async(&mut conn)
+            .await
+        {
+            Ok(claimed) => claimed,
+            Err(err) => {
+                tracing::error!("unable to claim job key: {:?}", err);
+
+                return true;
+            }
+        };
+
+        if claimed.is_some() {
+            continue;
+        }
+
+        match conn.get::<_, Option<String>>(key).await {
+            Ok(Some(owner)) if owner != job_id => {
+                tracing::debug!(%key, %owner, "job key was already claimed");
+
+                return false;
+            }
+            Ok(_) => (),
+            Err(err) => {
+                tracing::error!("unable to check job key owner: {:?}", err);
+            }
+        }
+    }
+
+    true
+}
+
 fn get_custom_span(job: &faktory::Job) -> tracing::Span {
     let custom: HashMap<String, String> = job
         .custom
@@ -382,7 +1014,7 @@ fn get_custom_span(job: &faktory::Job) -> tracing::Span {
     let propagator = opentelemetry::sdk::propagation::TraceContextPropagator::new();
     let context = propagator.extract(&custom);
 
-    let span = tracing::info_span!("faktory_job");
+    let span = tracing::info_span!("faktory_job", job_id = job.id(), kind = job.kind());
     span.set_parent(context);
 
     span
@@ -0,0 +1,129 @@
+use std::sync::Arc;
+
+use crate::{Error, Handler};
+
+lazy_static::lazy_static! {
+    static ref JOB_DURATION: prometheus::HistogramVec = prometheus::register_histogram_vec!("foxbot_job_duration_seconds", "Time to process a job", &["job"]).unwrap();
+    static ref JOB_FAILURE: prometheus::CounterVec = prometheus::register_counter_vec!("foxbot_job_failure_total", "Number of failed job attempts", &["job"]).unwrap();
+    static ref TELEGRAM_ERROR: prometheus::CounterVec = prometheus::register_counter_vec!("foxbot_telegram_error_total", "Number of errors returned by Telegram", &["code"]).unwrap();
+    static ref SITE_LOGGED_IN: prometheus::IntGaugeVec = prometheus::register_int_gauge_vec!("foxbot_site_logged_in", "If a site is still accepting its credentials", &["site"]).unwrap();
+}
+
+/// Start timing a job, which is recorded when the timer is dropped.
+pub fn job_timer(kind: &str) -> prometheus::HistogramTimer {
+    JOB_DURATION.with_label_values(&[kind]).start_timer()
+}
+
+/// Record that a job attempt failed.
+pub fn job_failed(kind: &str, err: &Error) {
+    JOB_FAILURE.with_label_values(&[kind]).inc();
+
+    if let Error::Telegram(err) = err {
+        TELEGRAM_ERROR
+            .with_label_values(&[&foxbot_utils::telegram_error_code(err)])
+            .inc();
+    }
+}
+
+/// Record if a site is still accepting its credentials.
+pub fn site_logged_in(site: &str, logged_in: bool) {
+    SITE_LOGGED_IN
+        .with_label_values(&[site])
+        .set(logged_in as i64);
+}
+
+async fn metrics(
+    req: hyper::Request<hyper::Body>,
+    runtime: tokio::runtime::Handle,
+    handler: Arc<Handler>,
+) -> Result<hyper::Response<hyper::Body>, std::convert::Infallible> {
+    use hyper::{Body, Response, StatusCode};
+
+    match req.uri().path() {
+        "/health" => Ok(Response::new(Body::from("OK"))),
+        path @ "/healthz" | path @ "/readyz" => {
+            // Connections belong to the worker's runtime, so checks must run
+            // there instead of on the metrics runtime.
+            let report = match runtime
+                .spawn(async move { handler.health_report().await })
+                .await
+            {
+                Ok(report) => report,
+                Err(err) => {
+                    tracing::error!("unable to run health checks: {:?}", err);
+
+                    let mut resp = Response::new(Body::default());
+                    *resp.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+                    return Ok(resp);
+                }
+            };
+
+            let healthy = if path == "/healthz" {
+                report.is_live()
+            } else {
+                report.is_ready()
+            };
+
+            let mut resp = Response::new(Body::from(report.to_json().to_string()));
+            resp.headers_mut().insert(
+                hyper::header::CONTENT_TYPE,
+                hyper::header::HeaderValue::from_static("application/json"),
+            );
+            if !healthy {
+                *resp.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+            }
+
+            Ok(resp)
+        }
+        "/metrics" => {
+            use prometheus::Encoder;
+            let encoder = prometheus::TextEncoder::new();
+            let metric_families = prometheus::gather();
+            let mut buf = vec![];
+            encoder.encode(&metric_families, &mut buf).unwrap();
+
+            Ok(Response::new(Body::from(buf)))
+        }
+        _ => {
+            let mut not_found = Response::new(Body::default());
+            *not_found.status_mut() = StatusCode::NOT_FOUND;
+            Ok(not_found)
+        }
+    }
+}
+
+/// Serve Prometheus metrics and health checks on a separate thread.
+///
+/// The worker's runtime only makes progress while jobs are running, so the
+/// metrics server gets its own.
+pub fn serve(
+    host: &str,
+    runtime: tokio::runtime::Handle,
+    handler: Arc<Handler>,
+) -> std::thread::JoinHandle<()> {
+    let addr: std::net::SocketAddr = host.parse().expect("invalid METRICS_HOST");
+
+    std::thread::spawn(move || {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("unable to create metrics runtime");
+
+        runtime.block_on(async move {
+            let make_svc = hyper::service::make_service_fn(move |_conn| {
+                let runtime = runtime.clone();
+                let handler = handler.clone();
+
+                async move {
+                    Ok::<_, std::convert::Infallible>(hyper::service::service_fn(move |req| {
+                        metrics(req, runtime.clone(), handler.clone())
+                    }))
+                }
+            });
+
+            tracing::info!("metrics listening on http://{}", addr);
+
+            hyper::Server::bind(&addr).serve(make_svc).await.unwrap();
+        });
+    })
+}
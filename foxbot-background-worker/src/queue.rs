@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use foxbot_models::QueuedJobs;
+
+use crate::Error;
+
+/// A function that processes a single job.
+pub type JobFn = Arc<dyn Fn(faktory::Job) -> Result<(), Error> + Send + Sync>;
+
+/// Registered job functions, by job kind.
+pub type Jobs = HashMap<String, JobFn>;
+
+/// A backend for storing and running jobs.
+#[async_trait]
+pub trait JobQueue: Send + Sync {
+    /// Name of the backend, used when reporting its health.
+    fn name(&self) -> &'static str;
+
+    /// Check that the backend is reachable.
+    async fn check(&self) -> anyhow::Result<()>;
+
+    /// Add a job to its queue. Jobs with `at` set must not run before then.
+    async fn enqueue(&self, job: faktory::Job) -> anyhow::Result<()>;
+
+    /// Process jobs using a number of workers, checking queues in the order
+    /// given. This blocks forever.
+    fn run(&self, jobs: Jobs, workers: usize, queues: Vec<&'static str>);
+}
+
+/// A queue using a Faktory server, from the `FAKTORY_URL` environment
+/// variable.
+pub struct FaktoryQueue {
+    producer: Arc<Mutex<faktory::Producer<std::net::TcpStream>>>,
+}
+
+impl FaktoryQueue {
+    pub fn connect() -> anyhow::Result<Self> {
+        let producer = faktory::Producer::connect(None)?;
+
+        Ok(Self {
+            producer: Arc::new(Mutex::new(producer)),
+        })
+    }
+}
+
+#[async_trait]
+impl JobQueue for FaktoryQueue {
+    fn name(&self) -> &'static str {
+        "faktory"
+    }
+
+    async fn check(&self) -> anyhow::Result<()> {
+        foxbot_utils::health::check_faktory(None).await
+    }
+
+    async fn enqueue(&self, job: faktory::Job) -> anyhow::Result<()> {
+        let producer = self.producer.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let mut producer = producer
+                .lock()
+                .map_err(|_err| anyhow::anyhow!("faktory producer was poisoned"))?;
+            producer.enqueue(job)?;
+
+            Ok(())
+        })
+        .await?
+    }
+
+    fn run(&self, jobs: Jobs, workers: usize, queues: Vec<&'static str>) {
+        let mut consumer = faktory::ConsumerBuilder::default();
+        consumer.workers(workers);
+
+        for (kind, f) in jobs {
+            consumer.register(kind, move |job| f(job));
+        }
+
+        let consumer = consumer.connect(None).unwrap();
+        consumer.run_to_completion(&queues);
+    }
+}
+
+/// A queue stored in Postgres, so small deployments don't need to run a
+/// Faktory server.
+///
+/// Workers reserve jobs with `SKIP LOCKED` and wait for new jobs with
+/// `LISTEN`, falling back to polling for scheduled jobs.
+#[derive(Clone)]
+pub struct PostgresQueue {
+    conn: sqlx::Pool<sqlx::Postgres>,
+    /// Used to open each worker's listener on its own connection, so
+    /// listeners don't hold onto connections from the pool.
+    database_url: String,
+    runtime: Arc<tokio::runtime::Runtime>,
+}
+
+/// Seconds a reserved job is locked before another worker may run it.
+const RESERVE_FOR: f64 = 60.0 * 30.0;
+/// Longest time to wait for a notification before checking for jobs again.
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+impl PostgresQueue {
+    pub fn new(
+        conn: sqlx::Pool<sqlx::Postgres>,
+        database_url: String,
+        runtime: Arc<tokio::runtime::Runtime>,
+    ) -> Self {
+        Self {
+            conn,
+            database_url,
+            runtime,
+        }
+    }
+
+    fn work(&self, jobs: &Jobs, queues: &[String]) -> anyhow::Result<()> {
+        let mut listener = self
+            .runtime
+            .block_on(sqlx::postgres::PgListener::connect(&self.database_url))?;
+        self.runtime.block_on(listener.listen("job_queue"))?;
+
+        loop {
+            let queued =
+                match self
+                    .runtime
+                    .block_on(QueuedJobs::reserve(&self.conn, queues, RESERVE_FOR))
+                {
+                    Ok(Some(queued)) => queued,
+                    Ok(None) => {
+                        // Timing out is expected, it means no jobs were added.
+                        let _ = self
+                            .runtime
+                            .block_on(tokio::time::timeout(POLL_INTERVAL, listener.recv()));
+                        continue;
+                    }
+                    Err(err) => {
+                        tracing::error!("unable to reserve job: {:?}", err);
+                        std::thread::sleep(POLL_INTERVAL);
+                        continue;
+                    }
+                };
+
+            let job: faktory::Job = match serde_json::from_value(queued.job) {
+                Ok(job) => job,
+                Err(err) => {
+                    tracing::error!(id = queued.id, "queued job was invalid: {:?}", err);
+                    self.runtime
+                        .block_on(QueuedJobs::complete(&self.conn, queued.id))?;
+                    continue;
+                }
+            };
+
+            let result = match jobs.get(job.kind()) {
+                Some(f) => f(job),
+                None => {
+                    tracing::error!(kind = job.kind(), "no function registered for job");
+                    Err(Error::MissingData)
+                }
+            };
+
+            if let Err(err) = result {
+                let delay = retry_delay(queued.attempts);
+                tracing::warn!(id = queued.id, delay, "job failed: {:?}", err);
+
+                self.runtime
+                    .block_on(QueuedJobs::retry(&self.conn, queued.id, delay))?;
+            } else {
+                self.runtime
+                    .block_on(QueuedJobs::complete(&self.conn, queued.id))?;
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl JobQueue for PostgresQueue {
+    fn name(&self) -> &'static str {
+        "postgres_queue"
+    }
+
+    async fn check(&self) -> anyhow::Result<()> {
+        foxbot_utils::health::check_postgres(&self.conn).await
+    }
+
+    async fn enqueue(&self, job: faktory::Job) -> anyhow::Result<()> {
+        let run_at = job.at.map(|at| at.timestamp());
+        let data = serde_json::to_value(&job)?;
+
+        QueuedJobs::push(&self.conn, &job.queue, data, run_at).await
+    }
+
+    fn run(&self, jobs: Jobs, workers: usize, queues: Vec<&'static str>) {
+        let jobs = Arc::new(jobs);
+        let queues: Arc<Vec<String>> = Arc::new(queues.into_iter().map(String::from).collect());
+
+        let threads: Vec<_> = (0..workers)
+            .map(|_| {
+                let queue = self.clone();
+                let jobs = jobs.clone();
+                let queues = queues.clone();
+
+                std::thread::spawn(move || loop {
+                    if let Err(err) = queue.work(&jobs, &queues) {
+                        tracing::error!("postgres queue worker failed: {:?}", err);
+                        std::thread::sleep(POLL_INTERVAL);
+                    }
+                })
+            })
+            .collect();
+
+        for thread in threads {
+            thread.join().unwrap();
+        }
+    }
+}
+
+/// Seconds to wait before retrying a job, growing with each attempt like
+/// Faktory's retry schedule.
+fn retry_delay(attempts: i32) -> f64 {
+    let attempts = attempts.max(1) as f64;
+
+    attempts.powi(4) + 15.0
+}
@@ -0,0 +1,26 @@
+use std::sync::Arc;
+
+use crate::*;
+
+/// Seconds between removing expired rehosted images.
+const CLEANUP_INTERVAL: u64 = 60 * 60;
+
+/// Periodically remove rehosted images that have expired.
+pub async fn run_cleanup(handler: Arc<Handler>) {
+    let media_cache = match &handler.media_cache {
+        Some(media_cache) => media_cache,
+        None => return,
+    };
+
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(CLEANUP_INTERVAL));
+
+    loop {
+        interval.tick().await;
+
+        match media_cache.remove_expired(&handler.conn).await {
+            Ok(0) => (),
+            Ok(count) => tracing::info!(count, "removed expired rehosted images"),
+            Err(err) => tracing::error!("unable to remove expired rehosted images: {:?}", err),
+        }
+    }
+}
@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+
+use redis::AsyncCommands;
+
+use crate::*;
+use foxbot_utils::{with_job_id, SCHEDULED_JOBS_KEY};
+
+/// Hash of scheduled job IDs to their serialized [`ScheduledJob`].
+const SCHEDULED_JOB_DATA_KEY: &str = "scheduled-job-data";
+/// Prefix of keys marking jobs that were enqueued again after being overdue.
+const REPLACED_JOB_PREFIX: &str = "scheduled-job-replaced";
+/// Seconds to remember that an overdue job was enqueued again, longer than
+/// Faktory could reasonably still hold onto the original.
+const REPLACED_JOB_TTL: usize = 60 * 60 * 24;
+/// Seconds a replaced job may be running before assuming it crashed, the same
+/// as how long Faktory reserves jobs for by default.
+const REPLACED_JOB_RUNNING_TTL: usize = 60 * 30;
+
+/// Seconds between checks for scheduled jobs that never ran.
+const FALLBACK_CHECK_INTERVAL: u64 = 30;
+/// Seconds a scheduled job may be late before assuming Faktory lost it.
+const OVERDUE_GRACE: i64 = 120;
+
+/// Everything needed to enqueue a scheduled job again.
+#[derive(serde::Deserialize, serde::Serialize)]
+struct ScheduledJob {
+    kind: String,
+    queue: String,
+    args: Vec<serde_json::Value>,
+    #[serde(default)]
+    custom: HashMap<String, serde_json::Value>,
+}
+
+/// Keep a copy of a job that is scheduled to run later, so it can still be
+/// run if Faktory loses it.
+pub async fn remember(
+    conn: &redis::aio::ConnectionManager,
+    job: &faktory::Job,
+    at: chrono::DateTime<chrono::Utc>,
+) -> anyhow::Result<()> {
+    let data = serde_json::to_string(&ScheduledJob {
+        kind: job.kind().to_string(),
+        queue: job.queue.clone(),
+        args: job.args().to_vec(),
+        custom: job.custom.clone(),
+    })?;
+
+    let mut conn = conn.clone();
+
+    redis::pipe()
+        .atomic()
+        .zadd(SCHEDULED_JOBS_KEY, job.id(), at.timestamp())
+        .hset(SCHEDULED_JOB_DATA_KEY, job.id(), data)
+        .query_async::<_, ()>(&mut conn)
+        .await?;
+
+    Ok(())
+}
+
+/// Check if a job that is starting should run, and stop tracking it.
+///
+/// Overdue jobs are enqueued again with the same ID while Faktory may still
+/// have the original, so only one copy of a replaced job may run at a time
+/// and none may run after one succeeded. A copy that started must call
+/// [`finish`] once it is done.
+pub async fn start(conn: &redis::aio::ConnectionManager, job_id: &str) -> anyhow::Result<bool> {
+    forget(conn, job_id).await?;
+
+    let script = redis::Script::new(
+        r#"
+        local state = redis.call('GET', KEYS[1])
+        if not state then
+            return 1
+        end
+        if state ~= 'pending' then
+            return 0
+        end
+        redis.call('SET', KEYS[1], 'running', 'EX', ARGV[1])
+        return 1
+        "#,
+    );
+
+    let mut conn = conn.clone();
+    let should_run: bool = script
+        .key(format!("{}:{}", REPLACED_JOB_PREFIX, job_id))
+        .arg(REPLACED_JOB_RUNNING_TTL)
+        .invoke_async(&mut conn)
+        .await?;
+
+    Ok(should_run)
+}
+
+/// Record that a job allowed to run by [`start`] is done. If a replaced job
+/// failed, the next copy or retry of it may run again.
+pub async fn finish(
+    conn: &redis::aio::ConnectionManager,
+    job_id: &str,
+    succeeded: bool,
+) -> anyhow::Result<()> {
+    let script = redis::Script::new(
+        r#"
+        if redis.call('GET', KEYS[1]) == 'running' then
+            redis.call('SET', KEYS[1], ARGV[1], 'EX', ARGV[2])
+        end
+        "#,
+    );
+
+    let mut conn = conn.clone();
+    script
+        .key(format!("{}:{}", REPLACED_JOB_PREFIX, job_id))
+        .arg(if succeeded { "ran" } else { "pending" })
+        .arg(REPLACED_JOB_TTL)
+        .invoke_async::<_, ()>(&mut conn)
+        .await?;
+
+    Ok(())
+}
+
+/// Remove a job that has started running, returning if it was still being
+/// tracked.
+pub async fn forget(conn: &redis::aio::ConnectionManager, job_id: &str) -> anyhow::Result<bool> {
+    let mut conn = conn.clone();
+
+    let (removed, _): (usize, usize) = redis::pipe()
+        .atomic()
+        .zrem(SCHEDULED_JOBS_KEY, job_id)
+        .hdel(SCHEDULED_JOB_DATA_KEY, job_id)
+        .query_async(&mut conn)
+        .await?;
+
+    Ok(removed > 0)
+}
+
+/// Periodically enqueue scheduled jobs that should have already run.
+///
+/// Multiple workers may run this at once, but only the worker that removes a
+/// job from the set will enqueue it.
+pub async fn run_fallback_scheduler(handler: Arc<Handler>) {
+    let mut interval =
+        tokio::time::interval(std::time::Duration::from_secs(FALLBACK_CHECK_INTERVAL));
+
+    loop {
+        interval.tick().await;
+
+        if let Err(err) = enqueue_overdue(&handler).await {
+            tracing::error!("unable to enqueue overdue jobs: {:?}", err);
+        }
+    }
+}
+
+async fn enqueue_overdue(handler: &Handler) -> anyhow::Result<()> {
+    let mut conn = handler.redis.clone();
+
+    let before = chrono::Utc::now().timestamp() - OVERDUE_GRACE;
+    let job_ids: Vec<String> = conn
+        .zrangebyscore(SCHEDULED_JOBS_KEY, "-inf", before)
+        .await?;
+
+    // Removing the job and marking it as replaced happens at once, so the
+    // original either starts before and the job isn't enqueued again, or
+    // starts after and knows it was replaced.
+    let script = redis::Script::new(
+        r#"
+        if redis.call('ZREM', KEYS[1], ARGV[1]) == 0 then
+            return {0, false}
+        end
+        local data = redis.call('HGET', KEYS[2], ARGV[1])
+        redis.call('HDEL', KEYS[2], ARGV[1])
+        if data then
+            redis.call('SET', KEYS[3], 'pending', 'EX', ARGV[2])
+        end
+        return {1, data}
+        "#,
+    );
+
+    for job_id in job_ids {
+        let (removed, data): (bool, Option<String>) = script
+            .key(SCHEDULED_JOBS_KEY)
+            .key(SCHEDULED_JOB_DATA_KEY)
+            .key(format!("{}:{}", REPLACED_JOB_PREFIX, job_id))
+            .arg(&job_id)
+            .arg(REPLACED_JOB_TTL)
+            .invoke_async(&mut conn)
+            .await?;
+
+        if !removed {
+            continue;
+        }
+
+        let scheduled: ScheduledJob = match data.as_deref().map(serde_json::from_str) {
+            Some(Ok(scheduled)) => scheduled,
+            _ => {
+                tracing::warn!(%job_id, "overdue job was missing data");
+                continue;
+            }
+        };
+
+        tracing::warn!(%job_id, kind = %scheduled.kind, "enqueuing overdue job");
+
+        let mut job = faktory::Job::new(scheduled.kind, scheduled.args).on_queue(&scheduled.queue);
+        job.custom = scheduled.custom;
+        let job = with_job_id(job, &job_id)?;
+
+        handler.enqueue(job).await;
+    }
+
+    Ok(())
+}
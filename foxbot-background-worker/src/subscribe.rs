@@ -3,7 +3,8 @@ use std::sync::Arc;
 use fluent::fluent_args;
 
 use crate::*;
-use foxbot_models::Subscriptions;
+use foxbot_models::{RepostWatches, Subscriptions};
+use foxbot_utils::callback::{routes, CallbackData};
 
 #[derive(serde::Serialize, serde::Deserialize)]
 struct HashNotify {
@@ -13,6 +14,9 @@ struct HashNotify {
     photo_id: Option<String>,
     #[serde(with = "string")]
     searched_hash: i64,
+    /// If this is for a repost watch, which is kept after notifying.
+    #[serde(default)]
+    repost: bool,
 }
 
 mod string {
@@ -52,9 +56,14 @@ pub async fn process_hash_new(handler: Arc<Handler>, job: faktory::Job) -> Resul
         .to_owned();
     let message: String = serde_json::value::from_value(data)?;
     let hash = message.parse().map_err(|_| Error::MissingData)?;
+    let site_id: Option<i64> = match job.args().get(1) {
+        Some(site_id) => serde_json::value::from_value(site_id.to_owned())?,
+        None => None,
+    };
 
     let subscriptions = Subscriptions::search_subscriptions(&handler.conn, hash).await?;
-    if subscriptions.is_empty() {
+    let repost_watches = RepostWatches::search(&handler.conn, hash).await?;
+    if subscriptions.is_empty() && repost_watches.is_empty() {
         tracing::trace!("got hash with no subscriptions");
         return Ok(());
     }
@@ -87,7 +96,7 @@ pub async fn process_hash_new(handler: Arc<Handler>, job: faktory::Job) -> Resul
                 buf.push_str(&get_message(bundle, "subscribe-found-multiple", None).unwrap());
                 buf.push('\n');
 
-                for result in matches {
+                for result in &matches {
                     let args = fluent_args![
                         "link" => result.url()
                     ];
@@ -111,21 +120,74 @@ pub async fn process_hash_new(handler: Arc<Handler>, job: faktory::Job) -> Resul
             searched_hash: sub.hash,
             message_id: sub.message_id,
             photo_id: sub.photo_id,
+            repost: false,
         })?;
 
-        let mut job = faktory::Job::new("hash_notify", vec![data]).on_queue("foxbot_background");
+        let mut job = faktory::Job::new("hash_notify", vec![data]).on_queue(QUEUE_DEFAULT);
         job.custom = get_faktory_custom();
 
         handler.enqueue(job).await;
     }
 
+    if repost_watches.is_empty() {
+        return Ok(());
+    }
+
+    // Only mention the file that was just indexed when FuzzySearch said which
+    // one it was, as the others were already known.
+    let reposts: Vec<_> = matches
+        .iter()
+        .filter(|file| {
+            site_id
+                .map(|site_id| file.site_id == site_id)
+                .unwrap_or(true)
+        })
+        .collect();
+    if reposts.is_empty() {
+        tracing::debug!("new file was not in matches for repost watch");
+        return Ok(());
+    }
+
+    let text = handler
+        .get_fluent_bundle(None, |bundle| {
+            let mut buf = get_message(bundle, "repost-found", None).unwrap();
+
+            for file in reposts {
+                let args = fluent_args![
+                    "link" => file.url()
+                ];
+
+                buf.push('\n');
+                buf.push_str(
+                    &get_message(bundle, "subscribe-found-multiple-item", Some(args)).unwrap(),
+                );
+            }
+
+            buf
+        })
+        .await;
+
+    for watch in repost_watches {
+        let data = serde_json::to_value(&HashNotify {
+            user_id: watch.user_id,
+            text: text.clone(),
+            searched_hash: watch.hash,
+            message_id: watch.message_id,
+            photo_id: watch.photo_id,
+            repost: true,
+        })?;
+
+        let job = faktory::Job::new("hash_notify", vec![data]).on_queue(QUEUE_DEFAULT);
+        handler.enqueue(job).await;
+    }
+
     Ok(())
 }
 
 #[tracing::instrument(skip(handler, job), fields(job_id = job.id()))]
 #[deny(clippy::unwrap_used)]
 pub async fn process_hash_notify(handler: Arc<Handler>, job: faktory::Job) -> Result<(), Error> {
-    use tgbotapi::requests::{SendMessage, SendPhoto};
+    use tgbotapi::requests::{ReplyMarkup, SendMessage, SendPhoto};
 
     let data = job
         .args()
@@ -135,6 +197,28 @@ pub async fn process_hash_notify(handler: Arc<Handler>, job: faktory::Job) -> Re
         .to_owned();
     let notify: HashNotify = serde_json::value::from_value(data)?;
 
+    let reply_markup = if notify.repost {
+        let stop = handler
+            .get_fluent_bundle(None, |bundle| {
+                get_message(bundle, "repost-stop", None).unwrap()
+            })
+            .await;
+
+        Some(ReplyMarkup::InlineKeyboardMarkup(InlineKeyboardMarkup {
+            inline_keyboard: vec![vec![InlineKeyboardButton {
+                text: stop,
+                callback_data: Some(
+                    CallbackData::new(routes::REPOST_STOP)
+                        .arg(notify.searched_hash)
+                        .encode()?,
+                ),
+                ..Default::default()
+            }]],
+        }))
+    } else {
+        None
+    };
+
     let mut was_sent = false;
 
     if let Some(photo_id) = notify.photo_id {
@@ -144,6 +228,7 @@ pub async fn process_hash_notify(handler: Arc<Handler>, job: faktory::Job) -> Re
             reply_to_message_id: notify.message_id,
             allow_sending_without_reply: Some(true),
             caption: Some(notify.text.clone()),
+            reply_markup: reply_markup.clone(),
             ..Default::default()
         };
 
@@ -158,12 +243,16 @@ pub async fn process_hash_notify(handler: Arc<Handler>, job: faktory::Job) -> Re
             reply_to_message_id: notify.message_id,
             text: notify.text,
             allow_sending_without_reply: Some(true),
+            reply_markup,
             ..Default::default()
         };
         handler.telegram.make_request(&send_message).await?;
     }
 
-    Subscriptions::remove_subscription(&handler.conn, notify.user_id, notify.searched_hash).await?;
+    if !notify.repost {
+        Subscriptions::remove_subscription(&handler.conn, notify.user_id, notify.searched_hash)
+            .await?;
+    }
 
     Ok(())
 }
@@ -0,0 +1,261 @@
+use std::sync::Arc;
+
+use tgbotapi::requests::{ReplyMarkup, SendMessage, SendPhoto};
+
+use crate::*;
+use foxbot_models::{ArtistWatch, ArtistWatches};
+use foxbot_sites::PostInfo;
+
+/// Seconds between checks for watched pages that are due to be checked.
+const WATCH_POLL_INTERVAL: u64 = 60;
+/// Seconds between checks of each watched page.
+const WATCH_CHECK_INTERVAL: f64 = 60.0 * 30.0;
+/// Most watched pages to check at once.
+const WATCH_BATCH_SIZE: i64 = 50;
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct WatchCheck {
+    watch_id: i32,
+}
+
+/// Periodically enqueue checks for watched pages that are due.
+///
+/// Multiple workers may run this at once, but each watch is only taken by one
+/// of them.
+pub async fn run_watch_poller(handler: Arc<Handler>) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(WATCH_POLL_INTERVAL));
+
+    loop {
+        interval.tick().await;
+
+        if let Err(err) = enqueue_due_watches(&handler).await {
+            tracing::error!("unable to enqueue watch checks: {:?}", err);
+        }
+    }
+}
+
+async fn enqueue_due_watches(handler: &Handler) -> anyhow::Result<()> {
+    let watch_ids =
+        ArtistWatches::take_due(&handler.conn, WATCH_CHECK_INTERVAL, WATCH_BATCH_SIZE).await?;
+
+    if !watch_ids.is_empty() {
+        tracing::debug!(count = watch_ids.len(), "enqueueing watch checks");
+    }
+
+    for watch_id in watch_ids {
+        let data = serde_json::to_value(&WatchCheck { watch_id })?;
+
+        let job = faktory::Job::new("watch_check", vec![data]).on_queue(QUEUE_LOW);
+        handler.enqueue(job).await;
+    }
+
+    Ok(())
+}
+
+#[tracing::instrument(skip(handler, job), fields(job_id = job.id()))]
+#[deny(clippy::unwrap_used)]
+pub async fn process_watch_check(handler: Arc<Handler>, job: faktory::Job) -> Result<(), Error> {
+    let data = job
+        .args()
+        .iter()
+        .next()
+        .ok_or(Error::MissingData)?
+        .to_owned();
+    let WatchCheck { watch_id } = serde_json::value::from_value(data)?;
+
+    let watch = match ArtistWatches::get(&handler.conn, watch_id).await? {
+        Some(watch) => watch,
+        None => {
+            tracing::debug!("watch was removed before it was checked");
+            return Ok(());
+        }
+    };
+
+    let posts = {
+        let mut sites = handler.sites.lock().await;
+
+        let site = match sites.iter_mut().find(|site| site.name() == watch.site) {
+            Some(site) => site,
+            None => {
+                tracing::warn!(site = %watch.site, "watch was for unknown site");
+                return Ok(());
+            }
+        };
+
+        site.latest_posts(&watch.url).await?
+    };
+
+    let posts = match posts {
+        Some(posts) => posts,
+        None => {
+            tracing::warn!(url = %watch.url, "site can no longer watch url");
+            return Ok(());
+        }
+    };
+
+    let new_posts = new_posts(&posts, watch.last_seen.as_deref());
+    tracing::debug!(count = new_posts.len(), "found new posts for watch");
+
+    // Start over from the newest post if the last seen post went missing,
+    // instead of sending everything on the page.
+    if new_posts.is_empty() {
+        if let Some(newest) = posts.first() {
+            if watch.last_seen.as_deref() != Some(newest.source_or_url()) {
+                tracing::info!("last seen post is missing, skipping to newest post");
+                ArtistWatches::set_last_seen(&handler.conn, watch.id, newest.source_or_url())
+                    .await?;
+            }
+        }
+    }
+
+    // Deliver the oldest posts first and remember each one as it's sent, so
+    // a failure part of the way through won't send posts again.
+    for post in new_posts.iter().rev() {
+        match send_post(&handler, &watch, post).await {
+            Ok(()) => (),
+            Err(tgbotapi::Error::Telegram(tgbotapi::TelegramError {
+                error_code: Some(403),
+                description,
+                ..
+            })) => {
+                tracing::warn!("got 403 error, removing watch: {:?}", description);
+                ArtistWatches::delete(&handler.conn, watch.id).await?;
+
+                return Ok(());
+            }
+            Err(err) => return Err(err.into()),
+        }
+
+        ArtistWatches::set_last_seen(&handler.conn, watch.id, post.source_or_url()).await?;
+    }
+
+    Ok(())
+}
+
+/// Get the posts newer than the last seen post, newest first.
+///
+/// If the last seen post is no longer on the page, such as after it was
+/// deleted, there's no way to tell which posts are new so none are returned.
+fn new_posts<'a>(posts: &'a [PostInfo], last_seen: Option<&str>) -> &'a [PostInfo] {
+    let last_seen = match last_seen {
+        Some(last_seen) => last_seen,
+        None => return posts,
+    };
+
+    match posts
+        .iter()
+        .position(|post| post.source_or_url() == last_seen)
+    {
+        Some(end) => &posts[..end],
+        None => &[],
+    }
+}
+
+/// Send a new post to the chat watching for it, with a button linking to its
+/// source.
+async fn send_post(
+    handler: &Handler,
+    watch: &ArtistWatch,
+    post: &PostInfo,
+) -> Result<(), tgbotapi::Error> {
+    let mut args = fluent::FluentArgs::new();
+    args.insert("url", watch.url.clone().into());
+
+    let text = handler
+        .get_fluent_bundle(None, |bundle| {
+            get_message(bundle, "watch-new-post", Some(args)).unwrap()
+        })
+        .await;
+
+    let markup = ReplyMarkup::InlineKeyboardMarkup(InlineKeyboardMarkup {
+        inline_keyboard: vec![vec![InlineKeyboardButton {
+            text: post.site_name.to_string(),
+            url: Some(post.source_or_url().to_string()),
+            ..Default::default()
+        }]],
+    });
+
+    if matches!(post.file_type.as_ref(), "png" | "jpg" | "jpeg") {
+        let photo_url = rehosted_url(handler, post).await;
+
+        let send_photo = SendPhoto {
+            chat_id: watch.chat_id.into(),
+            photo: tgbotapi::FileType::Url(photo_url),
+            caption: Some(text.clone()),
+            reply_markup: Some(markup.clone()),
+            ..Default::default()
+        };
+
+        match handler.telegram.make_request(&send_photo).await {
+            Ok(_) => return Ok(()),
+            // Telegram can't always load images by URL, so fall back to
+            // sending a link.
+            Err(tgbotapi::Error::Telegram(tgbotapi::TelegramError {
+                error_code: Some(400),
+                description,
+                ..
+            })) => {
+                tracing::warn!("unable to send watched post as photo: {:?}", description);
+            }
+            Err(err) => return Err(err),
+        }
+    }
+
+    let send_message = SendMessage {
+        chat_id: watch.chat_id.into(),
+        text: format!("{}\n{}", text, post.url),
+        reply_markup: Some(markup),
+        ..Default::default()
+    };
+
+    handler.telegram.make_request(&send_message).await?;
+
+    Ok(())
+}
+
+/// Get the URL Telegram should load a post's image from, rehosting it if
+/// its site needs it.
+async fn rehosted_url(handler: &Handler, post: &PostInfo) -> String {
+    let media_cache = match &handler.media_cache {
+        Some(media_cache) => media_cache,
+        None => return post.url.clone(),
+    };
+
+    match media_cache.rehost(&handler.conn, post).await {
+        Ok(post) => post.url,
+        Err(err) => {
+            tracing::warn!("unable to rehost watched post: {:?}", err);
+            post.url.clone()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::new_posts;
+    use foxbot_sites::PostInfo;
+
+    fn post(id: i32) -> PostInfo {
+        PostInfo {
+            url: format!("https://example.com/{}.png", id),
+            source_link: Some(format!("https://example.com/{}", id)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_new_posts() {
+        let posts = vec![post(3), post(2), post(1)];
+
+        let new = new_posts(&posts, Some("https://example.com/2"));
+        assert_eq!(new.len(), 1);
+        assert_eq!(new[0].source_link.as_deref(), Some("https://example.com/3"));
+
+        assert!(new_posts(&posts, Some("https://example.com/3")).is_empty());
+        assert!(
+            new_posts(&posts, Some("https://example.com/0")).is_empty(),
+            "posts should not be sent when the last seen post is missing"
+        );
+        assert_eq!(new_posts(&posts, None).len(), 3);
+    }
+}
@@ -0,0 +1,203 @@
+//! Resolve links with the same site loaders and configuration as the bot,
+//! printing what was found as JSON.
+//!
+//! Usage: `foxbot-cli [--user TELEGRAM_ID] URL...`
+//!
+//! Logs are written to stderr, so the output can be piped to tools like `jq`.
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use foxbot_sites::{
+    credentials::UserCredentials, Anonymous, BoxedSite, Credentials, PostInfo, Proxies,
+};
+
+#[derive(Deserialize)]
+struct Config {
+    // Site config
+    fa_a: String,
+    fa_b: String,
+    flaresolverr_endpoint: Option<String>,
+    weasyl_apitoken: String,
+    inkbunny_username: String,
+    inkbunny_password: String,
+    e621_login: String,
+    e621_api_key: String,
+
+    // Twitter config
+    twitter_consumer_key: String,
+    twitter_consumer_secret: String,
+    twitter_thread_limit: Option<usize>,
+
+    // FuzzySearch config
+    fautil_apitoken: String,
+
+    proxy: Option<String>,
+    site_proxies: Option<Vec<String>>,
+    database_url: String,
+}
+
+struct Args {
+    /// Telegram user whose saved credentials should be used.
+    user_id: Option<i64>,
+    links: Vec<String>,
+}
+
+/// What happened when resolving a link.
+#[derive(Serialize)]
+struct Resolution {
+    /// The link as it was given.
+    link: String,
+    /// The link after shortened links were followed and tracking parameters
+    /// were removed, which is what sites are matched against.
+    url: String,
+    /// The first site that supported the link.
+    site: Option<&'static str>,
+    url_id: Option<String>,
+    /// Posts loaded by the site, empty if it supported the link but found
+    /// nothing.
+    posts: Option<Vec<PostInfo>>,
+    error: Option<String>,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    load_env();
+
+    tracing_subscriber::fmt()
+        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+        .with_writer(std::io::stderr)
+        .init();
+
+    let args = parse_args()?;
+
+    let secrets = foxbot_utils::secrets::providers_from_env();
+    let config: Config = foxbot_utils::config::load_config_with_secrets(
+        &secrets,
+        foxbot_utils::secrets::SITE_CREDENTIALS,
+    )
+    .await?;
+
+    // Sites save sessions and look up user credentials in the database, but
+    // links that don't need them can be resolved without connecting.
+    let pool = sqlx::postgres::PgPoolOptions::new()
+        .connect_lazy(&config.database_url)
+        .context("invalid DATABASE_URL")?;
+
+    let proxies = Proxies::new(
+        config.proxy.clone(),
+        config.site_proxies.as_deref().unwrap_or_default(),
+    )?;
+
+    let mut sites = foxbot_sites::get_all_sites(
+        config.fa_a,
+        config.fa_b,
+        config.flaresolverr_endpoint,
+        config.fautil_apitoken,
+        config.weasyl_apitoken,
+        config.twitter_consumer_key,
+        config.twitter_consumer_secret,
+        config.twitter_thread_limit.unwrap_or_default(),
+        config.inkbunny_username,
+        config.inkbunny_password,
+        config.e621_login,
+        config.e621_api_key,
+        pool.clone(),
+        &proxies,
+    )
+    .await;
+
+    let credentials: Box<dyn Credentials> = match args.user_id {
+        Some(user_id) => Box::new(UserCredentials::new(pool, user_id)),
+        None => Box::new(Anonymous),
+    };
+
+    let mut failed = false;
+
+    for link in args.links {
+        let resolution = resolve(&mut sites, credentials.as_ref(), link).await;
+        failed |= resolution.error.is_some();
+
+        println!("{}", serde_json::to_string_pretty(&resolution)?);
+    }
+
+    if failed {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+fn parse_args() -> anyhow::Result<Args> {
+    let mut args = std::env::args().skip(1);
+    let mut parsed = Args {
+        user_id: None,
+        links: vec![],
+    };
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--user" => {
+                let user_id = args.next().context("--user needs a Telegram user ID")?;
+                parsed.user_id = Some(
+                    user_id
+                        .parse()
+                        .context("--user must be a Telegram user ID")?,
+                );
+            }
+            _ => parsed.links.push(arg),
+        }
+    }
+
+    if parsed.links.is_empty() {
+        anyhow::bail!("usage: foxbot-cli [--user TELEGRAM_ID] URL...");
+    }
+
+    Ok(parsed)
+}
+
+/// Resolve a link the same way the bot does, with the first site that
+/// supports it.
+async fn resolve(
+    sites: &mut [BoxedSite],
+    credentials: &dyn Credentials,
+    link: String,
+) -> Resolution {
+    let url = foxbot_utils::urls::normalize_url(&link).await;
+
+    let mut resolution = Resolution {
+        link,
+        url,
+        site: None,
+        url_id: None,
+        posts: None,
+        error: None,
+    };
+
+    for site in sites.iter_mut() {
+        let url_match = match site.match_url(&resolution.url).await {
+            Some(url_match) => url_match,
+            None => continue,
+        };
+
+        resolution.site = Some(site.name());
+        resolution.url_id = site.url_id(&resolution.url);
+
+        match site.get_images(credentials, &url_match).await {
+            Ok(posts) => resolution.posts = Some(posts.unwrap_or_default()),
+            Err(err) => resolution.error = Some(format!("{:?}", err)),
+        }
+
+        break;
+    }
+
+    resolution
+}
+
+#[cfg(feature = "env")]
+fn load_env() {
+    dotenv::dotenv().unwrap();
+}
+
+#[cfg(not(feature = "env"))]
+fn load_env() {}
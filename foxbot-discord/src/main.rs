@@ -0,0 +1,204 @@
+//! Discord frontend that replies to images posted in servers with their
+//! sources, like the bot does in Telegram groups.
+
+use anyhow::Context as _;
+use serde::Deserialize;
+use serenity::{
+    async_trait,
+    client::{Context, EventHandler},
+    model::{channel::Attachment, channel::Message, gateway::Ready},
+    prelude::GatewayIntents,
+};
+
+use foxbot_models::FileCache;
+use foxbot_utils::{build_source_text, download, LangBundle};
+
+/// Largest attachment that will be downloaded when `MEDIA_MAX_BYTES` isn't
+/// set.
+const DEFAULT_MAX_BYTES: u64 = 50_000_000;
+/// Distance sources must be within to be included in a reply.
+const MAX_SOURCE_DISTANCE: i64 = 3;
+/// Extensions of attachments that can be searched.
+const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "gif", "webp"];
+
+#[derive(Deserialize)]
+struct Config {
+    discord_token: String,
+    fautil_apitoken: String,
+    database_url: String,
+    media_max_bytes: Option<u64>,
+    media_max_pixels: Option<u64>,
+}
+
+/// Credentials used by the Discord frontend, which may be loaded from a
+/// [`SecretProvider`](foxbot_utils::secrets::SecretProvider).
+static DISCORD_CREDENTIALS: &[&str] = &["DISCORD_TOKEN", "FAUTIL_APITOKEN", "DATABASE_URL"];
+
+struct Handler {
+    conn: sqlx::Pool<sqlx::Postgres>,
+    fapi: fuzzysearch::FuzzySearch,
+    bundle: LangBundle,
+    max_bytes: u64,
+}
+
+#[tokio::main]
+async fn main() {
+    load_env();
+
+    tracing_subscriber::fmt()
+        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+        .init();
+
+    let secrets = foxbot_utils::secrets::providers_from_env();
+    let config: Config =
+        foxbot_utils::config::load_config_with_secrets(&secrets, DISCORD_CREDENTIALS)
+            .await
+            .expect("unable to load config");
+
+    let conn = sqlx::postgres::PgPoolOptions::new()
+        .max_connections(2)
+        .connect(&config.database_url)
+        .await
+        .expect("unable to connect to database");
+
+    download::set_limits(download::DownloadLimits::new(
+        config.media_max_bytes.map(|max_bytes| max_bytes as usize),
+        config.media_max_pixels,
+    ));
+
+    let langs = foxbot_utils::load_langs();
+
+    let handler = Handler {
+        conn,
+        fapi: fuzzysearch::FuzzySearch::new(config.fautil_apitoken),
+        bundle: foxbot_utils::get_lang_bundle(&langs, foxbot_utils::DEFAULT_LANG),
+        max_bytes: config.media_max_bytes.unwrap_or(DEFAULT_MAX_BYTES),
+    };
+
+    let mut client = serenity::Client::builder(&config.discord_token)
+        .intents(GatewayIntents::GUILD_MESSAGES)
+        .event_handler(handler)
+        .await
+        .expect("unable to create discord client");
+
+    if let Err(err) = client.start().await {
+        tracing::error!("discord client stopped: {:?}", err);
+    }
+}
+
+#[async_trait]
+impl EventHandler for Handler {
+    async fn ready(&self, _ctx: Context, ready: Ready) {
+        tracing::info!(user = %ready.user.name, "connected to discord");
+    }
+
+    async fn message(&self, ctx: Context, msg: Message) {
+        if msg.author.bot || msg.guild_id.is_none() {
+            return;
+        }
+
+        if let Err(err) = self.reply_with_sources(&ctx, &msg).await {
+            tracing::error!(message_id = %msg.id, "unable to source message: {:?}", err);
+        }
+    }
+}
+
+impl Handler {
+    /// Reply to a message with the sources of its images, if any were found.
+    async fn reply_with_sources(&self, ctx: &Context, msg: &Message) -> anyhow::Result<()> {
+        let mut matches = Vec::new();
+
+        for attachment in msg
+            .attachments
+            .iter()
+            .filter(|attachment| is_image(attachment) && attachment.size <= self.max_bytes)
+        {
+            let hash = self.hash_attachment(attachment).await?;
+            let files =
+                foxbot_utils::lookup_single_hash(&self.fapi, hash, Some(MAX_SOURCE_DISTANCE))
+                    .await?;
+
+            // Only the closest sources are useful, like in Telegram groups.
+            let closest = match files.first().and_then(|file| file.distance) {
+                Some(distance) => distance,
+                None => continue,
+            };
+            matches.extend(
+                files
+                    .into_iter()
+                    .take_while(|file| file.distance == Some(closest)),
+            );
+        }
+
+        if matches.is_empty() {
+            return Ok(());
+        }
+
+        let matches: Vec<_> = matches.iter().collect();
+        let text = build_source_text(&self.bundle, &matches);
+
+        msg.channel_id
+            .send_message(&ctx.http, |m| {
+                m.content(text)
+                    .reference_message(msg)
+                    .allowed_mentions(|mentions| mentions.replied_user(false))
+            })
+            .await
+            .context("unable to send sources")?;
+
+        Ok(())
+    }
+
+    /// Get the hash of an attachment, downloading it only if it wasn't
+    /// already hashed.
+    ///
+    /// Hashes are cached with Telegram files, using the attachment ID with a
+    /// prefix as the file ID.
+    async fn hash_attachment(&self, attachment: &Attachment) -> anyhow::Result<i64> {
+        let file_id = format!("discord-{}", attachment.id);
+
+        if let Some(hash) = FileCache::get(&self.conn, &file_id)
+            .await
+            .context("unable to query file cache")?
+        {
+            return Ok(hash);
+        }
+
+        // Discord includes the dimensions of images it could read, so check
+        // them before anything is downloaded.
+        if let (Some(width), Some(height)) = (attachment.width, attachment.height) {
+            let max_pixels = download::limits().max_pixels;
+            if width.saturating_mul(height) > max_pixels {
+                anyhow::bail!("attachment is {}x{}, larger than allowed", width, height);
+            }
+        }
+
+        let data = attachment
+            .download()
+            .await
+            .context("unable to download attachment")?;
+        download::check_image(&data)?;
+
+        let hash = foxbot_utils::decode::hash_bytes(data).await?;
+
+        FileCache::set(&self.conn, &file_id, hash)
+            .await
+            .context("unable to set file cache")?;
+
+        Ok(hash)
+    }
+}
+
+fn is_image(attachment: &Attachment) -> bool {
+    foxbot_sites::get_file_ext(&attachment.filename)
+        .map(|ext| IMAGE_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+#[cfg(feature = "env")]
+fn load_env() {
+    dotenv::dotenv().unwrap();
+}
+
+#[cfg(not(feature = "env"))]
+fn load_env() {}
@@ -1,9 +1,19 @@
+use std::collections::HashMap;
+
 use anyhow::Context;
 
 lazy_static::lazy_static! {
     static ref CACHE_REQUESTS: prometheus::CounterVec = prometheus::register_counter_vec!("foxbot_cache_requests_total", "Number of file cache hits and misses", &["result"]).unwrap();
 }
 
+/// Number of file cache hits and misses since the process started.
+pub fn file_cache_requests() -> (u64, u64) {
+    let hits = CACHE_REQUESTS.with_label_values(&["hit"]).get() as u64;
+    let misses = CACHE_REQUESTS.with_label_values(&["miss"]).get() as u64;
+
+    (hits, misses)
+}
+
 /// Each available site, for configuration usage.
 #[derive(Clone, Debug, PartialEq, Hash, Eq, serde::Deserialize)]
 pub enum Sites {
@@ -66,12 +76,45 @@ pub struct UserConfig;
 
 pub enum UserConfigKey {
     SiteSortOrder,
+    NeverSharePersonal,
+    InlineCaptions,
 }
 
 impl UserConfigKey {
     fn as_str(&self) -> &str {
         match self {
             UserConfigKey::SiteSortOrder => "site-sort-order",
+            UserConfigKey::NeverSharePersonal => "never-share-personal",
+            UserConfigKey::InlineCaptions => "inline-captions",
+        }
+    }
+}
+
+/// How inline results use text from a post, like a Tweet, as a caption.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InlineCaptions {
+    /// Send one result without the caption and another with it.
+    Both,
+    /// Only send results with the caption.
+    Always,
+    /// Never include the caption.
+    Never,
+}
+
+impl Default for InlineCaptions {
+    fn default() -> Self {
+        Self::Both
+    }
+}
+
+impl InlineCaptions {
+    /// The option after this one, for cycling through them in settings.
+    pub fn next(self) -> Self {
+        match self {
+            Self::Both => Self::Always,
+            Self::Always => Self::Never,
+            Self::Never => Self::Both,
         }
     }
 }
@@ -140,12 +183,91 @@ impl UserConfig {
     }
 }
 
+/// How sources are added to a channel post.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceStyle {
+    /// Inline keyboard buttons, falling back to the caption for media groups.
+    Buttons,
+    /// Always written into the caption.
+    Caption,
+}
+
+/// When sources should be posted as a reply in a channel's linked discussion
+/// group.
+#[derive(Clone, Copy, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CompanionReplies {
+    /// Never reply in the discussion group.
+    Off,
+    /// Only reply when the channel post could not be edited.
+    Fallback,
+    /// Reply in addition to editing the channel post.
+    Also,
+    /// Reply without editing the channel post.
+    Instead,
+}
+
+/// A channel's preferences for how sources are written, stored with
+/// [`GroupConfigKey::SourceTemplate`].
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct SourceTemplate {
+    pub style: SourceStyle,
+    /// Format for each source in a caption. `{site}` and `{url}` are replaced
+    /// with the source's site name and link.
+    pub format: String,
+    /// If artist names should be added to the caption as hashtags.
+    pub hashtags: bool,
+    /// If full source details should be posted in the discussion group.
+    pub companion: CompanionReplies,
+    /// Most sources to include from each site, up to [`MAX_SOURCES_PER_SITE`].
+    pub sources_per_site: usize,
+    /// If buttons should show how similar each source's image was.
+    pub show_distance: bool,
+    /// If sources should only be added for exact matches by a single artist.
+    pub strict: bool,
+}
+
+/// Most sources a channel may have added from each site.
+pub const MAX_SOURCES_PER_SITE: usize = 3;
+
+impl Default for SourceTemplate {
+    fn default() -> Self {
+        Self {
+            style: SourceStyle::Buttons,
+            format: "{url}".to_string(),
+            hashtags: false,
+            companion: CompanionReplies::Off,
+            sources_per_site: 1,
+            show_distance: false,
+            strict: false,
+        }
+    }
+}
+
+impl SourceTemplate {
+    /// Write a single source using the template's format.
+    pub fn format_source(&self, site: &str, url: &str) -> String {
+        self.format.replace("{site}", site).replace("{url}", url)
+    }
+}
+
 pub struct GroupConfig;
 
 pub enum GroupConfigKey {
     GroupAdd,
     GroupNoPreviews,
     HasDeletePermission,
+    HasEditPermission,
+    EditPermissionNotified,
+    NsfwSpoilers,
+    SourceTemplate,
+    SilentSourcing,
+    SourceDistance,
+    RepostWarnings,
+    BlockedSites,
+    ChannelNetwork,
 }
 
 impl GroupConfigKey {
@@ -154,6 +276,15 @@ impl GroupConfigKey {
             GroupConfigKey::GroupAdd => "group_add",
             GroupConfigKey::GroupNoPreviews => "group_no_previews",
             GroupConfigKey::HasDeletePermission => "has_delete_permission",
+            GroupConfigKey::HasEditPermission => "has_edit_permission",
+            GroupConfigKey::EditPermissionNotified => "edit_permission_notified",
+            GroupConfigKey::NsfwSpoilers => "nsfw_spoilers",
+            GroupConfigKey::SourceTemplate => "source_template",
+            GroupConfigKey::SilentSourcing => "silent_sourcing",
+            GroupConfigKey::SourceDistance => "source_distance",
+            GroupConfigKey::RepostWarnings => "repost_warnings",
+            GroupConfigKey::BlockedSites => "blocked_sites",
+            GroupConfigKey::ChannelNetwork => "channel_network",
         }
     }
 }
@@ -198,8 +329,54 @@ impl GroupConfig {
 
         Ok(())
     }
+
+    /// Get the largest distance between image hashes a chat accepts as a
+    /// source, stored with [`GroupConfigKey::SourceDistance`].
+    pub async fn source_distance(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        chat_id: i64,
+    ) -> anyhow::Result<u64> {
+        let distance: Option<u64> =
+            Self::get(conn, chat_id, GroupConfigKey::SourceDistance).await?;
+
+        Ok(distance
+            .unwrap_or(DEFAULT_SOURCE_DISTANCE)
+            .min(MAX_SOURCE_DISTANCE))
+    }
+
+    /// Get the sites a chat never wants used as a source, stored with
+    /// [`GroupConfigKey::BlockedSites`].
+    pub async fn blocked_sites(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        chat_id: i64,
+    ) -> anyhow::Result<Vec<Sites>> {
+        let sites: Option<Vec<Sites>> =
+            Self::get(conn, chat_id, GroupConfigKey::BlockedSites).await?;
+
+        Ok(sites.unwrap_or_default())
+    }
+
+    /// Get the network a channel shares sources with, stored with
+    /// [`GroupConfigKey::ChannelNetwork`]. Leaving a network stores null.
+    pub async fn channel_network(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        chat_id: i64,
+    ) -> anyhow::Result<Option<String>> {
+        let network: Option<Option<String>> =
+            Self::get(conn, chat_id, GroupConfigKey::ChannelNetwork).await?;
+
+        Ok(network.flatten())
+    }
 }
 
+/// Distance between image hashes accepted as a source unless a chat has
+/// chosen another.
+pub const DEFAULT_SOURCE_DISTANCE: u64 = 3;
+
+/// Largest distance between image hashes a chat may accept, past which
+/// matches are mostly unrelated images.
+pub const MAX_SOURCE_DISTANCE: u64 = 10;
+
 /// A Twitter account, as stored within the database.
 #[derive(sqlx::FromRow)]
 pub struct TwitterAccount {
@@ -384,6 +561,94 @@ impl FileCache {
 
         Ok(())
     }
+
+    /// Remove every cached hash, returning how many were removed.
+    pub async fn flush(conn: &sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<u64> {
+        let result = sqlx::query!("DELETE FROM file_id_cache")
+            .execute(conn)
+            .await
+            .context("unable to flush file_id_cache")?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+/// Type of media stored by Telegram.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MediaType {
+    Photo,
+    Animation,
+}
+
+impl MediaType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MediaType::Photo => "photo",
+            MediaType::Animation => "animation",
+        }
+    }
+
+    fn from_name(s: &str) -> Option<Self> {
+        match s {
+            "photo" => Some(MediaType::Photo),
+            "animation" => Some(MediaType::Animation),
+            _ => None,
+        }
+    }
+}
+
+/// A Telegram file ID for media previously sent by the bot.
+pub struct MediaFileId {
+    pub media_type: MediaType,
+    pub file_id: String,
+}
+
+impl MediaFileId {
+    /// Look up the file ID for a URL within a post.
+    pub async fn get(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        url_id: &str,
+        url: &str,
+    ) -> anyhow::Result<Option<Self>> {
+        let row = sqlx::query!(
+            "SELECT file_type, file_id FROM media_file_id WHERE url_id = $1 AND url = $2",
+            url_id,
+            url
+        )
+        .fetch_optional(conn)
+        .await
+        .context("unable to select media file id")?;
+
+        Ok(row.and_then(|row| {
+            MediaType::from_name(&row.file_type).map(|media_type| MediaFileId {
+                media_type,
+                file_id: row.file_id,
+            })
+        }))
+    }
+
+    /// Save the file ID for a URL within a post, replacing any previous ID.
+    pub async fn set(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        url_id: &str,
+        url: &str,
+        media_type: MediaType,
+        file_id: &str,
+    ) -> anyhow::Result<()> {
+        sqlx::query!(
+            "INSERT INTO media_file_id (url_id, url, file_type, file_id) VALUES ($1, $2, $3, $4)
+                ON CONFLICT (url_id, url) DO UPDATE SET file_type = EXCLUDED.file_type, file_id = EXCLUDED.file_id",
+            url_id,
+            url,
+            media_type.as_str(),
+            file_id
+        )
+        .execute(conn)
+        .await
+        .context("unable to insert media file id")?;
+
+        Ok(())
+    }
 }
 
 #[derive(sqlx::FromRow)]
@@ -574,7 +839,8 @@ impl CachedPost {
         let post = sqlx::query!(
             "SELECT id, post_url, thumb, cdn_url, width, height
             FROM cached_post
-            WHERE post_url = $1 AND thumb = $2",
+            WHERE post_url = $1 AND thumb = $2
+                AND (expires_at IS NULL OR expires_at > current_timestamp)",
             post_url,
             thumb
         )
@@ -616,6 +882,92 @@ impl CachedPost {
 
         Ok(row.id)
     }
+
+    /// Save a rehosted image that should be removed after a number of seconds,
+    /// replacing any existing entry for the post.
+    pub async fn save_expiring(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        post_url: &str,
+        cdn_url: &str,
+        dimensions: (u32, u32),
+        ttl: i64,
+    ) -> anyhow::Result<()> {
+        sqlx::query!(
+            "INSERT INTO cached_post (post_url, thumb, cdn_url, width, height, expires_at) VALUES
+                ($1, false, $2, $3, $4, current_timestamp + $5 * interval '1 second')
+            ON CONFLICT (post_url, thumb) DO UPDATE SET
+                cdn_url = EXCLUDED.cdn_url,
+                width = EXCLUDED.width,
+                height = EXCLUDED.height,
+                expires_at = EXCLUDED.expires_at",
+            post_url,
+            cdn_url,
+            dimensions.0 as i32,
+            dimensions.1 as i32,
+            ttl as f64
+        )
+        .execute(conn)
+        .await
+        .context("unable to save expiring cached post")?;
+
+        Ok(())
+    }
+
+    /// Get up to `limit` expired entries, as their IDs and CDN URLs.
+    pub async fn expired(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        limit: i64,
+    ) -> anyhow::Result<Vec<(i32, String)>> {
+        let expired = sqlx::query!(
+            "SELECT id, cdn_url FROM cached_post WHERE expires_at < current_timestamp LIMIT $1",
+            limit
+        )
+        .map(|row| (row.id, row.cdn_url))
+        .fetch_all(conn)
+        .await
+        .context("unable to get expired cached posts")?;
+
+        Ok(expired)
+    }
+
+    /// Remove an entry, once its image was removed.
+    pub async fn remove(conn: &sqlx::Pool<sqlx::Postgres>, id: i32) -> anyhow::Result<()> {
+        sqlx::query!("DELETE FROM cached_post WHERE id = $1", id)
+            .execute(conn)
+            .await
+            .context("unable to remove cached post")?;
+
+        Ok(())
+    }
+
+    /// Check if any entry that hasn't expired still uses a CDN URL.
+    pub async fn cdn_url_in_use(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        cdn_url: &str,
+    ) -> anyhow::Result<bool> {
+        let in_use = sqlx::query_scalar!(
+            r#"SELECT EXISTS(
+                SELECT 1 FROM cached_post
+                WHERE cdn_url = $1 AND (expires_at IS NULL OR expires_at >= current_timestamp)
+            ) AS "in_use!""#,
+            cdn_url
+        )
+        .fetch_one(conn)
+        .await
+        .context("unable to check if cdn url is in use")?;
+
+        Ok(in_use)
+    }
+
+    /// Remove every cached post, returning how many were removed.
+    pub async fn flush(conn: &sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<u64> {
+        let result = sqlx::query!("DELETE FROM cached_post")
+            .execute(conn)
+            .await
+            .context("unable to flush cached_post")?;
+
+        Ok(result.rows_affected())
+    }
 }
 
 pub struct Permissions;
@@ -787,3 +1139,1599 @@ impl Subscriptions {
         Ok(subscriptions)
     }
 }
+
+/// An image that was previously posted in a chat.
+pub struct PreviousPost {
+    pub message_id: i32,
+    pub posted_by: Option<String>,
+    /// Seconds since the image was posted.
+    pub age: i64,
+}
+
+/// Hashes of images posted in chats, for noticing when they are posted again.
+pub struct ChatImageHashes;
+
+impl ChatImageHashes {
+    pub async fn insert(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        chat_id: i64,
+        message_id: i32,
+        hash: i64,
+        posted_by: Option<&str>,
+    ) -> anyhow::Result<()> {
+        sqlx::query!(
+            "INSERT INTO chat_image_hash (chat_id, message_id, hash, posted_by)
+                VALUES (lookup_chat_by_telegram_id($1), $2, $3, $4)",
+            chat_id,
+            message_id,
+            hash,
+            posted_by
+        )
+        .execute(conn)
+        .await
+        .context("unable to insert chat image hash")?;
+
+        Ok(())
+    }
+
+    /// Find the first time an image similar to a hash was posted in a chat.
+    pub async fn first_similar(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        chat_id: i64,
+        hash: i64,
+    ) -> anyhow::Result<Option<PreviousPost>> {
+        let post = sqlx::query_as!(
+            PreviousPost,
+            r#"SELECT message_id, posted_by, extract(epoch FROM current_timestamp - posted_at)::BIGINT AS "age!"
+            FROM chat_image_hash
+            WHERE chat_id = lookup_chat_by_telegram_id($1) AND hash <@ ($2, 3)
+            ORDER BY posted_at
+            LIMIT 1"#,
+            chat_id,
+            hash
+        )
+        .fetch_optional(conn)
+        .await
+        .context("unable to search chat image hashes")?;
+
+        Ok(post)
+    }
+}
+
+/// Images users want to know about when they are posted again.
+///
+/// Unlike [`Subscriptions`], these are kept after a match is found.
+pub struct RepostWatches;
+
+impl RepostWatches {
+    pub async fn add(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        user_id: i64,
+        hash: i64,
+        message_id: Option<i32>,
+        photo_id: Option<&str>,
+    ) -> anyhow::Result<()> {
+        sqlx::query!(
+            "INSERT INTO repost_watch (account_id, hash, message_id, photo_id)
+                VALUES (lookup_account_by_telegram_id($1), $2, $3, $4) ON CONFLICT DO NOTHING",
+            user_id,
+            hash,
+            message_id,
+            photo_id,
+        )
+        .execute(conn)
+        .await
+        .context("unable to insert repost watch")?;
+
+        Ok(())
+    }
+
+    pub async fn remove(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        user_id: i64,
+        hash: i64,
+    ) -> anyhow::Result<()> {
+        sqlx::query!(
+            "DELETE FROM repost_watch
+            WHERE account_id = lookup_account_by_telegram_id($1) AND hash = $2",
+            user_id,
+            hash
+        )
+        .execute(conn)
+        .await
+        .context("unable to delete repost watch")?;
+
+        Ok(())
+    }
+
+    /// Find everyone watching for an image similar to a hash.
+    pub async fn search(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        hash: i64,
+    ) -> anyhow::Result<Vec<Subscription>> {
+        let watches = sqlx::query!(
+            "SELECT account.telegram_id user_id, hash, message_id, photo_id
+            FROM repost_watch
+            JOIN account ON account.id = repost_watch.account_id
+            WHERE hash <@ ($1, 3)",
+            hash
+        )
+        .map(|row| Subscription {
+            user_id: row.user_id,
+            hash: row.hash,
+            message_id: row.message_id,
+            photo_id: row.photo_id,
+        })
+        .fetch_all(conn)
+        .await
+        .context("unable to search repost watches")?;
+
+        Ok(watches)
+    }
+}
+
+pub struct InlineStats;
+
+impl InlineStats {
+    /// Record that a user sent an inline result from a site.
+    pub async fn record(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        user_id: i64,
+        site: &str,
+    ) -> anyhow::Result<()> {
+        sqlx::query!(
+            "INSERT INTO inline_chosen (account_id, site) VALUES (lookup_account_by_telegram_id($1), $2)",
+            user_id,
+            site
+        )
+        .execute(conn)
+        .await
+        .context("unable to insert chosen inline result")?;
+
+        Ok(())
+    }
+
+    /// Count how many results were sent from each site within the past number
+    /// of days, ordered by most used.
+    pub async fn site_counts(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        days: i32,
+    ) -> anyhow::Result<Vec<(String, i64)>> {
+        let rows = sqlx::query!(
+            "SELECT site, count(*) count
+            FROM inline_chosen
+            WHERE created_at > current_timestamp - make_interval(days => $1)
+            GROUP BY site
+            ORDER BY count DESC",
+            days
+        )
+        .fetch_all(conn)
+        .await
+        .context("unable to select inline site counts")?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| (row.site, row.count.unwrap_or_default()))
+            .collect())
+    }
+}
+
+/// A background job that failed too many times and was removed from the queue.
+pub struct FailedJob {
+    pub id: i32,
+    pub job_id: String,
+    pub kind: String,
+    pub queue: String,
+    pub args: serde_json::Value,
+}
+
+pub struct FailedJobs;
+
+impl FailedJobs {
+    /// Save a job that could not be completed.
+    pub async fn insert(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        job_id: &str,
+        kind: &str,
+        queue: &str,
+        args: serde_json::Value,
+        error: &str,
+    ) -> anyhow::Result<()> {
+        sqlx::query!(
+            "INSERT INTO failed_jobs (job_id, kind, queue, args, error) VALUES ($1, $2, $3, $4, $5)",
+            job_id,
+            kind,
+            queue,
+            args,
+            error
+        )
+        .execute(conn)
+        .await
+        .context("unable to insert failed job")?;
+
+        Ok(())
+    }
+
+    /// Get every failed job that has not yet been retried.
+    pub async fn pending(conn: &sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<Vec<FailedJob>> {
+        let jobs = sqlx::query_as!(
+            FailedJob,
+            "SELECT id, job_id, kind, queue, args
+            FROM failed_jobs
+            WHERE retried_at IS NULL
+            ORDER BY id"
+        )
+        .fetch_all(conn)
+        .await
+        .context("unable to get pending failed jobs")?;
+
+        Ok(jobs)
+    }
+
+    /// Mark a failed job as retried, after it was enqueued again.
+    pub async fn mark_retried(conn: &sqlx::Pool<sqlx::Postgres>, id: i32) -> anyhow::Result<()> {
+        sqlx::query!(
+            "UPDATE failed_jobs SET retried_at = current_timestamp WHERE id = $1",
+            id
+        )
+        .execute(conn)
+        .await
+        .context("unable to mark failed job as retried")?;
+
+        Ok(())
+    }
+
+    /// Count failed jobs that have not yet been retried.
+    pub async fn pending_count(conn: &sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<i64> {
+        let count = sqlx::query_scalar!(
+            r#"SELECT count(*) AS "count!" FROM failed_jobs WHERE retried_at IS NULL"#
+        )
+        .fetch_one(conn)
+        .await
+        .context("unable to count failed jobs")?;
+
+        Ok(count)
+    }
+}
+
+/// A job reserved from the Postgres job queue.
+pub struct QueuedJob {
+    pub id: i64,
+    pub job: serde_json::Value,
+    pub attempts: i32,
+}
+
+/// A job queue stored in Postgres, for deployments without Faktory.
+///
+/// Inserting a job notifies the `job_queue` channel with the job's queue.
+pub struct QueuedJobs;
+
+impl QueuedJobs {
+    /// Add a job to a queue, optionally not running it until a timestamp.
+    pub async fn push(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        queue: &str,
+        job: serde_json::Value,
+        run_at: Option<i64>,
+    ) -> anyhow::Result<()> {
+        let run_at = run_at.map(|run_at| run_at as f64);
+
+        sqlx::query!(
+            "INSERT INTO job_queue (queue, job, run_at) VALUES ($1, $2, coalesce(to_timestamp($3), current_timestamp))",
+            queue,
+            job,
+            run_at
+        )
+        .execute(conn)
+        .await
+        .context("unable to push job")?;
+
+        Ok(())
+    }
+
+    /// Reserve the next available job from the first queue that has one.
+    ///
+    /// Jobs are locked for a number of seconds, after which they may be
+    /// reserved again if they were not completed.
+    pub async fn reserve(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        queues: &[String],
+        reserve_for: f64,
+    ) -> anyhow::Result<Option<QueuedJob>> {
+        let job = sqlx::query_as!(
+            QueuedJob,
+            "UPDATE job_queue
+            SET locked_until = current_timestamp + make_interval(secs => $2), attempts = attempts + 1
+            WHERE id = (
+                SELECT id FROM job_queue
+                WHERE queue = ANY($1)
+                    AND run_at <= current_timestamp
+                    AND (locked_until IS NULL OR locked_until < current_timestamp)
+                ORDER BY array_position($1, queue), run_at
+                FOR UPDATE SKIP LOCKED
+                LIMIT 1
+            )
+            RETURNING id, job, attempts",
+            queues,
+            reserve_for
+        )
+        .fetch_optional(conn)
+        .await
+        .context("unable to reserve job")?;
+
+        Ok(job)
+    }
+
+    /// Remove a job that has finished.
+    pub async fn complete(conn: &sqlx::Pool<sqlx::Postgres>, id: i64) -> anyhow::Result<()> {
+        sqlx::query!("DELETE FROM job_queue WHERE id = $1", id)
+            .execute(conn)
+            .await
+            .context("unable to complete job")?;
+
+        Ok(())
+    }
+
+    /// Release a failed job so it can run again after a delay, in seconds.
+    pub async fn retry(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        id: i64,
+        delay: f64,
+    ) -> anyhow::Result<()> {
+        sqlx::query!(
+            "UPDATE job_queue
+            SET locked_until = NULL, run_at = current_timestamp + make_interval(secs => $2)
+            WHERE id = $1",
+            id,
+            delay
+        )
+        .execute(conn)
+        .await
+        .context("unable to retry job")?;
+
+        Ok(())
+    }
+
+    /// Count the jobs waiting in each queue, including scheduled jobs.
+    pub async fn depths(conn: &sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<Vec<(String, i64)>> {
+        let depths = sqlx::query!(
+            r#"SELECT queue, count(*) AS "count!" FROM job_queue GROUP BY queue ORDER BY queue"#
+        )
+        .fetch_all(conn)
+        .await
+        .context("unable to count queued jobs")?
+        .into_iter()
+        .map(|row| (row.queue, row.count))
+        .collect();
+
+        Ok(depths)
+    }
+}
+
+/// A page being watched for new posts on behalf of a chat.
+pub struct ArtistWatch {
+    pub id: i32,
+    pub chat_id: i64,
+    pub site: String,
+    pub url: String,
+    pub last_seen: Option<String>,
+}
+
+pub struct ArtistWatches;
+
+impl ArtistWatches {
+    /// Start watching a page for a chat, returning if it was not already
+    /// being watched.
+    pub async fn add(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        chat_id: i64,
+        site: &str,
+        url: &str,
+        last_seen: Option<&str>,
+    ) -> anyhow::Result<bool> {
+        let id = sqlx::query_scalar!(
+            "INSERT INTO artist_watch (chat_id, site, url, last_seen)
+                VALUES (lookup_chat_by_telegram_id($1), $2, $3, $4)
+                ON CONFLICT DO NOTHING
+                RETURNING id",
+            chat_id,
+            site,
+            url,
+            last_seen
+        )
+        .fetch_optional(conn)
+        .await
+        .context("unable to insert artist watch")?;
+
+        Ok(id.is_some())
+    }
+
+    /// Count how many pages a chat is watching.
+    pub async fn count(conn: &sqlx::Pool<sqlx::Postgres>, chat_id: i64) -> anyhow::Result<i64> {
+        let count = sqlx::query_scalar!(
+            r#"SELECT count(*) AS "count!" FROM artist_watch WHERE chat_id = lookup_chat_by_telegram_id($1)"#,
+            chat_id
+        )
+        .fetch_one(conn)
+        .await
+        .context("unable to count artist watches")?;
+
+        Ok(count)
+    }
+
+    /// Get every page a chat is watching, oldest first.
+    pub async fn list(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        chat_id: i64,
+    ) -> anyhow::Result<Vec<ArtistWatch>> {
+        let watches = sqlx::query!(
+            "SELECT id, site, url, last_seen
+            FROM artist_watch
+            WHERE chat_id = lookup_chat_by_telegram_id($1)
+            ORDER BY id",
+            chat_id
+        )
+        .map(|row| ArtistWatch {
+            id: row.id,
+            chat_id,
+            site: row.site,
+            url: row.url,
+            last_seen: row.last_seen,
+        })
+        .fetch_all(conn)
+        .await
+        .context("unable to select artist watches")?;
+
+        Ok(watches)
+    }
+
+    /// Get a watch by its ID.
+    pub async fn get(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        id: i32,
+    ) -> anyhow::Result<Option<ArtistWatch>> {
+        let watch = sqlx::query_as!(
+            ArtistWatch,
+            r#"SELECT id, site, url, last_seen, (
+                SELECT chat_telegram.telegram_id
+                FROM chat_telegram
+                WHERE chat_telegram.chat_id = artist_watch.chat_id
+                ORDER BY abs(chat_telegram.telegram_id) DESC
+                LIMIT 1
+            ) AS "chat_id!"
+            FROM artist_watch
+            WHERE id = $1"#,
+            id
+        )
+        .fetch_optional(conn)
+        .await
+        .context("unable to select artist watch")?;
+
+        Ok(watch)
+    }
+
+    /// Stop watching a page, returning if the chat was watching it.
+    pub async fn remove(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        chat_id: i64,
+        id: i32,
+    ) -> anyhow::Result<bool> {
+        let result = sqlx::query!(
+            "DELETE FROM artist_watch WHERE id = $1 AND chat_id = lookup_chat_by_telegram_id($2)",
+            id,
+            chat_id
+        )
+        .execute(conn)
+        .await
+        .context("unable to delete artist watch")?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Remove a watch that can no longer be delivered.
+    pub async fn delete(conn: &sqlx::Pool<sqlx::Postgres>, id: i32) -> anyhow::Result<()> {
+        sqlx::query!("DELETE FROM artist_watch WHERE id = $1", id)
+            .execute(conn)
+            .await
+            .context("unable to delete artist watch")?;
+
+        Ok(())
+    }
+
+    /// Take watches that are due to be checked, scheduling their next check
+    /// a number of seconds from now.
+    pub async fn take_due(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        interval: f64,
+        limit: i64,
+    ) -> anyhow::Result<Vec<i32>> {
+        let ids = sqlx::query_scalar!(
+            "UPDATE artist_watch
+            SET next_check_at = current_timestamp + make_interval(secs => $1)
+            WHERE id IN (
+                SELECT id FROM artist_watch
+                WHERE next_check_at <= current_timestamp
+                ORDER BY next_check_at
+                FOR UPDATE SKIP LOCKED
+                LIMIT $2
+            )
+            RETURNING id",
+            interval,
+            limit
+        )
+        .fetch_all(conn)
+        .await
+        .context("unable to take due artist watches")?;
+
+        Ok(ids)
+    }
+
+    /// Record the newest post seen on a watched page.
+    pub async fn set_last_seen(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        id: i32,
+        last_seen: &str,
+    ) -> anyhow::Result<()> {
+        sqlx::query!(
+            "UPDATE artist_watch SET last_seen = $2 WHERE id = $1",
+            id,
+            last_seen
+        )
+        .execute(conn)
+        .await
+        .context("unable to update artist watch")?;
+
+        Ok(())
+    }
+}
+
+/// Sources previously found by FuzzySearch, kept so images can still be
+/// sourced while it is unavailable.
+pub struct KnownSources;
+
+impl KnownSources {
+    /// Remember a source for a hash, replacing anything already known about
+    /// the same post.
+    pub async fn record(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        site: &str,
+        site_id: i64,
+        hash: i64,
+        file: serde_json::Value,
+    ) -> anyhow::Result<()> {
+        sqlx::query!(
+            "INSERT INTO known_source (site, site_id, hash, file) VALUES ($1, $2, $3, $4)
+                ON CONFLICT (site, site_id) DO UPDATE
+                SET hash = EXCLUDED.hash, file = EXCLUDED.file, last_seen = current_timestamp",
+            site,
+            site_id,
+            hash,
+            file
+        )
+        .execute(conn)
+        .await
+        .context("unable to record known source")?;
+
+        Ok(())
+    }
+
+    /// Find known sources with hashes within a distance of 10.
+    pub async fn similar(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        hash: i64,
+    ) -> anyhow::Result<Vec<serde_json::Value>> {
+        let files =
+            sqlx::query_scalar!("SELECT file FROM known_source WHERE hash <@ ($1, 10)", hash)
+                .fetch_all(conn)
+                .await
+                .context("unable to search known sources")?;
+
+        Ok(files)
+    }
+}
+
+/// Cookies and user agent a site loader needs to keep using, like those from
+/// solving a challenge page.
+#[derive(Clone, Debug, Default)]
+pub struct SiteSession {
+    pub cookies: HashMap<String, String>,
+    pub user_agent: Option<String>,
+}
+
+pub struct SiteSessions;
+
+impl SiteSessions {
+    pub async fn get(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        site: &str,
+    ) -> anyhow::Result<Option<SiteSession>> {
+        let session = sqlx::query!(
+            "SELECT cookies, user_agent FROM site_session WHERE site = $1",
+            site
+        )
+        .fetch_optional(conn)
+        .await
+        .context("unable to get site session")?;
+
+        let session = match session {
+            Some(session) => session,
+            None => return Ok(None),
+        };
+
+        Ok(Some(SiteSession {
+            cookies: serde_json::from_value(session.cookies)
+                .context("site session had invalid cookies")?,
+            user_agent: session.user_agent,
+        }))
+    }
+
+    pub async fn save(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        site: &str,
+        session: &SiteSession,
+    ) -> anyhow::Result<()> {
+        sqlx::query!(
+            "INSERT INTO site_session (site, cookies, user_agent) VALUES ($1, $2, $3)
+                ON CONFLICT (site) DO UPDATE
+                SET cookies = EXCLUDED.cookies, user_agent = EXCLUDED.user_agent, updated_at = current_timestamp",
+            site,
+            serde_json::to_value(&session.cookies)?,
+            session.user_agent
+        )
+        .execute(conn)
+        .await
+        .context("unable to save site session")?;
+
+        Ok(())
+    }
+}
+
+/// Something the bot changed in a chat, kept so chat owners can see what
+/// the bot did.
+pub struct Action {
+    pub message_id: i32,
+    /// The message the bot sent, for replies.
+    pub reply_message_id: Option<i32>,
+    pub action: String,
+    /// Links to the sources that were added.
+    pub sources: Vec<String>,
+    pub job_id: Option<String>,
+    /// Seconds since the action happened.
+    pub age: i64,
+}
+
+/// What kind of change the bot made in a chat.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ActionKind {
+    /// Sources were added to a channel post's caption or keyboard.
+    ChannelEdit,
+    /// The bot replied to a message with sources.
+    Reply,
+}
+
+impl ActionKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::ChannelEdit => "channel_edit",
+            Self::Reply => "reply",
+        }
+    }
+
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "channel_edit" => Some(Self::ChannelEdit),
+            "reply" => Some(Self::Reply),
+            _ => None,
+        }
+    }
+}
+
+/// A change the bot is about to record.
+pub struct NewAction<'a> {
+    pub chat_id: i64,
+    pub message_id: i32,
+    /// The message the bot sent, for replies.
+    pub reply_message_id: Option<i32>,
+    pub kind: ActionKind,
+    pub sources: &'a [String],
+    pub job_id: Option<&'a str>,
+    /// The caption before it was edited, so the edit can be undone.
+    pub previous_caption: Option<&'a str>,
+    pub previous_caption_entities: Option<&'a [tgbotapi::MessageEntity]>,
+}
+
+/// The information needed to revert an action.
+pub struct UndoableAction {
+    pub id: i64,
+    pub kind: ActionKind,
+    pub message_id: i32,
+    pub reply_message_id: Option<i32>,
+    pub previous_caption: Option<String>,
+    pub previous_caption_entities: Option<Vec<tgbotapi::MessageEntity>>,
+}
+
+pub struct Actions;
+
+impl Actions {
+    /// Record a change the bot made to a message.
+    pub async fn record(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        action: &NewAction<'_>,
+    ) -> anyhow::Result<()> {
+        let previous_caption_entities = action
+            .previous_caption_entities
+            .map(serde_json::to_value)
+            .transpose()?;
+
+        sqlx::query!(
+            "INSERT INTO actions (chat_id, message_id, reply_message_id, action, sources, job_id, previous_caption, previous_caption_entities)
+                VALUES (lookup_chat_by_telegram_id($1), $2, $3, $4, $5, $6, $7, $8)",
+            action.chat_id,
+            action.message_id,
+            action.reply_message_id,
+            action.kind.as_str(),
+            action.sources,
+            action.job_id,
+            action.previous_caption,
+            previous_caption_entities
+        )
+        .execute(conn)
+        .await
+        .context("unable to insert action")?;
+
+        Ok(())
+    }
+
+    /// Get the most recent action in a chat that hasn't been undone.
+    pub async fn last_undoable(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        chat_id: i64,
+    ) -> anyhow::Result<Option<UndoableAction>> {
+        let action = sqlx::query!(
+            "SELECT id, action, message_id, reply_message_id, previous_caption, previous_caption_entities
+            FROM actions
+            WHERE chat_id = lookup_chat_by_telegram_id($1) AND undone_at IS NULL
+            ORDER BY created_at DESC
+            LIMIT 1",
+            chat_id
+        )
+        .fetch_optional(conn)
+        .await
+        .context("unable to select last action")?;
+
+        let action = match action {
+            Some(action) => action,
+            None => return Ok(None),
+        };
+
+        let kind = match ActionKind::parse(&action.action) {
+            Some(kind) => kind,
+            None => return Ok(None),
+        };
+
+        Ok(Some(UndoableAction {
+            id: action.id,
+            kind,
+            message_id: action.message_id,
+            reply_message_id: action.reply_message_id,
+            previous_caption: action.previous_caption,
+            previous_caption_entities: action
+                .previous_caption_entities
+                .map(serde_json::from_value)
+                .transpose()
+                .context("action had invalid caption entities")?,
+        }))
+    }
+
+    /// Get the sources the bot included in a reply.
+    pub async fn reply_sources(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        chat_id: i64,
+        reply_message_id: i32,
+    ) -> anyhow::Result<Option<Vec<String>>> {
+        let sources = sqlx::query_scalar!(
+            "SELECT sources
+            FROM actions
+            WHERE chat_id = lookup_chat_by_telegram_id($1) AND reply_message_id = $2",
+            chat_id,
+            reply_message_id
+        )
+        .fetch_optional(conn)
+        .await
+        .context("unable to select reply sources")?;
+
+        Ok(sources)
+    }
+
+    /// Mark an action as undone so it isn't reverted again.
+    pub async fn mark_undone(conn: &sqlx::Pool<sqlx::Postgres>, id: i64) -> anyhow::Result<()> {
+        sqlx::query!(
+            "UPDATE actions SET undone_at = current_timestamp WHERE id = $1",
+            id
+        )
+        .execute(conn)
+        .await
+        .context("unable to mark action undone")?;
+
+        Ok(())
+    }
+
+    /// Get the most recent actions in a chat, newest first.
+    pub async fn recent(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        chat_id: i64,
+        limit: i64,
+    ) -> anyhow::Result<Vec<Action>> {
+        let actions = sqlx::query_as!(
+            Action,
+            r#"SELECT message_id, reply_message_id, action, sources, job_id, extract(epoch FROM current_timestamp - created_at)::BIGINT AS "age!"
+            FROM actions
+            WHERE chat_id = lookup_chat_by_telegram_id($1)
+            ORDER BY created_at DESC
+            LIMIT $2"#,
+            chat_id,
+            limit
+        )
+        .fetch_all(conn)
+        .await
+        .context("unable to select recent actions")?;
+
+        Ok(actions)
+    }
+
+    /// Remove actions older than a number of days, returning how many were
+    /// removed.
+    pub async fn remove_older_than(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        days: i32,
+    ) -> anyhow::Result<u64> {
+        let result = sqlx::query!(
+            "DELETE FROM actions WHERE created_at < current_timestamp - make_interval(days => $1)",
+            days
+        )
+        .execute(conn)
+        .await
+        .context("unable to remove old actions")?;
+
+        Ok(result.rows_affected())
+    }
+}
+
+/// A source users reported as not matching an image.
+#[derive(serde::Serialize)]
+pub struct WrongSource {
+    pub hash: i64,
+    pub url: String,
+    /// Number of different people who reported the source for the hash.
+    pub reports: i32,
+    /// When the source was first reported, as a Unix timestamp.
+    pub reported_at: i64,
+}
+
+/// Number of different people who must report a source as wrong before it is
+/// hidden in every chat, instead of only in the chats it was reported in.
+pub const WRONG_SOURCE_REPORTERS: i32 = 3;
+
+/// Sources that were reported as incorrect for an image, which are no longer
+/// shown for that image.
+pub struct WrongSources;
+
+impl WrongSources {
+    /// Report a source as incorrect for a hash in a chat.
+    ///
+    /// Reports from the same person in the same chat are only counted once.
+    pub async fn report(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        user_id: i64,
+        chat_id: i64,
+        hash: i64,
+        url: &str,
+    ) -> anyhow::Result<()> {
+        let mut tx = conn.begin().await?;
+
+        let id = sqlx::query_scalar!(
+            "INSERT INTO wrong_source (hash, url, account_id) VALUES ($1, $2, lookup_account_by_telegram_id($3))
+                ON CONFLICT (hash, url) DO UPDATE SET hash = EXCLUDED.hash
+                RETURNING id",
+            hash,
+            url,
+            user_id
+        )
+        .fetch_one(&mut tx)
+        .await
+        .context("unable to insert wrong source")?;
+
+        sqlx::query!(
+            "INSERT INTO wrong_source_report (wrong_source_id, account_id, chat_id)
+                VALUES ($1, lookup_account_by_telegram_id($2), lookup_chat_by_telegram_id($3))
+                ON CONFLICT DO NOTHING",
+            id,
+            user_id,
+            chat_id
+        )
+        .execute(&mut tx)
+        .await
+        .context("unable to insert wrong source report")?;
+
+        sqlx::query!(
+            "UPDATE wrong_source SET reports = (
+                SELECT count(DISTINCT account_id) FROM wrong_source_report WHERE wrong_source_id = $1
+            )::INTEGER WHERE id = $1",
+            id
+        )
+        .execute(&mut tx)
+        .await
+        .context("unable to update wrong source reports")?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    /// Get the URLs of sources that shouldn't be shown for a hash, either
+    /// because they were reported in the chat or by enough people.
+    pub async fn for_hash(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        hash: i64,
+        chat_id: Option<i64>,
+    ) -> anyhow::Result<Vec<String>> {
+        let urls = sqlx::query_scalar!(
+            "SELECT url FROM wrong_source
+            WHERE hash = $1 AND (
+                reports >= $2 OR EXISTS (
+                    SELECT 1 FROM wrong_source_report
+                    JOIN chat_telegram ON chat_telegram.chat_id = wrong_source_report.chat_id
+                    WHERE wrong_source_report.wrong_source_id = wrong_source.id
+                        AND chat_telegram.telegram_id = $3
+                )
+            )",
+            hash,
+            WRONG_SOURCE_REPORTERS,
+            chat_id
+        )
+        .fetch_all(conn)
+        .await
+        .context("unable to select wrong sources")?;
+
+        Ok(urls)
+    }
+
+    /// Get every reported source, for sharing with FuzzySearch.
+    pub async fn all(conn: &sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<Vec<WrongSource>> {
+        let sources = sqlx::query_as!(
+            WrongSource,
+            r#"SELECT hash, url, reports, extract(epoch FROM created_at)::BIGINT AS "reported_at!"
+            FROM wrong_source
+            ORDER BY created_at"#
+        )
+        .fetch_all(conn)
+        .await
+        .context("unable to select all wrong sources")?;
+
+        Ok(sources)
+    }
+}
+
+/// Artists people chose for images in chats.
+pub struct ArtistChoice;
+
+impl ArtistChoice {
+    /// Remember the artist someone chose for an image in a chat, replacing
+    /// any previous choice.
+    pub async fn choose(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        chat_id: i64,
+        hash: i64,
+        user_id: i64,
+        artist: &str,
+    ) -> anyhow::Result<()> {
+        sqlx::query!(
+            "INSERT INTO artist_choice (chat_id, hash, artist, account_id)
+                VALUES (lookup_chat_by_telegram_id($1), $2, $3, lookup_account_by_telegram_id($4))
+                ON CONFLICT (chat_id, hash) DO UPDATE
+                SET artist = EXCLUDED.artist, account_id = EXCLUDED.account_id, created_at = current_timestamp",
+            chat_id,
+            hash,
+            artist,
+            user_id
+        )
+        .execute(conn)
+        .await
+        .context("unable to insert artist choice")?;
+
+        Ok(())
+    }
+
+    /// Get the artist chosen for an image in a chat, if one was chosen.
+    pub async fn chosen(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        chat_id: i64,
+        hash: i64,
+    ) -> anyhow::Result<Option<String>> {
+        let artist = sqlx::query_scalar!(
+            "SELECT artist FROM artist_choice
+            JOIN chat_telegram ON chat_telegram.chat_id = artist_choice.chat_id
+            WHERE chat_telegram.telegram_id = $1 AND hash = $2",
+            chat_id,
+            hash
+        )
+        .fetch_optional(conn)
+        .await
+        .context("unable to select artist choice")?;
+
+        Ok(artist)
+    }
+}
+
+/// Users whose updates are ignored.
+pub struct BannedUsers;
+
+impl BannedUsers {
+    /// Ban a user, for a number of seconds or permanently if no TTL is given.
+    ///
+    /// Banning a user who is already banned replaces the existing ban.
+    pub async fn ban(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        user_id: i64,
+        reason: Option<&str>,
+        banned_by: Option<i64>,
+        ttl: Option<i64>,
+    ) -> anyhow::Result<()> {
+        sqlx::query!(
+            "INSERT INTO banned_user (account_id, reason, banned_by, expires_at) VALUES
+                (lookup_account_by_telegram_id($1), $2, $3, current_timestamp + $4 * interval '1 second')
+            ON CONFLICT (account_id) DO UPDATE SET
+                reason = EXCLUDED.reason,
+                banned_by = EXCLUDED.banned_by,
+                expires_at = EXCLUDED.expires_at,
+                created_at = current_timestamp",
+            user_id,
+            reason,
+            banned_by,
+            ttl.map(|ttl| ttl as f64)
+        )
+        .execute(conn)
+        .await
+        .context("unable to insert banned user")?;
+
+        Ok(())
+    }
+
+    /// Remove a user's ban, returning if they were banned.
+    pub async fn unban(conn: &sqlx::Pool<sqlx::Postgres>, user_id: i64) -> anyhow::Result<bool> {
+        let result = sqlx::query!(
+            "DELETE FROM banned_user
+            WHERE account_id = (SELECT id FROM account WHERE telegram_id = $1)",
+            user_id
+        )
+        .execute(conn)
+        .await
+        .context("unable to delete banned user")?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Get the Telegram IDs of every user whose ban hasn't expired.
+    pub async fn active(conn: &sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<Vec<i64>> {
+        let user_ids = sqlx::query_scalar!(
+            "SELECT account.telegram_id
+            FROM banned_user
+            JOIN account ON account.id = banned_user.account_id
+            WHERE banned_user.expires_at IS NULL OR banned_user.expires_at > current_timestamp"
+        )
+        .fetch_all(conn)
+        .await
+        .context("unable to select banned users")?;
+
+        Ok(user_ids)
+    }
+}
+
+/// Chats whose updates are ignored.
+pub struct BannedChats;
+
+impl BannedChats {
+    /// Ban a chat, for a number of seconds or permanently if no TTL is given.
+    ///
+    /// Banning a chat that is already banned replaces the existing ban.
+    pub async fn ban(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        chat_id: i64,
+        reason: Option<&str>,
+        banned_by: Option<i64>,
+        ttl: Option<i64>,
+    ) -> anyhow::Result<()> {
+        sqlx::query!(
+            "INSERT INTO banned_chat (chat_id, reason, banned_by, expires_at) VALUES
+                (lookup_chat_by_telegram_id($1), $2, $3, current_timestamp + $4 * interval '1 second')
+            ON CONFLICT (chat_id) DO UPDATE SET
+                reason = EXCLUDED.reason,
+                banned_by = EXCLUDED.banned_by,
+                expires_at = EXCLUDED.expires_at,
+                created_at = current_timestamp",
+            chat_id,
+            reason,
+            banned_by,
+            ttl.map(|ttl| ttl as f64)
+        )
+        .execute(conn)
+        .await
+        .context("unable to insert banned chat")?;
+
+        Ok(())
+    }
+
+    /// Remove a chat's ban, returning if it was banned.
+    pub async fn unban(conn: &sqlx::Pool<sqlx::Postgres>, chat_id: i64) -> anyhow::Result<bool> {
+        let result = sqlx::query!(
+            "DELETE FROM banned_chat
+            WHERE chat_id = (SELECT chat_id FROM chat_telegram WHERE telegram_id = $1)",
+            chat_id
+        )
+        .execute(conn)
+        .await
+        .context("unable to delete banned chat")?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Get the Telegram IDs of every chat whose ban hasn't expired.
+    ///
+    /// Chats that were migrated have each of their IDs included.
+    pub async fn active(conn: &sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<Vec<i64>> {
+        let chat_ids = sqlx::query_scalar!(
+            "SELECT chat_telegram.telegram_id
+            FROM banned_chat
+            JOIN chat_telegram ON chat_telegram.chat_id = banned_chat.chat_id
+            WHERE banned_chat.expires_at IS NULL OR banned_chat.expires_at > current_timestamp"
+        )
+        .fetch_all(conn)
+        .await
+        .context("unable to select banned chats")?;
+
+        Ok(chat_ids)
+    }
+}
+
+/// A chat that opted into announcements.
+pub struct AnnouncementChat {
+    /// Internal ID of the chat, used for paging through chats.
+    pub id: i32,
+    /// Current Telegram ID of the chat.
+    pub telegram_id: i64,
+}
+
+/// Chats that want to receive announcements about the bot.
+pub struct Announcements;
+
+impl Announcements {
+    /// Check if a chat receives announcements.
+    pub async fn is_subscribed(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        chat_id: i64,
+    ) -> anyhow::Result<bool> {
+        let subscribed = sqlx::query_scalar!(
+            r#"SELECT EXISTS(
+                SELECT 1 FROM announcement_chat
+                JOIN chat_telegram ON chat_telegram.chat_id = announcement_chat.chat_id
+                WHERE chat_telegram.telegram_id = $1
+            ) AS "subscribed!""#,
+            chat_id
+        )
+        .fetch_one(conn)
+        .await
+        .context("unable to check announcement subscription")?;
+
+        Ok(subscribed)
+    }
+
+    /// Start sending announcements to a chat.
+    pub async fn subscribe(conn: &sqlx::Pool<sqlx::Postgres>, chat_id: i64) -> anyhow::Result<()> {
+        sqlx::query!(
+            "INSERT INTO announcement_chat (chat_id) VALUES (lookup_chat_by_telegram_id($1))
+                ON CONFLICT DO NOTHING",
+            chat_id
+        )
+        .execute(conn)
+        .await
+        .context("unable to insert announcement chat")?;
+
+        Ok(())
+    }
+
+    /// Stop sending announcements to a chat.
+    pub async fn unsubscribe(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        chat_id: i64,
+    ) -> anyhow::Result<()> {
+        sqlx::query!(
+            "DELETE FROM announcement_chat
+            WHERE chat_id = (SELECT chat_id FROM chat_telegram WHERE telegram_id = $1)",
+            chat_id
+        )
+        .execute(conn)
+        .await
+        .context("unable to delete announcement chat")?;
+
+        Ok(())
+    }
+
+    /// Count how many chats receive announcements.
+    pub async fn count(conn: &sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<i64> {
+        let count = sqlx::query_scalar!(r#"SELECT count(*) AS "count!" FROM announcement_chat"#)
+            .fetch_one(conn)
+            .await
+            .context("unable to count announcement chats")?;
+
+        Ok(count)
+    }
+
+    /// Get the next chats that receive announcements, after an internal chat
+    /// ID.
+    pub async fn page(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        after_id: i32,
+        limit: i64,
+    ) -> anyhow::Result<Vec<AnnouncementChat>> {
+        let chats = sqlx::query_as!(
+            AnnouncementChat,
+            r#"SELECT announcement_chat.chat_id AS id, (
+                SELECT chat_telegram.telegram_id FROM chat_telegram
+                WHERE chat_telegram.chat_id = announcement_chat.chat_id
+                ORDER BY abs(chat_telegram.telegram_id) DESC
+                LIMIT 1
+            ) AS "telegram_id!"
+            FROM announcement_chat
+            WHERE announcement_chat.chat_id > $1
+            ORDER BY announcement_chat.chat_id
+            LIMIT $2"#,
+            after_id,
+            limit
+        )
+        .fetch_all(conn)
+        .await
+        .context("unable to select announcement chats")?;
+
+        Ok(chats)
+    }
+
+    /// Record that an announcement was delivered to a chat, clearing its
+    /// failures.
+    pub async fn delivered(conn: &sqlx::Pool<sqlx::Postgres>, id: i32) -> anyhow::Result<()> {
+        sqlx::query!(
+            "UPDATE announcement_chat SET failures = 0, last_error = NULL WHERE chat_id = $1",
+            id
+        )
+        .execute(conn)
+        .await
+        .context("unable to mark announcement delivered")?;
+
+        Ok(())
+    }
+
+    /// Record that an announcement couldn't be delivered to a chat, removing
+    /// the chat once it has failed too many times in a row.
+    ///
+    /// Returns if the chat was removed.
+    pub async fn failed(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        id: i32,
+        error: &str,
+        max_failures: i32,
+    ) -> anyhow::Result<bool> {
+        let failures = sqlx::query_scalar!(
+            "UPDATE announcement_chat SET failures = failures + 1, last_error = $2
+            WHERE chat_id = $1 RETURNING failures",
+            id,
+            error
+        )
+        .fetch_optional(conn)
+        .await
+        .context("unable to mark announcement failed")?;
+
+        if matches!(failures, Some(failures) if failures >= max_failures) {
+            sqlx::query!("DELETE FROM announcement_chat WHERE chat_id = $1", id)
+                .execute(conn)
+                .await
+                .context("unable to remove failing announcement chat")?;
+
+            return Ok(true);
+        }
+
+        Ok(false)
+    }
+}
+
+/// An announcement sent to every chat that opted into them.
+pub struct Broadcast {
+    pub id: i32,
+    pub text: String,
+    /// Telegram ID of the user who sent the broadcast.
+    pub created_by: i64,
+    /// Number of chats the broadcast was delivered to.
+    pub sent: i32,
+    /// Number of chats the broadcast couldn't be delivered to.
+    pub failed: i32,
+}
+
+/// Announcements that were broadcast.
+pub struct Broadcasts;
+
+impl Broadcasts {
+    /// Save a new broadcast, returning its ID.
+    pub async fn create(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        text: &str,
+        created_by: i64,
+    ) -> anyhow::Result<i32> {
+        let id = sqlx::query_scalar!(
+            "INSERT INTO broadcast (text, created_by) VALUES ($1, $2) RETURNING id",
+            text,
+            created_by
+        )
+        .fetch_one(conn)
+        .await
+        .context("unable to insert broadcast")?;
+
+        Ok(id)
+    }
+
+    pub async fn get(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        id: i32,
+    ) -> anyhow::Result<Option<Broadcast>> {
+        let broadcast = sqlx::query_as!(
+            Broadcast,
+            "SELECT id, text, created_by, sent, failed FROM broadcast WHERE id = $1",
+            id
+        )
+        .fetch_optional(conn)
+        .await
+        .context("unable to select broadcast")?;
+
+        Ok(broadcast)
+    }
+
+    /// Add to the number of chats a broadcast was sent to and failed for.
+    pub async fn record(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        id: i32,
+        sent: i32,
+        failed: i32,
+    ) -> anyhow::Result<()> {
+        sqlx::query!(
+            "UPDATE broadcast SET sent = sent + $2, failed = failed + $3 WHERE id = $1",
+            id,
+            sent,
+            failed
+        )
+        .execute(conn)
+        .await
+        .context("unable to update broadcast counts")?;
+
+        Ok(())
+    }
+
+    /// Mark a broadcast as sent to every chat.
+    pub async fn complete(conn: &sqlx::Pool<sqlx::Postgres>, id: i32) -> anyhow::Result<()> {
+        sqlx::query!(
+            "UPDATE broadcast SET completed_at = current_timestamp WHERE id = $1",
+            id
+        )
+        .execute(conn)
+        .await
+        .context("unable to complete broadcast")?;
+
+        Ok(())
+    }
+}
+
+/// Details about a chat the bot is in.
+pub struct ChatDetails<'a> {
+    pub title: Option<&'a str>,
+    /// Type of the chat, like `supergroup` or `channel`.
+    pub chat_type: &'a str,
+    pub member_count: Option<i32>,
+}
+
+/// Chats the bot has been added to or removed from.
+pub struct Chats;
+
+impl Chats {
+    /// Save the details of a chat and if the bot is currently a member.
+    ///
+    /// The time the bot was added or removed is only updated when its
+    /// membership changes.
+    pub async fn update(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        chat_id: i64,
+        details: &ChatDetails<'_>,
+        is_member: bool,
+    ) -> anyhow::Result<()> {
+        sqlx::query!(
+            "UPDATE chat SET
+                title = $2,
+                chat_type = $3,
+                member_count = $4,
+                added_at = CASE WHEN $5 AND is_member IS DISTINCT FROM true
+                    THEN current_timestamp ELSE added_at END,
+                removed_at = CASE WHEN NOT $5 AND is_member IS DISTINCT FROM false
+                    THEN current_timestamp ELSE removed_at END,
+                is_member = $5
+            WHERE id = lookup_chat_by_telegram_id($1)",
+            chat_id,
+            details.title,
+            details.chat_type,
+            details.member_count,
+            is_member
+        )
+        .execute(conn)
+        .await
+        .context("unable to update chat details")?;
+
+        Ok(())
+    }
+
+    /// Remove everything configured for a chat, after the bot was removed
+    /// from it.
+    pub async fn remove_settings(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        chat_id: i64,
+    ) -> anyhow::Result<()> {
+        let mut tx = conn.begin().await?;
+
+        let id = match sqlx::query_scalar!(
+            "SELECT chat_id FROM chat_telegram WHERE telegram_id = $1",
+            chat_id
+        )
+        .fetch_optional(&mut tx)
+        .await?
+        {
+            Some(id) => id,
+            None => return Ok(()),
+        };
+
+        sqlx::query!("DELETE FROM group_config WHERE chat_id = $1", id)
+            .execute(&mut tx)
+            .await?;
+        sqlx::query!("DELETE FROM chat_administrator WHERE chat_id = $1", id)
+            .execute(&mut tx)
+            .await?;
+        sqlx::query!("DELETE FROM artist_watch WHERE chat_id = $1", id)
+            .execute(&mut tx)
+            .await?;
+        sqlx::query!("DELETE FROM announcement_chat WHERE chat_id = $1", id)
+            .execute(&mut tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+}
+
+/// Site credentials that were updated while the bot was running, which take
+/// precedence over configured credentials.
+pub struct SiteCredentials;
+
+impl SiteCredentials {
+    /// Get every stored credential, by configuration name like `FA_A`.
+    pub async fn all(conn: &sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<HashMap<String, String>> {
+        let credentials = sqlx::query!("SELECT name, value FROM site_credential")
+            .map(|row| (row.name, row.value))
+            .fetch_all(conn)
+            .await
+            .context("unable to select site credentials")?;
+
+        Ok(credentials.into_iter().collect())
+    }
+
+    /// Store a credential, replacing any previous value.
+    pub async fn set(
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        name: &str,
+        value: &str,
+        updated_by: Option<i64>,
+    ) -> anyhow::Result<()> {
+        sqlx::query!(
+            "INSERT INTO site_credential (name, value, updated_by) VALUES ($1, $2, $3)
+                ON CONFLICT (name) DO UPDATE SET value = EXCLUDED.value, updated_by = EXCLUDED.updated_by, updated_at = current_timestamp",
+            name,
+            value,
+            updated_by
+        )
+        .execute(conn)
+        .await
+        .context("unable to update site credential")?;
+
+        Ok(())
+    }
+
+    /// Remove a stored credential so the configured value is used again,
+    /// returning if one existed.
+    pub async fn remove(conn: &sqlx::Pool<sqlx::Postgres>, name: &str) -> anyhow::Result<bool> {
+        let result = sqlx::query!("DELETE FROM site_credential WHERE name = $1", name)
+            .execute(conn)
+            .await
+            .context("unable to remove site credential")?;
+
+        Ok(result.rows_affected() > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    /// Telegram user and chat IDs no longer fit in an `i32`, so every query
+    /// given one must bind it as a `BIGINT`.
+    #[test]
+    fn test_telegram_ids_are_bigint() {
+        let data: serde_json::Value =
+            serde_json::from_str(include_str!("../../sqlx-data.json")).unwrap();
+
+        let mut checked = 0;
+
+        for (hash, query) in data.as_object().unwrap() {
+            if hash == "db" {
+                continue;
+            }
+
+            let sql = query["query"].as_str().unwrap();
+            let params = query["describe"]["parameters"]["Left"].as_array().unwrap();
+
+            for pattern in &[
+                "lookup_account_by_telegram_id($",
+                "lookup_chat_by_telegram_id($",
+                "telegram_id = $",
+            ] {
+                for (index, _) in sql.match_indices(pattern) {
+                    let position: usize = sql[index + pattern.len()..]
+                        .chars()
+                        .take_while(|c| c.is_ascii_digit())
+                        .collect::<String>()
+                        .parse()
+                        .unwrap();
+
+                    assert_eq!(
+                        params[position - 1],
+                        "Int8",
+                        "telegram id should be a bigint in {:?}",
+                        sql
+                    );
+                    checked += 1;
+                }
+            }
+        }
+
+        assert!(checked > 0, "queries should have been checked");
+    }
+
+    /// Database failures while signing into Twitter should be returned as
+    /// errors instead of panicking.
+    #[tokio::test]
+    async fn test_twitter_database_errors() {
+        use super::{Twitter, TwitterAccount};
+
+        let conn = sqlx::postgres::PgPoolOptions::new()
+            .connect_timeout(std::time::Duration::from_secs(1))
+            .connect_lazy("postgres://foxbot@127.0.0.1:1/foxbot")
+            .unwrap();
+
+        let user_id = 5_000_000_000;
+
+        assert!(Twitter::set_request(&conn, user_id, "key", "secret")
+            .await
+            .is_err());
+        assert!(Twitter::get_request(&conn, "key").await.is_err());
+        assert!(Twitter::set_account(
+            &conn,
+            user_id,
+            TwitterAccount {
+                consumer_key: "key".to_string(),
+                consumer_secret: "secret".to_string(),
+            }
+        )
+        .await
+        .is_err());
+        assert!(Twitter::get_account(&conn, user_id).await.is_err());
+    }
+}
@@ -0,0 +1,76 @@
+//! Checks that matching arbitrary text against every site never panics, and
+//! that any URL given an ID is also supported, so loading it can rely on the
+//! matcher's captures.
+//!
+//! Run with `cargo +nightly fuzz run url_matchers` from `foxbot-sites`.
+
+#![no_main]
+
+use std::cell::RefCell;
+
+use foxbot_sites::{
+    credentials::MemorySessions, get_file_ext, BoxedSite, DeviantArt, E621Host, FurAffinity,
+    Inkbunny, Mastodon, Proxies, Site, Weasyl, E621,
+};
+use libfuzzer_sys::fuzz_target;
+
+thread_local! {
+    static RUNTIME: tokio::runtime::Runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+
+    static SITES: RefCell<Vec<BoxedSite>> = RefCell::new(RUNTIME.with(|runtime| runtime.block_on(sites())));
+}
+
+async fn sites() -> Vec<BoxedSite> {
+    let proxies = Proxies::default();
+
+    vec![
+        Box::new(E621::new(
+            E621Host::E621,
+            String::new(),
+            String::new(),
+            &proxies,
+        )),
+        Box::new(
+            FurAffinity::new(
+                (String::new(), String::new()),
+                String::new(),
+                &proxies,
+                None,
+                Box::new(MemorySessions::default()),
+            )
+            .await,
+        ),
+        Box::new(Weasyl::new(String::new(), &proxies)),
+        Box::new(Inkbunny::new(String::new(), String::new(), &proxies)),
+        Box::new(DeviantArt::new(&proxies)),
+        Box::new(Mastodon::new(&proxies)),
+    ]
+}
+
+fuzz_target!(|url: &str| {
+    if let Some(ext) = get_file_ext(url) {
+        assert!(url.contains(ext));
+    }
+
+    RUNTIME.with(|runtime| {
+        SITES.with(|sites| {
+            for site in sites.borrow_mut().iter_mut() {
+                let url_id = site.url_id(url);
+
+                // Mastodon makes requests to check if a URL is supported.
+                if site.name() == "Mastodon" || url_id.is_none() {
+                    continue;
+                }
+
+                assert!(
+                    runtime.block_on(site.match_url(url)).is_some(),
+                    "{} gave an ID to an unsupported URL",
+                    site.name()
+                );
+            }
+        })
+    });
+});
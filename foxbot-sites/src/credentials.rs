@@ -0,0 +1,128 @@
+//! Credentials and saved state that sites need, provided by whatever is using
+//! the loaders.
+
+use async_trait::async_trait;
+use std::{collections::HashMap, sync::Mutex};
+
+/// An access token for a Twitter account that authorized the application.
+#[derive(Clone, Debug)]
+pub struct TwitterAccess {
+    pub key: String,
+    pub secret: String,
+}
+
+/// Credentials belonging to whoever asked for a URL to be loaded.
+///
+/// Sites fall back to their own credentials when none are provided.
+#[async_trait]
+pub trait Credentials: Send + Sync {
+    /// A Twitter account to load Tweets with, so Tweets from locked accounts
+    /// it follows can be loaded.
+    async fn twitter(&self) -> anyhow::Result<Option<TwitterAccess>> {
+        Ok(None)
+    }
+}
+
+/// Load URLs without any credentials.
+pub struct Anonymous;
+
+impl Credentials for Anonymous {}
+
+/// Cookies and user agent a site loader needs to keep using, like those from
+/// solving a challenge page.
+#[derive(Clone, Debug, Default)]
+pub struct Session {
+    pub cookies: HashMap<String, String>,
+    pub user_agent: Option<String>,
+}
+
+/// Storage for sessions that should be kept between restarts.
+#[async_trait]
+pub trait SessionStore: Send + Sync {
+    async fn get(&self, site: &str) -> anyhow::Result<Option<Session>>;
+    async fn save(&self, site: &str, session: &Session) -> anyhow::Result<()>;
+}
+
+/// Sessions kept in memory, which are lost when the loaders are dropped.
+#[derive(Default)]
+pub struct MemorySessions(Mutex<HashMap<String, Session>>);
+
+#[async_trait]
+impl SessionStore for MemorySessions {
+    async fn get(&self, site: &str) -> anyhow::Result<Option<Session>> {
+        Ok(self.0.lock().unwrap().get(site).cloned())
+    }
+
+    async fn save(&self, site: &str, session: &Session) -> anyhow::Result<()> {
+        self.0
+            .lock()
+            .unwrap()
+            .insert(site.to_string(), session.clone());
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "database")]
+pub use database::UserCredentials;
+
+#[cfg(feature = "database")]
+mod database {
+    use anyhow::Context;
+    use async_trait::async_trait;
+
+    use super::{Credentials, Session, SessionStore, TwitterAccess};
+    use foxbot_models::{SiteSession, SiteSessions, Twitter};
+
+    /// Credentials a Telegram user saved with the bot.
+    pub struct UserCredentials {
+        conn: sqlx::Pool<sqlx::Postgres>,
+        user_id: i64,
+    }
+
+    impl UserCredentials {
+        pub fn new(conn: sqlx::Pool<sqlx::Postgres>, user_id: i64) -> Self {
+            Self { conn, user_id }
+        }
+    }
+
+    #[async_trait]
+    impl Credentials for UserCredentials {
+        async fn twitter(&self) -> anyhow::Result<Option<TwitterAccess>> {
+            tracing::trace!(
+                user_id = self.user_id,
+                "attempting to find saved credentials"
+            );
+
+            let account = Twitter::get_account(&self.conn, self.user_id)
+                .await
+                .context("unable to query twitter account")?;
+
+            Ok(account.map(|account| TwitterAccess {
+                key: account.consumer_key,
+                secret: account.consumer_secret,
+            }))
+        }
+    }
+
+    #[async_trait]
+    impl SessionStore for sqlx::Pool<sqlx::Postgres> {
+        async fn get(&self, site: &str) -> anyhow::Result<Option<Session>> {
+            let session = SiteSessions::get(self, site).await?;
+
+            Ok(session.map(|session| Session {
+                cookies: session.cookies,
+                user_agent: session.user_agent,
+            }))
+        }
+
+        async fn save(&self, site: &str, session: &Session) -> anyhow::Result<()> {
+            let session = SiteSession {
+                cookies: session.cookies.clone(),
+                user_agent: session.user_agent.clone(),
+            };
+
+            SiteSessions::save(self, site, &session).await
+        }
+    }
+}
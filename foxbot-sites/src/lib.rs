@@ -1,12 +1,27 @@
+//! Loaders that find images and their sources from links to art sites.
+//!
+//! A [`Resolver`] checks a URL against a set of [`Site`]s and loads it with
+//! the first that supports it. Each site is behind a feature with the site's
+//! name, and the `database` feature stores sessions and user credentials in
+//! the bot's database.
+
 use anyhow::Context;
 use async_trait::async_trait;
 use fuzzysearch::MatchType;
+#[cfg(feature = "furaffinity")]
 use reqwest::header;
-use serde::Deserialize;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::{borrow::Cow, collections::HashMap};
+#[cfg(feature = "furaffinity")]
 use thiserror::Error;
 
-use foxbot_models::Twitter as TwitterModel;
+pub mod credentials;
+#[cfg(feature = "weasyl")]
+pub mod weasyl;
+
+pub use credentials::{Anonymous, Credentials};
+#[cfg(feature = "furaffinity")]
+use credentials::{Session, SessionStore};
 
 /// User agent used with all HTTP requests to sites.
 const USER_AGENT: &str = concat!(
@@ -15,11 +30,130 @@ const USER_AGENT: &str = concat!(
     " developed by @Syfaro"
 );
 
+tokio::task_local! {
+    /// Number of HTTP requests sites have made in the current scope.
+    static HTTP_CALLS: std::cell::Cell<u32>;
+}
+
+/// Record that a site made an HTTP request, if requests are being counted.
+fn count_http_call() {
+    let _ = HTTP_CALLS.try_with(|calls| calls.set(calls.get() + 1));
+}
+
+/// A site refused a request because too many were made to it.
+#[derive(Debug, Error)]
+#[error("{site} is rate limiting requests")]
+pub struct RateLimited {
+    pub site: &'static str,
+}
+
+impl RateLimited {
+    /// Check if an error was caused by a site rate limiting requests.
+    pub fn is_cause(err: &anyhow::Error) -> bool {
+        err.chain().any(|err| err.is::<RateLimited>())
+    }
+}
+
+/// Return an error if a site responded that it is rate limiting requests.
+fn check_rate_limit(
+    site: &'static str,
+    resp: reqwest::Response,
+) -> Result<reqwest::Response, RateLimited> {
+    if resp.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+        tracing::warn!(site, "site is rate limiting requests");
+        return Err(RateLimited { site });
+    }
+
+    Ok(resp)
+}
+
+/// Run a future, also returning how many HTTP requests sites made while it
+/// ran.
+pub async fn count_http_calls<F>(fut: F) -> (F::Output, u32)
+where
+    F: std::future::Future,
+{
+    HTTP_CALLS
+        .scope(std::cell::Cell::new(0), async move {
+            let output = fut.await;
+            let calls = HTTP_CALLS.with(|calls| calls.get());
+
+            (output, calls)
+        })
+        .await
+}
+
+/// Proxies used for requests to sites, like an egress proxy that only some
+/// sites need to go through.
+///
+/// Twitter API requests are made by egg-mode with its own client, so they
+/// don't use these proxies.
+#[derive(Clone, Debug, Default)]
+pub struct Proxies {
+    /// Proxy used for any site without its own.
+    pub default: Option<String>,
+    /// Proxies for specific sites, by site name. A proxy of `direct` makes
+    /// requests to that site skip the default proxy.
+    pub sites: HashMap<String, String>,
+}
+
+impl Proxies {
+    /// Create proxies from a default proxy and a list of site proxies like
+    /// `FurAffinity=socks5://127.0.0.1:1080`.
+    pub fn new(default: Option<String>, sites: &[String]) -> anyhow::Result<Self> {
+        let sites = sites
+            .iter()
+            .map(|site| {
+                let mut parts = site.splitn(2, '=');
+
+                match (parts.next(), parts.next()) {
+                    (Some(name), Some(proxy)) => {
+                        Ok((name.trim().to_lowercase(), proxy.trim().to_string()))
+                    }
+                    _ => anyhow::bail!("site proxy {} must be like Site=url", site),
+                }
+            })
+            .collect::<anyhow::Result<_>>()?;
+
+        let proxies = Self { default, sites };
+
+        for proxy in proxies.sites.values().chain(proxies.default.iter()) {
+            if proxy != "direct" {
+                reqwest::Proxy::all(proxy).with_context(|| format!("invalid proxy {}", proxy))?;
+            }
+        }
+
+        Ok(proxies)
+    }
+
+    /// Get the proxy to use for a site, if any.
+    pub fn for_site(&self, name: &str) -> Option<&str> {
+        match self.sites.get(&name.to_lowercase()) {
+            Some(proxy) if proxy == "direct" => None,
+            Some(proxy) => Some(proxy),
+            None => self.default.as_deref(),
+        }
+    }
+
+    /// Start building a client for a site, with the user agent and any proxy
+    /// already set.
+    fn client_builder(&self, name: &str) -> reqwest::ClientBuilder {
+        let builder = reqwest::Client::builder().user_agent(USER_AGENT);
+
+        match self.for_site(name) {
+            Some(proxy) => {
+                builder.proxy(reqwest::Proxy::all(proxy).expect("proxies should be validated"))
+            }
+            None => builder,
+        }
+    }
+}
+
 /// A thread-safe and boxed Site.
 pub type BoxedSite = Box<dyn Site + Send + Sync>;
 
 /// A collection of information about a post obtained from a given URL.
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct PostInfo {
     /// File type, as a standard file extension (png, jpg, etc.)
     pub file_type: String,
@@ -33,85 +167,519 @@ pub struct PostInfo {
     pub source_link: Option<String>,
     /// Additional caption to add as a second result for the provided query
     pub extra_caption: Option<String>,
-    /// Title for video results
+    /// Title of the post, or for video results
     pub title: Option<String>,
     /// Human readable name of the site
-    pub site_name: &'static str,
+    pub site_name: Cow<'static, str>,
     /// Width and height of image, if available
     pub image_dimensions: Option<(u32, u32)>,
     /// Size of image in bytes, if available
     pub image_size: Option<usize>,
+    /// Content rating, if the site provided one
+    pub rating: Option<fuzzysearch::Rating>,
+    /// Artists who made the post, if the site provided them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub artists: Vec<String>,
+    /// Tags describing the post, if the site provided them
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Pools or collections the post is part of
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pools: Vec<PostPool>,
+    /// URL to the post this post is a variation of, if it has one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_link: Option<String>,
+    /// URLs to posts that are variations of this post
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub child_links: Vec<String>,
+    /// Position of this file in its post, starting at 1, if the post has
+    /// multiple files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_number: Option<usize>,
+    /// Number of files in the post, if it has multiple files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub files_count: Option<usize>,
+}
+
+/// A pool or collection a post is part of.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct PostPool {
+    pub name: String,
+    pub url: String,
+}
+
+impl PostInfo {
+    /// If this post should be treated as not safe for work.
+    ///
+    /// Everything from e621 is treated as NSFW because posts marked as safe
+    /// are still frequently suggestive.
+    pub fn is_nsfw(&self) -> bool {
+        matches!(
+            self.rating,
+            Some(fuzzysearch::Rating::Mature) | Some(fuzzysearch::Rating::Adult)
+        ) || self.site_name == E621Host::E621.name()
+    }
+
+    /// The link to this post's source, or the image itself if there is no
+    /// source.
+    pub fn source_or_url(&self) -> &str {
+        self.source_link.as_deref().unwrap_or(&self.url)
+    }
 }
 
 /// A basic attempt to get the extension from a given URL. It assumes the URL
 /// ends in a filename with an extension.
-fn get_file_ext(name: &str) -> Option<&str> {
+pub fn get_file_ext(name: &str) -> Option<&str> {
     name.split('.')
         .last()
         .map(|ext| ext.split('?').next())
         .flatten()
 }
 
+/// Normalize a link so equivalent URLs produce the same ID.
+///
+/// The scheme and host are lowercased, HTTP is treated the same as HTTPS, and
+/// default ports and fragments are removed. Links without a scheme are assumed
+/// to be HTTPS.
+pub fn canonical_url(url: &str) -> Option<String> {
+    let mut parsed = match url::Url::parse(url) {
+        Ok(parsed) => parsed,
+        Err(url::ParseError::RelativeUrlWithoutBase) => {
+            url::Url::parse(&format!("https://{}", url)).ok()?
+        }
+        Err(_) => return None,
+    };
+
+    match parsed.scheme() {
+        "https" => (),
+        "http" => parsed.set_scheme("https").ok()?,
+        _ => return None,
+    }
+
+    if parsed.port() == Some(443) {
+        parsed.set_port(None).ok()?;
+    }
+
+    parsed.set_fragment(None);
+
+    Some(parsed.to_string())
+}
+
 /// A site that we can potentially load image data from.
 #[async_trait]
 pub trait Site {
     /// The name of the site, as might be displayed to a user.
     fn name(&self) -> &'static str;
     /// A unique ID deterministically generated from the URL.
+    ///
+    /// Equivalent URLs, like the mobile and desktop links to a post, must
+    /// produce the same ID as it is used to cache results and detect links
+    /// that were already sourced.
     fn url_id(&self, url: &str) -> Option<String>;
 
-    /// Check if the URL might be supported by this site.
-    async fn url_supported(&mut self, url: &str) -> bool;
-    /// Attempt to load images from the given URL, with the Telegram user ID
-    /// in case credentials are needed.
+    /// Check if the URL might be supported by this site, returning the
+    /// parts of it needed to load it.
+    async fn match_url(&mut self, url: &str) -> Option<UrlMatch>;
+    /// Attempt to load images from a URL matched by this site, with the
+    /// requester's credentials in case they are needed.
     async fn get_images(
         &mut self,
-        user_id: i64,
-        url: &str,
+        credentials: &dyn Credentials,
+        url: &UrlMatch,
     ) -> anyhow::Result<Option<Vec<PostInfo>>>;
+
+    /// Load the newest posts from a page that can be watched for updates,
+    /// like an artist's gallery or a tag search, newest first.
+    ///
+    /// Returns `None` if the URL can't be watched on this site.
+    async fn latest_posts(&mut self, _url: &str) -> anyhow::Result<Option<Vec<PostInfo>>> {
+        Ok(None)
+    }
+
+    /// Check if the site is still accepting the configured credentials.
+    ///
+    /// Returns `None` if the site doesn't use credentials that can expire.
+    async fn check_login(&mut self) -> anyhow::Result<Option<bool>> {
+        Ok(None)
+    }
+
+    /// A public URL this site should always be able to load, used to check
+    /// that it's working and its credentials are accepted.
+    ///
+    /// It must be a real post with at least one image that is expected to
+    /// stay up. Sites without one aren't checked.
+    fn test_url(&self) -> Option<&'static str> {
+        None
+    }
+}
+
+/// The result of loading a site's test URL.
+#[derive(Debug)]
+pub struct SiteCheck {
+    pub site: &'static str,
+    pub url: &'static str,
+    /// How long it took to load the URL and check the site's login.
+    pub duration: std::time::Duration,
+    /// The number of posts loaded, or why the site failed.
+    pub result: anyhow::Result<usize>,
+}
+
+impl SiteCheck {
+    pub fn passed(&self) -> bool {
+        self.result.is_ok()
+    }
+}
+
+/// Load the test URL of every site that has one, without any user's
+/// credentials, so deployments can verify sites work before serving users.
+pub async fn check_sites(sites: &mut [BoxedSite]) -> Vec<SiteCheck> {
+    let mut checks = Vec::with_capacity(sites.len());
+
+    for site in sites.iter_mut() {
+        let url = match site.test_url() {
+            Some(url) => url,
+            None => continue,
+        };
+
+        let start = std::time::Instant::now();
+        let result = check_site(site, url).await;
+
+        checks.push(SiteCheck {
+            site: site.name(),
+            url,
+            duration: start.elapsed(),
+            result,
+        });
+    }
+
+    checks
+}
+
+async fn check_site(site: &mut BoxedSite, url: &str) -> anyhow::Result<usize> {
+    if let Some(false) = site.check_login().await? {
+        anyhow::bail!("site is not accepting credentials");
+    }
+
+    let url_match = site
+        .match_url(url)
+        .await
+        .context("site did not match its test url")?;
+
+    let posts = site
+        .get_images(&Anonymous, &url_match)
+        .await?
+        .context("site did not find its test post")?;
+
+    if posts.is_empty() {
+        anyhow::bail!("site did not find any images in its test post");
+    }
+
+    Ok(posts.len())
+}
+
+/// A URL that a site is able to load, with the parts of it needed to load it.
+///
+/// It can only be created by a site matching a URL, so loading it never needs
+/// to match the URL again.
+#[derive(Clone, Debug)]
+pub struct UrlMatch {
+    url: String,
+    site: &'static str,
+    kind: MatchKind,
+}
+
+/// What a matched URL points to.
+#[derive(Clone, Debug, PartialEq)]
+enum MatchKind {
+    Direct,
+    #[cfg(feature = "e621")]
+    E621Post {
+        id: String,
+    },
+    #[cfg(feature = "e621")]
+    E621File {
+        md5: String,
+    },
+    #[cfg(feature = "e621")]
+    E621Pool {
+        id: String,
+    },
+    #[cfg(feature = "twitter")]
+    Twitter {
+        screen_name: String,
+        id: Option<u64>,
+    },
+    #[cfg(feature = "furaffinity")]
+    FurAffinitySubmission {
+        id: i32,
+    },
+    #[cfg(feature = "furaffinity")]
+    FurAffinityFile {
+        file_name: String,
+    },
+    #[cfg(feature = "mastodon")]
+    Mastodon {
+        host: String,
+        id: String,
+        software: FediverseSoftware,
+        /// If media from the author's replies continuing the status should
+        /// also be loaded.
+        thread: bool,
+    },
+    #[cfg(feature = "weasyl")]
+    WeasylSubmission {
+        id: i32,
+    },
+    #[cfg(feature = "weasyl")]
+    WeasylCharacter {
+        id: i32,
+    },
+    #[cfg(feature = "inkbunny")]
+    Inkbunny {
+        id: i32,
+        /// Page of the submission that was linked, starting at 1.
+        page: Option<usize>,
+    },
+    #[cfg(feature = "deviantart")]
+    DeviantArt,
+}
+
+impl UrlMatch {
+    fn new<S: Site + ?Sized>(site: &S, url: &str, kind: MatchKind) -> Self {
+        Self {
+            url: url.to_string(),
+            site: site.name(),
+            kind,
+        }
+    }
+
+    /// The URL that was matched.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// The name of the site that matched the URL.
+    pub fn site(&self) -> &'static str {
+        self.site
+    }
+
+    /// Error for a site asked to load a URL another site matched.
+    fn wrong_site(&self, site: &str) -> anyhow::Error {
+        anyhow::anyhow!("{} can't load url matched by {}", site, self.site)
+    }
 }
 
+/// Number of posts loaded when checking a watched page.
+pub const LATEST_POSTS: usize = 5;
+
+/// Create every site with the bot's configuration, in the order they should
+/// be checked.
+#[cfg(all(
+    feature = "database",
+    feature = "e621",
+    feature = "twitter",
+    feature = "furaffinity",
+    feature = "weasyl",
+    feature = "inkbunny",
+    feature = "deviantart",
+    feature = "mastodon"
+))]
 pub async fn get_all_sites(
     fa_a: String,
     fa_b: String,
+    flaresolverr_endpoint: Option<String>,
     fuzzysearch_apitoken: String,
     weasyl_apitoken: String,
     twitter_consumer_key: String,
     twitter_consumer_secret: String,
+    twitter_thread_limit: usize,
     inkbunny_username: String,
     inkbunny_password: String,
     e621_login: String,
     e621_api_key: String,
     pool: sqlx::Pool<sqlx::Postgres>,
+    proxies: &Proxies,
 ) -> Vec<BoxedSite> {
-    vec![
-        Box::new(E621::new(
+    get_sites(
+        None,
+        fa_a,
+        fa_b,
+        flaresolverr_endpoint,
+        fuzzysearch_apitoken,
+        weasyl_apitoken,
+        twitter_consumer_key,
+        twitter_consumer_secret,
+        twitter_thread_limit,
+        inkbunny_username,
+        inkbunny_password,
+        e621_login,
+        e621_api_key,
+        pool,
+        proxies,
+    )
+    .await
+}
+
+/// Create the sites with the given names, or every site, in the order they
+/// should be checked.
+///
+/// Only creating some sites allows rebuilding those using a changed credential
+/// without replacing the others and their sessions.
+#[cfg(all(
+    feature = "database",
+    feature = "e621",
+    feature = "twitter",
+    feature = "furaffinity",
+    feature = "weasyl",
+    feature = "inkbunny",
+    feature = "deviantart",
+    feature = "mastodon"
+))]
+pub async fn get_sites(
+    names: Option<&[&str]>,
+    fa_a: String,
+    fa_b: String,
+    flaresolverr_endpoint: Option<String>,
+    fuzzysearch_apitoken: String,
+    weasyl_apitoken: String,
+    twitter_consumer_key: String,
+    twitter_consumer_secret: String,
+    twitter_thread_limit: usize,
+    inkbunny_username: String,
+    inkbunny_password: String,
+    e621_login: String,
+    e621_api_key: String,
+    pool: sqlx::Pool<sqlx::Postgres>,
+    proxies: &Proxies,
+) -> Vec<BoxedSite> {
+    let wanted = |name: &str| names.map_or(true, |names| names.contains(&name));
+    let mut sites: Vec<BoxedSite> = Vec::new();
+
+    if wanted("e621") {
+        sites.push(Box::new(E621::new(
             E621Host::E621,
             e621_login.clone(),
             e621_api_key.clone(),
-        )),
-        Box::new(E621::new(E621Host::E926, e621_login, e621_api_key)),
-        Box::new(FurAffinity::new((fa_a, fa_b), fuzzysearch_apitoken.clone())),
-        Box::new(Weasyl::new(weasyl_apitoken)),
-        Box::new(Twitter::new(twitter_consumer_key, twitter_consumer_secret, pool).await),
-        Box::new(Inkbunny::new(inkbunny_username, inkbunny_password)),
-        Box::new(Mastodon::default()),
-        Box::new(DeviantArt::default()),
-        Box::new(Direct::new(fuzzysearch_apitoken)),
-    ]
+            proxies,
+        )));
+    }
+
+    if wanted("e926") {
+        sites.push(Box::new(E621::new(
+            E621Host::E926,
+            e621_login,
+            e621_api_key,
+            proxies,
+        )));
+    }
+
+    if wanted("FurAffinity") {
+        sites.push(Box::new(
+            FurAffinity::new(
+                (fa_a, fa_b),
+                fuzzysearch_apitoken.clone(),
+                proxies,
+                flaresolverr_endpoint.map(|endpoint| {
+                    Box::new(FlareSolverr::new(endpoint)) as Box<dyn ChallengeSolver + Send + Sync>
+                }),
+                Box::new(pool),
+            )
+            .await,
+        ));
+    }
+
+    if wanted("Weasyl") {
+        sites.push(Box::new(Weasyl::new(weasyl_apitoken, proxies)));
+    }
+
+    if wanted("Twitter") {
+        sites.push(Box::new(
+            Twitter::new(twitter_consumer_key, twitter_consumer_secret)
+                .await
+                .with_thread_limit(twitter_thread_limit),
+        ));
+    }
+
+    if wanted("Inkbunny") {
+        sites.push(Box::new(Inkbunny::new(
+            inkbunny_username,
+            inkbunny_password,
+            proxies,
+        )));
+    }
+
+    if wanted("Mastodon") {
+        sites.push(Box::new(Mastodon::new(proxies)));
+    }
+
+    if wanted("DeviantArt") {
+        sites.push(Box::new(DeviantArt::new(proxies)));
+    }
+
+    if wanted("direct link") {
+        sites.push(Box::new(Direct::new(fuzzysearch_apitoken, proxies)));
+    }
+
+    sites
+}
+
+/// Loads images from URLs using a set of sites.
+///
+/// Sites are checked in order and the first one that matches a URL loads it,
+/// so more general sites like [`Direct`] should be last.
+pub struct Resolver {
+    sites: Vec<BoxedSite>,
+}
+
+impl Resolver {
+    pub fn new(sites: Vec<BoxedSite>) -> Self {
+        Self { sites }
+    }
+
+    /// The sites used to load URLs.
+    pub fn sites(&mut self) -> &mut [BoxedSite] {
+        &mut self.sites
+    }
+
+    /// Load the images from a URL without any credentials.
+    ///
+    /// URLs that no site supports and posts without images both return no
+    /// images.
+    pub async fn resolve(&mut self, url: &str) -> anyhow::Result<Vec<PostInfo>> {
+        self.resolve_with(&Anonymous, url).await
+    }
+
+    /// Load the images from a URL, using credentials if a site needs them.
+    pub async fn resolve_with(
+        &mut self,
+        credentials: &dyn Credentials,
+        url: &str,
+    ) -> anyhow::Result<Vec<PostInfo>> {
+        for site in self.sites.iter_mut() {
+            if let Some(url_match) = site.match_url(url).await {
+                let posts = site.get_images(credentials, &url_match).await?;
+                return Ok(posts.unwrap_or_default());
+            }
+        }
+
+        Ok(Vec::new())
+    }
 }
 
 // workaround for NoneError not actually being an Error
 // https://github.com/rust-lang-nursery/failure/issues/59#issuecomment-602862336
+#[cfg(feature = "furaffinity")]
 #[derive(Debug, Error)]
 #[error("NoneError")]
 struct NoneError;
 
+#[cfg(feature = "furaffinity")]
 trait OptionExt {
     type T;
     fn unwrap_fail(self) -> Result<Self::T, NoneError>;
 }
 
+#[cfg(feature = "furaffinity")]
 impl<U> OptionExt for Option<U> {
     type T = U;
     fn unwrap_fail(self) -> Result<Self::T, NoneError> {
@@ -137,12 +705,12 @@ impl Direct {
     /// Mime types we should consider valid images.
     const TYPES: &'static [&'static str] = &["image/png", "image/jpeg", "image/gif"];
 
-    pub fn new(fuzzysearch_apitoken: String) -> Self {
+    pub fn new(fuzzysearch_apitoken: String, proxies: &Proxies) -> Self {
         let fautil = std::sync::Arc::new(fuzzysearch::FuzzySearch::new(fuzzysearch_apitoken));
 
-        let client = reqwest::Client::builder()
+        let client = proxies
+            .client_builder("direct link")
             .timeout(std::time::Duration::from_secs(2))
-            .user_agent(USER_AGENT)
             .build()
             .expect("Unable to create client");
 
@@ -154,6 +722,7 @@ impl Direct {
     /// source and keep the request fast, but a timeout should be applied for
     /// use in inline queries in case FuzzySearch is running behind.
     async fn reverse_search(&self, url: &str) -> Option<fuzzysearch::File> {
+        count_http_call();
         let image = self.client.get(url).send().await;
 
         let image = match image {
@@ -189,39 +758,43 @@ impl Site for Direct {
             return None;
         }
 
-        Some(url.to_owned())
+        canonical_url(url)
     }
 
-    async fn url_supported(&mut self, url: &str) -> bool {
+    async fn match_url(&mut self, url: &str) -> Option<UrlMatch> {
         // If the URL extension isn't one in our list, ignore.
         if !Self::EXTENSIONS.iter().any(|ext| url.ends_with(ext)) {
-            return false;
+            return None;
         }
 
         // Make a HTTP HEAD request to determine the Content-Type.
-        let resp = match self.client.head(url).send().await {
-            Ok(resp) => resp,
-            Err(_) => return false,
-        };
+        count_http_call();
+        let resp = self.client.head(url).send().await.ok()?;
 
         if !resp.status().is_success() {
-            return false;
+            return None;
         }
 
-        let content_type = match resp.headers().get(reqwest::header::CONTENT_TYPE) {
-            Some(content_type) => content_type,
-            None => return false,
-        };
+        let content_type = resp.headers().get(reqwest::header::CONTENT_TYPE)?;
 
-        // Return if the Content-Type is in our list.
-        Self::TYPES.iter().any(|t| content_type == t)
+        // Only match if the Content-Type is in our list.
+        if Self::TYPES.iter().any(|t| content_type == t) {
+            Some(UrlMatch::new(self, url, MatchKind::Direct))
+        } else {
+            None
+        }
     }
 
     async fn get_images(
         &mut self,
-        _user_id: i64,
-        url: &str,
+        _credentials: &dyn Credentials,
+        url: &UrlMatch,
     ) -> anyhow::Result<Option<Vec<PostInfo>>> {
+        if url.kind != MatchKind::Direct {
+            return Err(url.wrong_site(self.name()));
+        }
+
+        let url = url.url();
         let u = url.to_string();
         let mut source_link = None;
         let mut source_name = None;
@@ -250,17 +823,19 @@ impl Site for Direct {
             file_type: ext.to_string(),
             url: u.clone(),
             source_link,
-            site_name: source_name.unwrap_or_else(|| self.name()),
+            site_name: source_name.unwrap_or_else(|| self.name()).into(),
             ..Default::default()
         }]))
     }
 }
 
+#[cfg(feature = "e621")]
 pub enum E621Host {
     E621,
     E926,
 }
 
+#[cfg(feature = "e621")]
 impl E621Host {
     pub fn name(&self) -> &'static str {
         match self {
@@ -281,40 +856,71 @@ impl E621Host {
 ///
 /// It can convert direct image links back into post URLs. It will only load the
 /// 10 most recent posts when given a pool link.
+#[cfg(feature = "e621")]
 pub struct E621 {
     show: regex::Regex,
     data: regex::Regex,
     pool: regex::Regex,
+    tags: regex::Regex,
 
     client: reqwest::Client,
 
     site: E621Host,
+    /// Base URL for API requests, which is the site's host unless changed.
+    endpoint: String,
     auth: (String, String),
 }
 
+#[cfg(feature = "e621")]
 #[derive(Debug, Deserialize)]
 struct E621PostFile {
     ext: Option<String>,
     url: Option<String>,
 }
 
+#[cfg(feature = "e621")]
 #[derive(Debug, Deserialize)]
 struct E621PostPreview {
     url: Option<String>,
 }
 
+#[cfg(feature = "e621")]
+#[derive(Debug, Default, Deserialize)]
+struct E621PostRelationships {
+    parent_id: Option<i32>,
+    #[serde(default)]
+    children: Vec<i32>,
+}
+
+#[cfg(feature = "e621")]
 #[derive(Debug, Deserialize)]
 struct E621Post {
     id: i32,
     file: E621PostFile,
     preview: E621PostPreview,
+    rating: Option<String>,
+    /// Tags for each category, like `artist` or `species`.
+    #[serde(default)]
+    tags: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    pools: Vec<i32>,
+    #[serde(default)]
+    relationships: E621PostRelationships,
 }
 
+#[cfg(feature = "e621")]
 #[derive(Debug, Deserialize)]
 struct E621Resp {
     post: Option<E621Post>,
 }
 
+#[cfg(feature = "e621")]
+#[derive(Debug, Deserialize)]
+struct E621Posts {
+    posts: Vec<E621Post>,
+}
+
+#[cfg(feature = "e621")]
 #[derive(Debug, Deserialize)]
 struct E621Pool {
     id: i32,
@@ -322,65 +928,154 @@ struct E621Pool {
     post_ids: Vec<i32>,
 }
 
+#[cfg(feature = "e621")]
+#[derive(Debug, Deserialize)]
+struct E621PoolName {
+    id: i32,
+    name: String,
+}
+
+#[cfg(feature = "e621")]
 struct E621Data {
     id: i32,
     file_url: String,
     file_ext: String,
     preview_url: String,
+    rating: Option<fuzzysearch::Rating>,
+    artists: Vec<String>,
+    tags: Vec<String>,
+    pool_ids: Vec<i32>,
+    parent_id: Option<i32>,
+    children: Vec<i32>,
 }
 
+/// Artist tags on e621 that describe the post instead of naming an artist.
+#[cfg(feature = "e621")]
+pub const E621_META_ARTISTS: &[&str] = &[
+    "anonymous_artist",
+    "avoid_posting",
+    "conditional_dnp",
+    "epilepsy_warning",
+    "sound_warning",
+    "third-party_edit",
+    "unknown_artist",
+    "unknown_artist_signature",
+];
+
+/// Tag categories on e621 that describe what is in a post.
+#[cfg(feature = "e621")]
+const E621_TAG_CATEGORIES: &[&str] = &["copyright", "character", "species", "general"];
+
+#[cfg(feature = "e621")]
 impl E621 {
-    pub fn new(host: E621Host, login: String, api_key: String) -> Self {
+    pub fn new(host: E621Host, login: String, api_key: String, proxies: &Proxies) -> Self {
         Self {
-            show: regex::Regex::new(&format!(r"(?:https?://)?{}/(?:post/show/|posts/)(?P<id>\d+)(?:/(?P<tags>.+))?", host.host())).unwrap(),
+            show: regex::Regex::new(&format!(r"(?i)(?:https?://)?{}/(?:post/show/|posts/)(?P<id>\d+)(?:/(?P<tags>.+))?", host.host())).unwrap(),
             data: regex::Regex::new(&format!(r"(?:https?://)?(?:static\d+\.{})/data/(?:(?P<modifier>sample|preview)/)?[0-9a-f]{{2}}/[0-9a-f]{{2}}/(?P<md5>[0-9a-f]{{32}})\.(?P<ext>.+)", host.host())).unwrap(),
-            pool: regex::Regex::new(&format!(r"(?:https?://)?{}/pools/(?P<id>\d+)(?:/(?P<tags>.+))?", host.host())).unwrap(),
+            pool: regex::Regex::new(&format!(r"(?i)(?:https?://)?{}/pools/(?P<id>\d+)(?:/(?P<tags>.+))?", host.host())).unwrap(),
+            tags: regex::Regex::new(&format!(r"(?i)(?:https?://)?{}/posts/?\?(?:\S*&)?tags=(?P<tags>[^&#\s]+)", host.host())).unwrap(),
 
-            client: reqwest::Client::builder().user_agent(USER_AGENT).build().unwrap(),
+            client: proxies.client_builder(host.name()).build().unwrap(),
 
+            endpoint: format!("https://{}", host.host()),
             site: host,
             auth: (login, api_key),
         }
     }
 
-    fn get_urls(resp: E621Resp) -> Option<E621Data> {
-        match resp {
-            E621Resp {
-                post:
-                    Some(E621Post {
-                        id,
-                        file:
-                            E621PostFile {
-                                ext: Some(file_ext),
-                                url: Some(file_url),
-                                ..
-                            },
-                        preview:
-                            E621PostPreview {
-                                url: Some(preview_url),
-                            },
+    /// Send API requests to a different base URL, like a local mirror.
+    pub fn with_endpoint(self, endpoint: String) -> Self {
+        Self { endpoint, ..self }
+    }
+
+    fn get_urls(post: E621Post) -> Option<E621Data> {
+        match post {
+            E621Post {
+                id,
+                file:
+                    E621PostFile {
+                        ext: Some(file_ext),
+                        url: Some(file_url),
                         ..
-                    }),
+                    },
+                preview:
+                    E621PostPreview {
+                        url: Some(preview_url),
+                    },
+                rating,
+                mut tags,
+                pools,
+                relationships,
             } => Some(E621Data {
                 id,
                 file_url,
                 file_ext,
                 preview_url,
+                rating: rating.as_deref().and_then(Self::parse_rating),
+                artists: Self::parse_artists(tags.remove("artist").unwrap_or_default()),
+                tags: E621_TAG_CATEGORIES
+                    .iter()
+                    .flat_map(|category| tags.remove(*category).unwrap_or_default())
+                    .collect(),
+                pool_ids: pools,
+                parent_id: relationships.parent_id,
+                children: relationships.children,
             }),
             _ => None,
         }
     }
 
-    /// Load the 10 most recent posts from a pool at a given URL.
-    #[tracing::instrument(skip(self, url), fields(pool_id))]
-    async fn get_pool(&mut self, url: &str) -> anyhow::Result<Option<Vec<PostInfo>>> {
-        let captures = self.pool.captures(url).unwrap();
-        let id = &captures["id"];
-        tracing::Span::current().record("pool_id", &id);
+    /// Remove artist tags that don't name an artist, like `conditional_dnp`.
+    fn parse_artists(mut artists: Vec<String>) -> Vec<String> {
+        artists.retain(|artist| !E621_META_ARTISTS.contains(&artist.as_str()));
+        artists
+    }
+
+    /// Load the names of pools a post is part of.
+    ///
+    /// Pools are only extra information, so they are skipped if they can't
+    /// be loaded.
+    async fn pools(&self, ids: &[i32]) -> Vec<PostPool> {
+        if ids.is_empty() {
+            return vec![];
+        }
 
+        let ids: Vec<String> = ids.iter().map(ToString::to_string).collect();
+        let endpoint = format!("{}/pools.json?search[id]={}", self.endpoint, ids.join(","));
+
+        let pools: Vec<E621PoolName> = match self.load(&endpoint).await {
+            Ok(pools) => pools,
+            Err(err) => {
+                tracing::warn!("unable to load e621 pools: {:?}", err);
+                return vec![];
+            }
+        };
+
+        pools
+            .into_iter()
+            .map(|pool| PostPool {
+                name: pool.name.replace('_', " "),
+                url: format!("https://{}/pools/{}", self.site.host(), pool.id),
+            })
+            .collect()
+    }
+
+    /// Convert an e621 rating into a FuzzySearch rating.
+    fn parse_rating(rating: &str) -> Option<fuzzysearch::Rating> {
+        match rating {
+            "s" => Some(fuzzysearch::Rating::General),
+            "q" => Some(fuzzysearch::Rating::Mature),
+            "e" => Some(fuzzysearch::Rating::Adult),
+            _ => None,
+        }
+    }
+
+    /// Load the 10 most recent posts from a pool.
+    #[tracing::instrument(skip(self), fields(pool_id = id))]
+    async fn get_pool(&mut self, id: &str) -> anyhow::Result<Option<Vec<PostInfo>>> {
         tracing::trace!("Loading e621 pool");
 
-        let endpoint = format!("https://{}/pools/{}.json", self.site.host(), id);
+        let endpoint = format!("{}/pools/{}.json", self.endpoint, id);
         let resp: E621Pool = self.load(&endpoint).await?;
 
         tracing::trace!(count = resp.post_count, "Discovered e621 pool items");
@@ -390,27 +1085,12 @@ impl E621 {
         for post_id in resp.post_ids.iter().rev().take(10).rev() {
             tracing::trace!(post_id, "Loading e621 post as part of pool");
 
-            let url = format!("https://{}/posts/{}.json", self.site.host(), post_id);
+            let url = format!("{}/posts/{}.json", self.endpoint, post_id);
             let resp: E621Resp = self.load(&url).await?;
 
-            let E621Data {
-                id,
-                file_url,
-                file_ext,
-                preview_url,
-            } = match Self::get_urls(resp) {
-                Some(vals) => vals,
-                None => continue,
-            };
-
-            posts.push(PostInfo {
-                file_type: file_ext,
-                url: file_url,
-                thumb: Some(preview_url),
-                source_link: Some(format!("https://{}/posts/{}", self.site.host(), id)),
-                site_name: self.name(),
-                ..Default::default()
-            });
+            if let Some(data) = resp.post.and_then(Self::get_urls) {
+                posts.push(self.post_info(data));
+            }
         }
 
         if posts.is_empty() {
@@ -420,118 +1100,268 @@ impl E621 {
         }
     }
 
+    /// Convert loaded post data into a post.
+    ///
+    /// Pool names need another request, so they're only added when loading a
+    /// single post.
+    fn post_info(&self, data: E621Data) -> PostInfo {
+        let post_link = |id| format!("https://{}/posts/{}", self.site.host(), id);
+
+        PostInfo {
+            file_type: data.file_ext,
+            url: data.file_url,
+            thumb: Some(data.preview_url),
+            source_link: Some(post_link(data.id)),
+            site_name: self.name().into(),
+            rating: data.rating,
+            artists: data.artists,
+            tags: data.tags,
+            parent_link: data.parent_id.map(post_link),
+            child_links: data.children.into_iter().map(post_link).collect(),
+            ..Default::default()
+        }
+    }
+
     /// Load arbitrary JSON data from a given URL.
     async fn load<T>(&self, url: &str) -> anyhow::Result<T>
     where
         T: serde::de::DeserializeOwned,
     {
+        count_http_call();
         let resp = self
             .client
             .get(url)
             .basic_auth(&self.auth.0, Some(&self.auth.1))
             .send()
             .await
-            .context("unable to request e621 api")?
+            .context("unable to request e621 api")?;
+
+        check_rate_limit("e621", resp)?
             .json()
             .await
-            .context("unable to parse e621 json")?;
-
-        Ok(resp)
+            .context("unable to parse e621 json")
     }
 }
 
+#[cfg(feature = "e621")]
 #[async_trait]
 impl Site for E621 {
     fn name(&self) -> &'static str {
         self.site.name()
     }
 
+    fn test_url(&self) -> Option<&'static str> {
+        match self.site {
+            E621Host::E621 => Some("https://e621.net/posts/2785411"),
+            E621Host::E926 => Some("https://e926.net/posts/2785411"),
+        }
+    }
+
     fn url_id(&self, url: &str) -> Option<String> {
         let captures = match self.show.captures(url) {
             Some(captures) => captures,
             _ => return None,
         };
 
-        let sub_id = &captures["id"];
+        let sub_id: i32 = captures["id"].parse().ok()?;
 
         Some(format!("{}-{}", self.site.name(), sub_id))
     }
 
-    async fn url_supported(&mut self, url: &str) -> bool {
-        self.show.is_match(url) || self.data.is_match(url) || self.pool.is_match(url)
+    async fn match_url(&mut self, url: &str) -> Option<UrlMatch> {
+        let kind = if let Some(captures) = self.show.captures(url) {
+            MatchKind::E621Post {
+                id: captures["id"].to_string(),
+            }
+        } else if let Some(captures) = self.data.captures(url) {
+            MatchKind::E621File {
+                md5: captures["md5"].to_string(),
+            }
+        } else if let Some(captures) = self.pool.captures(url) {
+            MatchKind::E621Pool {
+                id: captures["id"].to_string(),
+            }
+        } else {
+            return None;
+        };
+
+        Some(UrlMatch::new(self, url, kind))
     }
 
     async fn get_images(
         &mut self,
-        _user_id: i64,
-        url: &str,
+        _credentials: &dyn Credentials,
+        url: &UrlMatch,
     ) -> anyhow::Result<Option<Vec<PostInfo>>> {
-        let endpoint = if self.show.is_match(url) {
-            let captures = self.show.captures(url).unwrap();
-            let id = &captures["id"];
-
-            format!("https://{}/posts/{}.json", self.site.host(), id)
-        } else if self.data.is_match(url) {
-            let captures = self.data.captures(url).unwrap();
-            let md5 = &captures["md5"];
-
-            format!("https://{}/posts.json?md5={}", self.site.host(), md5)
-        } else {
-            return self.get_pool(url).await;
+        let endpoint = match &url.kind {
+            MatchKind::E621Post { id } => {
+                format!("{}/posts/{}.json", self.endpoint, id)
+            }
+            MatchKind::E621File { md5 } => {
+                format!("{}/posts.json?md5={}", self.endpoint, md5)
+            }
+            MatchKind::E621Pool { id } => {
+                return self.get_pool(id).await;
+            }
+            _ => return Err(url.wrong_site(self.name())),
         };
 
         let resp: E621Resp = self.load(&endpoint).await?;
 
-        let E621Data {
-            id,
-            file_url,
-            file_ext,
-            preview_url,
-        } = match Self::get_urls(resp) {
-            Some(vals) => vals,
+        let data = match resp.post.and_then(Self::get_urls) {
+            Some(data) => data,
             None => return Ok(None),
         };
 
+        let pools = self.pools(&data.pool_ids).await;
+
         Ok(Some(vec![PostInfo {
-            file_type: file_ext,
-            url: file_url,
-            thumb: Some(preview_url),
-            source_link: Some(format!("https://{}/posts/{}", self.site.host(), id)),
-            site_name: self.name(),
-            ..Default::default()
+            pools,
+            ..self.post_info(data)
         }]))
     }
+
+    async fn latest_posts(&mut self, url: &str) -> anyhow::Result<Option<Vec<PostInfo>>> {
+        let captures = match self.tags.captures(url) {
+            Some(captures) => captures,
+            None => return Ok(None),
+        };
+
+        let endpoint = format!(
+            "{}/posts.json?tags={}&limit={}",
+            self.endpoint, &captures["tags"], LATEST_POSTS
+        );
+        let resp: E621Posts = self.load(&endpoint).await?;
+
+        Ok(Some(
+            resp.posts
+                .into_iter()
+                .filter_map(Self::get_urls)
+                .map(|data| self.post_info(data))
+                .collect(),
+        ))
+    }
+}
+
+/// The parts of Twitter's API used to load Tweets.
+#[cfg(feature = "twitter")]
+#[async_trait]
+pub trait TwitterApi {
+    /// Load a single Tweet.
+    async fn show(
+        &self,
+        token: &egg_mode::Token,
+        id: u64,
+    ) -> anyhow::Result<egg_mode::tweet::Tweet>;
+
+    /// Load the newest page of a user's Tweets, only including Tweets newer
+    /// than `since_id` if given.
+    async fn timeline(
+        &self,
+        token: &egg_mode::Token,
+        user: egg_mode::user::UserID,
+        with_replies: bool,
+        since_id: Option<u64>,
+    ) -> anyhow::Result<Vec<egg_mode::tweet::Tweet>>;
+}
+
+/// Loads Tweets from Twitter's API.
+#[cfg(feature = "twitter")]
+struct EggMode;
+
+#[cfg(feature = "twitter")]
+#[async_trait]
+impl TwitterApi for EggMode {
+    async fn show(
+        &self,
+        token: &egg_mode::Token,
+        id: u64,
+    ) -> anyhow::Result<egg_mode::tweet::Tweet> {
+        count_http_call();
+        let tweet = egg_mode::tweet::show(id, token)
+            .await
+            .context("unable to load tweet")?;
+
+        Ok(tweet.response)
+    }
+
+    async fn timeline(
+        &self,
+        token: &egg_mode::Token,
+        user: egg_mode::user::UserID,
+        with_replies: bool,
+        since_id: Option<u64>,
+    ) -> anyhow::Result<Vec<egg_mode::tweet::Tweet>> {
+        let timeline =
+            egg_mode::tweet::user_timeline(user, with_replies, false, token).with_page_size(200);
+
+        count_http_call();
+        let tweets = timeline
+            .call(since_id, None)
+            .await
+            .context("unable to load timeline")?;
+
+        Ok(tweets.response)
+    }
 }
 
 /// A loader for Tweets.
 ///
 /// It can use user credentials to get Tweets from locked accounts.
+#[cfg(feature = "twitter")]
 pub struct Twitter {
     matcher: regex::Regex,
     consumer: egg_mode::KeyPair,
     token: egg_mode::Token,
-    conn: sqlx::Pool<sqlx::Postgres>,
+    api: Box<dyn TwitterApi + Send + Sync>,
+    /// Most replies continuing a thread to load media from.
+    thread_limit: usize,
 }
 
+#[cfg(feature = "twitter")]
 impl Twitter {
-    pub async fn new(
-        consumer_key: String,
-        consumer_secret: String,
-        conn: sqlx::Pool<sqlx::Postgres>,
-    ) -> Self {
+    pub async fn new(consumer_key: String, consumer_secret: String) -> Self {
         use egg_mode::KeyPair;
 
         let consumer = KeyPair::new(consumer_key, consumer_secret);
         let token = egg_mode::auth::bearer_token(&consumer).await.unwrap();
 
+        Self::with_token(consumer, token, Box::new(EggMode))
+    }
+
+    /// Create the loader without authenticating, loading Tweets from another
+    /// API, like recorded responses. User credentials are never used.
+    pub fn with_api(api: Box<dyn TwitterApi + Send + Sync>) -> Self {
+        Self::with_token(
+            egg_mode::KeyPair::new("", ""),
+            egg_mode::Token::Bearer(String::new()),
+            api,
+        )
+    }
+
+    fn with_token(
+        consumer: egg_mode::KeyPair,
+        token: egg_mode::Token,
+        api: Box<dyn TwitterApi + Send + Sync>,
+    ) -> Self {
         Self {
             matcher: regex::Regex::new(
-                r"https://(?:mobile\.)?twitter.com/(?P<screen_name>\w+)(?:/status/(?P<id>\d+))?",
+                r"(?i)https?://(?:mobile\.|www\.)?twitter\.com/(?P<screen_name>\w+)(?:/status/(?P<id>\d+))?",
             )
             .unwrap(),
             consumer,
             token,
-            conn,
+            api,
+            thread_limit: 0,
+        }
+    }
+
+    /// Also load media from up to `thread_limit` replies the author made to
+    /// continue a Tweet as a thread, like comics posted a page at a time.
+    pub fn with_thread_limit(self, thread_limit: usize) -> Self {
+        Self {
+            thread_limit,
+            ..self
         }
     }
 
@@ -541,23 +1371,29 @@ impl Twitter {
     async fn get_media(
         &self,
         token: &egg_mode::Token,
-        captures: &regex::Captures<'_>,
+        screen_name: &str,
+        id: Option<u64>,
     ) -> Option<(
         Box<egg_mode::user::TwitterUser>,
         Vec<egg_mode::entities::MediaEntity>,
     )> {
-        if let Some(Ok(id)) = captures.name("id").map(|id| id.as_str().parse::<u64>()) {
-            let tweet = egg_mode::tweet::show(id, token).await.ok()?.response;
+        if let Some(id) = id {
+            let tweet = self.api.show(token, id).await.ok()?;
 
             let user = tweet.user?;
-            let media = tweet.extended_entities?.media;
+            let mut media = tweet.extended_entities?.media;
+
+            if self.thread_limit > 0 {
+                media.extend(self.thread_media(token, user.id, tweet.id).await);
+            }
 
             Some((user, media))
         } else {
-            let user = captures["screen_name"].to_owned();
-            let timeline =
-                egg_mode::tweet::user_timeline(user, false, false, token).with_page_size(200);
-            let (_timeline, feed) = timeline.start().await.ok()?;
+            let feed = self
+                .api
+                .timeline(token, screen_name.to_owned().into(), false, None)
+                .await
+                .ok()?;
 
             let user = feed.iter().next()?.user.as_ref()?.to_owned();
 
@@ -571,55 +1407,116 @@ impl Twitter {
             Some((user, media))
         }
     }
+
+    /// Get the media from replies the author made to continue a thread.
+    ///
+    /// Replies are found in the author's most recent Tweets, so threads
+    /// followed by many other Tweets may only be partially loaded.
+    async fn thread_media(
+        &self,
+        token: &egg_mode::Token,
+        user_id: u64,
+        head_id: u64,
+    ) -> Vec<egg_mode::entities::MediaEntity> {
+        let tweets = match self
+            .api
+            .timeline(token, user_id.into(), true, Some(head_id))
+            .await
+        {
+            Ok(tweets) => tweets,
+            Err(err) => {
+                tracing::warn!("unable to load twitter thread: {:?}", err);
+                return vec![];
+            }
+        };
+
+        let mut media = vec![];
+        let mut current_id = head_id;
+
+        for _ in 0..self.thread_limit {
+            let reply = tweets
+                .iter()
+                .filter(|tweet| {
+                    tweet.in_reply_to_status_id == Some(current_id)
+                        && tweet.in_reply_to_user_id == Some(user_id)
+                })
+                .min_by_key(|tweet| tweet.id);
+
+            let reply = match reply {
+                Some(reply) => reply,
+                None => break,
+            };
+
+            if let Some(entities) = &reply.extended_entities {
+                media.extend(entities.media.iter().cloned());
+            }
+
+            current_id = reply.id;
+        }
+
+        tracing::debug!(count = media.len(), "loaded media from twitter thread");
+
+        media
+    }
 }
 
+#[cfg(feature = "twitter")]
 #[async_trait]
 impl Site for Twitter {
     fn name(&self) -> &'static str {
         "Twitter"
     }
 
+    fn test_url(&self) -> Option<&'static str> {
+        Some("https://twitter.com/TheEllenShow/status/440322224407314432")
+    }
+
     fn url_id(&self, url: &str) -> Option<String> {
         let captures = match self.matcher.captures(url) {
             Some(captures) => captures,
             _ => return None,
         };
 
-        // Get the ID of the Tweet if possible, otherwise use the screen name.
-        let id = captures
-            .name("id")
-            .map(|id| id.as_str())
-            .unwrap_or(&captures["screen_name"]);
+        // Get the ID of the Tweet if possible, otherwise use the screen name,
+        // which isn't case sensitive.
+        let id = match captures.name("id") {
+            Some(id) => id.as_str().parse::<u64>().ok()?.to_string(),
+            None => captures["screen_name"].to_lowercase(),
+        };
 
         Some(format!("Twitter-{}", id))
     }
 
-    async fn url_supported(&mut self, url: &str) -> bool {
-        self.matcher.is_match(url)
+    async fn match_url(&mut self, url: &str) -> Option<UrlMatch> {
+        let captures = self.matcher.captures(url)?;
+
+        let kind = MatchKind::Twitter {
+            screen_name: captures["screen_name"].to_string(),
+            id: captures.name("id").and_then(|id| id.as_str().parse().ok()),
+        };
+
+        Some(UrlMatch::new(self, url, kind))
     }
 
     async fn get_images(
         &mut self,
-        user_id: i64,
-        url: &str,
+        credentials: &dyn Credentials,
+        url: &UrlMatch,
     ) -> anyhow::Result<Option<Vec<PostInfo>>> {
-        let captures = self.matcher.captures(url).unwrap();
-
-        tracing::trace!(user_id, "attempting to find saved credentials",);
-
-        let account = TwitterModel::get_account(&self.conn, user_id)
-            .await
-            .context("unable to query twitter account")?;
+        let (screen_name, id) = match &url.kind {
+            MatchKind::Twitter { screen_name, id } => (screen_name, *id),
+            _ => return Err(url.wrong_site(self.name())),
+        };
 
-        let token = match account {
-            Some(account) => egg_mode::Token::Access {
+        let token = match credentials.twitter().await? {
+            Some(access) => egg_mode::Token::Access {
                 consumer: self.consumer.clone(),
-                access: egg_mode::KeyPair::new(account.consumer_key, account.consumer_secret),
+                access: egg_mode::KeyPair::new(access.key, access.secret),
             },
             _ => self.token.clone(),
         };
 
-        let (user, media) = match self.get_media(&token, &captures).await {
+        let (user, media) = match self.get_media(&token, screen_name, id).await {
             None => return Ok(None),
             Some(data) => data,
         };
@@ -635,7 +1532,7 @@ impl Site for Twitter {
                         source_link: Some(item.expanded_url),
                         personal: user.protected,
                         title: Some(user.screen_name.clone()),
-                        site_name: self.name(),
+                        site_name: self.name().into(),
                         ..Default::default()
                     }),
                     None => Some(PostInfo {
@@ -644,7 +1541,7 @@ impl Site for Twitter {
                         thumb: Some(format!("{}:thumb", item.media_url_https.clone())),
                         source_link: Some(item.expanded_url),
                         personal: user.protected,
-                        site_name: self.name(),
+                        site_name: self.name().into(),
                         ..Default::default()
                     }),
                 })
@@ -654,6 +1551,7 @@ impl Site for Twitter {
 }
 
 /// Find the video in a Tweet with the highest bitrate.
+#[cfg(feature = "twitter")]
 fn get_best_video(media: &egg_mode::entities::MediaEntity) -> Option<&str> {
     let video_info = match &media.video_info {
         Some(video_info) => video_info,
@@ -668,39 +1566,355 @@ fn get_best_video(media: &egg_mode::entities::MediaEntity) -> Option<&str> {
     Some(&highest_bitrate.url)
 }
 
+/// Cookies and page content from solving a challenge page.
+#[cfg(feature = "furaffinity")]
+pub struct ChallengeSolution {
+    /// The page that was behind the challenge.
+    pub body: String,
+    pub cookies: HashMap<String, String>,
+    /// User agent the cookies are valid for, if they are tied to one.
+    pub user_agent: Option<String>,
+}
+
+/// Something that can get past challenge pages, like Cloudflare's browser
+/// checks, for sites that use them.
+#[cfg(feature = "furaffinity")]
+#[async_trait]
+pub trait ChallengeSolver {
+    /// Load a URL, sending the given cookies.
+    async fn solve(
+        &self,
+        url: &str,
+        cookies: &HashMap<String, String>,
+    ) -> anyhow::Result<ChallengeSolution>;
+}
+
+/// Solves challenges with a [FlareSolverr](https://github.com/FlareSolverr/FlareSolverr)
+/// instance.
+#[cfg(feature = "furaffinity")]
+pub struct FlareSolverr {
+    client: reqwest::Client,
+    endpoint: String,
+}
+
+#[cfg(feature = "furaffinity")]
+#[derive(Serialize)]
+struct FlareSolverrRequest<'a> {
+    cmd: &'static str,
+    url: &'a str,
+    #[serde(rename = "maxTimeout")]
+    max_timeout: u64,
+    cookies: Vec<FlareSolverrCookie>,
+}
+
+#[cfg(feature = "furaffinity")]
+#[derive(Serialize, Deserialize)]
+struct FlareSolverrCookie {
+    name: String,
+    value: String,
+}
+
+#[cfg(feature = "furaffinity")]
+#[derive(Deserialize)]
+struct FlareSolverrResponse {
+    status: String,
+    message: String,
+    solution: Option<FlareSolverrSolution>,
+}
+
+#[cfg(feature = "furaffinity")]
+#[derive(Deserialize)]
+struct FlareSolverrSolution {
+    response: String,
+    cookies: Vec<FlareSolverrCookie>,
+    #[serde(rename = "userAgent")]
+    user_agent: Option<String>,
+}
+
+#[cfg(feature = "furaffinity")]
+impl FlareSolverr {
+    /// Milliseconds FlareSolverr may spend on a challenge.
+    const MAX_TIMEOUT: u64 = 60_000;
+
+    pub fn new(endpoint: String) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            endpoint,
+        }
+    }
+}
+
+#[cfg(feature = "furaffinity")]
+#[async_trait]
+impl ChallengeSolver for FlareSolverr {
+    async fn solve(
+        &self,
+        url: &str,
+        cookies: &HashMap<String, String>,
+    ) -> anyhow::Result<ChallengeSolution> {
+        let request = FlareSolverrRequest {
+            cmd: "request.get",
+            url,
+            max_timeout: Self::MAX_TIMEOUT,
+            cookies: cookies
+                .iter()
+                .map(|(name, value)| FlareSolverrCookie {
+                    name: name.to_owned(),
+                    value: value.to_owned(),
+                })
+                .collect(),
+        };
+
+        count_http_call();
+        let resp: FlareSolverrResponse = self
+            .client
+            .post(&format!("{}/v1", self.endpoint.trim_end_matches('/')))
+            .json(&request)
+            .send()
+            .await
+            .context("unable to request flaresolverr")?
+            .json()
+            .await
+            .context("flaresolverr returned invalid json")?;
+
+        let solution = match resp.solution {
+            Some(solution) if resp.status == "ok" => solution,
+            _ => anyhow::bail!(
+                "flaresolverr was unable to solve challenge: {}",
+                resp.message
+            ),
+        };
+
+        Ok(ChallengeSolution {
+            body: solution.response,
+            cookies: solution
+                .cookies
+                .into_iter()
+                .map(|cookie| (cookie.name, cookie.value))
+                .collect(),
+            user_agent: solution.user_agent,
+        })
+    }
+}
+
 /// A loader for FurAffinity.
 ///
 /// It converts direct image URLs back into submission URLs using FuzzySearch.
+#[cfg(feature = "furaffinity")]
 pub struct FurAffinity {
-    cookies: std::collections::HashMap<String, String>,
+    jar: std::sync::Arc<reqwest::cookie::Jar>,
+    /// User agent to use instead of the default, because cookies from a
+    /// solved challenge only work with the user agent that solved it.
+    user_agent: std::sync::RwLock<Option<String>>,
+    solver: Option<Box<dyn ChallengeSolver + Send + Sync>>,
+    sessions: Box<dyn SessionStore>,
     fapi: fuzzysearch::FuzzySearch,
     submission: scraper::Selector,
+    submission_title: scraper::Selector,
+    submission_artist: scraper::Selector,
+    submission_rating: scraper::Selector,
+    og_title: scraper::Selector,
+    gallery_item: scraper::Selector,
+    logged_in: scraper::Selector,
     client: reqwest::Client,
+    endpoint: String,
     matcher: regex::Regex,
+    gallery: regex::Regex,
 }
 
+#[cfg(feature = "furaffinity")]
 impl FurAffinity {
-    pub fn new(cookies: (String, String), util_api: String) -> Self {
-        let mut c = std::collections::HashMap::new();
+    const SITE_URL: &'static str = "https://www.furaffinity.net/";
+    /// Submission loaded to check if the cookies are still logged in. Every
+    /// page includes the user's name when logged in, so it only needs to be
+    /// a page that doesn't redirect.
+    const LOGIN_CHECK_URL: &'static str = "https://www.furaffinity.net/view/16437648/";
+
+    /// Create the loader, restoring cookies saved from earlier requests. The
+    /// configured `a` and `b` cookies are always used over saved ones.
+    pub async fn new(
+        cookies: (String, String),
+        util_api: String,
+        proxies: &Proxies,
+        solver: Option<Box<dyn ChallengeSolver + Send + Sync>>,
+        sessions: Box<dyn SessionStore>,
+    ) -> Self {
+        let session = match sessions.get("FurAffinity").await {
+            Ok(session) => session.unwrap_or_default(),
+            Err(err) => {
+                tracing::warn!("unable to load furaffinity session: {:?}", err);
+                Session::default()
+            }
+        };
 
-        c.insert("a".into(), cookies.0);
-        c.insert("b".into(), cookies.1);
+        let jar = std::sync::Arc::new(reqwest::cookie::Jar::default());
+        for (name, value) in &session.cookies {
+            Self::add_cookie(&jar, name, value);
+        }
+        Self::add_cookie(&jar, "a", &cookies.0);
+        Self::add_cookie(&jar, "b", &cookies.1);
 
         Self {
-            cookies: c,
-            fapi: fuzzysearch::FuzzySearch::new(util_api),
-            submission: scraper::Selector::parse("#submissionImg").unwrap(),
-            client: reqwest::Client::builder()
-                .user_agent(USER_AGENT)
+            client: proxies
+                .client_builder("FurAffinity")
+                .cookie_provider(jar.clone())
                 .build()
                 .unwrap(),
+            endpoint: "https://www.furaffinity.net".to_string(),
+            jar,
+            user_agent: std::sync::RwLock::new(session.user_agent),
+            solver,
+            sessions,
+            fapi: fuzzysearch::FuzzySearch::new(util_api),
+            submission: scraper::Selector::parse("#submissionImg").unwrap(),
+            submission_title: scraper::Selector::parse(".submission-title h2").unwrap(),
+            submission_artist: scraper::Selector::parse(
+                ".submission-id-sub-container a[href^='/user/']",
+            )
+            .unwrap(),
+            submission_rating: scraper::Selector::parse(".rating .rating-box").unwrap(),
+            og_title: scraper::Selector::parse("meta[property='og:title']").unwrap(),
+            gallery_item: scraper::Selector::parse("figure[id^='sid-']").unwrap(),
+            logged_in: scraper::Selector::parse("#my-username, .loggedin_user_avatar").unwrap(),
             matcher: regex::Regex::new(
-                r#"(?:https?://)?(?:(?:www\.)?furaffinity\.net/(?:view|full)/(?P<id>\d+)/?|(?:d\.furaffinity\.net|d\.facdn\.net)/art/\w+/(?P<file_id>\d+)/(?P<file_name>\S+))"#,
+                r#"(?i)(?:https?://)?(?:(?:www\.)?furaffinity\.net/(?:view|full)/(?P<id>\d+)/?|(?:d\.furaffinity\.net|d\.facdn\.net)/art/\w+/(?P<file_id>\d+)/(?P<file_name>\S+))"#,
+            )
+            .unwrap(),
+            gallery: regex::Regex::new(
+                r#"(?i)(?:https?://)?(?:www\.)?furaffinity\.net/(?:gallery|user)/(?P<user>[^/\s]+)/?$"#,
             )
             .unwrap(),
         }
     }
 
+    /// Load pages from a different base URL, like a local mirror.
+    pub fn with_endpoint(self, endpoint: String) -> Self {
+        Self { endpoint, ..self }
+    }
+
+    fn add_cookie(jar: &reqwest::cookie::Jar, name: &str, value: &str) {
+        let url = reqwest::Url::parse(Self::SITE_URL).unwrap();
+        jar.add_cookie_str(
+            &format!("{}={}; Domain=furaffinity.net; Path=/", name, value),
+            &url,
+        );
+    }
+
+    /// Get every cookie that would be sent to FurAffinity.
+    fn current_cookies(&self) -> HashMap<String, String> {
+        use reqwest::cookie::CookieStore;
+
+        let url = reqwest::Url::parse(Self::SITE_URL).unwrap();
+
+        let cookies = match self.jar.cookies(&url) {
+            Some(cookies) => cookies,
+            None => return HashMap::new(),
+        };
+
+        cookies
+            .to_str()
+            .unwrap_or_default()
+            .split("; ")
+            .filter_map(|cookie| {
+                let mut parts = cookie.splitn(2, '=');
+                Some((parts.next()?.to_string(), parts.next()?.to_string()))
+            })
+            .collect()
+    }
+
+    /// Save the current cookies and user agent so they're used after a
+    /// restart.
+    async fn save_session(&self) {
+        let session = Session {
+            cookies: self.current_cookies(),
+            user_agent: self.user_agent.read().unwrap().clone(),
+        };
+
+        if let Err(err) = self.sessions.save(self.name(), &session).await {
+            tracing::warn!("unable to save furaffinity session: {:?}", err);
+        }
+    }
+
+    /// Check if a response is a Cloudflare challenge instead of the page.
+    fn is_challenge(resp: &reqwest::Response) -> bool {
+        let from_cloudflare = resp
+            .headers()
+            .get(header::SERVER)
+            .map(|server| server.as_bytes().eq_ignore_ascii_case(b"cloudflare"))
+            .unwrap_or(false);
+
+        from_cloudflare && matches!(resp.status().as_u16(), 403 | 503)
+    }
+
+    /// Load a page from FurAffinity, solving a challenge page if one was
+    /// returned and a solver is configured. Cookies are saved whenever they
+    /// change.
+    async fn fetch_page(&self, url: &str) -> anyhow::Result<String> {
+        let mut req = self.client.get(url);
+        if let Some(user_agent) = self.user_agent.read().unwrap().as_deref() {
+            req = req.header(header::USER_AGENT, user_agent);
+        }
+
+        count_http_call();
+        let resp = req
+            .send()
+            .await
+            .context("unable to request furaffinity page")?;
+        let resp = check_rate_limit("FurAffinity", resp)?;
+
+        if !Self::is_challenge(&resp) {
+            let set_cookies = resp.headers().contains_key(header::SET_COOKIE);
+
+            let text = resp
+                .text()
+                .await
+                .context("unable to get text from furaffinity page")?;
+
+            if set_cookies {
+                self.save_session().await;
+            }
+
+            return Ok(text);
+        }
+
+        let solver = match &self.solver {
+            Some(solver) => solver,
+            None => anyhow::bail!("furaffinity returned a challenge page"),
+        };
+
+        tracing::info!("solving furaffinity challenge");
+
+        let solution = solver
+            .solve(url, &self.current_cookies())
+            .await
+            .context("unable to solve furaffinity challenge")?;
+
+        for (name, value) in &solution.cookies {
+            Self::add_cookie(&self.jar, name, value);
+        }
+        *self.user_agent.write().unwrap() = solution.user_agent;
+
+        self.save_session().await;
+
+        Ok(solution.body)
+    }
+
+    /// Load the IDs of the newest submissions in a user's gallery.
+    async fn gallery_ids(&self, user: &str) -> anyhow::Result<Vec<i32>> {
+        let resp = self
+            .fetch_page(&format!("{}/gallery/{}/", self.endpoint, user))
+            .await
+            .context("unable to load furaffinity gallery")?;
+
+        let body = scraper::Html::parse_document(&resp);
+
+        Ok(body
+            .select(&self.gallery_item)
+            .filter_map(|figure| figure.value().id()?.strip_prefix("sid-")?.parse().ok())
+            .take(LATEST_POSTS)
+            .collect())
+    }
+
     /// Attempt to resolve a direct image URL into a submission using
     /// FuzzySearch.
     async fn load_direct_url(&self, filename: &str, url: &str) -> anyhow::Result<Option<PostInfo>> {
@@ -715,7 +1929,7 @@ impl FurAffinity {
                 return Ok(Some(PostInfo {
                     file_type: ext.to_string(),
                     url: url.to_owned(),
-                    site_name: self.name(),
+                    site_name: self.name().into(),
                     ..Default::default()
                 }));
             }
@@ -730,32 +1944,16 @@ impl FurAffinity {
             file_type: ext.to_string(),
             url: sub.url.clone(),
             source_link: Some(sub.url()),
-            site_name: self.name(),
+            site_name: self.name().into(),
             ..Default::default()
         }))
     }
 
-    /// Convert provided cookies into a string suitable for sending with a
-    /// HTTP request.
-    fn stringify_cookies(&self) -> String {
-        let mut cookies = vec![];
-        for (name, value) in &self.cookies {
-            cookies.push(format!("{}={}", name, value));
-        }
-        cookies.join("; ")
-    }
-
-    async fn load_from_fa(&self, url: &str) -> anyhow::Result<Option<PostInfo>> {
+    async fn load_from_fa(&self, id: i32, url: &str) -> anyhow::Result<Option<PostInfo>> {
         let resp = self
-            .client
-            .get(url)
-            .header(header::COOKIE, self.stringify_cookies())
-            .send()
-            .await
-            .context("unable to request furaffinity submission")?
-            .text()
+            .fetch_page(&format!("{}/view/{}/", self.endpoint, id))
             .await
-            .context("unable to get text from furaffinity submission")?;
+            .context("unable to load furaffinity submission")?;
 
         let body = scraper::Html::parse_document(&resp);
         let img = match body.select(&self.submission).next() {
@@ -776,15 +1974,60 @@ impl FurAffinity {
             None => return Ok(None),
         };
 
+        let (title, artist, rating) = self.submission_details(&body);
+
         Ok(Some(PostInfo {
             file_type: ext.to_string(),
             url: image_url.clone(),
             source_link: Some(url.to_string()),
-            site_name: self.name(),
+            site_name: self.name().into(),
+            title,
+            artists: artist.into_iter().collect(),
+            rating,
             ..Default::default()
         }))
     }
 
+    /// Find a submission's title, artist, and rating on its page.
+    ///
+    /// The page's OpenGraph title, like `Title by Artist`, is used when the
+    /// title or artist can't be found in the page itself.
+    fn submission_details(
+        &self,
+        body: &scraper::Html,
+    ) -> (Option<String>, Option<String>, Option<fuzzysearch::Rating>) {
+        let text = |selector: &scraper::Selector| {
+            body.select(selector)
+                .next()
+                .map(|elem| elem.text().collect::<String>().trim().to_string())
+                .filter(|text| !text.is_empty())
+        };
+
+        // Usernames can't contain spaces, so the last " by " separates the
+        // title from the artist.
+        let og_title = body
+            .select(&self.og_title)
+            .next()
+            .and_then(|elem| elem.value().attr("content"))
+            .and_then(|content| content.rsplit_once(" by "))
+            .map(|(title, artist)| (title.to_string(), artist.to_string()));
+
+        let title = text(&self.submission_title)
+            .or_else(|| og_title.as_ref().map(|(title, _artist)| title.clone()));
+        let artist =
+            text(&self.submission_artist).or_else(|| og_title.map(|(_title, artist)| artist));
+
+        let rating =
+            text(&self.submission_rating).and_then(|rating| match rating.to_lowercase().as_str() {
+                "general" => Some(fuzzysearch::Rating::General),
+                "mature" => Some(fuzzysearch::Rating::Mature),
+                "adult" => Some(fuzzysearch::Rating::Adult),
+                _ => None,
+            });
+
+        (title, artist, rating)
+    }
+
     async fn load_from_fuzzy(&self, id: i32) -> anyhow::Result<Option<PostInfo>> {
         self.fapi
             .lookup_id(id)
@@ -795,7 +2038,7 @@ impl FurAffinity {
                         file_type: get_file_ext(&file.filename)?.to_string(),
                         url: file.url.clone(),
                         source_link: Some(file.url()),
-                        site_name: self.name(),
+                        site_name: self.name().into(),
                         ..Default::default()
                     })
                 })
@@ -814,7 +2057,7 @@ impl FurAffinity {
         };
 
         let fuzzy = self.load_from_fuzzy(id);
-        let fa = self.load_from_fa(url);
+        let fa = self.load_from_fa(id, url);
 
         pin_mut!(fa);
         pin_mut!(fuzzy);
@@ -842,12 +2085,17 @@ impl FurAffinity {
     }
 }
 
+#[cfg(feature = "furaffinity")]
 #[async_trait]
 impl Site for FurAffinity {
     fn name(&self) -> &'static str {
         "FurAffinity"
     }
 
+    fn test_url(&self) -> Option<&'static str> {
+        Some(Self::LOGIN_CHECK_URL)
+    }
+
     fn url_id(&self, url: &str) -> Option<String> {
         let captures = match self.matcher.captures(url) {
             Some(captures) => captures,
@@ -855,7 +2103,8 @@ impl Site for FurAffinity {
         };
 
         if let Some(sub_id) = captures.name("id") {
-            Some(format!("FurAffinity-{}", sub_id.as_str()))
+            let sub_id: i32 = sub_id.as_str().parse().ok()?;
+            Some(format!("FurAffinity-{}", sub_id))
         } else {
             captures
                 .name("file_id")
@@ -863,73 +2112,359 @@ impl Site for FurAffinity {
         }
     }
 
-    async fn url_supported(&mut self, url: &str) -> bool {
-        self.matcher.is_match(url)
+    async fn match_url(&mut self, url: &str) -> Option<UrlMatch> {
+        let captures = self.matcher.captures(url)?;
+
+        let kind = if let Some(id) = captures.name("id") {
+            MatchKind::FurAffinitySubmission {
+                id: id.as_str().parse().ok()?,
+            }
+        } else {
+            MatchKind::FurAffinityFile {
+                file_name: captures.name("file_name")?.as_str().to_string(),
+            }
+        };
+
+        Some(UrlMatch::new(self, url, kind))
     }
 
     async fn get_images(
         &mut self,
-        _user_id: i64,
-        url: &str,
+        _credentials: &dyn Credentials,
+        url: &UrlMatch,
     ) -> anyhow::Result<Option<Vec<PostInfo>>> {
-        let captures = self
-            .matcher
-            .captures(url)
-            .context("Could not capture FurAffinity URL")?;
-
-        let image = if let Some(filename) = captures.name("file_name") {
-            self.load_direct_url(filename.as_str(), url).await
-        } else if let Some(id) = captures.name("id") {
-            let id: i32 = match id.as_str().parse() {
-                Ok(id) => id,
-                Err(_err) => return Ok(None),
-            };
-            self.load_submission(id, &url).await
-        } else {
-            return Ok(None);
+        let image = match &url.kind {
+            MatchKind::FurAffinityFile { file_name } => {
+                self.load_direct_url(file_name, url.url()).await
+            }
+            MatchKind::FurAffinitySubmission { id } => self.load_submission(*id, url.url()).await,
+            _ => return Err(url.wrong_site(self.name())),
         };
 
         image.map(|sub| sub.map(|post| vec![post]))
     }
+
+    async fn latest_posts(&mut self, url: &str) -> anyhow::Result<Option<Vec<PostInfo>>> {
+        let captures = match self.gallery.captures(url) {
+            Some(captures) => captures,
+            None => return Ok(None),
+        };
+
+        let ids = self.gallery_ids(&captures["user"]).await?;
+
+        let mut posts = Vec::with_capacity(ids.len());
+        for id in ids {
+            let url = format!("https://www.furaffinity.net/view/{}/", id);
+
+            // FuzzySearch may format the link differently, so always use the
+            // same link for a submission.
+            if let Some(post) = self.load_submission(id, &url).await? {
+                posts.push(PostInfo {
+                    source_link: Some(url),
+                    ..post
+                });
+            }
+        }
+
+        Ok(Some(posts))
+    }
+
+    async fn check_login(&mut self) -> anyhow::Result<Option<bool>> {
+        let resp = self
+            .fetch_page(Self::LOGIN_CHECK_URL)
+            .await
+            .context("unable to load furaffinity login check page")?;
+
+        let body = scraper::Html::parse_document(&resp);
+
+        Ok(Some(body.select(&self.logged_in).next().is_some()))
+    }
 }
 
-/// A loader for Mastodon instances.
+/// A loader for Mastodon instances, and other fediverse software with
+/// compatible links.
 ///
-/// It holds an in-memory cache of if a URL is a Mastodon instance.
+/// It holds an in-memory cache of the software each host is running, found
+/// with nodeinfo.
+#[cfg(feature = "mastodon")]
 pub struct Mastodon {
-    instance_cache: HashMap<String, bool>,
+    instance_cache: HashMap<String, Option<FediverseSoftware>>,
     matcher: regex::Regex,
     client: reqwest::Client,
 }
 
+/// Prefix of the nodeinfo schema versions a host may link to.
+#[cfg(feature = "mastodon")]
+const NODEINFO_SCHEMA_PREFIX: &str = "http://nodeinfo.diaspora.software/ns/schema/";
+
+/// Fediverse software that posts can be loaded from.
+#[cfg(feature = "mastodon")]
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FediverseSoftware {
+    Mastodon,
+    Pleroma,
+    Misskey,
+    GoToSocial,
+}
+
+#[cfg(feature = "mastodon")]
+impl FediverseSoftware {
+    /// Get the software from the name in its nodeinfo, including forks that
+    /// kept the same API.
+    fn from_nodeinfo_name(name: &str) -> Option<Self> {
+        let software = match name.to_lowercase().as_str() {
+            "mastodon" | "hometown" | "glitchcafe" => Self::Mastodon,
+            "pleroma" | "akkoma" => Self::Pleroma,
+            "misskey" | "calckey" | "firefish" | "foundkey" | "sharkey" => Self::Misskey,
+            "gotosocial" => Self::GoToSocial,
+            _ => return None,
+        };
+
+        Some(software)
+    }
+
+    /// If the software implements Mastodon's client API.
+    fn has_mastodon_api(self) -> bool {
+        !matches!(self, Self::Misskey)
+    }
+}
+
+#[cfg(feature = "mastodon")]
+#[derive(Deserialize)]
+struct NodeInfoLinks {
+    links: Vec<NodeInfoLink>,
+}
+
+#[cfg(feature = "mastodon")]
+#[derive(Deserialize)]
+struct NodeInfoLink {
+    rel: String,
+    href: String,
+}
+
+#[cfg(feature = "mastodon")]
+#[derive(Deserialize)]
+struct NodeInfo {
+    software: NodeInfoSoftware,
+}
+
+#[cfg(feature = "mastodon")]
+#[derive(Deserialize)]
+struct NodeInfoSoftware {
+    name: String,
+}
+
+#[cfg(feature = "mastodon")]
+#[derive(Deserialize)]
+struct MisskeyNote {
+    #[serde(default)]
+    visibility: Option<String>,
+    files: Vec<MisskeyFile>,
+}
+
+#[cfg(feature = "mastodon")]
+impl MisskeyNote {
+    /// If the note is only visible to followers or mentioned accounts.
+    fn is_restricted(&self) -> bool {
+        matches!(
+            self.visibility.as_deref(),
+            Some("followers") | Some("specified")
+        )
+    }
+}
+
+#[cfg(feature = "mastodon")]
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MisskeyFile {
+    url: String,
+    thumbnail_url: Option<String>,
+}
+
+/// Most replies continuing a Mastodon thread to load media from.
+#[cfg(feature = "mastodon")]
+const MASTODON_THREAD_LIMIT: usize = 20;
+
+#[cfg(feature = "mastodon")]
 #[derive(Deserialize)]
 struct MastodonStatus {
+    id: String,
     url: String,
+    #[serde(default)]
+    visibility: Option<String>,
+    account: MastodonAccount,
+    #[serde(default)]
+    in_reply_to_id: Option<String>,
     media_attachments: Vec<MastodonMediaAttachments>,
 }
 
+#[cfg(feature = "mastodon")]
+impl MastodonStatus {
+    /// If the status is only visible to followers or mentioned accounts.
+    fn is_restricted(&self) -> bool {
+        matches!(self.visibility.as_deref(), Some("private") | Some("direct"))
+    }
+}
+
+#[cfg(feature = "mastodon")]
+#[derive(Deserialize)]
+struct MastodonAccount {
+    id: String,
+}
+
+#[cfg(feature = "mastodon")]
+#[derive(Deserialize)]
+struct MastodonContext {
+    descendants: Vec<MastodonStatus>,
+}
+
+#[cfg(feature = "mastodon")]
 #[derive(Deserialize)]
 struct MastodonMediaAttachments {
     url: String,
     preview_url: String,
 }
 
+#[cfg(feature = "mastodon")]
 impl Mastodon {
     pub fn default() -> Self {
+        Self::new(&Proxies::default())
+    }
+
+    pub fn new(proxies: &Proxies) -> Self {
         Self {
             instance_cache: HashMap::new(),
             matcher: regex::Regex::new(
-                r#"(?P<host>https?://(?:\S+))/(?:notice|users/\w+/statuses|@\w+)/(?P<id>\d+)"#,
+                r#"(?P<host>https?://(?:\S+))/(?:(?:notice|notes|users/\w+/statuses|@\w+/statuses)/(?P<id>[0-9A-Za-z]+)\b|@\w+/(?P<numeric_id>\d+))"#,
             )
             .unwrap(),
-            client: reqwest::Client::builder()
-                .user_agent(USER_AGENT)
-                .build()
-                .unwrap(),
+            client: proxies.client_builder("Mastodon").build().unwrap(),
+        }
+    }
+
+    /// Get the ID of a matched status. Other software uses IDs with letters,
+    /// but only numeric IDs can follow a username without being confused
+    /// with profile pages like `/@name/media`.
+    fn status_id<'a>(captures: &regex::Captures<'a>) -> Option<&'a str> {
+        captures
+            .name("id")
+            .or_else(|| captures.name("numeric_id"))
+            .map(|id| id.as_str())
+    }
+
+    /// Find the software a host is running from its nodeinfo.
+    async fn detect_software(&self, base: &str) -> anyhow::Result<Option<FediverseSoftware>> {
+        count_http_call();
+        let links: NodeInfoLinks = self
+            .client
+            .get(&format!("{}/.well-known/nodeinfo", base))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .context("unable to decode nodeinfo links")?;
+
+        // Schema versions sort in order, so the newest one is used.
+        let link = match links
+            .links
+            .into_iter()
+            .filter(|link| link.rel.starts_with(NODEINFO_SCHEMA_PREFIX))
+            .max_by(|a, b| a.rel.cmp(&b.rel))
+        {
+            Some(link) => link,
+            None => return Ok(None),
+        };
+
+        count_http_call();
+        let nodeinfo: NodeInfo = self
+            .client
+            .get(&link.href)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .context("unable to decode nodeinfo")?;
+
+        let software = FediverseSoftware::from_nodeinfo_name(&nodeinfo.software.name);
+        tracing::debug!(
+            base,
+            name = %nodeinfo.software.name,
+            ?software,
+            "detected fediverse software"
+        );
+
+        Ok(software)
+    }
+
+    /// Get the files attached to a Misskey note, if it's visible without
+    /// being signed in.
+    async fn misskey_note(&self, base: &str, note_id: &str) -> anyhow::Result<Option<MisskeyNote>> {
+        count_http_call();
+        let resp = self
+            .client
+            .post(&format!("{}/api/notes/show", base))
+            .json(&serde_json::json!({ "noteId": note_id }))
+            .send()
+            .await
+            .context("unable to request misskey api")?;
+
+        // Misskey responds with a client error for notes that don't exist
+        // and ones we aren't allowed to see.
+        if resp.status().is_client_error() {
+            tracing::debug!(status = %resp.status(), "misskey note was missing or not visible");
+            return Ok(None);
+        }
+
+        let note = resp.json().await.context("unable to decode misskey api")?;
+
+        Ok(Some(note))
+    }
+
+    /// Get the replies the author of a status made to continue it as a
+    /// thread, in order.
+    async fn thread_replies(
+        &self,
+        base: &str,
+        status: &MastodonStatus,
+    ) -> anyhow::Result<Vec<MastodonStatus>> {
+        count_http_call();
+        let resp = self
+            .client
+            .get(&format!("{}/api/v1/statuses/{}/context", base, status.id))
+            .send()
+            .await
+            .context("unable to request mastodon context")?;
+        let context: MastodonContext = check_rate_limit("Mastodon", resp)?
+            .json()
+            .await
+            .context("unable to decode mastodon context")?;
+
+        let mut descendants = context.descendants;
+        let mut replies = Vec::new();
+        let mut current_id = status.id.clone();
+
+        while replies.len() < MASTODON_THREAD_LIMIT {
+            let position = descendants.iter().position(|reply| {
+                reply.in_reply_to_id.as_deref() == Some(current_id.as_str())
+                    && reply.account.id == status.account.id
+            });
+
+            let reply = match position {
+                Some(position) => descendants.remove(position),
+                None => break,
+            };
+
+            current_id = reply.id.clone();
+            replies.push(reply);
         }
+
+        Ok(replies)
     }
 }
 
+#[cfg(feature = "mastodon")]
 #[async_trait]
 impl Site for Mastodon {
     fn name(&self) -> &'static str {
@@ -942,80 +2477,146 @@ impl Site for Mastodon {
             _ => return None,
         };
 
-        let sub_id = &captures["id"];
+        // Status IDs are only unique on their own instance.
+        let host = url::Url::parse(&captures["host"]).ok()?;
+        let host = match host.port() {
+            Some(port) => format!("{}:{}", host.host_str()?, port),
+            None => host.host_str()?.to_string(),
+        };
+
+        let sub_id = Self::status_id(&captures)?;
 
-        Some(format!("Mastodon-{}", sub_id))
+        Some(format!("Mastodon-{}-{}", host, sub_id))
     }
 
-    async fn url_supported(&mut self, url: &str) -> bool {
-        let captures = match self.matcher.captures(url) {
-            Some(captures) => captures,
-            None => return false,
-        };
+    async fn match_url(&mut self, url: &str) -> Option<UrlMatch> {
+        let captures = self.matcher.captures(url)?;
 
         let base = captures["host"].to_owned();
 
-        if let Some(is_masto) = self.instance_cache.get(&base) {
-            if !is_masto {
-                return false;
-            }
-        }
+        let software = match self.instance_cache.get(&base) {
+            Some(software) => *software,
+            None => {
+                let software = self.detect_software(&base).await.unwrap_or_else(|err| {
+                    tracing::debug!(base = %base, "unable to load nodeinfo: {:?}", err);
+                    None
+                });
+                self.instance_cache.insert(base.clone(), software);
 
-        let resp = match self
-            .client
-            .head(&format!("{}/api/v1/instance", base))
-            .send()
-            .await
-        {
-            Ok(resp) => resp,
-            Err(_) => {
-                self.instance_cache.insert(base, false);
-                return false;
+                software
             }
+        }?;
+
+        let thread = url::Url::parse(url)
+            .map(|url| url.query_pairs().any(|(key, _value)| key == "thread"))
+            .unwrap_or(false);
+        let kind = MatchKind::Mastodon {
+            host: base,
+            id: Self::status_id(&captures)?.to_owned(),
+            software,
+            thread,
         };
 
-        if !resp.status().is_success() {
-            self.instance_cache.insert(base, false);
-            return false;
-        }
-
-        true
+        Some(UrlMatch::new(self, url, kind))
     }
 
     async fn get_images(
         &mut self,
-        _user_id: i64,
-        url: &str,
+        _credentials: &dyn Credentials,
+        url: &UrlMatch,
     ) -> anyhow::Result<Option<Vec<PostInfo>>> {
-        let captures = self.matcher.captures(url).unwrap();
+        let (base, status_id, software, thread) = match &url.kind {
+            MatchKind::Mastodon {
+                host,
+                id,
+                software,
+                thread,
+            } => (host, id, *software, *thread),
+            _ => return Err(url.wrong_site(self.name())),
+        };
 
-        let base = captures["host"].to_owned();
-        let status_id = captures["id"].to_owned();
+        // Misskey has its own API, which threads aren't loaded from.
+        if !software.has_mastodon_api() {
+            let note = match self.misskey_note(base, status_id).await? {
+                Some(note) if !note.files.is_empty() => note,
+                _ => return Ok(None),
+            };
 
-        let json: MastodonStatus = self
+            let source_link = format!("{}/notes/{}", base, status_id);
+            let personal = note.is_restricted();
+
+            return Ok(Some(
+                note.files
+                    .into_iter()
+                    .filter_map(|file| {
+                        Some(PostInfo {
+                            file_type: get_file_ext(&file.url)?.to_owned(),
+                            url: file.url,
+                            thumb: file.thumbnail_url,
+                            source_link: Some(source_link.clone()),
+                            site_name: self.name().into(),
+                            personal,
+                            ..Default::default()
+                        })
+                    })
+                    .collect(),
+            ));
+        }
+
+        count_http_call();
+        let resp = self
             .client
             .get(&format!("{}/api/v1/statuses/{}", base, status_id))
             .send()
             .await
-            .context("unable to request mastodon api")?
-            .json()
-            .await
-            .context("unable to decode mastodon api")?;
+            .context("unable to request mastodon api")?;
+        let resp = check_rate_limit("Mastodon", resp)?;
+
+        // Statuses that aren't public can't be loaded without being signed
+        // in, and look like they don't exist.
+        if resp.status() == reqwest::StatusCode::NOT_FOUND {
+            tracing::debug!("mastodon status was missing or not visible");
+            return Ok(None);
+        }
+
+        let json: MastodonStatus = resp.json().await.context("unable to decode mastodon api")?;
+
+        let replies = if thread {
+            self.thread_replies(base, &json)
+                .await
+                .unwrap_or_else(|err| {
+                    tracing::warn!("unable to load mastodon thread: {:?}", err);
+                    vec![]
+                })
+        } else {
+            vec![]
+        };
+
+        let media_attachments: Vec<_> = std::iter::once(&json)
+            .chain(replies.iter())
+            .flat_map(|status| {
+                status
+                    .media_attachments
+                    .iter()
+                    .map(move |media| (media, status.is_restricted()))
+            })
+            .collect();
 
-        if json.media_attachments.is_empty() {
+        if media_attachments.is_empty() {
             return Ok(None);
         }
 
         Ok(Some(
-            json.media_attachments
-                .iter()
-                .filter_map(|media| {
+            media_attachments
+                .into_iter()
+                .filter_map(|(media, personal)| {
                     Some(PostInfo {
                         file_type: get_file_ext(&media.url)?.to_owned(),
                         url: media.url.clone(),
                         thumb: Some(media.preview_url.clone()),
                         source_link: Some(json.url.clone()),
-                        site_name: self.name(),
+                        site_name: self.name().into(),
+                        personal,
                         ..Default::default()
                     })
                 })
@@ -1025,22 +2626,48 @@ impl Site for Mastodon {
 }
 
 /// A loader for Weasyl.
+#[cfg(feature = "weasyl")]
 pub struct Weasyl {
-    api_key: String,
+    api: weasyl::Client,
     matcher: regex::Regex,
-    client: reqwest::Client,
+    character: regex::Regex,
+    gallery: regex::Regex,
 }
 
+#[cfg(feature = "weasyl")]
 impl Weasyl {
-    pub fn new(api_key: String) -> Self {
+    pub fn new(api_key: String, proxies: &Proxies) -> Self {
+        Self {
+            api: weasyl::Client::new(proxies.client_builder("Weasyl").build().unwrap(), api_key),
+            matcher: regex::Regex::new(r#"(?i)https?://www\.weasyl\.com/(?:(?:~|%7)(?:\w+)/submissions|submission)/(?P<id>\d+)(?:/\S*)?"#).unwrap(),
+            character: regex::Regex::new(r#"(?i)https?://www\.weasyl\.com/(?:(?:~|%7)(?:\w+)/characters|character)/(?P<id>\d+)(?:/\S*)?"#).unwrap(),
+            gallery: regex::Regex::new(r#"(?i)^https?://www\.weasyl\.com/(?:~|%7e|profile/)(?P<user>[\w-]+)(?:/submissions)?/?$"#).unwrap(),
+        }
+    }
+
+    /// Send API requests to a different base URL, like a local mirror.
+    pub fn with_endpoint(self, endpoint: String) -> Self {
         Self {
-            api_key,
-            matcher: regex::Regex::new(r#"https?://www\.weasyl\.com/(?:(?:~|%7)(?:\w+)/submissions|submission)/(?P<id>\d+)(?:/\S+)"#).unwrap(),
-            client: reqwest::Client::builder().user_agent(USER_AGENT).build().unwrap(),
+            api: self.api.with_endpoint(endpoint),
+            ..self
         }
     }
+
+    fn post(&self, media: &weasyl::Media, source_link: String) -> Option<PostInfo> {
+        let image = media.image()?;
+
+        Some(PostInfo {
+            file_type: get_file_ext(&image.url)?.to_owned(),
+            url: image.url.clone(),
+            thumb: media.thumbnail().map(|thumb| thumb.url.clone()),
+            source_link: Some(source_link),
+            site_name: self.name().into(),
+            ..Default::default()
+        })
+    }
 }
 
+#[cfg(feature = "weasyl")]
 #[async_trait]
 impl Site for Weasyl {
     fn name(&self) -> &'static str {
@@ -1048,99 +2675,77 @@ impl Site for Weasyl {
     }
 
     fn url_id(&self, url: &str) -> Option<String> {
-        let captures = match self.matcher.captures(url) {
-            Some(captures) => captures,
-            _ => return None,
-        };
+        if let Some(captures) = self.matcher.captures(url) {
+            let sub_id: i32 = captures["id"].parse().ok()?;
+            Some(format!("Weasyl-{}", sub_id))
+        } else {
+            let captures = self.character.captures(url)?;
+            let char_id: i32 = captures["id"].parse().ok()?;
+            Some(format!("WeasylCharacter-{}", char_id))
+        }
+    }
 
-        let sub_id: i32 = match captures["id"].to_owned().parse() {
-            Ok(id) => id,
-            _ => return None,
-        };
+    async fn match_url(&mut self, url: &str) -> Option<UrlMatch> {
+        let kind = if let Some(captures) = self.matcher.captures(url) {
+            MatchKind::WeasylSubmission {
+                id: captures["id"].parse().ok()?,
+            }
+        } else {
+            let captures = self.character.captures(url)?;
 
-        Some(format!("Weasyl-{}", sub_id))
-    }
+            MatchKind::WeasylCharacter {
+                id: captures["id"].parse().ok()?,
+            }
+        };
 
-    async fn url_supported(&mut self, url: &str) -> bool {
-        self.matcher.is_match(url)
+        Some(UrlMatch::new(self, url, kind))
     }
 
     async fn get_images(
         &mut self,
-        _user_id: i64,
-        url: &str,
+        _credentials: &dyn Credentials,
+        url: &UrlMatch,
     ) -> anyhow::Result<Option<Vec<PostInfo>>> {
-        let captures = self.matcher.captures(url).unwrap();
-        let sub_id = captures["id"].to_owned();
+        let media = match url.kind {
+            MatchKind::WeasylSubmission { id } => self.api.submission(id).await?.media,
+            MatchKind::WeasylCharacter { id } => self.api.character(id).await?.media,
+            _ => return Err(url.wrong_site(self.name())),
+        };
 
-        let resp: serde_json::Value = self
-            .client
-            .get(&format!(
-                "https://www.weasyl.com/api/submissions/{}/view",
-                sub_id
-            ))
-            .header("X-Weasyl-API-Key", self.api_key.as_bytes())
-            .send()
-            .await
-            .context("unable to request weasyl api")?
-            .json()
-            .await
-            .context("unable to parse weasyl json api")?;
-
-        let submissions = resp
-            .as_object()
-            .unwrap_fail()?
-            .get("media")
-            .unwrap_fail()?
-            .as_object()
-            .unwrap_fail()?
-            .get("submission")
-            .unwrap_fail()?
-            .as_array()
-            .unwrap_fail()?;
-
-        if submissions.is_empty() {
-            return Ok(None);
-        }
+        Ok(self
+            .post(&media, url.url().to_string())
+            .map(|post| vec![post]))
+    }
 
-        let thumbs = resp
-            .as_object()
-            .unwrap_fail()?
-            .get("media")
-            .unwrap_fail()?
-            .as_object()
-            .unwrap_fail()?
-            .get("thumbnail")
-            .unwrap_fail()?
-            .as_array()
-            .unwrap_fail()?;
+    async fn latest_posts(&mut self, url: &str) -> anyhow::Result<Option<Vec<PostInfo>>> {
+        let captures = match self.gallery.captures(url) {
+            Some(captures) => captures,
+            None => return Ok(None),
+        };
 
-        Ok(Some(
-            submissions
-                .iter()
-                .zip(thumbs)
-                .filter_map(|(sub, thumb)| {
-                    let sub_url = sub.get("url")?.as_str()?.to_owned();
-                    let thumb_url = thumb.get("url")?.as_str()?.to_owned();
+        let gallery = self
+            .api
+            .gallery(&captures["user"], LATEST_POSTS)
+            .await
+            .context("unable to load weasyl gallery")?;
 
-                    Some(PostInfo {
-                        file_type: get_file_ext(&sub_url)?.to_owned(),
-                        url: sub_url.clone(),
-                        thumb: Some(thumb_url),
-                        source_link: Some(url.to_string()),
-                        site_name: self.name(),
-                        ..Default::default()
-                    })
-                })
+        Ok(Some(
+            gallery
+                .submissions
+                .into_iter()
+                .filter_map(|sub| self.post(&sub.media, sub.link))
                 .collect(),
         ))
     }
 }
 
 /// A loader for Inkbunny.
+#[cfg(feature = "inkbunny")]
 pub struct Inkbunny {
     client: reqwest::Client,
     matcher: regex::Regex,
+    /// Base URL for API requests.
+    endpoint: String,
 
     username: String,
     password: String,
@@ -1148,6 +2753,7 @@ pub struct Inkbunny {
     sid: Option<String>,
 }
 
+#[cfg(feature = "inkbunny")]
 #[derive(Deserialize, Debug)]
 pub struct InkbunnyLogin {
     sid: String,
@@ -1155,6 +2761,7 @@ pub struct InkbunnyLogin {
     ratingsmask: String,
 }
 
+#[cfg(feature = "inkbunny")]
 #[derive(Deserialize, Debug)]
 pub struct InkbunnyFile {
     file_id: String,
@@ -1163,18 +2770,21 @@ pub struct InkbunnyFile {
     file_url_screen: String,
 }
 
+#[cfg(feature = "inkbunny")]
 #[derive(Deserialize, Debug)]
 pub struct InkbunnySubmission {
     submission_id: String,
     files: Vec<InkbunnyFile>,
 }
 
+#[cfg(feature = "inkbunny")]
 #[derive(Deserialize, Debug)]
 pub struct InkbunnySubmissions {
     results_count: i32,
     submissions: Vec<InkbunnySubmission>,
 }
 
+#[cfg(feature = "inkbunny")]
 #[derive(Deserialize, Debug)]
 #[serde(untagged)]
 pub enum InkbunnyResponse<T> {
@@ -1182,11 +2792,9 @@ pub enum InkbunnyResponse<T> {
     Success(T),
 }
 
+#[cfg(feature = "inkbunny")]
 impl Inkbunny {
-    /// API endpoint for logging into the site.
-    const API_LOGIN: &'static str = "https://inkbunny.net/api_login.php";
-    /// API endpoint for loading a submission.
-    const API_SUBMISSIONS: &'static str = "https://inkbunny.net/api_submissions.php";
+    const ENDPOINT: &'static str = "https://inkbunny.net";
 
     /// Log into Inkbunny, getting a session ID for future requests.
     pub async fn get_sid(&mut self) -> anyhow::Result<String> {
@@ -1194,9 +2802,10 @@ impl Inkbunny {
             return Ok(sid.clone());
         }
 
+        count_http_call();
         let resp: InkbunnyResponse<InkbunnyLogin> = self
             .client
-            .post(Self::API_LOGIN)
+            .post(&format!("{}/api_login.php", self.endpoint))
             .form(&vec![
                 ("username", &self.username),
                 ("password", &self.password),
@@ -1234,14 +2843,15 @@ impl Inkbunny {
             tracing::debug!(?ids, "loading Inkbunny submissions");
             let sid = self.get_sid().await?;
 
-            let resp: InkbunnyResponse<InkbunnySubmissions> = self
+            count_http_call();
+            let resp = self
                 .client
-                .post(Self::API_SUBMISSIONS)
+                .post(&format!("{}/api_submissions.php", self.endpoint))
                 .form(&vec![("sid", &sid), ("submission_ids", &ids)])
                 .send()
-                .await?
-                .json()
                 .await?;
+            let resp: InkbunnyResponse<InkbunnySubmissions> =
+                check_rate_limit("Inkbunny", resp)?.json().await?;
 
             match resp {
                 InkbunnyResponse::Success(submissions) => break submissions,
@@ -1257,15 +2867,16 @@ impl Inkbunny {
         Ok(submissions)
     }
 
-    pub fn new(username: String, password: String) -> Self {
-        let client = reqwest::Client::builder()
-            .user_agent(USER_AGENT)
-            .build()
-            .unwrap();
+    pub fn new(username: String, password: String, proxies: &Proxies) -> Self {
+        let client = proxies.client_builder("Inkbunny").build().unwrap();
 
         Self {
             client,
-            matcher: regex::Regex::new(r#"https?://inkbunny.net/s/(?P<id>\d+)"#).unwrap(),
+            matcher: regex::Regex::new(
+                r#"(?i)https?://inkbunny\.net/s/(?P<id>\d+)(?:-p(?P<page>\d+)-?)?(?:#p(?P<anchor>\d+))?"#,
+            )
+            .unwrap(),
+            endpoint: Self::ENDPOINT.to_string(),
 
             username,
             password,
@@ -1273,14 +2884,24 @@ impl Inkbunny {
             sid: None,
         }
     }
+
+    /// Send API requests to a different base URL, like a local mirror.
+    pub fn with_endpoint(self, endpoint: String) -> Self {
+        Self { endpoint, ..self }
+    }
 }
 
+#[cfg(feature = "inkbunny")]
 #[async_trait]
 impl Site for Inkbunny {
     fn name(&self) -> &'static str {
         "Inkbunny"
     }
 
+    fn test_url(&self) -> Option<&'static str> {
+        Some("https://inkbunny.net/s/2458963")
+    }
+
     fn url_id(&self, url: &str) -> Option<String> {
         let captures = match self.matcher.captures(url) {
             Some(captures) => captures,
@@ -1295,27 +2916,42 @@ impl Site for Inkbunny {
         Some(format!("Inkbunny-{}", sub_id))
     }
 
-    async fn url_supported(&mut self, url: &str) -> bool {
-        self.matcher.is_match(url)
+    async fn match_url(&mut self, url: &str) -> Option<UrlMatch> {
+        let captures = self.matcher.captures(url)?;
+
+        // Pages may be linked like `/s/1-p2-` or with an anchor like `#p2`.
+        let page = captures
+            .name("page")
+            .or_else(|| captures.name("anchor"))
+            .and_then(|page| page.as_str().parse().ok());
+
+        let kind = MatchKind::Inkbunny {
+            id: captures["id"].parse().ok()?,
+            page,
+        };
+
+        Some(UrlMatch::new(self, url, kind))
     }
 
     async fn get_images(
         &mut self,
-        _user_id: i64,
-        url: &str,
+        _credentials: &dyn Credentials,
+        url: &UrlMatch,
     ) -> anyhow::Result<Option<Vec<PostInfo>>> {
-        let captures = self.matcher.captures(url).unwrap();
-        let sub_id: i32 = match captures["id"].to_owned().parse() {
-            Ok(id) => id,
-            Err(_err) => return Ok(None),
+        let (sub_id, page) = match url.kind {
+            MatchKind::Inkbunny { id, page } => (id, page),
+            _ => return Err(url.wrong_site(self.name())),
         };
+        let url = url.url();
 
         let submissions = self.get_submissions(&[sub_id]).await?;
 
         let mut results = Vec::with_capacity(1);
 
         for submission in submissions.submissions {
-            for file in submission.files {
+            let files_count = submission.files.len();
+
+            for (index, file) in submission.files.into_iter().enumerate() {
                 let ext = match get_file_ext(&file.file_url_screen) {
                     Some(ext) => ext,
                     None => continue,
@@ -1326,29 +2962,43 @@ impl Site for Inkbunny {
                     url: file.file_url_screen.clone(),
                     thumb: Some(file.thumbnail_url_medium_noncustom.clone()),
                     source_link: Some(url.to_owned()),
-                    site_name: self.name(),
+                    site_name: self.name().into(),
+                    file_number: Some(index + 1).filter(|_| files_count > 1),
+                    files_count: Some(files_count).filter(|count| *count > 1),
                     ..Default::default()
                 });
             }
         }
 
+        // Only the linked page is wanted, unless it doesn't exist.
+        if let Some(page) = page {
+            if results.iter().any(|post| post.file_number == Some(page)) {
+                results.retain(|post| post.file_number == Some(page));
+            }
+        }
+
         Ok(Some(results))
     }
 }
 
 /// A loader for DeviantArt.
+#[cfg(feature = "deviantart")]
 pub struct DeviantArt {
     client: reqwest::Client,
     matcher: regex::Regex,
+    /// URL of the oEmbed API.
+    endpoint: String,
 }
 
 /// DeviantArt oEmbed responses can contain either integers or strings, so
 /// we provide a wrapper type for serde to deserialize from.
+#[cfg(feature = "deviantart")]
 #[derive(Clone, Copy, Debug)]
 struct AlwaysNum(u32);
 
 // This code is heavily based on the example from here:
 // https://users.rust-lang.org/t/deserialize-a-number-that-may-be-inside-a-string-serde-json/27318/4
+#[cfg(feature = "deviantart")]
 impl<'de> serde::Deserialize<'de> for AlwaysNum {
     fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
         struct NumVisitor;
@@ -1381,6 +3031,7 @@ impl<'de> serde::Deserialize<'de> for AlwaysNum {
     }
 }
 
+#[cfg(feature = "deviantart")]
 #[derive(Deserialize)]
 struct DeviantArtOEmbed {
     #[serde(rename = "type")]
@@ -1391,15 +3042,28 @@ struct DeviantArtOEmbed {
     height: AlwaysNum,
 }
 
+#[cfg(feature = "deviantart")]
 impl DeviantArt {
+    const ENDPOINT: &'static str = "https://backend.deviantart.com/oembed";
+
     pub fn default() -> Self {
+        Self::new(&Proxies::default())
+    }
+
+    pub fn new(proxies: &Proxies) -> Self {
         Self {
-            client: reqwest::Client::builder().user_agent(USER_AGENT).build().unwrap(),
-            matcher: regex::Regex::new(r#"(?:(?:deviantart\.com/(?:.+/)?art/.+-|fav\.me/)(?P<id>\d+)|sta\.sh/(?P<code>\w+))"#)
+            client: proxies.client_builder("DeviantArt").build().unwrap(),
+            matcher: regex::Regex::new(r#"(?i)(?:(?:deviantart\.com/(?:.+/)?art/.+-|fav\.me/)(?P<id>\d+)|sta\.sh/(?P<code>\w+))"#)
                 .unwrap(),
+            endpoint: Self::ENDPOINT.to_string(),
         }
     }
 
+    /// Send oEmbed requests to a different URL, like a local mirror.
+    pub fn with_endpoint(self, endpoint: String) -> Self {
+        Self { endpoint, ..self }
+    }
+
     /// Attempt to get an ID from our matcher's captures.
     fn get_id(&self, captures: &regex::Captures) -> Option<String> {
         if let Some(id) = captures.name("id") {
@@ -1414,14 +3078,19 @@ impl DeviantArt {
     }
 }
 
+#[cfg(feature = "deviantart")]
 #[async_trait]
 impl Site for DeviantArt {
     fn name(&self) -> &'static str {
         "DeviantArt"
     }
 
-    async fn url_supported(&mut self, url: &str) -> bool {
-        self.matcher.is_match(url)
+    async fn match_url(&mut self, url: &str) -> Option<UrlMatch> {
+        if self.matcher.is_match(url) {
+            Some(UrlMatch::new(self, url, MatchKind::DeviantArt))
+        } else {
+            None
+        }
     }
 
     fn url_id(&self, url: &str) -> Option<String> {
@@ -1433,13 +3102,21 @@ impl Site for DeviantArt {
 
     async fn get_images(
         &mut self,
-        _user_id: i64,
-        url: &str,
+        _credentials: &dyn Credentials,
+        url: &UrlMatch,
     ) -> anyhow::Result<Option<Vec<PostInfo>>> {
-        let mut endpoint = url::Url::parse("https://backend.deviantart.com/oembed").unwrap();
+        if url.kind != MatchKind::DeviantArt {
+            return Err(url.wrong_site(self.name()));
+        }
+        let url = url.url();
+
+        let mut endpoint =
+            url::Url::parse(&self.endpoint).context("invalid deviantart endpoint")?;
         endpoint.query_pairs_mut().append_pair("url", url);
 
-        let resp: DeviantArtOEmbed = self.client.get(endpoint).send().await?.json().await?;
+        count_http_call();
+        let resp = self.client.get(endpoint).send().await?;
+        let resp: DeviantArtOEmbed = check_rate_limit("DeviantArt", resp)?.json().await?;
 
         if resp.file_type != "photo" {
             return Ok(None);
@@ -1450,9 +3127,46 @@ impl Site for DeviantArt {
             url: resp.url,
             thumb: Some(resp.thumbnail_url),
             source_link: Some(url.to_owned()),
-            site_name: self.name(),
+            site_name: self.name().into(),
             image_dimensions: Some((resp.width.0, resp.height.0)),
             ..Default::default()
         }]))
     }
 }
+
+#[cfg(all(test, feature = "furaffinity"))]
+mod tests {
+    #[tokio::test]
+    async fn test_furaffinity_submission_details() {
+        let site = super::FurAffinity::new(
+            ("a".to_string(), "b".to_string()),
+            "api-key".to_string(),
+            &super::Proxies::default(),
+            None,
+            Box::new(super::credentials::MemorySessions::default()),
+        )
+        .await;
+
+        let body = scraper::Html::parse_document(include_str!(
+            "../tests/fixtures/furaffinity/submission.html"
+        ));
+        let (title, artist, rating) = site.submission_details(&body);
+
+        assert_eq!(title.as_deref(), Some("Fox in the Snow"));
+        assert_eq!(artist.as_deref(), Some("Syfaro"));
+        assert!(matches!(rating, Some(fuzzysearch::Rating::General)));
+
+        let body = scraper::Html::parse_document(
+            r#"<meta property="og:title" content="Fox by the Sea by Syfaro">"#,
+        );
+        let (title, artist, rating) = site.submission_details(&body);
+
+        assert_eq!(
+            title.as_deref(),
+            Some("Fox by the Sea"),
+            "titles should be found from the page's title"
+        );
+        assert_eq!(artist.as_deref(), Some("Syfaro"));
+        assert!(rating.is_none());
+    }
+}
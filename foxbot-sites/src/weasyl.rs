@@ -0,0 +1,234 @@
+//! Typed client for the parts of the Weasyl API used by the loader.
+
+use anyhow::Context;
+use serde::{de::DeserializeOwned, Deserialize};
+
+use crate::{check_rate_limit, count_http_call, get_file_ext};
+
+const API_URL: &str = "https://www.weasyl.com/api";
+
+/// A submission from `/api/submissions/{id}/view` or a user's gallery.
+#[derive(Debug, Deserialize)]
+pub struct Submission {
+    pub submitid: i32,
+    pub title: String,
+    pub link: String,
+    pub rating: String,
+    /// The kind of submission, like visual, literary, or multimedia.
+    pub subtype: String,
+    #[serde(default)]
+    pub owner_login: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub media: Media,
+}
+
+/// A character from `/api/characters/{id}/view`.
+#[derive(Debug, Deserialize)]
+pub struct Character {
+    pub charid: i32,
+    pub title: String,
+    pub link: String,
+    pub rating: String,
+    #[serde(default)]
+    pub owner_login: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    pub media: Media,
+}
+
+/// A page of a user's gallery from `/api/users/{login}/gallery`, newest
+/// first.
+#[derive(Debug, Deserialize)]
+pub struct Gallery {
+    pub submissions: Vec<Submission>,
+    /// Submission ID to request the previous page with.
+    pub backid: Option<i32>,
+    /// Submission ID to request the next page with.
+    pub nextid: Option<i32>,
+}
+
+/// Files attached to a submission or character.
+///
+/// Any of these may be missing. Submissions that aren't images, like
+/// stories, have a cover image in addition to the submitted file, and only
+/// get a generated thumbnail if the user didn't provide one.
+#[derive(Debug, Default, Deserialize)]
+pub struct Media {
+    #[serde(default)]
+    pub submission: Vec<MediaFile>,
+    #[serde(default)]
+    pub cover: Vec<MediaFile>,
+    #[serde(default)]
+    pub thumbnail: Vec<MediaFile>,
+    #[serde(default, rename = "thumbnail-generated")]
+    pub thumbnail_generated: Vec<MediaFile>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MediaFile {
+    pub mediaid: Option<i32>,
+    pub url: String,
+}
+
+impl Media {
+    /// Get the image to display, using the cover if the submitted file isn't
+    /// an image.
+    pub fn image(&self) -> Option<&MediaFile> {
+        self.submission
+            .iter()
+            .find(|file| is_image(&file.url))
+            .or_else(|| self.cover.iter().find(|file| is_image(&file.url)))
+    }
+
+    /// Get the thumbnail, preferring one provided by the user.
+    pub fn thumbnail(&self) -> Option<&MediaFile> {
+        self.thumbnail
+            .first()
+            .or_else(|| self.thumbnail_generated.first())
+    }
+}
+
+fn is_image(url: &str) -> bool {
+    matches!(
+        get_file_ext(url)
+            .map(|ext| ext.to_ascii_lowercase())
+            .as_deref(),
+        Some("png") | Some("jpg") | Some("jpeg") | Some("gif")
+    )
+}
+
+/// Client for the Weasyl API, authenticated with an API key.
+pub struct Client {
+    client: reqwest::Client,
+    api_key: String,
+    endpoint: String,
+}
+
+impl Client {
+    pub fn new(client: reqwest::Client, api_key: String) -> Self {
+        Self {
+            client,
+            api_key,
+            endpoint: API_URL.to_string(),
+        }
+    }
+
+    /// Send requests to a different base URL, like a local mirror.
+    pub fn with_endpoint(self, endpoint: String) -> Self {
+        Self { endpoint, ..self }
+    }
+
+    async fn get<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, String)],
+    ) -> anyhow::Result<T> {
+        count_http_call();
+        let resp = self
+            .client
+            .get(&format!("{}/{}", self.endpoint, path))
+            .query(query)
+            .header("X-Weasyl-API-Key", self.api_key.as_bytes())
+            .send()
+            .await
+            .context("unable to request weasyl api")?;
+
+        check_rate_limit("Weasyl", resp)?
+            .error_for_status()
+            .context("weasyl api returned error")?
+            .json()
+            .await
+            .context("unable to parse weasyl json api")
+    }
+
+    pub async fn submission(&self, id: i32) -> anyhow::Result<Submission> {
+        self.get(&format!("submissions/{}/view", id), &[]).await
+    }
+
+    pub async fn character(&self, id: i32) -> anyhow::Result<Character> {
+        self.get(&format!("characters/{}/view", id), &[]).await
+    }
+
+    /// Load the newest submissions in a user's gallery.
+    pub async fn gallery(&self, login: &str, count: usize) -> anyhow::Result<Gallery> {
+        self.get(
+            &format!("users/{}/gallery", login),
+            &[("count", count.to_string())],
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Character, Gallery, Submission};
+
+    #[test]
+    fn test_submission() {
+        let sub: Submission =
+            serde_json::from_str(include_str!("../tests/fixtures/weasyl/submission.json")).unwrap();
+
+        assert_eq!(sub.submitid, 1765987);
+        assert_eq!(sub.owner_login.as_deref(), Some("syfaro"));
+        assert_eq!(sub.tags, vec!["fox", "sketch"]);
+        assert!(sub
+            .media
+            .image()
+            .unwrap()
+            .url
+            .ends_with("syfaro-fox-sketch.png"));
+        assert_eq!(sub.media.thumbnail().unwrap().mediaid, Some(3172846));
+    }
+
+    #[test]
+    fn test_literary_submission() {
+        let sub: Submission =
+            serde_json::from_str(include_str!("../tests/fixtures/weasyl/literary.json")).unwrap();
+
+        assert_eq!(sub.subtype, "literary");
+        assert!(
+            sub.media
+                .image()
+                .unwrap()
+                .url
+                .ends_with("syfaro-a-short-story.jpg"),
+            "cover should be used when submission isn't an image"
+        );
+        assert_eq!(
+            sub.media.thumbnail().unwrap().mediaid,
+            Some(3172902),
+            "generated thumbnail should be used without a custom thumbnail"
+        );
+    }
+
+    #[test]
+    fn test_character() {
+        let character: Character =
+            serde_json::from_str(include_str!("../tests/fixtures/weasyl/character.json")).unwrap();
+
+        assert_eq!(character.charid, 178234);
+        assert!(character
+            .media
+            .image()
+            .unwrap()
+            .url
+            .ends_with("syfaro-foxbot.png"));
+        assert!(character.media.thumbnail().is_some());
+    }
+
+    #[test]
+    fn test_gallery() {
+        let gallery: Gallery =
+            serde_json::from_str(include_str!("../tests/fixtures/weasyl/gallery.json")).unwrap();
+
+        assert_eq!(gallery.backid, None);
+        assert_eq!(gallery.nextid, Some(1765901));
+        assert_eq!(gallery.submissions.len(), 2);
+
+        let ids: Vec<_> = gallery.submissions.iter().map(|sub| sub.submitid).collect();
+        assert_eq!(ids, vec![1766012, 1765987]);
+        assert!(gallery.submissions[0].media.submission.is_empty());
+        assert!(gallery.submissions[0].media.image().is_some());
+    }
+}
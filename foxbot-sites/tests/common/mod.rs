@@ -0,0 +1,46 @@
+//! Replays recorded site responses so loaders can be tested without the
+//! network.
+//!
+//! Fixtures in `tests/fixtures/<site>/` are responses saved from each site,
+//! with account details removed. To record a new one, save the body of the
+//! real request, like `curl -u login:key https://e621.net/posts/1.json`.
+
+// Each test binary only uses some of these helpers.
+#![allow(dead_code)]
+
+use foxbot_sites::{Anonymous, PostInfo, Site};
+use wiremock::{matchers, Mock, MockServer, ResponseTemplate};
+
+/// Read a recorded fixture.
+pub fn fixture(path: &str) -> String {
+    let path = format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), path);
+
+    std::fs::read_to_string(&path)
+        .unwrap_or_else(|err| panic!("unable to read fixture {}: {}", path, err))
+}
+
+/// A successful response with a recorded JSON body.
+pub fn json(path: &str) -> ResponseTemplate {
+    ResponseTemplate::new(200).set_body_raw(fixture(path), "application/json")
+}
+
+/// Respond to every request with the method and path with a recorded JSON
+/// body.
+pub async fn replay(server: &MockServer, method: &str, path: &str, fixture: &str) {
+    Mock::given(matchers::method(method))
+        .and(matchers::path(path))
+        .respond_with(json(fixture))
+        .mount(server)
+        .await;
+}
+
+/// Match a URL with a site and load its images, panicking if the site doesn't
+/// support the URL.
+pub async fn get_images<S: Site>(site: &mut S, url: &str) -> anyhow::Result<Option<Vec<PostInfo>>> {
+    let url_match = site
+        .match_url(url)
+        .await
+        .unwrap_or_else(|| panic!("{} should support {}", site.name(), url));
+
+    site.get_images(&Anonymous, &url_match).await
+}
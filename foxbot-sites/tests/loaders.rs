@@ -0,0 +1,874 @@
+use foxbot_sites::{
+    credentials::MemorySessions, Anonymous, DeviantArt, Direct, E621Host, FurAffinity, Inkbunny,
+    Mastodon, Proxies, Resolver, Site, Twitter, TwitterApi, Weasyl, E621,
+};
+use wiremock::{matchers, Mock, MockServer, ResponseTemplate};
+
+mod common;
+
+use common::{fixture, get_images, json, replay};
+
+fn e621(server: &MockServer) -> E621 {
+    E621::new(
+        E621Host::E621,
+        "login".to_string(),
+        "api-key".to_string(),
+        &Proxies::default(),
+    )
+    .with_endpoint(server.uri())
+}
+
+#[tokio::test]
+async fn test_e621_matching() {
+    let server = MockServer::start().await;
+    let mut site = e621(&server);
+
+    let post = "https://e621.net/posts/2785411";
+    assert!(site.match_url(post).await.is_some());
+    assert_eq!(site.url_id(post), Some("e621-2785411".to_string()));
+    assert!(site
+        .match_url("https://static1.e621.net/data/c8/bb/c8bbfb2d4f3eef5be0d4b1eb2b3e45a7.png")
+        .await
+        .is_some());
+    assert!(site
+        .match_url("https://e621.net/pools/24310")
+        .await
+        .is_some());
+    assert!(site
+        .match_url("https://e926.net/posts/2785411")
+        .await
+        .is_none());
+    assert_eq!(site.url_id("https://e621.net/pools/24310"), None);
+}
+
+#[tokio::test]
+async fn test_e621_post() {
+    let server = MockServer::start().await;
+    replay(&server, "GET", "/posts/2785411.json", "e621/post.json").await;
+    Mock::given(matchers::method("GET"))
+        .and(matchers::path("/pools.json"))
+        .and(matchers::query_param("search[id]", "24310"))
+        .respond_with(json("e621/pools.json"))
+        .mount(&server)
+        .await;
+    let mut site = e621(&server);
+
+    let posts = get_images(&mut site, "https://e621.net/posts/2785411")
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(posts.len(), 1);
+    assert_eq!(posts[0].file_type, "png");
+    assert_eq!(
+        posts[0].url,
+        "https://static1.e621.net/data/c8/bb/c8bbfb2d4f3eef5be0d4b1eb2b3e45a7.png"
+    );
+    assert_eq!(
+        posts[0].source_link.as_deref(),
+        Some("https://e621.net/posts/2785411"),
+        "links should use the site even when the API is elsewhere"
+    );
+    assert!(matches!(
+        posts[0].rating,
+        Some(fuzzysearch::Rating::General)
+    ));
+    assert_eq!(
+        posts[0].artists,
+        vec!["syfaro"],
+        "artist tags that don't name an artist should be removed"
+    );
+    assert_eq!(posts[0].tags, vec!["fox", "fur", "snow"]);
+    assert_eq!(
+        posts[0].pools,
+        vec![foxbot_sites::PostPool {
+            name: "Fox Adventures".to_string(),
+            url: "https://e621.net/pools/24310".to_string(),
+        }]
+    );
+    assert_eq!(posts[0].parent_link, None);
+    assert_eq!(posts[0].child_links, vec!["https://e621.net/posts/2785412"]);
+}
+
+#[tokio::test]
+async fn test_e621_direct_link() {
+    let server = MockServer::start().await;
+    Mock::given(matchers::method("GET"))
+        .and(matchers::path("/posts.json"))
+        .and(matchers::query_param(
+            "md5",
+            "c8bbfb2d4f3eef5be0d4b1eb2b3e45a7",
+        ))
+        .respond_with(json("e621/post.json"))
+        .mount(&server)
+        .await;
+    let mut site = e621(&server);
+
+    let posts = get_images(
+        &mut site,
+        "https://static1.e621.net/data/sample/c8/bb/c8bbfb2d4f3eef5be0d4b1eb2b3e45a7.jpg",
+    )
+    .await
+    .unwrap()
+    .unwrap();
+
+    assert_eq!(
+        posts[0].source_link.as_deref(),
+        Some("https://e621.net/posts/2785411")
+    );
+}
+
+#[tokio::test]
+async fn test_e621_deleted_post() {
+    let server = MockServer::start().await;
+    replay(&server, "GET", "/posts/2785400.json", "e621/deleted.json").await;
+    let mut site = e621(&server);
+
+    let posts = get_images(&mut site, "https://e621.net/posts/2785400")
+        .await
+        .unwrap();
+
+    assert!(posts.is_none(), "posts without files should be skipped");
+}
+
+#[tokio::test]
+async fn test_e621_rate_limited() {
+    let server = MockServer::start().await;
+    Mock::given(matchers::method("GET"))
+        .and(matchers::path("/posts/2785411.json"))
+        .respond_with(ResponseTemplate::new(429))
+        .mount(&server)
+        .await;
+    let mut site = e621(&server);
+
+    let err = get_images(&mut site, "https://e621.net/posts/2785411")
+        .await
+        .unwrap_err();
+
+    assert!(foxbot_sites::RateLimited::is_cause(&err));
+}
+
+#[tokio::test]
+async fn test_check_sites() {
+    let server = MockServer::start().await;
+    replay(&server, "GET", "/posts/2785411.json", "e621/post.json").await;
+    let mut sites: Vec<foxbot_sites::BoxedSite> = vec![Box::new(e621(&server))];
+
+    let checks = foxbot_sites::check_sites(&mut sites).await;
+    assert_eq!(checks.len(), 1);
+    assert!(checks[0].passed());
+    assert_eq!(checks[0].result.as_ref().unwrap(), &1);
+}
+
+#[tokio::test]
+async fn test_check_sites_missing_post() {
+    let server = MockServer::start().await;
+    replay(&server, "GET", "/posts/2785411.json", "e621/deleted.json").await;
+    let mut sites: Vec<foxbot_sites::BoxedSite> = vec![Box::new(e621(&server))];
+
+    let checks = foxbot_sites::check_sites(&mut sites).await;
+    assert_eq!(checks.len(), 1);
+    assert!(
+        !checks[0].passed(),
+        "sites that don't find their test post should fail"
+    );
+}
+
+#[tokio::test]
+async fn test_e621_pool() {
+    let server = MockServer::start().await;
+    replay(&server, "GET", "/pools/24310.json", "e621/pool.json").await;
+    replay(&server, "GET", "/posts/2785411.json", "e621/post.json").await;
+    replay(
+        &server,
+        "GET",
+        "/posts/2785412.json",
+        "e621/post_2785412.json",
+    )
+    .await;
+    let mut site = e621(&server);
+
+    let posts = get_images(&mut site, "https://e621.net/pools/24310")
+        .await
+        .unwrap()
+        .unwrap();
+
+    let links: Vec<_> = posts
+        .iter()
+        .map(|post| post.source_link.as_deref().unwrap())
+        .collect();
+    assert_eq!(
+        links,
+        vec![
+            "https://e621.net/posts/2785411",
+            "https://e621.net/posts/2785412"
+        ]
+    );
+    assert!(matches!(posts[1].rating, Some(fuzzysearch::Rating::Mature)));
+}
+
+fn weasyl(server: &MockServer) -> Weasyl {
+    Weasyl::new("api-key".to_string(), &Proxies::default()).with_endpoint(server.uri())
+}
+
+#[tokio::test]
+async fn test_weasyl_matching() {
+    let server = MockServer::start().await;
+    let mut site = weasyl(&server);
+
+    let submission = "https://www.weasyl.com/~syfaro/submissions/1765987/fox-sketch";
+    assert!(site.match_url(submission).await.is_some());
+    assert_eq!(site.url_id(submission), Some("Weasyl-1765987".to_string()));
+
+    let character = "https://www.weasyl.com/character/178234/foxbot";
+    assert!(site.match_url(character).await.is_some());
+    assert_eq!(
+        site.url_id(character),
+        Some("WeasylCharacter-178234".to_string())
+    );
+
+    assert!(site
+        .match_url("https://www.weasyl.com/~syfaro")
+        .await
+        .is_none());
+}
+
+#[tokio::test]
+async fn test_weasyl_submission() {
+    let server = MockServer::start().await;
+    Mock::given(matchers::method("GET"))
+        .and(matchers::path("/submissions/1765987/view"))
+        .and(matchers::header("X-Weasyl-API-Key", "api-key"))
+        .respond_with(json("weasyl/submission.json"))
+        .mount(&server)
+        .await;
+    let mut site = weasyl(&server);
+
+    let url = "https://www.weasyl.com/~syfaro/submissions/1765987/fox-sketch";
+    let posts = get_images(&mut site, url).await.unwrap().unwrap();
+
+    assert_eq!(posts.len(), 1);
+    assert_eq!(posts[0].file_type, "png");
+    assert_eq!(posts[0].source_link.as_deref(), Some(url));
+    assert!(posts[0].thumb.is_some());
+}
+
+#[tokio::test]
+async fn test_weasyl_literary() {
+    let server = MockServer::start().await;
+    replay(
+        &server,
+        "GET",
+        "/submissions/1766012/view",
+        "weasyl/literary.json",
+    )
+    .await;
+    let mut site = weasyl(&server);
+
+    let posts = get_images(
+        &mut site,
+        "https://www.weasyl.com/~syfaro/submissions/1766012/a-short-story",
+    )
+    .await
+    .unwrap()
+    .unwrap();
+
+    assert_eq!(posts[0].file_type, "jpg", "cover should be used");
+}
+
+#[tokio::test]
+async fn test_weasyl_character() {
+    let server = MockServer::start().await;
+    replay(
+        &server,
+        "GET",
+        "/characters/178234/view",
+        "weasyl/character.json",
+    )
+    .await;
+    let mut site = weasyl(&server);
+
+    let posts = get_images(&mut site, "https://www.weasyl.com/character/178234/foxbot")
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(posts.len(), 1);
+    assert!(posts[0].url.ends_with("syfaro-foxbot.png"));
+}
+
+fn inkbunny(server: &MockServer) -> Inkbunny {
+    Inkbunny::new(
+        "username".to_string(),
+        "password".to_string(),
+        &Proxies::default(),
+    )
+    .with_endpoint(server.uri())
+}
+
+#[tokio::test]
+async fn test_inkbunny_matching() {
+    let server = MockServer::start().await;
+    let mut site = inkbunny(&server);
+
+    let url = "https://inkbunny.net/s/2458963";
+    assert!(site.match_url(url).await.is_some());
+    assert_eq!(site.url_id(url), Some("Inkbunny-2458963".to_string()));
+    assert!(site
+        .match_url("https://inkbunny.net/Syfaro")
+        .await
+        .is_none());
+}
+
+#[tokio::test]
+async fn test_inkbunny_submission() {
+    let server = MockServer::start().await;
+    replay(&server, "POST", "/api_login.php", "inkbunny/login.json").await;
+    replay(
+        &server,
+        "POST",
+        "/api_submissions.php",
+        "inkbunny/submissions.json",
+    )
+    .await;
+    let mut site = inkbunny(&server);
+
+    let url = "https://inkbunny.net/s/2458963";
+    let posts = get_images(&mut site, url).await.unwrap().unwrap();
+
+    let types: Vec<_> = posts.iter().map(|post| post.file_type.as_str()).collect();
+    assert_eq!(types, vec!["png", "jpg"]);
+    assert!(posts
+        .iter()
+        .all(|post| post.source_link.as_deref() == Some(url)));
+    assert_eq!(posts[1].file_number, Some(2));
+    assert!(posts.iter().all(|post| post.files_count == Some(2)));
+}
+
+#[tokio::test]
+async fn test_inkbunny_page() {
+    let server = MockServer::start().await;
+    replay(&server, "POST", "/api_login.php", "inkbunny/login.json").await;
+    replay(
+        &server,
+        "POST",
+        "/api_submissions.php",
+        "inkbunny/submissions.json",
+    )
+    .await;
+    let mut site = inkbunny(&server);
+
+    for url in &[
+        "https://inkbunny.net/s/2458963#p2",
+        "https://inkbunny.net/s/2458963-p2-#pictop",
+    ] {
+        let posts = get_images(&mut site, url).await.unwrap().unwrap();
+
+        assert_eq!(posts.len(), 1, "only the linked page should be loaded");
+        assert_eq!(posts[0].file_type, "jpg");
+        assert_eq!(posts[0].file_number, Some(2));
+        assert_eq!(posts[0].files_count, Some(2));
+    }
+
+    let posts = get_images(&mut site, "https://inkbunny.net/s/2458963#p5")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(posts.len(), 2, "missing pages should load every file");
+}
+
+#[tokio::test]
+async fn test_inkbunny_expired_sid() {
+    let server = MockServer::start().await;
+    replay(&server, "POST", "/api_login.php", "inkbunny/login.json").await;
+    Mock::given(matchers::method("POST"))
+        .and(matchers::path("/api_submissions.php"))
+        .respond_with(json("inkbunny/expired_sid.json"))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    replay(
+        &server,
+        "POST",
+        "/api_submissions.php",
+        "inkbunny/submissions.json",
+    )
+    .await;
+    let mut site = inkbunny(&server);
+
+    let posts = get_images(&mut site, "https://inkbunny.net/s/2458963")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(posts.len(), 2);
+
+    let logins = server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .filter(|req| req.url.path() == "/api_login.php")
+        .count();
+    assert_eq!(logins, 2, "expired session should log in again");
+}
+
+fn deviantart(server: &MockServer) -> DeviantArt {
+    DeviantArt::new(&Proxies::default()).with_endpoint(format!("{}/oembed", server.uri()))
+}
+
+#[tokio::test]
+async fn test_deviantart_matching() {
+    let server = MockServer::start().await;
+    let mut site = deviantart(&server);
+
+    let url = "https://www.deviantart.com/syfaro/art/Fox-in-the-Snow-871240112";
+    assert!(site.match_url(url).await.is_some());
+    assert_eq!(site.url_id(url), Some("DeviantArt-871240112".to_string()));
+    assert_eq!(
+        site.url_id("https://sta.sh/0abcdef123"),
+        Some("DeviantArt-0abcdef123".to_string())
+    );
+    assert!(site
+        .match_url("https://www.deviantart.com/syfaro")
+        .await
+        .is_none());
+}
+
+#[tokio::test]
+async fn test_deviantart_photo() {
+    let server = MockServer::start().await;
+    let url = "https://www.deviantart.com/syfaro/art/Fox-in-the-Snow-871240112";
+    Mock::given(matchers::method("GET"))
+        .and(matchers::path("/oembed"))
+        .and(matchers::query_param("url", url))
+        .respond_with(json("deviantart/oembed.json"))
+        .mount(&server)
+        .await;
+    let mut site = deviantart(&server);
+
+    let posts = get_images(&mut site, url).await.unwrap().unwrap();
+
+    assert_eq!(posts.len(), 1);
+    assert_eq!(
+        posts[0].image_dimensions,
+        Some((1600, 1200)),
+        "dimensions may be numbers or strings"
+    );
+    assert_eq!(posts[0].source_link.as_deref(), Some(url));
+}
+
+#[tokio::test]
+async fn test_deviantart_not_photo() {
+    let server = MockServer::start().await;
+    replay(
+        &server,
+        "GET",
+        "/oembed",
+        "deviantart/oembed_literature.json",
+    )
+    .await;
+    let mut site = deviantart(&server);
+
+    let posts = get_images(
+        &mut site,
+        "https://www.deviantart.com/syfaro/art/A-Story-About-Foxes-871240113",
+    )
+    .await
+    .unwrap();
+    assert!(posts.is_none());
+}
+
+/// Respond to nodeinfo discovery as a host running some fediverse software.
+async fn mock_nodeinfo(server: &MockServer, software: &str) {
+    Mock::given(matchers::method("GET"))
+        .and(matchers::path("/.well-known/nodeinfo"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "links": [
+                {
+                    "rel": "http://nodeinfo.diaspora.software/ns/schema/2.0",
+                    "href": format!("{}/nodeinfo/2.0", server.uri()),
+                },
+            ],
+        })))
+        .mount(server)
+        .await;
+    Mock::given(matchers::method("GET"))
+        .and(matchers::path("/nodeinfo/2.0"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+            "version": "2.0",
+            "software": { "name": software, "version": "1.0.0" },
+        })))
+        .mount(server)
+        .await;
+}
+
+#[tokio::test]
+async fn test_mastodon() {
+    let server = MockServer::start().await;
+    mock_nodeinfo(&server, "mastodon").await;
+    replay(
+        &server,
+        "GET",
+        "/api/v1/statuses/106295130487526031",
+        "mastodon/status.json",
+    )
+    .await;
+    replay(
+        &server,
+        "GET",
+        "/api/v1/statuses/106295130487526099",
+        "mastodon/status_text.json",
+    )
+    .await;
+    let mut site = Mastodon::default();
+
+    let url = format!("{}/@syfaro/106295130487526031", server.uri());
+    assert!(site.match_url(&url).await.is_some());
+    assert_eq!(
+        site.url_id(&url),
+        Some(format!("Mastodon-{}-106295130487526031", server.address()))
+    );
+
+    let posts = get_images(&mut site, &url).await.unwrap().unwrap();
+    let types: Vec<_> = posts.iter().map(|post| post.file_type.as_str()).collect();
+    assert_eq!(types, vec!["png", "jpg"]);
+    assert_eq!(
+        posts[0].source_link.as_deref(),
+        Some("https://mastodon.example/@syfaro/106295130487526031")
+    );
+
+    let text = format!("{}/@syfaro/106295130487526099", server.uri());
+    assert!(get_images(&mut site, &text).await.unwrap().is_none());
+
+    // Statuses that aren't public look missing without being signed in.
+    Mock::given(matchers::method("GET"))
+        .and(matchers::path("/api/v1/statuses/106295130487526077"))
+        .respond_with(ResponseTemplate::new(404))
+        .mount(&server)
+        .await;
+    let private = format!("{}/@syfaro/106295130487526077", server.uri());
+    assert!(get_images(&mut site, &private).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_mastodon_thread() {
+    let server = MockServer::start().await;
+    mock_nodeinfo(&server, "mastodon").await;
+    replay(
+        &server,
+        "GET",
+        "/api/v1/statuses/106295130487526031",
+        "mastodon/status.json",
+    )
+    .await;
+    replay(
+        &server,
+        "GET",
+        "/api/v1/statuses/106295130487526031/context",
+        "mastodon/context.json",
+    )
+    .await;
+    let mut site = Mastodon::default();
+
+    // Threads are only loaded when asked for, and replies from other
+    // accounts are skipped.
+    let url = format!("{}/@syfaro/106295130487526031?thread", server.uri());
+    assert_eq!(
+        site.url_id(&url),
+        Some(format!("Mastodon-{}-106295130487526031", server.address()))
+    );
+
+    let posts = get_images(&mut site, &url).await.unwrap().unwrap();
+    let types: Vec<_> = posts.iter().map(|post| post.file_type.as_str()).collect();
+    assert_eq!(types, vec!["png", "jpg", "gif", "webp"]);
+    let personal: Vec<_> = posts.iter().map(|post| post.personal).collect();
+    assert_eq!(personal, vec![false, false, false, true]);
+    assert!(posts.iter().all(|post| post.source_link.as_deref()
+        == Some("https://mastodon.example/@syfaro/106295130487526031")));
+
+    let url = format!("{}/@syfaro/106295130487526031", server.uri());
+    let posts = get_images(&mut site, &url).await.unwrap().unwrap();
+    assert_eq!(posts.len(), 2);
+}
+
+#[tokio::test]
+async fn test_mastodon_not_instance() {
+    let server = MockServer::start().await;
+    Mock::given(matchers::method("GET"))
+        .and(matchers::path("/.well-known/nodeinfo"))
+        .respond_with(ResponseTemplate::new(404))
+        .mount(&server)
+        .await;
+    let mut site = Mastodon::default();
+
+    assert!(site
+        .match_url(&format!("{}/@syfaro/106295130487526031", server.uri()))
+        .await
+        .is_none());
+
+    // Software without a supported API isn't treated as an instance either.
+    let server = MockServer::start().await;
+    mock_nodeinfo(&server, "peertube").await;
+
+    assert!(site
+        .match_url(&format!("{}/@syfaro/106295130487526031", server.uri()))
+        .await
+        .is_none());
+}
+
+#[tokio::test]
+async fn test_misskey() {
+    let server = MockServer::start().await;
+    mock_nodeinfo(&server, "misskey").await;
+    Mock::given(matchers::method("POST"))
+        .and(matchers::path("/api/notes/show"))
+        .and(matchers::body_json(
+            serde_json::json!({ "noteId": "9dhtanzk8r" }),
+        ))
+        .respond_with(json("mastodon/misskey_note.json"))
+        .mount(&server)
+        .await;
+    let mut site = Mastodon::default();
+
+    let url = format!("{}/notes/9dhtanzk8r", server.uri());
+    assert_eq!(
+        site.url_id(&url),
+        Some(format!("Mastodon-{}-9dhtanzk8r", server.address()))
+    );
+
+    let posts = get_images(&mut site, &url).await.unwrap().unwrap();
+    let types: Vec<_> = posts.iter().map(|post| post.file_type.as_str()).collect();
+    assert_eq!(types, vec!["png", "webp"]);
+    assert_eq!(posts[0].source_link.as_deref(), Some(url.as_str()));
+    assert!(
+        posts.iter().all(|post| post.personal),
+        "followers only notes should be personal"
+    );
+}
+
+#[tokio::test]
+async fn test_match_from_other_site() {
+    let server = MockServer::start().await;
+    let mut weasyl = weasyl(&server);
+    let mut inkbunny = inkbunny(&server);
+
+    let url_match = weasyl
+        .match_url("https://www.weasyl.com/~syfaro/submissions/1765987/fox-sketch")
+        .await
+        .unwrap();
+    assert_eq!(url_match.site(), "Weasyl");
+
+    assert!(
+        inkbunny.get_images(&Anonymous, &url_match).await.is_err(),
+        "sites should refuse urls matched by another site"
+    );
+    assert!(server.received_requests().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_resolver() {
+    let server = MockServer::start().await;
+    replay(
+        &server,
+        "GET",
+        "/submissions/1765987/view",
+        "weasyl/submission.json",
+    )
+    .await;
+
+    let mut resolver = Resolver::new(vec![Box::new(e621(&server)), Box::new(weasyl(&server))]);
+
+    let posts = resolver
+        .resolve("https://www.weasyl.com/~syfaro/submissions/1765987/fox-sketch")
+        .await
+        .unwrap();
+    assert_eq!(posts.len(), 1);
+    assert_eq!(posts[0].site_name, "Weasyl");
+
+    let posts = resolver.resolve("https://example.com/").await.unwrap();
+    assert!(posts.is_empty(), "unsupported urls should have no posts");
+}
+
+async fn furaffinity(server: &MockServer) -> FurAffinity {
+    FurAffinity::new(
+        ("a".to_string(), "b".to_string()),
+        "api-key".to_string(),
+        &Proxies::default(),
+        None,
+        Box::new(MemorySessions::default()),
+    )
+    .await
+    .with_endpoint(server.uri())
+}
+
+#[tokio::test]
+async fn test_furaffinity_matching() {
+    let server = MockServer::start().await;
+    let mut site = furaffinity(&server).await;
+
+    let url = "https://www.furaffinity.net/view/16437648/";
+    assert!(site.match_url(url).await.is_some());
+    assert_eq!(site.url_id(url), Some("FurAffinity-16437648".to_string()));
+
+    let direct = "https://d.furaffinity.net/art/syfaro/1589924938/1589924938.syfaro_fox.png";
+    assert!(site.match_url(direct).await.is_some());
+    assert_eq!(
+        site.url_id(direct),
+        Some("FurAffinityFile-1589924938".to_string())
+    );
+
+    assert!(site
+        .match_url("https://www.furaffinity.net/user/syfaro/")
+        .await
+        .is_none());
+}
+
+#[tokio::test]
+async fn test_furaffinity_submission() {
+    let server = MockServer::start().await;
+    Mock::given(matchers::method("GET"))
+        .and(matchers::path("/view/16437648/"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_raw(fixture("furaffinity/submission.html"), "text/html"),
+        )
+        .mount(&server)
+        .await;
+    let mut site = furaffinity(&server).await;
+
+    let url = "https://www.furaffinity.net/full/16437648/";
+    let posts = get_images(&mut site, url).await.unwrap().unwrap();
+
+    assert_eq!(posts.len(), 1);
+    assert_eq!(posts[0].file_type, "png");
+    assert_eq!(
+        posts[0].url,
+        "https://d.furaffinity.net/art/syfaro/1589924938/1589924938.syfaro_fox.png"
+    );
+    assert_eq!(posts[0].source_link.as_deref(), Some(url));
+    assert_eq!(posts[0].title.as_deref(), Some("Fox in the Snow"));
+    assert_eq!(posts[0].artists, vec!["Syfaro"]);
+    assert!(matches!(
+        posts[0].rating,
+        Some(fuzzysearch::Rating::General)
+    ));
+}
+
+#[tokio::test]
+async fn test_furaffinity_missing_submission() {
+    let server = MockServer::start().await;
+    Mock::given(matchers::method("GET"))
+        .and(matchers::path("/view/16437649/"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(
+            "<html><body><p>The submission you are trying to find is not in our database.</p></body></html>",
+            "text/html",
+        ))
+        .mount(&server)
+        .await;
+    let mut site = furaffinity(&server).await;
+
+    let posts = get_images(&mut site, "https://www.furaffinity.net/view/16437649/")
+        .await
+        .unwrap();
+    assert!(posts.is_none());
+}
+
+/// Twitter responses loaded from fixtures instead of the API.
+struct RecordedTwitter;
+
+#[async_trait::async_trait]
+impl TwitterApi for RecordedTwitter {
+    async fn show(
+        &self,
+        _token: &egg_mode::Token,
+        id: u64,
+    ) -> anyhow::Result<egg_mode::tweet::Tweet> {
+        match id {
+            1397012345678901234 => Ok(serde_json::from_str(&fixture("twitter/tweet.json"))?),
+            _ => anyhow::bail!("no recorded tweet {}", id),
+        }
+    }
+
+    async fn timeline(
+        &self,
+        _token: &egg_mode::Token,
+        _user: egg_mode::user::UserID,
+        _with_replies: bool,
+        _since_id: Option<u64>,
+    ) -> anyhow::Result<Vec<egg_mode::tweet::Tweet>> {
+        Ok(vec![])
+    }
+}
+
+#[tokio::test]
+async fn test_twitter() {
+    let mut site = Twitter::with_api(Box::new(RecordedTwitter));
+
+    let url = "https://twitter.com/Syfaro/status/1397012345678901234";
+    assert_eq!(
+        site.url_id(url),
+        Some("Twitter-1397012345678901234".to_string())
+    );
+
+    let posts = get_images(&mut site, url).await.unwrap().unwrap();
+
+    let types: Vec<_> = posts.iter().map(|post| post.file_type.as_str()).collect();
+    assert_eq!(types, vec!["png", "mp4"]);
+    assert_eq!(
+        posts[0].url,
+        "https://pbs.twimg.com/media/E2KxFoxAbCdEfGh.png"
+    );
+    assert_eq!(
+        posts[0].source_link.as_deref(),
+        Some("https://twitter.com/Syfaro/status/1397012345678901234/photo/1")
+    );
+    assert!(
+        posts[1].url.contains("/1280x720/"),
+        "videos should use the highest bitrate"
+    );
+    assert!(posts.iter().all(|post| !post.personal));
+
+    let missing = "https://twitter.com/Syfaro/status/1397012345678901235";
+    assert!(get_images(&mut site, missing).await.unwrap().is_none());
+}
+
+#[tokio::test]
+async fn test_direct() {
+    let server = MockServer::start().await;
+    Mock::given(matchers::method("HEAD"))
+        .and(matchers::path("/fox.png"))
+        .respond_with(ResponseTemplate::new(200).insert_header("content-type", "image/png"))
+        .mount(&server)
+        .await;
+    Mock::given(matchers::method("GET"))
+        .and(matchers::path("/fox.png"))
+        .respond_with(ResponseTemplate::new(200).set_body_raw(vec![0u8; 16], "image/png"))
+        .mount(&server)
+        .await;
+    Mock::given(matchers::method("HEAD"))
+        .and(matchers::path("/page.png"))
+        .respond_with(ResponseTemplate::new(200).insert_header("content-type", "text/html"))
+        .mount(&server)
+        .await;
+    let mut site = Direct::new("api-key".to_string(), &Proxies::default());
+
+    let url = format!("{}/fox.png", server.uri());
+    let posts = get_images(&mut site, &url).await.unwrap().unwrap();
+
+    assert_eq!(posts.len(), 1);
+    assert_eq!(posts[0].file_type, "png");
+    assert_eq!(posts[0].url, url);
+
+    assert!(
+        site.match_url(&format!("{}/page.png", server.uri()))
+            .await
+            .is_none(),
+        "links that aren't images should be skipped"
+    );
+    assert!(
+        site.match_url(&format!("{}/missing.png", server.uri()))
+            .await
+            .is_none(),
+        "links that can't be loaded should be skipped"
+    );
+}
@@ -0,0 +1,184 @@
+use foxbot_sites::{
+    credentials::MemorySessions, BoxedSite, DeviantArt, Direct, E621Host, FurAffinity, Inkbunny,
+    Mastodon, Proxies, Site, Weasyl, E621,
+};
+
+mod common;
+
+/// A URL from the corpus and what should handle it.
+struct Case {
+    site: String,
+    url_id: Option<String>,
+    url: String,
+}
+
+fn corpus() -> Vec<Case> {
+    common::fixture("urls.tsv")
+        .lines()
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let mut parts = line.splitn(3, '\t');
+            let site = parts.next().unwrap().to_string();
+            let url_id = parts.next().expect("corpus line was missing url_id");
+            let url = parts
+                .next()
+                .expect("corpus line was missing url")
+                .to_string();
+
+            Case {
+                site,
+                url_id: Some(url_id).filter(|id| *id != "-").map(str::to_string),
+                url,
+            }
+        })
+        .collect()
+}
+
+/// Every site that can be created without network access.
+///
+/// Twitter needs to authenticate when it is created so it isn't checked, but
+/// other sites still must not claim its URLs.
+async fn sites() -> Vec<BoxedSite> {
+    let proxies = Proxies::default();
+
+    vec![
+        Box::new(E621::new(
+            E621Host::E621,
+            String::new(),
+            String::new(),
+            &proxies,
+        )),
+        Box::new(E621::new(
+            E621Host::E926,
+            String::new(),
+            String::new(),
+            &proxies,
+        )),
+        Box::new(
+            FurAffinity::new(
+                (String::new(), String::new()),
+                String::new(),
+                &proxies,
+                None,
+                Box::new(MemorySessions::default()),
+            )
+            .await,
+        ),
+        Box::new(Weasyl::new(String::new(), &proxies)),
+        Box::new(Inkbunny::new(String::new(), String::new(), &proxies)),
+        Box::new(DeviantArt::new(&proxies)),
+        Box::new(Mastodon::new(&proxies)),
+    ]
+}
+
+#[tokio::test]
+async fn test_url_corpus() {
+    let corpus = corpus();
+    assert!(corpus.len() > 100, "corpus should have been loaded");
+
+    let mut sites = sites().await;
+    let mut failures = Vec::new();
+
+    for case in &corpus {
+        for site in sites.iter_mut() {
+            let name = site.name();
+            let url_id = site.url_id(&case.url);
+
+            // Mastodon checks if the host is an instance, so only the ID
+            // can be checked without the network.
+            let supported = if name == "Mastodon" {
+                url_id.is_some()
+            } else {
+                site.match_url(&case.url).await.is_some()
+            };
+
+            if name == case.site {
+                if !supported {
+                    failures.push(format!("{} should support {:?}", name, case.url));
+                }
+
+                if url_id != case.url_id {
+                    failures.push(format!(
+                        "{} gave {:?} the ID {:?}, expected {:?}",
+                        name, case.url, url_id, case.url_id
+                    ));
+                }
+            } else if supported || url_id.is_some() {
+                failures.push(format!(
+                    "{} claimed {:?}, which belongs to {}",
+                    name, case.url, case.site
+                ));
+            }
+        }
+    }
+
+    assert!(
+        failures.is_empty(),
+        "{} corpus failures:\n{}",
+        failures.len(),
+        failures.join("\n")
+    );
+}
+
+#[tokio::test]
+async fn test_equivalent_url_ids() {
+    let mut sites = sites().await;
+    sites.push(Box::new(Direct::new(String::new(), &Proxies::default())));
+
+    let groups: &[&[&str]] = &[
+        &[
+            "https://example.com/image.png",
+            "http://example.com/image.png",
+            "HTTPS://EXAMPLE.COM/image.png",
+            "https://example.com:443/image.png",
+            "example.com/image.png",
+        ],
+        &[
+            "https://e621.net/posts/2785411",
+            "http://e621.net/posts/2785411",
+            "https://e621.net/posts/0002785411",
+        ],
+        &[
+            "https://www.furaffinity.net/view/16437648/",
+            "https://furaffinity.net/full/16437648",
+            "https://www.furaffinity.net/view/016437648",
+        ],
+    ];
+
+    for group in groups {
+        let ids: Vec<_> = group
+            .iter()
+            .map(|url| sites.iter().find_map(|site| site.url_id(url)))
+            .collect();
+
+        assert!(ids[0].is_some(), "{} should have an ID", group[0]);
+        assert!(
+            ids.iter().all(|id| id == &ids[0]),
+            "equivalent urls had different IDs: {:?}",
+            group.iter().zip(ids.iter()).collect::<Vec<_>>()
+        );
+    }
+
+    let direct = Direct::new(String::new(), &Proxies::default());
+    assert_ne!(
+        direct.url_id("https://example.com/image.png"),
+        direct.url_id("https://example.com/other.png")
+    );
+    assert_ne!(
+        direct.url_id("https://example.com/image.png"),
+        direct.url_id("https://example.com:8443/image.png")
+    );
+}
+
+#[test]
+fn test_get_file_ext() {
+    use foxbot_sites::get_file_ext;
+
+    assert_eq!(get_file_ext("https://example.com/image.png"), Some("png"));
+    assert_eq!(
+        get_file_ext("https://example.com/image.jpg?size=large"),
+        Some("jpg")
+    );
+    assert_eq!(get_file_ext("image.tar.gz"), Some("gz"));
+    assert_eq!(get_file_ext(""), Some(""));
+}
@@ -0,0 +1,571 @@
+use anyhow::Context;
+use tgbotapi::requests::TelegramRequest;
+
+use crate::rate_limit::RequestLimiter;
+
+/// Largest file, in bytes, bots may download from Telegram's hosted Bot API.
+pub const HOSTED_DOWNLOAD_LIMIT: usize = 20_000_000;
+
+/// Largest file, in bytes, downloaded through a self-hosted Bot API server.
+///
+/// The server allows files up to 2GB, but larger images aren't worth hashing.
+pub const LOCAL_DOWNLOAD_LIMIT: usize = 200_000_000;
+
+/// Create a Telegram client, using a self-hosted Bot API server if an
+/// endpoint was provided.
+pub fn telegram_client(token: String, api_endpoint: Option<String>) -> tgbotapi::Telegram {
+    match api_endpoint {
+        Some(api_endpoint) => {
+            tracing::info!(%api_endpoint, "using self-hosted bot api server");
+
+            tgbotapi::Telegram::new_with_endpoint(token, api_endpoint)
+        }
+        None => tgbotapi::Telegram::new(token),
+    }
+}
+
+/// Largest file that can be downloaded from the Bot API server in use.
+pub fn download_limit(api_endpoint: Option<&str>) -> usize {
+    if api_endpoint.is_some() {
+        LOCAL_DOWNLOAD_LIMIT
+    } else {
+        HOSTED_DOWNLOAD_LIMIT
+    }
+}
+
+/// Download a file that was sent to the bot.
+///
+/// A self-hosted Bot API server running with `--local` returns paths on its
+/// own filesystem instead of paths to download, so those are read directly.
+/// This requires the server's working directory to be mounted at the same
+/// path.
+///
+/// Files larger than [`LOCAL_DOWNLOAD_LIMIT`] are rejected, as nothing that
+/// large is worth hashing.
+#[tracing::instrument(err, skip(bot))]
+pub async fn download_file(bot: &tgbotapi::Telegram, file_id: &str) -> anyhow::Result<Vec<u8>> {
+    use tokio::io::AsyncReadExt;
+
+    let get_file = tgbotapi::requests::GetFile {
+        file_id: file_id.to_string(),
+    };
+
+    let file_info = bot
+        .make_request(&get_file)
+        .await
+        .context("unable to request file info from telegram")?;
+
+    if let Some(file_size) = file_info.file_size {
+        check_download_size(file_size as usize)?;
+    }
+
+    let file_path = file_info
+        .file_path
+        .context("telegram did not return file path")?;
+
+    if is_local_path(&file_path) {
+        tracing::trace!("reading file from local bot api server");
+
+        let file = tokio::fs::File::open(&file_path)
+            .await
+            .context("unable to open file from local bot api server")?;
+
+        // Read one byte past the limit, so a file that grew after Telegram
+        // reported its size is still caught.
+        let mut data = Vec::new();
+        file.take(LOCAL_DOWNLOAD_LIMIT as u64 + 1)
+            .read_to_end(&mut data)
+            .await
+            .context("unable to read file from local bot api server")?;
+        check_download_size(data.len())?;
+
+        Ok(data)
+    } else {
+        let data = bot
+            .download_file(&file_path)
+            .await
+            .context("unable to download file from telegram")?;
+        check_download_size(data.len())?;
+
+        Ok(data)
+    }
+}
+
+/// Check that a file isn't larger than any file we're willing to download.
+fn check_download_size(size: usize) -> anyhow::Result<()> {
+    if size > LOCAL_DOWNLOAD_LIMIT {
+        tracing::warn!(
+            size,
+            max_bytes = LOCAL_DOWNLOAD_LIMIT,
+            "telegram file is larger than max size"
+        );
+        anyhow::bail!("file is larger than maximum permissible download");
+    }
+
+    Ok(())
+}
+
+/// A reaction that can be added to a message.
+#[derive(serde::Serialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ReactionType {
+    Emoji { emoji: String },
+}
+
+/// Change the bot's reactions on a message.
+///
+/// tgbotapi doesn't support reactions yet.
+#[derive(serde::Serialize, Debug)]
+pub struct SetMessageReaction {
+    pub chat_id: tgbotapi::requests::ChatID,
+    pub message_id: i32,
+    pub reaction: Vec<ReactionType>,
+}
+
+impl tgbotapi::requests::TelegramRequest for SetMessageReaction {
+    type Response = bool;
+
+    fn endpoint(&self) -> &str {
+        "setMessageReaction"
+    }
+}
+
+/// A command shown in the menu of commands.
+#[derive(serde::Serialize, Debug, Clone, PartialEq)]
+pub struct BotCommand {
+    /// Name of the command, without the leading slash.
+    pub command: String,
+    pub description: String,
+}
+
+/// Chats a list of commands is shown in.
+#[derive(serde::Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BotCommandScope {
+    Default,
+    AllPrivateChats,
+    AllChatAdministrators,
+}
+
+/// Change the commands shown for a scope and language.
+///
+/// tgbotapi doesn't support command scopes yet.
+#[derive(serde::Serialize, Debug)]
+pub struct SetMyCommands {
+    pub commands: Vec<BotCommand>,
+    pub scope: BotCommandScope,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language_code: Option<String>,
+}
+
+impl tgbotapi::requests::TelegramRequest for SetMyCommands {
+    type Response = bool;
+
+    fn endpoint(&self) -> &str {
+        "setMyCommands"
+    }
+}
+
+/// Get the number of members in a chat.
+///
+/// tgbotapi doesn't support this request yet.
+#[derive(serde::Serialize, Debug)]
+pub struct GetChatMemberCount {
+    pub chat_id: tgbotapi::requests::ChatID,
+}
+
+impl tgbotapi::requests::TelegramRequest for GetChatMemberCount {
+    type Response = i32;
+
+    fn endpoint(&self) -> &str {
+        "getChatMemberCount"
+    }
+}
+
+/// If a file path returned by Telegram is on the Bot API server's filesystem.
+fn is_local_path(file_path: &str) -> bool {
+    std::path::Path::new(file_path).is_absolute()
+}
+
+/// How an error from Telegram should be handled.
+#[derive(Debug, PartialEq)]
+pub enum ErrorKind {
+    /// Rate limited, the request may be sent again after this many seconds.
+    RetryAfter(u64),
+    /// A network error or problem on Telegram's side that may go away.
+    Transient,
+    /// The request will never succeed as it was sent.
+    Terminal,
+}
+
+/// Decide if a request that failed is worth trying again.
+pub fn classify_error(err: &tgbotapi::Error) -> ErrorKind {
+    match err {
+        tgbotapi::Error::Telegram(tgbotapi::TelegramError {
+            parameters:
+                Some(tgbotapi::ResponseParameters {
+                    retry_after: Some(retry_after),
+                    ..
+                }),
+            ..
+        }) => ErrorKind::RetryAfter(*retry_after as u64),
+        tgbotapi::Error::Telegram(tgbotapi::TelegramError {
+            error_code: Some(code),
+            ..
+        }) if *code >= 500 => ErrorKind::Transient,
+        tgbotapi::Error::Telegram(tgbotapi::TelegramError {
+            error_code: Some(400),
+            description: Some(desc),
+            ..
+        }) if desc == "Bad Request: wrong file_id or the file is temporarily unavailable" => {
+            ErrorKind::Transient
+        }
+        tgbotapi::Error::Request(_) => ErrorKind::Transient,
+        _ => ErrorKind::Terminal,
+    }
+}
+
+/// Number of times a request that failed with a transient error is retried.
+const TRANSIENT_RETRIES: u32 = 3;
+
+/// Seconds an idempotency record is kept, longer than any job will be retried
+/// for.
+const IDEMPOTENCY_TTL: usize = 60 * 60 * 24;
+
+/// Seconds a request may be claimed before it was sent. If the process
+/// crashes before sending it, the request may be made again after this.
+const IDEMPOTENCY_PENDING_TTL: usize = 60;
+
+/// How often a pending claim is extended while its request is being made, as
+/// waiting for the limiter and retries may take longer than the claim lasts.
+const IDEMPOTENCY_REFRESH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(20);
+
+/// If making a request again would do something twice, like sending a
+/// message, instead of setting something to the same value.
+fn is_repeatable(endpoint: &str) -> bool {
+    !["send", "forward", "copy"]
+        .iter()
+        .any(|prefix| endpoint.starts_with(prefix))
+}
+
+/// If an idempotency key may be released after a request failed. Telegram may
+/// have already received a request that failed with a network error, so only
+/// repeatable requests or ones Telegram rejected may be made again.
+fn should_release_key(endpoint: &str, err: &tgbotapi::Error) -> bool {
+    matches!(err, tgbotapi::Error::Telegram(_)) || is_repeatable(endpoint)
+}
+
+/// A Telegram client that waits for the [`RequestLimiter`] before making
+/// requests and retries transient failures.
+///
+/// It dereferences to the underlying client for anything that shouldn't be
+/// limited, like downloading files.
+pub struct TelegramClient {
+    bot: tgbotapi::Telegram,
+    limiter: RequestLimiter,
+    redis: redis::aio::ConnectionManager,
+}
+
+impl TelegramClient {
+    pub fn new(bot: tgbotapi::Telegram, redis: redis::aio::ConnectionManager) -> Self {
+        Self {
+            bot,
+            limiter: RequestLimiter::new(redis.clone()),
+            redis,
+        }
+    }
+
+    /// Make a request, retrying network errors and server problems with
+    /// backoff. Being rate limited is left for the caller to handle.
+    ///
+    /// Requests that send something aren't retried after network errors, as
+    /// Telegram may have already received them.
+    pub async fn make_request<T>(&self, request: &T) -> Result<T::Response, tgbotapi::Error>
+    where
+        T: TelegramRequest,
+    {
+        let mut attempt = 0;
+
+        loop {
+            self.limiter.wait(request).await;
+
+            let err = match self.bot.make_request(request).await {
+                Ok(resp) => return Ok(resp),
+                Err(err) => err,
+            };
+
+            if attempt >= TRANSIENT_RETRIES || classify_error(&err) != ErrorKind::Transient {
+                return Err(err);
+            }
+
+            if matches!(err, tgbotapi::Error::Request(_)) && !is_repeatable(request.endpoint()) {
+                return Err(err);
+            }
+
+            let delay = std::time::Duration::from_millis(500 * 2u64.pow(attempt));
+            tracing::warn!(attempt, ?delay, "transient telegram error: {:?}", err);
+
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    /// Make a request at most once for a key, such as a job ID, so a job that
+    /// runs again after a crash doesn't send the same message twice.
+    ///
+    /// Returns `None` if the request was already made, or is being made. If
+    /// Telegram rejects the request the key is released so it may be tried
+    /// again. If it's unknown if Telegram received a request that can't be
+    /// repeated, the key is kept so it's never made twice.
+    pub async fn make_idempotent_request<T>(
+        &self,
+        key: &str,
+        request: &T,
+    ) -> Result<Option<T::Response>, tgbotapi::Error>
+    where
+        T: TelegramRequest,
+    {
+        let key = format!("idempotency:{}", key);
+        let mut redis = self.redis.clone();
+
+        let claimed: Result<Option<String>, _> = redis::cmd("SET")
+            .arg(&key)
+            .arg("pending")
+            .arg("NX")
+            .arg("EX")
+            .arg(IDEMPOTENCY_PENDING_TTL)
+            .query_async(&mut redis)
+            .await;
+
+        // Sending twice is better than never sending if Redis is unavailable.
+        match claimed {
+            Ok(Some(_)) => (),
+            Ok(None) => {
+                tracing::info!(%key, "request was already made, skipping");
+                return Ok(None);
+            }
+            Err(err) => tracing::warn!("unable to claim idempotency key: {:?}", err),
+        }
+
+        let result = self.make_pending_request(&key, request).await;
+
+        let recorded = match &result {
+            Ok(_) => {
+                redis::cmd("SET")
+                    .arg(&key)
+                    .arg("sent")
+                    .arg("EX")
+                    .arg(IDEMPOTENCY_TTL)
+                    .query_async::<_, ()>(&mut redis)
+                    .await
+            }
+            Err(err) if should_release_key(request.endpoint(), err) => {
+                redis::cmd("DEL")
+                    .arg(&key)
+                    .query_async::<_, ()>(&mut redis)
+                    .await
+            }
+            Err(_) => {
+                redis::cmd("SET")
+                    .arg(&key)
+                    .arg("unknown")
+                    .arg("EX")
+                    .arg(IDEMPOTENCY_TTL)
+                    .query_async::<_, ()>(&mut redis)
+                    .await
+            }
+        };
+
+        if let Err(err) = recorded {
+            tracing::warn!("unable to update idempotency key: {:?}", err);
+        }
+
+        result.map(Some)
+    }
+
+    /// Make a request, extending the pending claim on a key until it finishes
+    /// so another attempt can't claim it while this one is still running.
+    async fn make_pending_request<T>(
+        &self,
+        key: &str,
+        request: &T,
+    ) -> Result<T::Response, tgbotapi::Error>
+    where
+        T: TelegramRequest,
+    {
+        use futures::{
+            future::{self, Either},
+            pin_mut,
+        };
+
+        let mut redis = self.redis.clone();
+
+        let result = self.make_request(request);
+        pin_mut!(result);
+
+        loop {
+            let refresh = tokio::time::sleep(IDEMPOTENCY_REFRESH_INTERVAL);
+            pin_mut!(refresh);
+
+            match future::select(&mut result, refresh).await {
+                Either::Left((result, _refresh)) => return result,
+                Either::Right(_) => {
+                    tracing::debug!(%key, "request still pending, extending claim");
+
+                    if let Err(err) = redis::cmd("EXPIRE")
+                        .arg(key)
+                        .arg(IDEMPOTENCY_PENDING_TTL)
+                        .query_async::<_, ()>(&mut redis)
+                        .await
+                    {
+                        tracing::warn!("unable to extend idempotency key: {:?}", err);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl std::ops::Deref for TelegramClient {
+    type Target = tgbotapi::Telegram;
+
+    fn deref(&self) -> &Self::Target {
+        &self.bot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_is_repeatable() {
+        use super::is_repeatable;
+
+        assert!(is_repeatable("editMessageCaption"));
+        assert!(!is_repeatable("sendMessage"));
+        assert!(!is_repeatable("forwardMessage"));
+    }
+
+    #[test]
+    fn test_should_release_key() {
+        use super::should_release_key;
+
+        let rejected = tgbotapi::Error::Telegram(
+            serde_json::from_value(serde_json::json!({
+                "error_code": 400,
+                "description": "Bad Request: chat not found",
+            }))
+            .unwrap(),
+        );
+        assert!(should_release_key("sendMessage", &rejected));
+        assert!(should_release_key("editMessageCaption", &rejected));
+
+        let network =
+            tgbotapi::Error::Request(reqwest::Client::new().get("not a url").build().unwrap_err());
+        assert!(
+            !should_release_key("sendMessage", &network),
+            "sends that may have reached telegram should keep their key"
+        );
+        assert!(should_release_key("editMessageCaption", &network));
+    }
+
+    #[test]
+    fn test_check_download_size() {
+        use super::{check_download_size, LOCAL_DOWNLOAD_LIMIT};
+
+        assert!(check_download_size(LOCAL_DOWNLOAD_LIMIT).is_ok());
+        assert!(check_download_size(LOCAL_DOWNLOAD_LIMIT + 1).is_err());
+    }
+
+    #[test]
+    fn test_is_local_path() {
+        use super::is_local_path;
+
+        assert!(!is_local_path("photos/file_0.jpg"));
+        assert!(is_local_path(
+            "/var/lib/telegram-bot-api/123456:ABC/photos/file_0.jpg"
+        ));
+    }
+
+    #[test]
+    fn test_set_message_reaction() {
+        use super::{ReactionType, SetMessageReaction};
+
+        let reaction = SetMessageReaction {
+            chat_id: (-100123).into(),
+            message_id: 42,
+            reaction: vec![ReactionType::Emoji {
+                emoji: "👀".to_string(),
+            }],
+        };
+
+        assert_eq!(
+            serde_json::to_value(&reaction).unwrap(),
+            serde_json::json!({
+                "chat_id": -100123,
+                "message_id": 42,
+                "reaction": [{ "type": "emoji", "emoji": "👀" }],
+            })
+        );
+    }
+
+    #[test]
+    fn test_set_my_commands() {
+        use super::{BotCommand, BotCommandScope, SetMyCommands};
+
+        let commands = SetMyCommands {
+            commands: vec![BotCommand {
+                command: "help".to_string(),
+                description: "Show commands".to_string(),
+            }],
+            scope: BotCommandScope::AllPrivateChats,
+            language_code: None,
+        };
+
+        assert_eq!(
+            serde_json::to_value(&commands).unwrap(),
+            serde_json::json!({
+                "commands": [{ "command": "help", "description": "Show commands" }],
+                "scope": { "type": "all_private_chats" },
+            })
+        );
+    }
+
+    #[test]
+    fn test_classify_error() {
+        use super::{classify_error, ErrorKind};
+
+        let telegram_error = |value: serde_json::Value| {
+            tgbotapi::Error::Telegram(serde_json::from_value(value).unwrap())
+        };
+
+        let rate_limited = telegram_error(serde_json::json!({
+            "error_code": 429,
+            "description": "Too Many Requests: retry after 5",
+            "parameters": { "retry_after": 5 },
+        }));
+        assert_eq!(classify_error(&rate_limited), ErrorKind::RetryAfter(5));
+
+        let server_error = telegram_error(serde_json::json!({
+            "error_code": 502,
+            "description": "Bad Gateway",
+        }));
+        assert_eq!(classify_error(&server_error), ErrorKind::Transient);
+
+        let forbidden = telegram_error(serde_json::json!({
+            "error_code": 403,
+            "description": "Forbidden: bot was blocked by the user",
+        }));
+        assert_eq!(classify_error(&forbidden), ErrorKind::Terminal);
+    }
+
+    #[test]
+    fn test_download_limit() {
+        use super::{download_limit, HOSTED_DOWNLOAD_LIMIT, LOCAL_DOWNLOAD_LIMIT};
+
+        assert_eq!(download_limit(None), HOSTED_DOWNLOAD_LIMIT);
+        assert_eq!(
+            download_limit(Some("http://telegram-bot-api:8081")),
+            LOCAL_DOWNLOAD_LIMIT
+        );
+    }
+}
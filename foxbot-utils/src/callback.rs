@@ -0,0 +1,194 @@
+//! Compact data attached to inline keyboard buttons.
+//!
+//! Telegram limits callback data to 64 bytes. Data is encoded as a version,
+//! a route naming what should handle it, and any arguments, separated by
+//! colons, like `1:wrong-source:1234`. Buttons sent before data was versioned
+//! are still decoded, using everything before the first colon (or the dash
+//! before a trailing number) as the route.
+
+use std::str::FromStr;
+
+/// Version prefix of the current format.
+const VERSION: &str = "1";
+
+/// Separator between each part of callback data.
+const SEPARATOR: char = ':';
+
+/// Most bytes Telegram allows in callback data.
+pub const MAX_CALLBACK_DATA_LEN: usize = 64;
+
+/// Routes of buttons created away from the handler for them.
+pub mod routes {
+    /// Show the sources for a forwarded image.
+    pub const FORWARDED_SOURCE: &str = "group-forwarded-source";
+    /// Report the sources in a reply as wrong, with the image's hash.
+    pub const WRONG_SOURCE: &str = "wrong-source";
+    /// Choose between artists, with the index of the choice.
+    pub const ARTIST_CHOICE: &str = "artist-choice";
+    /// Notify when sources are found for an image, with the image's hash.
+    pub const NOTIFY: &str = "notify";
+    /// Watch for reposts of an image, with the image's hash.
+    pub const REPOST: &str = "repost";
+    /// Stop watching for reposts of an image, with the image's hash.
+    pub const REPOST_STOP: &str = "repost-stop";
+    /// Edit an image before sharing it, with the token of the shared results
+    /// and the name of the edit.
+    pub const EDIT_IMAGE: &str = "edit-image";
+}
+
+/// A route and its arguments from a button.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CallbackData {
+    pub route: String,
+    pub args: Vec<String>,
+}
+
+impl CallbackData {
+    pub fn new(route: &str) -> Self {
+        Self {
+            route: route.to_string(),
+            args: vec![],
+        }
+    }
+
+    /// Add an argument.
+    pub fn arg<A: ToString>(mut self, arg: A) -> Self {
+        self.args.push(arg.to_string());
+        self
+    }
+
+    /// Parse the argument at an index.
+    pub fn get<T: FromStr>(&self, index: usize) -> Option<T> {
+        self.args.get(index)?.parse().ok()
+    }
+
+    /// Encode the data for a button, failing if it contains a separator or is
+    /// too long for Telegram.
+    pub fn encode(&self) -> anyhow::Result<String> {
+        if std::iter::once(&self.route)
+            .chain(&self.args)
+            .any(|part| part.contains(SEPARATOR))
+        {
+            anyhow::bail!("callback data parts must not contain '{}'", SEPARATOR);
+        }
+
+        let mut data = String::from(VERSION);
+        for part in std::iter::once(&self.route).chain(&self.args) {
+            data.push(SEPARATOR);
+            data.push_str(part);
+        }
+
+        if data.len() > MAX_CALLBACK_DATA_LEN {
+            anyhow::bail!("callback data was {} bytes: {}", data.len(), data);
+        }
+
+        Ok(data)
+    }
+
+    /// Decode data from a button, in either the current or legacy format.
+    pub fn decode(data: &str) -> Option<Self> {
+        if data.is_empty() {
+            return None;
+        }
+
+        let (route, args) = match data
+            .strip_prefix(VERSION)
+            .and_then(|data| data.strip_prefix(SEPARATOR))
+        {
+            Some(data) => split_route(data),
+            None => decode_legacy(data),
+        };
+
+        Some(Self {
+            route: route.to_string(),
+            args: args.into_iter().map(str::to_string).collect(),
+        })
+    }
+}
+
+fn split_route(data: &str) -> (&str, Vec<&str>) {
+    let mut parts = data.split(SEPARATOR);
+    let route = parts.next().unwrap_or_default();
+
+    (route, parts.collect())
+}
+
+/// Decode data from buttons sent before it was versioned, like `s:order:` or
+/// `notify-1234`.
+fn decode_legacy(data: &str) -> (&str, Vec<&str>) {
+    if data.contains(SEPARATOR) {
+        return split_route(data);
+    }
+
+    // Hashes may be negative, so the first dash followed by only a number
+    // separates the route from its argument.
+    data.match_indices('-')
+        .map(|(index, _)| (&data[..index], &data[index + 1..]))
+        .find(|(_route, arg)| arg.parse::<i64>().is_ok())
+        .map(|(route, arg)| (route, vec![arg]))
+        .unwrap_or((data, vec![]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CallbackData;
+
+    #[test]
+    fn test_callback_data() {
+        let data = CallbackData::new("wrong-source").arg(-1234);
+        let encoded = data.encode().unwrap();
+        assert_eq!(encoded, "1:wrong-source:-1234");
+        assert_eq!(CallbackData::decode(&encoded), Some(data.clone()));
+        assert_eq!(data.get::<i64>(0), Some(-1234));
+
+        assert!(
+            CallbackData::new("route").arg("a:b").encode().is_err(),
+            "separators should not be allowed in arguments"
+        );
+        assert!(
+            CallbackData::new("route")
+                .arg("a".repeat(64))
+                .encode()
+                .is_err(),
+            "data should fit in telegram's limit"
+        );
+    }
+
+    #[test]
+    fn test_decode_legacy() {
+        let decode = |data| {
+            let data = CallbackData::decode(data).unwrap();
+            (data.route, data.args)
+        };
+
+        assert_eq!(
+            decode("s:order:FurAffinity:2"),
+            (
+                "s".to_string(),
+                vec![
+                    "order".to_string(),
+                    "FurAffinity".to_string(),
+                    "2".to_string()
+                ]
+            )
+        );
+        assert_eq!(
+            decode("notify-1234"),
+            ("notify".to_string(), vec!["1234".to_string()])
+        );
+        assert_eq!(
+            decode("repost-stop--1234"),
+            ("repost-stop".to_string(), vec!["-1234".to_string()])
+        );
+        assert_eq!(
+            decode("notify--1234"),
+            ("notify".to_string(), vec!["-1234".to_string()])
+        );
+        assert_eq!(
+            decode("group-forwarded-source"),
+            ("group-forwarded-source".to_string(), vec![])
+        );
+        assert_eq!(decode("delete"), ("delete".to_string(), vec![]));
+        assert_eq!(CallbackData::decode(""), None);
+    }
+}
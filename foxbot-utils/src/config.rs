@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::Context;
+
+use crate::secrets::SecretProvider;
+
+/// Load configuration from environment variables, using values from the TOML
+/// or YAML file at `CONFIG_FILE` for anything not set in the environment.
+///
+/// Keys in the file are the lowercase names of the environment variables.
+/// Tables may be used to group keys, so `endpoint` within an `s3` table is
+/// the same as `S3_ENDPOINT`.
+pub fn load_config<T: serde::de::DeserializeOwned>() -> anyhow::Result<T> {
+    load_config_with(HashMap::new())
+}
+
+/// Load configuration like [`load_config`], but with the named secrets from a
+/// provider taking precedence over the file.
+pub async fn load_config_with_secrets<T: serde::de::DeserializeOwned>(
+    provider: &dyn SecretProvider,
+    names: &[&str],
+) -> anyhow::Result<T> {
+    let secrets = provider
+        .get_many(names)
+        .await
+        .context("unable to load secrets")?;
+
+    load_config_with(secrets)
+}
+
+fn load_config_with<T: serde::de::DeserializeOwned>(
+    secrets: HashMap<String, String>,
+) -> anyhow::Result<T> {
+    let mut values = match std::env::var("CONFIG_FILE") {
+        Ok(path) => read_config_file(Path::new(&path))?,
+        Err(_) => HashMap::new(),
+    };
+
+    values.extend(secrets);
+    values.extend(std::env::vars());
+
+    envy::from_iter(values).map_err(|err| match err {
+        envy::Error::MissingValue(field) => anyhow::anyhow!(
+            "missing configuration value {}, set it in the environment or {} in CONFIG_FILE",
+            field.to_uppercase(),
+            field
+        ),
+        envy::Error::Custom(message) => anyhow::anyhow!("invalid configuration: {}", message),
+    })
+}
+
+/// Read a configuration file into environment variable names and values.
+fn read_config_file(path: &Path) -> anyhow::Result<HashMap<String, String>> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("unable to read config file {}", path.display()))?;
+
+    let value: serde_json::Value = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("toml") => toml::from_str(&contents)
+            .with_context(|| format!("unable to parse TOML in {}", path.display()))?,
+        Some("yaml") | Some("yml") => serde_yaml::from_str(&contents)
+            .with_context(|| format!("unable to parse YAML in {}", path.display()))?,
+        _ => anyhow::bail!(
+            "config file {} must have a toml, yaml, or yml extension",
+            path.display()
+        ),
+    };
+
+    let mut values = HashMap::new();
+    flatten_value(None, value, &mut values)?;
+
+    Ok(values)
+}
+
+/// Flatten nested tables into environment variable names, joining arrays with
+/// commas as envy expects.
+fn flatten_value(
+    prefix: Option<&str>,
+    value: serde_json::Value,
+    values: &mut HashMap<String, String>,
+) -> anyhow::Result<()> {
+    use serde_json::Value;
+
+    let name = || prefix.unwrap_or_default().to_uppercase();
+
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map {
+                let key = match prefix {
+                    Some(prefix) => format!("{}_{}", prefix, key),
+                    None => key,
+                };
+
+                flatten_value(Some(&key), value, values)?;
+            }
+        }
+        Value::Array(items) => {
+            let items = items
+                .into_iter()
+                .map(|item| match item {
+                    Value::String(s) => Ok(s),
+                    Value::Number(n) => Ok(n.to_string()),
+                    Value::Bool(b) => Ok(b.to_string()),
+                    _ => anyhow::bail!("{} may only contain strings, numbers, or booleans", name()),
+                })
+                .collect::<anyhow::Result<Vec<_>>>()?;
+
+            values.insert(name(), items.join(","));
+        }
+        Value::String(s) => {
+            values.insert(name(), s);
+        }
+        Value::Number(n) => {
+            values.insert(name(), n.to_string());
+        }
+        Value::Bool(b) => {
+            values.insert(name(), b.to_string());
+        }
+        Value::Null => (),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_flatten_value() {
+        use super::flatten_value;
+
+        let value = serde_json::json!({
+            "telegram_apitoken": "123:abc",
+            "cache_images": true,
+            "admin_ids": [1, 2],
+            "s3": {
+                "endpoint": "https://s3.example.com",
+            },
+            "sentry_dsn": null,
+        });
+
+        let mut values = std::collections::HashMap::new();
+        flatten_value(None, value, &mut values).unwrap();
+
+        assert_eq!(values["TELEGRAM_APITOKEN"], "123:abc");
+        assert_eq!(values["CACHE_IMAGES"], "true");
+        assert_eq!(values["ADMIN_IDS"], "1,2");
+        assert_eq!(values["S3_ENDPOINT"], "https://s3.example.com");
+        assert!(!values.contains_key("SENTRY_DSN"));
+
+        let mut values = std::collections::HashMap::new();
+        assert!(flatten_value(
+            None,
+            serde_json::json!({ "admin_ids": [{ "id": 1 }] }),
+            &mut values
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,182 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use tokio::sync::Semaphore;
+use tracing_futures::Instrument;
+
+/// Most images to decode or hash at once.
+const DECODE_WORKERS: usize = 4;
+/// Most tasks that may be waiting for a worker before more are rejected.
+const DECODE_MAX_QUEUED: usize = 64;
+/// Seconds to wait for a task to finish, including time spent waiting for a
+/// worker.
+const DECODE_TIMEOUT: u64 = 30;
+
+lazy_static::lazy_static! {
+    static ref POOL: DecodePool = DecodePool::new(
+        DECODE_WORKERS,
+        DECODE_MAX_QUEUED,
+        Duration::from_secs(DECODE_TIMEOUT),
+    );
+
+    static ref DECODE_QUEUED: prometheus::IntGauge = prometheus::register_int_gauge!("foxbot_decode_queued", "Number of image tasks waiting for a decode worker").unwrap();
+    static ref DECODE_REJECTED: prometheus::IntCounter = prometheus::register_int_counter!("foxbot_decode_rejected_total", "Number of image tasks rejected because the decode queue was full").unwrap();
+}
+
+/// A limited number of blocking threads for CPU heavy image work, so a burst
+/// of large images can't take over the runtime's blocking threads.
+pub struct DecodePool {
+    permits: Arc<Semaphore>,
+    queued: AtomicUsize,
+    max_queued: usize,
+    timeout: Duration,
+}
+
+impl DecodePool {
+    pub fn new(workers: usize, max_queued: usize, timeout: Duration) -> Self {
+        Self {
+            permits: Arc::new(Semaphore::new(workers)),
+            queued: AtomicUsize::new(0),
+            max_queued,
+            timeout,
+        }
+    }
+
+    /// Run a task once a worker is available.
+    ///
+    /// Fails immediately if too many tasks are already waiting, or if the task
+    /// doesn't finish within the timeout. A task that times out keeps its
+    /// worker until it finishes so the pool is never oversubscribed.
+    pub async fn run<F, T>(&self, f: F) -> anyhow::Result<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        tokio::time::timeout(self.timeout, self.run_queued(f))
+            .await
+            .context("image task timed out")?
+    }
+
+    async fn run_queued<F, T>(&self, f: F) -> anyhow::Result<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let permit = {
+            let _queued = QueuedGuard::enter(self)?;
+
+            self.permits
+                .clone()
+                .acquire_owned()
+                .await
+                .context("decode pool was closed")?
+        };
+
+        tokio::task::spawn_blocking(move || {
+            let result = f();
+            drop(permit);
+
+            result
+        })
+        .instrument(tracing::debug_span!("decode"))
+        .await
+        .context("unable to spawn blocking")
+    }
+}
+
+/// Counts a task as queued until it is dropped.
+struct QueuedGuard<'a> {
+    queued: &'a AtomicUsize,
+}
+
+impl<'a> QueuedGuard<'a> {
+    fn enter(pool: &'a DecodePool) -> anyhow::Result<Self> {
+        let queued = pool.queued.fetch_add(1, Ordering::SeqCst);
+
+        let guard = Self {
+            queued: &pool.queued,
+        };
+        DECODE_QUEUED.inc();
+
+        if queued >= pool.max_queued {
+            tracing::warn!(queued, "decode queue was full");
+            DECODE_REJECTED.inc();
+
+            anyhow::bail!("too many images are waiting to be processed");
+        }
+
+        Ok(guard)
+    }
+}
+
+impl Drop for QueuedGuard<'_> {
+    fn drop(&mut self) {
+        self.queued.fetch_sub(1, Ordering::SeqCst);
+        DECODE_QUEUED.dec();
+    }
+}
+
+/// Run CPU heavy image work, like decoding or hashing, on the shared decode
+/// pool.
+pub async fn run<F, T>(f: F) -> anyhow::Result<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    POOL.run(f).await
+}
+
+/// Hash image data on the decode pool.
+pub async fn hash_bytes<D>(data: D) -> anyhow::Result<i64>
+where
+    D: AsRef<[u8]> + Send + 'static,
+{
+    run(move || fuzzysearch::hash_bytes(data.as_ref()))
+        .await?
+        .context("unable to hash bytes")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::DecodePool;
+
+    #[tokio::test]
+    async fn test_decode_pool_queue_limit() {
+        let pool = std::sync::Arc::new(DecodePool::new(1, 1, Duration::from_secs(5)));
+        let (tx, rx) = std::sync::mpsc::channel::<()>();
+
+        // Occupy the only worker until the channel is closed.
+        let busy = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.run(move || rx.recv().ok()).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        // One task may wait for the worker, but another is rejected.
+        let waiting = tokio::spawn({
+            let pool = pool.clone();
+            async move { pool.run(|| 1).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(pool.run(|| 2).await.is_err());
+
+        drop(tx);
+        assert!(busy.await.unwrap().is_ok());
+        assert_eq!(waiting.await.unwrap().unwrap(), 1);
+        assert_eq!(pool.run(|| 3).await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_decode_pool_timeout() {
+        let pool = DecodePool::new(1, 1, Duration::from_millis(50));
+
+        let result = pool
+            .run(|| std::thread::sleep(Duration::from_millis(200)))
+            .await;
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,226 @@
+use std::io::Cursor;
+use std::sync::RwLock;
+
+use anyhow::Context;
+
+/// Default most bytes to download for a single file.
+pub const DEFAULT_MAX_BYTES: usize = 50_000_000;
+/// Default most pixels an image may have before it is decoded.
+pub const DEFAULT_MAX_PIXELS: u64 = 100_000_000;
+
+/// Stop trying to find the dimensions of an image after this many bytes, as
+/// every format we decode has them near the start of the file.
+const MAX_PROBE_BYTES: usize = 1_000_000;
+
+lazy_static::lazy_static! {
+    static ref LIMITS: RwLock<DownloadLimits> = RwLock::new(DownloadLimits::default());
+
+    static ref CLIENT: reqwest::Client = reqwest::Client::new();
+}
+
+/// Limits on media downloaded from untrusted URLs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DownloadLimits {
+    /// Most bytes to download.
+    pub max_bytes: usize,
+    /// Most pixels an image may have, checked before the image is decoded.
+    pub max_pixels: u64,
+}
+
+impl Default for DownloadLimits {
+    fn default() -> Self {
+        Self {
+            max_bytes: DEFAULT_MAX_BYTES,
+            max_pixels: DEFAULT_MAX_PIXELS,
+        }
+    }
+}
+
+impl DownloadLimits {
+    /// Create limits from configuration, using the defaults for anything that
+    /// isn't set.
+    pub fn new(max_bytes: Option<usize>, max_pixels: Option<u64>) -> Self {
+        Self {
+            max_bytes: max_bytes.unwrap_or(DEFAULT_MAX_BYTES),
+            max_pixels: max_pixels.unwrap_or(DEFAULT_MAX_PIXELS),
+        }
+    }
+
+    /// Get these limits with a lower byte limit, if it is lower.
+    pub fn with_max_bytes(self, max_bytes: usize) -> Self {
+        Self {
+            max_bytes: self.max_bytes.min(max_bytes),
+            ..self
+        }
+    }
+
+    /// Check if an image with the given dimensions is allowed.
+    fn check_dimensions(&self, (width, height): (u32, u32)) -> anyhow::Result<()> {
+        let pixels = width as u64 * height as u64;
+
+        if pixels > self.max_pixels {
+            tracing::warn!(
+                width,
+                height,
+                max_pixels = self.max_pixels,
+                "image has too many pixels"
+            );
+            anyhow::bail!("image is {}x{}, larger than allowed", width, height);
+        }
+
+        Ok(())
+    }
+}
+
+/// Set the limits used for all downloads.
+pub fn set_limits(limits: DownloadLimits) {
+    *LIMITS.write().unwrap() = limits;
+}
+
+/// Get the limits used for all downloads.
+pub fn limits() -> DownloadLimits {
+    *LIMITS.read().unwrap()
+}
+
+/// Download a file with the configured limits.
+pub async fn download(url: &str) -> anyhow::Result<bytes::Bytes> {
+    download_with_limits(url, limits()).await
+}
+
+/// Download a file, stopping as soon as it is known to be over the size
+/// limits.
+///
+/// The download is aborted if the content-length header or the bytes read
+/// are over the byte limit. Images are checked against the pixel limit as
+/// soon as their dimensions can be read from the start of the file.
+#[tracing::instrument(skip(limits))]
+pub async fn download_with_limits(
+    url: &str,
+    limits: DownloadLimits,
+) -> anyhow::Result<bytes::Bytes> {
+    let resp = CLIENT
+        .get(url)
+        .send()
+        .await
+        .context("unable to request file")?
+        .error_for_status()
+        .context("file returned error status")?;
+
+    read_limited(resp, limits).await
+}
+
+/// Read a response body, enforcing the limits as it is streamed.
+pub async fn read_limited(
+    mut resp: reqwest::Response,
+    limits: DownloadLimits,
+) -> anyhow::Result<bytes::Bytes> {
+    if let Some(content_length) = resp.content_length() {
+        if content_length > limits.max_bytes as u64 {
+            tracing::warn!(
+                content_length,
+                max_bytes = limits.max_bytes,
+                "content-length is larger than max size"
+            );
+            anyhow::bail!("Body is larger than maximum permissible download");
+        }
+    }
+
+    let mut buf = bytes::BytesMut::new();
+    let mut probed = false;
+
+    while let Some(chunk) = resp.chunk().await? {
+        buf.extend(chunk);
+
+        if buf.len() > limits.max_bytes {
+            tracing::warn!(
+                size = buf.len(),
+                max_bytes = limits.max_bytes,
+                "Requested download is larger than max size"
+            );
+            anyhow::bail!("Body is larger than maximum permissible download");
+        }
+
+        if !probed && buf.len() <= MAX_PROBE_BYTES {
+            if let Some(dimensions) = probe_dimensions(&buf) {
+                limits.check_dimensions(dimensions)?;
+                probed = true;
+            }
+        }
+    }
+
+    // Some formats only have dimensions after a large header, so check again
+    // with everything before anything tries to decode it.
+    if !probed {
+        if let Some(dimensions) = probe_dimensions(&buf) {
+            limits.check_dimensions(dimensions)?;
+        }
+    }
+
+    Ok(buf.freeze())
+}
+
+/// Check image data that wasn't downloaded with [`download`] against the
+/// configured pixel limit before it is decoded.
+///
+/// Data without dimensions that can be read is rejected, as it can't be
+/// checked.
+pub fn check_image(data: &[u8]) -> anyhow::Result<()> {
+    let dimensions = probe_dimensions(data).context("unable to read image dimensions")?;
+
+    limits().check_dimensions(dimensions)
+}
+
+/// Read the dimensions of an image without decoding it, if the data starts
+/// with a known image format and includes enough of the header.
+pub fn probe_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    image::io::Reader::new(Cursor::new(data))
+        .with_guessed_format()
+        .ok()?
+        .into_dimensions()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{probe_dimensions, DownloadLimits};
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let im = image::DynamicImage::new_rgb8(width, height);
+
+        let mut buf = Vec::new();
+        im.write_to(&mut buf, image::ImageOutputFormat::Png)
+            .unwrap();
+
+        buf
+    }
+
+    #[test]
+    fn test_probe_dimensions() {
+        let data = png(30, 20);
+
+        assert_eq!(probe_dimensions(&data), Some((30, 20)));
+        assert_eq!(
+            probe_dimensions(&data[..32]),
+            Some((30, 20)),
+            "dimensions should be found from only the header"
+        );
+        assert_eq!(probe_dimensions(b"not an image"), None);
+    }
+
+    #[test]
+    fn test_check_dimensions() {
+        let limits = DownloadLimits::new(None, Some(100));
+
+        assert!(limits.check_dimensions((10, 10)).is_ok());
+        assert!(limits.check_dimensions((10, 11)).is_err());
+        assert!(limits.check_dimensions((u32::MAX, u32::MAX)).is_err());
+    }
+
+    #[test]
+    fn test_with_max_bytes() {
+        let limits = DownloadLimits::new(Some(100), None);
+
+        assert_eq!(limits.with_max_bytes(50).max_bytes, 50);
+        assert_eq!(limits.with_max_bytes(200).max_bytes, 100);
+    }
+}
@@ -0,0 +1,352 @@
+//! Building formatted messages without breaking Telegram's parsing.
+//!
+//! Links and names from sites can contain characters like `_` or `<` that
+//! Telegram would otherwise treat as formatting, so any text that isn't
+//! meant to be formatting must be escaped for the message's parse mode.
+
+use tgbotapi::requests::ParseMode;
+
+use crate::Bundle;
+
+/// Marks where an argument was placed while formatting a localized message.
+const PLACEHOLDER_START: char = '\u{E000}';
+const PLACEHOLDER_END: char = '\u{E001}';
+
+/// A parse mode that messages can be built for.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Html,
+    MarkdownV2,
+}
+
+impl Format {
+    pub fn parse_mode(self) -> ParseMode {
+        match self {
+            Format::Html => ParseMode::HTML,
+            Format::MarkdownV2 => ParseMode::MarkdownV2,
+        }
+    }
+
+    /// Escape text so it's displayed exactly as written.
+    pub fn escape(self, text: &str) -> String {
+        match self {
+            Format::Html => escape_html(text),
+            Format::MarkdownV2 => escape_markdown_v2(text),
+        }
+    }
+}
+
+/// Escape text for messages using HTML.
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Escape text for messages using MarkdownV2.
+pub fn escape_markdown_v2(text: &str) -> String {
+    escape_chars(text, "_*[]()~`>#+-=|{}.!\\")
+}
+
+/// Escape text inside of code in MarkdownV2, which only needs backticks and
+/// backslashes escaped.
+fn escape_markdown_v2_code(text: &str) -> String {
+    escape_chars(text, "`\\")
+}
+
+/// Escape a URL inside of a MarkdownV2 link.
+fn escape_markdown_v2_url(text: &str) -> String {
+    escape_chars(text, ")\\")
+}
+
+fn escape_chars(text: &str, special: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        if special.contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+/// Builds a message in a format, escaping everything that isn't meant to be
+/// formatting.
+#[derive(Clone, Debug)]
+pub struct MessageBuilder {
+    format: Format,
+    text: String,
+}
+
+impl MessageBuilder {
+    pub fn new(format: Format) -> Self {
+        Self {
+            format,
+            text: String::new(),
+        }
+    }
+
+    pub fn html() -> Self {
+        Self::new(Format::Html)
+    }
+
+    pub fn markdown_v2() -> Self {
+        Self::new(Format::MarkdownV2)
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    /// Add plain text.
+    pub fn text(mut self, text: &str) -> Self {
+        self.text.push_str(&self.format.escape(text));
+        self
+    }
+
+    pub fn bold(mut self, text: &str) -> Self {
+        let text = self.format.escape(text);
+
+        match self.format {
+            Format::Html => self.text.push_str(&format!("<b>{}</b>", text)),
+            Format::MarkdownV2 => self.text.push_str(&format!("*{}*", text)),
+        }
+
+        self
+    }
+
+    pub fn italic(mut self, text: &str) -> Self {
+        let text = self.format.escape(text);
+
+        match self.format {
+            Format::Html => self.text.push_str(&format!("<i>{}</i>", text)),
+            Format::MarkdownV2 => self.text.push_str(&format!("_{}_", text)),
+        }
+
+        self
+    }
+
+    pub fn code(mut self, text: &str) -> Self {
+        match self.format {
+            Format::Html => self
+                .text
+                .push_str(&format!("<code>{}</code>", escape_html(text))),
+            Format::MarkdownV2 => self
+                .text
+                .push_str(&format!("`{}`", escape_markdown_v2_code(text))),
+        }
+
+        self
+    }
+
+    pub fn link(mut self, text: &str, url: &str) -> Self {
+        match self.format {
+            Format::Html => self.text.push_str(&format!(
+                "<a href=\"{}\">{}</a>",
+                escape_html(url),
+                escape_html(text)
+            )),
+            Format::MarkdownV2 => self.text.push_str(&format!(
+                "[{}]({})",
+                escape_markdown_v2(text),
+                escape_markdown_v2_url(url)
+            )),
+        }
+
+        self
+    }
+
+    pub fn newline(mut self) -> Self {
+        self.text.push('\n');
+        self
+    }
+
+    /// Add another message that was built in the same format.
+    pub fn append(mut self, other: MessageBuilder) -> Self {
+        assert_eq!(self.format, other.format, "formats must match");
+
+        self.text.push_str(&other.text);
+        self
+    }
+
+    /// Add a localized message, escaping its text and each of its arguments.
+    /// Arguments that are builders keep their formatting.
+    pub fn message(
+        mut self,
+        bundle: Bundle,
+        name: &str,
+        args: Vec<(&str, MessageArg)>,
+    ) -> Result<Self, Vec<fluent::FluentError>> {
+        let mut fluent_args = fluent::FluentArgs::new();
+        for (index, (arg_name, arg)) in args.iter().enumerate() {
+            let value = match arg {
+                // Numbers are used directly so they can still select plural
+                // forms, and are escaped with the rest of the message.
+                MessageArg::Number(number) => (*number).into(),
+                _ => format!("{}{}{}", PLACEHOLDER_START, index, PLACEHOLDER_END).into(),
+            };
+
+            fluent_args.insert(*arg_name, value);
+        }
+
+        let message = crate::get_message(bundle, name, Some(fluent_args))?;
+        let escaped = self.format.escape(&message);
+
+        let mut rest = escaped.as_str();
+        while let Some(start) = rest.find(PLACEHOLDER_START) {
+            self.text.push_str(&rest[..start]);
+            rest = &rest[start + PLACEHOLDER_START.len_utf8()..];
+
+            let end = match rest.find(PLACEHOLDER_END) {
+                Some(end) => end,
+                None => break,
+            };
+
+            if let Some((_name, arg)) = rest[..end]
+                .parse::<usize>()
+                .ok()
+                .and_then(|index| args.get(index))
+            {
+                match arg {
+                    MessageArg::Text(text) => self.text.push_str(&self.format.escape(text)),
+                    MessageArg::Formatted(builder) => self.text.push_str(&builder.text),
+                    MessageArg::Number(_number) => (),
+                }
+            }
+
+            rest = &rest[end + PLACEHOLDER_END.len_utf8()..];
+        }
+        self.text.push_str(rest);
+
+        Ok(self)
+    }
+
+    /// Get the built text and the parse mode it must be sent with.
+    pub fn build(self) -> (String, ParseMode) {
+        (self.text, self.format.parse_mode())
+    }
+}
+
+/// An argument for a localized message.
+#[derive(Clone, Debug)]
+pub enum MessageArg {
+    /// Text that is escaped.
+    Text(String),
+    /// Text that was already formatted.
+    Formatted(MessageBuilder),
+    Number(i64),
+}
+
+impl From<i64> for MessageArg {
+    fn from(number: i64) -> Self {
+        Self::Number(number)
+    }
+}
+
+impl From<&str> for MessageArg {
+    fn from(text: &str) -> Self {
+        Self::Text(text.to_string())
+    }
+}
+
+impl From<String> for MessageArg {
+    fn from(text: String) -> Self {
+        Self::Text(text)
+    }
+}
+
+impl From<MessageBuilder> for MessageArg {
+    fn from(builder: MessageBuilder) -> Self {
+        Self::Formatted(builder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(
+            escape_html("<b>Tom & Jerry</b>"),
+            "&lt;b&gt;Tom &amp; Jerry&lt;/b&gt;"
+        );
+        assert_eq!(
+            escape_markdown_v2("some_artist (v1.0)!"),
+            "some\\_artist \\(v1\\.0\\)\\!"
+        );
+    }
+
+    #[test]
+    fn test_builder() {
+        let (text, _parse_mode) = MessageBuilder::html()
+            .text("Source by ")
+            .bold("<artist>")
+            .text(": ")
+            .link("a_b", "https://example.com/?a=1&b=2")
+            .build();
+        assert_eq!(
+            text,
+            "Source by <b>&lt;artist&gt;</b>: <a href=\"https://example.com/?a=1&amp;b=2\">a_b</a>"
+        );
+
+        let (text, _parse_mode) = MessageBuilder::markdown_v2()
+            .text("by ")
+            .italic("some_artist")
+            .text(" ")
+            .code("id`1")
+            .text(" ")
+            .link("post.", "https://example.com/a_(b)")
+            .build();
+        assert_eq!(
+            text,
+            "by _some\\_artist_ `id\\`1` [post\\.](https://example.com/a_(b\\))"
+        );
+    }
+
+    #[test]
+    fn test_message() {
+        let resource = fluent::FluentResource::try_new(
+            "greeting = Hi <{ $name }>, your ID is { $id }. You have { $count } errors."
+                .to_string(),
+        )
+        .unwrap();
+        let mut bundle = crate::LangBundle::new(vec![]);
+        bundle.add_resource(resource).unwrap();
+        bundle.set_use_isolating(false);
+
+        let (text, _parse_mode) = MessageBuilder::html()
+            .message(
+                &bundle,
+                "greeting",
+                vec![
+                    ("name", "Tom & Jerry".into()),
+                    ("id", MessageBuilder::html().code("a<b").into()),
+                    ("count", 2.into()),
+                ],
+            )
+            .unwrap()
+            .build();
+
+        assert_eq!(
+            text,
+            "Hi &lt;Tom &amp; Jerry&gt;, your ID is <code>a&lt;b</code>. You have 2 errors."
+        );
+    }
+}
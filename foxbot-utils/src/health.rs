@@ -0,0 +1,146 @@
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+/// Longest time to wait for a single dependency to respond.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Result of checking a single dependency.
+struct DependencyCheck {
+    name: &'static str,
+    /// If the process should be restarted when this dependency is failing.
+    ///
+    /// Restarting won't fix an outage of an external service like Telegram,
+    /// so those checks only affect readiness.
+    liveness: bool,
+    duration: Duration,
+    error: Option<String>,
+}
+
+/// Status of each dependency needed to process updates and jobs.
+#[derive(Default)]
+pub struct HealthReport {
+    checks: Vec<DependencyCheck>,
+}
+
+impl HealthReport {
+    /// Run a check and add its result to the report.
+    pub async fn check<F>(&mut self, name: &'static str, liveness: bool, check: F)
+    where
+        F: Future<Output = anyhow::Result<()>>,
+    {
+        let start = Instant::now();
+
+        let error = match tokio::time::timeout(CHECK_TIMEOUT, check).await {
+            Ok(Ok(())) => None,
+            Ok(Err(err)) => Some(err.to_string()),
+            Err(_) => Some("timed out".to_string()),
+        };
+
+        if let Some(error) = &error {
+            tracing::warn!(name, "health check failed: {}", error);
+        }
+
+        self.checks.push(DependencyCheck {
+            name,
+            liveness,
+            duration: start.elapsed(),
+            error,
+        });
+    }
+
+    /// If every dependency that could be fixed by a restart is working.
+    pub fn is_live(&self) -> bool {
+        self.checks
+            .iter()
+            .all(|check| !check.liveness || check.error.is_none())
+    }
+
+    /// If every dependency is working.
+    pub fn is_ready(&self) -> bool {
+        self.checks.iter().all(|check| check.error.is_none())
+    }
+
+    /// Serialize the status of each dependency.
+    pub fn to_json(&self) -> serde_json::Value {
+        let dependencies: serde_json::Map<String, serde_json::Value> = self
+            .checks
+            .iter()
+            .map(|check| {
+                (
+                    check.name.to_string(),
+                    serde_json::json!({
+                        "healthy": check.error.is_none(),
+                        "duration_ms": check.duration.as_millis() as u64,
+                        "error": check.error,
+                    }),
+                )
+            })
+            .collect();
+
+        serde_json::json!({
+            "live": self.is_live(),
+            "ready": self.is_ready(),
+            "dependencies": dependencies,
+        })
+    }
+}
+
+/// Check that Postgres is accepting queries.
+pub async fn check_postgres(conn: &sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<()> {
+    sqlx::query("SELECT 1").execute(conn).await?;
+
+    Ok(())
+}
+
+/// Check that Redis is responding to commands.
+pub async fn check_redis(conn: &redis::aio::ConnectionManager) -> anyhow::Result<()> {
+    let mut conn = conn.clone();
+    redis::cmd("PING")
+        .query_async::<_, String>(&mut conn)
+        .await?;
+
+    Ok(())
+}
+
+/// Check that a new connection can be made to Faktory.
+pub async fn check_faktory(url: Option<String>) -> anyhow::Result<()> {
+    tokio::task::spawn_blocking(move || faktory::Producer::connect(url.as_deref()).map(|_| ()))
+        .await??;
+
+    Ok(())
+}
+
+/// Check that Telegram accepts the bot's token.
+pub async fn check_telegram(bot: &tgbotapi::Telegram) -> anyhow::Result<()> {
+    bot.make_request(&tgbotapi::requests::GetMe).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HealthReport;
+
+    #[tokio::test]
+    async fn test_health_report() {
+        let mut report = HealthReport::default();
+        report.check("postgres", true, async { Ok(()) }).await;
+        report
+            .check("telegram", false, async {
+                Err(anyhow::anyhow!("bad token"))
+            })
+            .await;
+
+        assert!(report.is_live());
+        assert!(!report.is_ready());
+
+        let json = report.to_json();
+        assert_eq!(json["dependencies"]["postgres"]["healthy"], true);
+        assert_eq!(json["dependencies"]["telegram"]["error"], "bad token");
+
+        report
+            .check("redis", true, async { Err(anyhow::anyhow!("refused")) })
+            .await;
+        assert!(!report.is_live());
+    }
+}
@@ -0,0 +1,223 @@
+//! Editing images before they're cached or shared.
+//!
+//! Thumbnails and images too large for Telegram are made with the same
+//! pipeline users can apply to an image before sharing it, so every image is
+//! converted the same way.
+
+use image::{DynamicImage, GenericImageView, ImageFormat, ImageOutputFormat};
+
+/// Longest side of thumbnails.
+pub const THUMBNAIL_SIZE: u32 = 400;
+
+/// Longest side of images that were too large to send.
+pub const MAX_SIZE: u32 = 2000;
+
+/// Quality of images converted to JPEGs.
+pub const JPEG_QUALITY: u8 = 90;
+
+/// A change made to an image.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ImageEdit {
+    /// Quickly resize to fit within a size, for thumbnails.
+    Thumbnail(u32),
+    /// Resize to fit within a size if it's larger, keeping as much detail as
+    /// possible.
+    Downscale(u32),
+    /// Replace transparency with a white background.
+    StripAlpha,
+}
+
+impl ImageEdit {
+    fn apply(self, im: DynamicImage) -> DynamicImage {
+        match self {
+            ImageEdit::Thumbnail(size) => im.thumbnail(size, size),
+            ImageEdit::Downscale(size) => {
+                let (width, height) = im.dimensions();
+                if width <= size && height <= size {
+                    return im;
+                }
+
+                im.resize(size, size, image::imageops::FilterType::Lanczos3)
+            }
+            ImageEdit::StripAlpha => strip_alpha(im),
+        }
+    }
+}
+
+/// Edits to make to an image, in order, and the format to save it as.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ImagePipeline {
+    edits: Vec<ImageEdit>,
+    jpeg_quality: Option<u8>,
+}
+
+impl ImagePipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn thumbnail(mut self, size: u32) -> Self {
+        self.edits.push(ImageEdit::Thumbnail(size));
+        self
+    }
+
+    pub fn downscale(mut self, size: u32) -> Self {
+        self.edits.push(ImageEdit::Downscale(size));
+        self
+    }
+
+    pub fn strip_alpha(mut self) -> Self {
+        self.edits.push(ImageEdit::StripAlpha);
+        self
+    }
+
+    /// Save the image as a JPEG, instead of in its original format.
+    pub fn jpeg(mut self, quality: u8) -> Self {
+        self.jpeg_quality = Some(quality);
+        self
+    }
+
+    /// If the image is used exactly as it was.
+    pub fn is_empty(&self) -> bool {
+        self.edits.is_empty() && self.jpeg_quality.is_none()
+    }
+
+    /// Decode an image and make each edit.
+    ///
+    /// Images without any edits keep their original data. Edited images are
+    /// saved as JPEGs if requested or if they already were, and as PNGs
+    /// otherwise.
+    pub fn process(&self, data: bytes::Bytes) -> anyhow::Result<EditedImage> {
+        let original = image::guess_format(&data).ok();
+        let im = image::load_from_memory(&data)?;
+
+        if self.is_empty() {
+            return Ok(EditedImage {
+                dimensions: im.dimensions(),
+                format: original.unwrap_or(ImageFormat::Jpeg),
+                data,
+            });
+        }
+
+        let im = self.edits.iter().fold(im, |im, edit| edit.apply(im));
+
+        let quality = match (self.jpeg_quality, original) {
+            (Some(quality), _) => Some(quality),
+            (None, Some(ImageFormat::Jpeg)) => Some(JPEG_QUALITY),
+            _ => None,
+        };
+
+        let mut buf = Vec::with_capacity(data.len());
+
+        let (im, format) = match quality {
+            // Images can have transparency or larger bit depths that can't be
+            // represented as JPEGs, so they must be converted to Rgb8 first.
+            Some(quality) => {
+                let im = DynamicImage::ImageRgb8(im.into_rgb8());
+                im.write_to(&mut buf, ImageOutputFormat::Jpeg(quality))?;
+                (im, ImageFormat::Jpeg)
+            }
+            None => {
+                im.write_to(&mut buf, ImageOutputFormat::Png)?;
+                (im, ImageFormat::Png)
+            }
+        };
+
+        Ok(EditedImage {
+            data: buf.into(),
+            dimensions: im.dimensions(),
+            format,
+        })
+    }
+}
+
+/// An image after it was edited.
+pub struct EditedImage {
+    pub data: bytes::Bytes,
+    pub dimensions: (u32, u32),
+    pub format: ImageFormat,
+}
+
+impl EditedImage {
+    /// File extension and content type of the image.
+    pub fn file_type(&self) -> (&'static str, &'static str) {
+        match self.format {
+            ImageFormat::Png => ("png", "image/png"),
+            ImageFormat::Gif => ("gif", "image/gif"),
+            ImageFormat::WebP => ("webp", "image/webp"),
+            _ => ("jpg", "image/jpeg"),
+        }
+    }
+}
+
+/// Blend transparent pixels onto a white background, instead of leaving
+/// whatever color was hidden behind them.
+fn strip_alpha(im: DynamicImage) -> DynamicImage {
+    if !im.color().has_alpha() {
+        return im;
+    }
+
+    let im = im.into_rgba8();
+    let (width, height) = im.dimensions();
+
+    DynamicImage::ImageRgb8(image::RgbImage::from_fn(width, height, |x, y| {
+        let image::Rgba([r, g, b, a]) = *im.get_pixel(x, y);
+        let blend = |c: u8| ((c as u32 * a as u32 + 255 * (255 - a as u32)) / 255) as u8;
+
+        image::Rgb([blend(r), blend(g), blend(b)])
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(im: DynamicImage) -> bytes::Bytes {
+        let mut buf = Vec::new();
+        im.write_to(&mut buf, ImageOutputFormat::Png).unwrap();
+
+        buf.into()
+    }
+
+    #[test]
+    fn test_downscale() {
+        let data = encode(DynamicImage::new_rgb8(300, 150));
+
+        let image = ImagePipeline::new().downscale(100).process(data).unwrap();
+        assert_eq!(image.dimensions, (100, 50));
+        assert_eq!(image.format, ImageFormat::Png);
+
+        let data = encode(DynamicImage::new_rgb8(30, 15));
+        let image = ImagePipeline::new().downscale(100).process(data).unwrap();
+        assert_eq!(image.dimensions, (30, 15), "small images should not grow");
+    }
+
+    #[test]
+    fn test_jpeg_strip_alpha() {
+        let im = image::RgbaImage::from_pixel(4, 4, image::Rgba([0, 0, 0, 0]));
+        let data = encode(DynamicImage::ImageRgba8(im));
+
+        let image = ImagePipeline::new()
+            .strip_alpha()
+            .jpeg(JPEG_QUALITY)
+            .process(data)
+            .unwrap();
+        assert_eq!(image.format, ImageFormat::Jpeg);
+        assert_eq!(image.file_type(), ("jpg", "image/jpeg"));
+
+        let im = image::load_from_memory(&image.data).unwrap().into_rgb8();
+        assert!(
+            im.pixels().all(|pixel| pixel.0.iter().all(|c| *c > 250)),
+            "transparent pixels should be white"
+        );
+    }
+
+    #[test]
+    fn test_unedited() {
+        let data = encode(DynamicImage::new_rgb8(30, 15));
+
+        let image = ImagePipeline::new().process(data.clone()).unwrap();
+        assert_eq!(image.data, data, "images without edits should be unchanged");
+        assert_eq!(image.dimensions, (30, 15));
+    }
+}
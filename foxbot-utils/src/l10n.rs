@@ -0,0 +1,235 @@
+//! Languages loaded from Fluent files in a directory, which can be reloaded
+//! while running.
+//!
+//! Each language is a directory named with its language identifier, like
+//! `langs/en-US`, containing any number of `.ftl` files. Adding a directory
+//! adds a language, and anything it hasn't translated uses the default
+//! language.
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+};
+
+use anyhow::Context;
+
+use crate::{get_lang_bundle, LangBundle, Langs, DEFAULT_LANG};
+
+/// Get the directory languages are loaded from, `LANGS_DIR` or `langs` in the
+/// current directory.
+pub fn langs_dir() -> PathBuf {
+    match std::env::var("LANGS_DIR") {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) => std::env::current_dir()
+            .expect("unable to get directory")
+            .join("langs"),
+    }
+}
+
+/// Load every language in a directory, checking that each file can be used.
+pub fn load_dir(dir: &Path) -> anyhow::Result<Langs> {
+    let mut langs = HashMap::new();
+
+    let entries = std::fs::read_dir(dir)
+        .with_context(|| format!("unable to read languages from {}", dir.display()))?;
+
+    for entry in entries {
+        let path = entry?.path();
+        if !path.is_dir() {
+            continue;
+        }
+
+        let langid = match path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.parse::<unic_langid::LanguageIdentifier>().ok())
+        {
+            Some(langid) => langid,
+            None => {
+                tracing::warn!(path = %path.display(), "skipping directory that isn't a language");
+                continue;
+            }
+        };
+
+        let resources = load_resources(&path)?;
+        if resources.is_empty() {
+            continue;
+        }
+
+        langs.insert(langid, resources);
+    }
+
+    let default_lang: unic_langid::LanguageIdentifier = DEFAULT_LANG.parse()?;
+    if !langs.contains_key(&default_lang) {
+        anyhow::bail!("default language {} was missing", DEFAULT_LANG);
+    }
+
+    Ok(langs)
+}
+
+/// Load the Fluent files for a language in a consistent order, making sure
+/// they parse and don't define the same message twice.
+fn load_resources(dir: &Path) -> anyhow::Result<Vec<String>> {
+    let mut paths = std::fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    paths.retain(|path| path.extension().and_then(|ext| ext.to_str()) == Some("ftl"));
+    paths.sort();
+
+    let mut bundle = LangBundle::new(vec![]);
+    let mut resources = Vec::with_capacity(paths.len());
+
+    for path in paths {
+        let contents = std::fs::read_to_string(&path)
+            .with_context(|| format!("unable to read {}", path.display()))?;
+
+        let resource =
+            fluent::FluentResource::try_new(contents.clone()).map_err(|(_resource, errors)| {
+                anyhow::anyhow!("unable to parse {}: {:?}", path.display(), errors)
+            })?;
+        bundle.add_resource(resource).map_err(|errors| {
+            anyhow::anyhow!("invalid messages in {}: {:?}", path.display(), errors)
+        })?;
+
+        resources.push(contents);
+    }
+
+    Ok(resources)
+}
+
+/// Languages and the bundles built for each requested language.
+pub struct Localization {
+    dir: PathBuf,
+    langs: RwLock<Langs>,
+    bundles: RwLock<HashMap<String, Arc<LangBundle>>>,
+}
+
+impl Localization {
+    /// Load languages from a directory.
+    pub fn load(dir: PathBuf) -> anyhow::Result<Self> {
+        let langs = load_dir(&dir)?;
+
+        Ok(Self {
+            dir,
+            langs: RwLock::new(langs),
+            bundles: Default::default(),
+        })
+    }
+
+    /// Load languages again, returning how many are available.
+    ///
+    /// If any file is invalid, the previously loaded languages are kept.
+    pub fn reload(&self) -> anyhow::Result<usize> {
+        let langs = load_dir(&self.dir)?;
+        let count = langs.len();
+
+        *self
+            .langs
+            .write()
+            .map_err(|_err| anyhow::anyhow!("languages lock was poisoned"))? = langs;
+        self.clear_cache();
+
+        tracing::info!(count, "reloaded languages");
+
+        Ok(count)
+    }
+
+    /// Get the bundle for a requested language, building it if needed.
+    pub fn bundle(&self, requested: &str) -> Arc<LangBundle> {
+        if let Some(bundle) = self
+            .bundles
+            .read()
+            .expect("language bundles lock was poisoned")
+            .get(requested)
+        {
+            return bundle.clone();
+        }
+
+        tracing::debug!(lang = requested, "got new language, building bundle");
+
+        let bundle = Arc::new(get_lang_bundle(
+            &self.langs.read().expect("languages lock was poisoned"),
+            requested,
+        ));
+
+        self.bundles
+            .write()
+            .expect("language bundles lock was poisoned")
+            .insert(requested.to_string(), bundle.clone());
+
+        bundle
+    }
+
+    /// Identifiers of the available languages, with the default language
+    /// first.
+    pub fn languages(&self) -> Vec<String> {
+        let mut languages: Vec<String> = self
+            .langs
+            .read()
+            .expect("languages lock was poisoned")
+            .keys()
+            .map(ToString::to_string)
+            .collect();
+        languages.sort_by_key(|lang| (lang != DEFAULT_LANG, lang.clone()));
+
+        languages
+    }
+
+    /// Number of bundles that have been built.
+    pub fn cached_bundles(&self) -> usize {
+        self.bundles
+            .read()
+            .expect("language bundles lock was poisoned")
+            .len()
+    }
+
+    /// Remove every built bundle, returning how many there were.
+    pub fn clear_cache(&self) -> usize {
+        let mut bundles = self
+            .bundles
+            .write()
+            .expect("language bundles lock was poisoned");
+        let removed = bundles.len();
+        bundles.clear();
+
+        removed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_lang(dir: &Path, lang: &str, contents: &str) {
+        let path = dir.join(lang);
+        std::fs::create_dir_all(&path).unwrap();
+        std::fs::write(path.join("foxbot.ftl"), contents).unwrap();
+    }
+
+    #[test]
+    fn test_fallback_and_reload() {
+        let dir = std::env::temp_dir().join(format!("foxbot-langs-{}", crate::generate_id()));
+        write_lang(&dir, "en-US", "hello = Hello\ngoodbye = Goodbye\n");
+
+        let l10n = Localization::load(dir.clone()).unwrap();
+        assert_eq!(l10n.languages(), vec!["en-US"]);
+
+        write_lang(&dir, "de-DE", "hello = Hallo\n");
+        assert_eq!(l10n.reload().unwrap(), 2);
+        assert_eq!(l10n.languages(), vec!["en-US", "de-DE"]);
+
+        let bundle = l10n.bundle("de-DE");
+        assert_eq!(crate::get_message(&bundle, "hello", None).unwrap(), "Hallo");
+        assert_eq!(
+            crate::get_message(&bundle, "goodbye", None).unwrap(),
+            "Goodbye"
+        );
+
+        write_lang(&dir, "de-DE", "hello = {\n");
+        assert!(l10n.reload().is_err(), "invalid files should not load");
+        assert_eq!(l10n.languages(), vec!["en-US", "de-DE"]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
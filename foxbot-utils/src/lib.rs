@@ -1,12 +1,114 @@
 use anyhow::Context;
 use fuzzysearch::SiteInfo;
 use std::time::Instant;
-use std::{collections::HashSet, str::FromStr, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    str::FromStr,
+    sync::Arc,
+};
 use tgbotapi::FileType;
 use tracing_futures::Instrument;
 
-use foxbot_models::{CachedPost, FileCache, Sites, UserConfig, UserConfigKey};
-use foxbot_sites::{BoxedSite, PostInfo};
+use foxbot_models::{
+    CachedPost, FileCache, KnownSources, MediaFileId, MediaType, Sites, UserConfig, UserConfigKey,
+};
+use foxbot_sites::{BoxedSite, Credentials, PostInfo};
+
+pub mod bot_api;
+pub mod callback;
+pub mod config;
+pub mod decode;
+pub mod download;
+pub mod formatting;
+pub mod health;
+pub mod image_edit;
+pub mod l10n;
+pub mod media_cache;
+pub mod payload;
+pub mod rate_limit;
+pub mod reverse;
+pub mod secrets;
+pub mod urls;
+
+lazy_static::lazy_static! {
+    static ref SITE_DURATION: prometheus::HistogramVec = prometheus::register_histogram_vec!("foxbot_site_duration_seconds", "Time to load images from a site", &["site"]).unwrap();
+    static ref SITE_LOADS: prometheus::CounterVec = prometheus::register_counter_vec!("foxbot_site_loads_total", "Number of links loaded from each site", &["site", "cached"]).unwrap();
+    static ref SITE_HTTP_CALLS: prometheus::HistogramVec = prometheus::register_histogram_vec!("foxbot_site_http_calls", "HTTP requests made to load a link", &["site"], vec![0.0, 1.0, 2.0, 3.0, 5.0, 10.0]).unwrap();
+    static ref FUZZYSEARCH_DURATION: prometheus::Histogram = prometheus::register_histogram!("foxbot_fuzzysearch_duration_seconds", "Time for FuzzySearch to look up a hash").unwrap();
+
+    static ref SITE_STATUS: std::sync::Mutex<std::collections::HashMap<&'static str, SiteStatus>> = Default::default();
+}
+
+/// Recent results of loading images from a site.
+#[derive(Clone, Debug, Default)]
+pub struct SiteStatus {
+    /// Number of successful loads since the process started.
+    pub successes: u64,
+    /// Number of failed loads since the process started.
+    pub failures: u64,
+    /// Number of failed loads since the last success.
+    pub consecutive_failures: u64,
+    /// The most recent error and when it happened, as a Unix timestamp.
+    pub last_error: Option<(String, u64)>,
+}
+
+/// Record the result of loading images from a site.
+fn record_site_result<T>(site: &'static str, result: &anyhow::Result<T>) {
+    let mut statuses = match SITE_STATUS.lock() {
+        Ok(statuses) => statuses,
+        Err(_) => return,
+    };
+    let status = statuses.entry(site).or_default();
+
+    match result {
+        Ok(_) => {
+            status.successes += 1;
+            status.consecutive_failures = 0;
+        }
+        Err(err) => {
+            let now = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or_default();
+
+            status.failures += 1;
+            status.consecutive_failures += 1;
+            status.last_error = Some((format!("{:?}", err), now));
+        }
+    }
+}
+
+/// Record metrics about loading a link from a site.
+///
+/// Links loaded from a cache made no HTTP requests, so only the cache hit is
+/// counted.
+pub fn record_site_load(site: &str, cached: bool, http_calls: u32) {
+    SITE_LOADS
+        .with_label_values(&[site, if cached { "true" } else { "false" }])
+        .inc();
+
+    if !cached {
+        SITE_HTTP_CALLS
+            .with_label_values(&[site])
+            .observe(http_calls as f64);
+    }
+}
+
+/// Get the status of each site that has been used since the process started.
+pub fn site_statuses() -> Vec<(&'static str, SiteStatus)> {
+    let statuses = match SITE_STATUS.lock() {
+        Ok(statuses) => statuses,
+        Err(_) => return vec![],
+    };
+
+    let mut statuses: Vec<_> = statuses
+        .iter()
+        .map(|(site, status)| (*site, status.clone()))
+        .collect();
+    statuses.sort_by_key(|(site, _status)| *site);
+
+    statuses
+}
 
 /// Generates a random 24 character alphanumeric string.
 ///
@@ -55,12 +157,17 @@ pub struct SiteCallback<'a> {
     pub link: &'a str,
     /// The amount of time it took for the site to load data from the URL.
     pub duration: i64,
+    /// If the site answered with data it already had, without making any
+    /// HTTP requests.
+    pub cached: bool,
+    /// Number of HTTP requests the site made to load the URL.
+    pub http_calls: u32,
     /// The results obtained by the loader.
     pub results: Vec<PostInfo>,
 }
 
-/// Find images from the given URLs using the site loaders with authentication
-/// from the given user.
+/// Find images from the given URLs using the site loaders with the requester's
+/// credentials.
 ///
 /// It goes through each URL with each site, in the provided order. When a site
 /// specifies that it supports a URL, the images are attempted to be loaded. If
@@ -69,9 +176,12 @@ pub struct SiteCallback<'a> {
 /// After a site reports it supports a URL, no other sites are attempted for
 /// that URL. When complete, it returns the URLs that appeared to contain no
 /// content.
-#[tracing::instrument(err, skip(user, sites, callback))]
+///
+/// Each URL is normalized with [`urls::normalize_url`] before being matched,
+/// so shortened and tracked links still resolve.
+#[tracing::instrument(err, skip(credentials, sites, callback))]
 pub async fn find_images<'a, C>(
-    user: &tgbotapi::User,
+    credentials: &dyn Credentials,
     links: Vec<&'a str>,
     sites: &mut [BoxedSite],
     callback: &mut C,
@@ -81,31 +191,61 @@ where
 {
     let mut missing = vec![];
 
-    'link: for link in links {
+    'link: for original_link in links {
+        let link = urls::normalize_url(original_link).await;
+        let link = link.as_str();
+
         for site in sites.iter_mut() {
             let start = Instant::now();
 
-            if site.url_supported(link).await {
+            let (url_match, match_calls) =
+                foxbot_sites::count_http_calls(site.match_url(link)).await;
+
+            if let Some(url_match) = url_match {
                 tracing::debug!(link, site = site.name(), "found supported link");
 
-                let images = site
-                    .get_images(user.id, link)
-                    .await
-                    .context("unable to extract site images")?;
+                let (images, load_calls) =
+                    foxbot_sites::count_http_calls(site.get_images(credentials, &url_match)).await;
+                let http_calls = match_calls + load_calls;
+                let cached = http_calls == 0;
+
+                record_site_result(site.name(), &images);
+                record_site_load(site.name(), cached, http_calls);
+                if let Err(err) = &images {
+                    let tags = vec![
+                        ("site", site.name().to_string()),
+                        ("link", link.to_string()),
+                    ];
+                    with_user_scope(None, Some(tags), || {
+                        sentry::integrations::anyhow::capture_anyhow(err);
+                    });
+                }
+                let images = images.context("unable to extract site images")?;
+
+                SITE_DURATION
+                    .with_label_values(&[site.name()])
+                    .observe(start.elapsed().as_secs_f64());
 
                 match images {
                     Some(results) => {
-                        tracing::debug!(site = site.name(), "found images: {:?}", results);
+                        tracing::debug!(
+                            site = site.name(),
+                            http_calls,
+                            "found images: {:?}",
+                            results
+                        );
                         callback(SiteCallback {
                             site,
                             link,
                             duration: start.elapsed().as_millis() as i64,
+                            cached,
+                            http_calls,
                             results,
                         });
                     }
                     _ => {
                         tracing::debug!(site = site.name(), "no images found");
-                        missing.push(link);
+                        missing.push(original_link);
                     }
                 }
 
@@ -120,9 +260,9 @@ where
 /// Information about an image uploaded to the bot's cache.
 pub struct ImageInfo {
     /// URL to the bot's image
-    url: String,
+    pub url: String,
     /// Dimensions of the image
-    dimensions: (u32, u32),
+    pub dimensions: (u32, u32),
 }
 
 /// Processes an image for inline query results.
@@ -142,8 +282,6 @@ async fn upload_image(
     thumb: bool,
     data: &bytes::Bytes,
 ) -> anyhow::Result<ImageInfo> {
-    use bytes::BufMut;
-
     if let Some(cached_post) = CachedPost::get(conn, url, thumb)
         .await
         .context("unable to get cached post")?
@@ -154,7 +292,25 @@ async fn upload_image(
         });
     }
 
-    let im = image::load_from_memory(data)?;
+    let image = store_image(s3, s3_bucket, s3_url, thumb, data).await?;
+
+    if let Err(err) = CachedPost::save(conn, url, &image.url, thumb, image.dimensions).await {
+        sentry::integrations::anyhow::capture_anyhow(&err);
+    }
+
+    Ok(image)
+}
+
+/// Convert an image as needed and upload it to the S3 bucket, without saving
+/// it in the cache.
+async fn store_image(
+    s3: &rusoto_s3::S3Client,
+    s3_bucket: &str,
+    s3_url: &str,
+    thumb: bool,
+    data: &bytes::Bytes,
+) -> anyhow::Result<ImageInfo> {
+    use image_edit::{ImagePipeline, JPEG_QUALITY, MAX_SIZE, THUMBNAIL_SIZE};
 
     // We need to determine what processing to do, if any, on the image before
     // caching it. We can start by checking if this is a thumbnail. If so, we
@@ -164,62 +320,68 @@ async fn upload_image(
     //
     // This used to always convert images to JPEGs, but it does not appear that
     // any Telegram client actually requires this.
-    //
-    // When we do have to convert images to JPEGs, make sure to convert them to
-    // Rgb8 before attempting to encode the data. Certain images can have larger
-    // bit depths that can't be represented as JPEGs and generate an error
-    // instead of working as expected.
-    let (im, buf) = if thumb {
-        let im = im.thumbnail(400, 400);
-        let im = image::DynamicImage::ImageRgb8(im.into_rgb8());
-        let mut buf = bytes::BytesMut::with_capacity(2_000_000).writer();
-        im.write_to(&mut buf, image::ImageOutputFormat::Jpeg(90))?;
-        (im, buf.into_inner().freeze())
+    let pipeline = if thumb {
+        ImagePipeline::new()
+            .thumbnail(THUMBNAIL_SIZE)
+            .jpeg(JPEG_QUALITY)
     } else if data.len() > 5_000_000 {
-        let im = im.resize(2000, 2000, image::imageops::FilterType::Lanczos3);
-        let im = image::DynamicImage::ImageRgb8(im.into_rgb8());
-        let mut buf = bytes::BytesMut::with_capacity(2_000_000).writer();
-        im.write_to(&mut buf, image::ImageOutputFormat::Jpeg(90))?;
-        (im, buf.into_inner().freeze())
+        ImagePipeline::new().downscale(MAX_SIZE).jpeg(JPEG_QUALITY)
     } else {
-        (im, data.clone())
+        ImagePipeline::new()
     };
 
-    use image::GenericImageView;
-    let dimensions = im.dimensions();
+    let name = if thumb { "thumb" } else { "image" };
+
+    store_edited_image(s3, s3_bucket, s3_url, name, &pipeline, data).await
+}
+
+/// Edit an image and upload it to the S3 bucket, without saving it in the
+/// cache.
+pub async fn store_edited_image(
+    s3: &rusoto_s3::S3Client,
+    s3_bucket: &str,
+    s3_url: &str,
+    name: &str,
+    pipeline: &image_edit::ImagePipeline,
+    data: &bytes::Bytes,
+) -> anyhow::Result<ImageInfo> {
+    let (pipeline, data) = (pipeline.clone(), data.clone());
+    let image = decode::run(move || pipeline.process(data)).await??;
+    let (extension, content_type) = image.file_type();
 
     use sha2::{Digest, Sha256};
     let mut hasher = Sha256::new();
-    hasher.update(&buf);
+    hasher.update(&image.data);
     let hash = hasher.finalize();
     let hash = hex::encode(hash);
 
-    let name = if thumb { "thumb" } else { "image" };
-
-    let key = format!("{}/{}/{}_{}.jpg", &hash[0..2], &hash[2..4], name, &hash);
+    let key = format!(
+        "{}/{}/{}_{}.{}",
+        &hash[0..2],
+        &hash[2..4],
+        name,
+        &hash,
+        extension
+    );
 
     let put = rusoto_s3::PutObjectRequest {
         acl: Some("public-read".into()),
         bucket: s3_bucket.to_string(),
-        content_type: Some("image/jpeg".into()),
+        content_type: Some(content_type.into()),
         key: key.clone(),
-        body: Some(buf.to_vec().into()),
-        content_length: Some(buf.len() as i64),
+        body: Some(image.data.to_vec().into()),
+        content_length: Some(image.data.len() as i64),
         ..Default::default()
     };
 
     use rusoto_s3::S3;
-    s3.put_object(put).await.unwrap();
+    s3.put_object(put).await.context("unable to upload image")?;
 
     let cdn_url = format!("{}/{}/{}", s3_url, s3_bucket, key);
 
-    if let Err(err) = CachedPost::save(conn, url, &cdn_url, thumb, dimensions).await {
-        sentry::integrations::anyhow::capture_anyhow(&err);
-    }
-
     Ok(ImageInfo {
         url: cdn_url,
-        dimensions,
+        dimensions: image.dimensions,
     })
 }
 
@@ -235,15 +397,25 @@ pub async fn download_image(url: &str) -> anyhow::Result<bytes::Bytes> {
 /// Calculate image dimensions from provided data, returning a new PostInfo.
 #[tracing::instrument(skip(data))]
 pub async fn size_post(post: &PostInfo, data: &bytes::Bytes) -> anyhow::Result<PostInfo> {
-    use image::GenericImageView;
-
     // If we already have image dimensions, assume they're valid and reuse.
     if post.image_dimensions.is_some() {
         return Ok(post.to_owned());
     }
 
-    let im = image::load_from_memory(data)?;
-    let dimensions = im.dimensions();
+    // Most formats have their dimensions in the header, so only decode the
+    // whole image if they can't be found there.
+    let dimensions = match download::probe_dimensions(data) {
+        Some(dimensions) => dimensions,
+        None => {
+            let data = data.clone();
+            decode::run(move || {
+                use image::GenericImageView;
+
+                image::load_from_memory(&data).map(|im| im.dimensions())
+            })
+            .await??
+        }
+    };
 
     Ok(PostInfo {
         image_dimensions: Some(dimensions),
@@ -286,11 +458,151 @@ pub async fn cache_post(
     })
 }
 
+/// Generate a thumbnail for a post that doesn't have one, from the already
+/// downloaded image data, and upload it to the S3 bucket.
+///
+/// Posts that already have a thumbnail are returned unchanged.
+#[tracing::instrument(err, skip(conn, s3, s3_bucket, s3_url, data))]
+pub async fn thumbnail_post(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    s3: &rusoto_s3::S3Client,
+    s3_bucket: &str,
+    s3_url: &str,
+    post: &PostInfo,
+    data: &bytes::Bytes,
+) -> anyhow::Result<PostInfo> {
+    if post.thumb.is_some() {
+        return Ok(post.to_owned());
+    }
+
+    let thumb = upload_image(conn, s3, s3_bucket, s3_url, &post.url, true, data).await?;
+
+    Ok(PostInfo {
+        thumb: Some(thumb.url),
+        ..post.to_owned()
+    })
+}
+
+/// Build a thumbnail URL for an image using an images.weserv.nl compatible
+/// resizing proxy.
+pub fn thumbnail_proxy_url(endpoint: &str, url: &str) -> String {
+    let mut proxy_url = match reqwest::Url::parse(endpoint) {
+        Ok(proxy_url) => proxy_url,
+        Err(err) => {
+            tracing::warn!(endpoint, "thumbnail proxy was invalid: {:?}", err);
+            return url.to_string();
+        }
+    };
+
+    proxy_url
+        .query_pairs_mut()
+        .append_pair("url", url)
+        .append_pair("w", "400")
+        .append_pair("h", "400")
+        .append_pair("fit", "inside")
+        .append_pair("output", "jpg");
+
+    proxy_url.to_string()
+}
+
+/// Find the URL ID for a post, using the source link if available.
+pub fn post_url_id(sites: &[BoxedSite], post: &PostInfo) -> Option<String> {
+    let source = post.source_link.as_deref().unwrap_or(&post.url);
+    sites.iter().find_map(|site| site.url_id(source))
+}
+
+/// Remember the file ID of media the bot sent for a post, so later results
+/// can reuse the file instead of having Telegram fetch the URL again.
+pub async fn cache_sent_media(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    url_id: &str,
+    post: &PostInfo,
+    message: &tgbotapi::Message,
+) -> anyhow::Result<()> {
+    let (media_type, file_id) =
+        if let Some(photo) = message.photo.as_deref().and_then(find_best_photo) {
+            (MediaType::Photo, &photo.file_id)
+        } else if let Some(animation) = &message.animation {
+            (MediaType::Animation, &animation.file_id)
+        } else {
+            return Ok(());
+        };
+
+    tracing::trace!(url_id, url = %post.url, "caching sent media file id");
+
+    MediaFileId::set(conn, url_id, &post.url, media_type, file_id).await
+}
+
 /// Find the photo with the largest number of pixels.
 pub fn find_best_photo(sizes: &[tgbotapi::PhotoSize]) -> Option<&tgbotapi::PhotoSize> {
     sizes.iter().max_by_key(|size| size.height * size.width)
 }
 
+/// A file within a message that can be downloaded and hashed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SourceableFile<'a> {
+    pub file_id: &'a str,
+    pub file_unique_id: &'a str,
+}
+
+/// Find the best file to use for sourcing a message.
+///
+/// Photos are preferred, but images sent as uncompressed documents are also
+/// used if they have an image MIME type and are no larger than
+/// `max_document_size`, usually from [`bot_api::download_limit`]. Videos and
+/// animations are matched using the thumbnail Telegram generated for them, as
+/// it's a representative frame we can get without decoding the video
+/// ourselves.
+pub fn find_sourceable_file(
+    message: &tgbotapi::Message,
+    max_document_size: usize,
+) -> Option<SourceableFile<'_>> {
+    if let Some(photo) = message.photo.as_deref().and_then(find_best_photo) {
+        return Some(SourceableFile {
+            file_id: &photo.file_id,
+            file_unique_id: &photo.file_unique_id,
+        });
+    }
+
+    let thumb = message
+        .animation
+        .as_ref()
+        .and_then(|animation| animation.thumb.as_ref())
+        .or_else(|| {
+            message
+                .video
+                .as_ref()
+                .and_then(|video| video.thumb.as_ref())
+        });
+    if let Some(thumb) = thumb {
+        return Some(SourceableFile {
+            file_id: &thumb.file_id,
+            file_unique_id: &thumb.file_unique_id,
+        });
+    }
+
+    let document = message.document.as_ref()?;
+
+    let is_image = document
+        .mime_type
+        .as_deref()
+        .map(|mime_type| mime_type.starts_with("image/"))
+        .unwrap_or(false);
+    // Documents without a size might be too large to download, so they're
+    // treated as being over the limit.
+    let is_too_large = document
+        .file_size
+        .map_or(true, |file_size| file_size as usize > max_document_size);
+    if !is_image || is_too_large {
+        return None;
+    }
+
+    Some(SourceableFile {
+        file_id: &document.file_id,
+        file_unique_id: &document.file_unique_id,
+    })
+}
+
 /// Get a message from the bundle with a language code, if provided.
 pub fn get_message(
     bundle: Bundle,
@@ -311,6 +623,47 @@ pub fn get_message(
     }
 }
 
+/// Start reporting errors to Sentry, if a DSN was configured.
+///
+/// The returned guard must be kept until the process exits so queued events
+/// are sent.
+pub fn init_sentry(dsn: Option<&str>) -> Option<sentry::ClientInitGuard> {
+    let guard = dsn.map(|dsn| {
+        sentry::init(sentry::ClientOptions {
+            dsn: Some(dsn.parse().expect("SENTRY_DSN must be a valid DSN")),
+            debug: true,
+            release: option_env!("RELEASE").map(std::borrow::Cow::from),
+            attach_stacktrace: true,
+            ..Default::default()
+        })
+    });
+
+    tracing::info!(
+        "sentry enabled: {}",
+        guard
+            .as_ref()
+            .map_or(false, sentry::ClientInitGuard::is_enabled)
+    );
+
+    guard
+}
+
+/// A tracing layer that adds logged events as breadcrumbs to Sentry events.
+///
+/// Events are only recorded as breadcrumbs, errors are still reported
+/// explicitly so they have the correct scope.
+pub fn sentry_breadcrumbs<S>() -> sentry_tracing::SentryLayer<S>
+where
+    S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+{
+    sentry_tracing::layer().event_filter(|metadata| match *metadata.level() {
+        tracing::Level::ERROR | tracing::Level::WARN | tracing::Level::INFO => {
+            sentry_tracing::EventFilter::Breadcrumb
+        }
+        _ => sentry_tracing::EventFilter::Ignore,
+    })
+}
+
 /// Add current opentelemetry span to a Sentry scope.
 pub fn add_sentry_tracing(scope: &mut sentry::Scope) {
     use tracing_opentelemetry::OpenTelemetrySpanExt;
@@ -325,9 +678,12 @@ pub fn add_sentry_tracing(scope: &mut sentry::Scope) {
         )
     });
 
-    let header_value = headers.get("uber-trace-id").unwrap();
-    let trace_id = header_value.to_str().unwrap();
-    scope.set_extra("uber-trace-id", trace_id.to_owned().into());
+    // Jaeger and OTLP propagate the trace with different headers.
+    for name in &["uber-trace-id", "traceparent"] {
+        if let Some(trace_id) = headers.get(*name).and_then(|value| value.to_str().ok()) {
+            scope.set_extra(name, trace_id.to_owned().into());
+        }
+    }
 }
 
 /// Tags to add to a sentry event.
@@ -512,42 +868,189 @@ pub async fn match_image(
     fapi: &fuzzysearch::FuzzySearch,
     file: &tgbotapi::PhotoSize,
     distance: Option<i64>,
+    chat_id: Option<i64>,
+) -> anyhow::Result<(i64, Vec<fuzzysearch::File>)> {
+    match_file(
+        bot,
+        conn,
+        fapi,
+        SourceableFile {
+            file_id: &file.file_id,
+            file_unique_id: &file.file_unique_id,
+        },
+        distance,
+        chat_id,
+    )
+    .await
+}
+
+/// Attempt to match any file Telegram has given us against FuzzySearch. This
+/// works identically to [`match_image`], but does not require a [`PhotoSize`].
+///
+/// If FuzzySearch can't be reached, sources it previously found for similar
+/// images are used instead.
+///
+/// [`PhotoSize`]: tgbotapi::PhotoSize
+#[tracing::instrument(err, skip(bot, conn, fapi))]
+pub async fn match_file(
+    bot: &tgbotapi::Telegram,
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    fapi: &fuzzysearch::FuzzySearch,
+    file: SourceableFile<'_>,
+    distance: Option<i64>,
+    chat_id: Option<i64>,
 ) -> anyhow::Result<(i64, Vec<fuzzysearch::File>)> {
-    if let Some(hash) = FileCache::get(conn, &file.file_unique_id)
+    let hash = hash_file(bot, conn, file).await?;
+
+    Ok((
+        hash,
+        lookup_hash(conn, fapi, hash, distance, chat_id).await?,
+    ))
+}
+
+/// Lookup a hash from FuzzySearch, without any sources users reported as
+/// wrong in the chat or that enough people reported everywhere.
+///
+/// If FuzzySearch can't be reached, sources it previously found for similar
+/// images are used instead.
+pub async fn lookup_hash(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    fapi: &fuzzysearch::FuzzySearch,
+    hash: i64,
+    distance: Option<i64>,
+    chat_id: Option<i64>,
+) -> anyhow::Result<Vec<fuzzysearch::File>> {
+    let files = match lookup_single_hash(fapi, hash, distance).await {
+        Ok(files) => {
+            if let Err(err) = remember_sources(conn, hash, &files).await {
+                tracing::warn!("unable to remember sources: {:?}", err);
+            }
+
+            files
+        }
+        Err(err) if is_unavailable(&err) => {
+            tracing::warn!("fuzzysearch unavailable, using known sources: {:?}", err);
+
+            lookup_known_sources(conn, hash, distance).await?
+        }
+        Err(err) => return Err(err),
+    };
+
+    Ok(without_wrong_sources(conn, hash, chat_id, files).await)
+}
+
+/// Remove sources that users reported as wrong for this image.
+async fn without_wrong_sources(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    hash: i64,
+    chat_id: Option<i64>,
+    mut files: Vec<fuzzysearch::File>,
+) -> Vec<fuzzysearch::File> {
+    let wrong = match foxbot_models::WrongSources::for_hash(conn, hash, chat_id).await {
+        Ok(wrong) => wrong,
+        Err(err) => {
+            tracing::warn!("unable to get wrong sources: {:?}", err);
+            return files;
+        }
+    };
+
+    if !wrong.is_empty() {
+        files.retain(|file| !wrong.contains(&file.url()));
+        tracing::debug!(count = wrong.len(), "removed wrong sources");
+    }
+
+    files
+}
+
+/// Check if an error was from being unable to reach FuzzySearch, rather than
+/// FuzzySearch rejecting the request.
+fn is_unavailable(err: &anyhow::Error) -> bool {
+    err.chain()
+        .filter_map(|err| err.downcast_ref::<reqwest::Error>())
+        .any(|err| {
+            err.is_connect()
+                || err.is_timeout()
+                || err
+                    .status()
+                    .map(|status| status.is_server_error())
+                    .unwrap_or(false)
+        })
+}
+
+/// Save the sources found for a hash so they can be used if FuzzySearch is
+/// unavailable later.
+async fn remember_sources(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    hash: i64,
+    files: &[fuzzysearch::File],
+) -> anyhow::Result<()> {
+    for file in files {
+        let file_hash = file.hash.unwrap_or(hash);
+
+        KnownSources::record(
+            conn,
+            file.site_name(),
+            file.site_id,
+            file_hash,
+            serde_json::to_value(file)?,
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Find sources previously seen for images similar to a hash.
+async fn lookup_known_sources(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    hash: i64,
+    distance: Option<i64>,
+) -> anyhow::Result<Vec<fuzzysearch::File>> {
+    let files = KnownSources::similar(conn, hash)
+        .await?
+        .into_iter()
+        .filter_map(|file| serde_json::from_value(file).ok())
+        .collect();
+
+    let mut matches = group_matches(&[hash], files, distance);
+    let mut files = matches.remove(&hash).unwrap_or_default();
+
+    if let Some(distance) = distance {
+        files.retain(|file| {
+            file.distance
+                .map_or(false, |file_distance| file_distance as i64 <= distance)
+        });
+    }
+
+    tracing::debug!(count = files.len(), "found known sources");
+
+    Ok(files)
+}
+
+/// Get the perceptual hash of a file, downloading and hashing it only if it
+/// wasn't already cached.
+pub async fn hash_file(
+    bot: &tgbotapi::Telegram,
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    file: SourceableFile<'_>,
+) -> anyhow::Result<i64> {
+    if let Some(hash) = FileCache::get(conn, file.file_unique_id)
         .await
         .context("unable to query file cache")?
     {
-        return lookup_single_hash(fapi, hash, distance)
-            .await
-            .map(|files| (hash, files));
+        return Ok(hash);
     }
 
-    let get_file = tgbotapi::requests::GetFile {
-        file_id: file.file_id.clone(),
-    };
-
-    let file_info = bot
-        .make_request(&get_file)
-        .await
-        .context("unable to request file info from telegram")?;
-    let data = bot
-        .download_file(&file_info.file_path.unwrap())
-        .await
-        .context("unable to download file from telegram")?;
+    let data = bot_api::download_file(bot, file.file_id).await?;
+    download::check_image(&data)?;
 
-    let hash = tokio::task::spawn_blocking(move || fuzzysearch::hash_bytes(&data))
-        .instrument(tracing::debug_span!("hash_bytes"))
-        .await
-        .context("unable to spawn blocking")?
-        .context("unable to hash bytes")?;
+    let hash = decode::hash_bytes(data).await?;
 
-    FileCache::set(conn, &file.file_unique_id, hash)
+    FileCache::set(conn, file.file_unique_id, hash)
         .await
         .context("unable to set file cache")?;
 
-    lookup_single_hash(fapi, hash, distance)
-        .await
-        .map(|files| (hash, files))
+    Ok(hash)
 }
 
 /// Lookup a single hash from FuzzySearch, ensuring that the distance has been
@@ -557,35 +1060,93 @@ pub async fn lookup_single_hash(
     hash: i64,
     distance: Option<i64>,
 ) -> anyhow::Result<Vec<fuzzysearch::File>> {
-    let mut matches = fapi
-        .lookup_hashes(&[hash], distance)
-        .await
-        .context("unable to lookup hash")?;
+    let mut matches = lookup_hashes(fapi, &[hash], distance).await?;
+
+    Ok(matches.remove(&hash).unwrap_or_default())
+}
 
-    for mut m in &mut matches {
-        m.distance =
-            hamming::distance_fast(&m.hash.unwrap().to_be_bytes(), &hash.to_be_bytes()).ok();
+/// Lookup many hashes from FuzzySearch in a single request, returning the
+/// matches for each hash with their distance calculated from it.
+pub async fn lookup_hashes(
+    fapi: &fuzzysearch::FuzzySearch,
+    hashes: &[i64],
+    distance: Option<i64>,
+) -> anyhow::Result<HashMap<i64, Vec<fuzzysearch::File>>> {
+    if hashes.is_empty() {
+        return Ok(HashMap::new());
     }
 
-    matches.sort_by(|a, b| {
-        a.distance
-            .unwrap()
-            .partial_cmp(&b.distance.unwrap())
-            .unwrap()
-    });
+    let timer = FUZZYSEARCH_DURATION.start_timer();
+    let matches = fapi
+        .lookup_hashes(hashes, distance)
+        .await
+        .context("unable to lookup hashes")?;
+    timer.observe_duration();
 
-    // Twitter general rating is probably bad, remove it.
-    matches.iter_mut().for_each(|m| {
-        if !matches!(m.site_info, Some(SiteInfo::Twitter)) {
-            return;
-        }
+    Ok(group_matches(hashes, matches, distance))
+}
+
+/// Assign matches to each searched hash they are within the distance of, or
+/// the closest hash if none, sorted by distance.
+fn group_matches(
+    hashes: &[i64],
+    matches: Vec<fuzzysearch::File>,
+    max_distance: Option<i64>,
+) -> HashMap<i64, Vec<fuzzysearch::File>> {
+    let mut grouped: HashMap<i64, Vec<fuzzysearch::File>> =
+        hashes.iter().map(|hash| (*hash, Vec::new())).collect();
+
+    for mut m in matches {
+        let file_hash = match m.hash {
+            Some(hash) => hash.to_be_bytes(),
+            None => continue,
+        };
 
-        if matches!(m.rating, Some(fuzzysearch::Rating::General)) {
+        // Twitter general rating is probably bad, remove it.
+        if matches!(m.site_info, Some(SiteInfo::Twitter))
+            && matches!(m.rating, Some(fuzzysearch::Rating::General))
+        {
             m.rating = None;
         }
-    });
 
-    Ok(matches)
+        let distances: Vec<(i64, u64)> = hashes
+            .iter()
+            .filter_map(|hash| {
+                hamming::distance_fast(&file_hash, &hash.to_be_bytes())
+                    .ok()
+                    .map(|distance| (*hash, distance))
+            })
+            .collect();
+
+        let mut within: Vec<(i64, u64)> = distances
+            .iter()
+            .copied()
+            .filter(|(_hash, distance)| {
+                max_distance.map_or(false, |max_distance| *distance as i64 <= max_distance)
+            })
+            .collect();
+        if within.is_empty() {
+            within.extend(
+                distances
+                    .iter()
+                    .copied()
+                    .min_by_key(|(_hash, distance)| *distance),
+            );
+        }
+
+        for (hash, distance) in within {
+            let mut m = m.clone();
+            m.distance = Some(distance);
+
+            grouped.entry(hash).or_default().push(m);
+        }
+    }
+
+    for matches in grouped.values_mut() {
+        matches.sort_by_key(|m| m.distance);
+    }
+
+    grouped
 }
 
 /// Sort match results based on a user's preferences.
@@ -647,13 +1208,30 @@ pub fn sort_results_by(order: &[Sites], results: &mut [fuzzysearch::File], site_
     });
 }
 
-/// Get the first match for each site.
+/// Remove results from sites a chat never wants used as a source.
+pub fn remove_blocked_sites(blocked: &[Sites], results: &mut Vec<fuzzysearch::File>) {
+    if blocked.is_empty() {
+        return;
+    }
+
+    results.retain(|result| {
+        !blocked
+            .iter()
+            .any(|site| site.as_str() == result.site_name())
+    });
+}
+
+/// Get up to `per_site` matches with distinct URLs for each site.
 ///
 /// This expects that the results have already been sorted based on distance and
 /// filtered for undesired results.
-pub fn first_of_each_site(results: &[fuzzysearch::File]) -> Vec<(Sites, fuzzysearch::File)> {
-    let mut firsts = Vec::with_capacity(Sites::default_order().len());
-    let mut seen = HashSet::new();
+pub fn top_of_each_site(
+    results: &[fuzzysearch::File],
+    per_site: usize,
+) -> Vec<(Sites, fuzzysearch::File)> {
+    let mut tops = Vec::with_capacity(Sites::default_order().len() * per_site);
+    let mut counts: std::collections::HashMap<Sites, usize> = Default::default();
+    let mut seen_urls = HashSet::new();
 
     for result in results {
         let site = match Sites::from_str(result.site_name()) {
@@ -661,49 +1239,180 @@ pub fn first_of_each_site(results: &[fuzzysearch::File]) -> Vec<(Sites, fuzzysea
             _ => continue,
         };
 
-        if seen.contains(&site) {
+        let count = counts.entry(site.clone()).or_default();
+        if *count >= per_site || !seen_urls.insert(result.url()) {
             continue;
         }
 
-        seen.insert(site.clone());
-        firsts.push((site, result.to_owned()));
+        *count += 1;
+        tops.push((site, result.to_owned()));
     }
 
-    firsts
+    tops
 }
 
-/// Extract all possible links from a Message. It looks at the text,
-/// caption, and all buttons within an inline keyboard. Uses URL parsing from
-/// Telegram.
-pub fn extract_links(message: &tgbotapi::Message) -> Vec<&str> {
-    let mut links: Vec<&str> = vec![];
+/// How much further than the closest match another match may be while still
+/// being considered for [`artist_choices`].
+const ARTIST_CHOICE_DISTANCE: u64 = 1;
 
-    // See if it was posted with a bot that included an inline keyboard.
-    if let Some(ref markup) = message.reply_markup {
-        for row in &markup.inline_keyboard {
-            for button in row {
-                if let Some(url) = &button.url {
-                    links.push(url);
-                }
-            }
-        }
-    }
+/// Most artists that are offered as choices.
+pub const MAX_ARTIST_CHOICES: usize = 5;
 
-    if let Some(ref entities) = message.entities {
-        links.extend(extract_entity_links(
-            message.text.as_ref().unwrap(),
-            entities,
-        ));
-    }
+/// Group the closest matches by artist when they were made by different
+/// artists, so the person who posted the image can pick the right one.
+///
+/// Returns an empty list if every close match has the same artists. Matches
+/// without any artists are ignored.
+pub fn artist_choices<'a>(
+    matches: &[&'a fuzzysearch::File],
+) -> Vec<(String, Vec<&'a fuzzysearch::File>)> {
+    let closest = match matches.iter().filter_map(|file| file.distance).min() {
+        Some(closest) => closest,
+        None => return vec![],
+    };
 
-    if let Some(ref entities) = message.caption_entities {
-        links.extend(extract_entity_links(
-            message.caption.as_ref().unwrap(),
-            entities,
-        ));
-    }
+    let mut choices: Vec<(String, Vec<&fuzzysearch::File>)> = Vec::new();
 
-    links
+    for file in matches {
+        if file
+            .distance
+            .map_or(true, |distance| distance > closest + ARTIST_CHOICE_DISTANCE)
+        {
+            continue;
+        }
+
+        let mut artists = match &file.artists {
+            Some(artists) if !artists.is_empty() => artists.clone(),
+            _ => continue,
+        };
+        artists.sort_by_key(|artist| artist.to_lowercase());
+        let name = artists.join(", ");
+
+        match choices
+            .iter_mut()
+            .find(|(existing, _files)| existing.to_lowercase() == name.to_lowercase())
+        {
+            Some((_name, files)) => files.push(file),
+            None => choices.push((name, vec![file])),
+        }
+    }
+
+    if choices.len() < 2 {
+        return vec![];
+    }
+
+    choices.truncate(MAX_ARTIST_CHOICES);
+    choices
+}
+
+/// Keep only exact matches, checking that they were all made by one artist.
+///
+/// Returns false if there were no exact matches, or they didn't have exactly
+/// one artist between them, in which case no sources should be added.
+pub fn retain_strict_matches(matches: &mut Vec<fuzzysearch::File>) -> bool {
+    matches.retain(|file| file.distance == Some(0));
+
+    let mut artists: Vec<String> = matches
+        .iter()
+        .flat_map(|file| file.artists.iter().flatten())
+        .map(|artist| artist.to_lowercase())
+        .collect();
+    artists.sort();
+    artists.dedup();
+
+    artists.len() == 1
+}
+
+/// Jobs that apply each of the sources someone can choose between, saved
+/// until a choice is made.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct ArtistChoices {
+    /// Kind of job to enqueue with the chosen arguments.
+    pub job_kind: String,
+    /// The user who posted the image, who may also choose.
+    pub poster_id: Option<i64>,
+    /// Job arguments for each choice, in the same order as the buttons.
+    pub choices: Vec<serde_json::Value>,
+    /// Hash of the image, so the choice can be remembered.
+    #[serde(default)]
+    pub hash: Option<i64>,
+    /// Name of the artist for each choice.
+    #[serde(default)]
+    pub names: Vec<String>,
+}
+
+/// Use the artist someone already chose for an image in a chat, if it's one of
+/// the choices.
+///
+/// The chosen artist's matches are returned and the choices are cleared so
+/// nobody is asked again.
+pub async fn apply_chosen_artist<'a>(
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    chat_id: i64,
+    hash: i64,
+    choices: &mut Vec<(String, Vec<&'a fuzzysearch::File>)>,
+) -> anyhow::Result<Option<Vec<&'a fuzzysearch::File>>> {
+    if choices.is_empty() {
+        return Ok(None);
+    }
+
+    let chosen = match foxbot_models::ArtistChoice::chosen(conn, chat_id, hash).await? {
+        Some(chosen) => chosen,
+        None => return Ok(None),
+    };
+
+    let index = match choices
+        .iter()
+        .position(|(name, _files)| name.to_lowercase() == chosen.to_lowercase())
+    {
+        Some(index) => index,
+        None => return Ok(None),
+    };
+
+    tracing::debug!(%chosen, "using previously chosen artist");
+
+    let (_name, files) = choices.swap_remove(index);
+    choices.clear();
+
+    Ok(Some(files))
+}
+
+/// Redis key holding the [`ArtistChoices`] for an image.
+pub fn artist_choices_key(chat_id: i64, message_id: i32) -> String {
+    format!("artist-choices:{}:{}", chat_id, message_id)
+}
+
+/// Extract all possible links from a Message. It looks at the text,
+/// caption, and all buttons within an inline keyboard. Uses URL parsing from
+/// Telegram, so links hidden behind hyperlinked text are included.
+///
+/// Each link is only returned once, in the order it first appeared.
+pub fn extract_links(message: &tgbotapi::Message) -> Vec<&str> {
+    let mut links: Vec<&str> = vec![];
+
+    // See if it was posted with a bot that included an inline keyboard.
+    if let Some(ref markup) = message.reply_markup {
+        for row in &markup.inline_keyboard {
+            for button in row {
+                if let Some(url) = &button.url {
+                    links.push(url);
+                }
+            }
+        }
+    }
+
+    if let (Some(text), Some(entities)) = (&message.text, &message.entities) {
+        links.extend(extract_entity_links(text, entities));
+    }
+
+    if let (Some(caption), Some(entities)) = (&message.caption, &message.caption_entities) {
+        links.extend(extract_entity_links(caption, entities));
+    }
+
+    let mut seen = HashSet::with_capacity(links.len());
+    links.retain(|link| seen.insert(*link));
+
+    links
 }
 
 /// Process all entities in Telegram message to find links.
@@ -715,7 +1424,9 @@ fn extract_entity_links<'a>(
 
     for entity in entities {
         if entity.entity_type == tgbotapi::MessageEntityType::TextLink {
-            links.push(entity.url.as_ref().unwrap());
+            if let Some(url) = &entity.url {
+                links.push(url);
+            }
         } else if entity.entity_type == tgbotapi::MessageEntityType::Url {
             links.push(get_entity_text(text, entity));
         }
@@ -724,6 +1435,59 @@ fn extract_entity_links<'a>(
     links
 }
 
+/// Where a forwarded message was originally posted.
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ForwardOrigin {
+    /// Name of the chat or user, like `@channel`, if known.
+    pub name: Option<String>,
+    /// Link to the original post, if it was in a public channel.
+    pub link: Option<String>,
+}
+
+/// Redis key holding the hash each source in a reply was found for, for
+/// replies with sources for several images like an album.
+pub fn wrong_source_key(chat_id: i64, message_id: i32) -> String {
+    format!("wrong-source:{}:{}", chat_id, message_id)
+}
+
+/// Redis key holding the source reply for a forwarded image until someone
+/// asks for it.
+pub fn forwarded_source_key(chat_id: i64, message_id: i32) -> String {
+    format!("forwarded-source:{}:{}", chat_id, message_id)
+}
+
+/// Get where a message was forwarded from, if it was forwarded.
+pub fn forward_origin(message: &tgbotapi::Message) -> Option<ForwardOrigin> {
+    message.forward_date?;
+
+    if let Some(chat) = &message.forward_from_chat {
+        let link = match (&chat.username, message.forward_from_message_id) {
+            (Some(username), Some(message_id)) => {
+                Some(format!("https://t.me/{}/{}", username, message_id))
+            }
+            _ => None,
+        };
+
+        let name = chat
+            .username
+            .as_ref()
+            .map(|username| format!("@{}", username))
+            .or_else(|| chat.title.clone());
+
+        return Some(ForwardOrigin { name, link });
+    }
+
+    let name = message
+        .forward_from
+        .as_ref()
+        .map(|user| match &user.username {
+            Some(username) => format!("@{}", username),
+            None => user.first_name.clone(),
+        });
+
+    Some(ForwardOrigin { name, link: None })
+}
+
 /// Try to calculate a UTF16 position in a UTF8 string.
 ///
 /// This is required because Telegram provides entities in UTF16 code units.
@@ -896,6 +1660,61 @@ pub async fn can_delete_in_chat(
     Ok(can_delete)
 }
 
+/// Check if the bot has permissions to edit messages in a channel. Checks the
+/// cache if not being told to ignore it.
+pub async fn can_edit_in_channel(
+    bot: &tgbotapi::Telegram,
+    conn: &sqlx::Pool<sqlx::Postgres>,
+    chat_id: i64,
+    user_id: i64,
+    ignore_cache: bool,
+) -> anyhow::Result<bool> {
+    use foxbot_models::{GroupConfig, GroupConfigKey};
+
+    if !ignore_cache {
+        let can_edit: Option<bool> =
+            GroupConfig::get(conn, chat_id, GroupConfigKey::HasEditPermission).await?;
+
+        if let Some(can_edit) = can_edit {
+            return Ok(can_edit);
+        }
+    }
+
+    let chat_member = bot
+        .make_request(&tgbotapi::requests::GetChatMember {
+            chat_id: chat_id.into(),
+            user_id,
+        })
+        .await?;
+    let can_edit = chat_member.can_edit_messages.unwrap_or(false);
+
+    GroupConfig::set(conn, GroupConfigKey::HasEditPermission, chat_id, can_edit).await?;
+
+    Ok(can_edit)
+}
+
+/// Compare secrets without leaking how much of them matched.
+pub fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+/// Get a label for the kind of error returned by Telegram, for use in metrics.
+///
+/// Errors from the Bot API use their error code, anything else is `other`.
+pub fn telegram_error_code(err: &tgbotapi::Error) -> String {
+    match err {
+        tgbotapi::Error::Telegram(tgbotapi::TelegramError {
+            error_code: Some(code),
+            ..
+        }) => code.to_string(),
+        _ => "other".to_string(),
+    }
+}
+
 /// Get the name of the localization for a given rating, or if it's unknown.
 pub fn get_rating_bundle_name(rating: &Option<fuzzysearch::Rating>) -> Option<&'static str> {
     match rating {
@@ -962,11 +1781,89 @@ pub fn source_reply(matches: &[fuzzysearch::File], bundle: Bundle<'_>) -> String
     }
 }
 
+/// Build the text for an automatic reply in a group containing each of the
+/// given matches.
+pub fn build_source_text(bundle: Bundle<'_>, matches: &[&fuzzysearch::File]) -> String {
+    if let [m] = matches {
+        let mut args = fluent::FluentArgs::new();
+        args.insert("link", m.url().into());
+
+        if let Some(rating) = get_rating_bundle_name(&m.rating) {
+            args.insert("rating", rating.into());
+
+            get_message(bundle, "automatic-single", Some(args)).unwrap()
+        } else {
+            get_message(bundle, "automatic-single-unknown", Some(args)).unwrap()
+        }
+    } else {
+        let mut buf = String::new();
+
+        buf.push_str(&get_message(bundle, "automatic-multiple", None).unwrap());
+        buf.push('\n');
+
+        for result in matches {
+            let mut args = fluent::FluentArgs::new();
+            args.insert("link", result.url().into());
+
+            let message = if let Some(rating) = get_rating_bundle_name(&result.rating) {
+                let rating = get_message(bundle, rating, None).unwrap();
+                args.insert("rating", rating.into());
+                get_message(bundle, "automatic-multiple-result", Some(args)).unwrap()
+            } else {
+                get_message(bundle, "automatic-multiple-result-unknown", Some(args)).unwrap()
+            };
+
+            buf.push_str(&message);
+            buf.push('\n');
+        }
+
+        buf
+    }
+}
+
+/// Describe extra details about a source, like its artists and the pools it's
+/// part of, returning nothing if the site didn't provide any.
+pub fn build_post_details(bundle: Bundle<'_>, post: &PostInfo) -> Option<String> {
+    let mut lines = vec![];
+
+    if !post.artists.is_empty() {
+        let mut args = fluent::FluentArgs::new();
+        args.insert("artists", post.artists.join(", ").into());
+        lines.push(get_message(bundle, "source-details-artists", Some(args)).ok()?);
+    }
+
+    for pool in &post.pools {
+        let mut args = fluent::FluentArgs::new();
+        args.insert("name", pool.name.clone().into());
+        args.insert("link", pool.url.clone().into());
+        lines.push(get_message(bundle, "source-details-pool", Some(args)).ok()?);
+    }
+
+    if let Some(parent_link) = &post.parent_link {
+        let mut args = fluent::FluentArgs::new();
+        args.insert("link", parent_link.clone().into());
+        lines.push(get_message(bundle, "source-details-parent", Some(args)).ok()?);
+    }
+
+    if !post.child_links.is_empty() {
+        let mut args = fluent::FluentArgs::new();
+        args.insert("links", post.child_links.join(", ").into());
+        lines.push(get_message(bundle, "source-details-children", Some(args)).ok()?);
+    }
+
+    if lines.is_empty() {
+        None
+    } else {
+        Some(lines.join("\n"))
+    }
+}
+
 /// A wrapper around checking the size of a file at a given URL.
 ///
 /// It manages checking the length using the content-length header if provided,
 /// or by downloading the contents if no such header exists. It also prevents
-/// resource attacks by limiting the maximum size of file it will download.
+/// resource attacks by limiting the maximum size of file it will download and
+/// the dimensions of images, using the [`download`] limits.
 pub struct CheckFileSize<'a> {
     pub url: &'a str,
     pub max_download: usize,
@@ -1030,24 +1927,10 @@ impl<'a> CheckFileSize<'a> {
             return Ok(bytes);
         }
 
-        let mut data = self.client.get(self.url).send().await?;
-
-        let mut buf = bytes::BytesMut::new();
-
-        while let Some(chunk) = data.chunk().await? {
-            buf.extend(chunk);
-
-            if buf.len() > self.max_download {
-                tracing::warn!(
-                    size = buf.len(),
-                    max_download = self.max_download,
-                    "Requested download is larger than max size"
-                );
-                anyhow::bail!("Body is larger than maximum permissible download");
-            }
-        }
+        let data = self.client.get(self.url).send().await?;
 
-        let bytes = buf.freeze();
+        let limits = download::limits().with_max_bytes(self.max_download);
+        let bytes = download::read_limited(data, limits).await?;
 
         self.bytes = Some(bytes);
         Ok(self.bytes.as_ref().unwrap())
@@ -1070,8 +1953,6 @@ impl<'a> CheckFileSize<'a> {
 /// JPEG image. Convert the result into a type usable for sending via Telegram.
 #[tracing::instrument]
 pub async fn resize_photo(url: &str, max_size: u64) -> anyhow::Result<tgbotapi::FileType> {
-    use bytes::BufMut;
-
     let mut check = CheckFileSize::new(url, 20_000_000);
     let size = check.get_size().await?;
 
@@ -1092,21 +1973,23 @@ pub async fn resize_photo(url: &str, max_size: u64) -> anyhow::Result<tgbotapi::
 
     let bytes = check.into_bytes().await?;
 
-    let im = image::load_from_memory(&bytes)?;
-    let im = im.resize(2000, 2000, image::imageops::FilterType::Lanczos3);
-    let im = image::DynamicImage::ImageRgb8(im.into_rgb8());
-
-    let mut buf = bytes::BytesMut::with_capacity(2_000_000).writer();
-    im.write_to(&mut buf, image::ImageOutputFormat::Jpeg(90))?;
+    let image = decode::run(move || {
+        image_edit::ImagePipeline::new()
+            .downscale(image_edit::MAX_SIZE)
+            .jpeg(image_edit::JPEG_QUALITY)
+            .process(bytes)
+    })
+    .await??;
 
-    let bytes = buf.into_inner().freeze().to_vec();
-    Ok(FileType::Bytes(format!("{}.jpg", generate_id()), bytes))
+    Ok(FileType::Bytes(
+        format!("{}.jpg", generate_id()),
+        image.data.to_vec(),
+    ))
 }
 
-/// Known resources for each language.
-pub static L10N_RESOURCES: &[&str] = &["foxbot.ftl"];
-/// Known languages.
-pub static L10N_LANGS: &[&str] = &["en-US"];
+/// Language used when a user's language isn't known or available, and for
+/// messages that haven't been translated.
+pub static DEFAULT_LANG: &str = "en-US";
 
 /// A collection of language identifiers and their corresponding data.
 pub type Langs = std::collections::HashMap<unic_langid::LanguageIdentifier, Vec<String>>;
@@ -1115,46 +1998,26 @@ pub type LangBundle = fluent::concurrent::FluentBundle<fluent::FluentResource>;
 
 /// Load all language data from the langs folder.
 pub fn load_langs() -> Langs {
-    let mut dir = std::env::current_dir().expect("Unable to get directory");
-    dir.push("langs");
-
-    let mut langs = std::collections::HashMap::new();
-
-    for lang in L10N_LANGS {
-        let path = dir.join(lang);
-
-        let mut lang_resources = Vec::with_capacity(L10N_RESOURCES.len());
-        let langid = lang
-            .parse::<unic_langid::LanguageIdentifier>()
-            .expect("Unable to parse language");
-
-        for resource in L10N_RESOURCES {
-            let file = path.join(resource);
-            let s = std::fs::read_to_string(file).expect("Unable to read file");
-
-            lang_resources.push(s);
-        }
-
-        langs.insert(langid, lang_resources);
-    }
-
-    langs
+    l10n::load_dir(&l10n::langs_dir()).expect("unable to load languages")
 }
 
 /// Get a bundle for a desired language.
+///
+/// Messages that haven't been translated into the language fall back to the
+/// next best language, and finally the default language.
 pub fn get_lang_bundle(langs: &Langs, requested: &str) -> LangBundle {
     let requested_locale = match requested.parse::<unic_langid::LanguageIdentifier>() {
         Ok(locale) => locale,
         Err(err) => {
             tracing::error!("unknown locale: {:?}", err);
-            L10N_LANGS[0]
+            DEFAULT_LANG
                 .parse::<unic_langid::LanguageIdentifier>()
                 .unwrap()
         }
     };
 
     let requested_locales: Vec<unic_langid::LanguageIdentifier> = vec![requested_locale];
-    let default_locale = L10N_LANGS[0]
+    let default_locale = DEFAULT_LANG
         .parse::<unic_langid::LanguageIdentifier>()
         .expect("unable to parse langid");
     let available: Vec<unic_langid::LanguageIdentifier> = langs.keys().map(Clone::clone).collect();
@@ -1165,18 +2028,31 @@ pub fn get_lang_bundle(langs: &Langs, requested: &str) -> LangBundle {
         fluent_langneg::NegotiationStrategy::Filtering,
     );
 
-    let current_locale = resolved_locales.get(0).expect("no locales were available");
+    if resolved_locales.is_empty() {
+        panic!("no locales were available");
+    }
 
     let mut bundle =
         fluent::concurrent::FluentBundle::<fluent::FluentResource>::new(resolved_locales.clone());
-    let resources = langs.get(current_locale).expect("missing known locale");
 
-    for resource in resources {
-        let resource = fluent::FluentResource::try_new(resource.to_string())
-            .expect("unable to parse FTL string");
-        bundle
-            .add_resource(resource)
-            .expect("unable to add resource");
+    // The best language is added first, so later languages only add the
+    // messages it's missing instead of replacing its translations.
+    for (index, locale) in resolved_locales.iter().enumerate() {
+        let resources = match langs.get(locale) {
+            Some(resources) => resources,
+            None => continue,
+        };
+
+        for resource in resources {
+            let resource = fluent::FluentResource::try_new(resource.to_string())
+                .expect("unable to parse FTL string");
+
+            if let Err(errors) = bundle.add_resource(resource) {
+                if index == 0 {
+                    panic!("unable to add resource: {:?}", errors);
+                }
+            }
+        }
     }
 
     bundle.set_use_isolating(false);
@@ -1184,6 +2060,16 @@ pub fn get_lang_bundle(langs: &Langs, requested: &str) -> LangBundle {
     bundle
 }
 
+/// Faktory queue for jobs that a user is actively waiting on.
+pub const QUEUE_HIGH: &str = "foxbot_high";
+/// Faktory queue for most background work, such as sourcing channel posts.
+pub const QUEUE_DEFAULT: &str = "foxbot_default";
+/// Faktory queue for bulk work that may take a long time, like backfills.
+pub const QUEUE_LOW: &str = "foxbot_low";
+
+/// Redis sorted set of scheduled job IDs, scored by when they should run.
+pub const SCHEDULED_JOBS_KEY: &str = "scheduled-jobs";
+
 pub fn get_faktory_custom() -> std::collections::HashMap<String, serde_json::Value> {
     use opentelemetry::propagation::TextMapPropagator;
     use tracing_opentelemetry::OpenTelemetrySpanExt;
@@ -1203,6 +2089,32 @@ pub fn get_faktory_custom() -> std::collections::HashMap<String, serde_json::Val
         .collect()
 }
 
+/// Give a job a specific ID, such as the ID of the job it replaces.
+///
+/// Job IDs can't be set directly, so the job goes through its serialized form.
+pub fn with_job_id(job: faktory::Job, job_id: &str) -> anyhow::Result<faktory::Job> {
+    let mut value = serde_json::to_value(&job)?;
+    value["jid"] = job_id.into();
+
+    Ok(serde_json::from_value(value)?)
+}
+
+/// Build a job to run a failed job again.
+///
+/// The job keeps the failed job's ID so it still owns any keys it claimed
+/// before failing, otherwise it would be skipped as a duplicate.
+pub fn retry_failed_job(failed: foxbot_models::FailedJob) -> anyhow::Result<faktory::Job> {
+    let args = match failed.args {
+        serde_json::Value::Array(args) => args,
+        args => vec![args],
+    };
+
+    let mut job = faktory::Job::new(failed.kind, args).on_queue(&failed.queue);
+    job.custom = get_faktory_custom();
+
+    with_job_id(job, &failed.job_id)
+}
+
 #[cfg(test)]
 mod tests {
     fn get_finder() -> linkify::LinkFinder {
@@ -1316,6 +2228,105 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_find_hidden_links() {
+        let message = tgbotapi::Message {
+            text: Some("Source here, also here".into()),
+            entities: Some(vec![
+                tgbotapi::MessageEntity {
+                    entity_type: tgbotapi::MessageEntityType::TextLink,
+                    offset: 7,
+                    length: 4,
+                    url: Some("https://www.furaffinity.net/view/1/".to_string()),
+                    user: None,
+                },
+                tgbotapi::MessageEntity {
+                    entity_type: tgbotapi::MessageEntityType::TextLink,
+                    offset: 18,
+                    length: 4,
+                    url: Some("https://www.furaffinity.net/view/1/".to_string()),
+                    user: None,
+                },
+                tgbotapi::MessageEntity {
+                    entity_type: tgbotapi::MessageEntityType::TextLink,
+                    offset: 0,
+                    length: 6,
+                    url: None,
+                    user: None,
+                },
+            ]),
+            reply_markup: Some(tgbotapi::InlineKeyboardMarkup {
+                inline_keyboard: vec![vec![tgbotapi::InlineKeyboardButton {
+                    url: Some("https://www.furaffinity.net/view/1/".into()),
+                    ..Default::default()
+                }]],
+            }),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            super::extract_links(&message),
+            vec!["https://www.furaffinity.net/view/1/"],
+            "hyperlinked text should be found once"
+        );
+
+        let message = tgbotapi::Message {
+            caption_entities: Some(vec![tgbotapi::MessageEntity {
+                entity_type: tgbotapi::MessageEntityType::Url,
+                offset: 0,
+                length: 10,
+                url: None,
+                user: None,
+            }]),
+            ..Default::default()
+        };
+
+        assert!(
+            super::extract_links(&message).is_empty(),
+            "entities without text should be ignored"
+        );
+    }
+
+    #[test]
+    fn test_forward_origin() {
+        use super::{forward_origin, ForwardOrigin};
+
+        assert_eq!(forward_origin(&tgbotapi::Message::default()), None);
+
+        let message = tgbotapi::Message {
+            forward_date: Some(0),
+            forward_from_chat: Some(tgbotapi::Chat {
+                username: Some("artchannel".into()),
+                ..Default::default()
+            }),
+            forward_from_message_id: Some(42),
+            ..Default::default()
+        };
+        assert_eq!(
+            forward_origin(&message),
+            Some(ForwardOrigin {
+                name: Some("@artchannel".into()),
+                link: Some("https://t.me/artchannel/42".into()),
+            })
+        );
+
+        let message = tgbotapi::Message {
+            forward_date: Some(0),
+            forward_from: Some(tgbotapi::User {
+                first_name: "Fox".into(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            forward_origin(&message),
+            Some(ForwardOrigin {
+                name: Some("Fox".into()),
+                link: None,
+            })
+        );
+    }
+
     #[tokio::test]
     async fn test_link_was_seen() {
         let finder = get_finder();
@@ -1332,6 +2343,7 @@ mod tests {
                 foxbot_sites::E621Host::E621,
                 "".into(),
                 "".into(),
+                &Default::default(),
             )),
             Box::new(foxbot_sites::Mastodon::default()),
         ];
@@ -1407,4 +2419,290 @@ mod tests {
         sort_results_by(&order, &mut results, true);
         assert!(matches_are_sorted(&results));
     }
+
+    #[test]
+    fn test_thumbnail_proxy_url() {
+        use super::thumbnail_proxy_url;
+
+        assert_eq!(
+            thumbnail_proxy_url(
+                "https://images.weserv.nl/",
+                "https://example.com/image.png?a=1&b=2"
+            ),
+            "https://images.weserv.nl/?url=https%3A%2F%2Fexample.com%2Fimage.png%3Fa%3D1%26b%3D2&w=400&h=400&fit=inside&output=jpg"
+        );
+        assert_eq!(
+            thumbnail_proxy_url("not a url", "https://example.com/image.png"),
+            "https://example.com/image.png",
+            "invalid proxies should use the original image"
+        );
+    }
+
+    #[test]
+    fn test_group_matches() {
+        use super::group_matches;
+
+        let file = |site_id, hash| fuzzysearch::File {
+            site_id,
+            hash: Some(hash),
+            ..Default::default()
+        };
+
+        let matches = vec![
+            file(1, 0b10_0011),
+            file(2, 0b1000),
+            file(3, 0b0111),
+            file(4, 0),
+        ];
+        let grouped = group_matches(&[0b1000, 0b0111], matches, Some(1));
+
+        let first: Vec<_> = grouped[&0b1000]
+            .iter()
+            .map(|m| (m.site_id, m.distance))
+            .collect();
+        assert_eq!(first, vec![(2, Some(0)), (4, Some(1))]);
+
+        // Too far from both hashes, so it's given to the closest.
+        let second: Vec<_> = grouped[&0b0111]
+            .iter()
+            .map(|m| (m.site_id, m.distance))
+            .collect();
+        assert_eq!(second, vec![(3, Some(0)), (1, Some(2))]);
+    }
+
+    #[test]
+    fn test_top_of_each_site() {
+        use super::top_of_each_site;
+
+        let weasyl = |site_id| fuzzysearch::File {
+            site_id,
+            distance: Some(0),
+            site_info: Some(fuzzysearch::SiteInfo::Weasyl),
+            ..Default::default()
+        };
+
+        let results = vec![weasyl(1), weasyl(1), weasyl(2), weasyl(3)];
+
+        let ids: Vec<_> = top_of_each_site(&results, 1)
+            .into_iter()
+            .map(|(_site, file)| file.site_id)
+            .collect();
+        assert_eq!(ids, vec![1]);
+
+        let ids: Vec<_> = top_of_each_site(&results, 2)
+            .into_iter()
+            .map(|(_site, file)| file.site_id)
+            .collect();
+        assert_eq!(ids, vec![1, 2], "duplicate urls should be skipped");
+    }
+
+    #[test]
+    fn test_retain_strict_matches() {
+        use super::retain_strict_matches;
+
+        let file = |site_id, distance, artist: &str| fuzzysearch::File {
+            site_id,
+            distance: Some(distance),
+            artists: Some(vec![artist.to_string()]),
+            ..Default::default()
+        };
+
+        let mut matches = vec![
+            file(1, 0, "Syfaro"),
+            file(2, 0, "syfaro"),
+            file(3, 1, "other"),
+        ];
+        assert!(retain_strict_matches(&mut matches));
+        let ids: Vec<_> = matches.iter().map(|file| file.site_id).collect();
+        assert_eq!(ids, vec![1, 2], "only exact matches should be kept");
+
+        let mut matches = vec![file(1, 0, "Syfaro"), file(2, 0, "other")];
+        assert!(
+            !retain_strict_matches(&mut matches),
+            "matches by different artists should not be strict"
+        );
+
+        let mut matches = vec![file(1, 1, "Syfaro")];
+        assert!(
+            !retain_strict_matches(&mut matches),
+            "inexact matches should not be strict"
+        );
+    }
+
+    #[test]
+    fn test_remove_blocked_sites() {
+        use super::remove_blocked_sites;
+        use foxbot_models::Sites;
+
+        let file = |site_id, site_info| fuzzysearch::File {
+            site_id,
+            site_info: Some(site_info),
+            ..Default::default()
+        };
+
+        let mut results = vec![
+            file(1, fuzzysearch::SiteInfo::Weasyl),
+            file(2, fuzzysearch::SiteInfo::Twitter),
+            file(3, fuzzysearch::SiteInfo::Weasyl),
+        ];
+
+        remove_blocked_sites(&[], &mut results);
+        assert_eq!(results.len(), 3);
+
+        remove_blocked_sites(&[Sites::Weasyl], &mut results);
+        let ids: Vec<_> = results.iter().map(|file| file.site_id).collect();
+        assert_eq!(ids, vec![2]);
+    }
+
+    #[test]
+    fn test_artist_choices() {
+        use super::artist_choices;
+
+        let file = |site_id, distance, artist: &str| fuzzysearch::File {
+            site_id,
+            distance: Some(distance),
+            artists: Some(vec![artist.to_string()]),
+            ..Default::default()
+        };
+
+        let same = vec![file(1, 0, "Syfaro"), file(2, 1, "syfaro")];
+        let same: Vec<_> = same.iter().collect();
+        assert!(
+            artist_choices(&same).is_empty(),
+            "artists should be compared without case"
+        );
+
+        let far = vec![file(1, 0, "Syfaro"), file(2, 3, "Other")];
+        let far: Vec<_> = far.iter().collect();
+        assert!(
+            artist_choices(&far).is_empty(),
+            "much further matches should be ignored"
+        );
+
+        let different = vec![
+            file(1, 0, "Syfaro"),
+            file(2, 1, "Other"),
+            file(3, 1, "syfaro"),
+        ];
+        let different: Vec<_> = different.iter().collect();
+        let choices: Vec<_> = artist_choices(&different)
+            .into_iter()
+            .map(|(name, files)| {
+                (
+                    name,
+                    files.iter().map(|file| file.site_id).collect::<Vec<_>>(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            choices,
+            vec![
+                ("Syfaro".to_string(), vec![1, 3]),
+                ("Other".to_string(), vec![2])
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_sourceable_file() {
+        use super::{bot_api::HOSTED_DOWNLOAD_LIMIT, find_sourceable_file, SourceableFile};
+
+        let document = |mime_type: &str, file_size: i32| tgbotapi::Document {
+            file_id: "document".to_string(),
+            file_unique_id: "document-unique".to_string(),
+            thumb: None,
+            file_name: None,
+            mime_type: Some(mime_type.to_string()),
+            file_size: Some(file_size),
+        };
+
+        let message = tgbotapi::Message::default();
+        assert_eq!(
+            find_sourceable_file(&message, HOSTED_DOWNLOAD_LIMIT),
+            None,
+            "messages without media should not have a file"
+        );
+
+        let message = tgbotapi::Message {
+            document: Some(document("image/png", 1_000)),
+            ..Default::default()
+        };
+        assert_eq!(
+            find_sourceable_file(&message, HOSTED_DOWNLOAD_LIMIT),
+            Some(SourceableFile {
+                file_id: "document",
+                file_unique_id: "document-unique",
+            }),
+            "image documents should be sourceable"
+        );
+
+        let message = tgbotapi::Message {
+            document: Some(document("application/pdf", 1_000)),
+            ..Default::default()
+        };
+        assert_eq!(
+            find_sourceable_file(&message, HOSTED_DOWNLOAD_LIMIT),
+            None,
+            "non-image documents should be ignored"
+        );
+
+        let message = tgbotapi::Message {
+            document: Some(document("image/png", 50_000_000)),
+            ..Default::default()
+        };
+        assert_eq!(
+            find_sourceable_file(&message, HOSTED_DOWNLOAD_LIMIT),
+            None,
+            "documents over the size limit should be ignored"
+        );
+        assert!(
+            find_sourceable_file(&message, super::bot_api::LOCAL_DOWNLOAD_LIMIT).is_some(),
+            "large documents should be sourceable with a local bot api server"
+        );
+
+        let message = tgbotapi::Message {
+            document: Some(tgbotapi::Document {
+                file_size: None,
+                ..document("image/png", 1_000)
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            find_sourceable_file(&message, HOSTED_DOWNLOAD_LIMIT),
+            None,
+            "documents without a size should be treated as too large"
+        );
+
+        let message = tgbotapi::Message {
+            animation: Some(tgbotapi::Animation {
+                thumb: Some(tgbotapi::PhotoSize {
+                    file_id: "thumb".to_string(),
+                    file_unique_id: "thumb-unique".to_string(),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            }),
+            // Telegram includes a document alongside animations.
+            document: Some(document("video/mp4", 1_000)),
+            ..Default::default()
+        };
+        assert_eq!(
+            find_sourceable_file(&message, HOSTED_DOWNLOAD_LIMIT),
+            Some(SourceableFile {
+                file_id: "thumb",
+                file_unique_id: "thumb-unique",
+            }),
+            "animations should use their thumbnail"
+        );
+    }
+
+    #[test]
+    fn test_constant_time_eq() {
+        use super::constant_time_eq;
+
+        assert!(constant_time_eq("secret", "secret"));
+        assert!(!constant_time_eq("secret", "secreT"));
+        assert!(!constant_time_eq("secret", "secret2"));
+        assert!(!constant_time_eq("", "secret"));
+    }
 }
@@ -0,0 +1,260 @@
+use anyhow::Context;
+use rusoto_s3::S3;
+
+use crate::{download, image_edit::ImagePipeline, store_edited_image, store_image, ImageInfo};
+use foxbot_models::CachedPost;
+use foxbot_sites::PostInfo;
+
+/// Seconds to keep rehosted images if no TTL was configured.
+pub const DEFAULT_REHOST_TTL: i64 = 60 * 60 * 24 * 7;
+/// Most expired images to remove at once.
+const CLEANUP_BATCH_SIZE: i64 = 100;
+
+/// Create an S3 client for an S3 compatible endpoint, like minio.
+pub fn s3_client(
+    endpoint: String,
+    region: String,
+    token: String,
+    secret: String,
+) -> rusoto_s3::S3Client {
+    let region = rusoto_core::Region::Custom {
+        name: region,
+        endpoint,
+    };
+
+    let client = rusoto_core::request::HttpClient::new().expect("unable to create http client");
+    let provider = rusoto_credential::StaticProvider::new_minimal(token, secret);
+
+    rusoto_s3::S3Client::new_with(client, provider, region)
+}
+
+/// Rehosts images from sites whose hosts often refuse to serve Telegram, so
+/// Telegram loads them from our bucket instead.
+pub struct MediaCache {
+    s3: rusoto_s3::S3Client,
+    bucket: String,
+    url: String,
+    sites: Vec<String>,
+    ttl: i64,
+}
+
+impl MediaCache {
+    /// Create a cache rehosting images from the named sites, which are kept
+    /// for `ttl` seconds.
+    pub fn new(
+        s3: rusoto_s3::S3Client,
+        bucket: String,
+        url: String,
+        sites: Vec<String>,
+        ttl: Option<i64>,
+    ) -> Self {
+        Self {
+            s3,
+            bucket,
+            url,
+            sites,
+            ttl: ttl.unwrap_or(DEFAULT_REHOST_TTL),
+        }
+    }
+
+    /// Check if a post's image should be rehosted.
+    pub fn should_rehost(&self, post: &PostInfo) -> bool {
+        matches!(post.file_type.as_ref(), "png" | "jpeg" | "jpg")
+            && self
+                .sites
+                .iter()
+                .any(|site| site.eq_ignore_ascii_case(&post.site_name))
+    }
+
+    /// Rehost a post's image if needed, downloading it only if it wasn't
+    /// already rehosted.
+    pub async fn rehost(
+        &self,
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        post: &PostInfo,
+    ) -> anyhow::Result<PostInfo> {
+        if !self.should_rehost(post) {
+            return Ok(post.to_owned());
+        }
+
+        if let Some(cached) = self.get(conn, post).await? {
+            return Ok(cached);
+        }
+
+        let data = download::download(&post.url).await?;
+        self.store(conn, post, &data).await
+    }
+
+    /// Rehost a post's image if needed, using data that was already
+    /// downloaded.
+    pub async fn rehost_data(
+        &self,
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        post: &PostInfo,
+        data: &bytes::Bytes,
+    ) -> anyhow::Result<PostInfo> {
+        if !self.should_rehost(post) {
+            return Ok(post.to_owned());
+        }
+
+        if let Some(cached) = self.get(conn, post).await? {
+            return Ok(cached);
+        }
+
+        self.store(conn, post, data).await
+    }
+
+    /// Edit an image and upload it, removing it along with rehosted images
+    /// once it expires.
+    ///
+    /// Edited images are only shared once, so they're cached by their own URL
+    /// instead of a post's.
+    pub async fn store_edited(
+        &self,
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        pipeline: &ImagePipeline,
+        data: &bytes::Bytes,
+    ) -> anyhow::Result<ImageInfo> {
+        let image =
+            store_edited_image(&self.s3, &self.bucket, &self.url, "edited", pipeline, data).await?;
+
+        CachedPost::save_expiring(conn, &image.url, &image.url, image.dimensions, self.ttl).await?;
+
+        Ok(image)
+    }
+
+    async fn get(
+        &self,
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        post: &PostInfo,
+    ) -> anyhow::Result<Option<PostInfo>> {
+        let cached = CachedPost::get(conn, &post.url, false)
+            .await
+            .context("unable to get cached post")?;
+
+        Ok(cached.map(|cached| PostInfo {
+            url: cached.cdn_url,
+            image_dimensions: Some(cached.dimensions),
+            ..post.to_owned()
+        }))
+    }
+
+    #[tracing::instrument(skip(self, conn, post, data), fields(url = %post.url))]
+    async fn store(
+        &self,
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        post: &PostInfo,
+        data: &bytes::Bytes,
+    ) -> anyhow::Result<PostInfo> {
+        let image = store_image(&self.s3, &self.bucket, &self.url, false, data).await?;
+
+        CachedPost::save_expiring(conn, &post.url, &image.url, image.dimensions, self.ttl).await?;
+
+        tracing::debug!(cdn_url = %image.url, "rehosted image");
+
+        Ok(PostInfo {
+            url: image.url,
+            image_dimensions: Some(image.dimensions),
+            ..post.to_owned()
+        })
+    }
+
+    /// Remove expired images from the bucket and the cache, returning how
+    /// many were removed.
+    ///
+    /// Entries are only removed after their image was, so images that
+    /// couldn't be deleted are tried again next time.
+    pub async fn remove_expired(&self, conn: &sqlx::Pool<sqlx::Postgres>) -> anyhow::Result<usize> {
+        let expired = CachedPost::expired(conn, CLEANUP_BATCH_SIZE).await?;
+
+        for (id, cdn_url) in &expired {
+            // The same image may have been cached for another post.
+            if !CachedPost::cdn_url_in_use(conn, cdn_url).await? {
+                self.delete(cdn_url).await?;
+            }
+
+            CachedPost::remove(conn, *id).await?;
+        }
+
+        Ok(expired.len())
+    }
+
+    /// Delete an image from the bucket.
+    async fn delete(&self, cdn_url: &str) -> anyhow::Result<()> {
+        let key = match self.key(cdn_url) {
+            Some(key) => key,
+            None => {
+                tracing::warn!(%cdn_url, "expired image was not in bucket");
+                return Ok(());
+            }
+        };
+
+        let delete = rusoto_s3::DeleteObjectRequest {
+            bucket: self.bucket.clone(),
+            key: key.to_string(),
+            ..Default::default()
+        };
+
+        self.s3
+            .delete_object(delete)
+            .await
+            .context("unable to delete expired image")?;
+
+        Ok(())
+    }
+
+    /// Get the object key from a CDN URL for this bucket.
+    fn key<'a>(&self, cdn_url: &'a str) -> Option<&'a str> {
+        cdn_url.strip_prefix(&format!("{}/{}/", self.url, self.bucket))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MediaCache;
+    use foxbot_sites::PostInfo;
+
+    fn cache() -> MediaCache {
+        let s3 = super::s3_client(
+            "http://localhost:9000".to_string(),
+            "us-east-1".to_string(),
+            "token".to_string(),
+            "secret".to_string(),
+        );
+
+        MediaCache::new(
+            s3,
+            "foxbot".to_string(),
+            "https://cdn.example.com".to_string(),
+            vec!["FurAffinity".to_string()],
+            None,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_should_rehost() {
+        let cache = cache();
+
+        let post = |site_name: &'static str, file_type: &str| PostInfo {
+            site_name: site_name.into(),
+            file_type: file_type.to_string(),
+            ..Default::default()
+        };
+
+        assert!(cache.should_rehost(&post("FurAffinity", "png")));
+        assert!(cache.should_rehost(&post("furaffinity", "jpg")));
+        assert!(!cache.should_rehost(&post("FurAffinity", "gif")));
+        assert!(!cache.should_rehost(&post("e621", "png")));
+    }
+
+    #[tokio::test]
+    async fn test_key() {
+        let cache = cache();
+
+        assert_eq!(
+            cache.key("https://cdn.example.com/foxbot/ab/cd/image_abcd.jpg"),
+            Some("ab/cd/image_abcd.jpg")
+        );
+        assert_eq!(cache.key("https://other.example.com/image.jpg"), None);
+    }
+}
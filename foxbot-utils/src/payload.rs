@@ -0,0 +1,233 @@
+//! Compact job payloads for messages.
+//!
+//! Jobs used to contain the entire [`tgbotapi::Message`] they were about,
+//! which is mostly fields the background worker never looks at. Instead, only
+//! what's needed to source a message is sent, and payloads that are still
+//! large are compressed.
+
+use std::io::{Read, Write};
+
+use anyhow::Context;
+
+use crate::{extract_links, find_sourceable_file, forward_origin, ForwardOrigin, SourceableFile};
+
+/// Serialized payloads larger than this many bytes are compressed.
+const COMPRESS_THRESHOLD: usize = 4 * 1024;
+
+/// Largest payload, in bytes after compression, that may be enqueued.
+const MAX_PAYLOAD_SIZE: usize = 256 * 1024;
+
+/// Field holding a compressed payload.
+const COMPRESSED_FIELD: &str = "compressed";
+
+/// The user who sent a message.
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct JobUser {
+    pub id: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    pub first_name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language_code: Option<String>,
+}
+
+impl From<&tgbotapi::User> for JobUser {
+    fn from(user: &tgbotapi::User) -> Self {
+        Self {
+            id: user.id,
+            username: user.username.clone(),
+            first_name: user.first_name.clone(),
+            language_code: user.language_code.clone(),
+        }
+    }
+}
+
+/// The parts of a message with an image needed to find its sources.
+#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct JobMessage {
+    pub chat_id: i64,
+    pub message_id: i32,
+    pub file_id: String,
+    pub file_unique_id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub media_group_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caption: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub caption_entities: Option<Vec<tgbotapi::MessageEntity>>,
+    /// Links that were already in the message.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub links: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<JobUser>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub forward_origin: Option<ForwardOrigin>,
+}
+
+impl JobMessage {
+    /// Keep the parts of a message needed by jobs, if it has a file that can
+    /// be sourced.
+    pub fn new(message: &tgbotapi::Message, max_document_size: usize) -> Option<Self> {
+        let file = find_sourceable_file(message, max_document_size)?;
+
+        Some(Self {
+            chat_id: message.chat.id,
+            message_id: message.message_id,
+            file_id: file.file_id.to_string(),
+            file_unique_id: file.file_unique_id.to_string(),
+            media_group_id: message.media_group_id.clone(),
+            caption: message.caption.clone(),
+            caption_entities: message.caption_entities.clone(),
+            links: extract_links(message)
+                .into_iter()
+                .map(ToString::to_string)
+                .collect(),
+            from: message.from.as_ref().map(JobUser::from),
+            forward_origin: forward_origin(message),
+        })
+    }
+
+    /// Load a message from a job, also accepting jobs that were enqueued with
+    /// an entire message.
+    pub fn from_job(value: serde_json::Value, max_document_size: usize) -> anyhow::Result<Self> {
+        let value = decode_value(value)?;
+
+        if value.get("file_unique_id").is_some() {
+            return serde_json::from_value(value).context("unable to parse job message");
+        }
+
+        let message: tgbotapi::Message =
+            serde_json::from_value(value).context("unable to parse legacy job message")?;
+
+        Self::new(&message, max_document_size).context("legacy job message had no file")
+    }
+
+    pub fn file(&self) -> SourceableFile<'_> {
+        SourceableFile {
+            file_id: &self.file_id,
+            file_unique_id: &self.file_unique_id,
+        }
+    }
+
+    pub fn links(&self) -> Vec<&str> {
+        self.links.iter().map(String::as_str).collect()
+    }
+}
+
+/// Serialize a job payload, compressing it if it's large.
+///
+/// Returns an error instead of creating payloads too large to enqueue.
+pub fn encode<T: serde::Serialize>(payload: &T) -> anyhow::Result<serde_json::Value> {
+    let data = serde_json::to_vec(payload)?;
+
+    if data.len() <= COMPRESS_THRESHOLD {
+        return serde_json::from_slice(&data).context("unable to encode payload");
+    }
+
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(&data)?;
+    let compressed = base64::encode(encoder.finish()?);
+
+    tracing::trace!(
+        size = data.len(),
+        compressed_size = compressed.len(),
+        "compressed job payload"
+    );
+
+    if compressed.len() > MAX_PAYLOAD_SIZE {
+        anyhow::bail!(
+            "job payload was too large: {} bytes compressed",
+            compressed.len()
+        );
+    }
+
+    Ok(serde_json::json!({ COMPRESSED_FIELD: compressed }))
+}
+
+/// Deserialize a job payload, whether or not it was compressed.
+pub fn decode<T: serde::de::DeserializeOwned>(value: serde_json::Value) -> anyhow::Result<T> {
+    serde_json::from_value(decode_value(value)?).context("unable to decode payload")
+}
+
+fn decode_value(value: serde_json::Value) -> anyhow::Result<serde_json::Value> {
+    let compressed = match value.get(COMPRESSED_FIELD).and_then(|data| data.as_str()) {
+        Some(compressed) => base64::decode(compressed).context("invalid compressed payload")?,
+        None => return Ok(value),
+    };
+
+    let mut data = Vec::new();
+    flate2::read::GzDecoder::new(compressed.as_slice())
+        .read_to_end(&mut data)
+        .context("unable to decompress payload")?;
+
+    serde_json::from_slice(&data).context("unable to parse decompressed payload")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_small() {
+        let message = JobMessage {
+            chat_id: -100123,
+            message_id: 42,
+            file_id: "photo".to_string(),
+            file_unique_id: "photo-unique".to_string(),
+            ..Default::default()
+        };
+
+        let value = encode(&message).unwrap();
+        assert!(
+            value.get(COMPRESSED_FIELD).is_none(),
+            "small payloads should not be compressed"
+        );
+        assert_eq!(decode::<JobMessage>(value).unwrap(), message);
+    }
+
+    #[test]
+    fn test_encode_large() {
+        let message = JobMessage {
+            caption: Some("a".repeat(COMPRESS_THRESHOLD * 2)),
+            ..Default::default()
+        };
+
+        let value = encode(&message).unwrap();
+        assert!(
+            value.get(COMPRESSED_FIELD).is_some(),
+            "large payloads should be compressed"
+        );
+        assert_eq!(decode::<JobMessage>(value).unwrap(), message);
+    }
+
+    #[test]
+    fn test_from_job_legacy() {
+        use crate::bot_api::HOSTED_DOWNLOAD_LIMIT;
+
+        let message = tgbotapi::Message {
+            message_id: 42,
+            chat: tgbotapi::Chat {
+                id: -100123,
+                ..Default::default()
+            },
+            photo: Some(vec![tgbotapi::PhotoSize {
+                file_id: "photo".to_string(),
+                file_unique_id: "photo-unique".to_string(),
+                width: 100,
+                height: 100,
+                ..Default::default()
+            }]),
+            ..Default::default()
+        };
+
+        let job_message = JobMessage::from_job(
+            serde_json::to_value(&message).unwrap(),
+            HOSTED_DOWNLOAD_LIMIT,
+        )
+        .unwrap();
+
+        assert_eq!(job_message.chat_id, -100123);
+        assert_eq!(job_message.message_id, 42);
+        assert_eq!(job_message.file_unique_id, "photo-unique");
+    }
+}
@@ -0,0 +1,307 @@
+//! Proactive limits on messages sent to Telegram, shared through Redis between
+//! the bot and the background worker.
+//!
+//! Telegram allows bots to send around 30 messages each second, no more than
+//! one each second to a single chat, and 20 each minute to a group. Waiting
+//! before sending smooths out bursts, like from backfills, instead of relying
+//! on `retry_after` errors.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tgbotapi::requests::TelegramRequest;
+
+/// Longest a request will wait for the limiter before being sent anyway.
+const MAX_WAIT: Duration = Duration::from_secs(30);
+
+/// A number of requests allowed in each period.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Limit {
+    name: &'static str,
+    /// If requests are counted separately for each chat.
+    per_chat: bool,
+    period_secs: u64,
+    max: u32,
+}
+
+const GLOBAL_LIMIT: Limit = Limit {
+    name: "global",
+    per_chat: false,
+    period_secs: 1,
+    max: 30,
+};
+
+const CHAT_LIMIT: Limit = Limit {
+    name: "chat",
+    per_chat: true,
+    period_secs: 1,
+    max: 1,
+};
+
+const GROUP_LIMIT: Limit = Limit {
+    name: "group",
+    per_chat: true,
+    period_secs: 60,
+    max: 20,
+};
+
+/// Check if an endpoint sends or edits messages, and should be limited.
+///
+/// Chat actions are sent often and aren't limited by Telegram in the same
+/// way, so they are always allowed.
+fn is_limited_endpoint(endpoint: &str) -> bool {
+    if endpoint == "sendChatAction" {
+        return false;
+    }
+
+    endpoint.starts_with("send")
+        || endpoint.starts_with("edit")
+        || matches!(endpoint, "forwardMessage" | "copyMessage")
+}
+
+/// Find the chat a request is sent to, if it has one.
+fn request_chat_id<T: TelegramRequest>(request: &T) -> Option<String> {
+    let value = serde_json::to_value(request).ok()?;
+
+    match value.get("chat_id")? {
+        serde_json::Value::Number(id) => Some(id.to_string()),
+        serde_json::Value::String(username) => Some(username.to_owned()),
+        _ => None,
+    }
+}
+
+/// Get the limits that apply to messages sent to a chat. Groups and channels
+/// have negative IDs, or are referenced by username.
+fn limits_for_chat(chat_id: &str) -> Vec<Limit> {
+    if chat_id.starts_with('-') || chat_id.starts_with('@') {
+        vec![GLOBAL_LIMIT, CHAT_LIMIT, GROUP_LIMIT]
+    } else {
+        vec![GLOBAL_LIMIT, CHAT_LIMIT]
+    }
+}
+
+/// Get the window a time falls in for a limit, and how long until it ends.
+fn window_at(limit: &Limit, now: Duration) -> (u64, Duration) {
+    let period = Duration::from_secs(limit.period_secs);
+
+    let window = now.as_secs() / limit.period_secs;
+    let ends = period * (window + 1) as u32;
+
+    (window, ends.saturating_sub(now))
+}
+
+/// Get how long to sleep before checking limits again, or `None` if the
+/// request has already waited as long as it may.
+///
+/// Sleeps are shortened so requests never wait much longer than allowed, even
+/// when a limit's window is longer.
+fn next_delay(delay: Duration, waited: Duration, max_wait: Duration) -> Option<Duration> {
+    let remaining = max_wait
+        .checked_sub(waited)
+        .filter(|remaining| *remaining > Duration::default())?;
+
+    Some(delay.min(remaining))
+}
+
+/// Limits messages sent to Telegram, counting them in Redis so every process
+/// shares the same limits.
+#[derive(Clone)]
+pub struct RequestLimiter {
+    redis: redis::aio::ConnectionManager,
+    max_wait: Duration,
+}
+
+impl RequestLimiter {
+    pub fn new(redis: redis::aio::ConnectionManager) -> Self {
+        Self {
+            redis,
+            max_wait: MAX_WAIT,
+        }
+    }
+
+    /// Set the longest a request will wait before being sent anyway, like for
+    /// requests answering users that shouldn't be held up for long.
+    pub fn with_max_wait(mut self, max_wait: Duration) -> Self {
+        self.max_wait = max_wait;
+        self
+    }
+
+    /// Wait until a request may be sent without exceeding any limits.
+    ///
+    /// Requests are sent anyway if Redis is unavailable or they have waited
+    /// too long, as Telegram will still tell us to retry later.
+    pub async fn wait<T: TelegramRequest>(&self, request: &T) {
+        if !is_limited_endpoint(request.endpoint()) {
+            return;
+        }
+
+        let chat_id = match request_chat_id(request) {
+            Some(chat_id) => chat_id,
+            None => return,
+        };
+
+        let limits = limits_for_chat(&chat_id);
+        let mut waited = Duration::default();
+
+        loop {
+            let delay = match self.try_acquire(&chat_id, &limits).await {
+                Ok(None) => return,
+                Ok(Some(delay)) => delay,
+                Err(err) => {
+                    tracing::warn!("unable to check telegram request limits: {:?}", err);
+                    return;
+                }
+            };
+
+            let delay = match next_delay(delay, waited, self.max_wait) {
+                Some(delay) => delay,
+                None => {
+                    tracing::warn!(%chat_id, "waited too long for request limit, sending anyway");
+                    return;
+                }
+            };
+
+            tracing::trace!(%chat_id, ?delay, "waiting for request limit");
+
+            tokio::time::sleep(delay).await;
+            waited += delay;
+        }
+    }
+
+    /// Count a request against each limit if none were exceeded, otherwise
+    /// returning how long to wait.
+    ///
+    /// Limits are checked and counted in a single script, so requests that
+    /// have to wait aren't counted and can't use up the next request's turn.
+    async fn try_acquire(
+        &self,
+        chat_id: &str,
+        limits: &[Limit],
+    ) -> anyhow::Result<Option<Duration>> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+
+        let script = redis::Script::new(
+            r#"
+            local exceeded = {}
+            for i, key in ipairs(KEYS) do
+                local count = tonumber(redis.call('GET', key) or '0')
+                if count >= tonumber(ARGV[i * 2 - 1]) then
+                    table.insert(exceeded, i)
+                end
+            end
+            if #exceeded > 0 then
+                return exceeded
+            end
+            for i, key in ipairs(KEYS) do
+                redis.call('INCR', key)
+                redis.call('EXPIRE', key, ARGV[i * 2])
+            end
+            return exceeded
+            "#,
+        );
+        let mut invocation = script.prepare_invoke();
+
+        let windows: Vec<_> = limits
+            .iter()
+            .map(|limit| {
+                let (window, remaining) = window_at(limit, now);
+
+                let key = if limit.per_chat {
+                    format!("telegram-limit:{}:{}:{}", limit.name, chat_id, window)
+                } else {
+                    format!("telegram-limit:{}:{}", limit.name, window)
+                };
+
+                invocation
+                    .key(key)
+                    .arg(limit.max)
+                    .arg(limit.period_secs as usize + 1);
+
+                remaining
+            })
+            .collect();
+
+        let mut redis = self.redis.clone();
+        let exceeded: Vec<usize> = invocation.invoke_async(&mut redis).await?;
+
+        let delay = exceeded
+            .into_iter()
+            .filter_map(|index| windows.get(index.checked_sub(1)?))
+            .copied()
+            .max();
+
+        Ok(delay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_limited_endpoint() {
+        assert!(is_limited_endpoint("sendMessage"));
+        assert!(is_limited_endpoint("editMessageReplyMarkup"));
+        assert!(is_limited_endpoint("forwardMessage"));
+        assert!(!is_limited_endpoint("sendChatAction"));
+        assert!(!is_limited_endpoint("getChatMember"));
+        assert!(!is_limited_endpoint("answerInlineQuery"));
+    }
+
+    #[test]
+    fn test_request_chat_id() {
+        let send_message = tgbotapi::requests::SendMessage {
+            chat_id: (-100123_i64).into(),
+            text: "test".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(request_chat_id(&send_message).as_deref(), Some("-100123"));
+
+        assert_eq!(request_chat_id(&tgbotapi::requests::GetMe), None);
+    }
+
+    #[test]
+    fn test_limits_for_chat() {
+        assert_eq!(limits_for_chat("123"), vec![GLOBAL_LIMIT, CHAT_LIMIT]);
+        assert_eq!(
+            limits_for_chat("-100123"),
+            vec![GLOBAL_LIMIT, CHAT_LIMIT, GROUP_LIMIT]
+        );
+        assert_eq!(
+            limits_for_chat("@channel"),
+            vec![GLOBAL_LIMIT, CHAT_LIMIT, GROUP_LIMIT]
+        );
+    }
+
+    #[test]
+    fn test_next_delay() {
+        let max_wait = Duration::from_secs(5);
+
+        assert_eq!(
+            next_delay(Duration::from_secs(1), Duration::default(), max_wait),
+            Some(Duration::from_secs(1))
+        );
+        assert_eq!(
+            next_delay(Duration::from_secs(60), Duration::from_secs(3), max_wait),
+            Some(Duration::from_secs(2)),
+            "sleeps should not go past the longest wait"
+        );
+        assert_eq!(
+            next_delay(Duration::from_secs(1), Duration::from_secs(5), max_wait),
+            None
+        );
+    }
+
+    #[test]
+    fn test_window_at() {
+        let now = Duration::from_millis(125_250);
+
+        assert_eq!(
+            window_at(&CHAT_LIMIT, now),
+            (125, Duration::from_millis(750))
+        );
+        assert_eq!(
+            window_at(&GROUP_LIMIT, now),
+            (2, Duration::from_millis(54_750))
+        );
+    }
+}
@@ -0,0 +1,983 @@
+//! Reverse image search providers.
+//!
+//! FuzzySearch only indexes a few furry art sites, so other providers like
+//! SauceNAO may also be searched to find sources on sites like Pixiv and
+//! Danbooru. Results from every provider are merged, keeping the first result
+//! found for each source. Less reliable providers like IQDB can be added as
+//! fallbacks, only searched when nothing else found a source.
+
+use std::{collections::HashSet, sync::Arc};
+
+use anyhow::Context;
+use async_trait::async_trait;
+
+use foxbot_models::FileCache;
+
+use crate::{
+    bot_api, decode, download, get_message, get_rating_bundle_name, hash_file, lookup_hash, Bundle,
+    SourceableFile,
+};
+
+/// SauceNAO's search endpoint.
+const SAUCENAO_ENDPOINT: &str = "https://saucenao.com/search.php";
+
+/// Number of results to request from SauceNAO.
+const SAUCENAO_RESULTS: usize = 8;
+
+/// Lowest similarity, as a percentage, of SauceNAO results that are used.
+const SAUCENAO_MIN_SIMILARITY: f32 = 80.0;
+
+/// Fields of SauceNAO results that may contain artist names, in the order
+/// they're checked.
+const SAUCENAO_ARTIST_FIELDS: &[&str] = &[
+    "member_name",
+    "author_name",
+    "creator",
+    "twitter_user_handle",
+];
+
+/// IQDB's search endpoint.
+const IQDB_ENDPOINT: &str = "https://iqdb.org/";
+
+/// Lowest similarity, as a percentage, of IQDB matches that are used. IQDB
+/// returns its closest images even when they're unrelated, so only very
+/// similar ones are kept.
+const IQDB_MIN_SIMILARITY: u8 = 90;
+
+/// Most works to include in a reply.
+const MAX_WORKS: usize = 5;
+
+/// Most other links for a work to include in a reply.
+const MAX_ALTERNATES: usize = 4;
+
+/// An image being searched for.
+#[derive(Clone, Debug)]
+pub struct ReverseImage {
+    pub hash: i64,
+    /// Contents of the image, if any provider needed them.
+    pub data: Option<Vec<u8>>,
+}
+
+/// A source found by a provider.
+#[derive(Clone, Debug)]
+pub struct ReverseMatch {
+    /// Name of the provider that found the source.
+    pub provider: &'static str,
+    pub site_name: String,
+    pub url: String,
+    pub artists: Vec<String>,
+    /// Other places the site said the work was posted, like where an e621
+    /// post came from.
+    pub links: Vec<String>,
+    /// The file from FuzzySearch, for sources it found.
+    pub file: Option<fuzzysearch::File>,
+}
+
+impl From<fuzzysearch::File> for ReverseMatch {
+    fn from(file: fuzzysearch::File) -> Self {
+        let links = match &file.site_info {
+            Some(fuzzysearch::SiteInfo::E621(e621)) => e621.sources.clone().unwrap_or_default(),
+            _ => Vec::new(),
+        };
+
+        Self {
+            provider: FuzzySearchIndex::NAME,
+            site_name: file.site_name().to_string(),
+            url: file.url(),
+            artists: file.artists.clone().unwrap_or_default(),
+            links,
+            file: Some(file),
+        }
+    }
+}
+
+/// A provider that can find sources for an image.
+#[async_trait]
+pub trait ReverseIndex {
+    /// Name of the provider.
+    fn name(&self) -> &'static str;
+
+    /// If searches need the contents of the image instead of only its hash.
+    fn needs_data(&self) -> bool {
+        false
+    }
+
+    /// Find sources for an image. Hash based providers only return sources
+    /// within the distance.
+    async fn search(
+        &self,
+        image: &ReverseImage,
+        distance: Option<i64>,
+    ) -> anyhow::Result<Vec<ReverseMatch>>;
+}
+
+pub type BoxedReverseIndex = Box<dyn ReverseIndex + Send + Sync>;
+
+/// Searches FuzzySearch, using previously found sources if it's unavailable.
+pub struct FuzzySearchIndex {
+    conn: sqlx::Pool<sqlx::Postgres>,
+    fapi: Arc<fuzzysearch::FuzzySearch>,
+}
+
+impl FuzzySearchIndex {
+    const NAME: &'static str = "FuzzySearch";
+
+    pub fn new(conn: sqlx::Pool<sqlx::Postgres>, fapi: Arc<fuzzysearch::FuzzySearch>) -> Self {
+        Self { conn, fapi }
+    }
+}
+
+#[async_trait]
+impl ReverseIndex for FuzzySearchIndex {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    async fn search(
+        &self,
+        image: &ReverseImage,
+        distance: Option<i64>,
+    ) -> anyhow::Result<Vec<ReverseMatch>> {
+        let files = lookup_hash(&self.conn, &self.fapi, image.hash, distance, None).await?;
+
+        Ok(files.into_iter().map(ReverseMatch::from).collect())
+    }
+}
+
+/// Searches SauceNAO, which indexes many anime and art sites.
+pub struct SauceNao {
+    client: reqwest::Client,
+    api_key: String,
+}
+
+#[derive(serde::Deserialize)]
+struct SauceNaoResponse {
+    #[serde(default)]
+    results: Vec<SauceNaoResult>,
+}
+
+#[derive(serde::Deserialize)]
+struct SauceNaoResult {
+    header: SauceNaoHeader,
+    data: serde_json::Value,
+}
+
+#[derive(serde::Deserialize)]
+struct SauceNaoHeader {
+    similarity: String,
+    index_id: i32,
+}
+
+impl SauceNao {
+    const NAME: &'static str = "SauceNAO";
+
+    pub fn new(api_key: String) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            api_key,
+        }
+    }
+}
+
+#[async_trait]
+impl ReverseIndex for SauceNao {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn needs_data(&self) -> bool {
+        true
+    }
+
+    async fn search(
+        &self,
+        image: &ReverseImage,
+        _distance: Option<i64>,
+    ) -> anyhow::Result<Vec<ReverseMatch>> {
+        let data = image
+            .data
+            .clone()
+            .context("saucenao needs the image contents")?;
+
+        let form = reqwest::multipart::Form::new().part(
+            "file",
+            reqwest::multipart::Part::bytes(data).file_name("image.jpg"),
+        );
+
+        let resp: SauceNaoResponse = self
+            .client
+            .post(SAUCENAO_ENDPOINT)
+            .query(&[
+                ("output_type", "2"),
+                ("numres", &SAUCENAO_RESULTS.to_string()),
+                ("api_key", &self.api_key),
+            ])
+            .multipart(form)
+            .send()
+            .await
+            .context("unable to request saucenao")?
+            .error_for_status()
+            .context("saucenao returned error")?
+            .json()
+            .await
+            .context("unable to decode saucenao response")?;
+
+        Ok(saucenao_matches(resp))
+    }
+}
+
+/// Get the sources from SauceNAO results that are similar enough.
+fn saucenao_matches(resp: SauceNaoResponse) -> Vec<ReverseMatch> {
+    resp.results
+        .into_iter()
+        .filter(|result| {
+            result
+                .header
+                .similarity
+                .parse::<f32>()
+                .map_or(false, |similarity| similarity >= SAUCENAO_MIN_SIMILARITY)
+        })
+        .filter_map(|result| {
+            let url = result
+                .data
+                .get("ext_urls")?
+                .as_array()?
+                .first()?
+                .as_str()?
+                .to_string();
+
+            Some(ReverseMatch {
+                provider: SauceNao::NAME,
+                site_name: saucenao_site_name(result.header.index_id, &url),
+                artists: saucenao_artists(&result.data),
+                links: saucenao_links(&result.data),
+                url,
+                file: None,
+            })
+        })
+        .collect()
+}
+
+/// Get the name of the site a SauceNAO index is for, or the host of the
+/// source for indexes that aren't known.
+fn saucenao_site_name(index_id: i32, url: &str) -> String {
+    let name = match index_id {
+        5 | 6 => "Pixiv",
+        9 => "Danbooru",
+        12 => "Yande.re",
+        25 => "Gelbooru",
+        26 => "Konachan",
+        29 => "e621",
+        34 => "DeviantArt",
+        40 => "FurAffinity",
+        41 => "Twitter",
+        42 => "Furry Network",
+        _ => return host_name(url).unwrap_or_else(|| SauceNao::NAME.to_string()),
+    };
+
+    name.to_string()
+}
+
+/// Get the host of a URL without any `www.` prefix, to name sites that
+/// aren't known.
+fn host_name(url: &str) -> Option<String> {
+    let url = reqwest::Url::parse(url).ok()?;
+
+    url.host_str()
+        .map(|host| host.trim_start_matches("www.").to_string())
+}
+
+/// Get where a SauceNAO result said its work was originally posted.
+fn saucenao_links(data: &serde_json::Value) -> Vec<String> {
+    data.get("source")
+        .and_then(|source| source.as_str())
+        .filter(|source| source.starts_with("http"))
+        .map(|source| vec![source.to_string()])
+        .unwrap_or_default()
+}
+
+/// Get the artists of a SauceNAO result, from the first field that has any.
+fn saucenao_artists(data: &serde_json::Value) -> Vec<String> {
+    for field in SAUCENAO_ARTIST_FIELDS {
+        let artists: Vec<String> = match data.get(field) {
+            Some(serde_json::Value::String(artist)) => vec![artist.to_owned()],
+            Some(serde_json::Value::Array(artists)) => artists
+                .iter()
+                .filter_map(|artist| artist.as_str())
+                .map(ToString::to_string)
+                .collect(),
+            _ => continue,
+        };
+
+        let artists: Vec<String> = artists
+            .into_iter()
+            .filter(|artist| !artist.is_empty())
+            .collect();
+        if !artists.is_empty() {
+            return artists;
+        }
+    }
+
+    vec![]
+}
+
+/// Searches IQDB, which indexes many anime imageboards.
+///
+/// Its results are less reliable than other providers, so it should only be
+/// used as a fallback.
+#[derive(Default)]
+pub struct Iqdb {
+    client: reqwest::Client,
+}
+
+impl Iqdb {
+    const NAME: &'static str = "IQDB";
+}
+
+#[async_trait]
+impl ReverseIndex for Iqdb {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    fn needs_data(&self) -> bool {
+        true
+    }
+
+    async fn search(
+        &self,
+        image: &ReverseImage,
+        _distance: Option<i64>,
+    ) -> anyhow::Result<Vec<ReverseMatch>> {
+        let data = image
+            .data
+            .clone()
+            .context("iqdb needs the image contents")?;
+
+        let form = reqwest::multipart::Form::new().part(
+            "file",
+            reqwest::multipart::Part::bytes(data).file_name("image.jpg"),
+        );
+
+        let body = self
+            .client
+            .post(IQDB_ENDPOINT)
+            .multipart(form)
+            .send()
+            .await
+            .context("unable to request iqdb")?
+            .error_for_status()
+            .context("iqdb returned error")?
+            .text()
+            .await
+            .context("unable to read iqdb response")?;
+
+        Ok(iqdb_matches(&body))
+    }
+}
+
+/// Get the matches from an IQDB results page that are similar enough.
+///
+/// Each result is a table headed with what kind of result it is. Only the
+/// best and additional matches are used, skipping the searched image and any
+/// possible matches.
+fn iqdb_matches(body: &str) -> Vec<ReverseMatch> {
+    let document = scraper::Html::parse_document(body);
+
+    let tables = scraper::Selector::parse("#pages > div > table").unwrap();
+    let heading = scraper::Selector::parse("th").unwrap();
+    let link = scraper::Selector::parse("td.image a").unwrap();
+    let cell = scraper::Selector::parse("td").unwrap();
+    let service = scraper::Selector::parse("img.service-icon").unwrap();
+
+    document
+        .select(&tables)
+        .filter_map(|table| {
+            let heading: String = table.select(&heading).next()?.text().collect();
+            if !matches!(heading.trim(), "Best match" | "Additional match") {
+                return None;
+            }
+
+            let href = table.select(&link).next()?.value().attr("href")?;
+            let url = match href.strip_prefix("//") {
+                Some(href) => format!("https://{}", href),
+                None => href.to_string(),
+            };
+
+            let similarity = table.select(&cell).find_map(|cell| {
+                let text: String = cell.text().collect();
+                text.trim().strip_suffix("% similarity")?.parse::<u8>().ok()
+            })?;
+            if similarity < IQDB_MIN_SIMILARITY {
+                return None;
+            }
+
+            let site_name = table
+                .select(&cell)
+                .find(|cell| cell.select(&service).next().is_some())
+                .map(|cell| cell.text().collect::<String>().trim().to_string())
+                .filter(|name| !name.is_empty())
+                .or_else(|| host_name(&url))
+                .unwrap_or_else(|| Iqdb::NAME.to_string());
+
+            Some(ReverseMatch {
+                provider: Iqdb::NAME,
+                site_name,
+                url,
+                artists: vec![],
+                links: vec![],
+                file: None,
+            })
+        })
+        .collect()
+}
+
+/// Searches every configured provider, merging their results.
+pub struct ReverseSearch {
+    indexes: Vec<BoxedReverseIndex>,
+    /// Providers only searched when no others found anything.
+    fallbacks: Vec<BoxedReverseIndex>,
+}
+
+impl ReverseSearch {
+    /// Search providers, preferring results from earlier ones.
+    pub fn new(indexes: Vec<BoxedReverseIndex>) -> Self {
+        Self {
+            indexes,
+            fallbacks: Vec::new(),
+        }
+    }
+
+    /// Add a provider that's only searched when no others found anything.
+    pub fn with_fallback(mut self, index: BoxedReverseIndex) -> Self {
+        self.fallbacks.push(index);
+        self
+    }
+
+    /// If any provider needs the contents of images.
+    pub fn needs_data(&self) -> bool {
+        self.indexes
+            .iter()
+            .chain(self.fallbacks.iter())
+            .any(|index| index.needs_data())
+    }
+
+    /// Load an image from Telegram to search, only downloading it if a
+    /// provider needs its contents or its hash wasn't cached.
+    pub async fn load_image(
+        &self,
+        bot: &tgbotapi::Telegram,
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        file: SourceableFile<'_>,
+    ) -> anyhow::Result<ReverseImage> {
+        if !self.needs_data() {
+            return Ok(ReverseImage {
+                hash: hash_file(bot, conn, file).await?,
+                data: None,
+            });
+        }
+
+        let data = bot_api::download_file(bot, file.file_id).await?;
+        download::check_image(&data)?;
+
+        let hash = match FileCache::get(conn, file.file_unique_id)
+            .await
+            .context("unable to query file cache")?
+        {
+            Some(hash) => hash,
+            None => {
+                let hash = decode::hash_bytes(data.clone()).await?;
+
+                FileCache::set(conn, file.file_unique_id, hash)
+                    .await
+                    .context("unable to set file cache")?;
+
+                hash
+            }
+        };
+
+        Ok(ReverseImage {
+            hash,
+            data: Some(data),
+        })
+    }
+
+    /// Search every provider at once, then the fallback providers if nothing
+    /// was found.
+    pub async fn search(
+        &self,
+        image: &ReverseImage,
+        distance: Option<i64>,
+    ) -> anyhow::Result<Vec<ReverseMatch>> {
+        let result = search_indexes(&self.indexes, image, distance).await;
+
+        if self.fallbacks.is_empty() || matches!(&result, Ok(matches) if !matches.is_empty()) {
+            return result;
+        }
+
+        tracing::debug!("no sources found, searching fallback providers");
+
+        search_indexes(&self.fallbacks, image, distance).await
+    }
+}
+
+/// Search each provider at once, merging their results.
+///
+/// Providers that fail are skipped, unless all of them failed.
+async fn search_indexes(
+    indexes: &[BoxedReverseIndex],
+    image: &ReverseImage,
+    distance: Option<i64>,
+) -> anyhow::Result<Vec<ReverseMatch>> {
+    let searches = indexes
+        .iter()
+        .map(|index| async move { (index.name(), index.search(image, distance).await) });
+
+    let mut results = Vec::with_capacity(indexes.len());
+    let mut last_err = None;
+
+    for (name, result) in futures::future::join_all(searches).await {
+        match result {
+            Ok(matches) => {
+                tracing::debug!(provider = name, count = matches.len(), "searched provider");
+                results.push(matches);
+            }
+            Err(err) => {
+                tracing::warn!(provider = name, "unable to search provider: {:?}", err);
+                last_err = Some(err);
+            }
+        }
+    }
+
+    match last_err {
+        Some(err) if results.is_empty() => Err(err),
+        _ => Ok(merge_matches(results)),
+    }
+}
+
+/// Merge the matches from each provider, keeping only the first match for
+/// each source.
+pub fn merge_matches(results: Vec<Vec<ReverseMatch>>) -> Vec<ReverseMatch> {
+    let mut seen = HashSet::new();
+
+    results
+        .into_iter()
+        .flatten()
+        .filter(|m| seen.insert(source_key(&m.url)))
+        .collect()
+}
+
+/// Get a key for a source so the same link from different providers is only
+/// shown once.
+fn source_key(url: &str) -> String {
+    foxbot_sites::canonical_url(url)
+        .unwrap_or_else(|| url.to_string())
+        .trim_end_matches('/')
+        .to_string()
+}
+
+/// Split matches into the files from FuzzySearch, which have all of their
+/// details, and sources found by other providers.
+pub fn split_files(matches: Vec<ReverseMatch>) -> (Vec<fuzzysearch::File>, Vec<ReverseMatch>) {
+    let mut files = Vec::new();
+    let mut others = Vec::new();
+
+    for m in matches {
+        match m.file {
+            Some(file) => files.push(file),
+            None => others.push(m),
+        }
+    }
+
+    (files, others)
+}
+
+/// A work found by providers, with the other places it was found.
+#[derive(Clone, Debug)]
+pub struct ReverseWork {
+    pub source: ReverseMatch,
+    /// The same work on other sites.
+    pub alternates: Vec<ReverseMatch>,
+}
+
+impl ReverseWork {
+    fn matches(&self) -> impl Iterator<Item = &ReverseMatch> {
+        std::iter::once(&self.source).chain(self.alternates.iter())
+    }
+
+    /// Check if a match is likely the same work, by having the same artist
+    /// or by one linking to the other.
+    ///
+    /// Every match is for the same searched image, so matches by the same
+    /// artist are almost always mirrors instead of different works.
+    fn is_same_work(&self, m: &ReverseMatch) -> bool {
+        let artists = artist_keys(m);
+
+        self.matches().any(|other| {
+            artist_keys(other)
+                .iter()
+                .any(|artist| artists.contains(artist))
+                || links_to(other, m)
+                || links_to(m, other)
+        })
+    }
+}
+
+/// Group matches that are the same work, using the first match of each work
+/// as its source.
+pub fn group_works(matches: Vec<ReverseMatch>) -> Vec<ReverseWork> {
+    let mut works: Vec<ReverseWork> = Vec::new();
+
+    for m in matches {
+        match works.iter_mut().find(|work| work.is_same_work(&m)) {
+            Some(work) => work.alternates.push(m),
+            None => works.push(ReverseWork {
+                source: m,
+                alternates: Vec::new(),
+            }),
+        }
+    }
+
+    works
+}
+
+/// Normalize the artists of a match so the same artist matches on each site,
+/// like `Syfaro` on Twitter and `syfaro_(artist)` on e621.
+fn artist_keys(m: &ReverseMatch) -> Vec<String> {
+    m.artists
+        .iter()
+        .filter(|artist| !foxbot_sites::E621_META_ARTISTS.contains(&artist.as_str()))
+        .map(|artist| {
+            artist
+                .trim_end_matches("_(artist)")
+                .chars()
+                .filter(|c| c.is_alphanumeric())
+                .flat_map(char::to_lowercase)
+                .collect::<String>()
+        })
+        .filter(|artist| !artist.is_empty())
+        .collect()
+}
+
+/// Check if a match links to another, like an e621 post to where it was
+/// originally posted.
+fn links_to(m: &ReverseMatch, other: &ReverseMatch) -> bool {
+    let key = source_key(&other.url);
+
+    m.links.iter().any(|link| source_key(link) == key)
+}
+
+/// Write a reply for the works found, with the other places each was found
+/// after it.
+///
+/// Like [`source_reply`](crate::source_reply), only the closest works from
+/// FuzzySearch are included.
+pub fn works_reply(works: &[ReverseWork], bundle: Bundle<'_>) -> String {
+    let closest = works
+        .iter()
+        .filter_map(|work| work.source.file.as_ref()?.distance)
+        .min();
+
+    let works: Vec<&ReverseWork> = works
+        .iter()
+        .filter(
+            |work| match work.source.file.as_ref().and_then(|file| file.distance) {
+                Some(distance) => Some(distance) == closest,
+                None => true,
+            },
+        )
+        .take(MAX_WORKS)
+        .collect();
+
+    let mut items = Vec::with_capacity(1 + works.len() * 2);
+
+    match works.as_slice() {
+        [] => return get_message(bundle, "reverse-no-results", None).unwrap(),
+        [work] => {
+            items.push(source_line(bundle, &work.source, false));
+            items.extend(alternates_line(bundle, work));
+        }
+        works => {
+            items.push(get_message(bundle, "reverse-multiple-results", None).unwrap());
+
+            for work in works {
+                items.push(source_line(bundle, &work.source, true));
+                items.extend(alternates_line(bundle, work));
+            }
+        }
+    }
+
+    items.join("\n")
+}
+
+/// Write the line for the source of a work, in a list of works if there are
+/// multiple.
+fn source_line(bundle: Bundle<'_>, m: &ReverseMatch, multiple: bool) -> String {
+    let mut args = fluent::FluentArgs::new();
+    args.insert("link", m.url.clone().into());
+
+    // Files from FuzzySearch are shown like they always have been, with their
+    // rating instead of where they were found.
+    if let Some(file) = &m.file {
+        let name = match (get_rating_bundle_name(&file.rating), multiple) {
+            (Some(rating), multiple) => {
+                let rating = get_message(bundle, rating, None).unwrap();
+                args.insert("rating", rating.into());
+
+                if multiple {
+                    "reverse-multiple-item"
+                } else {
+                    "reverse-result"
+                }
+            }
+            (None, true) => "reverse-multiple-item-unknown",
+            (None, false) => "reverse-result-unknown",
+        };
+
+        return get_message(bundle, name, Some(args)).unwrap();
+    }
+
+    args.insert("site", m.site_name.clone().into());
+    args.insert("provider", m.provider.into());
+
+    let name = if m.artists.is_empty() {
+        if multiple {
+            "reverse-other-item"
+        } else {
+            "reverse-other-result"
+        }
+    } else {
+        args.insert("artists", m.artists.join(", ").into());
+
+        if multiple {
+            "reverse-other-item-artists"
+        } else {
+            "reverse-other-result-artists"
+        }
+    };
+
+    get_message(bundle, name, Some(args)).unwrap()
+}
+
+/// Write the line listing the other places a work was found, if any.
+fn alternates_line(bundle: Bundle<'_>, work: &ReverseWork) -> Option<String> {
+    if work.alternates.is_empty() {
+        return None;
+    }
+
+    let links: Vec<&str> = work
+        .alternates
+        .iter()
+        .take(MAX_ALTERNATES)
+        .map(|m| m.url.as_str())
+        .collect();
+
+    let mut args = fluent::FluentArgs::new();
+    args.insert("links", links.join(", ").into());
+
+    Some(get_message(bundle, "reverse-alternates", Some(args)).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn other(url: &str) -> ReverseMatch {
+        ReverseMatch {
+            provider: SauceNao::NAME,
+            site_name: "Pixiv".to_string(),
+            url: url.to_string(),
+            artists: vec![],
+            links: vec![],
+            file: None,
+        }
+    }
+
+    #[test]
+    fn test_merge_matches() {
+        let fuzzysearch = ReverseMatch::from(fuzzysearch::File {
+            site_id: 123,
+            site_info: Some(fuzzysearch::SiteInfo::FurAffinity(
+                fuzzysearch::FurAffinityFile { file_id: 123 },
+            )),
+            ..Default::default()
+        });
+        let fuzzysearch_url = fuzzysearch.url.clone();
+
+        let merged = merge_matches(vec![
+            vec![fuzzysearch],
+            vec![
+                other(fuzzysearch_url.trim_end_matches('/')),
+                other("https://www.pixiv.net/artworks/456"),
+                other("HTTPS://WWW.PIXIV.NET/artworks/456"),
+            ],
+        ]);
+
+        let urls: Vec<_> = merged.iter().map(|m| m.url.as_str()).collect();
+        assert_eq!(
+            urls,
+            vec![
+                fuzzysearch_url.as_str(),
+                "https://www.pixiv.net/artworks/456"
+            ]
+        );
+        assert!(
+            merged[0].file.is_some(),
+            "earlier providers should be preferred"
+        );
+
+        let (files, others) = split_files(merged);
+        assert_eq!(files.len(), 1);
+        assert_eq!(others.len(), 1);
+    }
+
+    #[test]
+    fn test_group_works() {
+        let e621 = ReverseMatch::from(fuzzysearch::File {
+            site_id: 456,
+            site_info: Some(fuzzysearch::SiteInfo::E621(fuzzysearch::E621File {
+                sources: Some(vec!["https://www.pixiv.net/artworks/789".to_string()]),
+            })),
+            artists: Some(vec!["conditional_dnp".to_string()]),
+            ..Default::default()
+        });
+
+        let matches = vec![
+            ReverseMatch {
+                site_name: "Twitter".to_string(),
+                artists: vec!["Syfaro".to_string()],
+                ..other("https://twitter.com/Syfaro/status/123")
+            },
+            ReverseMatch {
+                site_name: "FurAffinity".to_string(),
+                artists: vec!["syfaro_(artist)".to_string()],
+                ..other("https://www.furaffinity.net/view/123/")
+            },
+            e621,
+            other("https://www.pixiv.net/artworks/789"),
+            ReverseMatch {
+                artists: vec!["conditional_dnp".to_string()],
+                ..other("https://www.pixiv.net/artworks/999")
+            },
+        ];
+
+        let works = group_works(matches);
+        let urls: Vec<Vec<_>> = works
+            .iter()
+            .map(|work| work.matches().map(|m| m.url.as_str()).collect())
+            .collect();
+
+        assert_eq!(
+            urls,
+            vec![
+                vec![
+                    "https://twitter.com/Syfaro/status/123",
+                    "https://www.furaffinity.net/view/123/"
+                ],
+                vec![
+                    "https://e621.net/posts/456",
+                    "https://www.pixiv.net/artworks/789"
+                ],
+                vec!["https://www.pixiv.net/artworks/999"],
+            ],
+            "works should be grouped by artist or links, ignoring meta artists"
+        );
+    }
+
+    #[test]
+    fn test_saucenao_matches() {
+        let resp: SauceNaoResponse = serde_json::from_value(serde_json::json!({
+            "header": { "status": 0 },
+            "results": [
+                {
+                    "header": { "similarity": "93.12", "index_id": 5 },
+                    "data": {
+                        "ext_urls": ["https://www.pixiv.net/member_illust.php?mode=medium&illust_id=456"],
+                        "member_name": "artist",
+                    },
+                },
+                {
+                    "header": { "similarity": "91.50", "index_id": 9 },
+                    "data": {
+                        "ext_urls": ["https://danbooru.donmai.us/post/show/789"],
+                        "creator": ["first", "second"],
+                    },
+                },
+                {
+                    "header": { "similarity": "88.00", "index_id": 999 },
+                    "data": {
+                        "ext_urls": ["https://www.example.com/art/1"],
+                        "creator": "",
+                    },
+                },
+                {
+                    "header": { "similarity": "45.00", "index_id": 5 },
+                    "data": {
+                        "ext_urls": ["https://www.pixiv.net/member_illust.php?mode=medium&illust_id=1"],
+                    },
+                },
+                {
+                    "header": { "similarity": "95.00", "index_id": 5 },
+                    "data": {},
+                },
+            ],
+        }))
+        .unwrap();
+
+        let matches = saucenao_matches(resp);
+        let sites: Vec<_> = matches.iter().map(|m| m.site_name.as_str()).collect();
+        assert_eq!(sites, vec!["Pixiv", "Danbooru", "example.com"]);
+        assert_eq!(matches[0].artists, vec!["artist"]);
+        assert_eq!(matches[1].artists, vec!["first", "second"]);
+        assert!(matches[2].artists.is_empty());
+    }
+
+    #[test]
+    fn test_iqdb_matches() {
+        let body = r#"
+            <div id="pages">
+                <div><table>
+                    <tr><th>Your image</th></tr>
+                    <tr><td class="image"><img src="/thu/thu_123.jpg"></td></tr>
+                </table></div>
+                <div><table>
+                    <tr><th>Best match</th></tr>
+                    <tr><td class="image"><a href="//danbooru.donmai.us/posts/123"><img src="/danbooru/thumb.jpg"></a></td></tr>
+                    <tr><td><img class="service-icon" src="/icon/danbooru.ico">Danbooru</td></tr>
+                    <tr><td>800×600 [Safe]</td></tr>
+                    <tr><td>96% similarity</td></tr>
+                </table></div>
+                <div><table>
+                    <tr><th>Additional match</th></tr>
+                    <tr><td class="image"><a href="https://yande.re/post/show/456"><img src="/yandere/thumb.jpg"></a></td></tr>
+                    <tr><td>800×600 [Safe]</td></tr>
+                    <tr><td>93% similarity</td></tr>
+                </table></div>
+                <div><table>
+                    <tr><th>Additional match</th></tr>
+                    <tr><td class="image"><a href="//gelbooru.com/index.php?page=post&amp;s=view&amp;id=789"><img></a></td></tr>
+                    <tr><td>85% similarity</td></tr>
+                </table></div>
+                <div><table>
+                    <tr><th>Possible match</th></tr>
+                    <tr><td class="image"><a href="//konachan.com/post/show/1"><img></a></td></tr>
+                    <tr><td>99% similarity</td></tr>
+                </table></div>
+            </div>
+        "#;
+
+        let matches = iqdb_matches(body);
+        let found: Vec<_> = matches
+            .iter()
+            .map(|m| (m.site_name.as_str(), m.url.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                ("Danbooru", "https://danbooru.donmai.us/posts/123"),
+                ("yande.re", "https://yande.re/post/show/456"),
+            ]
+        );
+        assert!(matches.iter().all(|m| m.provider == Iqdb::NAME));
+    }
+}
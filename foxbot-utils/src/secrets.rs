@@ -0,0 +1,463 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use anyhow::Context;
+use async_trait::async_trait;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+
+/// Configuration names of credentials used by site loaders.
+pub const SITE_CREDENTIALS: &[&str] = &[
+    "FA_A",
+    "FA_B",
+    "WEASYL_APITOKEN",
+    "INKBUNNY_USERNAME",
+    "INKBUNNY_PASSWORD",
+    "E621_LOGIN",
+    "E621_API_KEY",
+    "TWITTER_CONSUMER_KEY",
+    "TWITTER_CONSUMER_SECRET",
+    "FAUTIL_APITOKEN",
+];
+
+/// Redis key incremented whenever stored site credentials change, so other
+/// processes know to rebuild their sites.
+pub const CREDENTIALS_VERSION_KEY: &str = "site-credentials-version";
+
+/// Get the names of the sites that use a credential, so only they need to be
+/// rebuilt when it changes.
+pub fn sites_using(name: &str) -> &'static [&'static str] {
+    match name {
+        "FA_A" | "FA_B" => &["FurAffinity"],
+        "WEASYL_APITOKEN" => &["Weasyl"],
+        "INKBUNNY_USERNAME" | "INKBUNNY_PASSWORD" => &["Inkbunny"],
+        "E621_LOGIN" | "E621_API_KEY" => &["e621", "e926"],
+        "TWITTER_CONSUMER_KEY" | "TWITTER_CONSUMER_SECRET" => &["Twitter"],
+        "FAUTIL_APITOKEN" => &["FurAffinity", "direct link"],
+        _ => &[],
+    }
+}
+
+/// Encrypts site credentials before they're stored in the database, using
+/// `CREDENTIALS_KEY`, a base64 encoded 256-bit key.
+///
+/// Values are stored as the base64 encoded nonce followed by the ciphertext,
+/// and the credential name is authenticated so values can't be swapped.
+pub struct CredentialCipher(LessSafeKey);
+
+impl CredentialCipher {
+    pub fn new(key: &str) -> anyhow::Result<Self> {
+        let key = base64::decode(key).context("credentials key was not base64")?;
+        let key = UnboundKey::new(&AES_256_GCM, &key)
+            .map_err(|_err| anyhow::anyhow!("credentials key must be 32 bytes"))?;
+
+        Ok(Self(LessSafeKey::new(key)))
+    }
+
+    /// Encrypt the value of a credential.
+    pub fn encrypt(&self, name: &str, value: &str) -> anyhow::Result<String> {
+        let mut nonce = [0u8; NONCE_LEN];
+        SystemRandom::new()
+            .fill(&mut nonce)
+            .map_err(|_err| anyhow::anyhow!("unable to generate nonce"))?;
+
+        let mut data = value.as_bytes().to_vec();
+        self.0
+            .seal_in_place_append_tag(
+                Nonce::assume_unique_for_key(nonce),
+                Aad::from(name.as_bytes()),
+                &mut data,
+            )
+            .map_err(|_err| anyhow::anyhow!("unable to encrypt credential"))?;
+
+        let mut stored = nonce.to_vec();
+        stored.extend(data);
+
+        Ok(base64::encode(stored))
+    }
+
+    /// Decrypt a stored credential value.
+    pub fn decrypt(&self, name: &str, stored: &str) -> anyhow::Result<String> {
+        let mut nonce = base64::decode(stored).context("stored credential was not base64")?;
+        if nonce.len() < NONCE_LEN {
+            anyhow::bail!("stored credential was too short");
+        }
+
+        let mut data = nonce.split_off(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(&nonce)
+            .map_err(|_err| anyhow::anyhow!("stored credential had invalid nonce"))?;
+
+        let value = self
+            .0
+            .open_in_place(nonce, Aad::from(name.as_bytes()), &mut data)
+            .map_err(|_err| anyhow::anyhow!("unable to decrypt credential"))?;
+
+        String::from_utf8(value.to_vec()).context("credential was not utf-8")
+    }
+}
+
+/// Site credentials stored in the database, which take precedence over
+/// configured credentials so they can be changed without a restart.
+#[derive(Default)]
+pub struct CredentialOverrides(HashMap<String, String>);
+
+impl CredentialOverrides {
+    /// Load and decrypt stored credentials, using configured credentials for
+    /// any that couldn't be loaded.
+    ///
+    /// Stored credentials are ignored without a credentials key.
+    pub async fn load(conn: &sqlx::Pool<sqlx::Postgres>, key: Option<&str>) -> Self {
+        let cipher = match key.map(CredentialCipher::new) {
+            Some(Ok(cipher)) => cipher,
+            Some(Err(err)) => {
+                tracing::error!("unable to use credentials key: {:?}", err);
+                return Self::default();
+            }
+            None => return Self::default(),
+        };
+
+        let credentials = match foxbot_models::SiteCredentials::all(conn).await {
+            Ok(credentials) => credentials,
+            Err(err) => {
+                tracing::error!("unable to load stored site credentials: {:?}", err);
+                return Self::default();
+            }
+        };
+
+        Self(
+            credentials
+                .into_iter()
+                .filter_map(|(name, stored)| match cipher.decrypt(&name, &stored) {
+                    Ok(value) => Some((name, value)),
+                    Err(err) => {
+                        tracing::error!(%name, "unable to decrypt site credential: {:?}", err);
+                        None
+                    }
+                })
+                .collect(),
+        )
+    }
+
+    /// Get the value to use for a credential.
+    pub fn get(&self, name: &str, configured: &str) -> String {
+        self.0
+            .get(name)
+            .map(String::as_str)
+            .unwrap_or(configured)
+            .to_string()
+    }
+
+    /// Get the names of the sites using credentials that were added, changed,
+    /// or removed since the previous overrides.
+    pub fn changed_sites(&self, previous: &Self) -> Vec<&'static str> {
+        let mut sites: Vec<&'static str> = SITE_CREDENTIALS
+            .iter()
+            .filter(|name| self.0.get(**name) != previous.0.get(**name))
+            .flat_map(|name| sites_using(name))
+            .copied()
+            .collect();
+
+        sites.sort_unstable();
+        sites.dedup();
+
+        sites
+    }
+}
+
+/// A source of credentials, looked up by their configuration name.
+#[async_trait]
+pub trait SecretProvider: Send + Sync {
+    /// Get a secret, like `FA_A`, if this provider has it.
+    async fn get(&self, name: &str) -> anyhow::Result<Option<String>>;
+
+    /// Get every secret this provider has out of the given names.
+    async fn get_many(&self, names: &[&str]) -> anyhow::Result<HashMap<String, String>> {
+        let mut secrets = HashMap::with_capacity(names.len());
+
+        for name in names {
+            if let Some(value) = self.get(name).await? {
+                secrets.insert(name.to_string(), value);
+            }
+        }
+
+        Ok(secrets)
+    }
+}
+
+/// Secrets from environment variables.
+pub struct EnvSecrets;
+
+#[async_trait]
+impl SecretProvider for EnvSecrets {
+    async fn get(&self, name: &str) -> anyhow::Result<Option<String>> {
+        Ok(std::env::var(name).ok())
+    }
+}
+
+/// Secrets from files in a directory, like those mounted by Docker or
+/// Kubernetes. Each file is named with the lowercase secret name.
+pub struct FileSecrets {
+    dir: PathBuf,
+}
+
+impl FileSecrets {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+#[async_trait]
+impl SecretProvider for FileSecrets {
+    async fn get(&self, name: &str) -> anyhow::Result<Option<String>> {
+        let path = self.dir.join(name.to_lowercase());
+
+        match tokio::fs::read_to_string(&path).await {
+            Ok(value) => Ok(Some(value.trim_end().to_string())),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => {
+                Err(err).with_context(|| format!("unable to read secret {}", path.display()))
+            }
+        }
+    }
+}
+
+/// Secrets from a HashiCorp Vault KV version 2 secret, with a key for each
+/// lowercase secret name.
+pub struct VaultSecrets {
+    client: reqwest::Client,
+    addr: String,
+    token: String,
+    path: String,
+}
+
+impl VaultSecrets {
+    /// Create a provider for the secret at a path like `secret/data/foxbot`.
+    pub fn new(addr: String, token: String, path: String) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            addr,
+            token,
+            path,
+        }
+    }
+
+    async fn load(&self) -> anyhow::Result<HashMap<String, String>> {
+        let url = format!(
+            "{}/v1/{}",
+            self.addr.trim_end_matches('/'),
+            self.path.trim_start_matches('/')
+        );
+
+        let resp: serde_json::Value = self
+            .client
+            .get(&url)
+            .header("X-Vault-Token", &self.token)
+            .send()
+            .await
+            .context("unable to request vault secret")?
+            .error_for_status()
+            .context("vault returned error")?
+            .json()
+            .await
+            .context("vault returned invalid json")?;
+
+        let data = resp["data"]["data"]
+            .as_object()
+            .context("vault secret was missing data")?;
+
+        Ok(data
+            .iter()
+            .filter_map(|(key, value)| {
+                value
+                    .as_str()
+                    .map(|value| (key.to_lowercase(), value.to_string()))
+            })
+            .collect())
+    }
+}
+
+#[async_trait]
+impl SecretProvider for VaultSecrets {
+    async fn get(&self, name: &str) -> anyhow::Result<Option<String>> {
+        Ok(self.load().await?.remove(&name.to_lowercase()))
+    }
+
+    async fn get_many(&self, names: &[&str]) -> anyhow::Result<HashMap<String, String>> {
+        let mut data = self.load().await?;
+
+        Ok(names
+            .iter()
+            .filter_map(|name| {
+                data.remove(&name.to_lowercase())
+                    .map(|value| (name.to_string(), value))
+            })
+            .collect())
+    }
+}
+
+/// Check multiple providers, using the first value found for each secret.
+pub struct ChainedSecrets {
+    providers: Vec<Box<dyn SecretProvider>>,
+}
+
+#[async_trait]
+impl SecretProvider for ChainedSecrets {
+    async fn get(&self, name: &str) -> anyhow::Result<Option<String>> {
+        for provider in &self.providers {
+            if let Some(value) = provider.get(name).await? {
+                return Ok(Some(value));
+            }
+        }
+
+        Ok(None)
+    }
+
+    async fn get_many(&self, names: &[&str]) -> anyhow::Result<HashMap<String, String>> {
+        let mut secrets = HashMap::with_capacity(names.len());
+
+        for provider in &self.providers {
+            let missing: Vec<&str> = names
+                .iter()
+                .copied()
+                .filter(|name| !secrets.contains_key(*name))
+                .collect();
+            if missing.is_empty() {
+                break;
+            }
+
+            secrets.extend(provider.get_many(&missing).await?);
+        }
+
+        Ok(secrets)
+    }
+}
+
+/// Build the secret providers configured by environment variables.
+///
+/// Files in `SECRETS_DIR` are checked first, then the Vault secret at
+/// `VAULT_SECRET_PATH` if `VAULT_ADDR` and `VAULT_TOKEN` are set. Plain
+/// environment variables are handled by configuration loading.
+pub fn providers_from_env() -> ChainedSecrets {
+    let mut providers: Vec<Box<dyn SecretProvider>> = vec![];
+
+    if let Ok(dir) = std::env::var("SECRETS_DIR") {
+        providers.push(Box::new(FileSecrets::new(dir)));
+    }
+
+    if let (Ok(addr), Ok(token)) = (std::env::var("VAULT_ADDR"), std::env::var("VAULT_TOKEN")) {
+        let path =
+            std::env::var("VAULT_SECRET_PATH").unwrap_or_else(|_| "secret/data/foxbot".to_string());
+        providers.push(Box::new(VaultSecrets::new(addr, token, path)));
+    }
+
+    ChainedSecrets { providers }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct StaticSecrets(HashMap<String, String>);
+
+    #[async_trait]
+    impl SecretProvider for StaticSecrets {
+        async fn get(&self, name: &str) -> anyhow::Result<Option<String>> {
+            Ok(self.0.get(name).cloned())
+        }
+    }
+
+    #[test]
+    fn test_sites_using() {
+        for name in SITE_CREDENTIALS {
+            assert!(!sites_using(name).is_empty(), "{} had no sites", name);
+        }
+
+        assert_eq!(sites_using("E621_API_KEY"), &["e621", "e926"]);
+        assert!(sites_using("TELEGRAM_APITOKEN").is_empty());
+    }
+
+    #[test]
+    fn test_credential_cipher() {
+        let cipher = CredentialCipher::new(&base64::encode([7u8; 32])).unwrap();
+
+        let stored = cipher.encrypt("FA_A", "cookie").unwrap();
+        assert!(!stored.contains("cookie"));
+        assert_ne!(
+            stored,
+            cipher.encrypt("FA_A", "cookie").unwrap(),
+            "each value should use a new nonce"
+        );
+
+        assert_eq!(cipher.decrypt("FA_A", &stored).unwrap(), "cookie");
+        assert!(
+            cipher.decrypt("FA_B", &stored).is_err(),
+            "values should only decrypt for their own name"
+        );
+
+        let other = CredentialCipher::new(&base64::encode([8u8; 32])).unwrap();
+        assert!(other.decrypt("FA_A", &stored).is_err());
+
+        assert!(CredentialCipher::new(&base64::encode([7u8; 16])).is_err());
+    }
+
+    #[test]
+    fn test_credential_overrides() {
+        let overrides = CredentialOverrides(
+            vec![("FA_A".to_string(), "stored".to_string())]
+                .into_iter()
+                .collect(),
+        );
+
+        assert_eq!(overrides.get("FA_A", "configured"), "stored");
+        assert_eq!(overrides.get("FA_B", "configured"), "configured");
+
+        let updated = CredentialOverrides(
+            vec![
+                ("FA_A".to_string(), "stored".to_string()),
+                ("E621_API_KEY".to_string(), "stored".to_string()),
+            ]
+            .into_iter()
+            .collect(),
+        );
+        assert_eq!(updated.changed_sites(&overrides), &["e621", "e926"]);
+        assert_eq!(
+            CredentialOverrides::default().changed_sites(&overrides),
+            &["FurAffinity"]
+        );
+        assert!(overrides.changed_sites(&overrides).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_chained_secrets() {
+        let first = StaticSecrets(
+            vec![("FA_A".to_string(), "first".to_string())]
+                .into_iter()
+                .collect(),
+        );
+        let second = StaticSecrets(
+            vec![
+                ("FA_A".to_string(), "second".to_string()),
+                ("FA_B".to_string(), "second".to_string()),
+            ]
+            .into_iter()
+            .collect(),
+        );
+
+        let chained = ChainedSecrets {
+            providers: vec![Box::new(first), Box::new(second)],
+        };
+
+        let secrets = chained
+            .get_many(&["FA_A", "FA_B", "WEASYL_APITOKEN"])
+            .await
+            .unwrap();
+        assert_eq!(secrets["FA_A"], "first");
+        assert_eq!(secrets["FA_B"], "second");
+        assert!(!secrets.contains_key("WEASYL_APITOKEN"));
+
+        assert_eq!(
+            chained.get("FA_B").await.unwrap().as_deref(),
+            Some("second")
+        );
+    }
+}
@@ -0,0 +1,250 @@
+use std::{net::IpAddr, time::Duration};
+
+use reqwest::Url;
+
+/// Hosts that only redirect to another URL.
+const SHORTENERS: &[&str] = &[
+    "t.co",
+    "bit.ly",
+    "buff.ly",
+    "ow.ly",
+    "tinyurl.com",
+    "trib.al",
+];
+
+/// Most redirects to follow when resolving a shortened link.
+const MAX_REDIRECTS: usize = 5;
+
+/// Query parameters that only track where a link was shared from.
+const TRACKING_PARAMS: &[&str] = &["fbclid", "gclid", "igshid", "ref_src", "ref_url"];
+
+/// Query parameters that Twitter adds when sharing a Tweet.
+const TWITTER_PARAMS: &[&str] = &["s", "t"];
+
+lazy_static::lazy_static! {
+    static ref CLIENT: reqwest::Client = reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .timeout(Duration::from_secs(5))
+        .build()
+        .expect("unable to build url client");
+}
+
+/// Normalize a link before it is matched against sites.
+///
+/// Links from known shorteners are resolved to where they redirect and any
+/// tracking parameters are removed. If the link can't be resolved it is still
+/// cleaned up and returned.
+pub async fn normalize_url(link: &str) -> String {
+    let mut url = match Url::parse(link) {
+        Ok(url) => url,
+        Err(_) => return link.to_string(),
+    };
+
+    for _ in 0..MAX_REDIRECTS {
+        if !is_shortener(&url) {
+            break;
+        }
+
+        match resolve_redirect(&url).await {
+            Ok(Some(location)) => url = location,
+            Ok(None) => break,
+            Err(err) => {
+                tracing::warn!(%url, "unable to resolve shortened link: {:?}", err);
+                break;
+            }
+        }
+    }
+
+    strip_tracking(url).to_string()
+}
+
+/// Check if a link only points to public addresses, so requests to it can't
+/// reach internal services.
+///
+/// Links without a host or with a host that can't be resolved are not public.
+pub async fn is_public_url(link: &str) -> bool {
+    let url = match Url::parse(link) {
+        Ok(url) => url,
+        Err(_) => return false,
+    };
+
+    if !matches!(url.scheme(), "http" | "https") {
+        return false;
+    }
+
+    let (host, port) = match (url.host_str(), url.port_or_known_default()) {
+        (Some(host), Some(port)) => (host.trim_matches(|c| c == '[' || c == ']'), port),
+        _ => return false,
+    };
+
+    match tokio::net::lookup_host((host, port)).await {
+        Ok(addrs) => {
+            let addrs: Vec<_> = addrs.collect();
+            !addrs.is_empty() && addrs.iter().all(|addr| is_public_ip(addr.ip()))
+        }
+        Err(err) => {
+            tracing::debug!(host, "unable to resolve host: {:?}", err);
+            false
+        }
+    }
+}
+
+/// If an address is reachable from the internet, and not loopback, private,
+/// or otherwise reserved.
+fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                // Addresses in 0.0.0.0/8 may reach the local host.
+                || ip.octets()[0] == 0
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                // Shared address space used for carrier-grade NAT.
+                || (ip.octets()[0] == 100 && (ip.octets()[1] & 0xc0) == 64))
+        }
+        IpAddr::V6(ip) => {
+            if ip.is_loopback() || ip.is_unspecified() {
+                return false;
+            }
+
+            if let Some(ip) = ip.to_ipv4() {
+                return is_public_ip(IpAddr::V4(ip));
+            }
+
+            let first = ip.segments()[0];
+
+            // Unique local and link-local addresses.
+            (first & 0xfe00) != 0xfc00 && (first & 0xffc0) != 0xfe80
+        }
+    }
+}
+
+fn is_shortener(url: &Url) -> bool {
+    url.host_str()
+        .map(|host| SHORTENERS.contains(&host.trim_start_matches("www.")))
+        .unwrap_or(false)
+}
+
+/// Get the URL a link redirects to, if it redirects.
+async fn resolve_redirect(url: &Url) -> anyhow::Result<Option<Url>> {
+    let resp = CLIENT.head(url.clone()).send().await?;
+
+    if !resp.status().is_redirection() {
+        return Ok(None);
+    }
+
+    let location = match resp
+        .headers()
+        .get(reqwest::header::LOCATION)
+        .and_then(|location| location.to_str().ok())
+    {
+        Some(location) => location,
+        None => return Ok(None),
+    };
+
+    Ok(Some(url.join(location)?))
+}
+
+/// Remove query parameters that only track how a link was shared.
+///
+/// The query is only rebuilt if something was removed, as rebuilding it may
+/// change how the remaining parameters are encoded.
+fn strip_tracking(mut url: Url) -> Url {
+    let is_twitter = url
+        .host_str()
+        .map(|host| host == "twitter.com" || host.ends_with(".twitter.com"))
+        .unwrap_or(false);
+
+    let params: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(key, _value)| {
+            !key.starts_with("utm_")
+                && !TRACKING_PARAMS.contains(&key.as_ref())
+                && !(is_twitter && TWITTER_PARAMS.contains(&key.as_ref()))
+        })
+        .map(|(key, value)| (key.into_owned(), value.into_owned()))
+        .collect();
+
+    if params.len() == url.query_pairs().count() {
+        return url;
+    }
+
+    if params.is_empty() {
+        url.set_query(None);
+    } else {
+        url.query_pairs_mut().clear().extend_pairs(params);
+    }
+
+    url
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_public_ip, is_shortener, strip_tracking};
+    use reqwest::Url;
+
+    fn strip(url: &str) -> String {
+        strip_tracking(Url::parse(url).unwrap()).to_string()
+    }
+
+    #[test]
+    fn test_strip_tracking() {
+        assert_eq!(
+            strip("https://twitter.com/Syfaro/status/1?s=20"),
+            "https://twitter.com/Syfaro/status/1"
+        );
+        assert_eq!(
+            strip("https://mobile.twitter.com/Syfaro/status/1?s=20&t=abc"),
+            "https://mobile.twitter.com/Syfaro/status/1"
+        );
+        assert_eq!(
+            strip("https://www.furaffinity.net/view/1/?utm_source=share&utm_medium=tg"),
+            "https://www.furaffinity.net/view/1/"
+        );
+        assert_eq!(
+            strip("https://e621.net/posts?tags=fox&utm_campaign=x"),
+            "https://e621.net/posts?tags=fox"
+        );
+        assert_eq!(
+            strip("https://example.com/search?s=fox"),
+            "https://example.com/search?s=fox"
+        );
+
+        for url in [
+            "https://e621.net/posts?tags=fox%20rating:s&page=2",
+            "https://example.com/search?q=a+b&empty=&flag",
+            "https://example.com/page?",
+        ] {
+            assert_eq!(strip(url), url, "urls without tracking should be unchanged");
+        }
+    }
+
+    #[test]
+    fn test_is_shortener() {
+        assert!(is_shortener(&Url::parse("https://t.co/abc").unwrap()));
+        assert!(is_shortener(&Url::parse("https://www.bit.ly/abc").unwrap()));
+        assert!(!is_shortener(
+            &Url::parse("https://twitter.com/abc").unwrap()
+        ));
+    }
+
+    #[test]
+    fn test_is_public_ip() {
+        let public = |ip: &str| is_public_ip(ip.parse().unwrap());
+
+        assert!(public("1.1.1.1"));
+        assert!(public("2606:4700:4700::1111"));
+        assert!(!public("127.0.0.1"));
+        assert!(!public("10.0.0.1"));
+        assert!(!public("192.168.1.1"));
+        assert!(!public("169.254.169.254"));
+        assert!(!public("100.64.0.1"));
+        assert!(!public("0.0.0.0"));
+        assert!(!public("::1"));
+        assert!(!public("fd00::1"));
+        assert!(!public("fe80::1"));
+        assert!(!public("::ffff:127.0.0.1"));
+    }
+}
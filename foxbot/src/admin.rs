@@ -0,0 +1,202 @@
+use std::sync::Arc;
+
+use hyper::{Body, Request, Response, StatusCode};
+use redis::AsyncCommands;
+
+use crate::MessageHandler;
+
+/// Handle a request to the admin API.
+///
+/// Requests must include the `ADMIN_API_TOKEN` as a bearer token. The API is
+/// disabled if no token was configured.
+pub async fn handle(req: Request<Body>, handler: Arc<MessageHandler>) -> Response<Body> {
+    let token = match handler.config.admin_api_token.as_deref() {
+        Some(token) => token,
+        None => return status_response(StatusCode::NOT_FOUND),
+    };
+
+    if !is_authorized(&req, token) {
+        return status_response(StatusCode::UNAUTHORIZED);
+    }
+
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+
+    let result = match (&method, path.as_str()) {
+        (&hyper::Method::GET, "/admin/sites") => Ok(Some(sites())),
+        (&hyper::Method::GET, "/admin/queues") => queues(&handler).await.map(Some),
+        (&hyper::Method::GET, "/admin/caches") => Ok(Some(caches(&handler).await)),
+        (&hyper::Method::GET, "/admin/wrong-sources") => wrong_sources(&handler).await.map(Some),
+        (&hyper::Method::POST, path) if path.starts_with("/admin/caches/") => {
+            flush_cache(&handler, &path["/admin/caches/".len()..]).await
+        }
+        (&hyper::Method::PUT, path) if path.starts_with("/admin/credentials/") => {
+            let value = match hyper::body::to_bytes(req.into_body()).await {
+                Ok(body) => String::from_utf8_lossy(&body).trim().to_string(),
+                Err(_err) => return status_response(StatusCode::BAD_REQUEST),
+            };
+            if value.is_empty() {
+                return status_response(StatusCode::BAD_REQUEST);
+            }
+
+            update_credential(&handler, &path["/admin/credentials/".len()..], Some(&value)).await
+        }
+        (&hyper::Method::DELETE, path) if path.starts_with("/admin/credentials/") => {
+            update_credential(&handler, &path["/admin/credentials/".len()..], None).await
+        }
+        _ => Ok(None),
+    };
+
+    match result {
+        Ok(Some(value)) => {
+            let mut resp = Response::new(Body::from(value.to_string()));
+            resp.headers_mut().insert(
+                hyper::header::CONTENT_TYPE,
+                hyper::header::HeaderValue::from_static("application/json"),
+            );
+
+            resp
+        }
+        Ok(None) => status_response(StatusCode::NOT_FOUND),
+        Err(err) => {
+            tracing::error!("admin api request failed: {:?}", err);
+
+            status_response(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+fn status_response(status: StatusCode) -> Response<Body> {
+    let mut resp = Response::new(Body::default());
+    *resp.status_mut() = status;
+
+    resp
+}
+
+/// Check if the request included the correct bearer token.
+fn is_authorized(req: &Request<Body>, token: &str) -> bool {
+    let provided = match req
+        .headers()
+        .get(hyper::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+    {
+        Some(provided) => provided,
+        None => return false,
+    };
+
+    foxbot_utils::constant_time_eq(provided, token)
+}
+
+/// Status of each site loader since the bot started.
+fn sites() -> serde_json::Value {
+    let sites: serde_json::Map<String, serde_json::Value> = foxbot_utils::site_statuses()
+        .into_iter()
+        .map(|(site, status)| {
+            let (last_error, last_error_at) = match status.last_error {
+                Some((error, at)) => (Some(error), Some(at)),
+                None => (None, None),
+            };
+
+            (
+                site.to_string(),
+                serde_json::json!({
+                    "successes": status.successes,
+                    "failures": status.failures,
+                    "consecutive_failures": status.consecutive_failures,
+                    "last_error": last_error,
+                    "last_error_at": last_error_at,
+                }),
+            )
+        })
+        .collect();
+
+    serde_json::Value::Object(sites)
+}
+
+/// Number of jobs waiting to run or needing attention.
+async fn queues(handler: &MessageHandler) -> anyhow::Result<serde_json::Value> {
+    let mut redis = handler.redis.clone();
+
+    let postgres: serde_json::Map<String, serde_json::Value> =
+        foxbot_models::QueuedJobs::depths(&handler.conn)
+            .await?
+            .into_iter()
+            .map(|(queue, count)| (queue, count.into()))
+            .collect();
+    let scheduled: usize = redis.zcard(foxbot_utils::SCHEDULED_JOBS_KEY).await?;
+    let failed = foxbot_models::FailedJobs::pending_count(&handler.conn).await?;
+
+    Ok(serde_json::json!({
+        "backend": if handler.faktory.is_some() { "faktory" } else { "postgres" },
+        "postgres": postgres,
+        "scheduled": scheduled,
+        "failed": failed,
+    }))
+}
+
+/// Sources users reported as wrong, for improving FuzzySearch's matches.
+async fn wrong_sources(handler: &MessageHandler) -> anyhow::Result<serde_json::Value> {
+    let sources = foxbot_models::WrongSources::all(&handler.conn).await?;
+
+    Ok(serde_json::to_value(sources)?)
+}
+
+/// Cache usage since the bot started.
+async fn caches(handler: &MessageHandler) -> serde_json::Value {
+    let (hits, misses) = foxbot_models::file_cache_requests();
+    let hit_rate = if hits + misses > 0 {
+        Some(hits as f64 / (hits + misses) as f64)
+    } else {
+        None
+    };
+
+    let languages = handler.l10n.cached_bundles();
+
+    serde_json::json!({
+        "file_id": {
+            "hits": hits,
+            "misses": misses,
+            "hit_rate": hit_rate,
+        },
+        "languages": {
+            "entries": languages,
+        },
+    })
+}
+
+/// Remove everything from a cache, returning `None` if the cache is unknown.
+async fn flush_cache(
+    handler: &MessageHandler,
+    name: &str,
+) -> anyhow::Result<Option<serde_json::Value>> {
+    let removed = match name {
+        "file_id" => foxbot_models::FileCache::flush(&handler.conn).await?,
+        "cached_post" => foxbot_models::CachedPost::flush(&handler.conn).await?,
+        "languages" => handler.l10n.clear_cache() as u64,
+        _ => return Ok(None),
+    };
+
+    tracing::info!(name, removed, "flushed cache");
+
+    Ok(Some(serde_json::json!({ "removed": removed })))
+}
+
+/// Store a site credential from the request body, or remove it to use the
+/// configured value again. Returns `None` if it isn't a site credential.
+async fn update_credential(
+    handler: &MessageHandler,
+    name: &str,
+    value: Option<&str>,
+) -> anyhow::Result<Option<serde_json::Value>> {
+    if !foxbot_utils::secrets::SITE_CREDENTIALS.contains(&name) {
+        return Ok(None);
+    }
+
+    handler.update_site_credential(name, value, None).await?;
+
+    Ok(Some(serde_json::json!({
+        "name": name,
+        "sites": foxbot_utils::secrets::sites_using(name),
+    })))
+}
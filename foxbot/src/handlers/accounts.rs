@@ -0,0 +1,263 @@
+use anyhow::Context;
+use async_trait::async_trait;
+use tgbotapi::{
+    requests::{AnswerCallbackQuery, EditMessageText, ReplyMarkup, SendMessage},
+    CallbackQuery, InlineKeyboardButton, InlineKeyboardMarkup, Message, Update, User,
+};
+
+use super::{
+    CommandArgs, CommandHandler, CommandScope, CommandSpec, Handler,
+    Status::{self, Completed, Ignored},
+};
+use crate::MessageHandler;
+use foxbot_models::Twitter;
+use foxbot_utils::{get_message, needs_field};
+
+/// A site where a user may have linked an account.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum LinkedSite {
+    Twitter,
+}
+
+impl LinkedSite {
+    const ALL: &'static [LinkedSite] = &[LinkedSite::Twitter];
+
+    /// Identifier used in callback data.
+    fn key(self) -> &'static str {
+        match self {
+            LinkedSite::Twitter => "twitter",
+        }
+    }
+
+    fn from_key(key: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|site| site.key() == key)
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            LinkedSite::Twitter => "Twitter",
+        }
+    }
+
+    async fn is_linked(self, handler: &MessageHandler, user_id: i64) -> anyhow::Result<bool> {
+        match self {
+            LinkedSite::Twitter => Ok(Twitter::get_account(&handler.conn, user_id)
+                .await?
+                .is_some()),
+        }
+    }
+
+    /// Delete the user's credentials for this site.
+    ///
+    /// Site loaders look up credentials for each request, so nothing else
+    /// holds onto them after they are deleted.
+    async fn revoke(self, handler: &MessageHandler, user_id: i64) -> anyhow::Result<()> {
+        match self {
+            LinkedSite::Twitter => Twitter::remove_account(&handler.conn, user_id).await,
+        }
+    }
+}
+
+pub struct AccountsHandler;
+
+#[async_trait]
+impl Handler for AccountsHandler {
+    fn name(&self) -> &'static str {
+        "accounts"
+    }
+
+    async fn handle(&self, handler: &MessageHandler, update: &Update) -> anyhow::Result<Status> {
+        let callback_query = needs_field!(update, callback_query);
+        let data = needs_field!(callback_query, data);
+
+        let site = match data.strip_prefix("a:revoke:") {
+            Some(key) => LinkedSite::from_key(key),
+            None => return Ok(Ignored),
+        };
+
+        revoke(handler, callback_query, site).await
+    }
+}
+
+#[async_trait]
+impl CommandHandler for AccountsHandler {
+    fn commands(&self) -> &'static [CommandSpec] {
+        &[CommandSpec {
+            name: "/accounts",
+            aliases: &[],
+            description: "command-accounts",
+            args: &[],
+            scope: CommandScope::Private,
+        }]
+    }
+
+    async fn handle_command(
+        &self,
+        handler: &MessageHandler,
+        message: &Message,
+        _command: &str,
+        _args: &CommandArgs,
+    ) -> anyhow::Result<()> {
+        let user = message.from.as_ref().context("accounts missing user")?;
+
+        send_accounts_message(handler, message, user)
+            .await
+            .context("unable to send accounts message")
+    }
+}
+
+async fn send_accounts_message(
+    handler: &MessageHandler,
+    message: &Message,
+    user: &User,
+) -> anyhow::Result<()> {
+    let (text, markup) = accounts_message(handler, user).await?;
+
+    let message = SendMessage {
+        chat_id: user.id.into(),
+        text,
+        reply_markup: markup,
+        ..Default::default()
+    };
+
+    handler
+        .make_request(&message)
+        .await
+        .context("unable to send accounts message")?;
+
+    Ok(())
+}
+
+/// Build the text and revoke buttons listing a user's linked accounts.
+async fn accounts_message(
+    handler: &MessageHandler,
+    user: &User,
+) -> anyhow::Result<(String, Option<ReplyMarkup>)> {
+    let mut linked = Vec::with_capacity(LinkedSite::ALL.len());
+    for site in LinkedSite::ALL {
+        if site.is_linked(handler, user.id).await? {
+            linked.push(*site);
+        }
+    }
+
+    let lang = user.language_code.as_deref();
+
+    if linked.is_empty() {
+        let text = handler
+            .get_fluent_bundle(lang, |bundle| {
+                get_message(bundle, "accounts-none", None).unwrap()
+            })
+            .await;
+
+        return Ok((text, None));
+    }
+
+    let (text, buttons) = handler
+        .get_fluent_bundle(lang, |bundle| {
+            let text = get_message(bundle, "accounts-linked", None).unwrap();
+
+            let buttons = linked
+                .iter()
+                .map(|site| {
+                    let mut args = fluent::FluentArgs::new();
+                    args.insert("name", site.name().into());
+
+                    vec![InlineKeyboardButton {
+                        text: get_message(bundle, "accounts-revoke", Some(args)).unwrap(),
+                        callback_data: Some(format!("a:revoke:{}", site.key())),
+                        ..Default::default()
+                    }]
+                })
+                .collect();
+
+            (text, buttons)
+        })
+        .await;
+
+    let markup = ReplyMarkup::InlineKeyboardMarkup(InlineKeyboardMarkup {
+        inline_keyboard: buttons,
+    });
+
+    Ok((text, Some(markup)))
+}
+
+async fn revoke(
+    handler: &MessageHandler,
+    callback_query: &CallbackQuery,
+    site: Option<LinkedSite>,
+) -> anyhow::Result<Status> {
+    let user = &callback_query.from;
+
+    let text = match site {
+        Some(site) => {
+            site.revoke(handler, user.id)
+                .await
+                .context("unable to revoke account")?;
+
+            tracing::info!(
+                user_id = user.id,
+                site = site.key(),
+                "revoked linked account"
+            );
+
+            let mut args = fluent::FluentArgs::new();
+            args.insert("name", site.name().into());
+
+            handler
+                .get_fluent_bundle(user.language_code.as_deref(), |bundle| {
+                    get_message(bundle, "accounts-revoked", Some(args)).unwrap()
+                })
+                .await
+        }
+        None => {
+            handler
+                .get_fluent_bundle(user.language_code.as_deref(), |bundle| {
+                    get_message(bundle, "settings-unsupported", None).unwrap()
+                })
+                .await
+        }
+    };
+
+    let answer = AnswerCallbackQuery {
+        callback_query_id: callback_query.id.clone(),
+        text: Some(text),
+        ..Default::default()
+    };
+
+    handler
+        .make_request(&answer)
+        .await
+        .context("unable to answer revoke callback query")?;
+
+    let message = needs_field!(callback_query, message);
+    let (text, markup) = accounts_message(handler, user).await?;
+
+    let edit_message = EditMessageText {
+        chat_id: message.chat_id(),
+        message_id: Some(message.message_id),
+        text,
+        reply_markup: markup,
+        ..Default::default()
+    };
+
+    handler
+        .make_request(&edit_message)
+        .await
+        .context("unable to update accounts message")?;
+
+    Ok(Completed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LinkedSite;
+
+    #[test]
+    fn test_linked_site_key() {
+        for site in LinkedSite::ALL {
+            assert_eq!(LinkedSite::from_key(site.key()), Some(*site));
+        }
+
+        assert_eq!(LinkedSite::from_key("unknown"), None);
+    }
+}
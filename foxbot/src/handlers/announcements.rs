@@ -0,0 +1,135 @@
+use anyhow::Context;
+use async_trait::async_trait;
+use tgbotapi::{requests::SendMessage, Message};
+
+use super::{
+    permissions::is_chat_admin, ArgKind, CommandArg, CommandArgs, CommandHandler, CommandScope,
+    CommandSpec,
+};
+use crate::MessageHandler;
+use foxbot_models::{Announcements, Broadcasts};
+use foxbot_utils::{get_faktory_custom, get_message, QUEUE_LOW};
+
+/// Initial state of a `broadcast` job, matching the background worker's
+/// payload.
+#[derive(serde::Serialize)]
+struct BroadcastBatch {
+    broadcast_id: i32,
+    after_chat_id: i32,
+}
+
+/// Commands for opting into announcements and for bot administrators to send
+/// them.
+pub struct AnnouncementCommands;
+
+#[async_trait]
+impl CommandHandler for AnnouncementCommands {
+    fn commands(&self) -> &'static [CommandSpec] {
+        &[
+            CommandSpec {
+                name: "/announcements",
+                aliases: &[],
+                description: "command-announcements",
+                args: &[],
+                scope: CommandScope::Any,
+            },
+            CommandSpec {
+                name: "/broadcast",
+                aliases: &[],
+                description: "command-broadcast",
+                args: &[CommandArg::required("text", ArgKind::Rest)],
+                scope: CommandScope::BotAdmin,
+            },
+        ]
+    }
+
+    async fn handle_command(
+        &self,
+        handler: &MessageHandler,
+        message: &Message,
+        command: &str,
+        args: &CommandArgs,
+    ) -> anyhow::Result<()> {
+        match command {
+            "/announcements" => toggle_announcements(handler, message).await,
+            "/broadcast" => {
+                let text = args.text("text").context("broadcast missing text")?;
+                broadcast(handler, message, text).await
+            }
+            _ => unreachable!("unknown announcement command {}", command),
+        }
+    }
+}
+
+/// Turn announcements on or off for a chat. Only administrators may change it
+/// for groups.
+async fn toggle_announcements(handler: &MessageHandler, message: &Message) -> anyhow::Result<()> {
+    let user = message
+        .from
+        .as_ref()
+        .context("announcements missing user")?;
+
+    if message.chat.chat_type.is_group()
+        && !is_chat_admin(handler, message.chat.id, user.id).await?
+    {
+        handler
+            .send_generic_reply(message, "announcements-not-admin")
+            .await?;
+        return Ok(());
+    }
+
+    let name = if Announcements::is_subscribed(&handler.conn, message.chat.id).await? {
+        Announcements::unsubscribe(&handler.conn, message.chat.id).await?;
+        "announcements-disabled"
+    } else {
+        Announcements::subscribe(&handler.conn, message.chat.id).await?;
+        "announcements-enabled"
+    };
+
+    handler.send_generic_reply(message, name).await?;
+
+    Ok(())
+}
+
+/// Start sending an announcement to every chat that opted into them.
+async fn broadcast(handler: &MessageHandler, message: &Message, text: &str) -> anyhow::Result<()> {
+    let user = message.from.as_ref().context("broadcast missing user")?;
+
+    let broadcast_id = Broadcasts::create(&handler.conn, text, user.id).await?;
+    let count = Announcements::count(&handler.conn).await?;
+
+    tracing::info!(broadcast_id, count, "starting broadcast");
+
+    let batch = serde_json::to_value(&BroadcastBatch {
+        broadcast_id,
+        after_chat_id: 0,
+    })?;
+
+    let mut job = faktory::Job::new("broadcast", vec![batch]).on_queue(QUEUE_LOW);
+    job.custom = get_faktory_custom();
+
+    handler.enqueue(job).await?;
+
+    let text = handler
+        .get_fluent_bundle(user.language_code.as_deref(), |bundle| {
+            let mut args = fluent::FluentArgs::new();
+            args.insert("count", count.into());
+
+            get_message(bundle, "broadcast-started", Some(args)).unwrap()
+        })
+        .await;
+
+    let send_message = SendMessage {
+        chat_id: message.chat_id(),
+        reply_to_message_id: Some(message.message_id),
+        text,
+        ..Default::default()
+    };
+
+    handler
+        .make_request(&send_message)
+        .await
+        .context("unable to send broadcast reply")?;
+
+    Ok(())
+}
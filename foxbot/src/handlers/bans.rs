@@ -0,0 +1,291 @@
+use std::{
+    collections::HashSet,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use tgbotapi::{requests::SendMessage, Message};
+
+use super::{ArgKind, CommandArg, CommandArgs, CommandHandler, CommandScope, CommandSpec};
+use crate::MessageHandler;
+use foxbot_models::{BannedChats, BannedUsers};
+use foxbot_sites::RateLimited;
+use foxbot_utils::get_message;
+
+/// How long bans are used before being loaded again, so bans from other
+/// instances and expired bans are noticed.
+const BAN_REFRESH: Duration = Duration::from_secs(30);
+
+/// Seconds strikes from sites rate limiting a user's requests are remembered
+/// for.
+const STRIKE_PERIOD: u64 = 60 * 60;
+
+/// Number of minutes within the strike period a user's requests may be rate
+/// limited by sites before they are temporarily banned.
+const STRIKES_BEFORE_BAN: usize = 5;
+
+/// Seconds a user is banned for after their requests were rate limited too
+/// often.
+const STRIKE_BAN_TTL: i64 = 60 * 60;
+
+/// Bans loaded from the database.
+struct LoadedBans {
+    loaded_at: Instant,
+    users: HashSet<i64>,
+    chats: HashSet<i64>,
+}
+
+/// Cached list of banned users and chats, so every update doesn't need to
+/// query the database.
+pub struct BanList {
+    bans: std::sync::RwLock<Option<LoadedBans>>,
+    /// Held while bans are loaded, so stale bans are only loaded once no
+    /// matter how many updates are waiting on them.
+    loading: tokio::sync::Mutex<()>,
+}
+
+impl BanList {
+    pub fn new() -> Self {
+        Self {
+            bans: std::sync::RwLock::new(None),
+            loading: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// Check bans that were loaded recently enough to use.
+    fn check_loaded<F>(&self, check: F) -> Option<bool>
+    where
+        F: Fn(&LoadedBans) -> bool,
+    {
+        let bans = self.bans.read().expect("ban list lock was poisoned");
+
+        bans.as_ref()
+            .filter(|bans| bans.loaded_at.elapsed() < BAN_REFRESH)
+            .map(check)
+    }
+
+    /// Check if a user or chat is banned, loading bans again if they are
+    /// stale.
+    pub async fn is_banned(
+        &self,
+        conn: &sqlx::Pool<sqlx::Postgres>,
+        user_id: Option<i64>,
+        chat_id: Option<i64>,
+    ) -> anyhow::Result<bool> {
+        let check = |bans: &LoadedBans| {
+            user_id.map(|id| bans.users.contains(&id)).unwrap_or(false)
+                || chat_id.map(|id| bans.chats.contains(&id)).unwrap_or(false)
+        };
+
+        if let Some(banned) = self.check_loaded(check) {
+            return Ok(banned);
+        }
+
+        let _loading = self.loading.lock().await;
+
+        // Another update may have loaded bans while this one was waiting.
+        if let Some(banned) = self.check_loaded(check) {
+            return Ok(banned);
+        }
+
+        tracing::debug!("loading bans");
+
+        let bans = LoadedBans {
+            loaded_at: Instant::now(),
+            users: BannedUsers::active(conn).await?.into_iter().collect(),
+            chats: BannedChats::active(conn).await?.into_iter().collect(),
+        };
+        let banned = check(&bans);
+
+        *self.bans.write().expect("ban list lock was poisoned") = Some(bans);
+
+        Ok(banned)
+    }
+
+    /// Load bans again before the next check.
+    pub fn invalidate(&self) {
+        *self.bans.write().expect("ban list lock was poisoned") = None;
+    }
+}
+
+/// Record a strike for a user if loading their links failed because a site
+/// was rate limiting requests, passing through the result either way.
+pub(crate) async fn check_rate_limited<T>(
+    handler: &MessageHandler,
+    user_id: i64,
+    result: anyhow::Result<T>,
+) -> anyhow::Result<T> {
+    if let Err(err) = &result {
+        if RateLimited::is_cause(err) {
+            if let Err(err) = record_strike(handler, user_id).await {
+                tracing::warn!("unable to record rate limit strike: {:?}", err);
+            }
+        }
+    }
+
+    result
+}
+
+/// Record that a site rate limited requests made for a user, temporarily
+/// banning them if it has happened too often.
+///
+/// Each minute counts as at most one strike, so users aren't banned for a
+/// single burst of links.
+async fn record_strike(handler: &MessageHandler, user_id: i64) -> anyhow::Result<()> {
+    if handler.is_bot_admin(user_id) {
+        return Ok(());
+    }
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs();
+    let minute = now / 60;
+
+    let key = format!("ratelimit-strikes:{}", user_id);
+
+    let mut redis = handler.redis.clone();
+    let (strikes,): (usize,) = redis::pipe()
+        .atomic()
+        .zadd(&key, minute, minute)
+        .ignore()
+        .zrembyscore(&key, 0, minute.saturating_sub(STRIKE_PERIOD / 60))
+        .ignore()
+        .zcard(&key)
+        .expire(&key, STRIKE_PERIOD as usize)
+        .ignore()
+        .query_async(&mut redis)
+        .await
+        .context("unable to record rate limit strike")?;
+
+    tracing::debug!(user_id, strikes, "site rate limited user's requests");
+
+    if strikes < STRIKES_BEFORE_BAN {
+        return Ok(());
+    }
+
+    tracing::info!(
+        user_id,
+        strikes,
+        "temporarily banning user for being rate limited"
+    );
+
+    BannedUsers::ban(
+        &handler.conn,
+        user_id,
+        Some("rate limited"),
+        None,
+        Some(STRIKE_BAN_TTL),
+    )
+    .await?;
+
+    redis::cmd("DEL")
+        .arg(&key)
+        .query_async::<_, ()>(&mut redis)
+        .await
+        .context("unable to clear rate limit strikes")?;
+
+    handler.bans.invalidate();
+
+    Ok(())
+}
+
+/// Commands for bot administrators to ban and unban users and chats.
+pub struct BanCommands;
+
+#[async_trait]
+impl CommandHandler for BanCommands {
+    fn commands(&self) -> &'static [CommandSpec] {
+        &[
+            CommandSpec {
+                name: "/ban",
+                aliases: &[],
+                description: "command-ban",
+                args: &[
+                    CommandArg::required("id", ArgKind::Number),
+                    CommandArg::optional("reason", ArgKind::Rest),
+                ],
+                scope: CommandScope::BotAdmin,
+            },
+            CommandSpec {
+                name: "/unban",
+                aliases: &[],
+                description: "command-unban",
+                args: &[CommandArg::required("id", ArgKind::Number)],
+                scope: CommandScope::BotAdmin,
+            },
+        ]
+    }
+
+    async fn handle_command(
+        &self,
+        handler: &MessageHandler,
+        message: &Message,
+        command: &str,
+        args: &CommandArgs,
+    ) -> anyhow::Result<()> {
+        let id: i64 = args.get("id").context("ban missing id")?;
+        // Users always have positive IDs, while groups and channels are
+        // negative.
+        let is_chat = id < 0;
+
+        let name = match command {
+            "/ban" => {
+                let reason = args.text("reason");
+                let banned_by = message.from.as_ref().map(|from| from.id);
+
+                if is_chat {
+                    BannedChats::ban(&handler.conn, id, reason, banned_by, None).await?;
+                } else {
+                    BannedUsers::ban(&handler.conn, id, reason, banned_by, None).await?;
+                }
+
+                "ban-added"
+            }
+            "/unban" => {
+                let was_banned = if is_chat {
+                    BannedChats::unban(&handler.conn, id).await?
+                } else {
+                    BannedUsers::unban(&handler.conn, id).await?
+                };
+
+                if was_banned {
+                    "ban-removed"
+                } else {
+                    "ban-missing"
+                }
+            }
+            _ => unreachable!("unknown ban command {}", command),
+        };
+
+        handler.bans.invalidate();
+
+        let lang = message
+            .from
+            .as_ref()
+            .and_then(|from| from.language_code.as_deref());
+
+        let text = handler
+            .get_fluent_bundle(lang, |bundle| {
+                let mut args = fluent::FluentArgs::new();
+                args.insert("id", id.to_string().into());
+
+                get_message(bundle, name, Some(args)).unwrap()
+            })
+            .await;
+
+        let send_message = SendMessage {
+            chat_id: message.chat_id(),
+            reply_to_message_id: Some(message.message_id),
+            text,
+            ..Default::default()
+        };
+
+        handler
+            .make_request(&send_message)
+            .await
+            .context("unable to send ban reply")?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,131 @@
+use std::{collections::HashMap, sync::Arc};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use tgbotapi::{requests::AnswerCallbackQuery, CallbackQuery, Update};
+
+use super::{
+    Handler,
+    Status::{self, Completed, Ignored},
+};
+use crate::MessageHandler;
+use foxbot_utils::{callback::CallbackData, get_message, needs_field};
+
+/// How to answer a callback query once it was handled.
+pub enum CallbackAnswer {
+    /// Stop the loading indicator without showing anything.
+    Empty,
+    /// Show a localized message as a toast.
+    Toast(&'static str),
+    /// Show a localized message with arguments as a toast.
+    ToastWithArgs(&'static str, fluent::FluentArgs<'static>),
+    /// Show a localized message as an alert that must be dismissed.
+    Alert(&'static str),
+}
+
+/// Something that handles the buttons for one or more routes.
+#[async_trait]
+pub trait CallbackHandler: Send + Sync {
+    /// Routes of the callback data this handles.
+    fn routes(&self) -> &'static [&'static str];
+
+    /// Handle a button being pressed, returning how to answer it.
+    async fn handle_callback(
+        &self,
+        handler: &MessageHandler,
+        callback_query: &CallbackQuery,
+        data: &CallbackData,
+    ) -> anyhow::Result<CallbackAnswer>;
+}
+
+/// Dispatches callback queries to the handler registered for their route and
+/// answers them.
+pub struct CallbackRouter {
+    routes: HashMap<&'static str, Arc<dyn CallbackHandler>>,
+}
+
+impl CallbackRouter {
+    pub fn new() -> Self {
+        Self {
+            routes: HashMap::new(),
+        }
+    }
+
+    /// Register a handler for each of its routes.
+    pub fn register<H: CallbackHandler + 'static>(mut self, callback_handler: H) -> Self {
+        let callback_handler: Arc<dyn CallbackHandler> = Arc::new(callback_handler);
+
+        for route in callback_handler.routes() {
+            if self
+                .routes
+                .insert(route, callback_handler.clone())
+                .is_some()
+            {
+                panic!("callback route {} was registered twice", route);
+            }
+        }
+
+        self
+    }
+}
+
+#[async_trait]
+impl Handler for CallbackRouter {
+    fn name(&self) -> &'static str {
+        "callback"
+    }
+
+    async fn handle(&self, handler: &MessageHandler, update: &Update) -> anyhow::Result<Status> {
+        let callback_query = needs_field!(update, callback_query);
+        let data = needs_field!(callback_query, data);
+
+        // Anything unknown is left for handlers that haven't been moved to
+        // routes yet.
+        let data = match CallbackData::decode(data) {
+            Some(data) => data,
+            None => return Ok(Ignored),
+        };
+        let callback_handler = match self.routes.get(data.route.as_str()) {
+            Some(callback_handler) => callback_handler,
+            None => return Ok(Ignored),
+        };
+
+        tracing::debug!(route = %data.route, "handling callback query");
+
+        let answer = callback_handler
+            .handle_callback(handler, callback_query, &data)
+            .await?;
+
+        let (text, show_alert) = match answer {
+            CallbackAnswer::Empty => (None, None),
+            CallbackAnswer::Toast(name) => (Some((name, None)), None),
+            CallbackAnswer::ToastWithArgs(name, args) => (Some((name, Some(args))), None),
+            CallbackAnswer::Alert(name) => (Some((name, None)), Some(true)),
+        };
+
+        let text = match text {
+            Some((name, args)) => Some(
+                handler
+                    .get_fluent_bundle(callback_query.from.language_code.as_deref(), |bundle| {
+                        get_message(bundle, name, args).unwrap()
+                    })
+                    .await,
+            ),
+            None => None,
+        };
+
+        let answer = AnswerCallbackQuery {
+            callback_query_id: callback_query.id.clone(),
+            text,
+            show_alert,
+            ..Default::default()
+        };
+
+        handler
+            .make_request(&answer)
+            .await
+            .with_context(|| format!("unable to answer {} callback query", data.route))?;
+
+        Ok(Completed)
+    }
+}
@@ -1,11 +1,13 @@
+use anyhow::Context;
 use async_trait::async_trait;
-use tgbotapi::*;
+use tgbotapi::{requests::SendMessage, *};
 
 use super::{
     Handler,
     Status::{self, *},
 };
 use crate::MessageHandler;
+use foxbot_models::{CompanionReplies, GroupConfig, GroupConfigKey, SourceTemplate};
 use foxbot_utils::*;
 
 pub struct ChannelPhotoHandler;
@@ -16,34 +18,117 @@ impl Handler for ChannelPhotoHandler {
         "channel"
     }
 
-    async fn handle(
-        &self,
-        handler: &MessageHandler,
-        update: &Update,
-        _command: Option<&Command>,
-    ) -> anyhow::Result<Status> {
-        // Ensure we have a channel_post Message and a photo within.
+    async fn handle(&self, handler: &MessageHandler, update: &Update) -> anyhow::Result<Status> {
+        // Ensure we have a channel_post Message and a photo or image document
+        // within.
         let message = needs_field!(update, channel_post);
-        needs_field!(&message, photo);
+        let job_message = match payload::JobMessage::new(message, handler.download_limit()) {
+            Some(job_message) => job_message,
+            None => return Ok(Ignored),
+        };
 
         potential_return!(initial_filter(message));
 
-        let custom = get_faktory_custom();
+        let can_edit = can_edit_in_channel(
+            &handler.bot,
+            &handler.conn,
+            message.chat.id,
+            handler.bot_user.id,
+            false,
+        )
+        .await?;
+
+        if !can_edit {
+            tracing::debug!("missing permission to edit channel posts");
+
+            if let Err(err) = notify_missing_permission(handler, &message.chat).await {
+                tracing::warn!("unable to notify about missing permission: {:?}", err);
+            }
+
+            // Discussion replies don't need to edit the post, so they can
+            // still be sent.
+            let template: SourceTemplate = GroupConfig::get(
+                &handler.conn,
+                message.chat.id,
+                GroupConfigKey::SourceTemplate,
+            )
+            .await?
+            .unwrap_or_default();
+
+            if template.companion == CompanionReplies::Off {
+                return Ok(Completed);
+            }
+        }
+
+        let message = payload::encode(&job_message)?;
+        let mut job = faktory::Job::new("channel_update", vec![message]).on_queue(QUEUE_DEFAULT);
+        job.custom = get_faktory_custom();
+
+        handler.enqueue(job).await?;
+
+        Ok(Completed)
+    }
+}
 
-        let faktory = handler.faktory.clone();
-        let message = message.to_owned();
-        tokio::task::spawn_blocking(move || {
-            let mut faktory = faktory.lock().unwrap();
-            let message = serde_json::to_value(&message).unwrap();
-            let mut job =
-                faktory::Job::new("channel_update", vec![message]).on_queue("foxbot_background");
-            job.custom = custom;
+/// Let a channel's administrators know that the bot is unable to edit posts,
+/// only the first time it is noticed after losing the permission.
+async fn notify_missing_permission(handler: &MessageHandler, chat: &Chat) -> anyhow::Result<()> {
+    let notified: Option<bool> = GroupConfig::get(
+        &handler.conn,
+        chat.id,
+        GroupConfigKey::EditPermissionNotified,
+    )
+    .await?;
+    if notified.unwrap_or(false) {
+        return Ok(());
+    }
 
-            faktory.enqueue(job).unwrap();
-        });
+    GroupConfig::set(
+        &handler.conn,
+        GroupConfigKey::EditPermissionNotified,
+        chat.id,
+        true,
+    )
+    .await?;
+
+    let get_chat_administrators = requests::GetChatAdministrators {
+        chat_id: chat.id.into(),
+    };
+    let admins = handler
+        .make_request(&get_chat_administrators)
+        .await
+        .context("unable to get channel administrators")?;
+
+    let title = chat.title.clone().unwrap_or_default();
+
+    for admin in admins.into_iter().filter(|admin| !admin.user.is_bot) {
+        let text = handler
+            .get_fluent_bundle(admin.user.language_code.as_deref(), |bundle| {
+                let mut args = fluent::FluentArgs::new();
+                args.insert("title", title.clone().into());
+
+                get_message(bundle, "channel-missing-edit-permission", Some(args)).unwrap()
+            })
+            .await;
+
+        let send_message = SendMessage {
+            chat_id: admin.user.id.into(),
+            text,
+            ..Default::default()
+        };
 
-        Ok(Completed)
+        // Administrators who never started a chat with the bot can't be
+        // messaged.
+        if let Err(err) = handler.make_request(&send_message).await {
+            tracing::debug!(
+                user_id = admin.user.id,
+                "unable to notify administrator: {:?}",
+                err
+            );
+        }
     }
+
+    Ok(())
 }
 
 /// Filter updates to ignore any non-channel type messages and flag completed
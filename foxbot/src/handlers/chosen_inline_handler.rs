@@ -1,11 +1,14 @@
+use anyhow::Context;
 use async_trait::async_trait;
 use tgbotapi::*;
 
 use super::{
+    inline_handler::site_from_result_id,
     Handler,
     Status::{self, *},
 };
 use crate::MessageHandler;
+use foxbot_models::InlineStats;
 use foxbot_utils::*;
 
 pub struct ChosenInlineHandler;
@@ -16,16 +19,21 @@ impl Handler for ChosenInlineHandler {
         "chosen"
     }
 
-    async fn handle(
-        &self,
-        _handler: &MessageHandler,
-        update: &Update,
-        _command: Option<&Command>,
-    ) -> anyhow::Result<Status> {
-        let _chosen_result = needs_field!(update, chosen_inline_result);
+    async fn handle(&self, handler: &MessageHandler, update: &Update) -> anyhow::Result<Status> {
+        let chosen_result = needs_field!(update, chosen_inline_result);
 
-        // This doesn't need to do anything, returning completed will count it
-        // as a chosen inline handler already.
+        // Results without a site, such as informational articles, aren't
+        // useful for usage statistics.
+        let site = match site_from_result_id(&chosen_result.result_id) {
+            Some(site) => site,
+            None => return Ok(Completed),
+        };
+
+        tracing::debug!(site, "user chose inline result");
+
+        InlineStats::record(&handler.conn, chosen_result.from.id, site)
+            .await
+            .context("unable to record chosen inline result")?;
 
         Ok(Completed)
     }
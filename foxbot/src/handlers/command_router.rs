@@ -0,0 +1,529 @@
+use std::{collections::HashMap, str::FromStr, sync::Arc};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use tgbotapi::{requests::SendMessage, ChatType, Message, Update};
+
+use super::{
+    permissions::is_chat_admin,
+    Handler,
+    Status::{self, Completed, Ignored},
+};
+use crate::MessageHandler;
+use foxbot_utils::{
+    bot_api::{BotCommand, BotCommandScope, SetMyCommands},
+    get_message,
+    l10n::Localization,
+    needs_field,
+};
+
+lazy_static::lazy_static! {
+    static ref USED_COMMANDS: prometheus::HistogramVec = prometheus::register_histogram_vec!("foxbot_commands_duration_seconds", "Processing duration for each command", &["command"]).unwrap();
+}
+
+/// Who may use a command, which also decides where it is listed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CommandScope {
+    /// Anyone, in any chat.
+    Any,
+    /// Anyone, in a private chat with the bot.
+    Private,
+    /// Administrators of the group the command was sent in.
+    GroupAdmin,
+    /// Administrators of the bot, who are the only ones it is listed for.
+    BotAdmin,
+}
+
+/// What an argument must look like.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArgKind {
+    /// Any single word.
+    Word,
+    /// A whole number.
+    Number,
+    /// A channel username like `@channel`, or a chat ID.
+    Channel,
+    /// Everything left in the message.
+    Rest,
+}
+
+/// An argument a command accepts, in the order they must be given.
+#[derive(Debug)]
+pub struct CommandArg {
+    pub name: &'static str,
+    pub kind: ArgKind,
+    pub optional: bool,
+}
+
+impl CommandArg {
+    pub const fn required(name: &'static str, kind: ArgKind) -> Self {
+        Self {
+            name,
+            kind,
+            optional: false,
+        }
+    }
+
+    pub const fn optional(name: &'static str, kind: ArgKind) -> Self {
+        Self {
+            name,
+            kind,
+            optional: true,
+        }
+    }
+}
+
+/// A command that can be sent to the bot.
+#[derive(Debug)]
+pub struct CommandSpec {
+    /// Name of the command, including the leading slash.
+    pub name: &'static str,
+    /// Other names that run the same command.
+    pub aliases: &'static [&'static str],
+    /// Name of the localized message describing the command.
+    pub description: &'static str,
+    pub args: &'static [CommandArg],
+    pub scope: CommandScope,
+}
+
+impl CommandSpec {
+    /// How the command is used, like `/backfill <channel> <first> <last>`.
+    pub fn usage(&self) -> String {
+        let mut usage = self.name.to_string();
+
+        for arg in self.args {
+            let name = match arg.kind {
+                ArgKind::Rest => format!("{}...", arg.name),
+                _ => arg.name.to_string(),
+            };
+
+            usage.push(' ');
+            if arg.optional {
+                usage.push_str(&format!("[{}]", name));
+            } else {
+                usage.push_str(&format!("<{}>", name));
+            }
+        }
+
+        usage
+    }
+}
+
+/// Arguments given to a command, by name.
+#[derive(Debug, Default)]
+pub struct CommandArgs(HashMap<&'static str, String>);
+
+impl CommandArgs {
+    /// Parse the words after a command for its arguments.
+    ///
+    /// Optional arguments are skipped when the next word doesn't fit them,
+    /// and any extra words are ignored. Returns `None` if a required argument
+    /// was missing.
+    pub fn parse(args: &'static [CommandArg], text: &str) -> Option<Self> {
+        let mut words = text.split_whitespace().skip(1).peekable();
+        let mut parsed = HashMap::new();
+
+        for arg in args {
+            match words.peek() {
+                Some(word) if arg_fits(arg.kind, word) => {
+                    let value = if arg.kind == ArgKind::Rest {
+                        words.by_ref().collect::<Vec<_>>().join(" ")
+                    } else {
+                        words.next().unwrap_or_default().to_string()
+                    };
+
+                    parsed.insert(arg.name, value);
+                }
+                _ if arg.optional => continue,
+                _ => return None,
+            }
+        }
+
+        Some(Self(parsed))
+    }
+
+    /// Get the text of an argument.
+    pub fn text(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(String::as_str)
+    }
+
+    /// Parse the value of an argument.
+    pub fn get<T: FromStr>(&self, name: &str) -> Option<T> {
+        self.text(name)?.parse().ok()
+    }
+}
+
+fn arg_fits(kind: ArgKind, word: &str) -> bool {
+    match kind {
+        ArgKind::Word | ArgKind::Rest => true,
+        ArgKind::Number => word.parse::<i64>().is_ok(),
+        ArgKind::Channel => word.starts_with('@') || word.starts_with('-'),
+    }
+}
+
+/// Something that handles one or more commands.
+#[async_trait]
+pub trait CommandHandler: Send + Sync {
+    /// Commands this handles.
+    fn commands(&self) -> &'static [CommandSpec];
+
+    /// Run a command, given by its name instead of the alias that was used.
+    async fn handle_command(
+        &self,
+        handler: &MessageHandler,
+        message: &Message,
+        command: &str,
+        args: &CommandArgs,
+    ) -> anyhow::Result<()>;
+}
+
+/// The command listing every command someone can use.
+static HELP: CommandSpec = CommandSpec {
+    name: "/help",
+    aliases: &[],
+    description: "command-help",
+    args: &[],
+    scope: CommandScope::Any,
+};
+
+/// Dispatches commands to the handler registered for them after checking
+/// who sent them and their arguments, and builds the list of commands.
+pub struct CommandRouter {
+    commands: HashMap<&'static str, (&'static CommandSpec, Option<Arc<dyn CommandHandler>>)>,
+    specs: Vec<&'static CommandSpec>,
+}
+
+impl CommandRouter {
+    pub fn new() -> Self {
+        let mut router = Self {
+            commands: HashMap::new(),
+            specs: vec![],
+        };
+        router.add(&HELP, None);
+
+        router
+    }
+
+    /// Register a handler for each of its commands.
+    pub fn register<H: CommandHandler + 'static>(mut self, command_handler: H) -> Self {
+        let command_handler: Arc<dyn CommandHandler> = Arc::new(command_handler);
+
+        for spec in command_handler.commands() {
+            self.add(spec, Some(command_handler.clone()));
+        }
+
+        self
+    }
+
+    fn add(
+        &mut self,
+        spec: &'static CommandSpec,
+        command_handler: Option<Arc<dyn CommandHandler>>,
+    ) {
+        for name in std::iter::once(&spec.name).chain(spec.aliases) {
+            if self
+                .commands
+                .insert(*name, (spec, command_handler.clone()))
+                .is_some()
+            {
+                panic!("command {} was registered twice", name);
+            }
+        }
+
+        self.specs.push(spec);
+    }
+
+    /// Update the menu of commands for each scope and language.
+    pub async fn set_my_commands(
+        &self,
+        bot: &tgbotapi::Telegram,
+        l10n: &Localization,
+    ) -> anyhow::Result<()> {
+        use CommandScope::*;
+
+        // Chats use the most specific list that applies to them, so each
+        // list includes the commands anyone may use.
+        let menus: &[(BotCommandScope, &[CommandScope])] = &[
+            (BotCommandScope::Default, &[Any]),
+            (BotCommandScope::AllPrivateChats, &[Any, Private]),
+            (BotCommandScope::AllChatAdministrators, &[Any, GroupAdmin]),
+        ];
+
+        for (index, lang) in l10n.languages().iter().enumerate() {
+            let bundle = l10n.bundle(lang);
+
+            // Telegram uses two letter language codes. The first language is
+            // also used for anyone whose language isn't known.
+            let language_code = lang.split('-').next().map(str::to_string);
+            let language_codes = if index == 0 {
+                vec![None, language_code]
+            } else {
+                vec![language_code]
+            };
+
+            for (scope, scopes) in menus {
+                let commands: Vec<_> = self
+                    .specs
+                    .iter()
+                    .filter(|spec| scopes.contains(&spec.scope))
+                    .map(|spec| BotCommand {
+                        command: spec.name.trim_start_matches('/').to_string(),
+                        description: get_message(&bundle, spec.description, None).unwrap(),
+                    })
+                    .collect();
+
+                for language_code in &language_codes {
+                    let set_my_commands = SetMyCommands {
+                        commands: commands.clone(),
+                        scope: *scope,
+                        language_code: language_code.clone(),
+                    };
+
+                    bot.make_request(&set_my_commands)
+                        .await
+                        .with_context(|| format!("unable to set commands for {:?}", scope))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check if someone may use a command, explaining why not if they can't.
+    async fn allowed(
+        &self,
+        handler: &MessageHandler,
+        message: &Message,
+        spec: &CommandSpec,
+    ) -> anyhow::Result<bool> {
+        let user = message.from.as_ref().context("command missing user")?;
+
+        let denied = match spec.scope {
+            CommandScope::Any => None,
+            CommandScope::Private if message.chat.chat_type != ChatType::Private => {
+                Some("command-private")
+            }
+            CommandScope::Private => None,
+            CommandScope::GroupAdmin if !message.chat.chat_type.is_group() => {
+                Some("automatic-enable-not-group")
+            }
+            CommandScope::GroupAdmin => {
+                if is_chat_admin(handler, message.chat.id, user.id).await? {
+                    None
+                } else {
+                    Some("automatic-enable-not-admin")
+                }
+            }
+            CommandScope::BotAdmin => {
+                if !handler.is_bot_admin(user.id) {
+                    tracing::info!(
+                        user_id = user.id,
+                        command = spec.name,
+                        "non-admin attempted command"
+                    );
+                    return Ok(false);
+                }
+
+                None
+            }
+        };
+
+        match denied {
+            Some(name) => {
+                handler.send_generic_reply(message, name).await?;
+                Ok(false)
+            }
+            None => Ok(true),
+        }
+    }
+
+    /// Reply with how to use a command when its arguments were wrong.
+    async fn send_usage(
+        &self,
+        handler: &MessageHandler,
+        message: &Message,
+        spec: &CommandSpec,
+    ) -> anyhow::Result<()> {
+        let lang = message
+            .from
+            .as_ref()
+            .and_then(|from| from.language_code.as_deref());
+
+        let text = handler
+            .get_fluent_bundle(lang, |bundle| {
+                let mut args = fluent::FluentArgs::new();
+                args.insert("usage", spec.usage().into());
+
+                get_message(bundle, "command-usage", Some(args)).unwrap()
+            })
+            .await;
+
+        let send_message = SendMessage {
+            chat_id: message.chat_id(),
+            text,
+            reply_to_message_id: Some(message.message_id),
+            ..Default::default()
+        };
+
+        handler.make_request(&send_message).await?;
+
+        Ok(())
+    }
+
+    /// List the commands someone can use in the chat they asked in.
+    async fn send_help(&self, handler: &MessageHandler, message: &Message) -> anyhow::Result<()> {
+        let user = message.from.as_ref().context("help missing user")?;
+        let is_bot_admin = handler.is_bot_admin(user.id);
+
+        let specs = self.specs.iter().filter(|spec| match spec.scope {
+            CommandScope::Any => true,
+            CommandScope::Private => message.chat.chat_type == ChatType::Private,
+            CommandScope::GroupAdmin => message.chat.chat_type.is_group(),
+            CommandScope::BotAdmin => is_bot_admin,
+        });
+
+        let text = handler
+            .get_fluent_bundle(user.language_code.as_deref(), |bundle| {
+                let mut buf = get_message(bundle, "help-title", None).unwrap();
+
+                for spec in specs {
+                    let mut args = fluent::FluentArgs::new();
+                    args.insert("usage", spec.usage().into());
+                    args.insert(
+                        "description",
+                        get_message(bundle, spec.description, None).unwrap().into(),
+                    );
+
+                    buf.push('\n');
+                    buf.push_str(&get_message(bundle, "help-command", Some(args)).unwrap());
+                }
+
+                buf
+            })
+            .await;
+
+        let send_message = SendMessage {
+            chat_id: message.chat_id(),
+            text,
+            reply_to_message_id: if message.chat.chat_type.is_group() {
+                Some(message.message_id)
+            } else {
+                None
+            },
+            ..Default::default()
+        };
+
+        handler.make_request(&send_message).await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Handler for CommandRouter {
+    fn name(&self) -> &'static str {
+        "command"
+    }
+
+    async fn handle(&self, handler: &MessageHandler, update: &Update) -> anyhow::Result<Status> {
+        let message = needs_field!(update, message);
+
+        let command = match message.get_command() {
+            Some(command) => command,
+            None => return Ok(Ignored),
+        };
+
+        if let Some(username) = command.username {
+            let bot_username = handler.bot_user.username.as_ref().unwrap();
+            if username.to_lowercase() != bot_username.to_lowercase() {
+                tracing::debug!(?username, "got command for other bot");
+                return Ok(Ignored);
+            }
+        }
+
+        let (spec, command_handler) = match self.commands.get(command.name.as_str()) {
+            Some(command) => command,
+            None => {
+                tracing::info!(command = ?command.name, "unknown command");
+                return Ok(Ignored);
+            }
+        };
+
+        let _hist = USED_COMMANDS
+            .get_metric_with_label_values(&[spec.name])
+            .unwrap()
+            .start_timer();
+        tracing::debug!(command = spec.name, "got command");
+
+        if !self.allowed(handler, message, spec).await? {
+            return Ok(Completed);
+        }
+
+        let text = message.text.as_deref().unwrap_or_default();
+        let args = match CommandArgs::parse(spec.args, text) {
+            Some(args) => args,
+            None => {
+                self.send_usage(handler, message, spec).await?;
+                return Ok(Completed);
+            }
+        };
+
+        match command_handler {
+            Some(command_handler) => {
+                command_handler
+                    .handle_command(handler, message, spec.name, &args)
+                    .await?
+            }
+            None => self.send_help(handler, message).await?,
+        }
+
+        Ok(Completed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ArgKind, CommandArg, CommandArgs, CommandScope, CommandSpec};
+
+    static ARGS: &[CommandArg] = &[
+        CommandArg::optional("channel", ArgKind::Channel),
+        CommandArg::required("count", ArgKind::Number),
+        CommandArg::optional("note", ArgKind::Rest),
+    ];
+
+    #[test]
+    fn test_parse_args() {
+        let args = CommandArgs::parse(ARGS, "/test @channel 5 some words").unwrap();
+        assert_eq!(args.text("channel"), Some("@channel"));
+        assert_eq!(args.get::<i32>("count"), Some(5));
+        assert_eq!(args.text("note"), Some("some words"));
+
+        let args = CommandArgs::parse(ARGS, "/test 5").unwrap();
+        assert_eq!(
+            args.text("channel"),
+            None,
+            "optional arguments that don't fit should be skipped"
+        );
+        assert_eq!(args.get::<i32>("count"), Some(5));
+        assert_eq!(args.text("note"), None);
+
+        assert!(
+            CommandArgs::parse(ARGS, "/test @channel").is_none(),
+            "required arguments must be given"
+        );
+        assert!(CommandArgs::parse(ARGS, "/test five").is_none());
+    }
+
+    #[test]
+    fn test_usage() {
+        let spec = CommandSpec {
+            name: "/test",
+            aliases: &[],
+            description: "command-test",
+            args: ARGS,
+            scope: CommandScope::Any,
+        };
+
+        assert_eq!(spec.usage(), "/test [channel] <count> [note...]");
+    }
+}
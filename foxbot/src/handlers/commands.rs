@@ -4,74 +4,243 @@ use std::collections::HashMap;
 use tgbotapi::{requests::*, *};
 
 use super::{
-    Handler,
-    Status::{self, *},
+    inline_handler::send_share_prompt, permissions::is_chat_admin, ArgKind, CommandArg,
+    CommandArgs, CommandHandler, CommandScope, CommandSpec,
 };
 use crate::MessageHandler;
-use foxbot_models::{ChatAdmin, GroupConfig, GroupConfigKey};
-use foxbot_sites::PostInfo;
+use foxbot_models::{
+    ActionKind, Actions, ChatAdmin, CompanionReplies, FailedJobs, GroupConfig, GroupConfigKey,
+    InlineStats, SourceStyle, SourceTemplate,
+};
+use foxbot_sites::{credentials::UserCredentials, PostInfo};
 use foxbot_utils::*;
 
 // TODO: there's a lot of shared code between these commands.
 
-lazy_static::lazy_static! {
-    static ref USED_COMMANDS: prometheus::HistogramVec = prometheus::register_histogram_vec!("foxbot_commands_duration_seconds", "Processing duration for each command", &["command"]).unwrap();
+/// Number of days of inline usage to include in `/stats`.
+const STATS_DAYS: i32 = 30;
+
+/// Number of scheduled jobs to list in `/pendingedits`.
+const PENDING_EDITS_SHOWN: isize = 10;
+
+/// Number of recent actions to list in `/history`.
+const HISTORY_SHOWN: i64 = 10;
+
+/// Maximum number of channel messages that can be backfilled at once.
+const MAX_BACKFILL_MESSAGES: i32 = 5_000;
+
+/// Initial state of a `channel_backfill` job, matching the background
+/// worker's payload.
+#[derive(serde::Serialize)]
+struct ChannelBackfill {
+    channel_id: i64,
+    next_message_id: i32,
+    last_message_id: i32,
+    user_chat_id: i64,
+    progress_message_id: i32,
+    lang: Option<String>,
+}
+
+/// An optional channel as the first argument, for commands that work in
+/// groups or for managed channels.
+const CHANNEL_ARG: CommandArg = CommandArg::optional("channel", ArgKind::Channel);
+
+static COMMANDS: &[CommandSpec] = &[
+    command("/start", "command-start", &[], CommandScope::Any),
+    command(
+        "/mirror",
+        "command-mirror",
+        &[CommandArg::optional("links", ArgKind::Rest)],
+        CommandScope::Any,
+    ),
+    command("/source", "command-source", &[], CommandScope::Any),
+    command("/alts", "command-alts", &[], CommandScope::Any),
+    command(
+        "/groupsource",
+        "command-groupsource",
+        &[],
+        CommandScope::GroupAdmin,
+    ),
+    command(
+        "/grouppreviews",
+        "command-grouppreviews",
+        &[],
+        CommandScope::GroupAdmin,
+    ),
+    command(
+        "/groupspoilers",
+        "command-groupspoilers",
+        &[],
+        CommandScope::GroupAdmin,
+    ),
+    command(
+        "/groupsilent",
+        "command-groupsilent",
+        &[],
+        CommandScope::GroupAdmin,
+    ),
+    command(
+        "/grouprepost",
+        "command-grouprepost",
+        &[],
+        CommandScope::GroupAdmin,
+    ),
+    command(
+        "/sourcedistance",
+        "command-sourcedistance",
+        &[CHANNEL_ARG, CommandArg::optional("distance", ArgKind::Word)],
+        CommandScope::Any,
+    ),
+    command(
+        "/blocksites",
+        "command-blocksites",
+        &[CHANNEL_ARG, CommandArg::optional("sites", ArgKind::Rest)],
+        CommandScope::Any,
+    ),
+    command(
+        "/channelnetwork",
+        "command-channelnetwork",
+        &[CHANNEL_ARG, CommandArg::optional("name", ArgKind::Word)],
+        CommandScope::Any,
+    ),
+    command(
+        "/sourcetemplate",
+        "command-sourcetemplate",
+        &[
+            CHANNEL_ARG,
+            CommandArg::optional("setting", ArgKind::Word),
+            CommandArg::optional("value", ArgKind::Rest),
+        ],
+        CommandScope::Any,
+    ),
+    command(
+        "/backfill",
+        "command-backfill",
+        &[
+            CommandArg::required("channel", ArgKind::Channel),
+            CommandArg::required("first", ArgKind::Number),
+            CommandArg::required("last", ArgKind::Number),
+        ],
+        CommandScope::Private,
+    ),
+    command(
+        "/history",
+        "command-history",
+        &[CHANNEL_ARG],
+        CommandScope::Any,
+    ),
+    command("/undo", "command-undo", &[CHANNEL_ARG], CommandScope::Any),
+    command("/stats", "command-stats", &[], CommandScope::BotAdmin),
+    command(
+        "/retryjobs",
+        "command-retryjobs",
+        &[],
+        CommandScope::BotAdmin,
+    ),
+    command(
+        "/pendingedits",
+        "command-pendingedits",
+        &[],
+        CommandScope::BotAdmin,
+    ),
+    command("/error", "command-error", &[], CommandScope::BotAdmin),
+    command(
+        "/sitestatus",
+        "command-sitestatus",
+        &[],
+        CommandScope::BotAdmin,
+    ),
+    command(
+        "/reloadlangs",
+        "command-reloadlangs",
+        &[],
+        CommandScope::BotAdmin,
+    ),
+    command(
+        "/credential",
+        "command-credential",
+        &[
+            CommandArg::required("name", ArgKind::Word),
+            CommandArg::optional("value", ArgKind::Rest),
+        ],
+        CommandScope::BotAdmin,
+    ),
+];
+
+/// Longest name a channel network may have.
+const MAX_NETWORK_NAME_LEN: usize = 32;
+
+/// Check if a channel network name can be used. Names are part of Redis keys,
+/// so they may only contain letters, numbers, dashes, and underscores.
+fn is_valid_network_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_NETWORK_NAME_LEN
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+const fn command(
+    name: &'static str,
+    description: &'static str,
+    args: &'static [CommandArg],
+    scope: CommandScope,
+) -> CommandSpec {
+    CommandSpec {
+        name,
+        aliases: &[],
+        description,
+        args,
+        scope,
+    }
 }
 
-pub struct CommandHandler;
+/// Commands for using the bot that don't belong to a more specific handler.
+pub struct GeneralCommands;
 
 #[async_trait]
-impl Handler for CommandHandler {
-    fn name(&self) -> &'static str {
-        "command"
+impl CommandHandler for GeneralCommands {
+    fn commands(&self) -> &'static [CommandSpec] {
+        COMMANDS
     }
 
-    async fn handle(
+    async fn handle_command(
         &self,
         handler: &MessageHandler,
-        update: &Update,
-        _command: Option<&Command>,
-    ) -> anyhow::Result<Status> {
-        let message = needs_field!(update, message);
-
-        let command = match message.get_command() {
-            Some(command) => command,
-            None => return Ok(Ignored),
-        };
-
-        if let Some(username) = command.username {
-            let bot_username = handler.bot_user.username.as_ref().unwrap();
-            if username.to_lowercase() != bot_username.to_lowercase() {
-                tracing::debug!(?username, "got command for other bot");
-                return Ok(Ignored);
-            }
-        }
-
-        let _hist = USED_COMMANDS
-            .get_metric_with_label_values(&[command.name.as_ref()])
-            .unwrap()
-            .start_timer();
-        tracing::debug!(command = ?command.name, "got command");
-
-        match command.name.as_ref() {
-            "/help" | "/start" => handler.handle_welcome(message, &command.name).await,
+        message: &Message,
+        command: &str,
+        args: &CommandArgs,
+    ) -> anyhow::Result<()> {
+        match command {
+            "/start" => handler.handle_welcome(message).await,
             "/mirror" => self.handle_mirror(handler, message).await,
             "/source" => self.handle_source(handler, message).await,
             "/alts" => self.handle_alts(handler, message).await,
             "/error" => Err(anyhow::anyhow!("a test error message")),
             "/groupsource" => self.enable_group_source(handler, message).await,
             "/grouppreviews" => self.group_nopreviews(handler, message).await,
-            _ => {
-                tracing::info!(command = ?command.name, "unknown command");
-                return Ok(Ignored);
-            }
-        }?;
-
-        Ok(Completed)
+            "/groupspoilers" => self.group_spoilers(handler, message).await,
+            "/groupsilent" => self.group_silent(handler, message).await,
+            "/grouprepost" => self.group_repost(handler, message).await,
+            "/sourcedistance" => self.source_distance(handler, message, args).await,
+            "/blocksites" => self.blocked_sites(handler, message, args).await,
+            "/channelnetwork" => self.channel_network(handler, message, args).await,
+            "/sourcetemplate" => self.source_template(handler, message, args).await,
+            "/backfill" => self.backfill(handler, message, args).await,
+            "/stats" => self.handle_stats(handler, message).await,
+            "/retryjobs" => self.retry_jobs(handler, message).await,
+            "/pendingedits" => self.pending_edits(handler, message).await,
+            "/sitestatus" => self.site_status(handler, message).await,
+            "/credential" => self.credential(handler, message, args).await,
+            "/reloadlangs" => self.reload_langs(handler, message).await,
+            "/history" => self.history(handler, message, args).await,
+            "/undo" => self.undo(handler, message, args).await,
+            _ => unreachable!("unhandled command {}", command),
+        }
     }
 }
 
-impl CommandHandler {
+impl GeneralCommands {
     async fn handle_mirror(
         &self,
         handler: &MessageHandler,
@@ -104,22 +273,37 @@ impl CommandHandler {
             return Ok(());
         }
 
+        let credentials = UserCredentials::new(handler.conn.clone(), from.id);
         let mut results: Vec<PostInfo> = Vec::with_capacity(links.len());
 
-        let mut missing = {
+        let missing = {
             let mut sites = handler.sites.lock().await;
-            find_images(from, links, &mut sites, &mut |info| {
+            find_images(&credentials, links, &mut sites, &mut |info| {
                 results.extend(info.results);
             })
-            .await?
+            .await
         };
+        let mut missing = super::bans::check_rate_limited(handler, from.id, missing).await?;
 
         drop(action);
 
+        // Results from locked accounts or posts that aren't public are never
+        // mirrored into chats other people can see.
+        let mut hid_personal = false;
+        if message.chat.chat_type != ChatType::Private {
+            let count = results.len();
+            results.retain(|result| !result.personal);
+            hid_personal = results.len() != count;
+        }
+
         if results.is_empty() {
-            handler
-                .send_generic_reply(message, "mirror-no-results")
-                .await?;
+            let name = if hid_personal {
+                "mirror-personal"
+            } else {
+                "mirror-no-results"
+            };
+
+            handler.send_generic_reply(message, name).await?;
             return Ok(());
         }
 
@@ -128,6 +312,12 @@ impl CommandHandler {
         // link next to each other.
         results.dedup_by(|a, b| a.source_link == b.source_link && a.url == b.url);
 
+        // Chats may opt in to hiding NSFW media behind a spoiler.
+        let use_spoilers =
+            GroupConfig::get(&handler.conn, message.chat.id, GroupConfigKey::NsfwSpoilers)
+                .await?
+                .unwrap_or(false);
+
         if results.len() == 1 {
             let action = continuous_action(
                 handler.bot.clone(),
@@ -145,6 +335,7 @@ impl CommandHandler {
                     caption: result.source_link.clone(),
                     video: FileType::Url(result.url.clone()),
                     reply_to_message_id: Some(message.message_id),
+                    has_spoiler: Some(use_spoilers && result.is_nsfw()),
                     ..Default::default()
                 };
 
@@ -157,12 +348,14 @@ impl CommandHandler {
                     caption: result.source_link.clone(),
                     photo: file_type,
                     reply_to_message_id: Some(message.message_id),
+                    has_spoiler: Some(use_spoilers && result.is_nsfw()),
                     ..Default::default()
                 };
 
                 drop(action);
 
-                handler.make_request(&photo).await?;
+                let sent = handler.make_request(&photo).await?;
+                handler.remember_sent_media(result, &sent).await;
             } else {
                 missing.push(result.source_link.as_deref().unwrap_or(&result.url));
             }
@@ -177,12 +370,14 @@ impl CommandHandler {
                 );
 
                 let mut media = Vec::with_capacity(chunk.len());
+                let mut sent_results = Vec::with_capacity(chunk.len());
 
                 for result in chunk {
                     let input = match result.file_type.as_ref() {
                         "mp4" => InputMedia::Video(InputMediaVideo {
                             media: FileType::Url(result.url.to_owned()),
                             caption: result.source_link.clone(),
+                            has_spoiler: Some(use_spoilers && result.is_nsfw()),
                             ..Default::default()
                         }),
                         _ => {
@@ -190,6 +385,7 @@ impl CommandHandler {
                                 InputMedia::Photo(InputMediaPhoto {
                                     media: file_type,
                                     caption: result.source_link.clone(),
+                                    has_spoiler: Some(use_spoilers && result.is_nsfw()),
                                     ..Default::default()
                                 })
                             } else {
@@ -200,6 +396,7 @@ impl CommandHandler {
                     };
 
                     media.push(input);
+                    sent_results.push(result);
                 }
 
                 let media_group = SendMediaGroup {
@@ -209,12 +406,28 @@ impl CommandHandler {
                     ..Default::default()
                 };
 
-                handler.make_request(&media_group).await?;
+                let sent = handler.make_request(&media_group).await?;
 
                 drop(action);
+
+                for (result, message) in sent_results.into_iter().zip(sent.iter()) {
+                    handler.remember_sent_media(result, message).await;
+                }
             }
         }
 
+        // Mirroring in a private chat is a way to check results before
+        // sharing them, so offer to share the same results anywhere.
+        if message.chat.chat_type == ChatType::Private {
+            send_share_prompt(
+                handler,
+                message.chat_id(),
+                from.language_code.as_deref(),
+                &results,
+            )
+            .await?;
+        }
+
         if !missing.is_empty() {
             let links: Vec<String> = missing.iter().map(|item| format!("· {}", item)).collect();
             let mut args = fluent::FluentArgs::new();
@@ -323,6 +536,7 @@ impl CommandHandler {
             &handler.fapi,
             best_photo,
             Some(3),
+            Some(message.chat.id),
         )
         .await?
         .1;
@@ -390,6 +604,7 @@ impl CommandHandler {
                 &handler.fapi,
                 best_photo,
                 Some(10),
+                Some(message.chat.id),
             )
             .await?
         } else {
@@ -400,14 +615,16 @@ impl CommandHandler {
 
             let links = extract_links(message);
 
+            let credentials = UserCredentials::new(handler.conn.clone(), from.id);
             let mut results: Vec<PostInfo> = Vec::with_capacity(links.len());
             let missing = {
                 let mut sites = handler.sites.lock().await;
-                find_images(from, links, &mut sites, &mut |info| {
+                find_images(&credentials, links, &mut sites, &mut |info| {
                     results.extend(info.results);
                 })
-                .await?
+                .await
             };
+            let missing = super::bans::check_rate_limited(handler, from.id, missing).await?;
 
             if results.len() + missing.len() > 1 {
                 drop(action);
@@ -427,8 +644,7 @@ impl CommandHandler {
                 let bytes = CheckFileSize::new(&result.url, 20_000_000)
                     .into_bytes()
                     .await?;
-                let hash =
-                    tokio::task::spawn_blocking(move || fuzzysearch::hash_bytes(&bytes)).await??;
+                let hash = decode::hash_bytes(bytes).await?;
 
                 (
                     hash,
@@ -560,14 +776,13 @@ impl CommandHandler {
             .map_err(Into::into)
     }
 
+    /// Check if a command was sent in a group by one of its administrators,
+    /// for commands that may also be used elsewhere.
     async fn is_valid_admin_group(
         &self,
         handler: &MessageHandler,
         message: &Message,
-        bot_needs_admin: bool,
     ) -> anyhow::Result<bool> {
-        use tgbotapi::ChatMemberStatus::*;
-
         if !message.chat.chat_type.is_group() {
             handler
                 .send_generic_reply(message, "automatic-enable-not-group")
@@ -577,35 +792,29 @@ impl CommandHandler {
 
         let user = message.from.as_ref().unwrap();
 
-        // As of the Bot API 5.1, Telegram can now proactively send updates
-        // about user information. There is another handler that listens for
-        // these changes and saves them to the database. When possible we should
-        // use these saved values instead of making more requests.
-
-        let user_is_admin =
-            match ChatAdmin::is_admin(&handler.conn, user.id, message.chat.id).await? {
-                Some(is_admin) => is_admin,
-                _ => {
-                    let get_chat_member = GetChatMember {
-                        chat_id: message.chat_id(),
-                        user_id: user.id,
-                    };
-                    let chat_member = handler.make_request(&get_chat_member).await?;
-
-                    matches!(chat_member.status, Administrator | Creator)
-                }
-            };
-
-        if !user_is_admin {
+        if !is_chat_admin(handler, message.chat.id, user.id).await? {
             handler
                 .send_generic_reply(message, "automatic-enable-not-admin")
                 .await?;
             return Ok(false);
         }
 
-        if !bot_needs_admin {
-            return Ok(true);
-        }
+        Ok(true)
+    }
+
+    /// Check if the bot is an administrator of the group a command was sent
+    /// in, which it must be to see every message.
+    async fn bot_is_group_admin(
+        &self,
+        handler: &MessageHandler,
+        message: &Message,
+    ) -> anyhow::Result<bool> {
+        use tgbotapi::ChatMemberStatus::*;
+
+        // As of the Bot API 5.1, Telegram can now proactively send updates
+        // about user information. There is another handler that listens for
+        // these changes and saves them to the database. When possible we should
+        // use these saved values instead of making more requests.
 
         let bot_is_admin =
             match ChatAdmin::is_admin(&handler.conn, handler.bot_user.id, message.chat.id).await? {
@@ -646,7 +855,7 @@ impl CommandHandler {
         handler: &MessageHandler,
         message: &Message,
     ) -> anyhow::Result<()> {
-        if !self.is_valid_admin_group(handler, message, true).await? {
+        if !self.bot_is_group_admin(handler, message).await? {
             return Ok(());
         }
 
@@ -678,10 +887,6 @@ impl CommandHandler {
         handler: &MessageHandler,
         message: &Message,
     ) -> anyhow::Result<()> {
-        if !self.is_valid_admin_group(handler, message, false).await? {
-            return Ok(());
-        }
-
         let result = GroupConfig::get(
             &handler.conn,
             message.chat.id,
@@ -708,4 +913,1200 @@ impl CommandHandler {
 
         Ok(())
     }
+
+    async fn group_spoilers(
+        &self,
+        handler: &MessageHandler,
+        message: &Message,
+    ) -> anyhow::Result<()> {
+        let enabled =
+            GroupConfig::get(&handler.conn, message.chat.id, GroupConfigKey::NsfwSpoilers)
+                .await?
+                .unwrap_or(false);
+
+        GroupConfig::set(
+            &handler.conn,
+            GroupConfigKey::NsfwSpoilers,
+            message.chat.id,
+            !enabled,
+        )
+        .await?;
+
+        let name = if !enabled {
+            "automatic-spoilers-enable"
+        } else {
+            "automatic-spoilers-disable"
+        };
+
+        handler.send_generic_reply(message, name).await?;
+
+        Ok(())
+    }
+
+    async fn group_silent(
+        &self,
+        handler: &MessageHandler,
+        message: &Message,
+    ) -> anyhow::Result<()> {
+        let enabled = GroupConfig::get(
+            &handler.conn,
+            message.chat.id,
+            GroupConfigKey::SilentSourcing,
+        )
+        .await?
+        .unwrap_or(false);
+
+        GroupConfig::set(
+            &handler.conn,
+            GroupConfigKey::SilentSourcing,
+            message.chat.id,
+            !enabled,
+        )
+        .await?;
+
+        let name = if !enabled {
+            "automatic-silent-enable"
+        } else {
+            "automatic-silent-disable"
+        };
+
+        handler.send_generic_reply(message, name).await?;
+
+        Ok(())
+    }
+
+    async fn group_repost(
+        &self,
+        handler: &MessageHandler,
+        message: &Message,
+    ) -> anyhow::Result<()> {
+        let enabled = GroupConfig::get(
+            &handler.conn,
+            message.chat.id,
+            GroupConfigKey::RepostWarnings,
+        )
+        .await?
+        .unwrap_or(false);
+
+        GroupConfig::set(
+            &handler.conn,
+            GroupConfigKey::RepostWarnings,
+            message.chat.id,
+            !enabled,
+        )
+        .await?;
+
+        let name = if !enabled {
+            "automatic-repost-enable"
+        } else {
+            "automatic-repost-disable"
+        };
+
+        handler.send_generic_reply(message, name).await?;
+
+        Ok(())
+    }
+
+    /// Find the channel a command is referring to, ensuring the user is an
+    /// administrator of it.
+    ///
+    /// In private chats the channel must be provided as the first argument.
+    /// In a channel's discussion group, the linked channel is used.
+    async fn resolve_managed_channel(
+        &self,
+        handler: &MessageHandler,
+        message: &Message,
+        channel: Option<&str>,
+    ) -> anyhow::Result<Option<Chat>> {
+        use tgbotapi::ChatMemberStatus::*;
+
+        let user = message.from.as_ref().context("command missing user")?;
+
+        let chat_id: ChatID = match channel {
+            Some(channel) => channel.into(),
+            None if message.chat.chat_type.is_group() => {
+                let get_chat = GetChat {
+                    chat_id: message.chat_id(),
+                };
+                let chat = handler.make_request(&get_chat).await?;
+
+                match chat.linked_chat_id {
+                    Some(linked_chat_id) => linked_chat_id.into(),
+                    None => {
+                        handler
+                            .send_generic_reply(message, "channel-unknown")
+                            .await?;
+                        return Ok(None);
+                    }
+                }
+            }
+            None => {
+                handler
+                    .send_generic_reply(message, "channel-unknown")
+                    .await?;
+                return Ok(None);
+            }
+        };
+
+        let channel = match handler.make_request(&GetChat { chat_id }).await {
+            Ok(chat) if chat.chat_type == ChatType::Channel => chat,
+            _ => {
+                handler
+                    .send_generic_reply(message, "channel-unknown")
+                    .await?;
+                return Ok(None);
+            }
+        };
+
+        let user_is_admin = match ChatAdmin::is_admin(&handler.conn, user.id, channel.id).await? {
+            Some(is_admin) => is_admin,
+            _ => {
+                let get_chat_member = GetChatMember {
+                    chat_id: channel.id.into(),
+                    user_id: user.id,
+                };
+                let chat_member = handler.make_request(&get_chat_member).await?;
+
+                matches!(chat_member.status, Administrator | Creator)
+            }
+        };
+
+        if !user_is_admin {
+            handler
+                .send_generic_reply(message, "channel-not-admin")
+                .await?;
+            return Ok(None);
+        }
+
+        Ok(Some(channel))
+    }
+
+    /// View or change how similar an image must be to be sourced
+    /// automatically, for a group or a managed channel.
+    async fn source_distance(
+        &self,
+        handler: &MessageHandler,
+        message: &Message,
+        args: &CommandArgs,
+    ) -> anyhow::Result<()> {
+        let channel = args.text("channel");
+
+        let chat_id = if channel.is_none() && message.chat.chat_type.is_group() {
+            if !self.is_valid_admin_group(handler, message).await? {
+                return Ok(());
+            }
+
+            message.chat.id
+        } else {
+            match self
+                .resolve_managed_channel(handler, message, channel)
+                .await?
+            {
+                Some(channel) => channel.id,
+                None => return Ok(()),
+            }
+        };
+
+        let (name, distance) = match args.text("distance").map(|arg| arg.parse::<u64>()) {
+            None => (
+                "distance-current",
+                GroupConfig::source_distance(&handler.conn, chat_id).await?,
+            ),
+            Some(Ok(distance)) if distance <= foxbot_models::MAX_SOURCE_DISTANCE => {
+                GroupConfig::set(
+                    &handler.conn,
+                    GroupConfigKey::SourceDistance,
+                    chat_id,
+                    distance,
+                )
+                .await?;
+
+                ("distance-updated", distance)
+            }
+            Some(_) => ("distance-usage", foxbot_models::MAX_SOURCE_DISTANCE),
+        };
+
+        let lang = message
+            .from
+            .as_ref()
+            .and_then(|from| from.language_code.as_deref());
+
+        let text = handler
+            .get_fluent_bundle(lang, |bundle| {
+                let mut args = fluent::FluentArgs::new();
+                args.insert("distance", distance.into());
+
+                get_message(bundle, name, Some(args)).unwrap()
+            })
+            .await;
+
+        let send_message = SendMessage {
+            chat_id: message.chat_id(),
+            text,
+            reply_to_message_id: Some(message.message_id),
+            ..Default::default()
+        };
+
+        handler.make_request(&send_message).await?;
+
+        Ok(())
+    }
+
+    /// View or toggle sites that are never used as a source, for a group or a
+    /// managed channel.
+    async fn blocked_sites(
+        &self,
+        handler: &MessageHandler,
+        message: &Message,
+        args: &CommandArgs,
+    ) -> anyhow::Result<()> {
+        let channel = args.text("channel");
+
+        let chat_id = if channel.is_none() && message.chat.chat_type.is_group() {
+            if !self.is_valid_admin_group(handler, message).await? {
+                return Ok(());
+            }
+
+            message.chat.id
+        } else {
+            match self
+                .resolve_managed_channel(handler, message, channel)
+                .await?
+            {
+                Some(channel) => channel.id,
+                None => return Ok(()),
+            }
+        };
+
+        let mut blocked = GroupConfig::blocked_sites(&handler.conn, chat_id).await?;
+
+        let toggled = args
+            .text("sites")
+            .unwrap_or_default()
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                foxbot_models::Sites::default_order()
+                    .into_iter()
+                    .find(|site| site.as_str().eq_ignore_ascii_case(name))
+            })
+            .collect::<Option<Vec<_>>>();
+
+        let name = match toggled {
+            None => "blocked-sites-usage",
+            Some(toggled) => {
+                for site in &toggled {
+                    match blocked.iter().position(|blocked| blocked == site) {
+                        Some(index) => {
+                            blocked.remove(index);
+                        }
+                        None => blocked.push(site.clone()),
+                    }
+                }
+
+                if !toggled.is_empty() {
+                    GroupConfig::set(
+                        &handler.conn,
+                        GroupConfigKey::BlockedSites,
+                        chat_id,
+                        &blocked,
+                    )
+                    .await?;
+                }
+
+                if blocked.is_empty() {
+                    "blocked-sites-none"
+                } else {
+                    "blocked-sites-current"
+                }
+            }
+        };
+
+        let lang = message
+            .from
+            .as_ref()
+            .and_then(|from| from.language_code.as_deref());
+
+        let text = handler
+            .get_fluent_bundle(lang, |bundle| {
+                let list = |sites: Vec<foxbot_models::Sites>| {
+                    sites
+                        .iter()
+                        .map(|site| site.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                };
+
+                let mut args = fluent::FluentArgs::new();
+                args.insert("sites", list(blocked).into());
+                args.insert(
+                    "available",
+                    list(foxbot_models::Sites::default_order()).into(),
+                );
+
+                get_message(bundle, name, Some(args)).unwrap()
+            })
+            .await;
+
+        let send_message = SendMessage {
+            chat_id: message.chat_id(),
+            text,
+            reply_to_message_id: Some(message.message_id),
+            ..Default::default()
+        };
+
+        handler.make_request(&send_message).await?;
+
+        Ok(())
+    }
+
+    /// View or change the network a managed channel shares sources with.
+    async fn channel_network(
+        &self,
+        handler: &MessageHandler,
+        message: &Message,
+        args: &CommandArgs,
+    ) -> anyhow::Result<()> {
+        let channel = match self
+            .resolve_managed_channel(handler, message, args.text("channel"))
+            .await?
+        {
+            Some(channel) => channel,
+            None => return Ok(()),
+        };
+
+        let (name, network) = match args.text("name") {
+            None => match GroupConfig::channel_network(&handler.conn, channel.id).await? {
+                Some(network) => ("network-current", network),
+                None => ("network-none", String::new()),
+            },
+            Some("off") => {
+                GroupConfig::set(
+                    &handler.conn,
+                    GroupConfigKey::ChannelNetwork,
+                    channel.id,
+                    None::<String>,
+                )
+                .await?;
+
+                ("network-none", String::new())
+            }
+            Some(network) if is_valid_network_name(network) => {
+                GroupConfig::set(
+                    &handler.conn,
+                    GroupConfigKey::ChannelNetwork,
+                    channel.id,
+                    network,
+                )
+                .await?;
+
+                ("network-updated", network.to_string())
+            }
+            Some(_) => ("network-usage", String::new()),
+        };
+
+        let lang = message
+            .from
+            .as_ref()
+            .and_then(|from| from.language_code.as_deref());
+
+        let text = handler
+            .get_fluent_bundle(lang, |bundle| {
+                let mut args = fluent::FluentArgs::new();
+                args.insert("network", network.into());
+
+                get_message(bundle, name, Some(args)).unwrap()
+            })
+            .await;
+
+        let send_message = SendMessage {
+            chat_id: message.chat_id(),
+            text,
+            reply_to_message_id: Some(message.message_id),
+            ..Default::default()
+        };
+
+        handler.make_request(&send_message).await?;
+
+        Ok(())
+    }
+
+    async fn source_template(
+        &self,
+        handler: &MessageHandler,
+        message: &Message,
+        args: &CommandArgs,
+    ) -> anyhow::Result<()> {
+        let channel = match self
+            .resolve_managed_channel(handler, message, args.text("channel"))
+            .await?
+        {
+            Some(channel) => channel,
+            None => return Ok(()),
+        };
+
+        let mut template: SourceTemplate =
+            GroupConfig::get(&handler.conn, channel.id, GroupConfigKey::SourceTemplate)
+                .await?
+                .unwrap_or_default();
+
+        let setting = args.text("setting");
+        let value = args.text("value").unwrap_or_default();
+
+        let updated = match (setting, value) {
+            (None, _) => false,
+            (Some("style"), "buttons") => {
+                template.style = SourceStyle::Buttons;
+                true
+            }
+            (Some("style"), "caption") => {
+                template.style = SourceStyle::Caption;
+                true
+            }
+            (Some("format"), format) if format.contains("{url}") => {
+                template.format = format.to_string();
+                true
+            }
+            (Some("hashtags"), "on") => {
+                template.hashtags = true;
+                true
+            }
+            (Some("hashtags"), "off") => {
+                template.hashtags = false;
+                true
+            }
+            (Some("companion"), "off") => {
+                template.companion = CompanionReplies::Off;
+                true
+            }
+            (Some("companion"), "fallback") => {
+                template.companion = CompanionReplies::Fallback;
+                true
+            }
+            (Some("companion"), "also") => {
+                template.companion = CompanionReplies::Also;
+                true
+            }
+            (Some("companion"), "instead") => {
+                template.companion = CompanionReplies::Instead;
+                true
+            }
+            (Some("sources"), count) => match count.parse() {
+                Ok(count) if (1..=foxbot_models::MAX_SOURCES_PER_SITE).contains(&count) => {
+                    template.sources_per_site = count;
+                    true
+                }
+                _ => {
+                    handler
+                        .send_generic_reply(message, "template-usage")
+                        .await?;
+                    return Ok(());
+                }
+            },
+            (Some("distance"), "on") => {
+                template.show_distance = true;
+                true
+            }
+            (Some("distance"), "off") => {
+                template.show_distance = false;
+                true
+            }
+            (Some("strict"), "on") => {
+                template.strict = true;
+                true
+            }
+            (Some("strict"), "off") => {
+                template.strict = false;
+                true
+            }
+            (Some("reset"), _) => {
+                template = SourceTemplate::default();
+                true
+            }
+            _ => {
+                handler
+                    .send_generic_reply(message, "template-usage")
+                    .await?;
+                return Ok(());
+            }
+        };
+
+        if updated {
+            GroupConfig::set(
+                &handler.conn,
+                GroupConfigKey::SourceTemplate,
+                channel.id,
+                &template,
+            )
+            .await?;
+        }
+
+        let lang = message
+            .from
+            .as_ref()
+            .and_then(|from| from.language_code.as_deref());
+
+        let text = handler
+            .get_fluent_bundle(lang, |bundle| {
+                let mut args = fluent::FluentArgs::new();
+                args.insert(
+                    "style",
+                    match template.style {
+                        SourceStyle::Buttons => "buttons",
+                        SourceStyle::Caption => "caption",
+                    }
+                    .into(),
+                );
+                args.insert("format", template.format.clone().into());
+                args.insert(
+                    "hashtags",
+                    if template.hashtags { "on" } else { "off" }.into(),
+                );
+                args.insert(
+                    "companion",
+                    match template.companion {
+                        CompanionReplies::Off => "off",
+                        CompanionReplies::Fallback => "fallback",
+                        CompanionReplies::Also => "also",
+                        CompanionReplies::Instead => "instead",
+                    }
+                    .into(),
+                );
+                args.insert("sources", template.sources_per_site.into());
+                args.insert(
+                    "distance",
+                    if template.show_distance { "on" } else { "off" }.into(),
+                );
+                args.insert("strict", if template.strict { "on" } else { "off" }.into());
+
+                let name = if updated {
+                    "template-updated"
+                } else {
+                    "template-current"
+                };
+
+                get_message(bundle, name, Some(args)).unwrap()
+            })
+            .await;
+
+        let send_message = SendMessage {
+            chat_id: message.chat_id(),
+            text,
+            reply_to_message_id: Some(message.message_id),
+            disable_web_page_preview: Some(true),
+            ..Default::default()
+        };
+
+        handler.make_request(&send_message).await?;
+
+        Ok(())
+    }
+
+    async fn backfill(
+        &self,
+        handler: &MessageHandler,
+        message: &Message,
+        args: &CommandArgs,
+    ) -> anyhow::Result<()> {
+        let (channel, first, last) = match (
+            args.text("channel"),
+            args.get::<i32>("first"),
+            args.get::<i32>("last"),
+        ) {
+            (Some(channel), Some(first), Some(last)) if first > 0 && first <= last => {
+                (channel, first, last)
+            }
+            _ => {
+                handler
+                    .send_generic_reply(message, "backfill-usage")
+                    .await?;
+                return Ok(());
+            }
+        };
+
+        let lang = message
+            .from
+            .as_ref()
+            .and_then(|from| from.language_code.clone());
+
+        let count = last - first + 1;
+        if count > MAX_BACKFILL_MESSAGES {
+            let text = handler
+                .get_fluent_bundle(lang.as_deref(), |bundle| {
+                    let mut args = fluent::FluentArgs::new();
+                    args.insert("max", MAX_BACKFILL_MESSAGES.into());
+
+                    get_message(bundle, "backfill-too-many", Some(args)).unwrap()
+                })
+                .await;
+
+            let send_message = SendMessage {
+                chat_id: message.chat_id(),
+                text,
+                reply_to_message_id: Some(message.message_id),
+                ..Default::default()
+            };
+
+            handler.make_request(&send_message).await?;
+            return Ok(());
+        }
+
+        let channel = match self
+            .resolve_managed_channel(handler, message, Some(channel))
+            .await?
+        {
+            Some(channel) => channel,
+            None => return Ok(()),
+        };
+
+        let text = handler
+            .get_fluent_bundle(lang.as_deref(), |bundle| {
+                let mut args = fluent::FluentArgs::new();
+                args.insert("count", count.into());
+
+                get_message(bundle, "backfill-started", Some(args)).unwrap()
+            })
+            .await;
+
+        let send_message = SendMessage {
+            chat_id: message.chat_id(),
+            text,
+            reply_to_message_id: Some(message.message_id),
+            ..Default::default()
+        };
+
+        let progress_message = handler.make_request(&send_message).await?;
+
+        tracing::info!(
+            channel_id = channel.id,
+            first,
+            last,
+            "starting channel backfill"
+        );
+
+        let backfill = serde_json::to_value(&ChannelBackfill {
+            channel_id: channel.id,
+            next_message_id: first,
+            last_message_id: last,
+            user_chat_id: message.chat.id,
+            progress_message_id: progress_message.message_id,
+            lang,
+        })?;
+
+        let mut job = faktory::Job::new("channel_backfill", vec![backfill]).on_queue(QUEUE_LOW);
+        job.custom = get_faktory_custom();
+
+        handler.enqueue(job).await?;
+
+        Ok(())
+    }
+
+    async fn retry_jobs(&self, handler: &MessageHandler, message: &Message) -> anyhow::Result<()> {
+        let from = message
+            .from
+            .as_ref()
+            .context("retry jobs command missing user")?;
+
+        let jobs = FailedJobs::pending(&handler.conn).await?;
+        let count = jobs.len();
+
+        tracing::info!(count, "retrying failed jobs");
+
+        for failed in jobs {
+            let id = failed.id;
+            let job = retry_failed_job(failed)?;
+
+            // Jobs are only marked once they're enqueued, so any that weren't
+            // are still pending if enqueuing fails partway through.
+            handler.enqueue(job).await?;
+            FailedJobs::mark_retried(&handler.conn, id).await?;
+        }
+
+        let text = handler
+            .get_fluent_bundle(from.language_code.as_deref(), |bundle| {
+                let mut args = fluent::FluentArgs::new();
+                args.insert("count", count.into());
+
+                get_message(bundle, "retry-jobs", Some(args)).unwrap()
+            })
+            .await;
+
+        let send_message = SendMessage {
+            chat_id: message.chat_id(),
+            text,
+            reply_to_message_id: Some(message.message_id),
+            ..Default::default()
+        };
+
+        handler.make_request(&send_message).await?;
+
+        Ok(())
+    }
+
+    async fn pending_edits(
+        &self,
+        handler: &MessageHandler,
+        message: &Message,
+    ) -> anyhow::Result<()> {
+        use redis::AsyncCommands;
+
+        let from = message
+            .from
+            .as_ref()
+            .context("pending edits command missing user")?;
+
+        // Scheduled jobs are tracked by the background worker's scheduler.
+        let mut redis = handler.redis.clone();
+        let count: usize = redis.zcard(SCHEDULED_JOBS_KEY).await?;
+        let scheduled: Vec<(String, i64)> = redis
+            .zrange_withscores(SCHEDULED_JOBS_KEY, 0, PENDING_EDITS_SHOWN - 1)
+            .await?;
+
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_secs() as i64;
+
+        let mut items = Vec::with_capacity(scheduled.len());
+        for (job_id, at) in scheduled {
+            let data: Option<String> = redis.hget("scheduled-job-data", &job_id).await?;
+            let data: serde_json::Value = match data.as_deref().map(serde_json::from_str) {
+                Some(Ok(data)) => data,
+                _ => continue,
+            };
+
+            let kind = data["kind"].as_str().unwrap_or("unknown").to_string();
+            let chat = match &data["args"][0]["chat_id"] {
+                serde_json::Value::String(chat_id) => chat_id.to_owned(),
+                serde_json::Value::Null => "-".to_string(),
+                chat_id => chat_id.to_string(),
+            };
+
+            items.push((kind, chat, at - now));
+        }
+
+        let text = handler
+            .get_fluent_bundle(from.language_code.as_deref(), |bundle| {
+                if count == 0 {
+                    return get_message(bundle, "pending-edits-empty", None).unwrap();
+                }
+
+                let mut args = fluent::FluentArgs::new();
+                args.insert("count", count.into());
+
+                let mut buf = get_message(bundle, "pending-edits-title", Some(args)).unwrap();
+
+                for (kind, chat, seconds) in items {
+                    let mut args = fluent::FluentArgs::new();
+                    args.insert("kind", kind.into());
+                    args.insert("chat", chat.into());
+                    args.insert("seconds", seconds.into());
+
+                    buf.push('\n');
+                    buf.push_str(&get_message(bundle, "pending-edits-item", Some(args)).unwrap());
+                }
+
+                buf
+            })
+            .await;
+
+        let send_message = SendMessage {
+            chat_id: message.chat_id(),
+            text,
+            reply_to_message_id: Some(message.message_id),
+            ..Default::default()
+        };
+
+        handler.make_request(&send_message).await?;
+
+        Ok(())
+    }
+
+    /// Find the group or managed channel an action command is about, from
+    /// the chat it was sent in or the channel in its first argument.
+    async fn resolve_action_chat(
+        &self,
+        handler: &MessageHandler,
+        message: &Message,
+        args: &CommandArgs,
+    ) -> anyhow::Result<Option<i64>> {
+        let channel = args.text("channel");
+
+        if channel.is_none() && message.chat.chat_type.is_group() {
+            if !self.is_valid_admin_group(handler, message).await? {
+                return Ok(None);
+            }
+
+            return Ok(Some(message.chat.id));
+        }
+
+        let channel = self
+            .resolve_managed_channel(handler, message, channel)
+            .await?;
+
+        Ok(channel.map(|channel| channel.id))
+    }
+
+    /// Show the most recent changes the bot made in a group or a managed
+    /// channel.
+    async fn history(
+        &self,
+        handler: &MessageHandler,
+        message: &Message,
+        args: &CommandArgs,
+    ) -> anyhow::Result<()> {
+        let chat_id = match self.resolve_action_chat(handler, message, args).await? {
+            Some(chat_id) => chat_id,
+            None => return Ok(()),
+        };
+
+        let actions = Actions::recent(&handler.conn, chat_id, HISTORY_SHOWN).await?;
+
+        let lang = message
+            .from
+            .as_ref()
+            .and_then(|from| from.language_code.as_deref());
+
+        let text = handler
+            .get_fluent_bundle(lang, |bundle| {
+                if actions.is_empty() {
+                    return get_message(bundle, "history-empty", None).unwrap();
+                }
+
+                let mut buf = get_message(bundle, "history-title", None).unwrap();
+
+                for action in actions {
+                    let mut args = fluent::FluentArgs::new();
+                    args.insert("action", action.action.into());
+                    args.insert("message", action.message_id.into());
+                    args.insert("minutes", (action.age / 60).into());
+                    args.insert("count", action.sources.len().into());
+
+                    buf.push('\n');
+                    buf.push_str(&get_message(bundle, "history-item", Some(args)).unwrap());
+
+                    for source in action.sources {
+                        buf.push_str("\n    ");
+                        buf.push_str(&source);
+                    }
+                }
+
+                buf
+            })
+            .await;
+
+        let send_message = SendMessage {
+            chat_id: message.chat_id(),
+            text,
+            reply_to_message_id: Some(message.message_id),
+            disable_web_page_preview: Some(true),
+            ..Default::default()
+        };
+
+        handler.make_request(&send_message).await?;
+
+        Ok(())
+    }
+
+    /// Revert the most recent change the bot made in a group or a managed
+    /// channel, restoring a channel post's caption or deleting a reply.
+    async fn undo(
+        &self,
+        handler: &MessageHandler,
+        message: &Message,
+        args: &CommandArgs,
+    ) -> anyhow::Result<()> {
+        let chat_id = match self.resolve_action_chat(handler, message, args).await? {
+            Some(chat_id) => chat_id,
+            None => return Ok(()),
+        };
+
+        let action = match Actions::last_undoable(&handler.conn, chat_id).await? {
+            Some(action) => action,
+            None => {
+                handler.send_generic_reply(message, "undo-none").await?;
+                return Ok(());
+            }
+        };
+
+        tracing::info!(action_id = action.id, kind = ?action.kind, "undoing action");
+
+        let result = match action.kind {
+            // Editing the caption without a keyboard also removes any source
+            // buttons that were added.
+            ActionKind::ChannelEdit => {
+                let edit_caption = EditMessageCaption {
+                    chat_id: chat_id.into(),
+                    message_id: Some(action.message_id),
+                    caption: action.previous_caption,
+                    caption_entities: action.previous_caption_entities,
+                    ..Default::default()
+                };
+
+                handler.make_request(&edit_caption).await.map(|_| ())
+            }
+            ActionKind::Reply => {
+                let delete_message = DeleteMessage {
+                    chat_id: chat_id.into(),
+                    message_id: action.reply_message_id.unwrap_or(action.message_id),
+                };
+
+                handler.make_request(&delete_message).await.map(|_| ())
+            }
+        };
+
+        // Messages that were deleted or already changed can't be undone, so
+        // they shouldn't be tried again.
+        let name = match result {
+            Ok(()) => "undo-done",
+            Err(tgbotapi::Error::Telegram(err)) if err.error_code == Some(400) => {
+                tracing::warn!("unable to undo action: {:?}", err.description);
+                "undo-failed"
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        Actions::mark_undone(&handler.conn, action.id).await?;
+
+        handler.send_generic_reply(message, name).await?;
+
+        Ok(())
+    }
+
+    async fn handle_stats(
+        &self,
+        handler: &MessageHandler,
+        message: &Message,
+    ) -> anyhow::Result<()> {
+        let from = message
+            .from
+            .as_ref()
+            .context("stats command missing user")?;
+
+        let counts = InlineStats::site_counts(&handler.conn, STATS_DAYS).await?;
+
+        let text = handler
+            .get_fluent_bundle(from.language_code.as_deref(), |bundle| {
+                if counts.is_empty() {
+                    return get_message(bundle, "stats-empty", None).unwrap();
+                }
+
+                let mut args = fluent::FluentArgs::new();
+                args.insert("days", STATS_DAYS.into());
+
+                let mut buf = get_message(bundle, "stats-title", Some(args)).unwrap();
+                buf.push('\n');
+
+                for (site, count) in &counts {
+                    let mut args = fluent::FluentArgs::new();
+                    args.insert("site", site.as_str().into());
+                    args.insert("count", (*count).into());
+
+                    buf.push_str(&get_message(bundle, "stats-site", Some(args)).unwrap());
+                    buf.push('\n');
+                }
+
+                buf
+            })
+            .await;
+
+        let send_message = SendMessage {
+            chat_id: message.chat_id(),
+            text,
+            reply_to_message_id: Some(message.message_id),
+            ..Default::default()
+        };
+
+        handler.make_request(&send_message).await?;
+
+        Ok(())
+    }
+
+    /// Load each site's test URL with fresh site loaders, so checks don't
+    /// hold up users and credentials are loaded like at startup.
+    async fn site_status(&self, handler: &MessageHandler, message: &Message) -> anyhow::Result<()> {
+        let from = message
+            .from
+            .as_ref()
+            .context("site status command missing user")?;
+
+        let action = continuous_action(
+            handler.bot.clone(),
+            12,
+            message.chat_id(),
+            message.from.clone(),
+            ChatAction::Typing,
+        );
+
+        let mut sites = crate::build_sites(&handler.config, handler.conn.clone()).await;
+        let checks = foxbot_sites::check_sites(&mut sites).await;
+
+        drop(action);
+
+        let text = handler
+            .get_fluent_bundle(from.language_code.as_deref(), |bundle| {
+                let mut buf = get_message(bundle, "sitestatus-title", None).unwrap();
+                buf.push('\n');
+
+                for check in &checks {
+                    let mut args = fluent::FluentArgs::new();
+                    args.insert("site", check.site.into());
+                    args.insert("duration", (check.duration.as_millis() as u64).into());
+
+                    let line = match &check.result {
+                        Ok(count) => {
+                            args.insert("count", (*count).into());
+                            get_message(bundle, "sitestatus-pass", Some(args))
+                        }
+                        Err(err) => {
+                            args.insert("error", format!("{:#}", err).into());
+                            get_message(bundle, "sitestatus-fail", Some(args))
+                        }
+                    };
+
+                    buf.push_str(&line.unwrap());
+                    buf.push('\n');
+                }
+
+                buf
+            })
+            .await;
+
+        let send_message = SendMessage {
+            chat_id: message.chat_id(),
+            text,
+            reply_to_message_id: Some(message.message_id),
+            disable_web_page_preview: Some(true),
+            ..Default::default()
+        };
+
+        handler.make_request(&send_message).await?;
+
+        Ok(())
+    }
+
+    /// Update a site's credentials, or go back to the configured ones if no
+    /// value was given.
+    ///
+    /// Credentials may only be sent in private chats, and the message with
+    /// them is deleted once they're saved.
+    async fn credential(
+        &self,
+        handler: &MessageHandler,
+        message: &Message,
+        args: &CommandArgs,
+    ) -> anyhow::Result<()> {
+        let from = message
+            .from
+            .as_ref()
+            .context("credential command missing user")?;
+
+        if message.chat.chat_type != ChatType::Private {
+            handler
+                .send_generic_reply(message, "credential-private")
+                .await?;
+            return Ok(());
+        }
+
+        let name = args
+            .text("name")
+            .context("credential missing name")?
+            .to_uppercase();
+        let value = args.text("value");
+
+        if !foxbot_utils::secrets::SITE_CREDENTIALS.contains(&name.as_str()) {
+            handler
+                .send_generic_reply(message, "credential-unknown")
+                .await?;
+            return Ok(());
+        }
+
+        handler
+            .update_site_credential(&name, value, Some(from.id))
+            .await?;
+
+        if value.is_some() {
+            let delete_message = DeleteMessage {
+                chat_id: message.chat_id(),
+                message_id: message.message_id,
+            };
+
+            if let Err(err) = handler.make_request(&delete_message).await {
+                tracing::warn!("unable to delete credential message: {:?}", err);
+            }
+        }
+
+        let text = handler
+            .get_fluent_bundle(from.language_code.as_deref(), |bundle| {
+                let mut args = fluent::FluentArgs::new();
+                args.insert("name", name.as_str().into());
+                args.insert(
+                    "sites",
+                    foxbot_utils::secrets::sites_using(&name).join(", ").into(),
+                );
+
+                let message = if value.is_some() {
+                    "credential-updated"
+                } else {
+                    "credential-removed"
+                };
+
+                get_message(bundle, message, Some(args)).unwrap()
+            })
+            .await;
+
+        let send_message = SendMessage {
+            chat_id: message.chat_id(),
+            text,
+            ..Default::default()
+        };
+
+        handler.make_request(&send_message).await?;
+
+        Ok(())
+    }
+
+    /// Load languages again, so new translations can be used without a
+    /// restart. The background worker reloads its languages on SIGHUP.
+    async fn reload_langs(
+        &self,
+        handler: &MessageHandler,
+        message: &Message,
+    ) -> anyhow::Result<()> {
+        let from = message
+            .from
+            .as_ref()
+            .context("reload languages command missing user")?;
+
+        let result = handler.l10n.reload();
+
+        let text = handler
+            .get_fluent_bundle(from.language_code.as_deref(), |bundle| {
+                let mut args = fluent::FluentArgs::new();
+
+                let name = match &result {
+                    Ok(count) => {
+                        args.insert("count", (*count).into());
+                        "langs-reloaded"
+                    }
+                    Err(err) => {
+                        args.insert("error", format!("{:#}", err).into());
+                        "langs-reload-failed"
+                    }
+                };
+
+                get_message(bundle, name, Some(args)).unwrap()
+            })
+            .await;
+
+        let send_message = SendMessage {
+            chat_id: message.chat_id(),
+            text,
+            reply_to_message_id: Some(message.message_id),
+            ..Default::default()
+        };
+
+        handler.make_request(&send_message).await?;
+
+        Ok(())
+    }
 }
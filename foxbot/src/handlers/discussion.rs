@@ -0,0 +1,69 @@
+use anyhow::Context;
+use async_trait::async_trait;
+use tgbotapi::{ChatType, Update};
+
+use super::{
+    Handler,
+    Status::{self, Ignored},
+};
+use crate::MessageHandler;
+use foxbot_utils::needs_field;
+
+/// ID of the user Telegram uses when automatically forwarding channel posts
+/// into a linked discussion group.
+const TELEGRAM_SERVICE_USER_ID: i64 = 777000;
+
+/// How long to remember where a channel post was forwarded, in seconds.
+const DISCUSSION_THREAD_TTL: usize = 60 * 60 * 24;
+
+/// Remembers which discussion group message each channel post was forwarded
+/// to, so the background worker can reply in the post's thread.
+pub struct DiscussionHandler;
+
+#[async_trait]
+impl Handler for DiscussionHandler {
+    fn name(&self) -> &'static str {
+        "discussion"
+    }
+
+    async fn handle(&self, handler: &MessageHandler, update: &Update) -> anyhow::Result<Status> {
+        use redis::AsyncCommands;
+
+        let message = needs_field!(update, message);
+
+        if !message.chat.chat_type.is_group() {
+            return Ok(Ignored);
+        }
+
+        match &message.from {
+            Some(from) if from.id == TELEGRAM_SERVICE_USER_ID => (),
+            _ => return Ok(Ignored),
+        }
+
+        let (channel, channel_message_id) =
+            match (&message.forward_from_chat, message.forward_from_message_id) {
+                (Some(chat), Some(message_id)) if chat.chat_type == ChatType::Channel => {
+                    (chat, message_id)
+                }
+                _ => return Ok(Ignored),
+            };
+
+        tracing::trace!(
+            channel_id = channel.id,
+            channel_message_id,
+            "saving discussion thread for channel post"
+        );
+
+        let key = format!("discussion:{}:{}", channel.id, channel_message_id);
+        let value = format!("{}:{}", message.chat.id, message.message_id);
+
+        let mut redis = handler.redis.clone();
+        redis
+            .set_ex::<_, _, ()>(key, value, DISCUSSION_THREAD_TTL)
+            .await
+            .context("unable to save discussion thread")?;
+
+        // Other handlers may still want to look at the forwarded post.
+        Ok(Ignored)
+    }
+}
@@ -17,12 +17,7 @@ impl Handler for ErrorCleanup {
         "error_cleanup"
     }
 
-    async fn handle(
-        &self,
-        handler: &MessageHandler,
-        update: &Update,
-        _command: Option<&Command>,
-    ) -> anyhow::Result<Status> {
+    async fn handle(&self, handler: &MessageHandler, update: &Update) -> anyhow::Result<Status> {
         let callback_query = needs_field!(update, callback_query);
         let data = needs_field!(callback_query, data);
 
@@ -27,12 +27,7 @@ impl Handler for ErrorReplyHandler {
         "error_reply"
     }
 
-    async fn handle(
-        &self,
-        handler: &MessageHandler,
-        update: &Update,
-        _command: Option<&Command>,
-    ) -> anyhow::Result<Status> {
+    async fn handle(&self, handler: &MessageHandler, update: &Update) -> anyhow::Result<Status> {
         let message = needs_field!(update, message);
         let text = needs_field!(message, text);
         let reply_message = needs_field!(message, reply_to_message);
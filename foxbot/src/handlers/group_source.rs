@@ -1,12 +1,25 @@
+use anyhow::Context;
 use async_trait::async_trait;
-use tgbotapi::{Command, Update};
+use redis::AsyncCommands;
+use tgbotapi::{
+    requests::{DeleteMessage, EditMessageText},
+    CallbackQuery, Message, Update,
+};
 
 use super::{
-    Handler,
+    permissions::is_chat_admin,
+    CallbackAnswer, CallbackHandler, Handler,
     Status::{self, Completed, Ignored},
 };
 use crate::MessageHandler;
-use foxbot_utils::{get_faktory_custom, needs_field};
+use foxbot_models::{Actions, ArtistChoice, WrongSources};
+use foxbot_utils::{
+    artist_choices_key,
+    callback::{routes, CallbackData},
+    forwarded_source_key, get_faktory_custom, needs_field,
+    payload::{self, JobMessage},
+    wrong_source_key, ArtistChoices, QUEUE_HIGH,
+};
 
 pub struct GroupSourceHandler;
 
@@ -16,14 +29,12 @@ impl Handler for GroupSourceHandler {
         "group"
     }
 
-    async fn handle(
-        &self,
-        handler: &MessageHandler,
-        update: &Update,
-        _command: Option<&Command>,
-    ) -> anyhow::Result<Status> {
+    async fn handle(&self, handler: &MessageHandler, update: &Update) -> anyhow::Result<Status> {
         let message = needs_field!(update, message);
-        needs_field!(message, photo);
+        let job_message = match JobMessage::new(message, handler.download_limit()) {
+            Some(job_message) => job_message,
+            None => return Ok(Ignored),
+        };
 
         if matches!(message.via_bot, Some(tgbotapi::User { id, .. }) if id == handler.bot_user.id) {
             return Ok(Ignored);
@@ -31,20 +42,254 @@ impl Handler for GroupSourceHandler {
 
         tracing::debug!("passing group photo to background worker");
 
-        let custom = get_faktory_custom();
+        let message = payload::encode(&job_message)?;
+        let mut job = faktory::Job::new("group_photo", vec![message]).on_queue(QUEUE_HIGH);
+        job.custom = get_faktory_custom();
 
-        let faktory = handler.faktory.clone();
-        let message = message.to_owned();
-        tokio::task::spawn_blocking(move || {
-            let mut faktory = faktory.lock().unwrap();
-            let message = serde_json::to_value(&message).unwrap();
-            let mut job =
-                faktory::Job::new("group_photo", vec![message]).on_queue("foxbot_background");
-            job.custom = custom;
-
-            faktory.enqueue(job).unwrap();
-        });
+        handler.enqueue(job).await?;
 
         Ok(Completed)
     }
 }
+
+#[async_trait]
+impl CallbackHandler for GroupSourceHandler {
+    fn routes(&self) -> &'static [&'static str] {
+        &[
+            routes::FORWARDED_SOURCE,
+            routes::WRONG_SOURCE,
+            routes::ARTIST_CHOICE,
+        ]
+    }
+
+    async fn handle_callback(
+        &self,
+        handler: &MessageHandler,
+        callback_query: &CallbackQuery,
+        data: &CallbackData,
+    ) -> anyhow::Result<CallbackAnswer> {
+        let message = match callback_query.message.as_deref() {
+            Some(message) => message,
+            None => return Ok(CallbackAnswer::Empty),
+        };
+
+        match data.route.as_str() {
+            routes::FORWARDED_SOURCE => {
+                show_forwarded_source(handler, callback_query, message).await
+            }
+            routes::WRONG_SOURCE => {
+                // Replies for albums don't have a single hash, so the hash for
+                // each source is saved instead.
+                let hash = data
+                    .args
+                    .first()
+                    .map(|hash| hash.parse())
+                    .transpose()
+                    .context("wrong source callback had invalid hash")?;
+                report_wrong_source(handler, callback_query, message, hash).await
+            }
+            routes::ARTIST_CHOICE => {
+                let index = data.get(0).context("artist choice had invalid index")?;
+                choose_artist(handler, callback_query, message, index).await
+            }
+            _ => Ok(CallbackAnswer::Empty),
+        }
+    }
+}
+
+/// Replace the offer to find sources for a forwarded image with the sources
+/// found when it was sent.
+async fn show_forwarded_source(
+    handler: &MessageHandler,
+    callback_query: &CallbackQuery,
+    message: &Message,
+) -> anyhow::Result<CallbackAnswer> {
+    let forwarded = match message.reply_to_message.as_deref() {
+        Some(forwarded) => forwarded,
+        None => return Ok(CallbackAnswer::Empty),
+    };
+
+    let key = forwarded_source_key(forwarded.chat.id, forwarded.message_id);
+
+    let mut redis = handler.redis.clone();
+    let text: Option<String> = redis
+        .get(&key)
+        .await
+        .context("unable to get forwarded source")?;
+
+    let text = match text {
+        Some(text) => text,
+        None => return Ok(CallbackAnswer::Alert("automatic-forwarded-expired")),
+    };
+
+    let edit_message = EditMessageText {
+        chat_id: message.chat_id(),
+        message_id: Some(message.message_id),
+        text,
+        disable_web_page_preview: Some(true),
+        ..Default::default()
+    };
+
+    handler
+        .make_request(&edit_message)
+        .await
+        .context("unable to show forwarded source")?;
+
+    redis
+        .del::<_, ()>(&key)
+        .await
+        .context("unable to remove forwarded source")?;
+
+    Ok(CallbackAnswer::Empty)
+}
+
+/// Report the sources in a reply as wrong for the image, so they won't be
+/// shown for it again in the chat, and remove the reply.
+///
+/// Only the person who posted the image or a group administrator may report
+/// sources. Replies without a hash are for albums, which have a saved hash
+/// for each source.
+async fn report_wrong_source(
+    handler: &MessageHandler,
+    callback_query: &CallbackQuery,
+    message: &Message,
+    hash: Option<i64>,
+) -> anyhow::Result<CallbackAnswer> {
+    let user = &callback_query.from;
+
+    let posted_image = matches!(
+        message.reply_to_message.as_deref().and_then(|message| message.from.as_ref()),
+        Some(from) if from.id == user.id
+    );
+
+    let allowed = posted_image || is_chat_admin(handler, message.chat.id, user.id).await?;
+
+    let name = if !allowed {
+        "wrong-source-not-allowed"
+    } else {
+        let sources: Vec<(String, i64)> = match hash {
+            Some(hash) => {
+                Actions::reply_sources(&handler.conn, message.chat.id, message.message_id)
+                    .await?
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|source| (source, hash))
+                    .collect()
+            }
+            None => {
+                let key = wrong_source_key(message.chat.id, message.message_id);
+
+                let mut redis = handler.redis.clone();
+                let saved: Option<String> = redis
+                    .get(&key)
+                    .await
+                    .context("unable to get source hashes")?;
+
+                match saved {
+                    Some(saved) => serde_json::from_str(&saved)?,
+                    None => return Ok(CallbackAnswer::Alert("wrong-source-expired")),
+                }
+            }
+        };
+
+        tracing::info!(?hash, count = sources.len(), "reporting wrong sources");
+
+        for (source, hash) in &sources {
+            WrongSources::report(&handler.conn, user.id, message.chat.id, *hash, source).await?;
+        }
+
+        let delete_message = DeleteMessage {
+            chat_id: message.chat_id(),
+            message_id: message.message_id,
+        };
+
+        if let Err(err) = handler.make_request(&delete_message).await {
+            tracing::warn!("unable to delete wrong source reply: {:?}", err);
+        }
+
+        "wrong-source-reported"
+    };
+
+    Ok(CallbackAnswer::Toast(name))
+}
+
+/// Apply the sources for the artist someone chose.
+///
+/// In groups the choice is a reply to the image, which is replaced by the
+/// chosen sources. In channels the buttons are on the post itself, and are
+/// replaced when the post is edited.
+async fn choose_artist(
+    handler: &MessageHandler,
+    callback_query: &CallbackQuery,
+    message: &Message,
+    index: usize,
+) -> anyhow::Result<CallbackAnswer> {
+    let user = &callback_query.from;
+
+    let is_channel = message.chat.chat_type == tgbotapi::ChatType::Channel;
+    let image_message_id = match &message.reply_to_message {
+        _ if is_channel => message.message_id,
+        Some(image_message) => image_message.message_id,
+        None => return Ok(CallbackAnswer::Empty),
+    };
+
+    let key = artist_choices_key(message.chat.id, image_message_id);
+
+    let mut redis = handler.redis.clone();
+    let choices: Option<String> = redis
+        .get(&key)
+        .await
+        .context("unable to get artist choices")?;
+    let choices: Option<ArtistChoices> =
+        choices.as_deref().map(serde_json::from_str).transpose()?;
+
+    let name = match choices {
+        None => "artist-choice-expired",
+        Some(choices) => {
+            let allowed = choices.poster_id == Some(user.id)
+                || is_chat_admin(handler, message.chat.id, user.id).await?;
+
+            match choices.choices.get(index) {
+                _ if !allowed => "artist-choice-not-allowed",
+                None => "artist-choice-expired",
+                Some(choice) => {
+                    tracing::info!(index, kind = %choices.job_kind, "applying artist choice");
+
+                    // Remember the choice so it's used the next time the image
+                    // is posted here.
+                    if let (Some(hash), Some(artist)) = (choices.hash, choices.names.get(index)) {
+                        ArtistChoice::choose(&handler.conn, message.chat.id, hash, user.id, artist)
+                            .await?;
+                    }
+
+                    let mut job =
+                        faktory::Job::new(choices.job_kind.as_str(), vec![choice.clone()])
+                            .on_queue(QUEUE_HIGH);
+                    job.custom = get_faktory_custom();
+
+                    handler.enqueue(job).await?;
+
+                    redis
+                        .del::<_, ()>(&key)
+                        .await
+                        .context("unable to remove artist choices")?;
+
+                    if !is_channel {
+                        let delete_message = DeleteMessage {
+                            chat_id: message.chat_id(),
+                            message_id: message.message_id,
+                        };
+
+                        if let Err(err) = handler.make_request(&delete_message).await {
+                            tracing::warn!("unable to delete artist choice: {:?}", err);
+                        }
+                    }
+
+                    "artist-choice-chosen"
+                }
+            }
+        }
+    };
+
+    Ok(CallbackAnswer::Toast(name))
+}
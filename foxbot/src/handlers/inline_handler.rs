@@ -4,17 +4,68 @@ use futures::{stream::FuturesOrdered, StreamExt};
 use tgbotapi::{requests::*, *};
 
 use super::{
-    Handler,
+    CallbackAnswer, CallbackHandler, Handler,
     Status::{self, *},
 };
 use crate::{MessageHandler, ServiceData};
-use foxbot_models::Video;
-use foxbot_sites::PostInfo;
-use foxbot_utils::*;
+use foxbot_models::{InlineCaptions, MediaFileId, MediaType, UserConfig, UserConfigKey, Video};
+use foxbot_sites::{credentials::UserCredentials, PostInfo};
+use foxbot_utils::{
+    callback::{routes, CallbackData},
+    image_edit::{ImagePipeline, JPEG_QUALITY},
+    *,
+};
 
 /// Telegram allows inline results up to 5MB.
 static MAX_IMAGE_SIZE: usize = 5_000_000;
 
+/// Number of links to display on each page of inline results. Each link may
+/// produce more than one result, so this stays well under Telegram's limit of
+/// 50 results.
+static INLINE_PAGE_SIZE: usize = 20;
+
+/// Maximum hash distance for matches included in a source list.
+static MAX_SOURCE_LIST_DISTANCE: i64 = 3;
+
+/// Seconds to keep inline results cached for loading additional pages.
+static INLINE_RESULTS_TTL: usize = 60 * 10;
+
+/// Seconds in the sliding window that links resolved for each user are
+/// counted over.
+static INLINE_LIMIT_WINDOW: u64 = 60;
+
+/// Most images each user may edit in a window.
+static EDIT_LIMIT: u32 = 5;
+
+/// Seconds in each window that edited images are counted in.
+static EDIT_LIMIT_WINDOW: u64 = 60;
+
+/// Seconds to keep results prepared in a private chat available to share.
+static SHARE_TTL: usize = 60 * 60 * 24;
+
+/// Length of the tokens used to share results prepared in a private chat.
+static SHARE_TOKEN_LENGTH: usize = 8;
+
+/// Prefix of inline queries sharing a photo sourced in a private chat, like
+/// `r:abc123`.
+static REVERSE_SHARE_PREFIX: &str = "r:";
+
+/// Prefix of inline queries sharing results loaded in a private chat, like
+/// `t:abc123`.
+static SHARED_RESULTS_PREFIX: &str = "t:";
+
+/// Longest side of images downscaled before sharing, the largest size most
+/// Telegram clients display photos at.
+static SHARE_DOWNSCALE_SIZE: u32 = 1280;
+
+/// Edits that can be made to an image before sharing it, with the message
+/// for each button.
+static SHARE_EDITS: &[(&str, &str)] = &[
+    ("downscale", "share-edit-downscale"),
+    ("jpeg", "share-edit-jpeg"),
+    ("alpha", "share-edit-alpha"),
+];
+
 pub struct InlineHandler;
 
 #[derive(PartialEq)]
@@ -23,6 +74,21 @@ pub enum ResultType {
     VideoToBeProcessed,
 }
 
+/// A photo that was sourced in a private chat, which can be sent anywhere
+/// with an inline query.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub(crate) struct ReverseShare {
+    pub file_id: String,
+    pub sources: Vec<ReverseShareSource>,
+}
+
+/// A source found for a shared photo.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub(crate) struct ReverseShareSource {
+    pub site_name: String,
+    pub url: String,
+}
+
 #[derive(Debug)]
 enum SentAs {
     Video(String),
@@ -78,6 +144,135 @@ impl InlineHandler {
         Ok(())
     }
 
+    /// Answer an inline query for a photo that was sourced in a private chat,
+    /// with a button for each source.
+    async fn send_reverse_share(
+        &self,
+        handler: &MessageHandler,
+        inline: &InlineQuery,
+        token: &str,
+    ) -> anyhow::Result<()> {
+        let result = match load_reverse_share(handler, token).await? {
+            Some(share) => {
+                let inline_keyboard = share
+                    .sources
+                    .into_iter()
+                    .map(|source| {
+                        vec![InlineKeyboardButton {
+                            text: source.site_name,
+                            url: Some(source.url),
+                            ..Default::default()
+                        }]
+                    })
+                    .collect();
+
+                let mut item = InlineQueryResult::cached_photo(generate_id(), share.file_id);
+                item.reply_markup = Some(InlineKeyboardMarkup { inline_keyboard });
+
+                item
+            }
+            None => {
+                handler
+                    .get_fluent_bundle(inline.from.language_code.as_deref(), |bundle| {
+                        InlineQueryResult::article(
+                            generate_id(),
+                            get_message(bundle, "inline-share-expired-title", None).unwrap(),
+                            get_message(bundle, "inline-share-expired-body", None).unwrap(),
+                        )
+                    })
+                    .await
+            }
+        };
+
+        let answer_inline = AnswerInlineQuery {
+            inline_query_id: inline.id.to_owned(),
+            results: vec![result],
+            ..Default::default()
+        };
+
+        handler
+            .make_request(&answer_inline)
+            .await
+            .context("unable to answer shared reverse search query")?;
+
+        Ok(())
+    }
+
+    /// Send all images from an inline query as albums, after the user selected
+    /// the option to do so from the inline results.
+    async fn send_album(&self, handler: &MessageHandler, message: &Message) -> anyhow::Result<()> {
+        let text = message.text.as_ref().unwrap();
+        let token = match text.split("album-").nth(1) {
+            Some(token) => token.trim(),
+            None => return Ok(()),
+        };
+
+        let from = message
+            .from
+            .as_ref()
+            .context("album request missing user")?;
+        let lang = from.language_code.as_deref();
+
+        let results = match load_results(handler, from.id, token).await? {
+            Some(results) => results,
+            None => {
+                handler.send_generic_reply(message, "album-expired").await?;
+                return Ok(());
+            }
+        };
+
+        let images: Vec<_> = results.into_iter().filter(is_album_media).collect();
+
+        // Telegram allows up to 10 items in a media group and requires at
+        // least 2, so any single remaining image is sent on its own.
+        for chunk in images.chunks(10) {
+            if let [image] = chunk {
+                let send_photo = SendPhoto {
+                    chat_id: message.chat_id(),
+                    photo: FileType::Url(image.url.clone()),
+                    ..Default::default()
+                };
+
+                let sent = handler
+                    .make_request(&send_photo)
+                    .await
+                    .context("unable to send album photo")?;
+                handler.remember_sent_media(image, &sent).await;
+
+                continue;
+            }
+
+            let media = chunk
+                .iter()
+                .map(|image| {
+                    InputMedia::Photo(InputMediaPhoto {
+                        media: FileType::Url(image.url.clone()),
+                        ..Default::default()
+                    })
+                })
+                .collect();
+
+            let send_media_group = SendMediaGroup {
+                chat_id: message.chat_id(),
+                media,
+                ..Default::default()
+            };
+
+            let sent = handler
+                .make_request(&send_media_group)
+                .await
+                .context("unable to send album")?;
+
+            for (image, message) in chunk.iter().zip(sent.iter()) {
+                handler.remember_sent_media(image, message).await;
+            }
+        }
+
+        send_share_prompt(handler, message.chat_id(), lang, &images).await?;
+
+        Ok(())
+    }
+
     #[tracing::instrument(skip(self, handler), fields(video_id))]
     async fn video_progress(
         &self,
@@ -250,12 +445,7 @@ impl Handler for InlineHandler {
         "inline"
     }
 
-    async fn handle(
-        &self,
-        handler: &MessageHandler,
-        update: &Update,
-        _command: Option<&Command>,
-    ) -> anyhow::Result<Status> {
+    async fn handle(&self, handler: &MessageHandler, update: &Update) -> anyhow::Result<Status> {
         if let Some(message) = &update.message {
             match message.get_command() {
                 Some(cmd) if cmd.name == "/start" => {
@@ -263,6 +453,9 @@ impl Handler for InlineHandler {
                         if text.contains("process-") {
                             self.process_video(handler, message).await?;
                             return Ok(Completed);
+                        } else if text.contains("album-") {
+                            self.send_album(handler, message).await?;
+                            return Ok(Completed);
                         }
                     }
                 }
@@ -272,46 +465,145 @@ impl Handler for InlineHandler {
 
         let inline = needs_field!(update, inline_query);
 
-        let links: Vec<_> = handler.finder.links(&inline.query).collect();
-        let mut results: Vec<PostInfo> = Vec::new();
+        tracing::info!(query = ?inline.query, offset = ?inline.offset, "got query");
 
-        tracing::info!(query = ?inline.query, "got query");
-        tracing::debug!(?links, "found links");
+        if let Some(token) = inline.query.trim().strip_prefix(REVERSE_SHARE_PREFIX) {
+            self.send_reverse_share(handler, inline, token).await?;
+            return Ok(Completed);
+        }
 
-        // Lock sites in order to find which of these links are usable
-        {
-            let mut sites = handler.sites.lock().await;
-            let links = links.iter().map(|link| link.as_str()).collect();
-            find_images(&inline.from, links, &mut sites, &mut |info| {
-                results.extend(info.results);
-            })
-            .await
-            .context("unable to find images")?;
+        // Results prepared in a private chat are shared exactly as they were
+        // instead of loading links again.
+        let shared_token = inline.query.trim().strip_prefix(SHARED_RESULTS_PREFIX);
+
+        // Additional pages reuse the results from the first page instead of
+        // having to load everything again.
+        let (token, page, mut results, limited) = match get_cached_results(handler, inline).await? {
+            (token, page, Some(results)) => (token, page, results, false),
+            (_token, page, None) => {
+                let (results, limited) = match shared_token {
+                    Some(shared_token) => (
+                        load_shared_results(handler, shared_token)
+                            .await?
+                            .unwrap_or_default(),
+                        false,
+                    ),
+                    None => resolve_query(handler, inline).await?,
+                };
+                (generate_id(), page, results, limited)
+            }
+        };
+
+        let mut is_personal = results.iter().any(|result| result.personal);
+
+        // Users may opt out of ever seeing content from locked accounts,
+        // preventing accidentally sharing it somewhere it doesn't belong.
+        let mut hid_personal = false;
+        if is_personal && never_share_personal(handler, inline.from.id).await? {
+            results.retain(|result| !result.personal);
+            hid_personal = true;
+            is_personal = false;
+        }
+
+        let has_next_page = results.len() > (page + 1) * INLINE_PAGE_SIZE;
+        let has_album = page == 0 && results.iter().filter(|r| is_album_media(r)).count() > 1;
+
+        // Both loading the next page and sending an album need the results to
+        // be available later.
+        if has_next_page || has_album {
+            cache_results(handler, inline.from.id, &token, &results).await?;
         }
 
-        let is_personal = results.iter().any(|result| result.personal);
+        let next_offset = if has_next_page {
+            Some(format!("{}:{}", token, page + 1))
+        } else {
+            None
+        };
+
+        let captions = inline_captions(handler, inline.from.id).await?;
 
         let mut futs: FuturesOrdered<_> = results
             .iter()
-            .map(|result| process_result(handler, result, &inline.from))
+            .skip(page * INLINE_PAGE_SIZE)
+            .take(INLINE_PAGE_SIZE)
+            .map(|result| process_result(handler, result, &inline.from, captions))
             .collect();
 
         let mut responses: Vec<(ResultType, InlineQueryResult)> = vec![];
+
+        // Users resolving too many links only get results that were already
+        // loaded, so explain why some are missing.
+        if limited && page == 0 {
+            let article = handler
+                .get_fluent_bundle(inline.from.language_code.as_deref(), |bundle| {
+                    InlineQueryResult::article(
+                        generate_id(),
+                        get_message(bundle, "inline-limited-title", None).unwrap(),
+                        get_message(bundle, "inline-limited-body", None).unwrap(),
+                    )
+                })
+                .await;
+
+            responses.push((ResultType::Ready, article));
+        }
+
+        // Results from locked accounts are preceded by a warning so the user
+        // confirms they want to share them before picking one.
+        if is_personal && page == 0 {
+            let article = handler
+                .get_fluent_bundle(inline.from.language_code.as_deref(), |bundle| {
+                    InlineQueryResult::article(
+                        generate_id(),
+                        get_message(bundle, "inline-personal-title", None).unwrap(),
+                        get_message(bundle, "inline-personal-body", None).unwrap(),
+                    )
+                })
+                .await;
+
+            responses.push((ResultType::Ready, article));
+        }
+
         while let Some(item) = futs.next().await {
             if let Ok(Some(items)) = item {
                 responses.extend(items);
             }
         }
 
+        // Direct image links also get a text list of every source, for
+        // people who only want to share the links.
+        if page == 0 {
+            let links: Vec<_> = handler.finder.links(&inline.query).collect();
+            let direct_urls = results
+                .iter()
+                .filter(|result| links.iter().any(|link| link.as_str() == result.url))
+                .map(|result| result.url.as_str());
+
+            for url in direct_urls {
+                match build_source_list_result(handler, url, &inline.from).await {
+                    Ok(Some(article)) => responses.push((ResultType::Ready, article)),
+                    Ok(None) => (),
+                    Err(err) => tracing::warn!("unable to build source list: {:?}", err),
+                }
+            }
+        }
+
         // If we had no responses but the query was not empty, there were likely links
         // that we were unable to convert. We need to display that the links had no results.
-        if responses.is_empty() && !inline.query.is_empty() {
+        if responses.is_empty() && !inline.query.is_empty() && page == 0 {
+            let body = if hid_personal {
+                "inline-personal-hidden-body"
+            } else if shared_token.is_some() {
+                "inline-share-expired-body"
+            } else {
+                "inline-no-results-body"
+            };
+
             let article = handler
                 .get_fluent_bundle(inline.from.language_code.as_deref(), |bundle| {
                     InlineQueryResult::article(
                         generate_id(),
                         get_message(bundle, "inline-no-results-title", None).unwrap(),
-                        get_message(bundle, "inline-no-results-body", None).unwrap(),
+                        get_message(bundle, body, None).unwrap(),
                     )
                 })
                 .await;
@@ -337,6 +629,7 @@ impl Handler for InlineHandler {
             inline_query_id: inline.id.to_owned(),
             results: cleaned_responses,
             is_personal: Some(is_personal),
+            next_offset,
             ..Default::default()
         };
 
@@ -353,6 +646,19 @@ impl Handler for InlineHandler {
             answer_inline.switch_pm_parameter = Some("help".to_string());
         }
 
+        // Inline results can't be sent as an album, so offer to send them from
+        // a private chat instead.
+        if has_album {
+            let album_text = handler
+                .get_fluent_bundle(inline.from.language_code.as_deref(), |bundle| {
+                    get_message(bundle, "inline-album", None).unwrap()
+                })
+                .await;
+
+            answer_inline.switch_pm_text = Some(album_text);
+            answer_inline.switch_pm_parameter = Some(format!("album-{}", token));
+        }
+
         // If we had a video that needed to be processed, replace the switch pm
         // parameters to go and process that video.
         if let Some(video) = has_video {
@@ -370,6 +676,12 @@ impl Handler for InlineHandler {
             answer_inline.cache_time = Some(0);
         }
 
+        // Limited results are missing links that should load once the user
+        // slows down.
+        if limited {
+            answer_inline.cache_time = Some(0);
+        }
+
         handler
             .make_request(&answer_inline)
             .await
@@ -401,17 +713,690 @@ impl Handler for InlineHandler {
     }
 }
 
+#[async_trait]
+impl CallbackHandler for InlineHandler {
+    fn routes(&self) -> &'static [&'static str] {
+        &[routes::EDIT_IMAGE]
+    }
+
+    /// Edit an image that was prepared for sharing, then offer to share the
+    /// edited image instead.
+    async fn handle_callback(
+        &self,
+        handler: &MessageHandler,
+        callback_query: &CallbackQuery,
+        data: &CallbackData,
+    ) -> anyhow::Result<CallbackAnswer> {
+        let token: String = data.get(0).context("edit callback was missing token")?;
+        let pipeline = data
+            .args
+            .get(1)
+            .and_then(|edit| share_edit_pipeline(edit))
+            .context("edit callback had unknown edit")?;
+
+        if !allow_edit(handler, callback_query.from.id).await {
+            return Ok(CallbackAnswer::Alert("share-edit-limited"));
+        }
+
+        let mut results = match load_shared_results(handler, &token).await? {
+            Some(results) => results,
+            None => return Ok(CallbackAnswer::Alert("inline-share-expired-body")),
+        };
+
+        let result = match results.iter_mut().find(|result| is_album_media(result)) {
+            Some(result) => result,
+            None => return Ok(CallbackAnswer::Empty),
+        };
+
+        let data = download::download(&result.url).await?;
+        let image = handler
+            .media_cache
+            .store_edited(&handler.conn, &pipeline, &data)
+            .await
+            .context("unable to store edited image")?;
+
+        let file_type = image
+            .url
+            .rsplit('.')
+            .next()
+            .unwrap_or(&result.file_type)
+            .to_string();
+
+        *result = PostInfo {
+            url: image.url,
+            file_type,
+            image_dimensions: Some(image.dimensions),
+            image_size: None,
+            ..result.clone()
+        };
+
+        send_share_prompt(
+            handler,
+            callback_query.from.id.into(),
+            callback_query.from.language_code.as_deref(),
+            &results,
+        )
+        .await?;
+
+        Ok(CallbackAnswer::Toast("share-edit-complete"))
+    }
+}
+
+/// Build an article result containing a list of every source found for an
+/// image URL, if any were found.
+async fn build_source_list_result(
+    handler: &MessageHandler,
+    url: &str,
+    from: &User,
+) -> anyhow::Result<Option<InlineQueryResult>> {
+    let data = download_image(url).await?;
+    let hash = decode::hash_bytes(data).await?;
+
+    let lookup = lookup_single_hash(&handler.fapi, hash, Some(MAX_SOURCE_LIST_DISTANCE));
+    let mut matches = match tokio::time::timeout(std::time::Duration::from_secs(4), lookup).await {
+        Ok(matches) => matches?,
+        Err(_) => {
+            tracing::warn!("source list lookup timed out");
+            return Ok(None);
+        }
+    };
+
+    if matches.is_empty() {
+        return Ok(None);
+    }
+
+    sort_results(&handler.conn, from.id, &mut matches).await?;
+
+    let article = handler
+        .get_fluent_bundle(from.language_code.as_deref(), |bundle| {
+            let mut items = vec![get_message(bundle, "inline-sources-header", None).unwrap()];
+
+            for file in &matches {
+                let mut args = fluent::FluentArgs::new();
+                args.insert("link", file.url().into());
+
+                let item = if let Some(rating) = get_rating_bundle_name(&file.rating) {
+                    let rating = get_message(bundle, rating, None).unwrap();
+                    args.insert("rating", rating.into());
+
+                    get_message(bundle, "reverse-multiple-item", Some(args)).unwrap()
+                } else {
+                    get_message(bundle, "reverse-multiple-item-unknown", Some(args)).unwrap()
+                };
+
+                items.push(item);
+            }
+
+            let mut args = fluent::FluentArgs::new();
+            args.insert("count", matches.len().into());
+
+            InlineQueryResult::article(
+                generate_id(),
+                get_message(bundle, "inline-sources-title", Some(args)).unwrap(),
+                items.join("\n"),
+            )
+        })
+        .await;
+
+    Ok(Some(article))
+}
+
+/// Check if a user has chosen to never share results from locked accounts.
+async fn never_share_personal(handler: &MessageHandler, user_id: i64) -> anyhow::Result<bool> {
+    let never_share: Option<bool> =
+        UserConfig::get(&handler.conn, UserConfigKey::NeverSharePersonal, user_id)
+            .await
+            .context("unable to get personal sharing preference")?;
+
+    Ok(never_share.unwrap_or(false))
+}
+
+/// Get how a user wants text from posts to be used as captions.
+async fn inline_captions(handler: &MessageHandler, user_id: i64) -> anyhow::Result<InlineCaptions> {
+    let captions: Option<InlineCaptions> =
+        UserConfig::get(&handler.conn, UserConfigKey::InlineCaptions, user_id)
+            .await
+            .context("unable to get inline caption preference")?;
+
+    Ok(captions.unwrap_or_default())
+}
+
+/// Find all images from the links in an inline query.
+///
+/// Links the user recently loaded are reused. If loading the rest would put
+/// the user over their limit, only those already loaded are included and the
+/// results are marked as limited.
+async fn resolve_query(
+    handler: &MessageHandler,
+    inline: &InlineQuery,
+) -> anyhow::Result<(Vec<PostInfo>, bool)> {
+    let links: Vec<_> = handler.finder.links(&inline.query).collect();
+
+    tracing::debug!(?links, "found links");
+
+    let links: Vec<_> = links.iter().map(|link| link.as_str()).collect();
+
+    let mut link_results = Vec::with_capacity(links.len());
+    for link in &links {
+        link_results.push(load_link_results(handler, inline.from.id, link).await?);
+    }
+
+    let uncached = link_results.iter().filter(|item| item.is_none()).count();
+    let limited = uncached > 0 && !allow_links(handler, inline.from.id, uncached).await?;
+    if limited {
+        tracing::info!(
+            uncached,
+            "user is over inline link limit, only using cached results"
+        );
+    }
+
+    let mut results: Vec<PostInfo> = Vec::new();
+
+    // Lock sites in order to find which of these links are usable
+    let mut sites = handler.sites.lock().await;
+    let credentials = UserCredentials::new(handler.conn.clone(), inline.from.id);
+
+    for (link, cached) in links.into_iter().zip(link_results) {
+        if let Some(cached) = cached {
+            if let Some(post) = cached.first() {
+                record_site_load(&post.site_name, true, 0);
+            }
+
+            results.extend(cached);
+            continue;
+        }
+
+        if limited {
+            continue;
+        }
+
+        let mut found = Vec::new();
+        let images = find_images(&credentials, vec![link], &mut sites, &mut |info| {
+            found.extend(info.results);
+        })
+        .await;
+        super::bans::check_rate_limited(handler, inline.from.id, images)
+            .await
+            .context("unable to find images")?;
+
+        cache_link_results(handler, inline.from.id, link, &found).await?;
+        results.extend(found);
+    }
+
+    Ok((results, limited))
+}
+
+/// Check if a user may edit another image, counting it against the user's
+/// edits in the current window.
+///
+/// Edits are allowed if they couldn't be counted.
+async fn allow_edit(handler: &MessageHandler, user_id: i64) -> bool {
+    let now = match std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH) {
+        Ok(now) => now.as_secs(),
+        Err(_err) => return true,
+    };
+    let key = format!("edit-limit:{}:{}", user_id, now / EDIT_LIMIT_WINDOW);
+
+    let mut redis = handler.redis.clone();
+    let count: Result<(u32,), _> = redis::pipe()
+        .atomic()
+        .incr(&key, 1)
+        .expire(&key, EDIT_LIMIT_WINDOW as usize)
+        .ignore()
+        .query_async(&mut redis)
+        .await;
+
+    match count {
+        Ok((count,)) => count <= EDIT_LIMIT,
+        Err(err) => {
+            tracing::warn!("unable to count image edits: {:?}", err);
+            true
+        }
+    }
+}
+
+/// Check if a user may resolve more links, counting them against the user's
+/// sliding window of recently resolved links if so.
+async fn allow_links(handler: &MessageHandler, user_id: i64, count: usize) -> anyhow::Result<bool> {
+    let limit = handler.settings().inline_link_limit;
+    let key = format!("inline-limit:{}", user_id);
+
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_millis() as u64;
+    let window_start = now.saturating_sub(INLINE_LIMIT_WINDOW * 1000);
+
+    // Links are pruned, counted, and recorded in a single script, so
+    // concurrent queries can't each see room under the limit and both add
+    // their links.
+    let script = redis::Script::new(
+        r#"
+        redis.call('ZREMRANGEBYSCORE', KEYS[1], 0, ARGV[1])
+        local recent = redis.call('ZCARD', KEYS[1])
+        if recent + #ARGV - 4 > tonumber(ARGV[2]) then
+            return 0
+        end
+        for i = 5, #ARGV do
+            redis.call('ZADD', KEYS[1], ARGV[4], ARGV[i])
+        end
+        redis.call('EXPIRE', KEYS[1], ARGV[3])
+        return 1
+        "#,
+    );
+    let mut invocation = script.prepare_invoke();
+    invocation
+        .key(&key)
+        .arg(window_start)
+        .arg(limit)
+        .arg(INLINE_LIMIT_WINDOW)
+        .arg(now);
+
+    for _ in 0..count {
+        invocation.arg(format!("{}:{}", now, generate_id()));
+    }
+
+    let mut redis = handler.redis.clone();
+    let allowed: bool = invocation
+        .invoke_async(&mut redis)
+        .await
+        .context("unable to count recent inline links")?;
+
+    Ok(allowed)
+}
+
+/// Load the results a user recently got for a link.
+async fn load_link_results(
+    handler: &MessageHandler,
+    user_id: i64,
+    link: &str,
+) -> anyhow::Result<Option<Vec<PostInfo>>> {
+    use redis::AsyncCommands;
+
+    let key = format!("inline-link:{}:{}", user_id, link);
+
+    let mut redis = handler.redis.clone();
+    let data: Option<Vec<u8>> = redis
+        .get(&key)
+        .await
+        .context("unable to get cached link results")?;
+
+    match data {
+        Some(data) => Ok(Some(
+            serde_json::from_slice(&data).context("unable to parse link results")?,
+        )),
+        None => Ok(None),
+    }
+}
+
+/// Store the results for a link so the user can get them again without
+/// loading the link from the site.
+///
+/// Results are kept for each user as they may depend on the user's
+/// credentials.
+async fn cache_link_results(
+    handler: &MessageHandler,
+    user_id: i64,
+    link: &str,
+    results: &[PostInfo],
+) -> anyhow::Result<()> {
+    use redis::AsyncCommands;
+
+    let key = format!("inline-link:{}:{}", user_id, link);
+    let data = serde_json::to_vec(results)?;
+
+    let mut redis = handler.redis.clone();
+    redis
+        .set_ex::<_, _, ()>(&key, data, INLINE_RESULTS_TTL)
+        .await
+        .context("unable to cache link results")?;
+
+    Ok(())
+}
+
+/// Parse the offset of an inline query and load the results for it, if they
+/// are still cached.
+///
+/// Offsets are in the format `token:page`. An empty or invalid offset is
+/// treated as the first page.
+async fn get_cached_results(
+    handler: &MessageHandler,
+    inline: &InlineQuery,
+) -> anyhow::Result<(String, usize, Option<Vec<PostInfo>>)> {
+    let (token, page) = match parse_offset(&inline.offset) {
+        Some(offset) => offset,
+        None => return Ok((String::new(), 0, None)),
+    };
+
+    let results = load_results(handler, inline.from.id, token).await?;
+    if results.is_none() {
+        tracing::debug!("inline results expired, loading again");
+    }
+
+    Ok((token.to_string(), page, results))
+}
+
+/// Load previously cached results for an inline query.
+async fn load_results(
+    handler: &MessageHandler,
+    user_id: i64,
+    token: &str,
+) -> anyhow::Result<Option<Vec<PostInfo>>> {
+    use redis::AsyncCommands;
+
+    let key = format!("inline-results:{}:{}", user_id, token);
+
+    let mut redis = handler.redis.clone();
+    let data: Option<Vec<u8>> = redis
+        .get(&key)
+        .await
+        .context("unable to get cached inline results")?;
+
+    match data {
+        Some(data) => Ok(Some(
+            serde_json::from_slice(&data).context("unable to parse inline results")?,
+        )),
+        None => Ok(None),
+    }
+}
+
+/// Store results for an inline query so additional pages can be loaded.
+async fn cache_results(
+    handler: &MessageHandler,
+    user_id: i64,
+    token: &str,
+    results: &[PostInfo],
+) -> anyhow::Result<()> {
+    use redis::AsyncCommands;
+
+    let key = format!("inline-results:{}:{}", user_id, token);
+    let data = serde_json::to_vec(results)?;
+
+    let mut redis = handler.redis.clone();
+    redis
+        .set_ex::<_, _, ()>(&key, data, INLINE_RESULTS_TTL)
+        .await
+        .context("unable to cache inline results")?;
+
+    Ok(())
+}
+
+/// Store a photo sourced in a private chat so it can be shared, returning the
+/// inline query that shares it.
+pub(crate) async fn save_reverse_share(
+    handler: &MessageHandler,
+    share: &ReverseShare,
+) -> anyhow::Result<String> {
+    use redis::AsyncCommands;
+
+    let token = share_token();
+    let key = format!("inline-reverse:{}", token);
+    let data = serde_json::to_vec(share)?;
+
+    let mut redis = handler.redis.clone();
+    redis
+        .set_ex::<_, _, ()>(&key, data, SHARE_TTL)
+        .await
+        .context("unable to save reverse search share")?;
+
+    Ok(format!("{}{}", REVERSE_SHARE_PREFIX, token))
+}
+
+/// Offer to share results that were sent to a private chat in other chats.
+///
+/// Sharing the exact results that were sent avoids loading the links again,
+/// which may give different results.
+pub(crate) async fn send_share_prompt(
+    handler: &MessageHandler,
+    chat_id: ChatID,
+    lang: Option<&str>,
+    results: &[PostInfo],
+) -> anyhow::Result<()> {
+    let token = save_shared_results(handler, results).await?;
+
+    // A single image can be edited before it's shared, which gives another
+    // prompt to share the edited image.
+    let editable = matches!(results, [result] if is_album_media(result));
+
+    let (share_text, share_button, edit_buttons) = handler
+        .get_fluent_bundle(lang, |bundle| {
+            let edit_buttons: Vec<_> = if editable {
+                SHARE_EDITS
+                    .iter()
+                    .map(|(edit, name)| (*edit, get_message(bundle, name, None).unwrap()))
+                    .collect()
+            } else {
+                vec![]
+            };
+
+            (
+                get_message(bundle, "album-share-message", None).unwrap(),
+                get_message(bundle, "album-share", None).unwrap(),
+                edit_buttons,
+            )
+        })
+        .await;
+
+    let mut inline_keyboard = vec![vec![InlineKeyboardButton {
+        text: share_button,
+        switch_inline_query: Some(format!("{}{}", SHARED_RESULTS_PREFIX, token)),
+        ..Default::default()
+    }]];
+
+    if !edit_buttons.is_empty() {
+        inline_keyboard.push(
+            edit_buttons
+                .into_iter()
+                .map(|(edit, text)| {
+                    Ok(InlineKeyboardButton {
+                        text,
+                        callback_data: Some(
+                            CallbackData::new(routes::EDIT_IMAGE)
+                                .arg(&token)
+                                .arg(edit)
+                                .encode()?,
+                        ),
+                        ..Default::default()
+                    })
+                })
+                .collect::<anyhow::Result<_>>()?,
+        );
+    }
+
+    let send_message = SendMessage {
+        chat_id,
+        text: share_text,
+        reply_markup: Some(ReplyMarkup::InlineKeyboardMarkup(InlineKeyboardMarkup {
+            inline_keyboard,
+        })),
+        ..Default::default()
+    };
+
+    handler
+        .make_request(&send_message)
+        .await
+        .context("unable to send share message")?;
+
+    Ok(())
+}
+
+/// Store results loaded in a private chat so they can be shared exactly as
+/// they were, returning the token to share them with.
+pub(crate) async fn save_shared_results(
+    handler: &MessageHandler,
+    results: &[PostInfo],
+) -> anyhow::Result<String> {
+    use redis::AsyncCommands;
+
+    let token = share_token();
+    let key = format!("inline-shared:{}", token);
+    let data = serde_json::to_vec(results)?;
+
+    let mut redis = handler.redis.clone();
+    redis
+        .set_ex::<_, _, ()>(&key, data, SHARE_TTL)
+        .await
+        .context("unable to save shared results")?;
+
+    Ok(token)
+}
+
+/// Load results loaded in a private chat, if they haven't expired.
+async fn load_shared_results(
+    handler: &MessageHandler,
+    token: &str,
+) -> anyhow::Result<Option<Vec<PostInfo>>> {
+    use redis::AsyncCommands;
+
+    let key = format!("inline-shared:{}", token);
+
+    let mut redis = handler.redis.clone();
+    let data: Option<Vec<u8>> = redis
+        .get(&key)
+        .await
+        .context("unable to get shared results")?;
+
+    match data {
+        Some(data) => Ok(Some(
+            serde_json::from_slice(&data).context("unable to parse shared results")?,
+        )),
+        None => Ok(None),
+    }
+}
+
+/// Get the edits for the name of an edit from a button.
+fn share_edit_pipeline(edit: &str) -> Option<ImagePipeline> {
+    match edit {
+        "downscale" => Some(ImagePipeline::new().downscale(SHARE_DOWNSCALE_SIZE)),
+        "jpeg" => Some(ImagePipeline::new().jpeg(JPEG_QUALITY)),
+        "alpha" => Some(ImagePipeline::new().strip_alpha()),
+        _ => None,
+    }
+}
+
+/// Generate a token short enough to comfortably type in an inline query.
+fn share_token() -> String {
+    use rand::Rng;
+
+    rand::thread_rng()
+        .sample_iter(&rand::distributions::Alphanumeric)
+        .take(SHARE_TOKEN_LENGTH)
+        .collect()
+}
+
+/// Load a photo sourced in a private chat, if it hasn't expired.
+async fn load_reverse_share(
+    handler: &MessageHandler,
+    token: &str,
+) -> anyhow::Result<Option<ReverseShare>> {
+    use redis::AsyncCommands;
+
+    let key = format!("inline-reverse:{}", token);
+
+    let mut redis = handler.redis.clone();
+    let data: Option<Vec<u8>> = redis
+        .get(&key)
+        .await
+        .context("unable to get reverse search share")?;
+
+    match data {
+        Some(data) => Ok(Some(
+            serde_json::from_slice(&data).context("unable to parse reverse search share")?,
+        )),
+        None => Ok(None),
+    }
+}
+
+/// If a result is an image that can be sent as part of an album.
+fn is_album_media(result: &PostInfo) -> bool {
+    matches!(result.file_type.as_ref(), "png" | "jpeg" | "jpg")
+}
+
+/// Split an inline query offset into the results token and page number.
+fn parse_offset(offset: &str) -> Option<(&str, usize)> {
+    let mut parts = offset.splitn(2, ':');
+    let token = parts.next().filter(|token| !token.is_empty())?;
+    let page = parts.next()?.parse().ok()?;
+
+    Some((token, page))
+}
+
+/// Convert a [PostInfo] struct into InlineQueryResults, prefixing the IDs of
+/// ready results with the site name so chosen results can be attributed.
+///
+/// Results are built with and without the post's extra caption as the user
+/// prefers.
+async fn process_result(
+    handler: &MessageHandler,
+    result: &PostInfo,
+    from: &User,
+    captions: InlineCaptions,
+) -> anyhow::Result<Option<Vec<(ResultType, InlineQueryResult)>>> {
+    let has_caption = result.extra_caption.is_some();
+
+    let items = if has_caption && captions == InlineCaptions::Never {
+        let result = PostInfo {
+            extra_caption: None,
+            ..result.clone()
+        };
+
+        build_results(handler, &result, from).await?
+    } else {
+        build_results(handler, result, from).await?
+    };
+
+    Ok(items.map(|mut items| {
+        // Captioned results are always built after the uncaptioned one.
+        if has_caption && captions == InlineCaptions::Always && items.len() > 1 {
+            items.remove(0);
+        }
+
+        items
+            .into_iter()
+            .map(|(result_type, mut item)| {
+                if result_type == ResultType::Ready {
+                    item.id = format!("{}:{}", result.site_name, item.id);
+                }
+
+                (result_type, item)
+            })
+            .collect()
+    }))
+}
+
+/// Get the site name from the ID of a result created by [`process_result`].
+pub(crate) fn site_from_result_id(result_id: &str) -> Option<&str> {
+    result_id
+        .split_once(':')
+        .map(|(site, _id)| site)
+        .filter(|site| !site.is_empty())
+}
+
 /// Convert a [PostInfo] struct into an InlineQueryResult.
 ///
 /// It adds an inline keyboard for the direct link and source if available.
-async fn process_result(
+async fn build_results(
     handler: &MessageHandler,
     result: &PostInfo,
     from: &User,
 ) -> anyhow::Result<Option<Vec<(ResultType, InlineQueryResult)>>> {
-    let direct = handler
+    let (direct, source) = handler
         .get_fluent_bundle(from.language_code.as_deref(), |bundle| {
-            get_message(bundle, "inline-direct", None).unwrap()
+            let direct = get_message(bundle, "inline-direct", None).unwrap();
+
+            // Show which file this is when the post has several.
+            let source = match (result.file_number, result.files_count) {
+                (Some(number), Some(count)) => {
+                    let mut args = fluent::FluentArgs::new();
+                    args.insert("site", result.site_name.to_string().into());
+                    args.insert("number", number.into());
+                    args.insert("count", count.into());
+
+                    get_message(bundle, "inline-source-file", Some(args)).unwrap()
+                }
+                _ => result.site_name.to_string(),
+            };
+
+            (direct, source)
         })
         .await;
 
@@ -423,10 +1408,8 @@ async fn process_result(
     }];
 
     if let Some(source_link) = &result.source_link {
-        let text = result.site_name.to_string();
-
         row.push(InlineKeyboardButton {
-            text,
+            text: source,
             url: Some(source_link.clone()),
             callback_data: None,
             ..Default::default()
@@ -437,12 +1420,29 @@ async fn process_result(
         inline_keyboard: vec![row],
     };
 
-    let thumb_url = result.thumb.clone().unwrap_or_else(|| result.url.clone());
+    if let Some(results) = build_cached_result(handler, result, &keyboard).await? {
+        return Ok(Some(results));
+    }
 
-    match result.file_type.as_ref() {
-        "png" | "jpeg" | "jpg" => Ok(Some(
+    // Images without thumbnails get one generated once they're downloaded,
+    // but other types need a proxy or Telegram loads the full file.
+    let thumb_url = result.thumb.clone().or_else(|| {
+        handler
+            .config
+            .thumbnail_proxy
+            .as_deref()
+            .map(|endpoint| thumbnail_proxy_url(endpoint, &result.url))
+    });
+
+    if matches!(result.file_type.as_ref(), "png" | "jpeg" | "jpg") {
+        return Ok(Some(
             build_image_result(handler, result, thumb_url, &keyboard).await?,
-        )),
+        ));
+    }
+
+    let thumb_url = thumb_url.unwrap_or_else(|| result.url.clone());
+
+    match result.file_type.as_ref() {
         "webm" => {
             let source = match &result.source_link {
                 Some(link) => link.to_owned(),
@@ -473,14 +1473,71 @@ async fn process_result(
     }
 }
 
+/// Build results from a file ID the bot already has for this media, if one
+/// exists. These load instantly and avoid Telegram's limits on URL results.
+async fn build_cached_result(
+    handler: &MessageHandler,
+    result: &PostInfo,
+    keyboard: &InlineKeyboardMarkup,
+) -> anyhow::Result<Option<Vec<(ResultType, InlineQueryResult)>>> {
+    if !matches!(result.file_type.as_ref(), "png" | "jpeg" | "jpg" | "gif") {
+        return Ok(None);
+    }
+
+    let url_id = {
+        let sites = handler.sites.lock().await;
+        post_url_id(&sites, result)
+    };
+
+    let url_id = match url_id {
+        Some(url_id) => url_id,
+        None => return Ok(None),
+    };
+
+    let cached = match MediaFileId::get(&handler.conn, &url_id, &result.url).await? {
+        Some(cached) => cached,
+        None => return Ok(None),
+    };
+
+    tracing::debug!(%url_id, "using cached file id for result");
+
+    let build = |caption: Option<String>| {
+        let mut item = match cached.media_type {
+            MediaType::Photo => {
+                InlineQueryResult::cached_photo(generate_id(), cached.file_id.clone())
+            }
+            MediaType::Animation => {
+                InlineQueryResult::cached_gif(generate_id(), cached.file_id.clone())
+            }
+        };
+        item.reply_markup = Some(keyboard.clone());
+
+        match item.content {
+            InlineQueryType::CachedPhoto(ref mut photo) => photo.caption = caption,
+            InlineQueryType::CachedGif(ref mut gif) => gif.caption = caption,
+            _ => (),
+        }
+
+        (ResultType::Ready, item)
+    };
+
+    let mut results = vec![build(None)];
+
+    if let Some(message) = &result.extra_caption {
+        results.push(build(Some(message.to_owned())));
+    }
+
+    Ok(Some(results))
+}
+
 async fn build_image_result(
     handler: &MessageHandler,
     result: &PostInfo,
-    thumb_url: String,
+    thumb_url: Option<String>,
     keyboard: &InlineKeyboardMarkup,
 ) -> anyhow::Result<Vec<(ResultType, InlineQueryResult)>> {
     let mut result = result.to_owned();
-    result.thumb = Some(thumb_url);
+    result.thumb = thumb_url;
 
     // There is a bit of processing required to figure out how to handle an
     // image before sending it off to Telegram. First, we check if the config
@@ -492,7 +1549,7 @@ async fn build_image_result(
     //
     // [^1]: https://github.com/telegramdesktop/tdesktop/issues/4580
     let data = download_image(&result.url).await?;
-    let result = if handler.config.cache_all_images.unwrap_or(false) {
+    let result = if handler.settings().cache_all_images {
         cache_post(
             &handler.conn,
             &handler.s3,
@@ -502,6 +1559,11 @@ async fn build_image_result(
             &data,
         )
         .await?
+    } else if handler.media_cache.should_rehost(&result) {
+        handler
+            .media_cache
+            .rehost_data(&handler.conn, &result, &data)
+            .await?
     } else {
         let result = size_post(&result, &data).await?;
 
@@ -520,6 +1582,27 @@ async fn build_image_result(
         }
     };
 
+    let result = match thumbnail_post(
+        &handler.conn,
+        &handler.s3,
+        &handler.config.s3_bucket,
+        &handler.config.s3_url,
+        &result,
+        &data,
+    )
+    .await
+    {
+        Ok(result) => result,
+        Err(err) => {
+            tracing::warn!("unable to generate thumbnail: {:?}", err);
+
+            PostInfo {
+                thumb: Some(result.url.clone()),
+                ..result
+            }
+        }
+    };
+
     let mut photo = InlineQueryResult::photo(
         generate_id(),
         result.url.to_owned(),
@@ -639,7 +1722,7 @@ fn build_mp4_result(
         result
             .title
             .clone()
-            .unwrap_or_else(|| result.site_name.to_owned()),
+            .unwrap_or_else(|| result.site_name.to_string()),
     );
     video.reply_markup = Some(keyboard.clone());
 
@@ -677,3 +1760,41 @@ fn build_gif_result(
 
     results
 }
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_parse_offset() {
+        use super::parse_offset;
+
+        assert_eq!(parse_offset(""), None, "empty offset should be first page");
+        assert_eq!(
+            parse_offset("abc:2"),
+            Some(("abc", 2)),
+            "should parse token and page"
+        );
+        assert_eq!(parse_offset(":2"), None, "token must not be empty");
+        assert_eq!(parse_offset("abc:x"), None, "page must be a number");
+    }
+
+    #[test]
+    fn test_site_from_result_id() {
+        use super::site_from_result_id;
+
+        assert_eq!(
+            site_from_result_id("FurAffinity:abc"),
+            Some("FurAffinity"),
+            "should get site from tagged result"
+        );
+        assert_eq!(
+            site_from_result_id("direct link:abc"),
+            Some("direct link"),
+            "site names may contain spaces"
+        );
+        assert_eq!(
+            site_from_result_id("abc"),
+            None,
+            "untagged results should not have a site"
+        );
+    }
+}
@@ -0,0 +1,147 @@
+use anyhow::Context;
+use tgbotapi::{
+    requests::{ReplyMarkup, SendMessage},
+    ChatMemberStatus, ChatMemberUpdated, ChatType, InlineKeyboardButton, InlineKeyboardMarkup,
+};
+
+use crate::{MessageHandler, STARTING_ARTWORK};
+use foxbot_models::{ChatDetails, Chats};
+use foxbot_utils::{bot_api::GetChatMemberCount, get_message};
+
+/// Keep track of the chats the bot is in as it's added and removed, welcoming
+/// chats it was added to and forgetting the settings of chats it was removed
+/// from.
+pub(super) async fn handle_membership(
+    handler: &MessageHandler,
+    my_chat_member: &ChatMemberUpdated,
+) -> anyhow::Result<()> {
+    let chat = &my_chat_member.chat;
+
+    let chat_type = match chat.chat_type {
+        ChatType::Private => return Ok(()),
+        ChatType::Group => "group",
+        ChatType::Supergroup => "supergroup",
+        ChatType::Channel => "channel",
+    };
+
+    let was_member = is_member(&my_chat_member.old_chat_member.status);
+    let is_member = is_member(&my_chat_member.new_chat_member.status);
+
+    // Counting members fails once the bot is no longer in the chat.
+    let member_count = if is_member {
+        let get_member_count = GetChatMemberCount {
+            chat_id: chat.id.into(),
+        };
+
+        match handler.make_request(&get_member_count).await {
+            Ok(count) => Some(count),
+            Err(err) => {
+                tracing::warn!("unable to get chat member count: {:?}", err);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let details = ChatDetails {
+        title: chat.title.as_deref(),
+        chat_type,
+        member_count,
+    };
+
+    Chats::update(&handler.conn, chat.id, &details, is_member).await?;
+
+    match (was_member, is_member) {
+        (false, true) => {
+            tracing::info!(chat_id = chat.id, chat_type, "bot was added to chat");
+
+            send_onboarding(handler, my_chat_member)
+                .await
+                .context("unable to send onboarding message")?;
+        }
+        (true, false) => {
+            tracing::info!(chat_id = chat.id, chat_type, "bot was removed from chat");
+
+            Chats::remove_settings(&handler.conn, chat.id)
+                .await
+                .context("unable to remove settings for chat")?;
+        }
+        _ => (),
+    }
+
+    Ok(())
+}
+
+fn is_member(status: &ChatMemberStatus) -> bool {
+    !matches!(status, ChatMemberStatus::Left | ChatMemberStatus::Kicked)
+}
+
+fn random_artwork() -> String {
+    use rand::seq::SliceRandom;
+
+    STARTING_ARTWORK
+        .choose(&mut rand::thread_rng())
+        .unwrap()
+        .to_string()
+}
+
+/// Explain how to set up the bot. Groups are sent this directly, while for
+/// channels it goes to whoever added the bot instead of being posted.
+async fn send_onboarding(
+    handler: &MessageHandler,
+    my_chat_member: &ChatMemberUpdated,
+) -> anyhow::Result<()> {
+    let is_channel = my_chat_member.chat.chat_type == ChatType::Channel;
+    let lang = my_chat_member.from.language_code.as_deref();
+
+    let (text, try_me) = handler
+        .get_fluent_bundle(lang, |bundle| {
+            let name = if is_channel {
+                "onboarding-channel"
+            } else {
+                "onboarding-group"
+            };
+
+            (
+                get_message(bundle, name, None).unwrap(),
+                get_message(bundle, "welcome-try-me", None).unwrap(),
+            )
+        })
+        .await;
+
+    let send_message = if is_channel {
+        SendMessage {
+            chat_id: my_chat_member.from.id.into(),
+            text,
+            ..Default::default()
+        }
+    } else {
+        SendMessage {
+            chat_id: my_chat_member.chat.id.into(),
+            text,
+            reply_markup: Some(ReplyMarkup::InlineKeyboardMarkup(InlineKeyboardMarkup {
+                inline_keyboard: vec![vec![InlineKeyboardButton {
+                    text: try_me,
+                    switch_inline_query_current_chat: Some(random_artwork()),
+                    ..Default::default()
+                }]],
+            })),
+            ..Default::default()
+        }
+    };
+
+    // Whoever added the bot to a channel may have never started a chat with
+    // it, so it's fine if they can't be messaged.
+    match handler.make_request(&send_message).await {
+        Ok(_message) => Ok(()),
+        Err(tgbotapi::Error::Telegram(tgbotapi::TelegramError {
+            error_code: Some(403),
+            ..
+        })) if is_channel => {
+            tracing::debug!("unable to send channel onboarding to user");
+            Ok(())
+        }
+        Err(err) => Err(err.into()),
+    }
+}
@@ -0,0 +1,306 @@
+use async_trait::async_trait;
+use redis::AsyncCommands;
+use tgbotapi::{ChatType, Update};
+use tracing::Instrument;
+
+use super::Status::{self, Completed};
+use crate::{BoxedHandler, MessageHandler};
+use foxbot_utils::{chat_from_update, user_from_update};
+
+lazy_static::lazy_static! {
+    static ref HANDLING_DURATION: prometheus::Histogram = prometheus::register_histogram!("foxbot_handling_duration_seconds", "Request processing time duration").unwrap();
+    static ref HANDLER_DURATION: prometheus::HistogramVec = prometheus::register_histogram_vec!("foxbot_handler_duration_seconds", "Time for a handler to complete", &["handler"]).unwrap();
+    static ref STOPPED_UPDATES: prometheus::CounterVec = prometheus::register_counter_vec!("foxbot_middleware_stopped_total", "Number of updates stopped by middleware", &["middleware"]).unwrap();
+}
+
+pub type BoxedMiddleware = Box<dyn Middleware + Send + Sync>;
+
+/// A layer every update passes through before reaching the handlers.
+#[async_trait]
+pub trait Middleware: Send + Sync {
+    /// Name of the middleware, for debugging/logging uses.
+    fn name(&self) -> &'static str;
+
+    /// Called for every update, calling `next` to pass it on to the remaining
+    /// layers and handlers or returning early to stop it.
+    async fn handle(
+        &self,
+        handler: &MessageHandler,
+        update: &Update,
+        next: Next<'_>,
+    ) -> anyhow::Result<Status>;
+}
+
+/// Where an error came from, attached to errors from middleware and handlers.
+#[derive(Debug)]
+pub struct FailedIn(pub &'static str);
+
+impl std::fmt::Display for FailedIn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "error in {}", self.0)
+    }
+}
+
+/// The remaining layers and handlers for an update.
+pub struct Next<'a> {
+    middleware: &'a [BoxedMiddleware],
+    handlers: &'a [BoxedHandler],
+}
+
+impl<'a> Next<'a> {
+    pub fn new(middleware: &'a [BoxedMiddleware], handlers: &'a [BoxedHandler]) -> Self {
+        Self {
+            middleware,
+            handlers,
+        }
+    }
+
+    /// Run the update through the remaining layers, then give it to each
+    /// handler until one completes it.
+    pub async fn run(self, handler: &MessageHandler, update: &Update) -> anyhow::Result<Status> {
+        let (layer, middleware) = match self.middleware.split_first() {
+            Some(split) => split,
+            None => return self.dispatch(handler, update).await,
+        };
+
+        let next = Next {
+            middleware,
+            handlers: self.handlers,
+        };
+
+        layer
+            .handle(handler, update, next)
+            .instrument(tracing::debug_span!(
+                "middleware",
+                middleware = layer.name()
+            ))
+            .await
+            .map_err(|err| {
+                // Errors from later layers already say where they came from.
+                if err.downcast_ref::<FailedIn>().is_some() {
+                    err
+                } else {
+                    err.context(FailedIn(layer.name()))
+                }
+            })
+    }
+
+    async fn dispatch(self, handler: &MessageHandler, update: &Update) -> anyhow::Result<Status> {
+        for update_handler in self.handlers {
+            let hist = HANDLER_DURATION
+                .get_metric_with_label_values(&[update_handler.name()])
+                .unwrap()
+                .start_timer();
+
+            let status = update_handler
+                .handle(handler, update)
+                .instrument(tracing::info_span!(
+                    "handler_handle",
+                    handler = update_handler.name()
+                ))
+                .await;
+
+            match status {
+                Ok(Status::Ignored) => hist.stop_and_discard(),
+                Ok(Completed) => {
+                    tracing::debug!(handled_by = update_handler.name(), "Completed update");
+                    hist.stop_and_record();
+
+                    return Ok(Completed);
+                }
+                Err(err) => {
+                    hist.stop_and_record();
+
+                    return Err(err.context(FailedIn(update_handler.name())));
+                }
+            }
+        }
+
+        Ok(Status::Ignored)
+    }
+}
+
+/// Record that a layer stopped an update.
+fn stopped(middleware: &'static str) -> anyhow::Result<Status> {
+    STOPPED_UPDATES.with_label_values(&[middleware]).inc();
+
+    Ok(Completed)
+}
+
+/// Times how long each update takes to handle.
+pub struct MetricsMiddleware;
+
+#[async_trait]
+impl Middleware for MetricsMiddleware {
+    fn name(&self) -> &'static str {
+        "metrics"
+    }
+
+    async fn handle(
+        &self,
+        handler: &MessageHandler,
+        update: &Update,
+        next: Next<'_>,
+    ) -> anyhow::Result<Status> {
+        let _hist = HANDLING_DURATION.start_timer();
+
+        next.run(handler, update).await
+    }
+}
+
+/// Ignores updates from banned users and chats, whether they were banned in
+/// the config or with `/ban`.
+///
+/// This runs before anything else so banned updates are dropped as early as
+/// possible.
+pub struct BanMiddleware;
+
+#[async_trait]
+impl Middleware for BanMiddleware {
+    fn name(&self) -> &'static str {
+        "ban"
+    }
+
+    async fn handle(
+        &self,
+        handler: &MessageHandler,
+        update: &Update,
+        next: Next<'_>,
+    ) -> anyhow::Result<Status> {
+        let banned_ids = handler.settings().banned_ids;
+
+        let user_id = user_from_update(update).map(|user| user.id);
+        let chat_id = chat_from_update(update).map(|chat| chat.id);
+
+        let banned = [user_id, chat_id]
+            .iter()
+            .flatten()
+            .any(|id| banned_ids.contains(id))
+            || match handler
+                .bans
+                .is_banned(&handler.conn, user_id, chat_id)
+                .await
+            {
+                Ok(banned) => banned,
+                // Rather than ignoring everyone when the database is
+                // unavailable, allow the update through.
+                Err(err) => {
+                    tracing::warn!("unable to check bans: {:?}", err);
+                    false
+                }
+            };
+
+        if banned {
+            tracing::debug!(?user_id, ?chat_id, "ignoring update from banned id");
+            return stopped(self.name());
+        }
+
+        next.run(handler, update).await
+    }
+}
+
+/// Ignores updates from groups and channels that aren't allowed, if only some
+/// chats may use the bot.
+///
+/// Private chats and updates without a chat, like inline queries, are always
+/// allowed.
+pub struct AllowlistMiddleware;
+
+#[async_trait]
+impl Middleware for AllowlistMiddleware {
+    fn name(&self) -> &'static str {
+        "allowlist"
+    }
+
+    async fn handle(
+        &self,
+        handler: &MessageHandler,
+        update: &Update,
+        next: Next<'_>,
+    ) -> anyhow::Result<Status> {
+        let allowed_chats = match handler.settings().allowed_chats {
+            Some(allowed_chats) => allowed_chats,
+            None => return next.run(handler, update).await,
+        };
+
+        match chat_from_update(update) {
+            Some(chat)
+                if chat.chat_type != ChatType::Private && !allowed_chats.contains(&chat.id) =>
+            {
+                tracing::debug!(chat_id = chat.id, "ignoring update from chat not allowed");
+                stopped(self.name())
+            }
+            _ => next.run(handler, update).await,
+        }
+    }
+}
+
+/// Ignores updates from users who have sent too many in the last minute.
+///
+/// Counts are kept in Redis so they are shared between instances. Bot
+/// administrators are never limited.
+pub struct RateLimitMiddleware;
+
+impl RateLimitMiddleware {
+    /// Seconds in each window updates are counted in.
+    const WINDOW: u64 = 60;
+
+    /// Count an update from a user, returning how many they have sent in the
+    /// current window.
+    async fn count(handler: &MessageHandler, user_id: i64) -> anyhow::Result<u32> {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_secs();
+        let window = now / Self::WINDOW;
+        let key = format!("ratelimit:user:{}:{}", user_id, window);
+
+        let mut redis = handler.redis.clone();
+        let count: u32 = redis.incr(&key, 1).await?;
+        if count == 1 {
+            redis.expire::<_, ()>(&key, Self::WINDOW as usize).await?;
+        }
+
+        Ok(count)
+    }
+}
+
+#[async_trait]
+impl Middleware for RateLimitMiddleware {
+    fn name(&self) -> &'static str {
+        "ratelimit"
+    }
+
+    async fn handle(
+        &self,
+        handler: &MessageHandler,
+        update: &Update,
+        next: Next<'_>,
+    ) -> anyhow::Result<Status> {
+        let limit = match handler.settings().user_rate_limit {
+            Some(limit) => limit,
+            None => return next.run(handler, update).await,
+        };
+
+        let user = match user_from_update(update) {
+            Some(user) if !handler.is_bot_admin(user.id) => user,
+            _ => return next.run(handler, update).await,
+        };
+
+        // Rather than ignoring everyone when Redis is unavailable, allow the
+        // update through.
+        let count = match Self::count(handler, user.id).await {
+            Ok(count) => count,
+            Err(err) => {
+                tracing::warn!("unable to count updates for rate limit: {:?}", err);
+                return next.run(handler, update).await;
+            }
+        };
+
+        if count > limit {
+            tracing::debug!(user_id = user.id, count, "ignoring update over rate limit");
+            return stopped(self.name());
+        }
+
+        next.run(handler, update).await
+    }
+}
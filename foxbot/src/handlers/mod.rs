@@ -1,33 +1,53 @@
 use async_trait::async_trait;
 
+mod accounts;
+mod announcements;
+mod bans;
+mod callback_router;
 mod channel_photo;
 mod chosen_inline_handler;
+mod command_router;
 mod commands;
+mod discussion;
 mod error_cleanup;
 mod error_reply;
-mod group_add;
 mod group_source;
 mod inline_handler;
+mod membership;
+mod middleware;
 mod permissions;
 mod photo;
 pub mod settings;
 mod subscribe;
 mod twitter;
+mod watch;
 
 use crate::{MessageHandler, ServiceData};
+pub use accounts::AccountsHandler;
+pub use announcements::AnnouncementCommands;
+pub use bans::{BanCommands, BanList};
+pub use callback_router::{CallbackAnswer, CallbackHandler, CallbackRouter};
 pub use channel_photo::ChannelPhotoHandler;
 pub use chosen_inline_handler::ChosenInlineHandler;
-pub use commands::CommandHandler;
+pub use command_router::{
+    ArgKind, CommandArg, CommandArgs, CommandHandler, CommandRouter, CommandScope, CommandSpec,
+};
+pub use commands::GeneralCommands;
+pub use discussion::DiscussionHandler;
 pub use error_cleanup::ErrorCleanup;
 pub use error_reply::ErrorReplyHandler;
-pub use group_add::GroupAddHandler;
 pub use group_source::GroupSourceHandler;
 pub use inline_handler::InlineHandler;
+pub use middleware::{
+    AllowlistMiddleware, BanMiddleware, BoxedMiddleware, FailedIn, MetricsMiddleware, Middleware,
+    Next, RateLimitMiddleware,
+};
 pub use permissions::PermissionHandler;
 pub use photo::PhotoHandler;
 pub use settings::SettingsHandler;
 pub use subscribe::SubscribeHandler;
 pub use twitter::TwitterHandler;
+pub use watch::WatchHandler;
 
 #[derive(Debug, PartialEq)]
 pub enum Status {
@@ -48,7 +68,6 @@ pub trait Handler: Send + Sync {
         &self,
         handler: &MessageHandler,
         update: &tgbotapi::Update,
-        command: Option<&tgbotapi::Command>,
     ) -> anyhow::Result<Status>;
 
     /// Method called for every service update.
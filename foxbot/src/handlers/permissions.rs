@@ -21,7 +21,6 @@ impl Handler for PermissionHandler {
         &self,
         handler: &MessageHandler,
         update: &tgbotapi::Update,
-        _command: Option<&tgbotapi::Command>,
     ) -> anyhow::Result<Status> {
         if let tgbotapi::Update {
             message:
@@ -50,6 +49,28 @@ impl Handler for PermissionHandler {
     }
 }
 
+/// Check if a user is an administrator of a chat, using the saved
+/// permissions if they are known.
+pub(super) async fn is_chat_admin(
+    handler: &MessageHandler,
+    chat_id: i64,
+    user_id: i64,
+) -> anyhow::Result<bool> {
+    use tgbotapi::ChatMemberStatus::*;
+
+    if let Some(is_admin) = ChatAdmin::is_admin(&handler.conn, user_id, chat_id).await? {
+        return Ok(is_admin);
+    }
+
+    let get_chat_member = tgbotapi::requests::GetChatMember {
+        chat_id: chat_id.into(),
+        user_id,
+    };
+    let chat_member = handler.make_request(&get_chat_member).await?;
+
+    Ok(matches!(chat_member.status, Administrator | Creator))
+}
+
 async fn handle_my_chat_member(
     handler: &MessageHandler,
     my_chat_member: &Option<ChatMemberUpdated>,
@@ -77,13 +98,57 @@ async fn handle_my_chat_member(
         tracing::error!("Unable to set group delete permission: {:?}", err);
     }
 
+    if my_chat_member.chat.chat_type == tgbotapi::ChatType::Channel {
+        if let Err(err) = update_edit_permission(handler, my_chat_member).await {
+            tracing::error!("Unable to set channel edit permission: {:?}", err);
+        }
+    }
+
     if let Err(err) = Permissions::add_change(&handler.conn, my_chat_member).await {
         tracing::error!("Unable to save permission change: {:?}", err);
     }
 
+    if let Err(err) = super::membership::handle_membership(handler, my_chat_member).await {
+        tracing::error!("Unable to handle membership change: {:?}", err);
+    }
+
     Ok(true)
 }
 
+/// Remember if the bot can edit posts in a channel. When the permission is
+/// granted, administrators should be told again if it is lost.
+async fn update_edit_permission(
+    handler: &MessageHandler,
+    my_chat_member: &ChatMemberUpdated,
+) -> anyhow::Result<()> {
+    let chat_id = my_chat_member.chat.id;
+
+    let can_edit = my_chat_member
+        .new_chat_member
+        .can_edit_messages
+        .unwrap_or(false);
+
+    GroupConfig::set(
+        &handler.conn,
+        GroupConfigKey::HasEditPermission,
+        chat_id,
+        can_edit,
+    )
+    .await?;
+
+    if can_edit {
+        GroupConfig::set(
+            &handler.conn,
+            GroupConfigKey::EditPermissionNotified,
+            chat_id,
+            false,
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
 async fn handle_chat_member(
     handler: &MessageHandler,
     chat_member: &Option<ChatMemberUpdated>,
@@ -2,18 +2,21 @@ use anyhow::Context;
 use async_trait::async_trait;
 use tgbotapi::{
     requests::{ChatAction, SendMessage},
-    ChatType, Command, Update,
+    ChatType, Update,
 };
 
 use super::{
+    inline_handler::{save_reverse_share, ReverseShare, ReverseShareSource},
     Handler,
     Status::{self, Completed, Ignored},
 };
 use crate::MessageHandler;
 use foxbot_models::{GroupConfig, GroupConfigKey};
 use foxbot_utils::{
-    continuous_action, find_best_photo, get_message, match_image, needs_field, sort_results,
-    source_reply,
+    callback::{routes, CallbackData},
+    continuous_action, find_best_photo, get_message, needs_field,
+    reverse::{group_works, split_files, works_reply, ReverseMatch},
+    sort_results, SourceableFile,
 };
 
 pub struct PhotoHandler;
@@ -24,12 +27,7 @@ impl Handler for PhotoHandler {
         "photo"
     }
 
-    async fn handle(
-        &self,
-        handler: &MessageHandler,
-        update: &Update,
-        _command: Option<&Command>,
-    ) -> anyhow::Result<Status> {
+    async fn handle(&self, handler: &MessageHandler, update: &Update) -> anyhow::Result<Status> {
         let message = needs_field!(update, message);
         let photos = needs_field!(message, photo);
 
@@ -50,14 +48,20 @@ impl Handler for PhotoHandler {
         );
 
         let best_photo = find_best_photo(photos).unwrap();
-        let (hash, mut matches) = match_image(
-            &handler.bot,
-            &handler.conn,
-            &handler.fapi,
-            best_photo,
-            Some(3),
-        )
-        .await?;
+        let image = handler
+            .reverse
+            .load_image(
+                &handler.bot,
+                &handler.conn,
+                SourceableFile {
+                    file_id: &best_photo.file_id,
+                    file_unique_id: &best_photo.file_unique_id,
+                },
+            )
+            .await?;
+        let hash = image.hash;
+
+        let (mut matches, others) = split_files(handler.reverse.search(&image, Some(3)).await?);
         sort_results(
             &handler.conn,
             message.from.as_ref().unwrap().id,
@@ -69,7 +73,7 @@ impl Handler for PhotoHandler {
         // function, but we need custom handling to allow for subscribing to
         // updates on this hash.
 
-        if matches.is_empty() {
+        if matches.is_empty() && others.is_empty() {
             let (text, subscribe) = handler
                 .get_fluent_bundle(
                     message.from.as_ref().unwrap().language_code.as_deref(),
@@ -91,7 +95,9 @@ impl Handler for PhotoHandler {
                         tgbotapi::InlineKeyboardMarkup {
                             inline_keyboard: vec![vec![tgbotapi::InlineKeyboardButton {
                                 text: subscribe,
-                                callback_data: Some(format!("notify-{}", hash)),
+                                callback_data: Some(
+                                    CallbackData::new(routes::NOTIFY).arg(hash).encode()?,
+                                ),
                                 ..Default::default()
                             }]],
                         },
@@ -103,10 +109,44 @@ impl Handler for PhotoHandler {
             return Ok(Completed);
         }
 
-        let text = handler
+        // Sources FuzzySearch found come first, so each work is shown as the
+        // site the user prefers with the other sites it was posted to after
+        // it.
+        let works = group_works(
+            matches
+                .into_iter()
+                .map(ReverseMatch::from)
+                .chain(others)
+                .collect(),
+        );
+
+        // Inline queries can't contain photos, so the photo and its sources
+        // are saved for the user to send anywhere with a short query.
+        let share_query = save_reverse_share(
+            handler,
+            &ReverseShare {
+                file_id: best_photo.file_id.clone(),
+                sources: works
+                    .iter()
+                    .map(|work| ReverseShareSource {
+                        site_name: work.source.site_name.clone(),
+                        url: work.source.url.clone(),
+                    })
+                    .collect(),
+            },
+        )
+        .await?;
+
+        let (text, watch_reposts, share) = handler
             .get_fluent_bundle(
                 message.from.as_ref().unwrap().language_code.as_deref(),
-                |bundle| source_reply(&matches, bundle),
+                |bundle| {
+                    (
+                        works_reply(&works, bundle),
+                        get_message(bundle, "repost-watch", None).unwrap(),
+                        get_message(bundle, "reverse-share", None).unwrap(),
+                    )
+                },
             )
             .await;
 
@@ -125,6 +165,24 @@ impl Handler for PhotoHandler {
             text,
             disable_web_page_preview: Some(disable_preview),
             reply_to_message_id: Some(message.message_id),
+            reply_markup: Some(tgbotapi::requests::ReplyMarkup::InlineKeyboardMarkup(
+                tgbotapi::InlineKeyboardMarkup {
+                    inline_keyboard: vec![
+                        vec![tgbotapi::InlineKeyboardButton {
+                            text: watch_reposts,
+                            callback_data: Some(
+                                CallbackData::new(routes::REPOST).arg(hash).encode()?,
+                            ),
+                            ..Default::default()
+                        }],
+                        vec![tgbotapi::InlineKeyboardButton {
+                            text: share,
+                            switch_inline_query: Some(share_query),
+                            ..Default::default()
+                        }],
+                    ],
+                },
+            )),
             ..Default::default()
         };
 
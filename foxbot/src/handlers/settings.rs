@@ -1,121 +1,101 @@
 use anyhow::Context;
 use async_trait::async_trait;
 use tgbotapi::{
-    requests::{
-        AnswerCallbackQuery, EditMessageReplyMarkup, EditMessageText, ReplyMarkup, SendMessage,
-    },
-    CallbackQuery, Command, InlineKeyboardButton, InlineKeyboardMarkup, Message, Update,
+    requests::{EditMessageReplyMarkup, EditMessageText, ReplyMarkup, SendMessage},
+    CallbackQuery, InlineKeyboardButton, InlineKeyboardMarkup, Message,
 };
 
 use super::{
-    Handler,
-    Status::{self, Completed, Ignored},
+    CallbackAnswer, CallbackHandler, CommandArgs, CommandHandler, CommandScope, CommandSpec,
 };
 use crate::MessageHandler;
-use foxbot_models::{Sites, UserConfig, UserConfigKey};
-use foxbot_utils::{get_message, needs_field};
+use foxbot_models::{InlineCaptions, Sites, UserConfig, UserConfigKey};
+use foxbot_utils::{callback::CallbackData, get_message};
+
+/// Route of the settings buttons.
+const ROUTE: &str = "s";
 
 pub struct SettingsHandler;
 
 #[async_trait]
-impl Handler for SettingsHandler {
-    fn name(&self) -> &'static str {
-        "settings"
+impl CommandHandler for SettingsHandler {
+    fn commands(&self) -> &'static [CommandSpec] {
+        &[CommandSpec {
+            name: "/settings",
+            aliases: &[],
+            description: "command-settings",
+            args: &[],
+            scope: CommandScope::Any,
+        }]
     }
 
-    async fn handle(
+    async fn handle_command(
         &self,
         handler: &MessageHandler,
-        update: &Update,
-        command: Option<&Command>,
-    ) -> anyhow::Result<Status> {
-        if let Some(command) = command {
-            if command.name == "/settings" {
-                send_settings_message(handler, update.message.as_ref().unwrap())
-                    .await
-                    .context("unable to send settings message")?;
-                return Ok(Completed);
-            }
-        }
+        message: &Message,
+        _command: &str,
+        _args: &CommandArgs,
+    ) -> anyhow::Result<()> {
+        send_settings_message(handler, message)
+            .await
+            .context("unable to send settings message")?;
 
-        let callback_query = needs_field!(update, callback_query);
-        let data = needs_field!(callback_query, data);
+        Ok(())
+    }
+}
 
-        if !data.starts_with("s:") {
-            return Ok(Ignored);
-        }
+#[async_trait]
+impl CallbackHandler for SettingsHandler {
+    fn routes(&self) -> &'static [&'static str] {
+        &[ROUTE]
+    }
 
-        if data.starts_with("s:order:") {
-            return order(handler, callback_query, data).await;
-        }
+    async fn handle_callback(
+        &self,
+        handler: &MessageHandler,
+        callback_query: &CallbackQuery,
+        data: &CallbackData,
+    ) -> anyhow::Result<CallbackAnswer> {
+        let reply_message = match callback_query.message.as_deref() {
+            Some(message) => message,
+            None => return Ok(CallbackAnswer::Empty),
+        };
 
-        Ok(Completed)
+        match data.args.first().map(String::as_str) {
+            Some("order") => order(handler, callback_query, reply_message, data).await,
+            Some("personal") => personal(handler, callback_query, reply_message).await,
+            Some("captions") => captions(handler, callback_query, reply_message).await,
+            _ => Ok(CallbackAnswer::Empty),
+        }
     }
 }
 
 async fn order(
     handler: &MessageHandler,
     callback_query: &CallbackQuery,
-    data: &str,
-) -> anyhow::Result<Status> {
-    if data.ends_with(":e") {
-        let text = handler
-            .get_fluent_bundle(callback_query.from.language_code.as_deref(), |bundle| {
-                get_message(bundle, "settings-unsupported", None).unwrap()
-            })
-            .await;
-
-        let answer = AnswerCallbackQuery {
-            callback_query_id: callback_query.id.clone(),
-            text: Some(text),
-            ..Default::default()
-        };
-
-        handler
-            .make_request(&answer)
-            .await
-            .context("unable to answer order callback query")?;
-
-        return Ok(Completed);
+    reply_message: &Message,
+    data: &CallbackData,
+) -> anyhow::Result<CallbackAnswer> {
+    let site: Option<Sites> = data.get(1);
+    let pos = data.args.get(2).map(String::as_str);
+
+    if pos == Some("e") {
+        return Ok(CallbackAnswer::Toast("settings-unsupported"));
     }
 
-    if data.ends_with(":-") {
-        let site: Sites = data.split(':').nth(2).unwrap().parse().unwrap();
-
+    if let (Some(site), Some("-")) = (&site, pos) {
         let mut args = fluent::FluentArgs::new();
-        args.insert("name", site.as_str().into());
-
-        let text = handler
-            .get_fluent_bundle(callback_query.from.language_code.as_deref(), |bundle| {
-                get_message(bundle, "settings-move-unable", Some(args)).unwrap()
-            })
-            .await;
-
-        let answer = AnswerCallbackQuery {
-            callback_query_id: callback_query.id.clone(),
-            text: Some(text),
-            ..Default::default()
-        };
+        args.insert("name", site.as_str().to_string().into());
 
-        handler
-            .make_request(&answer)
-            .await
-            .context("unable to answer order callback query")?;
-
-        return Ok(Completed);
+        return Ok(CallbackAnswer::ToastWithArgs("settings-move-unable", args));
     }
 
-    let reply_message = needs_field!(callback_query, message);
     let from = reply_message
         .from
         .as_ref()
         .and_then(|from| from.language_code.as_deref());
 
-    let pos = data.split(':').nth(3);
-    if let Some(pos) = pos {
-        let site = data.split(':').nth(2).unwrap().parse().unwrap();
-        let pos: usize = pos.parse().unwrap();
-
+    if let (Some(site), Some(pos)) = (site, data.get::<usize>(2)) {
         let order: Option<Vec<String>> = UserConfig::get(
             &handler.conn,
             UserConfigKey::SiteSortOrder,
@@ -150,21 +130,6 @@ async fn order(
         .await
         .context("unable to set user sort order")?;
 
-        let mut args = fluent::FluentArgs::new();
-        args.insert("name", site.as_str().into());
-
-        let text = handler
-            .get_fluent_bundle(callback_query.from.language_code.as_deref(), |bundle| {
-                get_message(bundle, "settings-move-updated", Some(args)).unwrap()
-            })
-            .await;
-
-        let answer = AnswerCallbackQuery {
-            callback_query_id: callback_query.id.clone(),
-            text: Some(text),
-            ..Default::default()
-        };
-
         let keyboard = sort_order_keyboard(&handler.conn, callback_query.from.id).await?;
 
         let edit_message = EditMessageReplyMarkup {
@@ -174,13 +139,15 @@ async fn order(
             ..Default::default()
         };
 
-        futures::try_join!(
-            handler.make_request(&edit_message),
-            handler.make_request(&answer)
-        )
-        .context("unable to edit message or answer query")?;
+        handler
+            .make_request(&edit_message)
+            .await
+            .context("unable to edit sort order message")?;
 
-        return Ok(Completed);
+        let mut args = fluent::FluentArgs::new();
+        args.insert("name", site.as_str().to_string().into());
+
+        return Ok(CallbackAnswer::ToastWithArgs("settings-move-updated", args));
     }
 
     let text = handler
@@ -199,18 +166,106 @@ async fn order(
         ..Default::default()
     };
 
-    let answer = AnswerCallbackQuery {
-        callback_query_id: callback_query.id.clone(),
+    handler
+        .make_request(&edit_message)
+        .await
+        .context("unable to edit settings message")?;
+
+    Ok(CallbackAnswer::Empty)
+}
+
+async fn personal(
+    handler: &MessageHandler,
+    callback_query: &CallbackQuery,
+    reply_message: &Message,
+) -> anyhow::Result<CallbackAnswer> {
+    let never_share: Option<bool> = UserConfig::get(
+        &handler.conn,
+        UserConfigKey::NeverSharePersonal,
+        callback_query.from.id,
+    )
+    .await
+    .context("unable to query user personal preference")?;
+    let never_share = !never_share.unwrap_or(false);
+
+    UserConfig::set(
+        &handler.conn,
+        UserConfigKey::NeverSharePersonal,
+        callback_query.from.id,
+        never_share,
+    )
+    .await
+    .context("unable to set user personal preference")?;
+
+    let name = if never_share {
+        "settings-personal-updated-never"
+    } else {
+        "settings-personal-updated-allowed"
+    };
+
+    let lang = callback_query.from.language_code.as_deref();
+    let keyboard = main_keyboard(handler, callback_query.from.id, lang).await?;
+
+    let edit_message = EditMessageReplyMarkup {
+        message_id: Some(reply_message.message_id),
+        chat_id: reply_message.chat_id(),
+        reply_markup: Some(ReplyMarkup::InlineKeyboardMarkup(keyboard)),
         ..Default::default()
     };
 
-    futures::try_join!(
-        handler.make_request(&edit_message),
-        handler.make_request(&answer)
+    handler
+        .make_request(&edit_message)
+        .await
+        .context("unable to edit settings message")?;
+
+    Ok(CallbackAnswer::Toast(name))
+}
+
+async fn captions(
+    handler: &MessageHandler,
+    callback_query: &CallbackQuery,
+    reply_message: &Message,
+) -> anyhow::Result<CallbackAnswer> {
+    let captions: Option<InlineCaptions> = UserConfig::get(
+        &handler.conn,
+        UserConfigKey::InlineCaptions,
+        callback_query.from.id,
     )
-    .context("unable to edit message or answer callback query")?;
+    .await
+    .context("unable to query user caption preference")?;
+    let captions = captions.unwrap_or_default().next();
+
+    UserConfig::set(
+        &handler.conn,
+        UserConfigKey::InlineCaptions,
+        callback_query.from.id,
+        captions,
+    )
+    .await
+    .context("unable to set user caption preference")?;
+
+    let name = match captions {
+        InlineCaptions::Both => "settings-captions-updated-both",
+        InlineCaptions::Always => "settings-captions-updated-always",
+        InlineCaptions::Never => "settings-captions-updated-never",
+    };
 
-    Ok(Completed)
+    let lang = callback_query.from.language_code.as_deref();
+    let keyboard = main_keyboard(handler, callback_query.from.id, lang).await?;
+
+    let edit_message = EditMessageReplyMarkup {
+        message_id: Some(reply_message.message_id),
+        chat_id: reply_message.chat_id(),
+        reply_markup: Some(ReplyMarkup::InlineKeyboardMarkup(keyboard)),
+        ..Default::default()
+    };
+
+    handler
+        .make_request(&edit_message)
+        .await
+        .context("unable to edit settings message")?;
+
+    Ok(CallbackAnswer::Toast(name))
 }
 
 async fn send_settings_message(
@@ -222,19 +277,8 @@ async fn send_settings_message(
         .as_ref()
         .and_then(|user| user.language_code.as_deref());
 
-    let site_preference = handler
-        .get_fluent_bundle(from, |bundle| {
-            get_message(bundle, "settings-site-preference", None).unwrap()
-        })
-        .await;
-
-    let keyboard = InlineKeyboardMarkup {
-        inline_keyboard: vec![vec![InlineKeyboardButton {
-            text: site_preference,
-            callback_data: Some("s:order:".into()),
-            ..Default::default()
-        }]],
-    };
+    let user_id = message.from.as_ref().context("settings missing user")?.id;
+    let keyboard = main_keyboard(handler, user_id, from).await?;
 
     let text = handler
         .get_fluent_bundle(from, |bundle| {
@@ -262,6 +306,64 @@ async fn send_settings_message(
     Ok(sent_message)
 }
 
+async fn main_keyboard(
+    handler: &MessageHandler,
+    user_id: i64,
+    lang: Option<&str>,
+) -> anyhow::Result<InlineKeyboardMarkup> {
+    let never_share: Option<bool> =
+        UserConfig::get(&handler.conn, UserConfigKey::NeverSharePersonal, user_id)
+            .await
+            .context("unable to query user personal preference")?;
+
+    let personal_name = if never_share.unwrap_or(false) {
+        "settings-personal-never"
+    } else {
+        "settings-personal-allowed"
+    };
+
+    let captions: Option<InlineCaptions> =
+        UserConfig::get(&handler.conn, UserConfigKey::InlineCaptions, user_id)
+            .await
+            .context("unable to query user caption preference")?;
+
+    let captions_name = match captions.unwrap_or_default() {
+        InlineCaptions::Both => "settings-captions-both",
+        InlineCaptions::Always => "settings-captions-always",
+        InlineCaptions::Never => "settings-captions-never",
+    };
+
+    let (site_preference, personal, captions) = handler
+        .get_fluent_bundle(lang, |bundle| {
+            (
+                get_message(bundle, "settings-site-preference", None).unwrap(),
+                get_message(bundle, personal_name, None).unwrap(),
+                get_message(bundle, captions_name, None).unwrap(),
+            )
+        })
+        .await;
+
+    Ok(InlineKeyboardMarkup {
+        inline_keyboard: vec![
+            vec![InlineKeyboardButton {
+                text: site_preference,
+                callback_data: Some(CallbackData::new(ROUTE).arg("order").encode()?),
+                ..Default::default()
+            }],
+            vec![InlineKeyboardButton {
+                text: personal,
+                callback_data: Some(CallbackData::new(ROUTE).arg("personal").encode()?),
+                ..Default::default()
+            }],
+            vec![InlineKeyboardButton {
+                text: captions,
+                callback_data: Some(CallbackData::new(ROUTE).arg("captions").encode()?),
+                ..Default::default()
+            }],
+        ],
+    })
+}
+
 async fn sort_order_keyboard(
     conn: &sqlx::Pool<sqlx::Postgres>,
     user_id: i64,
@@ -283,32 +385,34 @@ async fn sort_order_keyboard(
     }
 
     for (idx, site) in sites.iter().enumerate() {
+        let order = CallbackData::new(ROUTE).arg("order").arg(site.as_str());
+
         let up = if idx == 0 {
-            format!("s:order:{}:-", site.as_str())
+            order.clone().arg("-")
         } else {
-            format!("s:order:{}:{}", site.as_str(), idx - 1)
+            order.clone().arg(idx - 1)
         };
 
         let down = if idx == sites.len() - 1 {
-            format!("s:order:{}:-", site.as_str())
+            order.clone().arg("-")
         } else {
-            format!("s:order:{}:{}", site.as_str(), idx + 1)
+            order.clone().arg(idx + 1)
         };
 
         buttons.push(vec![
             InlineKeyboardButton {
                 text: site.as_str().into(),
-                callback_data: Some(format!("s:order:{}:e", site.as_str())),
+                callback_data: Some(order.arg("e").encode()?),
                 ..Default::default()
             },
             InlineKeyboardButton {
                 text: "⬆".into(),
-                callback_data: Some(up),
+                callback_data: Some(up.encode()?),
                 ..Default::default()
             },
             InlineKeyboardButton {
                 text: "⬇".into(),
-                callback_data: Some(down),
+                callback_data: Some(down.encode()?),
                 ..Default::default()
             },
         ]);
@@ -1,13 +1,17 @@
+use anyhow::Context;
 use async_trait::async_trait;
-use tgbotapi::requests::{AnswerCallbackQuery, EditMessageReplyMarkup};
+use tgbotapi::requests::EditMessageReplyMarkup;
 
 use super::{
-    Handler,
-    Status::{self, Completed, Ignored},
+    CallbackAnswer, CallbackHandler, Handler,
+    Status::{self, Ignored},
 };
 use crate::{MessageHandler, ServiceData};
-use foxbot_models::Subscriptions;
-use foxbot_utils::{find_best_photo, get_faktory_custom, get_message};
+use foxbot_models::{RepostWatches, Subscriptions};
+use foxbot_utils::{
+    callback::{routes, CallbackData},
+    find_best_photo, get_faktory_custom, QUEUE_HIGH,
+};
 
 pub struct SubscribeHandler;
 
@@ -19,24 +23,9 @@ impl Handler for SubscribeHandler {
 
     async fn handle(
         &self,
-        handler: &MessageHandler,
-        update: &tgbotapi::Update,
-        _command: Option<&tgbotapi::Command>,
+        _handler: &MessageHandler,
+        _update: &tgbotapi::Update,
     ) -> anyhow::Result<Status> {
-        if let Some(callback_query) = &update.callback_query {
-            if !callback_query
-                .data
-                .as_deref()
-                .unwrap_or_default()
-                .starts_with("notify-")
-            {
-                return Ok(Ignored);
-            }
-
-            self.subscribe(handler, callback_query).await?;
-            return Ok(Completed);
-        }
-
         Ok(Ignored)
     }
 
@@ -45,56 +34,55 @@ impl Handler for SubscribeHandler {
         handler: &MessageHandler,
         service: &ServiceData,
     ) -> anyhow::Result<()> {
-        let hash = match service {
-            ServiceData::NewHash { hash } => *hash,
+        let (hash, site_id) = match service {
+            ServiceData::NewHash { hash, site_id } => (*hash, *site_id),
             _ => return Ok(()),
         };
 
-        let custom = get_faktory_custom();
+        let message = serde_json::to_value(hash.to_string())?;
+        let site_id = serde_json::to_value(site_id)?;
+        let mut job = faktory::Job::new("hash_new", vec![message, site_id]).on_queue(QUEUE_HIGH);
+        job.custom = get_faktory_custom();
 
-        let faktory = handler.faktory.clone();
-        tokio::task::spawn_blocking(move || {
-            let mut faktory = faktory.lock().unwrap();
-            let message = serde_json::to_value(hash.to_string()).unwrap();
-            let mut job =
-                faktory::Job::new("hash_new", vec![message]).on_queue("foxbot_background");
-            job.custom = custom;
-
-            faktory.enqueue(job).unwrap();
-        });
+        handler.enqueue(job).await?;
 
         Ok(())
     }
 }
 
+#[async_trait]
+impl CallbackHandler for SubscribeHandler {
+    fn routes(&self) -> &'static [&'static str] {
+        &[routes::NOTIFY, routes::REPOST, routes::REPOST_STOP]
+    }
+
+    async fn handle_callback(
+        &self,
+        handler: &MessageHandler,
+        callback_query: &tgbotapi::CallbackQuery,
+        data: &CallbackData,
+    ) -> anyhow::Result<CallbackAnswer> {
+        let hash: i64 = data.get(0).context("subscribe callback had invalid hash")?;
+
+        match data.route.as_str() {
+            routes::NOTIFY => self.subscribe(handler, callback_query, hash).await,
+            routes::REPOST => self.watch_reposts(handler, callback_query, hash).await,
+            routes::REPOST_STOP => self.stop_repost_watch(handler, callback_query, hash).await,
+            _ => Ok(CallbackAnswer::Empty),
+        }
+    }
+}
+
 impl SubscribeHandler {
     async fn subscribe(
         &self,
         handler: &MessageHandler,
         callback_query: &tgbotapi::CallbackQuery,
-    ) -> anyhow::Result<()> {
-        let data = callback_query.data.as_deref().unwrap();
-        let hash = data.strip_prefix("notify-").unwrap();
-        let hash: i64 = hash.parse()?;
-
+        hash: i64,
+    ) -> anyhow::Result<CallbackAnswer> {
         tracing::trace!(hash, "attempting to add subscription for hash");
 
-        let (message_id, photo_id) = match callback_query.message.as_deref() {
-            Some(tgbotapi::Message {
-                reply_to_message: Some(message),
-                ..
-            }) => (
-                Some(message.message_id),
-                message.photo.as_ref().and_then(|photo| {
-                    find_best_photo(photo)
-                        .iter()
-                        .next()
-                        .map(|photo| photo.file_id.clone())
-                }),
-            ),
-            Some(message) => (Some(message.message_id), None),
-            None => (None, None),
-        };
+        let (message_id, photo_id) = notify_target(callback_query);
 
         if let Err(err) = Subscriptions::add_subscription(
             &handler.conn,
@@ -108,23 +96,7 @@ impl SubscribeHandler {
             tracing::error!("unable to add hash subscription: {:?}", err);
             sentry::integrations::anyhow::capture_anyhow(&err);
 
-            let text = handler
-                .get_fluent_bundle(callback_query.from.language_code.as_deref(), |bundle| {
-                    get_message(bundle, "subscribe-error", None).unwrap()
-                })
-                .await;
-
-            handler
-                .bot
-                .make_request(&AnswerCallbackQuery {
-                    callback_query_id: callback_query.id.clone(),
-                    show_alert: Some(true),
-                    text: Some(text),
-                    ..Default::default()
-                })
-                .await?;
-
-            return Err(err);
+            return Ok(CallbackAnswer::Alert("subscribe-error"));
         }
 
         // TODO: it's possible FuzzySearch sent the hash in the time between
@@ -148,21 +120,83 @@ impl SubscribeHandler {
                 .await;
         }
 
-        let text = handler
-            .get_fluent_bundle(callback_query.from.language_code.as_deref(), |bundle| {
-                get_message(bundle, "subscribe-success", None).unwrap()
-            })
-            .await;
-
-        handler
-            .bot
-            .make_request(&AnswerCallbackQuery {
-                callback_query_id: callback_query.id.clone(),
-                text: Some(text),
-                ..Default::default()
-            })
-            .await?;
+        Ok(CallbackAnswer::Toast("subscribe-success"))
+    }
 
-        Ok(())
+    /// Keep watching for an image to be posted again, even though sources
+    /// were already found.
+    async fn watch_reposts(
+        &self,
+        handler: &MessageHandler,
+        callback_query: &tgbotapi::CallbackQuery,
+        hash: i64,
+    ) -> anyhow::Result<CallbackAnswer> {
+        let (message_id, photo_id) = notify_target(callback_query);
+
+        RepostWatches::add(
+            &handler.conn,
+            callback_query.from.id,
+            hash,
+            message_id,
+            photo_id.as_deref(),
+        )
+        .await?;
+
+        self.clear_buttons(handler, callback_query).await;
+
+        Ok(CallbackAnswer::Toast("repost-watching"))
+    }
+
+    async fn stop_repost_watch(
+        &self,
+        handler: &MessageHandler,
+        callback_query: &tgbotapi::CallbackQuery,
+        hash: i64,
+    ) -> anyhow::Result<CallbackAnswer> {
+        RepostWatches::remove(&handler.conn, callback_query.from.id, hash).await?;
+
+        self.clear_buttons(handler, callback_query).await;
+
+        Ok(CallbackAnswer::Toast("repost-stopped"))
+    }
+
+    /// Remove the buttons from the message of a callback query.
+    async fn clear_buttons(
+        &self,
+        handler: &MessageHandler,
+        callback_query: &tgbotapi::CallbackQuery,
+    ) {
+        if let Some(message) = callback_query.message.as_deref() {
+            let _ = handler
+                .bot
+                .make_request(&EditMessageReplyMarkup {
+                    chat_id: message.chat_id(),
+                    message_id: Some(message.message_id),
+                    reply_markup: None,
+                    ..Default::default()
+                })
+                .await;
+        }
+    }
+}
+
+/// Get the message and photo a notification for a callback query should
+/// reply to.
+fn notify_target(callback_query: &tgbotapi::CallbackQuery) -> (Option<i32>, Option<String>) {
+    match callback_query.message.as_deref() {
+        Some(tgbotapi::Message {
+            reply_to_message: Some(message),
+            ..
+        }) => (
+            Some(message.message_id),
+            message.photo.as_ref().and_then(|photo| {
+                find_best_photo(photo)
+                    .iter()
+                    .next()
+                    .map(|photo| photo.file_id.clone())
+            }),
+        ),
+        Some(message) => (Some(message.message_id), None),
+        None => (None, None),
     }
 }
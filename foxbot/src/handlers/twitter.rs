@@ -1,7 +1,8 @@
+use anyhow::Context;
 use async_trait::async_trait;
 
 use super::{
-    Handler,
+    CommandArgs, CommandHandler, CommandScope, CommandSpec, Handler,
     Status::{self, Completed, Ignored},
 };
 use crate::{Config, MessageHandler, ServiceData};
@@ -20,20 +21,7 @@ impl Handler for TwitterHandler {
         &self,
         handler: &MessageHandler,
         update: &tgbotapi::Update,
-        command: Option<&tgbotapi::Command>,
     ) -> anyhow::Result<Status> {
-        match command {
-            Some(cmd) if cmd.name == "/twitter" => {
-                let message = needs_field!(update, message);
-                let user = needs_field!(message, from);
-                return handle_command(handler, message, user)
-                    .await
-                    .map(|_| Completed);
-            }
-            Some(_) => return Ok(Ignored),
-            _ => (),
-        }
-
         match &update.callback_query {
             Some(tgbotapi::CallbackQuery {
                 data: Some(data), ..
@@ -70,18 +58,39 @@ impl Handler for TwitterHandler {
     }
 }
 
-async fn handle_command(
+#[async_trait]
+impl CommandHandler for TwitterHandler {
+    fn commands(&self) -> &'static [CommandSpec] {
+        &[CommandSpec {
+            name: "/twitter",
+            aliases: &[],
+            description: "command-twitter",
+            args: &[],
+            scope: CommandScope::Private,
+        }]
+    }
+
+    async fn handle_command(
+        &self,
+        handler: &MessageHandler,
+        message: &tgbotapi::Message,
+        _command: &str,
+        _args: &CommandArgs,
+    ) -> anyhow::Result<()> {
+        let user = message
+            .from
+            .as_ref()
+            .context("twitter command missing user")?;
+
+        send_account_message(handler, message, user).await
+    }
+}
+
+async fn send_account_message(
     handler: &MessageHandler,
     message: &tgbotapi::Message,
     user: &tgbotapi::User,
 ) -> anyhow::Result<()> {
-    if message.chat.chat_type != tgbotapi::ChatType::Private {
-        handler
-            .send_generic_reply(message, "twitter-private")
-            .await?;
-        return Ok(());
-    }
-
     if let Some(account) = Twitter::get_account(&handler.conn, user.id).await? {
         let access = get_access(&handler.config, account);
 
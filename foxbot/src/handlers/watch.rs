@@ -0,0 +1,319 @@
+use anyhow::Context;
+use async_trait::async_trait;
+use tgbotapi::{
+    requests::{AnswerCallbackQuery, EditMessageText, GetChatMember, ReplyMarkup, SendMessage},
+    CallbackQuery, Chat, InlineKeyboardButton, InlineKeyboardMarkup, Message, Update,
+};
+
+use super::{
+    ArgKind, CommandArg, CommandArgs, CommandHandler, CommandScope, CommandSpec, Handler,
+    Status::{self, Completed, Ignored},
+};
+use crate::MessageHandler;
+use foxbot_models::{ArtistWatches, ChatAdmin};
+use foxbot_utils::{get_message, needs_field, urls::normalize_url};
+
+/// Maximum number of pages a chat may watch.
+const MAX_WATCHES: i64 = 10;
+
+pub struct WatchHandler;
+
+#[async_trait]
+impl Handler for WatchHandler {
+    fn name(&self) -> &'static str {
+        "watch"
+    }
+
+    async fn handle(&self, handler: &MessageHandler, update: &Update) -> anyhow::Result<Status> {
+        let callback_query = needs_field!(update, callback_query);
+        let data = needs_field!(callback_query, data);
+
+        let id = match data.strip_prefix("w:remove:") {
+            Some(id) => id.parse().context("invalid watch id")?,
+            None => return Ok(Ignored),
+        };
+
+        remove_watch(handler, callback_query, id).await
+    }
+}
+
+#[async_trait]
+impl CommandHandler for WatchHandler {
+    fn commands(&self) -> &'static [CommandSpec] {
+        &[CommandSpec {
+            name: "/watch",
+            aliases: &[],
+            description: "command-watch",
+            args: &[CommandArg::optional("link", ArgKind::Word)],
+            scope: CommandScope::Any,
+        }]
+    }
+
+    async fn handle_command(
+        &self,
+        handler: &MessageHandler,
+        message: &Message,
+        _command: &str,
+        args: &CommandArgs,
+    ) -> anyhow::Result<()> {
+        let user = message.from.as_ref().context("watch missing user")?;
+
+        if !can_manage_watches(handler, &message.chat, user.id).await? {
+            handler
+                .send_generic_reply(message, "watch-not-admin")
+                .await?;
+            return Ok(());
+        }
+
+        match args.text("link") {
+            Some(url) => add_watch(handler, message, url).await,
+            None => send_watches(handler, message).await,
+        }
+    }
+}
+
+/// Check if a user may change what a chat is watching. Anyone may in a
+/// private chat, but only administrators may in groups.
+async fn can_manage_watches(
+    handler: &MessageHandler,
+    chat: &Chat,
+    user_id: i64,
+) -> anyhow::Result<bool> {
+    use tgbotapi::ChatMemberStatus::*;
+
+    if !chat.chat_type.is_group() {
+        return Ok(true);
+    }
+
+    match ChatAdmin::is_admin(&handler.conn, user_id, chat.id).await? {
+        Some(is_admin) => Ok(is_admin),
+        None => {
+            let get_chat_member = GetChatMember {
+                chat_id: chat.id.into(),
+                user_id,
+            };
+            let chat_member = handler.make_request(&get_chat_member).await?;
+
+            Ok(matches!(chat_member.status, Administrator | Creator))
+        }
+    }
+}
+
+async fn reply(
+    handler: &MessageHandler,
+    message: &Message,
+    name: &str,
+    args: fluent::FluentArgs<'_>,
+) -> anyhow::Result<()> {
+    let lang = message
+        .from
+        .as_ref()
+        .and_then(|from| from.language_code.as_deref());
+
+    let text = handler
+        .get_fluent_bundle(lang, |bundle| {
+            get_message(bundle, name, Some(args)).unwrap()
+        })
+        .await;
+
+    let send_message = SendMessage {
+        chat_id: message.chat_id(),
+        reply_to_message_id: Some(message.message_id),
+        text,
+        disable_web_page_preview: Some(true),
+        ..Default::default()
+    };
+
+    handler
+        .make_request(&send_message)
+        .await
+        .context("unable to send watch reply")?;
+
+    Ok(())
+}
+
+/// Start watching a page, remembering its newest post so only posts after
+/// this point are delivered.
+async fn add_watch(handler: &MessageHandler, message: &Message, url: &str) -> anyhow::Result<()> {
+    if ArtistWatches::count(&handler.conn, message.chat.id).await? >= MAX_WATCHES {
+        let mut args = fluent::FluentArgs::new();
+        args.insert("count", MAX_WATCHES.into());
+
+        return reply(handler, message, "watch-limit", args).await;
+    }
+
+    let url = normalize_url(url).await;
+
+    let latest = {
+        let mut sites = handler.sites.lock().await;
+
+        let mut latest = None;
+        for site in sites.iter_mut() {
+            if let Some(posts) = site
+                .latest_posts(&url)
+                .await
+                .context("unable to load latest posts")?
+            {
+                latest = Some((site.name(), posts));
+                break;
+            }
+        }
+
+        latest
+    };
+
+    let (site, posts) = match latest {
+        Some(latest) => latest,
+        None => {
+            handler
+                .send_generic_reply(message, "watch-unsupported")
+                .await?;
+            return Ok(());
+        }
+    };
+
+    let last_seen = posts.first().map(|post| post.source_or_url());
+    let added = ArtistWatches::add(&handler.conn, message.chat.id, site, &url, last_seen).await?;
+
+    tracing::info!(site, %url, added, "added watch");
+
+    let mut args = fluent::FluentArgs::new();
+    args.insert("site", site.into());
+    args.insert("url", url.into());
+
+    let name = if added { "watch-added" } else { "watch-exists" };
+    reply(handler, message, name, args).await
+}
+
+async fn send_watches(handler: &MessageHandler, message: &Message) -> anyhow::Result<()> {
+    let lang = message
+        .from
+        .as_ref()
+        .and_then(|from| from.language_code.as_deref());
+
+    let (text, markup) = watches_message(handler, message.chat.id, lang).await?;
+
+    let send_message = SendMessage {
+        chat_id: message.chat_id(),
+        reply_to_message_id: Some(message.message_id),
+        text,
+        reply_markup: markup,
+        disable_web_page_preview: Some(true),
+        ..Default::default()
+    };
+
+    handler
+        .make_request(&send_message)
+        .await
+        .context("unable to send watches message")?;
+
+    Ok(())
+}
+
+/// Build the text and remove buttons listing the pages a chat is watching.
+async fn watches_message(
+    handler: &MessageHandler,
+    chat_id: i64,
+    lang: Option<&str>,
+) -> anyhow::Result<(String, Option<ReplyMarkup>)> {
+    let watches = ArtistWatches::list(&handler.conn, chat_id).await?;
+
+    if watches.is_empty() {
+        let text = handler
+            .get_fluent_bundle(lang, |bundle| {
+                get_message(bundle, "watch-none", None).unwrap()
+            })
+            .await;
+
+        return Ok((text, None));
+    }
+
+    let (text, buttons) = handler
+        .get_fluent_bundle(lang, |bundle| {
+            let mut text = get_message(bundle, "watch-list", None).unwrap();
+            let mut buttons = Vec::with_capacity(watches.len());
+
+            for (index, watch) in watches.iter().enumerate() {
+                let mut args = fluent::FluentArgs::new();
+                args.insert("number", (index + 1).into());
+                args.insert("site", watch.site.clone().into());
+                args.insert("url", watch.url.clone().into());
+
+                let item = get_message(bundle, "watch-list-item", Some(args.clone())).unwrap();
+                text.push('\n');
+                text.push_str(&item);
+
+                buttons.push(vec![InlineKeyboardButton {
+                    text: get_message(bundle, "watch-remove", Some(args)).unwrap(),
+                    callback_data: Some(format!("w:remove:{}", watch.id)),
+                    ..Default::default()
+                }]);
+            }
+
+            (text, buttons)
+        })
+        .await;
+
+    let markup = ReplyMarkup::InlineKeyboardMarkup(InlineKeyboardMarkup {
+        inline_keyboard: buttons,
+    });
+
+    Ok((text, Some(markup)))
+}
+
+async fn remove_watch(
+    handler: &MessageHandler,
+    callback_query: &CallbackQuery,
+    id: i32,
+) -> anyhow::Result<Status> {
+    let message = needs_field!(callback_query, message);
+    let user = &callback_query.from;
+    let lang = user.language_code.as_deref();
+
+    let name = if !can_manage_watches(handler, &message.chat, user.id).await? {
+        "watch-not-admin"
+    } else if ArtistWatches::remove(&handler.conn, message.chat.id, id).await? {
+        tracing::info!(id, "removed watch");
+
+        "watch-removed"
+    } else {
+        "watch-missing"
+    };
+
+    let text = handler
+        .get_fluent_bundle(lang, |bundle| get_message(bundle, name, None).unwrap())
+        .await;
+
+    let answer = AnswerCallbackQuery {
+        callback_query_id: callback_query.id.clone(),
+        text: Some(text),
+        ..Default::default()
+    };
+
+    handler
+        .make_request(&answer)
+        .await
+        .context("unable to answer watch callback query")?;
+
+    if name == "watch-not-admin" {
+        return Ok(Completed);
+    }
+
+    let (text, markup) = watches_message(handler, message.chat.id, lang).await?;
+
+    let edit_message = EditMessageText {
+        chat_id: message.chat_id(),
+        message_id: Some(message.message_id),
+        text,
+        reply_markup: markup,
+        disable_web_page_preview: Some(true),
+        ..Default::default()
+    };
+
+    handler
+        .make_request(&edit_message)
+        .await
+        .context("unable to update watches message")?;
+
+    Ok(Completed)
+}
@@ -1,28 +1,29 @@
 use sentry::integrations::anyhow::capture_anyhow;
-use std::collections::HashMap;
 use std::sync::Arc;
 use tgbotapi::{requests::*, *};
-use tokio::sync::{Mutex, RwLock};
+use tokio::sync::Mutex;
 use tracing::Instrument;
-use unic_langid::LanguageIdentifier;
 
 use foxbot_utils::*;
 
+mod admin;
 mod coconut;
 mod handlers;
 
 lazy_static::lazy_static! {
     static ref REQUEST_DURATION: prometheus::Histogram = prometheus::register_histogram!("foxbot_request_duration_seconds", "Time to start processing request").unwrap();
-    static ref HANDLING_DURATION: prometheus::Histogram = prometheus::register_histogram!("foxbot_handling_duration_seconds", "Request processing time duration").unwrap();
-    static ref HANDLER_DURATION: prometheus::HistogramVec = prometheus::register_histogram_vec!("foxbot_handler_duration_seconds", "Time for a handler to complete", &["handler"]).unwrap();
     static ref TELEGRAM_REQUEST: prometheus::Counter = prometheus::register_counter!("foxbot_telegram_request_total", "Number of requests made to Telegram").unwrap();
-    static ref TELEGRAM_ERROR: prometheus::Counter = prometheus::register_counter!("foxbot_telegram_error_total", "Number of errors returned by Telegram").unwrap();
+    static ref TELEGRAM_ERROR: prometheus::CounterVec = prometheus::register_counter_vec!("foxbot_telegram_error_total", "Number of errors returned by Telegram", &["code"]).unwrap();
 }
 
 type BoxedHandler = Box<dyn handlers::Handler + Send + Sync>;
 
-static CONCURRENT_HANDLERS: usize = 2;
+/// Number of tasks processing updates other than inline queries. Updates from
+/// the same chat always go to the same task so they are handled in order.
+static UPDATE_SHARDS: usize = 8;
 static INLINE_HANDLERS: usize = 10;
+/// Most links a user may load in inline queries each minute, by default.
+static DEFAULT_INLINE_LINK_LIMIT: usize = 60;
 
 /// Artwork used for examples throughout the bot.
 static STARTING_ARTWORK: &[&str] = &[
@@ -35,37 +36,45 @@ static STARTING_ARTWORK: &[&str] = &[
     "https://www.furaffinity.net/view/32232169/",
 ];
 
-static L10N_RESOURCES: &[&str] = &["foxbot.ftl"];
-static L10N_LANGS: &[&str] = &["en-US"];
-
 #[derive(serde::Deserialize, Debug, Clone)]
 pub struct Config {
     // Site config
     pub fa_a: String,
     pub fa_b: String,
+    pub flaresolverr_endpoint: Option<String>,
     pub weasyl_apitoken: String,
     pub inkbunny_username: String,
     pub inkbunny_password: String,
     pub e621_login: String,
     pub e621_api_key: String,
+    credentials_key: Option<String>,
 
     // Twitter config
     pub twitter_consumer_key: String,
     pub twitter_consumer_secret: String,
+    twitter_thread_limit: Option<usize>,
     pub twitter_callback: String,
 
     // Logging
     jaeger_collector: Option<String>,
+    otlp_endpoint: Option<String>,
     pub sentry_dsn: Option<String>,
     pub sentry_organization_slug: Option<String>,
     pub sentry_project_slug: Option<String>,
 
     // Telegram config
     telegram_apitoken: String,
+    telegram_api_endpoint: Option<String>,
     pub use_webhooks: Option<bool>,
     pub webhook_endpoint: Option<String>,
     pub http_host: Option<String>,
     http_secret: Option<String>,
+    webhook_secret_token: Option<String>,
+    pub admin_ids: Option<Vec<i64>>,
+    banned_ids: Option<Vec<i64>>,
+    allowed_chats: Option<Vec<i64>>,
+    user_rate_limit: Option<u32>,
+    inline_link_limit: Option<usize>,
 
     // File storage
     pub s3_endpoint: String,
@@ -76,6 +85,8 @@ pub struct Config {
     pub s3_url: String,
 
     pub fautil_apitoken: String,
+    saucenao_api_key: Option<String>,
+    iqdb_fallback: Option<bool>,
 
     // Video storage
     b2_account_id: String,
@@ -89,11 +100,24 @@ pub struct Config {
     pub size_images: Option<bool>,
     pub cache_images: Option<bool>,
     pub cache_all_images: Option<bool>,
+    pub thumbnail_proxy: Option<String>,
+
+    // Site proxies
+    proxy: Option<String>,
+    site_proxies: Option<Vec<String>>,
+    rehost_sites: Option<Vec<String>>,
+    rehost_ttl: Option<i64>,
+
+    // Media download limits
+    media_max_bytes: Option<usize>,
+    media_max_pixels: Option<u64>,
 
     redis_dsn: String,
     faktory_url: Option<String>,
+    queue_backend: Option<String>,
 
     metrics_host: String,
+    admin_api_token: Option<String>,
 
     // Postgres database
     db_host: String,
@@ -102,8 +126,142 @@ pub struct Config {
     db_name: String,
 }
 
-/// Configure tracing with Jaeger.
-fn configure_tracing(collector: String) {
+/// Credentials used only by the bot, which may be loaded from a
+/// [`SecretProvider`](foxbot_utils::secrets::SecretProvider) in addition to
+/// [`SITE_CREDENTIALS`](foxbot_utils::secrets::SITE_CREDENTIALS).
+static BOT_CREDENTIALS: &[&str] = &[
+    "TELEGRAM_APITOKEN",
+    "HTTP_SECRET",
+    "WEBHOOK_SECRET_TOKEN",
+    "ADMIN_API_TOKEN",
+    "SENTRY_DSN",
+    "S3_TOKEN",
+    "S3_SECRET",
+    "B2_APP_KEY",
+    "COCONUT_APITOKEN",
+    "COCONUT_SECRET",
+    "DB_PASS",
+    "SAUCENAO_API_KEY",
+    "CREDENTIALS_KEY",
+];
+
+impl Config {
+    /// Load and validate configuration, using credentials from the secret
+    /// provider where available.
+    async fn load(secrets: &dyn foxbot_utils::secrets::SecretProvider) -> anyhow::Result<Self> {
+        let names: Vec<&str> = foxbot_utils::secrets::SITE_CREDENTIALS
+            .iter()
+            .chain(BOT_CREDENTIALS)
+            .copied()
+            .collect();
+
+        let config: Self = foxbot_utils::config::load_config_with_secrets(secrets, &names).await?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// Build the proxies to use for each site.
+    fn proxies(&self) -> anyhow::Result<foxbot_sites::Proxies> {
+        foxbot_sites::Proxies::new(
+            self.proxy.clone(),
+            self.site_proxies.as_deref().unwrap_or_default(),
+        )
+    }
+
+    /// Check for settings that are individually valid but can't be used
+    /// together, reporting every problem at once.
+    fn validate(&self) -> anyhow::Result<()> {
+        let mut problems = vec![];
+
+        if self.jaeger_collector.is_none() && self.otlp_endpoint.is_none() {
+            problems.push("JAEGER_COLLECTOR or OTLP_ENDPOINT must be set");
+        }
+
+        if matches!(self.use_webhooks, Some(true)) {
+            if self.webhook_endpoint.is_none() {
+                problems.push("WEBHOOK_ENDPOINT must be set when USE_WEBHOOKS is enabled");
+            }
+            if self.http_host.is_none() {
+                problems.push("HTTP_HOST must be set when USE_WEBHOOKS is enabled");
+            }
+            if self.http_secret.is_none() && self.webhook_secret_token.is_none() {
+                problems.push(
+                    "HTTP_SECRET or WEBHOOK_SECRET_TOKEN must be set when USE_WEBHOOKS is enabled",
+                );
+            }
+        }
+
+        if self.proxies().is_err() {
+            problems.push(
+                "PROXY and SITE_PROXIES must be valid proxy URLs, with site proxies like FurAffinity=socks5://host:port",
+            );
+        }
+
+        if !matches!(
+            self.queue_backend.as_deref(),
+            None | Some("faktory") | Some("postgres")
+        ) {
+            problems.push("QUEUE_BACKEND must be faktory or postgres");
+        }
+
+        if self.metrics_host.parse::<std::net::SocketAddr>().is_err() {
+            problems.push("METRICS_HOST must be an address like 0.0.0.0:8081");
+        }
+
+        if let Some(key) = self.credentials_key.as_deref() {
+            if foxbot_utils::secrets::CredentialCipher::new(key).is_err() {
+                problems.push("CREDENTIALS_KEY must be a base64 encoded 32 byte key");
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            anyhow::bail!("invalid configuration:\n  {}", problems.join("\n  "))
+        }
+    }
+}
+
+/// Settings that can be changed without restarting by sending SIGHUP.
+///
+/// Everything else, including credentials, is only read at startup.
+#[derive(Clone, Debug)]
+pub struct Settings {
+    pub size_images: bool,
+    pub cache_images: bool,
+    pub cache_all_images: bool,
+    pub admin_ids: Vec<i64>,
+    /// Users and chats whose updates are ignored.
+    pub banned_ids: Vec<i64>,
+    /// Groups and channels that may use the bot, if not all of them.
+    pub allowed_chats: Option<Vec<i64>>,
+    /// Most updates a user may send each minute.
+    pub user_rate_limit: Option<u32>,
+    /// Most links a user may load from sites in inline queries each minute.
+    pub inline_link_limit: usize,
+}
+
+impl From<&Config> for Settings {
+    fn from(config: &Config) -> Self {
+        Self {
+            size_images: config.size_images.unwrap_or(false),
+            cache_images: config.cache_images.unwrap_or(false),
+            cache_all_images: config.cache_all_images.unwrap_or(false),
+            admin_ids: config.admin_ids.clone().unwrap_or_default(),
+            banned_ids: config.banned_ids.clone().unwrap_or_default(),
+            allowed_chats: config.allowed_chats.clone(),
+            user_rate_limit: config.user_rate_limit,
+            inline_link_limit: config
+                .inline_link_limit
+                .unwrap_or(DEFAULT_INLINE_LINK_LIMIT),
+        }
+    }
+}
+
+/// Configure tracing, exporting spans with OTLP if an endpoint was provided
+/// or Jaeger otherwise.
+fn configure_tracing(collector: Option<String>, otlp_endpoint: Option<String>) {
     use opentelemetry::KeyValue;
     use tracing_subscriber::layer::SubscriberExt;
 
@@ -116,17 +274,38 @@ fn configure_tracing(collector: String) {
         "release"
     };
 
-    opentelemetry::global::set_text_map_propagator(opentelemetry_jaeger::Propagator::new());
-
-    let tracer = opentelemetry_jaeger::new_pipeline()
-        .with_agent_endpoint(collector)
-        .with_service_name("foxbot")
-        .with_tags(vec![
-            KeyValue::new("environment", env.to_owned()),
-            KeyValue::new("version", env!("CARGO_PKG_VERSION")),
-        ])
-        .install_batch(opentelemetry::runtime::Tokio)
-        .unwrap();
+    let tracer = match otlp_endpoint {
+        Some(endpoint) => {
+            opentelemetry::global::set_text_map_propagator(
+                opentelemetry::sdk::propagation::TraceContextPropagator::new(),
+            );
+
+            opentelemetry_otlp::new_pipeline()
+                .with_endpoint(endpoint)
+                .with_trace_config(opentelemetry::sdk::trace::config().with_resource(
+                    opentelemetry::sdk::Resource::new(vec![
+                        KeyValue::new("service.name", "foxbot"),
+                        KeyValue::new("environment", env.to_owned()),
+                        KeyValue::new("version", env!("CARGO_PKG_VERSION")),
+                    ]),
+                ))
+                .install_batch(opentelemetry::runtime::Tokio)
+                .unwrap()
+        }
+        None => {
+            opentelemetry::global::set_text_map_propagator(opentelemetry_jaeger::Propagator::new());
+
+            opentelemetry_jaeger::new_pipeline()
+                .with_agent_endpoint(collector.expect("Missing JAEGER_COLLECTOR or OTLP_ENDPOINT"))
+                .with_service_name("foxbot")
+                .with_tags(vec![
+                    KeyValue::new("environment", env.to_owned()),
+                    KeyValue::new("version", env!("CARGO_PKG_VERSION")),
+                ])
+                .install_batch(opentelemetry::runtime::Tokio)
+                .unwrap()
+        }
+    };
 
     let trace = tracing_opentelemetry::layer().with_tracer(tracer);
     let env_filter = tracing_subscriber::EnvFilter::from_default_env();
@@ -139,6 +318,7 @@ fn configure_tracing(collector: String) {
         let subscriber = tracing_subscriber::Registry::default()
             .with(env_filter)
             .with(trace)
+            .with(foxbot_utils::sentry_breadcrumbs())
             .with(subscriber);
         tracing::subscriber::set_global_default(subscriber).unwrap();
     } else {
@@ -146,6 +326,7 @@ fn configure_tracing(collector: String) {
         let subscriber = tracing_subscriber::Registry::default()
             .with(env_filter)
             .with(trace)
+            .with(foxbot_utils::sentry_breadcrumbs())
             .with(subscriber);
         tracing::subscriber::set_global_default(subscriber).unwrap();
     }
@@ -181,6 +362,121 @@ fn setup_shutdown() -> tokio::sync::mpsc::Receiver<bool> {
     shutdown_rx
 }
 
+/// Create the reverse image search providers, adding SauceNAO after
+/// FuzzySearch and IQDB as a fallback if they were configured.
+fn build_reverse_search(
+    config: &Config,
+    conn: sqlx::Pool<sqlx::Postgres>,
+    fapi: Arc<fuzzysearch::FuzzySearch>,
+) -> foxbot_utils::reverse::ReverseSearch {
+    use foxbot_utils::reverse::{BoxedReverseIndex, FuzzySearchIndex, Iqdb, SauceNao};
+
+    let mut indexes: Vec<BoxedReverseIndex> = vec![Box::new(FuzzySearchIndex::new(conn, fapi))];
+
+    if let Some(api_key) = &config.saucenao_api_key {
+        indexes.push(Box::new(SauceNao::new(api_key.clone())));
+    }
+
+    let reverse = foxbot_utils::reverse::ReverseSearch::new(indexes);
+
+    if config.iqdb_fallback.unwrap_or(false) {
+        reverse.with_fallback(Box::new(Iqdb::default()))
+    } else {
+        reverse
+    }
+}
+
+/// Create every site loader with credentials from the configuration, or
+/// those stored with `/credential`.
+async fn build_sites(
+    config: &Config,
+    pool: sqlx::Pool<sqlx::Postgres>,
+) -> Vec<foxbot_sites::BoxedSite> {
+    build_named_sites(config, pool, None).await
+}
+
+/// Create the site loaders with the given names, or every site loader.
+async fn build_named_sites(
+    config: &Config,
+    pool: sqlx::Pool<sqlx::Postgres>,
+    names: Option<&[&str]>,
+) -> Vec<foxbot_sites::BoxedSite> {
+    let credentials =
+        foxbot_utils::secrets::CredentialOverrides::load(&pool, config.credentials_key.as_deref())
+            .await;
+
+    foxbot_sites::get_sites(
+        names,
+        credentials.get("FA_A", &config.fa_a),
+        credentials.get("FA_B", &config.fa_b),
+        config.flaresolverr_endpoint.clone(),
+        credentials.get("FAUTIL_APITOKEN", &config.fautil_apitoken),
+        credentials.get("WEASYL_APITOKEN", &config.weasyl_apitoken),
+        credentials.get("TWITTER_CONSUMER_KEY", &config.twitter_consumer_key),
+        credentials.get("TWITTER_CONSUMER_SECRET", &config.twitter_consumer_secret),
+        config.twitter_thread_limit.unwrap_or_default(),
+        credentials.get("INKBUNNY_USERNAME", &config.inkbunny_username),
+        credentials.get("INKBUNNY_PASSWORD", &config.inkbunny_password),
+        credentials.get("E621_LOGIN", &config.e621_login),
+        credentials.get("E621_API_KEY", &config.e621_api_key),
+        pool,
+        &config.proxies().expect("proxies should be validated"),
+    )
+    .await
+}
+
+/// Load every site's test URL, printing the results and exiting with an error
+/// if any site failed.
+async fn check_sites(sites: &mut [foxbot_sites::BoxedSite]) -> ! {
+    let checks = foxbot_sites::check_sites(sites).await;
+
+    for check in &checks {
+        match &check.result {
+            Ok(count) => println!(
+                "PASS {} in {}ms, {} posts from {}",
+                check.site,
+                check.duration.as_millis(),
+                count,
+                check.url
+            ),
+            Err(err) => println!(
+                "FAIL {} in {}ms: {:#}",
+                check.site,
+                check.duration.as_millis(),
+                err
+            ),
+        }
+    }
+
+    let code = if checks.iter().all(|check| check.passed()) {
+        0
+    } else {
+        1
+    };
+
+    std::process::exit(code);
+}
+
+/// Reload settings whenever the process receives SIGHUP.
+#[cfg(unix)]
+fn reload_on_hangup(handler: Arc<MessageHandler>) {
+    tokio::spawn(async move {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut stream =
+            signal(SignalKind::hangup()).expect("unable to create hangup signal stream");
+
+        while stream.recv().await.is_some() {
+            if let Err(err) = handler.reload_settings().await {
+                tracing::error!("unable to reload settings: {:#}", err);
+            }
+        }
+    });
+}
+
+#[cfg(not(unix))]
+fn reload_on_hangup(_handler: Arc<MessageHandler>) {}
+
 #[cfg(feature = "env")]
 fn load_env() {
     dotenv::dotenv().unwrap();
@@ -206,6 +502,9 @@ pub enum ServiceData {
     },
     NewHash {
         hash: i64,
+        /// ID of the newly indexed submission on its site, if FuzzySearch
+        /// included it.
+        site_id: Option<i64>,
     },
 }
 
@@ -221,21 +520,61 @@ impl From<Box<tgbotapi::Update>> for HandlerUpdate {
     }
 }
 
+/// Get the ID of the chat an update belongs to, used to keep each chat's
+/// updates in order.
+///
+/// Service updates aren't associated with a chat, so they share a key.
+fn ordering_key(update: &HandlerUpdate) -> i64 {
+    let update = match update {
+        HandlerUpdate::Telegram(update) => update,
+        HandlerUpdate::Service(_) => return 0,
+    };
+
+    if let Some(message) = update.message.as_ref().or(update.channel_post.as_ref()) {
+        message.chat.id
+    } else if let Some(message) = update
+        .callback_query
+        .as_ref()
+        .and_then(|query| query.message.as_ref())
+    {
+        message.chat.id
+    } else if let Some(member) = update
+        .my_chat_member
+        .as_ref()
+        .or(update.chat_member.as_ref())
+    {
+        member.chat.id
+    } else if let Some(result) = &update.chosen_inline_result {
+        result.from.id
+    } else {
+        update.update_id as i64
+    }
+}
+
+/// Pick which shard should process updates with a given ordering key.
+fn shard_for(key: i64, shards: usize) -> usize {
+    key.rem_euclid(shards as i64) as usize
+}
+
 #[tokio::main]
 async fn main() {
     load_env();
 
-    let config = match envy::from_env::<Config>() {
+    let secrets = foxbot_utils::secrets::providers_from_env();
+    let config = match Config::load(&secrets).await {
         Ok(config) => config,
-        Err(err) => panic!("{:#?}", err),
+        Err(err) => panic!("{:#}", err),
     };
 
-    let jaeger_collector = match &config.jaeger_collector {
-        Some(collector) => collector.clone(),
-        _ => panic!("Missing JAEGER_COLLECTOR"),
-    };
+    download::set_limits(download::DownloadLimits::new(
+        config.media_max_bytes,
+        config.media_max_pixels,
+    ));
 
-    configure_tracing(jaeger_collector);
+    configure_tracing(
+        config.jaeger_collector.clone(),
+        config.otlp_endpoint.clone(),
+    );
 
     let pool = sqlx::postgres::PgPoolOptions::new()
         .max_connections(8)
@@ -255,81 +594,88 @@ async fn main() {
         config.fautil_apitoken.clone(),
     ));
 
-    let sites = foxbot_sites::get_all_sites(
-        config.fa_a.clone(),
-        config.fa_b.clone(),
-        config.fautil_apitoken.clone(),
-        config.weasyl_apitoken.clone(),
-        config.twitter_consumer_key.clone(),
-        config.twitter_consumer_secret.clone(),
-        config.inkbunny_username.clone(),
-        config.inkbunny_password.clone(),
-        config.e621_login.clone(),
-        config.e621_api_key.clone(),
-        pool.clone(),
-    )
-    .await;
-
-    let bot = Arc::new(Telegram::new(config.telegram_apitoken.clone()));
-
-    let mut finder = linkify::LinkFinder::new();
-    finder.kinds(&[linkify::LinkKind::Url]);
-
-    let mut dir = std::env::current_dir().expect("Unable to get directory");
-    dir.push("langs");
-
-    let mut langs = HashMap::new();
+    let reverse = build_reverse_search(&config, pool.clone(), fapi.clone());
 
-    for lang in L10N_LANGS {
-        let path = dir.join(lang);
+    let mut sites = build_sites(&config, pool.clone()).await;
 
-        let mut lang_resources = Vec::with_capacity(L10N_RESOURCES.len());
-        let langid = lang
-            .parse::<LanguageIdentifier>()
-            .expect("Unable to parse language");
+    if std::env::args().any(|arg| arg == "--check-sites") {
+        check_sites(&mut sites).await;
+    }
 
-        for resource in L10N_RESOURCES {
-            let file = path.join(resource);
-            let s = std::fs::read_to_string(file).expect("Unable to read file");
+    let bot = Arc::new(bot_api::telegram_client(
+        config.telegram_apitoken.clone(),
+        config.telegram_api_endpoint.clone(),
+    ));
 
-            lang_resources.push(s);
-        }
+    let mut finder = linkify::LinkFinder::new();
+    finder.kinds(&[linkify::LinkKind::Url]);
 
-        langs.insert(langid, lang_resources);
-    }
+    let l10n = foxbot_utils::l10n::Localization::load(foxbot_utils::l10n::langs_dir())
+        .expect("unable to load languages");
 
     let bot_user = bot
         .make_request(&GetMe)
         .await
         .expect("Unable to fetch bot user");
 
+    let callback_router = handlers::CallbackRouter::new()
+        .register(handlers::SettingsHandler)
+        .register(handlers::GroupSourceHandler)
+        .register(handlers::SubscribeHandler)
+        .register(handlers::InlineHandler);
+
+    let command_router = handlers::CommandRouter::new()
+        .register(handlers::GeneralCommands)
+        .register(handlers::SettingsHandler)
+        .register(handlers::TwitterHandler)
+        .register(handlers::AccountsHandler)
+        .register(handlers::WatchHandler)
+        .register(handlers::BanCommands)
+        .register(handlers::AnnouncementCommands);
+
+    if let Err(err) = command_router.set_my_commands(&bot, &l10n).await {
+        tracing::warn!("unable to set bot commands: {:?}", err);
+    }
+
+    let middleware: Vec<handlers::BoxedMiddleware> = vec![
+        Box::new(handlers::BanMiddleware),
+        Box::new(handlers::MetricsMiddleware),
+        Box::new(handlers::AllowlistMiddleware),
+        Box::new(handlers::RateLimitMiddleware),
+    ];
+
     let handlers: Vec<BoxedHandler> = vec![
+        Box::new(callback_router),
         Box::new(handlers::InlineHandler),
         Box::new(handlers::ChosenInlineHandler),
         Box::new(handlers::ChannelPhotoHandler),
-        Box::new(handlers::GroupAddHandler),
+        Box::new(handlers::DiscussionHandler),
         Box::new(handlers::PhotoHandler),
-        Box::new(handlers::CommandHandler),
+        Box::new(command_router),
         Box::new(handlers::GroupSourceHandler),
         Box::new(handlers::ErrorReplyHandler::new()),
-        Box::new(handlers::SettingsHandler),
         Box::new(handlers::TwitterHandler),
+        Box::new(handlers::AccountsHandler),
+        Box::new(handlers::WatchHandler),
         Box::new(handlers::SubscribeHandler),
         Box::new(handlers::ErrorCleanup),
         Box::new(handlers::PermissionHandler),
     ];
 
-    let region = rusoto_core::Region::Custom {
-        name: config.s3_region.clone(),
-        endpoint: config.s3_endpoint.clone(),
-    };
-
-    let client = rusoto_core::request::HttpClient::new().unwrap();
-    let provider = rusoto_credential::StaticProvider::new_minimal(
+    let s3 = media_cache::s3_client(
+        config.s3_endpoint.clone(),
+        config.s3_region.clone(),
         config.s3_token.clone(),
         config.s3_secret.clone(),
     );
-    let s3 = rusoto_s3::S3Client::new_with(client, provider, region);
+
+    let media_cache = media_cache::MediaCache::new(
+        s3.clone(),
+        config.s3_bucket.clone(),
+        config.s3_url.clone(),
+        config.rehost_sites.clone().unwrap_or_default(),
+        config.rehost_ttl,
+    );
 
     let coconut = coconut::Coconut::new(
         config.coconut_apitoken.clone(),
@@ -344,49 +690,49 @@ async fn main() {
         .await
         .expect("Unable to open Redis connection");
 
-    let faktory = faktory::Producer::connect(config.faktory_url.as_deref())
-        .expect("Unable to connect to Faktory");
+    let faktory = match config.queue_backend.as_deref() {
+        Some("postgres") => None,
+        _ => Some(
+            faktory::Producer::connect(config.faktory_url.as_deref())
+                .expect("Unable to connect to Faktory"),
+        ),
+    };
 
     let handler = Arc::new(MessageHandler {
         bot_user,
-        langs,
-        best_lang: RwLock::new(HashMap::new()),
+        l10n,
+        middleware,
         handlers,
+        bans: handlers::BanList::new(),
+        settings: std::sync::RwLock::new(Settings::from(&config)),
+        secrets,
         config: config.clone(),
 
         bot: bot.clone(),
+        // Updates are answered as they're handled, so they shouldn't be held up
+        // waiting for a group's limit to reset.
+        limiter: foxbot_utils::rate_limit::RequestLimiter::new(redis.clone())
+            .with_max_wait(std::time::Duration::from_secs(5)),
         fapi,
+        reverse,
         finder,
         s3,
+        media_cache,
         coconut,
-        faktory: Arc::new(std::sync::Mutex::new(faktory)),
+        faktory: faktory.map(|faktory| Arc::new(std::sync::Mutex::new(faktory))),
 
         sites: Mutex::new(sites),
         conn: pool,
         redis,
     });
 
-    let _guard = config.sentry_dsn.as_ref().map(|sentry_dsn| {
-        sentry::init(sentry::ClientOptions {
-            dsn: Some(sentry_dsn.parse().unwrap()),
-            debug: true,
-            release: option_env!("RELEASE").map(std::borrow::Cow::from),
-            attach_stacktrace: true,
-            ..Default::default()
-        })
-    });
+    let _guard = foxbot_utils::init_sentry(config.sentry_dsn.as_deref());
 
-    tracing::info!(
-        "sentry enabled: {}",
-        _guard
-            .as_ref()
-            .map_or(false, sentry::ClientInitGuard::is_enabled)
-    );
-
-    serve_metrics(config.clone()).await;
+    serve_metrics(config.clone(), handler.clone()).await;
+    reload_on_hangup(handler.clone());
 
     // Allow buffering more updates than can be run at once
-    let (update_tx, update_rx) = tokio::sync::mpsc::channel(CONCURRENT_HANDLERS * 2);
+    let (update_tx, mut update_rx) = tokio::sync::mpsc::channel(UPDATE_SHARDS * 2);
     let (inline_tx, inline_rx) = tokio::sync::mpsc::channel(INLINE_HANDLERS * 2);
 
     let shutdown = setup_shutdown();
@@ -405,8 +751,9 @@ async fn main() {
             .webhook_endpoint
             .as_ref()
             .expect("Missing WEBHOOK_ENDPOINT");
-        let set_webhook = SetWebhook {
+        let set_webhook = SetWebhookWithSecret {
             url: webhook_endpoint.clone(),
+            secret_token: config.webhook_secret_token.clone(),
             allowed_updates: Some(vec![
                 "message".into(),
                 "channel_post".into(),
@@ -460,20 +807,48 @@ async fn main() {
             .await;
     });
 
-    // Process all other updates, limited by `CONCURRENT_HANDLERS`.
-    ReceiverStream::new(update_rx)
-        .for_each_concurrent(CONCURRENT_HANDLERS, |(update, span)| {
+    // All other updates are split between `UPDATE_SHARDS` tasks by chat, so
+    // different chats are processed concurrently while each chat's updates
+    // are still handled in the order they were received. Each shard's queue is
+    // unbounded so a busy chat never delays or drops updates for other shards.
+    let (shard_txs, shard_tasks): (Vec<_>, Vec<_>) = (0..UPDATE_SHARDS)
+        .map(|_| {
+            let (tx, mut rx) =
+                tokio::sync::mpsc::unbounded_channel::<(HandlerUpdate, tracing::Span)>();
             let handler = handler.clone();
 
-            async move {
-                tokio::spawn(async move {
-                    handler.handle_update(update).instrument(span).await;
-                })
-                .await
-                .unwrap();
-            }
+            let task = tokio::spawn(async move {
+                while let Some((update, span)) = rx.recv().await {
+                    let handler = handler.clone();
+
+                    // A panic while handling one update shouldn't stop this
+                    // shard from processing updates for its other chats.
+                    if let Err(err) = tokio::spawn(async move {
+                        handler.handle_update(update).instrument(span).await;
+                    })
+                    .await
+                    {
+                        tracing::error!("update handler failed: {:?}", err);
+                    }
+                }
+            });
+
+            (tx, task)
         })
-        .await;
+        .unzip();
+
+    while let Some((update, span)) = update_rx.recv().await {
+        let shard = shard_for(ordering_key(&update), shard_txs.len());
+
+        if shard_txs[shard].send((update, span)).is_err() {
+            tracing::error!(shard, "update shard stopped");
+        }
+    }
+
+    drop(shard_txs);
+    for task in shard_tasks {
+        task.await.unwrap();
+    }
 
     opentelemetry::global::shutdown_tracer_provider();
 }
@@ -486,6 +861,7 @@ async fn handle_request(
     update_tx: tokio::sync::mpsc::Sender<(HandlerUpdate, tracing::Span)>,
     inline_tx: tokio::sync::mpsc::Sender<(HandlerUpdate, tracing::Span)>,
     secret: &str,
+    secret_token: Option<&str>,
     fuzzysearch_secret: &str,
     video_secret: &str,
     templates: Arc<handlebars::Handlebars<'_>>,
@@ -501,6 +877,20 @@ async fn handle_request(
         (&hyper::Method::POST, path) if path == secret => {
             let _hist = REQUEST_DURATION.start_timer();
 
+            if let Some(secret_token) = secret_token {
+                let provided = req
+                    .headers()
+                    .get("X-Telegram-Bot-Api-Secret-Token")
+                    .and_then(|value| value.to_str().ok());
+
+                if !matches!(provided, Some(provided) if constant_time_eq(provided, secret_token)) {
+                    tracing::warn!("webhook request had invalid secret token");
+                    let mut resp = Response::default();
+                    *resp.status_mut() = StatusCode::UNAUTHORIZED;
+                    return Ok(resp);
+                }
+            }
+
             let body = req.into_body();
             let bytes = hyper::body::to_bytes(body)
                 .await
@@ -560,9 +950,11 @@ async fn handle_request(
                 return Ok(Response::new(Body::from("x")));
             };
 
+            let site_id = data.get("site_id").and_then(serde_json::Value::as_i64);
+
             update_tx
                 .send((
-                    HandlerUpdate::Service(ServiceData::NewHash { hash }),
+                    HandlerUpdate::Service(ServiceData::NewHash { hash, site_id }),
                     tracing::Span::current(),
                 ))
                 .await
@@ -694,6 +1086,26 @@ async fn handle_request(
     }
 }
 
+/// Set the webhook, including a secret token Telegram sends with each update.
+///
+/// tgbotapi's [`SetWebhook`] has no field for the secret token.
+#[derive(serde::Serialize, Debug)]
+struct SetWebhookWithSecret {
+    url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    secret_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    allowed_updates: Option<Vec<String>>,
+}
+
+impl TelegramRequest for SetWebhookWithSecret {
+    type Response = bool;
+
+    fn endpoint(&self) -> &str {
+        "setWebhook"
+    }
+}
+
 /// Start a web server to handle webhooks and pass updates to [handle_request].
 async fn receive_webhook(
     update_tx: tokio::sync::mpsc::Sender<(HandlerUpdate, tracing::Span)>,
@@ -707,8 +1119,17 @@ async fn receive_webhook(
         .parse()
         .expect("Invalid HTTP_HOST");
 
-    let secret_path = format!("/{}", config.http_secret.unwrap());
+    // Requests are authenticated by the secret path, the secret token, or
+    // both. Without a secret path, updates are accepted on `/telegram`.
+    if config.http_secret.is_none() && config.webhook_secret_token.is_none() {
+        panic!("Missing HTTP_SECRET or WEBHOOK_SECRET_TOKEN");
+    }
+
+    let secret_path = format!("/{}", config.http_secret.as_deref().unwrap_or("telegram"));
     let secret_path: &'static str = Box::leak(secret_path.into_boxed_str());
+    let secret_token: Option<&'static str> = config
+        .webhook_secret_token
+        .map(|secret_token| &*Box::leak(secret_token.into_boxed_str()));
     let fuzzysearch_secret = format!("/{}", config.fautil_apitoken);
     let fuzzysearch_secret: &'static str = Box::leak(fuzzysearch_secret.into_boxed_str());
     let video_secret = format!("/{}", config.coconut_secret);
@@ -741,6 +1162,7 @@ async fn receive_webhook(
                     update_tx.clone(),
                     inline_tx.clone(),
                     secret_path,
+                    secret_token,
                     fuzzysearch_secret,
                     video_secret,
                     templates.clone(),
@@ -768,11 +1190,35 @@ async fn receive_webhook(
 
 async fn metrics(
     req: hyper::Request<hyper::Body>,
+    handler: Arc<MessageHandler>,
 ) -> Result<hyper::Response<hyper::Body>, std::convert::Infallible> {
     use hyper::{Body, Response, StatusCode};
 
+    if req.uri().path().starts_with("/admin/") {
+        return Ok(admin::handle(req, handler).await);
+    }
+
     match req.uri().path() {
         "/health" => Ok(Response::new(Body::from("OK"))),
+        path @ "/healthz" | path @ "/readyz" => {
+            let report = handler.health_report().await;
+            let healthy = if path == "/healthz" {
+                report.is_live()
+            } else {
+                report.is_ready()
+            };
+
+            let mut resp = Response::new(Body::from(report.to_json().to_string()));
+            resp.headers_mut().insert(
+                hyper::header::CONTENT_TYPE,
+                hyper::header::HeaderValue::from_static("application/json"),
+            );
+            if !healthy {
+                *resp.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+            }
+
+            Ok(resp)
+        }
         "/metrics" => {
             tracing::trace!("encoding metrics");
 
@@ -792,11 +1238,17 @@ async fn metrics(
     }
 }
 
-async fn serve_metrics(config: Config) {
+async fn serve_metrics(config: Config, handler: Arc<MessageHandler>) {
     let addr = config.metrics_host.parse().expect("Invalid METRICS_HOST");
 
-    let make_svc = hyper::service::make_service_fn(|_conn| async {
-        Ok::<_, std::convert::Infallible>(hyper::service::service_fn(metrics))
+    let make_svc = hyper::service::make_service_fn(move |_conn| {
+        let handler = handler.clone();
+
+        async move {
+            Ok::<_, std::convert::Infallible>(hyper::service::service_fn(move |req| {
+                metrics(req, handler.clone())
+            }))
+        }
     });
 
     tokio::spawn(async move {
@@ -868,21 +1320,32 @@ async fn poll_updates(
 pub struct MessageHandler {
     // State
     pub bot_user: User,
-    langs: HashMap<LanguageIdentifier, Vec<String>>,
-    best_lang: RwLock<HashMap<String, fluent::concurrent::FluentBundle<fluent::FluentResource>>>,
+    pub l10n: foxbot_utils::l10n::Localization,
+    middleware: Vec<handlers::BoxedMiddleware>,
     handlers: Vec<BoxedHandler>,
+    bans: handlers::BanList,
 
     // API clients
     pub bot: Arc<Telegram>,
+    /// Limits messages sent to Telegram, shared with the background worker.
+    limiter: foxbot_utils::rate_limit::RequestLimiter,
     pub fapi: Arc<fuzzysearch::FuzzySearch>,
+    /// Reverse image search providers, starting with FuzzySearch.
+    pub reverse: foxbot_utils::reverse::ReverseSearch,
     pub finder: linkify::LinkFinder,
     pub s3: rusoto_s3::S3Client,
+    /// Rehosts images from sites Telegram has trouble loading, and stores
+    /// edited images until they expire.
+    pub media_cache: media_cache::MediaCache,
     pub coconut: coconut::Coconut,
-    pub faktory: Arc<std::sync::Mutex<faktory::Producer<std::net::TcpStream>>>,
+    /// Faktory producer, if jobs are not being stored in Postgres.
+    pub faktory: Option<Arc<std::sync::Mutex<faktory::Producer<std::net::TcpStream>>>>,
 
     // Configuration
     pub sites: Mutex<Vec<foxbot_sites::BoxedSite>>, // We always need mutable access, no reason to use a RwLock
     pub config: Config,
+    settings: std::sync::RwLock<Settings>,
+    secrets: foxbot_utils::secrets::ChainedSecrets,
 
     // Storage
     pub conn: sqlx::Pool<sqlx::Postgres>,
@@ -894,29 +1357,12 @@ impl MessageHandler {
     where
         C: FnOnce(&fluent::concurrent::FluentBundle<fluent::FluentResource>) -> R,
     {
-        let requested = requested.unwrap_or(L10N_LANGS[0]);
+        let requested = requested.unwrap_or(DEFAULT_LANG);
 
         tracing::trace!(lang = requested, "looking up language bundle");
 
-        {
-            let lock = self.best_lang.read().await;
-            if let Some(bundle) = lock.get(requested) {
-                return callback(bundle);
-            }
-        }
-
-        tracing::info!(lang = requested, "got new language, building bundle");
-
-        let bundle = get_lang_bundle(&self.langs, requested);
-
-        {
-            let mut lock = self.best_lang.write().await;
-            lock.insert(requested.to_string(), bundle);
-        }
-
-        let lock = self.best_lang.read().await;
-        let bundle = lock.get(requested).expect("value just inserted is missing");
-        callback(bundle)
+        let bundle = self.l10n.bundle(requested);
+        callback(&bundle)
     }
 
     pub async fn report_error<C>(
@@ -955,32 +1401,33 @@ impl MessageHandler {
             sentry::capture_error(&e);
         }
 
-        let msg = self
+        let (msg, parse_mode) = self
             .get_fluent_bundle(lang_code.as_deref(), |bundle| {
-                let mut args = fluent::FluentArgs::new();
-                args.insert("count", (recent_error_count + 1).into());
+                use foxbot_utils::formatting::MessageBuilder;
+
+                let mut args = vec![("count", (recent_error_count as i64 + 1).into())];
 
-                if u.is_nil() {
+                let name = if u.is_nil() {
                     if recent_error_count > 0 {
-                        get_message(bundle, "error-generic-count", Some(args))
+                        "error-generic-count"
                     } else {
-                        get_message(bundle, "error-generic", None)
+                        "error-generic"
                     }
                 } else {
-                    let f = format!("`{}`", u.to_string());
-                    args.insert("uuid", fluent::FluentValue::from(f));
+                    args.push(("uuid", MessageBuilder::html().code(&u.to_string()).into()));
 
-                    let name = if recent_error_count > 0 {
+                    if recent_error_count > 0 {
                         "error-uuid-count"
                     } else {
                         "error-uuid"
-                    };
+                    }
+                };
 
-                    get_message(bundle, name, Some(args))
-                }
+                MessageBuilder::html().message(bundle, name, args)
             })
             .await
-            .unwrap();
+            .unwrap()
+            .build();
 
         let delete_markup = Some(ReplyMarkup::InlineKeyboardMarkup(InlineKeyboardMarkup {
             inline_keyboard: vec![vec![InlineKeyboardButton {
@@ -1007,7 +1454,7 @@ impl MessageHandler {
                 chat_id: message.chat_id(),
                 message_id: Some(message_id),
                 text: msg,
-                parse_mode: Some(ParseMode::Markdown),
+                parse_mode: Some(parse_mode),
                 reply_markup: delete_markup,
                 ..Default::default()
             };
@@ -1025,7 +1472,7 @@ impl MessageHandler {
             let send_message = SendMessage {
                 chat_id: message.chat_id(),
                 text: msg,
-                parse_mode: Some(ParseMode::Markdown),
+                parse_mode: Some(parse_mode),
                 reply_to_message_id: Some(message.message_id),
                 reply_markup: delete_markup,
                 ..Default::default()
@@ -1052,7 +1499,7 @@ impl MessageHandler {
     }
 
     #[tracing::instrument(skip(self, message))]
-    async fn handle_welcome(&self, message: &Message, command: &str) -> anyhow::Result<()> {
+    async fn handle_welcome(&self, message: &Message) -> anyhow::Result<()> {
         use rand::seq::SliceRandom;
 
         let from = message.from.as_ref().unwrap();
@@ -1073,15 +1520,9 @@ impl MessageHandler {
             }]],
         });
 
-        let name = if command == "group-add" {
-            "welcome-group"
-        } else {
-            "welcome"
-        };
-
         let welcome = self
             .get_fluent_bundle(from.language_code.as_deref(), |bundle| {
-                get_message(bundle, name, None).unwrap()
+                get_message(bundle, "welcome", None).unwrap()
             })
             .await;
 
@@ -1098,6 +1539,184 @@ impl MessageHandler {
             .map_err(Into::into)
     }
 
+    /// Largest document that can be downloaded to find sources.
+    fn download_limit(&self) -> usize {
+        bot_api::download_limit(self.config.telegram_api_endpoint.as_deref())
+    }
+
+    /// Get the current reloadable settings.
+    pub fn settings(&self) -> Settings {
+        self.settings
+            .read()
+            .expect("settings lock was poisoned")
+            .clone()
+    }
+
+    /// Load configuration again, applying any reloadable settings,
+    /// rebuilding sites so rotated credentials are used, and reloading
+    /// languages.
+    async fn reload_settings(&self) -> anyhow::Result<()> {
+        let config = Config::load(&self.secrets).await?;
+
+        let settings = Settings::from(&config);
+        tracing::info!(?settings, "reloaded settings");
+
+        *self
+            .settings
+            .write()
+            .map_err(|_err| anyhow::anyhow!("settings lock was poisoned"))? = settings;
+
+        let sites = build_sites(&config, self.conn.clone()).await;
+        *self.sites.lock().await = sites;
+        tracing::info!("rebuilt sites with current credentials");
+
+        self.l10n.reload()?;
+
+        Ok(())
+    }
+
+    /// Store a site credential, or remove the stored value to use the
+    /// configured one again, then rebuild the sites that use it.
+    ///
+    /// Other sites keep their sessions, and the background worker rebuilds
+    /// its sites when it sees the credentials changed.
+    async fn update_site_credential(
+        &self,
+        name: &str,
+        value: Option<&str>,
+        updated_by: Option<i64>,
+    ) -> anyhow::Result<()> {
+        use redis::AsyncCommands;
+
+        if !foxbot_utils::secrets::SITE_CREDENTIALS.contains(&name) {
+            anyhow::bail!("{} is not a site credential", name);
+        }
+
+        let config = Config::load(&self.secrets).await?;
+
+        match value {
+            Some(value) => {
+                let key = config.credentials_key.as_deref().ok_or_else(|| {
+                    anyhow::anyhow!("CREDENTIALS_KEY must be set to store site credentials")
+                })?;
+                let value =
+                    foxbot_utils::secrets::CredentialCipher::new(key)?.encrypt(name, value)?;
+
+                foxbot_models::SiteCredentials::set(&self.conn, name, &value, updated_by).await?;
+            }
+            None => {
+                foxbot_models::SiteCredentials::remove(&self.conn, name).await?;
+            }
+        }
+
+        let affected = foxbot_utils::secrets::sites_using(name);
+        tracing::info!(name, ?affected, ?updated_by, "updated site credential");
+
+        let rebuilt = build_named_sites(&config, self.conn.clone(), Some(affected)).await;
+
+        let mut sites = self.sites.lock().await;
+        for rebuilt in rebuilt {
+            if let Some(site) = sites.iter_mut().find(|site| site.name() == rebuilt.name()) {
+                *site = rebuilt;
+            }
+        }
+        drop(sites);
+
+        let mut redis = self.redis.clone();
+        let _version: i64 = redis
+            .incr(foxbot_utils::secrets::CREDENTIALS_VERSION_KEY, 1)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Check if a user is allowed to use bot administration commands.
+    fn is_bot_admin(&self, user_id: i64) -> bool {
+        self.settings().admin_ids.contains(&user_id)
+    }
+
+    /// Check each dependency needed to handle updates.
+    async fn health_report(&self) -> foxbot_utils::health::HealthReport {
+        use foxbot_utils::health;
+
+        let mut report = health::HealthReport::default();
+
+        report
+            .check("postgres", true, health::check_postgres(&self.conn))
+            .await;
+        report
+            .check("redis", true, health::check_redis(&self.redis))
+            .await;
+        if self.faktory.is_some() {
+            report
+                .check(
+                    "faktory",
+                    true,
+                    health::check_faktory(self.config.faktory_url.clone()),
+                )
+                .await;
+        }
+        report
+            .check("telegram", false, health::check_telegram(&self.bot))
+            .await;
+
+        report
+    }
+
+    /// Enqueue a job for the background worker, using Faktory unless jobs are
+    /// configured to be stored in Postgres.
+    ///
+    /// The current trace context is attached to the job if it was not already
+    /// set, so the worker's spans are part of the same trace.
+    #[tracing::instrument(skip(self, job), fields(kind = job.kind()))]
+    pub async fn enqueue(&self, mut job: faktory::Job) -> anyhow::Result<()> {
+        if job.custom.is_empty() {
+            job.custom = get_faktory_custom();
+        }
+
+        match &self.faktory {
+            Some(faktory) => {
+                let faktory = faktory.clone();
+
+                tokio::task::spawn_blocking(move || {
+                    let mut faktory = faktory
+                        .lock()
+                        .map_err(|_err| anyhow::anyhow!("faktory producer was poisoned"))?;
+                    faktory.enqueue(job)?;
+
+                    Ok::<_, anyhow::Error>(())
+                })
+                .await??;
+            }
+            None => {
+                let run_at = job.at.map(|at| at.timestamp());
+                let data = serde_json::to_value(&job)?;
+
+                foxbot_models::QueuedJobs::push(&self.conn, &job.queue, data, run_at).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Save the file ID of media sent for a post so it can be reused in inline
+    /// results. Failures are only logged, as the media was already sent.
+    async fn remember_sent_media(&self, post: &foxbot_sites::PostInfo, message: &Message) {
+        let url_id = {
+            let sites = self.sites.lock().await;
+            post_url_id(&sites, post)
+        };
+
+        let url_id = match url_id {
+            Some(url_id) => url_id,
+            None => return,
+        };
+
+        if let Err(err) = cache_sent_media(&self.conn, &url_id, post, message).await {
+            tracing::warn!("unable to cache sent media: {:?}", err);
+        }
+    }
+
     #[tracing::instrument(skip(self, message))]
     async fn send_generic_reply(&self, message: &Message, name: &str) -> anyhow::Result<Message> {
         let language_code = message
@@ -1123,8 +1742,6 @@ impl MessageHandler {
 
     #[tracing::instrument(skip(self, handler_update), fields(user_id, chat_id))]
     async fn handle_update(&self, handler_update: HandlerUpdate) {
-        let _hist = HANDLING_DURATION.start_timer();
-
         tracing::trace!(?handler_update, "handling update");
 
         sentry::configure_scope(|mut scope| {
@@ -1163,53 +1780,32 @@ impl MessageHandler {
             .as_ref()
             .and_then(|message| message.get_command());
 
-        for handler in &self.handlers {
-            let hist = HANDLER_DURATION
-                .get_metric_with_label_values(&[handler.name()])
-                .unwrap()
-                .start_timer();
-
-            match handler
-                .handle(self, &update, command.as_ref())
-                .instrument(tracing::info_span!(
-                    "handler_handle",
-                    handler = handler.name()
-                ))
-                .await
-            {
-                Ok(status) if status == handlers::Status::Completed => {
-                    tracing::debug!(handled_by = handler.name(), "Completed update");
-                    hist.stop_and_record();
+        let next = handlers::Next::new(&self.middleware, &self.handlers);
 
-                    break;
-                }
-                Err(err) => {
-                    tracing::error!(handled_by = handler.name(), "Handler error: {:?}", err);
-                    hist.stop_and_record();
+        if let Err(err) = next.run(self, &update).await {
+            let failed_in = err
+                .downcast_ref::<handlers::FailedIn>()
+                .map(|failed_in| failed_in.0)
+                .unwrap_or("unknown");
 
-                    let mut tags = vec![("handler", handler.name().to_string())];
-                    if let Some(user) = user {
-                        tags.push(("user_id", user.id.to_string()));
-                    }
-                    if let Some(chat) = chat {
-                        tags.push(("chat_id", chat.id.to_string()));
-                    }
-                    if let Some(command) = command {
-                        tags.push(("command", command.name));
-                    }
+            tracing::error!(handled_by = failed_in, "Handler error: {:?}", err);
 
-                    if let Some(msg) = &update.message {
-                        self.report_error(msg, Some(tags), || capture_anyhow(&err))
-                            .await;
-                    } else {
-                        capture_anyhow(&err);
-                    }
+            let mut tags = vec![("handler", failed_in.to_string())];
+            if let Some(user) = user {
+                tags.push(("user_id", user.id.to_string()));
+            }
+            if let Some(chat) = chat {
+                tags.push(("chat_id", chat.id.to_string()));
+            }
+            if let Some(command) = command {
+                tags.push(("command", command.name));
+            }
 
-                    break;
-                }
-                _ => {
-                    hist.stop_and_discard();
-                }
+            if let Some(msg) = &update.message {
+                self.report_error(msg, Some(tags), || capture_anyhow(&err))
+                    .await;
+            } else {
+                with_user_scope(user, Some(tags), || capture_anyhow(&err));
             }
         }
     }
@@ -1225,49 +1821,38 @@ impl MessageHandler {
         let mut attempts = 0;
 
         loop {
+            self.limiter.wait(request).await;
+
             let err = match self.bot.make_request(request).await {
                 Ok(resp) => return Ok(resp),
                 Err(err) => err,
             };
 
             if attempts > 2 {
-                TELEGRAM_ERROR.inc();
+                TELEGRAM_ERROR
+                    .with_label_values(&[&telegram_error_code(&err)])
+                    .inc();
                 return Err(err);
             }
 
-            let retry_after = match err {
-                tgbotapi::Error::Telegram(tgbotapi::TelegramError {
-                    parameters:
-                        Some(tgbotapi::ResponseParameters {
-                            retry_after: Some(retry_after),
-                            ..
-                        }),
-                    ..
-                }) => {
+            let retry_after = match bot_api::classify_error(&err) {
+                bot_api::ErrorKind::RetryAfter(retry_after) => {
                     tracing::warn!(retry_after, "Rate limited");
                     retry_after
                 }
-                tgbotapi::Error::Telegram(tgbotapi::TelegramError {
-                    error_code: Some(400),
-                    description: Some(desc),
-                    ..
-                }) if desc
-                    == "Bad Request: wrong file_id or the file is temporarily unavailable" =>
-                {
-                    tracing::warn!("file_id temporarily unavailable");
-                    2
-                }
-                tgbotapi::Error::Request(err) => {
-                    tracing::warn!("Telegram request network error: {:?}", err);
-                    2
+                bot_api::ErrorKind::Transient => {
+                    tracing::warn!("Transient Telegram error: {:?}", err);
+                    2 << attempts
                 }
-                _ => {
-                    TELEGRAM_ERROR.inc();
+                bot_api::ErrorKind::Terminal => {
+                    TELEGRAM_ERROR
+                        .with_label_values(&[&telegram_error_code(&err)])
+                        .inc();
                     return Err(err);
                 }
             };
 
-            tokio::time::sleep(Duration::from_secs(retry_after as u64)).await;
+            tokio::time::sleep(Duration::from_secs(retry_after)).await;
 
             attempts += 1;
         }
@@ -1284,3 +1869,72 @@ pub mod test_helpers {
             .expect("Unable to open Redis connection")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn test_shard_for() {
+        use super::shard_for;
+
+        assert_eq!(shard_for(-1001234567890, 8), 6);
+        assert_eq!(shard_for(-1001234567891, 8), 5);
+        assert_eq!(shard_for(16, 8), 0);
+        assert_eq!(shard_for(-1, 8), 7);
+    }
+
+    #[tokio::test]
+    async fn test_twitter_callback() {
+        use super::{handle_request, HandlerUpdate, ServiceData};
+
+        let mut hbs = handlebars::Handlebars::new();
+        hbs.register_templates_directory(
+            ".hbs",
+            concat!(env!("CARGO_MANIFEST_DIR"), "/../templates/"),
+        )
+        .unwrap();
+        let templates = std::sync::Arc::new(hbs);
+
+        let (update_tx, mut update_rx) = tokio::sync::mpsc::channel(1);
+        let (inline_tx, _inline_rx) = tokio::sync::mpsc::channel(1);
+
+        let callback = |query: &str| {
+            let req = hyper::Request::get(format!("/twitter/callback?{}", query))
+                .body(hyper::Body::empty())
+                .unwrap();
+
+            handle_request(
+                req,
+                update_tx.clone(),
+                inline_tx.clone(),
+                "/secret",
+                None,
+                "fuzzysearch",
+                "video",
+                templates.clone(),
+            )
+        };
+
+        let resp = callback("oauth_token=token&oauth_verifier=verifier")
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), hyper::StatusCode::OK);
+        assert!(matches!(
+            update_rx.recv().await,
+            Some((HandlerUpdate::Service(ServiceData::TwitterVerified { token, verifier }), _))
+                if token == "token" && verifier == "verifier"
+        ));
+
+        let resp = callback("oauth_token=token").await.unwrap();
+        assert_eq!(resp.status(), hyper::StatusCode::BAD_REQUEST);
+
+        let resp = callback("denied=token").await.unwrap();
+        assert_eq!(resp.status(), hyper::StatusCode::OK);
+
+        drop(callback);
+        drop(update_tx);
+        assert!(
+            update_rx.recv().await.is_none(),
+            "only verified accounts should be sent to handlers"
+        );
+    }
+}
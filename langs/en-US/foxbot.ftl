@@ -15,11 +15,13 @@ welcome =
     
     If I'm given edit permissions in your channel, I'll automatically edit posts to include a source link.
     
+    Send /watch with a link to an artist's gallery or an e621 tag search and I'll send you their new posts.
+    
     For more details, visit { -docsLink }. Also check out { -newsChannelName } for feature updates.
     
     Contact my creator { -creatorName } if you have any issues or feature suggestions.
 
-welcome-group =
+onboarding-group =
     Hi, I'm { -botName }.
     
     I'm here to help with sharing images! If you use me as an inline bot, I can easily get images from most furry sites, while keeping a link to the original source.
@@ -28,7 +30,17 @@ welcome-group =
     · /mirror - I'll look at all the links in your message or the message you're replying to and mirror them
     · /source - I'll attempt to find if the photo you're replying to has been posted on FurAffinity
     
-    You can also enable automatically finding sources for images posted in here with the /groupsource command. However, I must be an administrator in the group for this to work and it can only be enabled by an administrator.
+    You can also enable automatically finding sources for images posted in here with the /groupsource command. However, I must be an administrator in the group for this to work and it can only be enabled by an administrator. If you'd rather I stay quiet, /groupsilent makes me react to images instead of replying. Use /grouprepost if you'd like me to point out images that were already posted here.
+    
+    For more details, visit { -docsLink }. Also check out { -newsChannelName } for feature updates.
+
+onboarding-channel =
+    Thanks for adding me to your channel!
+    
+    To get started, make me an administrator with permission to edit messages. Then I'll edit new posts to include a source link.
+    
+    · /sourcetemplate - customize how sources are shown in your posts
+    · /backfill - add sources to posts made before I was added
     
     For more details, visit { -docsLink }. Also check out { -newsChannelName } for feature updates.
 
@@ -37,14 +49,40 @@ welcome-try-me = Try Me!
 # Inline Keyboard
 inline-direct = Direct Link
 inline-source = Source
+inline-source-file = { $site } ({ $number } of { $count })
 
 # No Inline Results
 inline-no-results-title = No results found
 inline-no-results-body = I could not find any results for the provided query.
+inline-personal-hidden-body = These results are from a locked account or a post that isn't public, and you've chosen to never share them.
+inline-limited-title = ⏳ Slow down
+inline-limited-body = You've sent a lot of links recently, so I'm only showing ones I've already loaded for you. Try again in a minute.
+
+# Locked Account Results
+inline-personal-title = ⚠️ Content that isn't public
+inline-personal-body = Some of these results come from a locked account or a post that isn't public. Please only share them with people allowed to see them.
 
 # Inline Results Misc
 inline-help = Help
 inline-process = Process Video
+inline-album = Send as Album
+inline-sources-title = Send { $count ->
+    [one] source link
+   *[other] { $count } source links
+}
+inline-sources-header = Sources:
+inline-share-expired-title = No longer available
+inline-share-expired-body = This was prepared too long ago to share. Send it to me again to get a new link.
+
+# Inline Albums
+album-expired = Sorry, these results have expired. Please try searching again.
+album-share-message = Want to share these images?
+album-share = Share
+share-edit-downscale = Downscale
+share-edit-jpeg = Convert to JPEG
+share-edit-alpha = Remove Transparency
+share-edit-complete = Edited! Share it with the new message.
+share-edit-limited = You've edited a lot of images recently. Please wait a minute and try again.
 
 # Inline Videos
 video-starting = Starting to process video...
@@ -60,21 +98,32 @@ reverse-result-unknown = I found this: { $link }
 reverse-multiple-results = I found multiple sources:
 reverse-multiple-item = · { $link } ({ $rating })
 reverse-multiple-item-unknown = · { $link }
+reverse-other-result = I found this on { $site }: { $link } (via { $provider })
+reverse-other-result-artists = I found this on { $site }: { $link } by { $artists } (via { $provider })
+reverse-other-item = · { $site }: { $link } (via { $provider })
+reverse-other-item-artists = · { $site }: { $link } by { $artists } (via { $provider })
+reverse-alternates = ↳ Also at: { $links }
 reverse-subscribe = Notify Me
+reverse-share = Share via Inline
 
 # Twitter Onboarding
 twitter-callback = Please follow this link to add your Twitter account: { $link }
 twitter-welcome = Welcome aboard, { $userName }!
-twitter-private = Let's do this in a private chat.
 twitter-not-for-you = It doesn't look like anything to me
 twitter-existing-account = It looks like you already have the account { $account } added. Are you sure you want to change this?
 twitter-change-anyway = Change Account 
 twitter-remove-account = Remove Account
 twitter-removed-account = Okay, got it. Your Twitter account has been removed.
 
+accounts-none = You haven't linked any accounts. You can add your Twitter account with /twitter.
+accounts-linked = These are the accounts you've linked. Tap one to remove it.
+accounts-revoke = Remove { $name }
+accounts-revoked = Okay, got it. Your { $name } account has been removed.
+
 # In-group mirroring
 mirror-no-links = Sorry, I could not find any links to mirror images from.
 mirror-no-results = Sorry, I could not get any images from this message.
+mirror-personal = These images are from a locked account or a post that isn't public, so I won't mirror them here.
 mirror-missing =
     I could not fetch images from the following links:
     
@@ -99,6 +148,14 @@ automatic-single-unknown = It looks like this image may have come from here: { $
 automatic-multiple = I found a few places this image may have come from:
 automatic-multiple-result = · { $link } ({ $rating })
 automatic-multiple-result-unknown = · { $link }
+source-details-artists = Art by { $artists }
+source-details-pool = Part of { $name }: { $link }
+source-details-parent = Parent post: { $link }
+source-details-children = Child posts: { $links }
+automatic-forwarded = This was forwarded from { $origin }. I can also look for where the artwork was originally posted.
+automatic-forwarded-unknown = This was forwarded. I can also look for where the artwork was originally posted.
+automatic-forwarded-button = Find Original Source
+automatic-forwarded-expired = Sorry, I no longer have sources for this image. Try /source instead.
 automatic-enable-not-admin = Sorry, you must be a group admin to enable this.
 automatic-enable-bot-not-admin = Sorry, you must give me admin permissions due to a Telegram limitation.
 automatic-enable-success = Automatic group sourcing is now enabled!
@@ -106,6 +163,97 @@ automatic-disable = This feature is now turned off.
 automatic-enable-not-group = This feature is only supported in groups.
 automatic-preview-disable = Sourced image previews disabled.
 automatic-preview-enable = Sourced image previews enabled.
+automatic-spoilers-enable = NSFW images will now be hidden behind a spoiler.
+automatic-spoilers-disable = NSFW images will no longer be hidden behind a spoiler.
+automatic-silent-enable = I'll react to images I find sources for instead of replying. Reply to one with /source to see them.
+automatic-silent-disable = I'll reply with sources for images again.
+automatic-repost-enable = I'll let you know when an image has already been posted here.
+automatic-repost-disable = I'll stop pointing out images that were already posted here.
+repost-warning = { $days ->
+        [0] This was already posted here earlier today.
+        [one] This was already posted here yesterday.
+       *[other] This was already posted here { $days } days ago.
+    }
+repost-warning-user = { $days ->
+        [0] This was already posted here earlier today by { $user }.
+        [one] This was already posted here yesterday by { $user }.
+       *[other] This was already posted here { $days } days ago by { $user }.
+    }
+
+# Channel management
+channel-unknown = I couldn't find that channel. Please include the channel's username or ID, or use this command in the channel's discussion group.
+channel-not-admin = Sorry, you must be an administrator of the channel to do this.
+channel-missing-edit-permission = I'm unable to add sources to posts in { $title } because I don't have permission to edit messages. Please make me an administrator with permission to edit messages of others.
+
+# Channel source templates
+template-current =
+    Sources are currently added with these settings:
+    · style: { $style }
+    · format: { $format }
+    · hashtags: { $hashtags }
+    · discussion replies: { $companion }
+    · sources per site: { $sources }
+    · show distance: { $distance }
+    · exact matches only: { $strict }
+template-updated =
+    Updated! Sources will now be added with these settings:
+    · style: { $style }
+    · format: { $format }
+    · hashtags: { $hashtags }
+    · discussion replies: { $companion }
+    · sources per site: { $sources }
+    · show distance: { $distance }
+    · exact matches only: { $strict }
+template-usage =
+    Usage: /sourcetemplate [channel] <setting> <value>
+    · style buttons|caption
+    · format <text containing {"{url}"} and optionally {"{site}"}>
+    · hashtags on|off
+    · companion off|fallback|also|instead
+    · sources 1-3
+    · distance on|off
+    · strict on|off
+    · reset
+source-button-exact = { $site } (exact)
+source-button-distance = { $site } (~{ $distance })
+
+# Source distance
+distance-current = Images are sourced automatically when their similarity distance is { $distance } or less. Lower numbers only match nearly identical images.
+distance-updated = Updated! Images will now be sourced automatically when their similarity distance is { $distance } or less.
+distance-usage = Usage: /sourcedistance [channel] <distance>, where the distance is a number from 0 to { $distance }.
+
+# Blocked sites
+blocked-sites-current = Sources from { $sites } will never be used. Send /blocksites [channel] followed by a site name to unblock it.
+blocked-sites-none = Sources from every site will be used. Send /blocksites [channel] followed by a site name to never use it.
+blocked-sites-usage = Usage: /blocksites [channel] <site>, which blocks the site or unblocks it if it's already blocked. Available sites are { $available }.
+
+# Channel networks
+network-current = This channel shares sources with other channels in the { $network } network. Send /channelnetwork [channel] off to stop.
+network-none = This channel isn't part of a network. Channels that post the same images can share sources by joining a network with /channelnetwork [channel] <name>.
+network-updated = Updated! This channel now shares sources with other channels in the { $network } network.
+network-usage = Usage: /channelnetwork [channel] <name>, where the name only contains letters, numbers, dashes, and underscores. Use off to leave a network.
+
+# Channel backfill
+backfill-usage =
+    Usage: /backfill <channel> <first message ID> <last message ID>
+
+    The message ID is the number at the end of a post's link. I'll briefly forward each post here to look at it, then remove it.
+backfill-too-many = Sorry, I can only look at { $max } messages at a time.
+backfill-started = Looking for sources in { $count ->
+        [one] { $count } message
+       *[other] { $count } messages
+    }. This may take a while.
+backfill-progress = Still working, { $remaining } messages left to check. Found images in { $found } so far.
+backfill-complete = Finished! Found { $found ->
+        [one] { $found } image
+       *[other] { $found } images
+    } to look for sources.
+
+# Channel discussion replies
+discussion-header = Sources for this post:
+channel-overflow-header = More sources for this post:
+discussion-source = · { $link }
+discussion-source-artists = · { $link } by { $artists }
 
 # Error Messages
 error-generic = Oh no, something went wrong! Please send a message to my creator, { -creatorName }, saying what happened.
@@ -126,6 +274,33 @@ settings-unsupported = Sorry, not yet supported.
 settings-move-unable = Unable to move { $name } to that position
 settings-move-updated = Updated position for { $name }
 settings-site-preference = Site Preference
+settings-personal-allowed = Locked Content: Allowed
+settings-personal-never = Locked Content: Never Share
+settings-personal-updated-allowed = Results from locked accounts and posts that aren't public will be shown with a warning.
+settings-personal-updated-never = Results from locked accounts and posts that aren't public will no longer be shown.
+settings-captions-both = Post Text: With and Without
+settings-captions-always = Post Text: Always Include
+settings-captions-never = Post Text: Never Include
+settings-captions-updated-both = Results will be shown both with and without the post's text.
+settings-captions-updated-always = Results will always include the post's text.
+settings-captions-updated-never = Results will never include the post's text.
+
+# Bot Statistics
+stats-title = Inline results sent per site in the past { $days } days:
+stats-site = · { $site }: { $count }
+stats-empty = No inline results have been sent recently.
+
+sitestatus-title = Site checks:
+sitestatus-pass = ✅ { $site } loaded { $count } posts in { $duration }ms
+sitestatus-fail = ❌ { $site } failed after { $duration }ms: { $error }
+
+credential-private = Credentials can only be updated in a private chat with me.
+credential-unknown = That's not a site credential. Use a name like FA_A or INKBUNNY_PASSWORD.
+credential-updated = Updated { $name } and reloaded { $sites }.
+credential-removed = Removed the stored { $name }, { $sites } now uses the configured value.
+
+langs-reloaded = Reloaded { $count } languages.
+langs-reload-failed = Unable to reload languages, still using the previous translations: { $error }
 
 rating-general = SFW
 rating-adult = NSFW
@@ -139,3 +314,115 @@ subscribe-found-single =
     You can see it here: { $link }
 subscribe-found-multiple = I found matches for an image you were looking for!
 subscribe-found-multiple-item = · { $link }
+
+repost-watch = Watch for Reposts
+repost-watching = I'll let you know if this is posted again!
+repost-found = An image you were watching for was posted again:
+repost-stop = Stop Watching
+repost-stopped = Okay, I'll stop watching for this image.
+
+# Failed Jobs
+job-failed = A { $kind } job failed { $attempts } times and was stopped: { $error }
+retry-jobs = { $count ->
+        [0] There are no failed jobs to retry.
+        [one] Retrying { $count } failed job.
+       *[other] Retrying { $count } failed jobs.
+    }
+
+# Site Logins
+site-login-failed = { $site } is no longer accepting its credentials, so its results will be limited until they are updated.
+site-login-restored = { $site } is accepting its credentials again.
+
+# Scheduled Jobs
+pending-edits-title = { $count ->
+        [one] There is { $count } scheduled job:
+       *[other] There are { $count } scheduled jobs:
+    }
+pending-edits-item = · { $kind } for { $chat } in { $seconds }s
+pending-edits-empty = There are no scheduled jobs.
+
+# Action history
+history-title = Recent changes I made in this chat:
+history-item = · { $action } on message { $message } { $minutes } minutes ago, { $count ->
+        [one] { $count } source
+       *[other] { $count } sources
+    }
+history-empty = I haven't changed anything in this chat recently.
+undo-done = Okay, I undid the last change I made.
+undo-failed = I couldn't undo the last change, the message may have been deleted or edited.
+undo-none = There's nothing for me to undo in this chat.
+
+# Wrong sources
+wrong-source-reported = Thanks, I won't show those sources for this image here again.
+wrong-source-expired = These sources can no longer be reported.
+wrong-source-not-allowed = Only the person who posted the image or a group administrator can report sources.
+
+# Artist choices
+artist-choice = I found this image from more than one artist. Which one is right?
+artist-choice-chosen = Thanks, I'll add the sources for that artist.
+artist-choice-expired = This choice has expired.
+artist-choice-not-allowed = Only the person who posted the image or an administrator can choose.
+
+# Artist watches
+watch-not-admin = Only group administrators can change what this group is watching.
+watch-unsupported = I can't watch that link. Try a FurAffinity or Weasyl gallery, or an e621 tag search.
+watch-limit = This chat is already watching { $count } pages, which is the most I can watch.
+watch-added = Okay, I'll send new posts from { $site } at { $url } here.
+watch-exists = This chat is already watching { $url }.
+watch-none = This chat isn't watching anything. Send /watch with a link to start.
+watch-list = This chat is watching these pages. Tap one to stop watching it.
+watch-list-item = { $number }. { $site }: { $url }
+watch-remove = Stop watching #{ $number }
+watch-removed = Okay, I'll stop watching that page.
+watch-missing = That page isn't being watched anymore.
+watch-new-post = New post from { $url }
+
+# Commands
+command-private = Let's do this in a private chat.
+command-usage = Usage: { $usage }
+help-title = These are the commands you can use:
+help-command = { $usage } — { $description }
+command-help = List the commands you can use
+command-start = Get started with the bot
+command-mirror = Mirror links in a message
+command-source = Find the source of an image
+command-alts = Find other versions of an image
+command-settings = Change your preferences
+command-accounts = Manage your linked accounts
+command-twitter = Link your Twitter account
+command-watch = Watch a gallery for new posts
+command-groupsource = Toggle automatic sourcing
+command-grouppreviews = Toggle link previews
+command-groupspoilers = Toggle hiding sources as spoilers
+command-groupsilent = Toggle replying to media silently
+command-grouprepost = Toggle repost detection
+command-sourcedistance = Change how similar sources must be
+command-blocksites = Choose sites to never use as sources
+command-channelnetwork = Share sources with other channels
+command-sourcetemplate = Change how channel sources look
+command-backfill = Add sources to older channel posts
+command-history = Show recent changes the bot made
+command-undo = Undo the bot's last change
+command-stats = Show bot statistics
+command-retryjobs = Retry failed jobs
+command-pendingedits = Show pending channel edits
+command-error = Send a test error
+command-sitestatus = Check that every site is working
+command-credential = Update a site credential
+command-reloadlangs = Reload translations
+command-ban = Ban a user or chat
+command-unban = Remove a ban
+command-announcements = Toggle announcements about the bot
+command-broadcast = Send an announcement to every chat that wants them
+
+# Bans
+ban-added = Okay, { $id } is banned.
+ban-removed = Okay, { $id } is no longer banned.
+ban-missing = { $id } wasn't banned.
+
+# Announcements
+announcements-not-admin = Only group administrators can change if this group gets announcements.
+announcements-enabled = Okay, I'll send announcements about updates to the bot here.
+announcements-disabled = Okay, I'll stop sending announcements here.
+broadcast-started = Sending the announcement to { $count } chats. I'll let you know when it's done.
+broadcast-complete = Finished sending the announcement to { $sent } chats. { $failed } couldn't receive it.
@@ -0,0 +1,10 @@
+CREATE TABLE media_file_id (
+    id SERIAL PRIMARY KEY,
+    url_id TEXT NOT NULL,
+    url TEXT NOT NULL,
+    file_type TEXT NOT NULL,
+    file_id TEXT NOT NULL,
+    created_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT current_timestamp
+);
+
+CREATE UNIQUE INDEX media_file_id_url_id_url_idx ON media_file_id (url_id, url);
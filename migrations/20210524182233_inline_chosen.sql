@@ -0,0 +1,8 @@
+CREATE TABLE inline_chosen (
+    id SERIAL PRIMARY KEY,
+    account_id INTEGER NOT NULL REFERENCES account (id) ON DELETE CASCADE,
+    site TEXT NOT NULL,
+    created_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT current_timestamp
+);
+
+CREATE INDEX inline_chosen_created_at_idx ON inline_chosen (created_at);
@@ -0,0 +1,12 @@
+CREATE TABLE failed_jobs (
+    id SERIAL PRIMARY KEY,
+    job_id TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    queue TEXT NOT NULL,
+    args JSONB NOT NULL,
+    error TEXT NOT NULL,
+    failed_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT current_timestamp,
+    retried_at TIMESTAMP WITHOUT TIME ZONE
+);
+
+CREATE INDEX failed_jobs_pending_idx ON failed_jobs (id) WHERE retried_at IS NULL;
@@ -0,0 +1,20 @@
+CREATE TABLE job_queue (
+    id BIGSERIAL PRIMARY KEY,
+    queue TEXT NOT NULL,
+    job JSONB NOT NULL,
+    run_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT current_timestamp,
+    locked_until TIMESTAMP WITHOUT TIME ZONE,
+    attempts INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE INDEX job_queue_run_at_idx ON job_queue (run_at);
+
+CREATE FUNCTION notify_job_queue() RETURNS trigger AS $$
+BEGIN
+    PERFORM pg_notify('job_queue', NEW.queue);
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER job_queue_notify AFTER INSERT ON job_queue
+    FOR EACH ROW EXECUTE PROCEDURE notify_job_queue();
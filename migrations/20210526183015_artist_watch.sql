@@ -0,0 +1,13 @@
+CREATE TABLE artist_watch (
+    id SERIAL PRIMARY KEY,
+    chat_id INTEGER NOT NULL REFERENCES chat (id) ON DELETE CASCADE,
+    site TEXT NOT NULL,
+    url TEXT NOT NULL,
+    last_seen TEXT,
+    next_check_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT current_timestamp,
+    created_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT current_timestamp,
+
+    UNIQUE (chat_id, url)
+);
+
+CREATE INDEX artist_watch_next_check_at_idx ON artist_watch (next_check_at);
@@ -0,0 +1,11 @@
+CREATE TABLE repost_watch (
+    account_id INTEGER NOT NULL REFERENCES account (id) ON DELETE CASCADE,
+    hash BIGINT NOT NULL,
+    message_id INT,
+    photo_id TEXT,
+    created_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT current_timestamp,
+
+    PRIMARY KEY (account_id, hash)
+);
+
+CREATE INDEX bk_repost_watch_idx ON repost_watch USING spgist (hash bktree_ops);
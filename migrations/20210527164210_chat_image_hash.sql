@@ -0,0 +1,11 @@
+CREATE TABLE chat_image_hash (
+    id BIGSERIAL PRIMARY KEY,
+    chat_id INTEGER NOT NULL REFERENCES chat (id) ON DELETE CASCADE,
+    message_id INTEGER NOT NULL,
+    hash BIGINT NOT NULL,
+    posted_by TEXT,
+    posted_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT current_timestamp
+);
+
+CREATE INDEX bk_chat_image_hash_idx ON chat_image_hash USING spgist (hash bktree_ops);
+CREATE INDEX chat_image_hash_chat_id_idx ON chat_image_hash (chat_id);
@@ -0,0 +1,11 @@
+CREATE TABLE known_source (
+    id BIGSERIAL PRIMARY KEY,
+    site TEXT NOT NULL,
+    site_id BIGINT NOT NULL,
+    hash BIGINT NOT NULL,
+    file JSONB NOT NULL,
+    last_seen TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT current_timestamp,
+    UNIQUE (site, site_id)
+);
+
+CREATE INDEX bk_known_source_hash_idx ON known_source USING spgist (hash bktree_ops);
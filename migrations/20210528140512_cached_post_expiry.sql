@@ -0,0 +1,3 @@
+ALTER TABLE cached_post ADD COLUMN expires_at TIMESTAMP WITHOUT TIME ZONE;
+
+CREATE INDEX cached_post_expires_at_idx ON cached_post (expires_at) WHERE expires_at IS NOT NULL;
@@ -0,0 +1,6 @@
+CREATE TABLE site_session (
+    site TEXT PRIMARY KEY,
+    cookies JSONB NOT NULL,
+    user_agent TEXT,
+    updated_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT current_timestamp
+);
@@ -0,0 +1,13 @@
+CREATE TABLE actions (
+    id BIGSERIAL PRIMARY KEY,
+    chat_id INTEGER NOT NULL REFERENCES chat (id) ON DELETE CASCADE,
+    message_id INTEGER NOT NULL,
+    reply_message_id INTEGER,
+    action TEXT NOT NULL,
+    sources TEXT[] NOT NULL,
+    job_id TEXT,
+    created_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT current_timestamp
+);
+
+CREATE INDEX actions_chat_id_idx ON actions (chat_id, created_at);
+CREATE INDEX actions_created_at_idx ON actions (created_at);
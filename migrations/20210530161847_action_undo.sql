@@ -0,0 +1,3 @@
+ALTER TABLE actions ADD COLUMN previous_caption TEXT;
+ALTER TABLE actions ADD COLUMN previous_caption_entities JSONB;
+ALTER TABLE actions ADD COLUMN undone_at TIMESTAMP WITHOUT TIME ZONE;
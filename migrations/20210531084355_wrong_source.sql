@@ -0,0 +1,9 @@
+CREATE TABLE wrong_source (
+    id BIGSERIAL PRIMARY KEY,
+    hash BIGINT NOT NULL,
+    url TEXT NOT NULL,
+    account_id INTEGER NOT NULL REFERENCES account (id) ON DELETE CASCADE,
+    reports INTEGER NOT NULL DEFAULT 1,
+    created_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT current_timestamp,
+    UNIQUE (hash, url)
+);
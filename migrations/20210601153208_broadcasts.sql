@@ -0,0 +1,16 @@
+CREATE TABLE announcement_chat (
+    chat_id INTEGER PRIMARY KEY REFERENCES chat (id) ON DELETE CASCADE,
+    failures INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    created_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT current_timestamp
+);
+
+CREATE TABLE broadcast (
+    id SERIAL PRIMARY KEY,
+    text TEXT NOT NULL,
+    created_by BIGINT NOT NULL,
+    sent INTEGER NOT NULL DEFAULT 0,
+    failed INTEGER NOT NULL DEFAULT 0,
+    created_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT current_timestamp,
+    completed_at TIMESTAMP WITHOUT TIME ZONE
+);
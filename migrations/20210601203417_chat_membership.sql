@@ -0,0 +1,7 @@
+ALTER TABLE chat
+    ADD COLUMN title TEXT,
+    ADD COLUMN chat_type TEXT,
+    ADD COLUMN member_count INTEGER,
+    ADD COLUMN is_member BOOLEAN,
+    ADD COLUMN added_at TIMESTAMP WITHOUT TIME ZONE,
+    ADD COLUMN removed_at TIMESTAMP WITHOUT TIME ZONE;
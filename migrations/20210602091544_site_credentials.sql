@@ -0,0 +1,6 @@
+CREATE TABLE site_credential (
+    name TEXT PRIMARY KEY,
+    value TEXT NOT NULL,
+    updated_by BIGINT,
+    updated_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT current_timestamp
+);
@@ -0,0 +1,17 @@
+-- Each person who reported a source as wrong and the chat they reported it
+-- in, so sources are only hidden everywhere once enough people report them.
+CREATE TABLE wrong_source_report (
+    id BIGSERIAL PRIMARY KEY,
+    wrong_source_id BIGINT NOT NULL REFERENCES wrong_source (id) ON DELETE CASCADE,
+    account_id INTEGER NOT NULL REFERENCES account (id) ON DELETE CASCADE,
+    chat_id INTEGER REFERENCES chat (id) ON DELETE CASCADE,
+    created_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT current_timestamp,
+    UNIQUE (wrong_source_id, account_id, chat_id)
+);
+
+-- Existing reports didn't record a chat, so they only count towards hiding
+-- a source everywhere.
+INSERT INTO wrong_source_report (wrong_source_id, account_id, created_at)
+    SELECT id, account_id, created_at FROM wrong_source;
+
+UPDATE wrong_source SET reports = 1;
@@ -0,0 +1,11 @@
+-- The artist someone chose for an image in a chat, so the choice is applied
+-- again instead of asking every time the image is posted.
+CREATE TABLE artist_choice (
+    id BIGSERIAL PRIMARY KEY,
+    chat_id INTEGER NOT NULL REFERENCES chat (id) ON DELETE CASCADE,
+    hash BIGINT NOT NULL,
+    artist TEXT NOT NULL,
+    account_id INTEGER NOT NULL REFERENCES account (id) ON DELETE CASCADE,
+    created_at TIMESTAMP WITHOUT TIME ZONE NOT NULL DEFAULT current_timestamp,
+    UNIQUE (chat_id, hash)
+);
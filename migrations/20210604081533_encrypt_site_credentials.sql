@@ -0,0 +1,3 @@
+-- Stored credentials were previously plaintext. They're now encrypted with
+-- CREDENTIALS_KEY and have to be stored again.
+DELETE FROM site_credential;